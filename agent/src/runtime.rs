@@ -21,11 +21,16 @@ use std::{
 use std::os::unix::fs::DirBuilderExt as _;
 
 use anyhow::{Result, anyhow};
+use aws_lc_rs::{
+    agreement::{ECDH_P256, ECDH_P384, PrivateKey, UnparsedPublicKey, X25519, agree},
+    error::Unspecified,
+};
 use bincode_next::{config::standard, decode_from_slice, encode_to_vec};
 use clap::Parser as _;
 use dialoguer::Password;
 use libmoshpit::{
-    AgentIdentityInfo, AgentRequest, AgentResponse, fingerprint, load_identity_key, load_public_key,
+    AgentIdentityInfo, AgentRequest, AgentResponse, KEY_ALGORITHM_P256, KEY_ALGORITHM_P384,
+    KEY_ALGORITHM_X25519, fingerprint, load_identity_key, load_public_key,
 };
 use tokio::{
     io::{AsyncReadExt as _, AsyncWriteExt as _},
@@ -243,6 +248,31 @@ fn sign_data(id: &Identity, data: &[u8]) -> AgentResponse {
     }
 }
 
+fn agree_data(id: &Identity, peer_public_key: &[u8]) -> AgentResponse {
+    let agreement_alg = match id.algorithm.as_str() {
+        KEY_ALGORITHM_X25519 => &X25519,
+        KEY_ALGORITHM_P256 => &ECDH_P256,
+        KEY_ALGORITHM_P384 => &ECDH_P384,
+        _ => {
+            return AgentResponse::Error(format!(
+                "algorithm {} does not support key agreement",
+                id.algorithm
+            ));
+        }
+    };
+    let private_key = match PrivateKey::from_private_key(agreement_alg, &id.private_key) {
+        Ok(private_key) => private_key,
+        Err(e) => return AgentResponse::Error(format!("key load failed: {e}")),
+    };
+    let peer_public_key = UnparsedPublicKey::new(agreement_alg, peer_public_key);
+    match agree(&private_key, peer_public_key, Unspecified, |key_material| {
+        Ok(key_material.to_vec())
+    }) {
+        Ok(secret) => AgentResponse::SharedSecret(secret),
+        Err(e) => AgentResponse::Error(format!("key agreement failed: {e}")),
+    }
+}
+
 #[cfg_attr(nightly, allow(clippy::too_many_lines))]
 async fn dispatch_request(
     request: AgentRequest,
@@ -301,6 +331,17 @@ async fn dispatch_request(
             }
         }
 
+        AgentRequest::Agree {
+            fingerprint: fp,
+            peer_public_key,
+        } => {
+            let map = identities.lock().await;
+            match map.get(&fp) {
+                Some(id) => agree_data(id, &peer_public_key),
+                None => AgentResponse::Error(format!("identity not found: {fp}")),
+            }
+        }
+
         AgentRequest::AddIdentity {
             key_path,
             passphrase,
@@ -1000,7 +1041,7 @@ mod tests {
 
     use super::{
         AgentConfig, AgentIdentityInfo, AgentRequest, AgentResponse, ConnectionState, Identity,
-        ShellKind, Vault, best_identity, check_not_already_running, dispatch_request,
+        ShellKind, Vault, agree_data, best_identity, check_not_already_running, dispatch_request,
         format_socket_env, format_unset_socket_env, handle_connection, is_process_alive,
         new_identity_map, reload_from_vault, sign_data, socket_from_value, unlock_backend,
     };
@@ -1224,6 +1265,64 @@ mod tests {
         );
     }
 
+    #[test]
+    fn agree_data_matches_peer_side_agreement() {
+        use aws_lc_rs::{
+            agreement::{PrivateKey, UnparsedPublicKey, X25519, agree},
+            error::Unspecified,
+        };
+        use libmoshpit::load_identity_key;
+
+        let key = load_identity_key(&PathBuf::from(TEST_KEY_PATH), None).expect("load test key");
+        let id = Identity {
+            full_pub_key_bytes: vec![],
+            algorithm: key.key_algorithm().clone(),
+            fingerprint: "SHA256:agree".to_string(),
+            private_key: key.private_key().clone(),
+        };
+        let peer = PrivateKey::generate(&X25519).expect("generate peer key");
+        let peer_pub = peer.compute_public_key().expect("peer public key");
+        let expected = agree(
+            &peer,
+            UnparsedPublicKey::new(&X25519, key.public_key()),
+            Unspecified,
+            |km| Ok(km.to_vec()),
+        )
+        .expect("peer agree");
+        let resp = agree_data(&id, peer_pub.as_ref());
+        assert!(matches!(resp, AgentResponse::SharedSecret(ref s) if *s == expected));
+    }
+
+    #[test]
+    fn agree_data_unsupported_algorithm_returns_error() {
+        let id = dummy_identity("SHA256:test", "ML-DSA-65");
+        let resp = agree_data(&id, &[0u8; 32]);
+        assert!(
+            matches!(resp, AgentResponse::Error(ref msg) if msg.contains("does not support key agreement"))
+        );
+    }
+
+    #[tokio::test]
+    async fn dispatch_agree_not_found() {
+        let ids = new_identity_map();
+        let vault = empty_vault();
+        let vault_path = PathBuf::from("/tmp/nonexistent-vault-agent-test");
+        let mp = empty_passphrase();
+        let resp = dispatch_request(
+            AgentRequest::Agree {
+                fingerprint: "SHA256:nosuchkey".to_string(),
+                peer_public_key: vec![0u8; 32],
+            },
+            &ids,
+            &vault,
+            &vault_path,
+            &mp,
+            &unlocked_state(),
+        )
+        .await;
+        assert!(matches!(resp, AgentResponse::Error(_)));
+    }
+
     #[tokio::test]
     async fn dispatch_add_identity() {
         let ids = new_identity_map();
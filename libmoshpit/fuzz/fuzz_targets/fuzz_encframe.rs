@@ -22,7 +22,7 @@ use aws_lc_rs::{
     hmac::{HMAC_SHA512, Key},
 };
 use libfuzzer_sys::fuzz_target;
-use libmoshpit::EncryptedFrame;
+use libmoshpit::{EncryptedFrame, NonceScheme};
 use uuid::Uuid;
 
 fuzz_target!(|data: &[u8]| {
@@ -35,7 +35,7 @@ fuzz_target!(|data: &[u8]| {
     let id = Uuid::nil();
 
     let mut cursor = Cursor::new(data);
-    match EncryptedFrame::parse(&mut cursor, id, &hmac, &rnk, 64) {
+    match EncryptedFrame::parse(&mut cursor, id, Some(&hmac), &rnk, 64, NonceScheme::Random) {
         Ok(Some(_)) => {
             // Parsed a valid frame — no panic, that's success (very unlikely
             // with random data given the HMAC gate, but theoretically possible).
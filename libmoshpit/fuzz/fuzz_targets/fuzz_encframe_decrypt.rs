@@ -34,7 +34,7 @@ use aws_lc_rs::{
     hmac::{HMAC_SHA512, Key, sign},
 };
 use libfuzzer_sys::fuzz_target;
-use libmoshpit::EncryptedFrame;
+use libmoshpit::{EncryptedFrame, NonceScheme};
 use uuid::Uuid;
 
 static HOOK: Once = Once::new();
@@ -101,5 +101,5 @@ fuzz_target!(|data: &[u8]| {
     let mut cursor = Cursor::new(packet.as_slice());
     // HMAC + AEAD now succeed by construction, so this drives the bincode
     // decode of `data`. All outcomes (Ok/Err) are fine; only panics fail.
-    let _ = EncryptedFrame::parse(&mut cursor, id, &hmac, &rnk, 64);
+    let _ = EncryptedFrame::parse(&mut cursor, id, Some(&hmac), &rnk, 64, NonceScheme::Random);
 });
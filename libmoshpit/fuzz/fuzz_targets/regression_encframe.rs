@@ -25,7 +25,7 @@ use aws_lc_rs::{
     aead::{AES_256_GCM_SIV, LessSafeKey, UnboundKey},
     hmac::{HMAC_SHA512, Key},
 };
-use libmoshpit::EncryptedFrame;
+use libmoshpit::{EncryptedFrame, NonceScheme};
 use uuid::Uuid;
 
 /// Helper that mirrors the fuzz target body exactly.
@@ -41,7 +41,7 @@ fn run_fuzz_encframe(data: &[u8]) {
     let id = Uuid::nil();
     let mut cursor = Cursor::new(data);
     // All outcomes (Ok or Err) are acceptable; only panics are failures.
-    let _ = EncryptedFrame::parse(&mut cursor, id, &hmac, &rnk, 64);
+    let _ = EncryptedFrame::parse(&mut cursor, id, Some(&hmac), &rnk, 64, NonceScheme::Random);
 }
 
 #[test]
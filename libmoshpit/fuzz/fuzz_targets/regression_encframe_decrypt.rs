@@ -25,7 +25,7 @@ use aws_lc_rs::{
     aead::{AES_256_GCM_SIV, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
     hmac::{HMAC_SHA512, Key, sign},
 };
-use libmoshpit::EncryptedFrame;
+use libmoshpit::{EncryptedFrame, NonceScheme};
 use uuid::Uuid;
 
 fn build_packet(rnk: &LessSafeKey, hmac: &Key, id: Uuid, inner: &[u8]) -> Option<Vec<u8>> {
@@ -63,7 +63,7 @@ fn run_fuzz_encframe_decrypt(data: &[u8]) {
         return;
     };
     let mut cursor = Cursor::new(packet.as_slice());
-    let _ = EncryptedFrame::parse(&mut cursor, id, &hmac, &rnk, 64);
+    let _ = EncryptedFrame::parse(&mut cursor, id, Some(&hmac), &rnk, 64, NonceScheme::Random);
 }
 
 #[test]
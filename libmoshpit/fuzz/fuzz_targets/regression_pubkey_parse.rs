@@ -12,10 +12,6 @@
 //! `&[u8]` constant so that `cargo test` permanently guards against
 //! regressions without requiring a nightly fuzzer run.
 //!
-//! These tests are only meaningful with libmoshpit's `unstable` feature
-//! enabled (run `cargo test --features unstable`); otherwise they compile to
-//! an empty body.
-//!
 //! To add a new crash:
 //! 1. Extract the bytes from the artifact zip downloaded from CI.
 //! 2. Run `xxd -i crash-<hash>` (or `hexdump -C`) to get the byte values.
@@ -27,14 +23,10 @@
 ///
 /// Any panic inside this function is a confirmed bug: the fuzzer found an
 /// input that panics the SSH-format public-key parser.
-#[cfg(feature = "unstable")]
 fn run_fuzz_pubkey_parse(data: &[u8]) {
     let _ = libmoshpit::parse_full_public_key(data);
 }
 
-#[cfg(not(feature = "unstable"))]
-fn run_fuzz_pubkey_parse(_data: &[u8]) {}
-
 #[test]
 fn regression_empty() {
     run_fuzz_pubkey_parse(&[]);
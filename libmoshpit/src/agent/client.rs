@@ -130,6 +130,26 @@ impl AgentClient {
         }
     }
 
+    /// Run an ECDH agreement between the identity key identified by `fingerprint`
+    /// and `peer_public_key`, returning the raw shared secret.
+    ///
+    /// # Errors
+    /// Returns an error if the agent is unreachable, the fingerprint is unknown,
+    /// the key is not an ECDH key, or the agent is locked.
+    pub async fn agree(&self, fingerprint: &str, peer_public_key: &[u8]) -> Result<Vec<u8>> {
+        match self
+            .send(&AgentRequest::Agree {
+                fingerprint: fingerprint.to_string(),
+                peer_public_key: peer_public_key.to_vec(),
+            })
+            .await?
+        {
+            AgentResponse::SharedSecret(secret) => Ok(secret),
+            AgentResponse::Error(e) => Err(anyhow!("agent error: {e}")),
+            other => Err(anyhow!("unexpected agent response: {other:?}")),
+        }
+    }
+
     /// Query the agent's current state: whether it is locked and which identities are loaded.
     ///
     /// Returns `(locked, identities)`. A connection error means the agent is not running.
@@ -243,4 +263,29 @@ mod tests {
         let client = AgentClient::new(socket_path);
         assert!(client.status().await.is_err());
     }
+
+    #[tokio::test]
+    async fn agree_returns_shared_secret() {
+        let dir = TempDir::new().expect("temp dir");
+        let socket_path = dir.path().join("test-agent-agree.sock");
+        drop(spawn_mock_agent(
+            &socket_path,
+            AgentResponse::SharedSecret(vec![5u8; 32]),
+        ));
+        let client = AgentClient::new(socket_path);
+        let secret = client
+            .agree("SHA256:aabbcc", &[1u8; 32])
+            .await
+            .expect("agree should succeed");
+        assert_eq!(secret, vec![5u8; 32]);
+    }
+
+    #[tokio::test]
+    async fn agree_unexpected_response_errors() {
+        let dir = TempDir::new().expect("temp dir");
+        let socket_path = dir.path().join("test-agent-agree-unexpected.sock");
+        drop(spawn_mock_agent(&socket_path, AgentResponse::Ok));
+        let client = AgentClient::new(socket_path);
+        assert!(client.agree("SHA256:aabbcc", &[1u8; 32]).await.is_err());
+    }
 }
@@ -14,8 +14,8 @@
 //! [u32 big-endian message length][bincode-next encoded message]
 //! ```
 //!
-//! Private keys never cross the socket — only public keys and signatures are
-//! returned from the agent.
+//! Private keys never cross the socket — only public keys, signatures, and
+//! identity-key ECDH shared secrets are returned from the agent.
 
 use bincode_next::{Decode, Encode};
 
@@ -80,6 +80,17 @@ pub enum AgentRequest {
     Shutdown,
     /// Query the agent's current state (locked flag + loaded identities).
     Status,
+    /// Run an ECDH agreement between the private key identified by `fingerprint`
+    /// and `peer_public_key`.
+    ///
+    /// Only meaningful for X25519 / P-256 / P-384 keys; used to answer the
+    /// server's identity proof-of-possession challenge during key exchange.
+    Agree {
+        /// `SHA256:<base64>` fingerprint (without trailing comment).
+        fingerprint: String,
+        /// Raw peer public key bytes on the identity key's curve.
+        peer_public_key: Vec<u8>,
+    },
 }
 
 /// Responses from the agent.
@@ -102,6 +113,8 @@ pub enum AgentResponse {
         /// Identities currently held in memory (empty when locked).
         identities: Vec<AgentIdentityInfo>,
     },
+    /// The ECDH shared secret produced in response to [`AgentRequest::Agree`].
+    SharedSecret(Vec<u8>),
 }
 
 #[cfg(test)]
@@ -150,6 +163,30 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn roundtrip_request_agree() -> anyhow::Result<()> {
+        let request = AgentRequest::Agree {
+            fingerprint: "SHA256:abcd".to_string(),
+            peer_public_key: vec![7u8; 32],
+        };
+        let encoded = encode_to_vec(&request, standard())?;
+        let (rt, _): (AgentRequest, _) = decode_from_slice(&encoded, standard())?;
+        assert!(matches!(
+            rt,
+            AgentRequest::Agree { ref fingerprint, ref peer_public_key }
+                if fingerprint == "SHA256:abcd" && peer_public_key == &vec![7u8; 32]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_response_shared_secret() -> anyhow::Result<()> {
+        let encoded = encode_to_vec(AgentResponse::SharedSecret(vec![1, 2, 3]), standard())?;
+        let (rt, _): (AgentResponse, _) = decode_from_slice(&encoded, standard())?;
+        assert!(matches!(rt, AgentResponse::SharedSecret(ref s) if s == &[1, 2, 3]));
+        Ok(())
+    }
+
     #[test]
     fn agent_identity_info_clone_and_debug() {
         let info = AgentIdentityInfo {
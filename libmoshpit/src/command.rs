@@ -0,0 +1,167 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Remote commands: `mp host -- command args` in place of a login shell.
+//!
+//! From [`REMOTE_COMMAND_MIN_PROTOCOL_VERSION`] the client can name a command
+//! with [`Frame::RemoteCommand`](crate::Frame::RemoteCommand) during key
+//! exchange.  moshpits runs it through the user's shell with `-c` instead of
+//! starting a login shell, either on a PTY like a shell, or with its standard
+//! streams carried as [`ForwardMux`](crate::ForwardMux) channels opened with
+//! [`EncryptedFrame::ForwardOpenStdio`](crate::EncryptedFrame::ForwardOpenStdio):
+//! those are binary-clean, keep standard output and error apart, and pass on
+//! the end of `mp`'s standard input.  Either way its [`ExitStatus`] goes back in
+//! [`EncryptedFrame::CommandExit`](crate::EncryptedFrame::CommandExit), repeated
+//! until acknowledged, and becomes the exit status of `mp`.  A command session
+//! lost before it ends is never started again: resuming it once it is gone is
+//! rejected with
+//! [`KexFailureReason::SessionEnded`](crate::KexFailureReason::SessionEnded).
+//!
+//! From [`PTY_EXIT_STATUS_MIN_PROTOCOL_VERSION`] a login shell reports how it
+//! ended too, in [`EncryptedFrame::PtyExitStatus`](crate::EncryptedFrame::PtyExitStatus)
+//! in place of the bare [`EncryptedFrame::PtyExit`](crate::EncryptedFrame::PtyExit).
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use bincode_next::{Decode, Encode};
+use getset::{CopyGetters, Getters};
+
+use crate::FileCopy;
+
+/// Lowest negotiated protocol version whose peers understand
+/// [`Frame::RemoteCommand`](crate::Frame::RemoteCommand) and the frames
+/// carrying a command's standard streams and exit status.
+pub const REMOTE_COMMAND_MIN_PROTOCOL_VERSION: u16 = 16;
+
+/// Lowest negotiated protocol version whose peers understand
+/// [`EncryptedFrame::PtyExitStatus`](crate::EncryptedFrame::PtyExitStatus).
+pub const PTY_EXIT_STATUS_MIN_PROTOCOL_VERSION: u16 = 17;
+
+/// Exit status reported for a signal is this plus the signal number, as
+/// shells report it in `$?`.
+const SIGNAL_EXIT_BASE: i32 = 128;
+
+/// A command for the server to run in place of the login shell.
+#[derive(Clone, CopyGetters, Debug, Eq, Getters, PartialEq)]
+pub struct RemoteCommand {
+    /// The command line, run with the user's shell as `shell -c command`.
+    #[getset(get = "pub")]
+    command: String,
+    /// Whether the command runs on a PTY like a login shell.  Without one its
+    /// standard streams are carried on channels of their own.
+    #[getset(get_copy = "pub")]
+    pty: bool,
+    /// The file copy this session runs instead of `command`, for `mp cp`.
+    #[getset(get = "pub")]
+    file_copy: Option<FileCopy>,
+}
+
+impl RemoteCommand {
+    /// A command running `command` with or without a PTY.
+    #[must_use]
+    pub fn new(command: String, pty: bool) -> Self {
+        Self {
+            command,
+            pty,
+            file_copy: None,
+        }
+    }
+
+    /// A session running the server's half of `copy`.  It has no PTY, so its
+    /// standard streams carry the copy protocol.
+    #[must_use]
+    pub fn copying(copy: FileCopy) -> Self {
+        Self {
+            command: String::new(),
+            pty: false,
+            file_copy: Some(copy),
+        }
+    }
+
+    /// The command line for `args`, joined with spaces as `ssh` does: the
+    /// remote shell splits and expands it again.
+    #[must_use]
+    pub fn from_args(args: &[String], pty: bool) -> Self {
+        Self::new(args.join(" "), pty)
+    }
+}
+
+/// How a remote command or login shell ended.
+#[derive(Clone, Copy, Debug, Decode, Encode, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ExitStatus {
+    /// It exited with this code.
+    Code(i32),
+    /// It was terminated by this signal.
+    Signal(i32),
+}
+
+impl ExitStatus {
+    /// The status as a shell reports it in `$?`: the exit code, or 128 plus
+    /// the number of the terminating signal.
+    #[must_use]
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Code(code) => code,
+            Self::Signal(signal) => SIGNAL_EXIT_BASE.saturating_add(signal),
+        }
+    }
+}
+
+impl From<std::process::ExitStatus> for ExitStatus {
+    fn from(status: std::process::ExitStatus) -> Self {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt as _;
+            if let Some(signal) = status.signal() {
+                return Self::Signal(signal);
+            }
+        }
+        Self::Code(status.code().unwrap_or(-1))
+    }
+}
+
+impl Display for ExitStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Code(code) => write!(f, "exit code {code}"),
+            Self::Signal(signal) => write!(f, "signal {signal}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExitStatus, RemoteCommand};
+
+    #[test]
+    fn arguments_are_joined_for_the_remote_shell() {
+        let args = ["ls".to_string(), "-l".to_string(), "/tmp".to_string()];
+        let command = RemoteCommand::from_args(&args, false);
+        assert_eq!(command.command(), "ls -l /tmp");
+        assert!(!command.pty());
+    }
+
+    #[test]
+    fn signals_map_to_shell_exit_codes() {
+        assert_eq!(ExitStatus::Code(3).code(), 3);
+        assert_eq!(ExitStatus::Signal(9).code(), 137);
+        assert_eq!(ExitStatus::Signal(15).to_string(), "signal 15");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn child_statuses_convert() -> anyhow::Result<()> {
+        use std::process::Command;
+
+        let status = Command::new("sh").args(["-c", "exit 7"]).status()?;
+        assert_eq!(ExitStatus::from(status), ExitStatus::Code(7));
+        let status = Command::new("sh").args(["-c", "kill -9 $$"]).status()?;
+        assert_eq!(ExitStatus::from(status), ExitStatus::Signal(9));
+        Ok(())
+    }
+}
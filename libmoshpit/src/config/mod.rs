@@ -18,13 +18,14 @@ use tokio::sync::Mutex;
 use uuid::Uuid;
 
 use crate::{
-    KexMode,
+    KexMode, RemoteCommand,
     error::Error,
     kex::negotiate::{
         AlgorithmList, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, ProtocolSupport,
         supported_algorithms,
     },
-    session::SessionRegistry,
+    kex::ticket::{ResumptionTicket, TicketIssuer},
+    session::{SessionRegistry, SessionRequest},
     to_path_buf,
     udp::DiffMode,
 };
@@ -78,6 +79,12 @@ pub trait KexConfig {
     fn server_id(&self) -> Option<String> {
         None
     }
+    /// The `known_hosts` keys older releases used for the server, consulted
+    /// when there is no entry under [`server_id`](Self::server_id).
+    /// Empty by default.
+    fn legacy_server_ids(&self) -> Vec<String> {
+        Vec::new()
+    }
     /// The requested UDP diff transport mode.
     /// Client implementations override this to return their configured mode;
     /// server implementations use the default (`Reliable`) since the server
@@ -143,6 +150,52 @@ pub trait KexConfig {
     fn allow_tcp_transport(&self) -> bool {
         false
     }
+    /// Whether a rejected client is told exactly which account check failed
+    /// (unknown user, key not in `authorized_keys`, or permissions too open).
+    /// Returns `false` by default so clients cannot probe which users exist;
+    /// server implementations override this from the `detailed_auth_failures`
+    /// config field.
+    fn detailed_auth_failures(&self) -> bool {
+        false
+    }
+    /// The resumption ticket to present instead of a full key exchange, only
+    /// relevant for client mode and only used together with
+    /// [`resume_session_uuid`](Self::resume_session_uuid).
+    /// Returns `None` by default; client implementations override this.
+    fn resumption_ticket(&self) -> Option<ResumptionTicket> {
+        None
+    }
+    /// The issuer that seals and redeems resumption tickets, only relevant for
+    /// server mode.  Returns `None` by default, which disables tickets;
+    /// server implementations override this.
+    fn ticket_issuer(&self) -> Option<TicketIssuer> {
+        None
+    }
+    /// Whether to send a speculative key share with the `KexInit`, only
+    /// relevant for client mode.  Returns `false` by default; client
+    /// implementations override this, and turn it off for a server that has
+    /// answered with [`MoshpitError::EarlyKeyShareUnsupported`](crate::MoshpitError::EarlyKeyShareUnsupported).
+    fn early_key_share(&self) -> bool {
+        false
+    }
+    /// Whether to ask the server to forward this client's agent, only
+    /// relevant for client mode.  Returns `false` by default; client
+    /// implementations override this.
+    fn forward_agent(&self) -> bool {
+        false
+    }
+    /// The command to run in place of a login shell, only relevant for
+    /// client mode.  Returns `None` by default; client implementations
+    /// override this.
+    fn remote_command(&self) -> Option<RemoteCommand> {
+        None
+    }
+    /// The named session to start or resume, or the request to list
+    /// sessions, only relevant for client mode.  Returns `None` by default;
+    /// client implementations override this.
+    fn session_request(&self) -> Option<SessionRequest> {
+        None
+    }
     /// The data-channel transport mode this client endpoint prefers.
     ///
     /// `Udp` (default): connect to the server's UDP data port after KEX.
@@ -318,6 +371,12 @@ mod tests {
         assert!(!cfg.allow_tcp_transport());
     }
 
+    #[test]
+    fn kex_config_detailed_auth_failures_default_is_false() {
+        let cfg = TestKexConfig::default();
+        assert!(!cfg.detailed_auth_failures());
+    }
+
     #[test]
     fn kex_config_transport_preference_default_is_udp() {
         let cfg = TestKexConfig::default();
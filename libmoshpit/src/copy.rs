@@ -0,0 +1,805 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! File copy: `mp cp` over an authenticated session.
+//!
+//! From [`FILE_COPY_MIN_PROTOCOL_VERSION`] the client can ask for a
+//! [`FileCopy`] with [`Frame::FileCopy`](crate::Frame::FileCopy) during key
+//! exchange.  moshpits then runs its own copy helper as the session user in
+//! place of a piped [`RemoteCommand`](crate::RemoteCommand), and the two ends
+//! talk the protocol in this module over the helper's standard streams, which
+//! travel on forwarded channels and so are reliable over either transport.
+//!
+//! The sender announces each directory and file with its relative path,
+//! permission bits and modification time.  The receiver writes every file to
+//! a partial file next to its destination, named with [`PARTIAL_SUFFIX`], and
+//! only renames it into place once it is complete.  For every file it answers
+//! with the length of the partial file an interrupted copy left behind and a
+//! SHA-256 digest of those bytes; when the sender's file starts with the same
+//! bytes only the rest is sent, so the copy resumes where it stopped.  Files
+//! the copy did not write itself are never read or truncated.  Both ends
+//! collect what they could not copy, swap the counts at the end, and the
+//! helper's exit status, which `mp` exits with, reports whether everything
+//! arrived.
+
+use std::{
+    io::{Error as IoError, ErrorKind, SeekFrom},
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context as _, Result};
+use aws_lc_rs::digest::{Context as DigestContext, SHA256};
+use bincode_next::{Decode, Encode, config::standard, encode_to_vec};
+use getset::{CopyGetters, Getters};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncRead, AsyncReadExt as _, AsyncSeekExt as _, AsyncWrite, AsyncWriteExt as _},
+};
+
+use crate::{MoshpitError, frames::decode_frame};
+
+/// Lowest negotiated protocol version whose peers understand
+/// [`Frame::FileCopy`](crate::Frame::FileCopy).
+pub const FILE_COPY_MIN_PROTOCOL_VERSION: u16 = 18;
+
+/// Bytes of file data carried by one [`CopyMessage::Data`].
+const CHUNK_LEN: usize = 32 * 1024;
+
+/// Appended to a destination's name for the file it is received into.  Only
+/// a partial file left by an interrupted copy is resumed.
+const PARTIAL_SUFFIX: &str = ".mp-partial";
+
+/// Permission bits sent for entries on platforms without Unix modes.
+#[cfg(not(unix))]
+const DEFAULT_FILE_MODE: u32 = 0o644;
+#[cfg(not(unix))]
+const DEFAULT_DIR_MODE: u32 = 0o755;
+
+/// The server's half of an `mp cp`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FileCopy {
+    /// The client sends; the server receives into `target`.
+    Upload {
+        /// Where the files go on the server.
+        target: String,
+    },
+    /// The server sends `sources`; the client receives.
+    Download {
+        /// The server paths to send.
+        sources: Vec<String>,
+        /// Send the contents of directories instead of refusing them.
+        recursive: bool,
+    },
+}
+
+/// How one end of a copy went.
+#[derive(Clone, CopyGetters, Debug, Default, Eq, Getters, PartialEq)]
+pub struct CopyOutcome {
+    /// What this end could not copy, one line per entry naming its path.
+    #[getset(get = "pub")]
+    errors: Vec<String>,
+    /// The number of entries this end could not copy.
+    #[getset(get_copy = "pub")]
+    failed: u32,
+    /// The number of entries the other end could not copy.
+    #[getset(get_copy = "pub")]
+    peer_failed: u32,
+}
+
+impl CopyOutcome {
+    /// The number of entries that could not be copied by either end.
+    #[must_use]
+    pub fn not_copied(&self) -> u32 {
+        self.failed.saturating_add(self.peer_failed)
+    }
+
+    /// Record that an entry could not be copied, for `message`.
+    fn fail(&mut self, message: String) {
+        self.errors.push(message);
+        self.failed = self.failed.saturating_add(1);
+    }
+}
+
+/// One message of the copy protocol, framed on the stream as a big-endian
+/// `u32` length followed by the bincode encoding.
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+enum CopyMessage {
+    /// Sender → receiver: this many top-level entries follow.
+    Begin(u32),
+    /// Sender → receiver: directory at `(path, mode, mtime)`.
+    Dir((String, u32, i64)),
+    /// Sender → receiver: file at `(path, mode, mtime, size)`, answered with
+    /// [`CopyMessage::Resume`] or [`CopyMessage::Skip`].
+    File((String, u32, i64, u64)),
+    /// Receiver → sender: the receiver holds `len` bytes of the file already,
+    /// with this SHA-256 digest.
+    Resume((u64, Vec<u8>)),
+    /// Receiver → sender: the file cannot be written; send nothing for it.
+    Skip,
+    /// Sender → receiver: file data follows from this offset, up to its size.
+    Start(u64),
+    /// Sender → receiver: the next bytes of the current file.
+    Data(Vec<u8>),
+    /// Sender → receiver: nothing more follows; the sender failed this many
+    /// entries.
+    End(u32),
+    /// Receiver → sender: everything is written; the receiver failed this
+    /// many entries.
+    Done(u32),
+}
+
+/// Send `sources` over `writer`, reading the receiver's answers from `reader`.
+/// Directories are sent with their contents when `recursive`, and refused
+/// otherwise.  `progress` is called with each file's path, the bytes sent so
+/// far and its size.
+///
+/// Returns what could not be copied; the receiver's failures are only
+/// counted.
+///
+/// # Errors
+/// * The stream fails or the receiver breaks the protocol.
+pub async fn send_files<R, W>(
+    sources: &[PathBuf],
+    recursive: bool,
+    mut reader: R,
+    mut writer: W,
+    mut progress: impl FnMut(&str, u64, u64),
+) -> Result<CopyOutcome>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let count = u32::try_from(sources.len())?;
+    write_message(&mut writer, &CopyMessage::Begin(count)).await?;
+    let mut outcome = CopyOutcome::default();
+    // Depth first, in name order, with each directory before its contents.
+    let mut pending: Vec<(PathBuf, String)> = Vec::new();
+    for source in sources.iter().rev() {
+        if let Some(name) = entry_name(source) {
+            pending.push((source.clone(), name));
+        } else {
+            outcome.fail(format!(
+                "{}: cannot copy a path without a name",
+                source.display()
+            ));
+        }
+    }
+    while let Some((path, rel)) = pending.pop() {
+        let metadata = match fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                outcome.fail(format!("{}: {e}", path.display()));
+                continue;
+            }
+        };
+        let mode = mode_of(&metadata);
+        let mtime = mtime_of(&metadata);
+        if metadata.is_dir() {
+            if !recursive {
+                outcome.fail(format!(
+                    "{}: is a directory (not copied without -r)",
+                    path.display()
+                ));
+                continue;
+            }
+            let children = match read_dir_sorted(&path, &mut outcome).await {
+                Ok(children) => children,
+                Err(e) => {
+                    outcome.fail(format!("{}: {e}", path.display()));
+                    continue;
+                }
+            };
+            write_message(&mut writer, &CopyMessage::Dir((rel.clone(), mode, mtime))).await?;
+            for name in children.into_iter().rev() {
+                pending.push((path.join(&name), format!("{rel}/{name}")));
+            }
+        } else if metadata.is_file() {
+            let mut file = match File::open(&path).await {
+                Ok(file) => file,
+                Err(e) => {
+                    outcome.fail(format!("{}: {e}", path.display()));
+                    continue;
+                }
+            };
+            let size = metadata.len();
+            write_message(
+                &mut writer,
+                &CopyMessage::File((rel.clone(), mode, mtime, size)),
+            )
+            .await?;
+            writer.flush().await?;
+            let (held, digest) = match read_message(&mut reader).await? {
+                CopyMessage::Resume(resume) => resume,
+                CopyMessage::Skip => continue,
+                _ => return Err(MoshpitError::InvalidFrame.into()),
+            };
+            if !send_file(
+                &mut file,
+                &rel,
+                size,
+                held,
+                &digest,
+                &mut writer,
+                &mut progress,
+            )
+            .await?
+            {
+                outcome.fail(format!(
+                    "{}: file shrank while being copied",
+                    path.display()
+                ));
+            }
+        } else {
+            outcome.fail(format!(
+                "{}: not a regular file or directory",
+                path.display()
+            ));
+        }
+    }
+    write_message(&mut writer, &CopyMessage::End(outcome.failed)).await?;
+    writer.flush().await?;
+    let CopyMessage::Done(receiver_failed) = read_message(&mut reader).await? else {
+        return Err(MoshpitError::InvalidFrame.into());
+    };
+    outcome.peer_failed = receiver_failed;
+    Ok(outcome)
+}
+
+/// Send the data of one file from where the receiver's copy stops matching.
+/// Returns `false` when the file ended before `size`; the rest is then sent
+/// as zeros so the receiver still gets the length it was promised.
+async fn send_file<W>(
+    file: &mut File,
+    rel: &str,
+    size: u64,
+    held: u64,
+    digest: &[u8],
+    writer: &mut W,
+    progress: &mut impl FnMut(&str, u64, u64),
+) -> Result<bool>
+where
+    W: AsyncWrite + Unpin,
+{
+    let start = if held <= size && prefix_digest(file, held).await? == digest {
+        held
+    } else {
+        0
+    };
+    write_message(writer, &CopyMessage::Start(start)).await?;
+    let _position = file.seek(SeekFrom::Start(start)).await?;
+    let mut sent = start;
+    let mut complete = true;
+    let mut buffer = vec![0u8; CHUNK_LEN];
+    progress(rel, sent, size);
+    while sent < size {
+        let want = usize::try_from((size - sent).min(CHUNK_LEN as u64))?;
+        let read = if complete {
+            file.read(&mut buffer[..want]).await?
+        } else {
+            0
+        };
+        let chunk = if read == 0 {
+            complete = false;
+            vec![0u8; want]
+        } else {
+            buffer[..read].to_vec()
+        };
+        sent += chunk.len() as u64;
+        write_message(writer, &CopyMessage::Data(chunk)).await?;
+        progress(rel, sent, size);
+    }
+    Ok(complete)
+}
+
+/// Receive into `target` what the sender sends over `reader`, answering it on
+/// `writer`.  A single top-level entry is written as `target` itself unless
+/// `target` is an existing directory; several go inside it.  `progress` is
+/// called with each file's path, the bytes written so far and its size.
+///
+/// Files are received into partial files named with [`PARTIAL_SUFFIX`] and
+/// renamed into place once complete, replacing what was there.  Returns what
+/// could not be copied; the sender's failures are only counted.
+///
+/// # Errors
+/// * The stream fails or the sender breaks the protocol, including sending a
+///   path that leaves `target`.
+pub async fn receive_files<R, W>(
+    target: &Path,
+    mut reader: R,
+    mut writer: W,
+    mut progress: impl FnMut(&str, u64, u64),
+) -> Result<CopyOutcome>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let CopyMessage::Begin(count) = read_message(&mut reader).await? else {
+        return Err(MoshpitError::InvalidFrame.into());
+    };
+    let into_target = fs::metadata(target)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir());
+    let usable = into_target || count <= 1;
+    let mut outcome = CopyOutcome::default();
+    if !usable {
+        outcome
+            .errors
+            .push(format!("{}: not a directory", target.display()));
+    }
+    // Directory modes and times are applied last, once nothing more is
+    // written inside them, deepest first.
+    let mut directories: Vec<(PathBuf, u32, i64)> = Vec::new();
+    loop {
+        match read_message(&mut reader).await? {
+            CopyMessage::Dir((rel, mode, mtime)) => {
+                let path = destination(target, &rel, into_target)?;
+                if !usable {
+                    outcome.failed = outcome.failed.saturating_add(1);
+                    continue;
+                }
+                match create_dir(&path).await {
+                    Ok(()) => directories.push((path, mode, mtime)),
+                    Err(e) => outcome.fail(format!("{}: {e}", path.display())),
+                }
+            }
+            CopyMessage::File((rel, mode, mtime, size)) => {
+                let path = destination(target, &rel, into_target)?;
+                let partial = partial_path(&path);
+                let file = if usable {
+                    match open_partial(&partial).await {
+                        Ok(file) => Some(file),
+                        Err(e) => {
+                            outcome.fail(format!("{}: {e}", partial.display()));
+                            None
+                        }
+                    }
+                } else {
+                    outcome.failed = outcome.failed.saturating_add(1);
+                    None
+                };
+                let Some(mut file) = file else {
+                    write_message(&mut writer, &CopyMessage::Skip).await?;
+                    writer.flush().await?;
+                    continue;
+                };
+                let held = file.metadata().await?.len().min(size);
+                let digest = prefix_digest(&mut file, held).await?;
+                write_message(&mut writer, &CopyMessage::Resume((held, digest))).await?;
+                writer.flush().await?;
+                let written = receive_file(file, &rel, size, &mut reader, &mut progress).await?;
+                let finished = match written {
+                    Ok(file) => match finish_entry(file, mode, mtime).await {
+                        Ok(()) => fs::rename(&partial, &path).await,
+                        Err(e) => Err(e),
+                    },
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = finished {
+                    outcome.fail(format!("{}: {e}", path.display()));
+                }
+            }
+            CopyMessage::End(sender_failed) => {
+                for (path, mode, mtime) in directories.iter().rev() {
+                    let finished = match File::open(path).await {
+                        Ok(dir) => finish_entry(dir, *mode, *mtime).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = finished {
+                        outcome.fail(format!("{}: {e}", path.display()));
+                    }
+                }
+                write_message(&mut writer, &CopyMessage::Done(outcome.failed)).await?;
+                writer.flush().await?;
+                outcome.peer_failed = sender_failed;
+                return Ok(outcome);
+            }
+            _ => return Err(MoshpitError::InvalidFrame.into()),
+        }
+    }
+}
+
+/// Write the data of one file.  The outer error is a broken stream; the
+/// inner one a local write failure, after which the data is still consumed
+/// so the transfer can go on.
+async fn receive_file<R>(
+    mut file: File,
+    rel: &str,
+    size: u64,
+    reader: &mut R,
+    progress: &mut impl FnMut(&str, u64, u64),
+) -> Result<Result<File, IoError>>
+where
+    R: AsyncRead + Unpin,
+{
+    let CopyMessage::Start(start) = read_message(reader).await? else {
+        return Err(MoshpitError::InvalidFrame.into());
+    };
+    if start > size {
+        return Err(MoshpitError::InvalidFrame.into());
+    }
+    let mut local = async {
+        file.set_len(start).await?;
+        let _position = file.seek(SeekFrom::Start(start)).await?;
+        Ok::<(), IoError>(())
+    }
+    .await;
+    let mut received = start;
+    progress(rel, received, size);
+    while received < size {
+        let CopyMessage::Data(chunk) = read_message(reader).await? else {
+            return Err(MoshpitError::InvalidFrame.into());
+        };
+        received += chunk.len() as u64;
+        if received > size {
+            return Err(MoshpitError::InvalidFrame.into());
+        }
+        if local.is_ok() {
+            local = file.write_all(&chunk).await;
+        }
+        progress(rel, received, size);
+    }
+    let flushed = file.flush().await;
+    Ok(local.and(flushed).map(|()| file))
+}
+
+/// The partial file `path` is received into.
+fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_os_string();
+    partial.push(PARTIAL_SUFFIX);
+    PathBuf::from(partial)
+}
+
+/// Open the partial file at `path`, keeping what an interrupted copy left in
+/// it.  A new one is only readable by its owner until it is complete.
+async fn open_partial(path: &Path) -> Result<File, IoError> {
+    let mut options = OpenOptions::new();
+    let _ = options.read(true).write(true).create(true).truncate(false);
+    #[cfg(unix)]
+    let _ = options.mode(0o600);
+    options.open(path).await
+}
+
+/// Give a received entry the sender's permission bits and modification time.
+async fn finish_entry(file: File, mode: u32, mtime: i64) -> Result<(), IoError> {
+    let file = file.into_std().await;
+    let modified = if mtime >= 0 {
+        UNIX_EPOCH + Duration::from_secs(mtime.unsigned_abs())
+    } else {
+        UNIX_EPOCH - Duration::from_secs(mtime.unsigned_abs())
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        file.set_permissions(std::fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    match file.set_modified(modified) {
+        // Directories cannot be opened for writing everywhere; their time is
+        // only a courtesy.
+        Err(e) if file.metadata()?.is_dir() && e.kind() == ErrorKind::PermissionDenied => Ok(()),
+        result => result,
+    }
+}
+
+/// Where `rel`, a path from the sender, goes under `target`.  Without
+/// `into_target` the top-level entry is `target` itself.
+fn destination(target: &Path, rel: &str, into_target: bool) -> Result<PathBuf> {
+    let mut path = target.to_path_buf();
+    for (i, part) in rel.split('/').enumerate() {
+        let mut components = Path::new(part).components();
+        let (Some(Component::Normal(name)), None) = (components.next(), components.next()) else {
+            return Err(MoshpitError::UnsafeCopyPath.into());
+        };
+        if i > 0 || into_target {
+            path.push(name);
+        }
+    }
+    Ok(path)
+}
+
+/// The name a top-level source is sent under: its last component, or for
+/// `.`-like paths the name of the directory it stands for.
+fn entry_name(source: &Path) -> Option<String> {
+    let name = match source.file_name() {
+        Some(name) => name.to_os_string(),
+        None => std::path::absolute(source)
+            .ok()?
+            .canonicalize()
+            .ok()?
+            .file_name()?
+            .to_os_string(),
+    };
+    name.into_string().ok()
+}
+
+/// Create the directory at `path`, which may already exist.
+async fn create_dir(path: &Path) -> Result<(), IoError> {
+    match fs::create_dir(path).await {
+        Err(e) if e.kind() == ErrorKind::AlreadyExists && fs::metadata(path).await?.is_dir() => {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// The names in directory `path`, sorted.  Names that are not valid UTF-8
+/// cannot be sent and are left out as failures in `outcome`.
+async fn read_dir_sorted(path: &Path, outcome: &mut CopyOutcome) -> Result<Vec<String>, IoError> {
+    let mut entries = fs::read_dir(path).await?;
+    let mut names = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        match entry.file_name().into_string() {
+            Ok(name) => names.push(name),
+            Err(name) => outcome.fail(format!(
+                "{}: skipping a name that is not UTF-8",
+                path.join(name).display()
+            )),
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// SHA-256 of the first `len` bytes of `file`.
+async fn prefix_digest(file: &mut File, len: u64) -> Result<Vec<u8>> {
+    let _position = file.seek(SeekFrom::Start(0)).await?;
+    let mut context = DigestContext::new(&SHA256);
+    let mut buffer = vec![0u8; CHUNK_LEN];
+    let mut left = len;
+    while left > 0 {
+        let want = usize::try_from(left.min(CHUNK_LEN as u64))?;
+        let read = file.read(&mut buffer[..want]).await?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buffer[..read]);
+        left -= read as u64;
+    }
+    Ok(context.finish().as_ref().to_vec())
+}
+
+#[cfg(unix)]
+fn mode_of(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt as _;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode_of(metadata: &std::fs::Metadata) -> u32 {
+    if metadata.is_dir() {
+        DEFAULT_DIR_MODE
+    } else {
+        DEFAULT_FILE_MODE
+    }
+}
+
+/// Modification time in whole seconds since the Unix epoch.
+fn mtime_of(metadata: &std::fs::Metadata) -> i64 {
+    match metadata
+        .modified()
+        .map(|time| time.duration_since(UNIX_EPOCH))
+    {
+        Ok(Ok(since)) => i64::try_from(since.as_secs()).unwrap_or(i64::MAX),
+        Ok(Err(before)) => i64::try_from(before.duration().as_secs()).map_or(i64::MIN, |s| -s),
+        Err(_) => i64::try_from(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+        )
+        .unwrap_or(i64::MAX),
+    }
+}
+
+async fn write_message<W>(writer: &mut W, message: &CopyMessage) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let encoded = encode_to_vec(message, standard())?;
+    let len = u32::try_from(encoded.len())?;
+    writer.write_all(&len.to_be_bytes()).await?;
+    writer.write_all(&encoded).await?;
+    Ok(())
+}
+
+async fn read_message<R>(reader: &mut R) -> Result<CopyMessage>
+where
+    R: AsyncRead + Unpin,
+{
+    let len = reader
+        .read_u32()
+        .await
+        .context("the other end of the copy went away")? as usize;
+    if len > CHUNK_LEN * 2 {
+        return Err(MoshpitError::FrameTooLarge.into());
+    }
+    let mut buf = vec![0u8; len];
+    let _read = reader.read_exact(&mut buf).await?;
+    decode_frame(&buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{create_dir_all, metadata, read, write},
+        path::{Path, PathBuf},
+        slice::from_ref,
+    };
+
+    use anyhow::Result;
+    use tempfile::TempDir;
+    use tokio::io::{duplex, split};
+
+    use super::{PARTIAL_SUFFIX, destination, receive_files, send_files};
+
+    /// Copy `sources` into `target` through an in-memory stream pair.
+    async fn copy(sources: &[PathBuf], recursive: bool, target: &Path) -> Result<(u32, u32)> {
+        Ok(copy_with_starts(sources, recursive, target).await?.0)
+    }
+
+    /// Copy `sources` into `target`, also returning the offset each file was
+    /// sent from.
+    async fn copy_with_starts(
+        sources: &[PathBuf],
+        recursive: bool,
+        target: &Path,
+    ) -> Result<((u32, u32), Vec<u64>)> {
+        let (near, far) = duplex(64 * 1024);
+        let (near_read, near_write) = split(near);
+        let (far_read, far_write) = split(far);
+        let target = target.to_path_buf();
+        let far_end =
+            tokio::spawn(
+                async move { receive_files(&target, far_read, far_write, |_, _, _| {}).await },
+            );
+        let mut starts = Vec::new();
+        let mut current = String::new();
+        let record = |rel: &str, sent, _| {
+            if rel != current {
+                current = rel.to_string();
+                starts.push(sent);
+            }
+        };
+        let sent = send_files(sources, recursive, near_read, near_write, record).await?;
+        let received = far_end.await??;
+        Ok(((sent.not_copied(), received.not_copied()), starts))
+    }
+
+    #[test]
+    fn paths_from_the_sender_stay_inside_the_target() -> Result<()> {
+        let target = Path::new("/srv/in");
+        assert_eq!(destination(target, "a/b", true)?, target.join("a/b"));
+        assert_eq!(destination(target, "a/b", false)?, target.join("b"));
+        assert!(destination(target, "../etc", true).is_err());
+        assert!(destination(target, "a/../../etc", true).is_err());
+        assert!(destination(target, "/etc", true).is_err());
+        assert!(destination(target, "a//b", true).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn directories_copy_recursively_with_their_metadata() -> Result<()> {
+        let from = TempDir::new()?;
+        let to = TempDir::new()?;
+        let tree = from.path().join("tree");
+        create_dir_all(tree.join("sub"))?;
+        write(tree.join("a.txt"), b"alpha")?;
+        write(tree.join("sub/b.bin"), vec![7u8; 100_000])?;
+        #[cfg(unix)]
+        {
+            use std::{
+                fs::{Permissions, set_permissions},
+                os::unix::fs::PermissionsExt as _,
+            };
+            set_permissions(tree.join("a.txt"), Permissions::from_mode(0o640))?;
+        }
+
+        assert_eq!(copy(from_ref(&tree), true, to.path()).await?, (0, 0));
+        let copied = to.path().join("tree");
+        assert_eq!(read(copied.join("a.txt"))?, b"alpha");
+        assert_eq!(read(copied.join("sub/b.bin"))?, vec![7u8; 100_000]);
+        assert_eq!(
+            metadata(copied.join("a.txt"))?
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs(),
+            metadata(tree.join("a.txt"))?
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs()
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            assert_eq!(
+                metadata(copied.join("a.txt"))?.permissions().mode() & 0o777,
+                0o640
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn directories_need_recursive() -> Result<()> {
+        let from = TempDir::new()?;
+        let to = TempDir::new()?;
+        let dir = from.path().join("dir");
+        create_dir_all(&dir)?;
+        assert_eq!(copy(&[dir], false, to.path()).await?, (1, 1));
+        assert!(!to.path().join("dir").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_single_file_can_be_renamed() -> Result<()> {
+        let from = TempDir::new()?;
+        let to = TempDir::new()?;
+        let file = from.path().join("report.txt");
+        write(&file, b"numbers")?;
+        let renamed = to.path().join("copy.txt");
+        assert_eq!(copy(&[file], false, &renamed).await?, (0, 0));
+        assert_eq!(read(renamed)?, b"numbers");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_partial_files_left_by_a_copy_resume() -> Result<()> {
+        let from = TempDir::new()?;
+        let to = TempDir::new()?;
+        let contents: Vec<u8> = (0..=250u8).cycle().take(200_000).collect();
+        let file = from.path().join("big");
+        write(&file, &contents)?;
+        let copied = to.path().join("big");
+        let partial = to.path().join(format!("big{PARTIAL_SUFFIX}"));
+
+        // A prefix left by an interrupted copy is kept, completed and moved
+        // into place.
+        write(&partial, &contents[..70_000])?;
+        let (failed, starts) = copy_with_starts(from_ref(&file), false, to.path()).await?;
+        assert_eq!((failed, starts), ((0, 0), vec![70_000]));
+        assert_eq!(read(&copied)?, contents);
+        assert!(!partial.exists());
+
+        // A stale partial file is rewritten from the start.
+        write(&partial, b"something else entirely")?;
+        let (failed, starts) = copy_with_starts(from_ref(&file), false, to.path()).await?;
+        assert_eq!((failed, starts), ((0, 0), vec![0]));
+        assert_eq!(read(&copied)?, contents);
+
+        // A longer partial file is cut back to the source's length.
+        let mut longer = contents.clone();
+        longer.extend_from_slice(b"tail");
+        write(&partial, &longer)?;
+        assert_eq!(copy(from_ref(&file), false, to.path()).await?, (0, 0));
+        assert_eq!(read(&copied)?, contents);
+
+        // A file the copy did not write is never resumed, only replaced.
+        write(&copied, &contents[..70_000])?;
+        let (failed, starts) = copy_with_starts(&[file], false, to.path()).await?;
+        assert_eq!((failed, starts), ((0, 0), vec![0]));
+        assert_eq!(read(&copied)?, contents);
+        assert!(!partial.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn several_sources_need_a_directory() -> Result<()> {
+        let from = TempDir::new()?;
+        let to = TempDir::new()?;
+        let one = from.path().join("one");
+        let two = from.path().join("two");
+        write(&one, b"1")?;
+        write(&two, b"2")?;
+        let missing = to.path().join("missing");
+        let (sent, received) = copy(&[one, two], false, &missing).await?;
+        assert_eq!((sent, received), (2, 2));
+        assert!(!missing.exists());
+        Ok(())
+    }
+}
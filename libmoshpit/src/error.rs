@@ -3,6 +3,8 @@
 use clap::error::ErrorKind;
 use thiserror::Error;
 
+use crate::kex::failure::KexFailureReason;
+
 /// Errors that can occur in moshpit
 #[derive(Clone, Copy, Debug, Error, Eq, PartialEq)]
 pub enum Error {
@@ -72,6 +74,28 @@ pub enum Error {
     /// An invalid server destination format was provided
     #[error("An invalid server destination format was provided")]
     InvalidServerDestination,
+    /// An invalid port-forwarding specification was provided
+    #[error(
+        "An invalid port forwarding specification was provided, expected [bind:]port:host:hostport"
+    )]
+    InvalidForwardSpec,
+    /// An invalid dynamic-forwarding specification was provided
+    #[error("An invalid dynamic forwarding specification was provided, expected [bind:]port")]
+    InvalidDynamicForwardSpec,
+    /// A Unix-domain socket forward was requested on a platform without them
+    #[error("Unix domain sockets are not supported on this platform")]
+    UnixSocketsUnsupported,
+    /// A SOCKS client sent a request the dynamic forward cannot serve
+    #[error("Unsupported or malformed SOCKS request")]
+    InvalidSocksRequest,
+    /// `mp cp` was given operands it cannot copy between
+    #[error(
+        "mp cp copies local files to [user@]host:path or remote files to a local path, on one host"
+    )]
+    InvalidCopyOperands,
+    /// The sending end of a file copy named a path outside the destination
+    #[error("File copy path escapes its destination")]
+    UnsafeCopyPath,
     /// A frame was received that exceeds the maximum allowed length
     #[error("Frame too large")]
     FrameTooLarge,
@@ -93,6 +117,21 @@ pub enum Error {
     /// The client and server have no overlapping supported wire protocol version
     #[error("Incompatible wire protocol version")]
     IncompatibleProtocolVersion,
+    /// The server host name did not resolve to any usable address
+    #[error("Server host name did not resolve to any address")]
+    HostResolutionFailed,
+    /// The session key ratchet has no further epochs to move to
+    #[error("Session key epoch counter exhausted")]
+    RekeyEpochExhausted,
+    /// The peer stopped answering the UDP handshake transport
+    #[error("UDP handshake peer did not respond")]
+    UdpHandshakeTimedOut,
+    /// The server is too old to read the early key share the client sent
+    #[error("server does not support early key shares")]
+    EarlyKeyShareUnsupported,
+    /// The server rejected the key exchange for the given reason
+    #[error("Server rejected the key exchange: {0}")]
+    KexRejected(KexFailureReason),
 }
 
 /// Converts an `anyhow::Error` into a suitable exit code or clap message for a CLI application.
@@ -0,0 +1,277 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Acting for the session's account (the `session_user` of a
+//! [`ForwardPolicy`](crate::ForwardPolicy)) when moshpits runs as root.
+//!
+//! Every socket the mux dials or binds on the account's behalf is handled by a
+//! child process running as that account, so the kernel applies the account's
+//! own access rather than root's.  TCP sockets are created in the child as
+//! well and passed back, since a socket's owner, which firewall rules such as
+//! `iptables -m owner` and local peers looking a connection up go by, is fixed
+//! when it is created.
+
+use std::{
+    io::{Error, ErrorKind, Result as IoResult},
+    mem::zeroed,
+    net::{SocketAddr, TcpStream as StdTcpStream},
+    os::{
+        fd::{AsRawFd as _, FromRawFd as _, OwnedFd, RawFd},
+        unix::net::UnixStream as StdUnixStream,
+    },
+};
+
+use socket2::{Domain, SockAddr, Socket, Type};
+use tokio::{
+    net::{TcpStream, lookup_host},
+    task::spawn_blocking,
+};
+
+use crate::forward::policy::ForwardUser;
+
+/// Room for the control message carrying one file descriptor, aligned for its
+/// header.
+type ControlBuffer = [u64; 8];
+
+/// Connect to `host:port` as `user`, trying each address `host` resolves to in
+/// turn.
+///
+/// # Errors
+/// * `host` cannot be resolved.
+/// * No address can be connected to; the error is the last one seen.
+pub(crate) async fn connect_tcp(host: &str, port: u16, user: &ForwardUser) -> IoResult<TcpStream> {
+    let mut last = None;
+    for address in lookup_host((host, port)).await? {
+        match connect_tcp_address(address, user.clone()).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last.unwrap_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
+        )
+    }))
+}
+
+async fn connect_tcp_address(address: SocketAddr, user: ForwardUser) -> IoResult<TcpStream> {
+    let socket = spawn_blocking(move || start_tcp_connect(address, &user))
+        .await
+        .map_err(Error::other)??;
+    let stream = TcpStream::from_std(StdTcpStream::from(socket))?;
+    stream.writable().await?;
+    match stream.take_error()? {
+        Some(e) => Err(e),
+        None => Ok(stream),
+    }
+}
+
+/// Create a nonblocking TCP socket as `user` and start connecting it to
+/// `address`; the connection may still be in progress when this returns.
+fn start_tcp_connect(address: SocketAddr, user: &ForwardUser) -> IoResult<OwnedFd> {
+    let target = SockAddr::from(address);
+    let (receiver, sender) = StdUnixStream::pair()?;
+    as_user(Some(user), || {
+        let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+        socket.set_nonblocking(true)?;
+        if let Err(e) = socket.connect(&target)
+            && e.raw_os_error() != Some(libc::EINPROGRESS)
+        {
+            return Err(e);
+        }
+        send_fd(&sender, socket.as_raw_fd())
+    })?;
+    receive_fd(&receiver)
+}
+
+/// Pass `fd` over `stream`.  Makes system calls only, so it is safe in a
+/// forked child.
+#[allow(unsafe_code)]
+fn send_fd(stream: &StdUnixStream, fd: RawFd) -> IoResult<()> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr().cast(),
+        iov_len: byte.len(),
+    };
+    let mut control: ControlBuffer = [0; 8];
+    let fd_len =
+        u32::try_from(size_of::<RawFd>()).map_err(|_| Error::from(ErrorKind::InvalidInput))?;
+    // SAFETY: `msg` points at `iov` and `control`, which outlive the call and
+    // have room for the one header and descriptor written through `cmsg`.
+    let sent = unsafe {
+        let mut msg: libc::msghdr = zeroed();
+        msg.msg_iov = &raw mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        // The field types vary between platforms.
+        msg.msg_controllen = libc::CMSG_SPACE(fd_len) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&raw const msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fd_len) as _;
+        libc::CMSG_DATA(cmsg).cast::<RawFd>().write_unaligned(fd);
+        libc::sendmsg(stream.as_raw_fd(), &raw const msg, 0)
+    };
+    if sent < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Receive a file descriptor sent by [`send_fd`], kept out of the programs
+/// this process starts.
+#[allow(unsafe_code)]
+fn receive_fd(stream: &StdUnixStream) -> IoResult<OwnedFd> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr().cast(),
+        iov_len: byte.len(),
+    };
+    let mut control: ControlBuffer = [0; 8];
+    let fd_len =
+        u32::try_from(size_of::<RawFd>()).map_err(|_| Error::from(ErrorKind::InvalidInput))?;
+    // SAFETY: `msg` points at `iov` and `control`, which outlive the call, and
+    // the kernel fills in at most `msg_controllen` bytes of the latter.
+    let fd = unsafe {
+        let mut msg: libc::msghdr = zeroed();
+        msg.msg_iov = &raw mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        // The field types vary between platforms.
+        msg.msg_controllen = libc::CMSG_SPACE(fd_len) as _;
+        if libc::recvmsg(stream.as_raw_fd(), &raw mut msg, 0) < 0 {
+            return Err(Error::last_os_error());
+        }
+        let cmsg = libc::CMSG_FIRSTHDR(&raw const msg);
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+        {
+            return Err(Error::other("no socket came back from the socket helper"));
+        }
+        OwnedFd::from_raw_fd(libc::CMSG_DATA(cmsg).cast::<RawFd>().read_unaligned())
+    };
+    // SAFETY: `fd` is open.
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+/// Run `action` as `user`.
+///
+/// Without a `user`, or when this process already runs as `user`, `action`
+/// runs here.  Otherwise it runs in a forked child that first drops to the
+/// account's groups and ids.  The child only makes system calls between `fork`
+/// and `_exit`, so `action` must not allocate or lock, and reports the error
+/// `action` returns, if any, as its exit status.
+#[allow(unsafe_code)]
+pub(crate) fn as_user<F>(user: Option<&ForwardUser>, action: F) -> IoResult<()>
+where
+    F: FnOnce() -> IoResult<()>,
+{
+    // SAFETY: `geteuid` has no preconditions and cannot fail.
+    let Some(user) = user.filter(|user| unsafe { libc::geteuid() } != user.uid()) else {
+        return action();
+    };
+    let groups = user.groups();
+    #[cfg(target_os = "linux")]
+    let group_count = groups.len();
+    #[cfg(not(target_os = "linux"))]
+    let group_count =
+        libc::c_int::try_from(groups.len()).map_err(|_| Error::from(ErrorKind::InvalidInput))?;
+    // SAFETY: the child touches no locks or allocations before `_exit`.
+    let child = unsafe { libc::fork() };
+    if child < 0 {
+        return Err(Error::last_os_error());
+    }
+    if child == 0 {
+        // SAFETY: plain system calls on values prepared before the fork.
+        let dropped = unsafe {
+            libc::setgroups(group_count, groups.as_ptr()) == 0
+                && libc::setgid(user.gid()) == 0
+                && libc::setuid(user.uid()) == 0
+        };
+        let result = if dropped {
+            action()
+        } else {
+            Err(Error::last_os_error())
+        };
+        let status = result.err().map_or(0, |e| {
+            e.raw_os_error()
+                .filter(|errno| (1..=255).contains(errno))
+                .unwrap_or(libc::EIO)
+        });
+        // SAFETY: ends the child without running the parent's destructors.
+        unsafe { libc::_exit(status) }
+    }
+    let mut status = 0;
+    // SAFETY: waits for the child forked above, which nothing else reaps.
+    while unsafe { libc::waitpid(child, &raw mut status, 0) } < 0 {
+        let e = Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    match libc::WIFEXITED(status).then(|| libc::WEXITSTATUS(status)) {
+        Some(0) => Ok(()),
+        Some(errno) => Err(Error::from_raw_os_error(errno)),
+        None => Err(Error::other("socket helper was killed")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt as _;
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+    use tokio::net::TcpListener;
+
+    use super::connect_tcp;
+    use crate::forward::policy::ForwardUser;
+
+    #[tokio::test]
+    async fn tcp_connects_as_this_process() -> Result<()> {
+        let meta = tempdir()?.path().metadata()?;
+        let user = ForwardUser::new(meta.uid(), meta.gid(), vec![]);
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+
+        let stream = connect_tcp("127.0.0.1", port, &user).await?;
+        let (peer, _) = listener.accept().await?;
+        assert_eq!(peer.peer_addr()?, stream.local_addr()?);
+
+        drop(listener);
+        assert!(connect_tcp("127.0.0.1", port, &user).await.is_err());
+        Ok(())
+    }
+
+    /// The connecting socket belongs to the session's account, not root.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn tcp_connects_as_the_session_user() -> Result<()> {
+        use std::{fs::metadata, os::fd::AsRawFd as _};
+
+        if tempdir()?.path().metadata()?.uid() != 0 {
+            // Only root can act for another account.
+            return Ok(());
+        }
+        let nobody = ForwardUser::new(65_534, 65_534, vec![]);
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+
+        let stream = connect_tcp("127.0.0.1", port, &nobody).await?;
+        let _peer = listener.accept().await?;
+        let socket = metadata(format!("/proc/self/fd/{}", stream.as_raw_fd()))?;
+        assert_eq!(socket.uid(), 65_534);
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Agent forwarding (`mp --forward-agent`).
+//!
+//! The server listens on a per-session socket in a private directory and
+//! opens an [`EncryptedFrame::ForwardOpenAgent`](crate::EncryptedFrame::ForwardOpenAgent)
+//! channel for every connection to it.  The client relays the agent requests
+//! read from such a channel to its own `mpa`, one at a time, and writes the
+//! responses back.  Only requests that use a loaded key are relayed; the
+//! others are answered with an error without reaching the agent, since
+//! whoever can reach the server's socket must not manage the client's keys.
+//! Key agreement is relayed only when the client opts in: it is how X25519,
+//! P-256 and P-384 identities prove possession during key exchange, but it
+//! also hands whoever can reach the socket a shared secret with every loaded
+//! key for any peer key they choose.
+
+use std::{env::temp_dir, path::PathBuf};
+
+use anyhow::Result;
+use bincode_next::{config::standard, decode_from_slice, encode_to_vec};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _},
+    net::UnixListener,
+};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+    AgentClient, AgentRequest, AgentResponse,
+    forward::{
+        policy::ForwardUser,
+        unix::{SocketFile, bind_private},
+    },
+};
+
+/// Name of the socket inside the session's private directory.
+const AGENT_SOCKET_NAME: &str = "agent.sock";
+
+/// Largest agent request relayed; longer ones end the channel.
+const MAX_REQUEST_LEN: usize = 1024 * 1024;
+
+/// Whether `request` may be relayed from a forwarded agent socket: listing
+/// identities, fetching their public keys and signing with them, and agreeing
+/// keys with them when `allow_agree` is set, but never loading, removing or
+/// unlocking keys.
+pub(crate) fn is_forwardable(request: &AgentRequest, allow_agree: bool) -> bool {
+    match request {
+        AgentRequest::ListSupportedIdentities { .. }
+        | AgentRequest::GetPublicKey(_)
+        | AgentRequest::Sign { .. } => true,
+        AgentRequest::Agree { .. } => allow_agree,
+        _ => false,
+    }
+}
+
+/// Listen on a new agent socket for a session, on behalf of `user` when set.
+///
+/// # Errors
+/// * The private directory or the socket in it cannot be created.
+pub(crate) fn bind(user: Option<&ForwardUser>) -> Result<(UnixListener, SocketFile)> {
+    let dir = temp_dir().join(format!("moshpit-agent-{}", Uuid::new_v4()));
+    bind_private(&dir, AGENT_SOCKET_NAME, user)
+}
+
+/// Answer the agent requests read from `stream` through the agent at `socket`
+/// until the stream ends, relaying key agreement only when `allow_agree` is
+/// set.
+pub(crate) async fn relay<S>(mut stream: S, socket: PathBuf, allow_agree: bool)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let agent = AgentClient::new(socket);
+    if let Err(e) = relay_requests(&mut stream, &agent, allow_agree).await {
+        debug!("forwarded agent channel closed: {e}");
+    }
+}
+
+async fn relay_requests<S>(stream: &mut S, agent: &AgentClient, allow_agree: bool) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(request) = read_request(stream).await? {
+        let response = if is_forwardable(&request, allow_agree) {
+            agent.send(&request).await.unwrap_or_else(|e| {
+                debug!("cannot reach the agent for a forwarded request: {e}");
+                AgentResponse::Error("agent unavailable".to_string())
+            })
+        } else {
+            debug!(
+                "refusing forwarded agent request {}",
+                request_name(&request)
+            );
+            AgentResponse::Error("request not permitted through a forwarded agent".to_string())
+        };
+        let encoded = encode_to_vec(&response, standard())?;
+        stream
+            .write_all(&u32::try_from(encoded.len())?.to_be_bytes())
+            .await?;
+        stream.write_all(&encoded).await?;
+        stream.flush().await?;
+    }
+    Ok(())
+}
+
+/// The next request on `stream`, or `None` once it ends between requests.
+async fn read_request<S>(stream: &mut S) -> Result<Option<AgentRequest>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut len = [0u8; 4];
+    if stream.read(&mut len[..1]).await? == 0 {
+        return Ok(None);
+    }
+    let _ = stream.read_exact(&mut len[1..]).await?;
+    let len = usize::try_from(u32::from_be_bytes(len))?;
+    if len == 0 || len > MAX_REQUEST_LEN {
+        return Ok(None);
+    }
+    let mut buf = vec![0u8; len];
+    let _ = stream.read_exact(&mut buf).await?;
+    let (request, _) = decode_from_slice::<AgentRequest, _>(&buf, standard())?;
+    Ok(Some(request))
+}
+
+/// The variant name of `request`, without its arguments, which may hold a
+/// passphrase.
+fn request_name(request: &AgentRequest) -> &'static str {
+    match request {
+        AgentRequest::ListIdentities => "ListIdentities",
+        AgentRequest::ListSupportedIdentities { .. } => "ListSupportedIdentities",
+        AgentRequest::GetPublicKey(_) => "GetPublicKey",
+        AgentRequest::Sign { .. } => "Sign",
+        AgentRequest::AddIdentity { .. } => "AddIdentity",
+        AgentRequest::RemoveIdentity(_) => "RemoveIdentity",
+        AgentRequest::RemoveAllIdentities => "RemoveAllIdentities",
+        AgentRequest::Lock => "Lock",
+        AgentRequest::Unlock(_) => "Unlock",
+        AgentRequest::Shutdown => "Shutdown",
+        AgentRequest::Status => "Status",
+        AgentRequest::Agree { .. } => "Agree",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use bincode_next::{config::standard, decode_from_slice, encode_to_vec};
+    use tempfile::tempdir;
+    use tokio::{
+        io::{AsyncReadExt as _, AsyncWriteExt as _, duplex},
+        spawn,
+    };
+
+    use super::{is_forwardable, relay};
+    use crate::{AgentRequest, AgentResponse};
+
+    fn agree() -> AgentRequest {
+        AgentRequest::Agree {
+            fingerprint: "SHA256:abcd".to_string(),
+            peer_public_key: vec![7; 32],
+        }
+    }
+
+    #[test]
+    fn only_requests_using_loaded_keys_are_forwardable() {
+        for allow_agree in [false, true] {
+            assert!(is_forwardable(
+                &AgentRequest::ListSupportedIdentities {
+                    supported_algorithms: vec!["X25519".to_string()],
+                },
+                allow_agree
+            ));
+            assert!(is_forwardable(
+                &AgentRequest::GetPublicKey("SHA256:abcd".to_string()),
+                allow_agree
+            ));
+            assert!(is_forwardable(
+                &AgentRequest::Sign {
+                    fingerprint: "SHA256:abcd".to_string(),
+                    data: vec![1, 2, 3],
+                },
+                allow_agree
+            ));
+            assert!(!is_forwardable(
+                &AgentRequest::AddIdentity {
+                    key_path: "/tmp/id".to_string(),
+                    passphrase: None,
+                },
+                allow_agree
+            ));
+            assert!(!is_forwardable(
+                &AgentRequest::Unlock("secret".to_string()),
+                allow_agree
+            ));
+            assert!(!is_forwardable(&AgentRequest::Shutdown, allow_agree));
+        }
+    }
+
+    #[test]
+    fn key_agreement_is_forwardable_only_when_allowed() {
+        assert!(!is_forwardable(&agree(), false));
+        assert!(is_forwardable(&agree(), true));
+    }
+
+    #[tokio::test]
+    async fn relay_refuses_key_management_without_asking_the_agent() -> Result<()> {
+        let dir = tempdir()?;
+        let (mut near, far) = duplex(4096);
+        let relayed = spawn(relay(far, dir.path().join("missing.sock"), false));
+
+        for request in [
+            AgentRequest::Unlock("secret".to_string()),
+            agree(),
+            AgentRequest::GetPublicKey("SHA256:abcd".to_string()),
+        ] {
+            let encoded = encode_to_vec(&request, standard())?;
+            near.write_all(&u32::try_from(encoded.len())?.to_be_bytes())
+                .await?;
+            near.write_all(&encoded).await?;
+            let mut buf = vec![0u8; near.read_u32().await? as usize];
+            let _ = near.read_exact(&mut buf).await?;
+            let (response, _) = decode_from_slice::<AgentResponse, _>(&buf, standard())?;
+            let AgentResponse::Error(message) = response else {
+                panic!("unexpected response {response:?}");
+            };
+            // Only the permitted request reaches (and misses) the agent.
+            assert_eq!(
+                message == "agent unavailable",
+                is_forwardable(&request, false)
+            );
+        }
+
+        drop(near);
+        relayed.await?;
+        Ok(())
+    }
+}
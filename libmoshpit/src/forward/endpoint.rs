@@ -0,0 +1,472 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! The two ends of a channel: what it dials, what it listens on, and the local
+//! stream it carries, each either TCP or a Unix-domain socket, for a
+//! forwarded agent, the relay to the local `mpa`, or for a remote command, one
+//! of its standard streams.
+
+use std::{
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    io::{ErrorKind, Result as IoResult},
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use anyhow::Result;
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use tokio::{io::duplex, spawn};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf, stderr, stdin, stdout},
+    net::{TcpListener, TcpStream},
+};
+
+#[cfg(not(unix))]
+use crate::MoshpitError;
+#[cfg(unix)]
+use crate::forward::{
+    account, agent,
+    unix::{self, SocketFile},
+};
+use crate::{
+    EncryptedFrame, ForwardSpec,
+    forward::{
+        listen::{bind_address_or_default, bind_listeners},
+        policy::ForwardUser,
+    },
+};
+
+/// Bytes buffered each way between a forwarded agent channel and its relay.
+#[cfg(unix)]
+const AGENT_RELAY_BUFFER: usize = 64 * 1024;
+
+/// What a channel connects to on the side that did not open it.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Dial {
+    /// `host:port`, for a fixed forward.
+    Tcp { host: String, port: u16 },
+    /// `host:port`, named by a SOCKS client waiting for its reply.
+    Socks { host: String, port: u16 },
+    /// A Unix-domain socket.
+    Unix(String),
+    /// The agent of the side that did not open the channel.
+    Agent,
+    /// Standard stream `fd` of the side that did not open the channel.
+    Stdio(u8),
+}
+
+impl Dial {
+    /// The target end of `spec`.
+    pub(crate) fn of_target(spec: &ForwardSpec) -> Self {
+        match spec.host_path() {
+            Some(path) => Dial::Unix(path.clone()),
+            None => Dial::Tcp {
+                host: spec.host().clone(),
+                port: spec.host_port(),
+            },
+        }
+    }
+
+    /// A target as carried in listen requests: `host:port`, or a socket path
+    /// with port 0.
+    pub(crate) fn from_wire(host: String, port: u16) -> Self {
+        if port == 0 {
+            Dial::Unix(host)
+        } else {
+            Dial::Tcp { host, port }
+        }
+    }
+
+    /// The inverse of [`Dial::from_wire`].
+    pub(crate) fn to_wire(&self) -> (String, u16) {
+        match self {
+            Dial::Tcp { host, port } | Dial::Socks { host, port } => (host.clone(), *port),
+            Dial::Unix(path) => (path.clone(), 0),
+            Dial::Agent | Dial::Stdio(_) => (String::new(), 0),
+        }
+    }
+
+    /// The frame that opens channel `id` to this target on the peer's side.
+    pub(crate) fn frame(&self, id: u32) -> EncryptedFrame {
+        match self {
+            Dial::Tcp { host, port } => EncryptedFrame::ForwardOpen((id, host.clone(), *port)),
+            Dial::Socks { host, port } => EncryptedFrame::ForwardConnect((id, host.clone(), *port)),
+            Dial::Unix(path) => EncryptedFrame::ForwardOpenUnix((id, path.clone())),
+            Dial::Agent => EncryptedFrame::ForwardOpenAgent(id),
+            Dial::Stdio(fd) => EncryptedFrame::ForwardOpenStdio((id, *fd)),
+        }
+    }
+
+    /// Connect to this target, as `user` when set: TCP targets from a socket
+    /// the account owns, Unix sockets with its access.  [`Dial::Agent`] connects
+    /// to a relay to the agent listening on `agent`, which passes on key
+    /// agreement only when `agent_agree` is set.
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub(crate) async fn connect(
+        &self,
+        user: Option<&ForwardUser>,
+        agent: Option<&Path>,
+        agent_agree: bool,
+    ) -> IoResult<LocalStream> {
+        match self {
+            Dial::Tcp { host, port } | Dial::Socks { host, port } => {
+                #[cfg(unix)]
+                if let Some(user) = user {
+                    let stream = account::connect_tcp(host, *port, user).await?;
+                    return Ok(LocalStream::Tcp(stream));
+                }
+                Ok(LocalStream::Tcp(
+                    TcpStream::connect((host.as_str(), *port)).await?,
+                ))
+            }
+            #[cfg(unix)]
+            Dial::Unix(path) => Ok(LocalStream::Unix(unix::connect(path, user).await?)),
+            #[cfg(unix)]
+            Dial::Agent => {
+                let socket = agent.ok_or(ErrorKind::NotFound)?.to_path_buf();
+                let (near, far) = duplex(AGENT_RELAY_BUFFER);
+                let _handle = spawn(agent::relay(far, socket, agent_agree));
+                Ok(LocalStream::Duplex(near))
+            }
+            Dial::Stdio(fd) => Ok(LocalStream::Stdio(StdioStream::local(*fd)?)),
+            #[cfg(not(unix))]
+            Dial::Unix(_) | Dial::Agent => Err(ErrorKind::Unsupported.into()),
+        }
+    }
+}
+
+impl Display for Dial {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Dial::Tcp { host, port } | Dial::Socks { host, port } => write!(f, "{host}:{port}"),
+            Dial::Unix(path) => write!(f, "{path}"),
+            Dial::Agent => write!(f, "the agent"),
+            Dial::Stdio(fd) => write!(f, "standard stream {fd}"),
+        }
+    }
+}
+
+/// What the peer asked this side to listen on.
+#[derive(Debug)]
+pub(crate) enum Bind {
+    /// A TCP port; an empty `address` stands for `localhost`.
+    Tcp { address: String, port: u16 },
+    /// A Unix-domain socket.
+    Unix(String),
+}
+
+impl Bind {
+    /// Bind the listeners, on behalf of `user` when set.
+    ///
+    /// # Errors
+    /// * The address cannot be resolved or bound.
+    /// * The socket path is not accessible to `user`, or Unix sockets are not
+    ///   supported on this platform.
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub(crate) async fn listen(&self, user: Option<&ForwardUser>) -> Result<Vec<Listener>> {
+        match self {
+            Bind::Tcp { address, port } => {
+                let listeners = bind_listeners(bind_address_or_default(address), *port).await?;
+                Ok(listeners.into_iter().map(Listener::Tcp).collect())
+            }
+            #[cfg(unix)]
+            Bind::Unix(path) => {
+                let (listener, file) = unix::bind(path, user)?;
+                Ok(vec![Listener::Unix(listener, file)])
+            }
+            #[cfg(not(unix))]
+            Bind::Unix(_) => Err(MoshpitError::UnixSocketsUnsupported.into()),
+        }
+    }
+}
+
+impl Display for Bind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Bind::Tcp { address, port } => {
+                write!(f, "{}:{port}", bind_address_or_default(address))
+            }
+            Bind::Unix(path) => write!(f, "{path}"),
+        }
+    }
+}
+
+/// A socket accepting connections for a forward.
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    /// Removes its socket file once dropped.
+    #[cfg(unix)]
+    Unix(UnixListener, SocketFile),
+}
+
+impl Listener {
+    /// Bind the Unix socket `path` as this process.
+    ///
+    /// # Errors
+    /// * The socket cannot be bound, or Unix sockets are not supported on this
+    ///   platform.
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub(crate) fn bind_unix(path: &str) -> Result<Self> {
+        #[cfg(unix)]
+        {
+            let (listener, file) = unix::bind(path, None)?;
+            Ok(Listener::Unix(listener, file))
+        }
+        #[cfg(not(unix))]
+        {
+            Err(MoshpitError::UnixSocketsUnsupported.into())
+        }
+    }
+
+    /// Bind a new agent socket in a private directory, on behalf of `user`
+    /// when set.
+    ///
+    /// # Errors
+    /// * The directory or socket cannot be created, or Unix sockets are not
+    ///   supported on this platform.
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub(crate) fn bind_agent(user: Option<&ForwardUser>) -> Result<Self> {
+        #[cfg(unix)]
+        {
+            let (listener, file) = agent::bind(user)?;
+            Ok(Listener::Unix(listener, file))
+        }
+        #[cfg(not(unix))]
+        {
+            Err(MoshpitError::UnixSocketsUnsupported.into())
+        }
+    }
+
+    /// Accept the next connection, with a description of where it came from.
+    pub(crate) async fn accept(&self) -> IoResult<(LocalStream, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((LocalStream::Tcp(stream), peer.to_string()))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, file) => {
+                let (stream, _peer) = listener.accept().await?;
+                Ok((LocalStream::Unix(stream), file.path().display().to_string()))
+            }
+        }
+    }
+
+    /// Where this listens, and its TCP port (0 for a Unix socket).
+    ///
+    /// # Errors
+    /// * The local address of a TCP listener cannot be read.
+    pub(crate) fn local(&self) -> IoResult<(String, u16)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let addr = listener.local_addr()?;
+                Ok((addr.to_string(), addr.port()))
+            }
+            #[cfg(unix)]
+            Listener::Unix(_, file) => Ok((file.path().display().to_string(), 0)),
+        }
+    }
+}
+
+/// The local stream a channel carries.
+#[derive(Debug)]
+pub(crate) enum LocalStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    /// The relay of a forwarded agent channel.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    Duplex(DuplexStream),
+    /// A standard stream of this process or of a remote command.
+    Stdio(StdioStream),
+}
+
+/// One standard stream seen as a channel's local stream: reads come from
+/// `read`, or end straight away without it, and writes go to `write`, or are
+/// discarded without it.  Shutting it down closes `write`, which is how a
+/// command sees the end of its standard input.
+pub(crate) struct StdioStream {
+    read: Option<Box<dyn AsyncRead + Send + Unpin>>,
+    write: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+}
+
+impl StdioStream {
+    pub(crate) fn new(
+        read: Option<Box<dyn AsyncRead + Send + Unpin>>,
+        write: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+    ) -> Self {
+        Self { read, write }
+    }
+
+    /// Standard stream `fd` of this process: input is read, output written.
+    fn local(fd: u8) -> IoResult<Self> {
+        match fd {
+            0 => Ok(Self::new(Some(Box::new(stdin())), None)),
+            1 => Ok(Self::new(None, Some(Box::new(stdout())))),
+            2 => Ok(Self::new(None, Some(Box::new(stderr())))),
+            _ => Err(ErrorKind::InvalidInput.into()),
+        }
+    }
+}
+
+impl Debug for StdioStream {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("StdioStream")
+            .field("read", &self.read.is_some())
+            .field("write", &self.write.is_some())
+            .finish()
+    }
+}
+
+impl AsyncRead for StdioStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        match &mut self.get_mut().read {
+            Some(read) => Pin::new(read).poll_read(cx, buf),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+impl AsyncWrite for StdioStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        match &mut self.get_mut().write {
+            Some(write) => Pin::new(write).poll_write(cx, buf),
+            None => Poll::Ready(Ok(buf.len())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        match &mut self.get_mut().write {
+            Some(write) => Pin::new(write).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let this = self.get_mut();
+        let Some(write) = &mut this.write else {
+            return Poll::Ready(Ok(()));
+        };
+        let shut = Pin::new(write).poll_shutdown(cx);
+        if shut.is_ready() {
+            this.write = None;
+        }
+        shut
+    }
+}
+
+impl AsyncRead for LocalStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        match self.get_mut() {
+            LocalStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            LocalStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+            LocalStream::Duplex(stream) => Pin::new(stream).poll_read(cx, buf),
+            LocalStream::Stdio(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for LocalStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        match self.get_mut() {
+            LocalStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            LocalStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+            LocalStream::Duplex(stream) => Pin::new(stream).poll_write(cx, buf),
+            LocalStream::Stdio(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        match self.get_mut() {
+            LocalStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            LocalStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+            LocalStream::Duplex(stream) => Pin::new(stream).poll_flush(cx),
+            LocalStream::Stdio(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        match self.get_mut() {
+            LocalStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            LocalStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+            LocalStream::Duplex(stream) => Pin::new(stream).poll_shutdown(cx),
+            LocalStream::Stdio(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dial;
+    #[cfg(target_os = "linux")]
+    use super::LocalStream;
+
+    #[test]
+    fn listen_targets_round_trip_the_wire() {
+        for dial in [
+            Dial::Tcp {
+                host: "localhost".to_string(),
+                port: 3000,
+            },
+            Dial::Unix("/run/app.sock".to_string()),
+        ] {
+            let (host, port) = dial.to_wire();
+            assert_eq!(Dial::from_wire(host, port), dial);
+        }
+    }
+    /// Run as root, forwarded TCP targets, fixed or named by a SOCKS client,
+    /// are dialed from a socket the session's account owns.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn tcp_targets_are_dialed_as_the_session_user() -> anyhow::Result<()> {
+        use std::{fs::metadata, os::fd::AsRawFd as _, os::unix::fs::MetadataExt as _};
+
+        use tokio::net::TcpListener;
+
+        use crate::forward::policy::ForwardUser;
+
+        if metadata("/proc/self")?.uid() != 0 {
+            // Only root can act for another account.
+            return Ok(());
+        }
+        let nobody = ForwardUser::new(65_534, 65_534, vec![]);
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let (host, port) = ("127.0.0.1".to_string(), listener.local_addr()?.port());
+        for dial in [
+            Dial::Tcp {
+                host: host.clone(),
+                port,
+            },
+            Dial::Socks { host, port },
+        ] {
+            let LocalStream::Tcp(stream) = dial.connect(Some(&nobody), None, false).await? else {
+                panic!("expected a TCP stream");
+            };
+            let _peer = listener.accept().await?;
+            let socket = metadata(format!("/proc/self/fd/{}", stream.as_raw_fd()))?;
+            assert_eq!(socket.uid(), 65_534);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Listening sockets: the local end of `mp -L` and the remote end of `mp -R`.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::{
+    net::{TcpListener, lookup_host},
+    time::Instant,
+};
+use tracing::debug;
+
+use crate::{EncryptedFrame, ForwardSpec, MoshpitError, forward::endpoint::Dial};
+
+/// Bind address used when a forward does not name one: loopback only.
+pub(crate) const DEFAULT_BIND_ADDRESS: &str = "localhost";
+
+/// Bind every address `bind_address` resolves to on `port`.
+///
+/// With `port` 0 the first address gets a port from the system and the rest
+/// are bound to the same one.
+///
+/// # Errors
+/// * `bind_address` cannot be resolved.
+/// * None of its addresses can be bound.
+pub(crate) async fn bind_listeners(bind_address: &str, port: u16) -> Result<Vec<TcpListener>> {
+    let mut port = port;
+    let mut listeners = Vec::new();
+    let mut last_error = None;
+    for mut addr in lookup_host((bind_address, port)).await? {
+        if port != 0 {
+            addr.set_port(port);
+        }
+        match TcpListener::bind(addr).await {
+            Ok(listener) => {
+                port = listener.local_addr()?.port();
+                listeners.push(listener);
+            }
+            Err(e) => {
+                debug!("cannot listen on {addr}: {e}");
+                last_error = Some(e);
+            }
+        }
+    }
+    if listeners.is_empty() {
+        return Err(match last_error {
+            Some(e) => e.into(),
+            None => MoshpitError::HostResolutionFailed.into(),
+        });
+    }
+    Ok(listeners)
+}
+
+/// A request for the peer to listen on our behalf (`mp -R`), resent until
+/// answered and again on every newly attached connection, since the peer may
+/// have lost the session that held the listener.
+#[derive(Debug)]
+pub(crate) struct ListenRequest {
+    pub(crate) spec: ForwardSpec,
+    /// The peer's last answer: the port it listens on (0 for a Unix socket), or
+    /// `None` when it refused.  `None` until it first answers.
+    #[allow(clippy::option_option)]
+    pub(crate) answer: Option<Option<u16>>,
+    /// Whether the peer answered since the request was last (re)sent.
+    pub(crate) answered: bool,
+    pub(crate) rto: Duration,
+    pub(crate) deadline: Instant,
+}
+
+impl ListenRequest {
+    pub(crate) fn frame(&self, id: u32) -> EncryptedFrame {
+        let (host, host_port) = Dial::of_target(&self.spec).to_wire();
+        match self.spec.bind_path() {
+            Some(path) => EncryptedFrame::ForwardListenUnix((id, path.clone(), host, host_port)),
+            None => EncryptedFrame::ForwardListen((
+                id,
+                self.spec.bind_address().clone().unwrap_or_default(),
+                self.spec.bind_port(),
+                host,
+                host_port,
+            )),
+        }
+    }
+}
+
+/// The state of a listener the peer asked for.
+#[derive(Debug)]
+pub(crate) enum RemoteListen {
+    /// Being bound; duplicates of the request are ignored meanwhile.
+    Binding { target: Dial },
+    /// Bound to the given port, 0 for a Unix socket.
+    Listening(u16),
+    /// Refused by policy or failed to bind.
+    Refused,
+}
+
+impl RemoteListen {
+    /// The answer to (a retransmission of) request `id`, once there is one.
+    pub(crate) fn reply(&self, id: u32) -> Option<EncryptedFrame> {
+        match self {
+            RemoteListen::Binding { .. } => None,
+            RemoteListen::Listening(port) => Some(EncryptedFrame::ForwardListening((id, *port))),
+            RemoteListen::Refused => Some(EncryptedFrame::ForwardListenRefused(id)),
+        }
+    }
+}
+
+/// The address to bind for a listen request's `bind_address`.
+pub(crate) fn bind_address_or_default(bind_address: &str) -> &str {
+    if bind_address.is_empty() {
+        DEFAULT_BIND_ADDRESS
+    } else {
+        bind_address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::bind_listeners;
+
+    #[tokio::test]
+    async fn port_zero_binds_one_port_on_every_address() -> Result<()> {
+        let listeners = bind_listeners("localhost", 0).await?;
+        let port = listeners[0].local_addr()?.port();
+        assert_ne!(port, 0);
+        for listener in &listeners {
+            assert_eq!(listener.local_addr()?.port(), port);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1651 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Port forwarding: TCP streams carried over the session's data channel.
+//!
+//! From [`PORT_FORWARDING_MIN_PROTOCOL_VERSION`] either peer can open numbered
+//! channels inside the session with [`EncryptedFrame::ForwardOpen`].  Each
+//! channel is a byte stream of its own: [`EncryptedFrame::ForwardData`]
+//! segments are tagged with their stream offset and retransmitted until the
+//! peer's cumulative [`EncryptedFrame::ForwardAck`] covers them, because the
+//! UDP data channel may drop or reorder them.  The peer only acknowledges bytes
+//! once they are written to the local socket, and a sender never has more than
+//! a window of unacknowledged bytes outstanding, so a slow consumer on one
+//! channel stalls only that channel's reader.  [`EncryptedFrame::ForwardClose`]
+//! half-closes a stream at an offset and [`EncryptedFrame::ForwardReset`]
+//! aborts it.
+//!
+//! From [`REMOTE_FORWARDING_MIN_PROTOCOL_VERSION`] a peer can also ask the other
+//! side to listen for it with [`EncryptedFrame::ForwardListen`], which is
+//! answered with [`EncryptedFrame::ForwardListening`] or
+//! [`EncryptedFrame::ForwardListenRefused`] as the [`ForwardPolicy`] decides.
+//! Connections to such a listener come back as channels opened towards the
+//! requester, which only accepts opens to the targets it asked for.
+//!
+//! From [`DYNAMIC_FORWARDING_MIN_PROTOCOL_VERSION`] a listener can also speak
+//! SOCKS5 instead of forwarding to a fixed target: each connection names its
+//! own target, which goes to the peer unresolved in an
+//! [`EncryptedFrame::ForwardConnect`] so the peer's policy can tell such opens
+//! apart.  The SOCKS client gets its reply once the peer has connected.
+//!
+//! From [`UNIX_FORWARDING_MIN_PROTOCOL_VERSION`] either end of a forward can be
+//! a Unix-domain socket instead: [`EncryptedFrame::ForwardOpenUnix`] opens a
+//! channel to a socket path and [`EncryptedFrame::ForwardListenUnix`] asks the
+//! peer to listen on one.  A listen request names a socket target by giving
+//! its path with port 0.
+//!
+//! From [`AGENT_FORWARDING_MIN_PROTOCOL_VERSION`] the server can also open
+//! channels to the client's agent with [`EncryptedFrame::ForwardOpenAgent`],
+//! one for every connection to the session's agent socket.  The client relays
+//! only the agent requests that use a loaded key.
+//!
+//! From [`REMOTE_COMMAND_MIN_PROTOCOL_VERSION`](crate::REMOTE_COMMAND_MIN_PROTOCOL_VERSION)
+//! the server can also open channels to the client's standard streams with
+//! [`EncryptedFrame::ForwardOpenStdio`], carrying those of a remote command
+//! run without a PTY.
+//!
+//! The channels belong to a [`ForwardMux`], which outlives any one connection:
+//! after a roam or reconnect the new data channel is attached to the same mux,
+//! and everything still unacknowledged is sent again on it.  The client opens
+//! odd channel ids and the server even ones, so the two never collide.  Closing
+//! the mux, or dropping its last handle, closes every channel and listener.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    mem::replace,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use tokio::{
+    io::{
+        AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _, ReadHalf, WriteHalf, split,
+    },
+    net::TcpStream,
+    select, spawn,
+    sync::{
+        Semaphore,
+        mpsc::{
+            Receiver, Sender, UnboundedReceiver, UnboundedSender, WeakSender, channel,
+            unbounded_channel,
+        },
+        oneshot,
+    },
+    task::JoinHandle,
+    time::{Instant, sleep, sleep_until, timeout},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, trace, warn};
+
+use crate::{
+    DynamicForwardSpec, EncryptedFrame, ForwardPolicy, ForwardSpec, ForwardUser, MoshpitError,
+    forward::{
+        endpoint::{Bind, Dial, Listener, LocalStream, StdioStream},
+        listen::{ListenRequest, RemoteListen, bind_address_or_default, bind_listeners},
+    },
+};
+
+#[cfg(unix)]
+pub(crate) mod account;
+pub(crate) mod agent;
+pub(crate) mod endpoint;
+pub(crate) mod listen;
+pub(crate) mod policy;
+pub(crate) mod socks;
+pub(crate) mod spec;
+#[cfg(unix)]
+pub(crate) mod unix;
+
+/// Lowest negotiated protocol version whose peers understand the
+/// `EncryptedFrame::Forward*` frames.
+pub const PORT_FORWARDING_MIN_PROTOCOL_VERSION: u16 = 11;
+
+/// Lowest negotiated protocol version whose peers understand
+/// [`EncryptedFrame::ForwardListen`] and its answers.
+pub const REMOTE_FORWARDING_MIN_PROTOCOL_VERSION: u16 = 12;
+
+/// Lowest negotiated protocol version whose peers understand
+/// [`EncryptedFrame::ForwardConnect`].
+pub const DYNAMIC_FORWARDING_MIN_PROTOCOL_VERSION: u16 = 13;
+
+/// Lowest negotiated protocol version whose peers understand
+/// [`EncryptedFrame::ForwardOpenUnix`] and [`EncryptedFrame::ForwardListenUnix`].
+pub const UNIX_FORWARDING_MIN_PROTOCOL_VERSION: u16 = 14;
+
+/// Lowest negotiated protocol version whose peers understand
+/// [`EncryptedFrame::ForwardOpenAgent`] and the agent forwarding request made
+/// during key exchange.
+pub const AGENT_FORWARDING_MIN_PROTOCOL_VERSION: u16 = 15;
+
+/// Largest payload of one [`EncryptedFrame::ForwardData`].  With the
+/// wire, crypto and bincode overhead a segment stays within one
+/// [`MAX_UDP_PAYLOAD`](crate::MAX_UDP_PAYLOAD) datagram.
+const FORWARD_CHUNK_LEN: u32 = 800;
+
+/// Unacknowledged bytes one channel may have in flight.
+const FORWARD_WINDOW: usize = 128 * 1024;
+
+/// Delay before the first retransmission of a segment or open.
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+
+/// Ceiling for the doubling retransmission delay.
+const MAX_RTO: Duration = Duration::from_secs(8);
+
+/// How often the mux looks for segments due for retransmission.
+const RETRANSMIT_TICK: Duration = Duration::from_millis(250);
+
+/// How long the accepting side spends dialing a channel's target.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a SOCKS client gets to name its target.
+const SOCKS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Channels open at once through one mux.  Further opens are refused.
+const MAX_CHANNELS: usize = 256;
+
+/// Listeners one peer may have this side hold open.  Further requests are
+/// refused.
+const MAX_REMOTE_LISTENS: usize = 32;
+
+/// Frames queued from the data-channel readers, and events queued from the
+/// channel tasks.
+const QUEUE: usize = 256;
+
+/// Pause after a failed `accept` so a persistent error (such as running out of
+/// file descriptors) does not spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Which end of the session a [`ForwardMux`] runs on.  Decides the parity of
+/// the channel ids it allocates.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ForwardRole {
+    /// `mp`: opens odd channel ids.
+    Client,
+    /// `moshpits`: opens even channel ids.
+    Server,
+}
+
+impl ForwardRole {
+    fn first_id(self) -> u32 {
+        match self {
+            ForwardRole::Client => 1,
+            ForwardRole::Server => 2,
+        }
+    }
+
+    /// Whether `id` is one this side allocates.
+    fn owns(self, id: u32) -> bool {
+        (id % 2 == 1) == (self == ForwardRole::Client)
+    }
+}
+
+/// Handle to the task that multiplexes forwarded streams over a session.
+///
+/// Cheap to clone; the task stops, closing every forwarded stream and
+/// listener, once [`ForwardMux::close`] is called or the last handle is
+/// dropped.
+#[derive(Clone, Debug)]
+pub struct ForwardMux {
+    commands: Sender<Command>,
+    frames: Sender<EncryptedFrame>,
+    closed: CancellationToken,
+}
+
+impl ForwardMux {
+    /// Start a mux for `role`, honouring the peer's requests as `policy`
+    /// allows.  Refused opens are answered with [`EncryptedFrame::ForwardReset`].
+    #[must_use]
+    pub fn spawn(role: ForwardRole, policy: ForwardPolicy) -> Self {
+        let (commands, commands_rx) = channel(QUEUE);
+        let (frames, frames_rx) = channel(QUEUE);
+        let (events, events_rx) = channel(QUEUE);
+        let closed = CancellationToken::new();
+        let mux = Mux {
+            role,
+            policy,
+            data_tx: None,
+            channels: HashMap::new(),
+            next_id: role.first_id(),
+            highest_peer_id: 0,
+            open_targets: HashSet::new(),
+            listen_requests: BTreeMap::new(),
+            next_listen_id: 1,
+            remote_listens: HashMap::new(),
+            stdio: HashMap::new(),
+            commands: commands.downgrade(),
+            closed: closed.clone(),
+            events,
+        };
+        let guard = closed.clone().drop_guard();
+        let _handle = spawn(async move {
+            let _guard = guard;
+            mux.run(commands_rx, frames_rx, events_rx).await;
+        });
+        Self {
+            commands,
+            frames,
+            closed,
+        }
+    }
+
+    /// Send this mux's frames on `data_tx` from now on, the data channel of a
+    /// newly established connection.  Everything the peer has not acknowledged
+    /// yet is sent again straight away.
+    pub async fn attach(&self, data_tx: Sender<EncryptedFrame>) {
+        let _sent = self.commands.send(Command::Attach(data_tx)).await;
+    }
+
+    /// Stop the mux now, closing every forwarded stream and listener, even
+    /// while other handles remain.
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+
+    /// The sender the data-channel readers hand the peer's `Forward*` frames to.
+    #[must_use]
+    pub fn frame_tx(&self) -> Sender<EncryptedFrame> {
+        self.frames.clone()
+    }
+
+    /// Open a channel that connects `stream` to `host:port` on the peer's side.
+    ///
+    /// The stream is closed if the peer refuses the channel or cannot connect.
+    pub async fn open(&self, stream: TcpStream, host: String, port: u16) {
+        let open = Command::Open {
+            stream: LocalStream::Tcp(stream),
+            dial: Dial::Tcp { host, port },
+            done: None,
+        };
+        let _sent = self.commands.send(open).await;
+    }
+
+    /// Open a channel to standard stream `fd` of the peer, reading the bytes
+    /// sent to it from `read` and writing the bytes it sends back to `write`.
+    /// Without `read` the channel carries nothing towards the peer, and
+    /// without `write` whatever the peer sends is discarded.
+    ///
+    /// The returned receiver resolves once the channel is gone: when both
+    /// directions have ended and everything sent was acknowledged, or when the
+    /// peer refused or reset it.
+    pub async fn open_stdio(
+        &self,
+        fd: u8,
+        read: Option<Box<dyn AsyncRead + Send + Unpin>>,
+        write: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+    ) -> oneshot::Receiver<()> {
+        let (done, gone) = oneshot::channel();
+        let open = Command::Open {
+            stream: LocalStream::Stdio(StdioStream::new(read, write)),
+            dial: Dial::Stdio(fd),
+            done: Some(done),
+        };
+        let _sent = self.commands.send(open).await;
+        gone
+    }
+
+    /// Serve the peer's channel to standard stream `fd` from `read` and
+    /// `write` instead of this process's own standard stream, as
+    /// [`ForwardMux::open_stdio`] does for channels this side opens.  Used once:
+    /// a later channel to `fd` gets the real stream again.
+    pub async fn provide_stdio(
+        &self,
+        fd: u8,
+        read: Option<Box<dyn AsyncRead + Send + Unpin>>,
+        write: Option<Box<dyn AsyncWrite + Send + Unpin>>,
+    ) {
+        let stream = StdioStream::new(read, write);
+        let _sent = self
+            .commands
+            .send(Command::ProvideStdio { fd, stream })
+            .await;
+    }
+
+    /// Listen on the local end of `spec` and open a channel to its remote end
+    /// for every accepted connection, until the mux stops.
+    ///
+    /// Binds every address the bind host resolves to (`localhost` when the
+    /// spec has none) and returns the addresses bound.  A spec whose local end
+    /// is a Unix socket goes to [`ForwardMux::listen_unix`] instead.
+    ///
+    /// # Errors
+    /// * [`MoshpitError::InvalidForwardSpec`] when the local end is a Unix
+    ///   socket.
+    /// * The bind host cannot be resolved.
+    /// * None of its addresses can be bound.
+    pub async fn listen(&self, spec: &ForwardSpec) -> Result<Vec<SocketAddr>> {
+        if spec.bind_path().is_some() {
+            return Err(MoshpitError::InvalidForwardSpec.into());
+        }
+        let target = Target::Fixed(Dial::of_target(spec));
+        self.serve(spec.bind_address().as_deref(), spec.bind_port(), target)
+            .await
+    }
+
+    /// Listen on the local Unix socket of `spec`, as [`ForwardMux::listen`]
+    /// does on a TCP port, and return its path.  The socket is created with
+    /// mode 0600, must not exist yet, and is removed once the mux stops.
+    ///
+    /// # Errors
+    /// * [`MoshpitError::InvalidForwardSpec`] when the local end is not a Unix
+    ///   socket.
+    /// * The socket cannot be bound, or Unix sockets are not supported on this
+    ///   platform.
+    pub fn listen_unix(&self, spec: &ForwardSpec) -> Result<PathBuf> {
+        let path = spec
+            .bind_path()
+            .as_deref()
+            .ok_or(MoshpitError::InvalidForwardSpec)?;
+        let listener = Listener::bind_unix(path)?;
+        let (bound, _port) = listener.local()?;
+        let _handle = spawn(accept_loop(
+            listener,
+            self.commands.downgrade(),
+            self.closed.clone(),
+            Target::Fixed(Dial::of_target(spec)),
+        ));
+        Ok(PathBuf::from(bound))
+    }
+
+    /// Listen on a new agent socket for the session, in a private directory
+    /// created on behalf of `user` when set, and open a channel to the peer's
+    /// agent for every accepted connection, until the mux stops.  Returns the
+    /// socket's path, which is removed along with its directory once the mux
+    /// stops.
+    ///
+    /// # Errors
+    /// * The directory or socket cannot be created, or Unix sockets are not
+    ///   supported on this platform.
+    pub fn listen_agent(&self, user: Option<&ForwardUser>) -> Result<PathBuf> {
+        let listener = Listener::bind_agent(user)?;
+        let (bound, _port) = listener.local()?;
+        let _handle = spawn(accept_loop(
+            listener,
+            self.commands.downgrade(),
+            self.closed.clone(),
+            Target::Fixed(Dial::Agent),
+        ));
+        Ok(PathBuf::from(bound))
+    }
+
+    /// Run a SOCKS5 proxy on the local end of `spec`, opening a channel to the
+    /// target each client names, until the mux stops.  Names are resolved by
+    /// the peer.
+    ///
+    /// Binds as [`ForwardMux::listen`] does and returns the addresses bound.
+    ///
+    /// # Errors
+    /// * The bind host cannot be resolved.
+    /// * None of its addresses can be bound.
+    pub async fn listen_socks(&self, spec: &DynamicForwardSpec) -> Result<Vec<SocketAddr>> {
+        self.serve(
+            spec.bind_address().as_deref(),
+            spec.bind_port(),
+            Target::Socks,
+        )
+        .await
+    }
+
+    async fn serve(
+        &self,
+        bind_address: Option<&str>,
+        port: u16,
+        target: Target,
+    ) -> Result<Vec<SocketAddr>> {
+        let bind_address = bind_address_or_default(bind_address.unwrap_or(""));
+        let listeners = bind_listeners(bind_address, port).await?;
+        let mut bound = Vec::with_capacity(listeners.len());
+        for listener in listeners {
+            bound.push(listener.local_addr()?);
+            let _handle = spawn(accept_loop(
+                Listener::Tcp(listener),
+                self.commands.downgrade(),
+                self.closed.clone(),
+                target.clone(),
+            ));
+        }
+        Ok(bound)
+    }
+
+    /// Ask the peer to listen on the bind end of `spec` and open a channel
+    /// back to `host:hostport` on this side for every connection it accepts.
+    ///
+    /// The request is sent once a connection is attached and repeated on
+    /// every later one; the peer's answer is logged.
+    pub async fn request_listen(&self, spec: ForwardSpec) {
+        let _sent = self.commands.send(Command::RequestListen(spec)).await;
+    }
+}
+
+/// Where the connections accepted on a forwarded port go.
+#[derive(Clone, Debug)]
+enum Target {
+    /// Always to the same target on the peer's side.
+    Fixed(Dial),
+    /// Wherever each SOCKS5 client asks.
+    Socks,
+}
+
+/// Accept connections on a forwarded port and open a channel for each.
+async fn accept_loop(
+    listener: Listener,
+    commands: WeakSender<Command>,
+    closed: CancellationToken,
+    target: Target,
+) {
+    loop {
+        select! {
+            () = closed.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    let Some(commands) = commands.upgrade() else {
+                        break;
+                    };
+                    match &target {
+                        Target::Fixed(dial) => {
+                            trace!("forwarding {peer} to {dial}");
+                            let open = Command::Open {
+                                stream,
+                                dial: dial.clone(),
+                                done: None,
+                            };
+                            if commands.send(open).await.is_err() {
+                                break;
+                            }
+                        }
+                        Target::Socks => {
+                            let _handle = spawn(socks_open(stream, peer, commands));
+                        }
+                    }
+                }
+                Err(e) => {
+                    debug!("accept on forwarded port failed: {e}");
+                    sleep(ACCEPT_BACKOFF).await;
+                }
+            },
+        }
+    }
+}
+
+/// Read the target of a SOCKS5 client and open a channel to it.
+async fn socks_open(mut stream: LocalStream, peer: String, commands: Sender<Command>) {
+    let (host, port) = match timeout(SOCKS_TIMEOUT, socks::accept(&mut stream)).await {
+        Ok(Ok(target)) => target,
+        Ok(Err(e)) => {
+            debug!("SOCKS client {peer}: {e}");
+            return;
+        }
+        Err(_) => {
+            debug!("SOCKS client {peer} did not name a target in time");
+            return;
+        }
+    };
+    trace!("SOCKS client {peer}: connecting to {host}:{port}");
+    let open = Command::Open {
+        stream,
+        dial: Dial::Socks { host, port },
+        done: None,
+    };
+    let _sent = commands.send(open).await;
+}
+
+/// Requests from [`ForwardMux`] handles.
+#[derive(Debug)]
+enum Command {
+    Attach(Sender<EncryptedFrame>),
+    Open {
+        stream: LocalStream,
+        dial: Dial,
+        done: Option<oneshot::Sender<()>>,
+    },
+    RequestListen(ForwardSpec),
+    ProvideStdio {
+        fd: u8,
+        stream: StdioStream,
+    },
+}
+
+/// Reports from a channel's tasks.
+#[derive(Debug)]
+enum Event {
+    /// The target of a channel the peer opened was reached.
+    Connected { id: u32, stream: LocalStream },
+    /// The target of a channel the peer opened could not be reached.
+    ConnectFailed(u32),
+    /// Bytes read from the local socket.
+    Read { id: u32, bytes: Vec<u8> },
+    /// The local socket reached end of stream.
+    Eof(u32),
+    /// `len` stream units (bytes, or 1 for the end of stream) were written to
+    /// the local socket.
+    Written { id: u32, len: u64 },
+    /// The local socket failed.
+    Failed(u32),
+    /// The listeners for the peer's listen request `request` were bound, or
+    /// could not be.
+    Listened {
+        request: u32,
+        listeners: Option<Vec<Listener>>,
+    },
+}
+
+/// A sent segment awaiting acknowledgement.  `None` marks the end of stream,
+/// which occupies one offset.
+#[derive(Debug)]
+struct Segment {
+    payload: Option<Vec<u8>>,
+    rto: Duration,
+    deadline: Instant,
+}
+
+impl Segment {
+    fn new(payload: Option<Vec<u8>>) -> Self {
+        Self {
+            payload,
+            rto: INITIAL_RTO,
+            deadline: Instant::now() + INITIAL_RTO,
+        }
+    }
+
+    /// The stream offsets this segment occupies.
+    fn len(&self) -> u64 {
+        self.payload
+            .as_ref()
+            .map_or(1, |payload| payload.len() as u64)
+    }
+
+    fn frame(&self, id: u32, offset: u64) -> EncryptedFrame {
+        match &self.payload {
+            Some(payload) => EncryptedFrame::ForwardData((id, offset, payload.clone())),
+            None => EncryptedFrame::ForwardClose((id, offset)),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    /// Opened on this side, waiting for the peer to confirm.  The local stream
+    /// is held back until then; for a [`Dial::Socks`] open it is a SOCKS client
+    /// waiting for its reply.
+    Opening {
+        stream: LocalStream,
+        dial: Dial,
+        rto: Duration,
+        deadline: Instant,
+    },
+    /// Opened by the peer, dialing its target.
+    Connecting,
+    /// Carrying data.
+    Open,
+}
+
+#[derive(Debug)]
+struct Channel {
+    state: State,
+    // Sending half.
+    send_next: u64,
+    unacked: BTreeMap<u64, Segment>,
+    fin_sent: bool,
+    window: Arc<Semaphore>,
+    // Receiving half.  `None` in `reorder` marks the peer's end of stream.
+    recv_next: u64,
+    recv_written: u64,
+    reorder: BTreeMap<u64, Option<Vec<u8>>>,
+    fin_received: bool,
+    write_tx: Option<UnboundedSender<Option<Vec<u8>>>>,
+    tasks: Vec<JoinHandle<()>>,
+    /// Dropped along with the channel, telling whoever opened it that it is gone.
+    done: Option<oneshot::Sender<()>>,
+}
+
+impl Channel {
+    fn new(state: State) -> Self {
+        Self {
+            state,
+            send_next: 0,
+            unacked: BTreeMap::new(),
+            fin_sent: false,
+            window: Arc::new(Semaphore::new(FORWARD_WINDOW)),
+            recv_next: 0,
+            recv_written: 0,
+            reorder: BTreeMap::new(),
+            fin_received: false,
+            write_tx: None,
+            tasks: Vec::new(),
+            done: None,
+        }
+    }
+
+    /// Start moving bytes between `stream` and the channel, first telling a
+    /// SOCKS client it is connected when `socks_reply` is set.
+    fn start(&mut self, id: u32, stream: LocalStream, socks_reply: bool, events: &Sender<Event>) {
+        let (read_half, write_half) = split(stream);
+        let (write_tx, write_rx) = unbounded_channel();
+        self.state = State::Open;
+        self.write_tx = Some(write_tx);
+        self.tasks.push(spawn(read_loop(
+            id,
+            read_half,
+            self.window.clone(),
+            events.clone(),
+        )));
+        self.tasks.push(spawn(write_loop(
+            id,
+            write_half,
+            socks_reply,
+            write_rx,
+            events.clone(),
+        )));
+    }
+
+    /// Treat any answer from the peer as confirmation of a channel opened here.
+    fn confirm(&mut self, id: u32, events: &Sender<Event>) {
+        if matches!(self.state, State::Opening { .. })
+            && let State::Opening { stream, dial, .. } = replace(&mut self.state, State::Open)
+        {
+            self.start(id, stream, matches!(dial, Dial::Socks { .. }), events);
+        }
+    }
+
+    /// The peer refused or lost the channel: tell a SOCKS client still waiting
+    /// for its reply before its stream is dropped.
+    fn refuse(&mut self) {
+        if matches!(
+            self.state,
+            State::Opening {
+                dial: Dial::Socks { .. },
+                ..
+            }
+        ) && let State::Opening { mut stream, .. } = replace(&mut self.state, State::Connecting)
+        {
+            let _handle = spawn(async move {
+                let _replied = socks::reply(&mut stream, socks::HOST_UNREACHABLE).await;
+            });
+        }
+    }
+
+    /// The open and the segments whose retransmission timers have fired, or
+    /// all of them when `all` is set.
+    fn due(&mut self, id: u32, now: Instant, all: bool) -> Vec<EncryptedFrame> {
+        let mut frames = Vec::new();
+        if let State::Opening {
+            dial,
+            rto,
+            deadline,
+            ..
+        } = &mut self.state
+            && (all || *deadline <= now)
+        {
+            frames.push(dial.frame(id));
+            *rto = if all {
+                INITIAL_RTO
+            } else {
+                (*rto * 2).min(MAX_RTO)
+            };
+            *deadline = now + *rto;
+        }
+        for (&offset, segment) in &mut self.unacked {
+            if all || segment.deadline <= now {
+                frames.push(segment.frame(id, offset));
+                segment.rto = if all {
+                    INITIAL_RTO
+                } else {
+                    (segment.rto * 2).min(MAX_RTO)
+                };
+                segment.deadline = now + segment.rto;
+            }
+        }
+        frames
+    }
+
+    /// Both directions have ended and been acknowledged.
+    fn is_finished(&self) -> bool {
+        self.fin_sent
+            && self.unacked.is_empty()
+            && self.fin_received
+            && self.recv_written == self.recv_next
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+struct Mux {
+    role: ForwardRole,
+    policy: ForwardPolicy,
+    data_tx: Option<Sender<EncryptedFrame>>,
+    channels: HashMap<u32, Channel>,
+    next_id: u32,
+    highest_peer_id: u32,
+    /// Targets the peer may open channels to regardless of the policy: the
+    /// local ends of our own listen requests.
+    open_targets: HashSet<Dial>,
+    /// Our listen requests to the peer, by request id.
+    listen_requests: BTreeMap<u32, ListenRequest>,
+    next_listen_id: u32,
+    /// The peer's listen requests to us, by request id.
+    remote_listens: HashMap<u32, RemoteListen>,
+    /// Streams standing in for our own standard streams, by descriptor.
+    stdio: HashMap<u8, StdioStream>,
+    /// For the accept loops of the peer's listeners.
+    commands: WeakSender<Command>,
+    closed: CancellationToken,
+    events: Sender<Event>,
+}
+
+impl Mux {
+    async fn run(
+        mut self,
+        mut commands: Receiver<Command>,
+        mut frames: Receiver<EncryptedFrame>,
+        mut events: Receiver<Event>,
+    ) {
+        let closed = self.closed.clone();
+        let mut tick = Instant::now() + RETRANSMIT_TICK;
+        loop {
+            select! {
+                () = closed.cancelled() => break,
+                command = commands.recv() => match command {
+                    Some(Command::Attach(data_tx)) => self.attach(data_tx).await,
+                    Some(Command::Open { stream, dial, done }) => {
+                        self.open(stream, dial, done).await;
+                    }
+                    Some(Command::RequestListen(spec)) => self.request_listen(spec).await,
+                    Some(Command::ProvideStdio { fd, stream }) => {
+                        let _previous = self.stdio.insert(fd, stream);
+                    }
+                    None => break,
+                },
+                Some(frame) = frames.recv() => self.receive(frame).await,
+                Some(event) = events.recv() => self.handle_event(event).await,
+                () = sleep_until(tick) => {
+                    self.retransmit(false).await;
+                    tick = Instant::now() + RETRANSMIT_TICK;
+                }
+            }
+        }
+    }
+
+    /// Send `frame` on the attached data channel, detaching it once closed.
+    async fn send(&mut self, frame: EncryptedFrame) {
+        let sent = match &self.data_tx {
+            Some(data_tx) => data_tx.send(frame).await.is_ok(),
+            None => return,
+        };
+        if !sent {
+            self.data_tx = None;
+        }
+    }
+
+    async fn attach(&mut self, data_tx: Sender<EncryptedFrame>) {
+        self.data_tx = Some(data_tx);
+        self.retransmit(true).await;
+    }
+
+    /// Resend what is due, or everything unacknowledged when `all` is set.
+    async fn retransmit(&mut self, all: bool) {
+        if self.data_tx.is_none() {
+            return;
+        }
+        let now = Instant::now();
+        let mut frames: Vec<EncryptedFrame> = self
+            .channels
+            .iter_mut()
+            .flat_map(|(&id, channel)| channel.due(id, now, all))
+            .collect();
+        for (&id, request) in &mut self.listen_requests {
+            if all {
+                // The peer may have lost the session that held the listener.
+                request.answered = false;
+                request.rto = INITIAL_RTO;
+            } else if request.answered || request.deadline > now {
+                continue;
+            } else {
+                request.rto = (request.rto * 2).min(MAX_RTO);
+            }
+            request.deadline = now + request.rto;
+            frames.push(request.frame(id));
+        }
+        for frame in frames {
+            self.send(frame).await;
+        }
+    }
+
+    async fn open(&mut self, stream: LocalStream, dial: Dial, done: Option<oneshot::Sender<()>>) {
+        if self.channels.len() >= MAX_CHANNELS {
+            debug!("too many forwarded channels, refusing connection to {dial}");
+            return;
+        }
+        let Some(next_id) = self.next_id.checked_add(2) else {
+            debug!("forwarded channel ids exhausted, refusing connection to {dial}");
+            return;
+        };
+        let id = replace(&mut self.next_id, next_id);
+        let open = dial.frame(id);
+        let state = State::Opening {
+            stream,
+            dial,
+            rto: INITIAL_RTO,
+            deadline: Instant::now() + INITIAL_RTO,
+        };
+        let mut channel = Channel::new(state);
+        channel.done = done;
+        let _previous = self.channels.insert(id, channel);
+        self.send(open).await;
+    }
+
+    async fn request_listen(&mut self, spec: ForwardSpec) {
+        let id = self.next_listen_id;
+        self.next_listen_id += 1;
+        let _new = self.open_targets.insert(Dial::of_target(&spec));
+        let request = ListenRequest {
+            spec,
+            answer: None,
+            answered: false,
+            rto: INITIAL_RTO,
+            deadline: Instant::now() + INITIAL_RTO,
+        };
+        let frame = request.frame(id);
+        let _previous = self.listen_requests.insert(id, request);
+        self.send(frame).await;
+    }
+
+    /// Handle the peer's request to listen on `bind` and open channels back to
+    /// `target` on its side.
+    async fn peer_listen(&mut self, request: u32, bind: Bind, target: Dial) {
+        if let Some(listen) = self.remote_listens.get(&request) {
+            // A retransmission: answer again once there is an answer.
+            if let Some(reply) = listen.reply(request) {
+                self.send(reply).await;
+            }
+            return;
+        }
+        let allowed = match &bind {
+            Bind::Tcp { address, port } => self.policy.allows_listen(address, *port),
+            Bind::Unix(_) => cfg!(unix) && self.policy.allow_unix(),
+        };
+        if !allowed || self.remote_listens.len() >= MAX_REMOTE_LISTENS {
+            debug!("refusing to listen on {bind} for the peer");
+            let _previous = self.remote_listens.insert(request, RemoteListen::Refused);
+            self.send(EncryptedFrame::ForwardListenRefused(request))
+                .await;
+            return;
+        }
+        let _previous = self
+            .remote_listens
+            .insert(request, RemoteListen::Binding { target });
+        let events = self.events.clone();
+        let user = self.policy.session_user().cloned();
+        let _handle = spawn(async move {
+            let listeners = match bind.listen(user.as_ref()).await {
+                Ok(listeners) => Some(listeners),
+                Err(e) => {
+                    debug!("cannot listen on {bind} for the peer: {e}");
+                    None
+                }
+            };
+            let _sent = events.send(Event::Listened { request, listeners }).await;
+        });
+    }
+
+    /// Start accepting on the listeners bound for the peer's listen `request`.
+    async fn listened(&mut self, request: u32, listeners: Option<Vec<Listener>>) {
+        let Some(RemoteListen::Binding { target }) = self.remote_listens.remove(&request) else {
+            return;
+        };
+        let mut bound_port = None;
+        for listener in listeners.into_iter().flatten() {
+            if let Ok((local, port)) = listener.local() {
+                info!("listening on {local} for the peer, forwarding to {target}");
+                bound_port = Some(port);
+            }
+            let _handle = spawn(accept_loop(
+                listener,
+                self.commands.clone(),
+                self.closed.clone(),
+                Target::Fixed(target.clone()),
+            ));
+        }
+        let listen = bound_port.map_or(RemoteListen::Refused, RemoteListen::Listening);
+        let reply = listen.reply(request);
+        let _previous = self.remote_listens.insert(request, listen);
+        if let Some(reply) = reply {
+            self.send(reply).await;
+        }
+    }
+
+    /// Handle the peer's answer to our listen request `request`; `port` is
+    /// `None` when it refused.
+    fn listen_answered(&mut self, request: u32, port: Option<u16>) {
+        let Some(listen) = self.listen_requests.get_mut(&request) else {
+            return;
+        };
+        if listen.answer != Some(port) {
+            match (port, listen.spec.bind_path()) {
+                (Some(_), Some(path)) => info!(
+                    "server listening on {path}, forwarding to {}",
+                    listen.spec.target()
+                ),
+                (Some(port), None) => info!(
+                    "server listening on port {port}, forwarding to {}",
+                    listen.spec.target()
+                ),
+                (None, _) => warn!("server refused remote forward {}", listen.spec),
+            }
+        }
+        listen.answered = true;
+        listen.answer = Some(port);
+    }
+
+    /// Handle one `Forward*` frame from the peer.
+    async fn receive(&mut self, frame: EncryptedFrame) {
+        match frame {
+            EncryptedFrame::ForwardOpen((id, host, port)) => {
+                self.peer_open(id, Dial::Tcp { host, port }).await;
+            }
+            EncryptedFrame::ForwardConnect((id, host, port)) => {
+                self.peer_open(id, Dial::Socks { host, port }).await;
+            }
+            EncryptedFrame::ForwardOpenUnix((id, path)) => {
+                self.peer_open(id, Dial::Unix(path)).await;
+            }
+            EncryptedFrame::ForwardOpenAgent(id) => self.peer_open(id, Dial::Agent).await,
+            EncryptedFrame::ForwardOpenStdio((id, fd)) => {
+                self.peer_open(id, Dial::Stdio(fd)).await;
+            }
+            EncryptedFrame::ForwardData((id, offset, bytes)) => {
+                self.peer_segment(id, offset, Some(bytes)).await;
+            }
+            EncryptedFrame::ForwardClose((id, offset)) => {
+                self.peer_segment(id, offset, None).await;
+            }
+            EncryptedFrame::ForwardAck((id, acked)) => self.peer_ack(id, acked),
+            EncryptedFrame::ForwardReset(id) => {
+                if let Some(mut channel) = self.channels.remove(&id) {
+                    debug!("forwarded channel {id} reset by peer");
+                    channel.refuse();
+                }
+            }
+            EncryptedFrame::ForwardListen((request, address, port, host, host_port)) => {
+                if self.role == ForwardRole::Server {
+                    let bind = Bind::Tcp { address, port };
+                    self.peer_listen(request, bind, Dial::from_wire(host, host_port))
+                        .await;
+                }
+            }
+            EncryptedFrame::ForwardListenUnix((request, path, host, host_port)) => {
+                if self.role == ForwardRole::Server {
+                    let bind = Bind::Unix(path);
+                    self.peer_listen(request, bind, Dial::from_wire(host, host_port))
+                        .await;
+                }
+            }
+            EncryptedFrame::ForwardListening((request, port)) => {
+                self.listen_answered(request, Some(port));
+            }
+            EncryptedFrame::ForwardListenRefused(request) => self.listen_answered(request, None),
+            _ => {}
+        }
+    }
+
+    /// Handle the peer's open of channel `id` to `dial`.
+    async fn peer_open(&mut self, id: u32, dial: Dial) {
+        if self.role.owns(id) {
+            return;
+        }
+        if let Some(channel) = self.channels.get(&id) {
+            // A retransmitted open whose confirmation was lost.
+            if matches!(channel.state, State::Open) {
+                let ack = EncryptedFrame::ForwardAck((id, channel.recv_written));
+                self.send(ack).await;
+            }
+            return;
+        }
+        if id <= self.highest_peer_id {
+            // A stale open for a channel already gone; the peer may still be
+            // waiting on it if the reset was lost.
+            self.send(EncryptedFrame::ForwardReset(id)).await;
+            return;
+        }
+        self.highest_peer_id = id;
+        let allowed = match &dial {
+            Dial::Socks { .. } => self.policy.allow_dynamic(),
+            Dial::Unix(_) => self.policy.allow_unix() || self.open_targets.contains(&dial),
+            Dial::Tcp { .. } => self.policy.allow_open() || self.open_targets.contains(&dial),
+            Dial::Agent => cfg!(unix) && self.policy.agent_socket().is_some(),
+            Dial::Stdio(fd) => self.policy.allow_stdio() || self.stdio.contains_key(fd),
+        };
+        if !allowed || self.channels.len() >= MAX_CHANNELS {
+            debug!("refusing forwarded channel {id} to {dial}");
+            self.send(EncryptedFrame::ForwardReset(id)).await;
+            return;
+        }
+        trace!("forwarded channel {id}: connecting to {dial}");
+        let mut channel = Channel::new(State::Connecting);
+        let events = self.events.clone();
+        let user = self.policy.session_user().cloned();
+        let agent = self.policy.agent_socket().map(Path::to_path_buf);
+        let agent_agree = self.policy.allow_agent_agree();
+        let provided = match &dial {
+            Dial::Stdio(fd) => self.stdio.remove(fd),
+            _ => None,
+        };
+        channel.tasks.push(spawn(async move {
+            let connect = async {
+                match provided {
+                    Some(stream) => Ok(LocalStream::Stdio(stream)),
+                    None => {
+                        dial.connect(user.as_ref(), agent.as_deref(), agent_agree)
+                            .await
+                    }
+                }
+            };
+            let event = match timeout(CONNECT_TIMEOUT, connect).await {
+                Ok(Ok(stream)) => Event::Connected { id, stream },
+                Ok(Err(e)) => {
+                    debug!("forwarded channel {id}: cannot connect to {dial}: {e}");
+                    Event::ConnectFailed(id)
+                }
+                Err(_) => {
+                    debug!("forwarded channel {id}: connecting to {dial} timed out");
+                    Event::ConnectFailed(id)
+                }
+            };
+            let _sent = events.send(event).await;
+        }));
+        let _previous = self.channels.insert(id, channel);
+    }
+
+    /// Handle a data segment, or the end of stream when `payload` is `None`.
+    async fn peer_segment(&mut self, id: u32, offset: u64, payload: Option<Vec<u8>>) {
+        let Some(channel) = self.channels.get_mut(&id) else {
+            // An end of stream whose acknowledgement was lost after the channel
+            // finished is acknowledged again; data for an unknown channel
+            // cannot be delivered.
+            let reply = match payload {
+                Some(_) => EncryptedFrame::ForwardReset(id),
+                None => EncryptedFrame::ForwardAck((id, offset + 1)),
+            };
+            self.send(reply).await;
+            return;
+        };
+        channel.confirm(id, &self.events);
+        if !matches!(channel.state, State::Open) {
+            return;
+        }
+        if offset < channel.recv_next || channel.fin_received {
+            let ack = EncryptedFrame::ForwardAck((id, channel.recv_written));
+            self.send(ack).await;
+            return;
+        }
+        let len = payload.as_ref().map_or(1, |bytes| bytes.len() as u64);
+        if offset + len > channel.recv_written + FORWARD_WINDOW as u64 + 1 {
+            trace!("forwarded channel {id}: dropping segment beyond the window");
+            return;
+        }
+        let _previous = channel.reorder.insert(offset, payload);
+        while let Some(segment) = channel.reorder.remove(&channel.recv_next) {
+            let fin = segment.is_none();
+            channel.recv_next += segment.as_ref().map_or(1, |bytes| bytes.len() as u64);
+            if let Some(tx) = &channel.write_tx {
+                let _queued = tx.send(segment);
+            }
+            if fin {
+                channel.fin_received = true;
+                channel.reorder.clear();
+                break;
+            }
+        }
+    }
+
+    fn peer_ack(&mut self, id: u32, acked: u64) {
+        let Some(channel) = self.channels.get_mut(&id) else {
+            return;
+        };
+        channel.confirm(id, &self.events);
+        let mut freed = 0;
+        while let Some(entry) = channel.unacked.first_entry() {
+            if *entry.key() + entry.get().len() > acked {
+                break;
+            }
+            freed += entry.remove().payload.map_or(0, |payload| payload.len());
+        }
+        channel.window.add_permits(freed);
+        self.remove_if_finished(id);
+    }
+
+    async fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Connected { id, stream } => {
+                // The channel may have been reset while its target was dialed.
+                if let Some(channel) = self.channels.get_mut(&id) {
+                    channel.start(id, stream, false, &self.events);
+                    self.send(EncryptedFrame::ForwardAck((id, 0))).await;
+                }
+            }
+            Event::ConnectFailed(id) | Event::Failed(id) => {
+                if self.channels.remove(&id).is_some() {
+                    self.send(EncryptedFrame::ForwardReset(id)).await;
+                }
+            }
+            Event::Read { id, bytes } => self.queue_segment(id, Some(bytes)).await,
+            Event::Eof(id) => self.queue_segment(id, None).await,
+            Event::Listened { request, listeners } => self.listened(request, listeners).await,
+            Event::Written { id, len } => {
+                let Some(channel) = self.channels.get_mut(&id) else {
+                    return;
+                };
+                channel.recv_written += len;
+                let ack = EncryptedFrame::ForwardAck((id, channel.recv_written));
+                self.send(ack).await;
+                self.remove_if_finished(id);
+            }
+        }
+    }
+
+    /// Assign the next stream offset to a segment read locally and send it.
+    async fn queue_segment(&mut self, id: u32, payload: Option<Vec<u8>>) {
+        let Some(channel) = self.channels.get_mut(&id) else {
+            return;
+        };
+        let offset = channel.send_next;
+        let segment = Segment::new(payload);
+        channel.send_next += segment.len();
+        channel.fin_sent |= segment.payload.is_none();
+        let frame = segment.frame(id, offset);
+        let _previous = channel.unacked.insert(offset, segment);
+        self.send(frame).await;
+    }
+
+    fn remove_if_finished(&mut self, id: u32) {
+        if self.channels.get(&id).is_some_and(Channel::is_finished) {
+            trace!("forwarded channel {id} closed");
+            let _finished = self.channels.remove(&id);
+        }
+    }
+}
+
+/// Read the local socket into segments, never holding more than the window
+/// of unacknowledged bytes.
+async fn read_loop(
+    id: u32,
+    mut read_half: ReadHalf<LocalStream>,
+    window: Arc<Semaphore>,
+    events: Sender<Event>,
+) {
+    let mut buf = vec![0u8; FORWARD_CHUNK_LEN as usize];
+    loop {
+        let Ok(permits) = window.clone().acquire_many_owned(FORWARD_CHUNK_LEN).await else {
+            return;
+        };
+        let event = match read_half.read(&mut buf).await {
+            Ok(0) => Event::Eof(id),
+            Ok(n) => {
+                // The mux returns the permits for these bytes once acknowledged.
+                permits.forget();
+                window.add_permits(buf.len() - n);
+                Event::Read {
+                    id,
+                    bytes: buf[..n].to_vec(),
+                }
+            }
+            Err(e) => {
+                debug!("forwarded channel {id}: read failed: {e}");
+                Event::Failed(id)
+            }
+        };
+        let more = matches!(event, Event::Read { .. });
+        if events.send(event).await.is_err() || !more {
+            return;
+        }
+    }
+}
+
+/// Write the peer's segments to the local socket, reporting each once written
+/// so it can be acknowledged.  `None` shuts down the write side.  With
+/// `socks_reply` the SOCKS client is first told it is connected.
+async fn write_loop(
+    id: u32,
+    mut write_half: WriteHalf<LocalStream>,
+    socks_reply: bool,
+    mut rx: UnboundedReceiver<Option<Vec<u8>>>,
+    events: Sender<Event>,
+) {
+    if socks_reply && let Err(e) = socks::reply(&mut write_half, socks::SUCCEEDED).await {
+        debug!("forwarded channel {id}: SOCKS reply failed: {e}");
+        let _sent = events.send(Event::Failed(id)).await;
+        return;
+    }
+    while let Some(segment) = rx.recv().await {
+        let written = match &segment {
+            Some(bytes) => write_half.write_all(bytes).await,
+            None => write_half.shutdown().await,
+        };
+        let event = match written {
+            Ok(()) => Event::Written {
+                id,
+                len: segment.as_ref().map_or(1, |bytes| bytes.len() as u64),
+            },
+            Err(e) => {
+                debug!("forwarded channel {id}: write failed: {e}");
+                Event::Failed(id)
+            }
+        };
+        let failed = matches!(event, Event::Failed(_));
+        if events.send(event).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use anyhow::Result;
+    use tokio::{
+        io::{AsyncReadExt as _, AsyncWriteExt as _},
+        net::{TcpListener, TcpStream},
+        spawn,
+        sync::mpsc::{Receiver, Sender, channel},
+        time::{sleep, timeout},
+    };
+
+    use super::{
+        ForwardMux, ForwardRole,
+        spec::{parse_dynamic_forward_spec, parse_forward_spec},
+    };
+    use crate::{EncryptedFrame, ForwardPolicy};
+
+    fn server_policy(allow_open: bool) -> ForwardPolicy {
+        ForwardPolicy::builder()
+            .allow_open(allow_open)
+            .allow_dynamic(allow_open)
+            .allow_unix(allow_open)
+            .listen_addresses(vec!["localhost".to_string()])
+            .build()
+    }
+
+    /// Carry frames from one mux's data channel to the other's readers,
+    /// dropping every `drop_every`th frame (0 drops none).
+    fn link(mut rx: Receiver<EncryptedFrame>, to: Sender<EncryptedFrame>, drop_every: usize) {
+        let _handle = spawn(async move {
+            let mut count = 0;
+            while let Some(frame) = rx.recv().await {
+                count += 1;
+                if drop_every != 0 && count % drop_every == 0 {
+                    continue;
+                }
+                let _sent = to.try_send(frame);
+            }
+        });
+    }
+
+    async fn connected_pair(drop_every: usize) -> (ForwardMux, ForwardMux) {
+        let client = ForwardMux::spawn(ForwardRole::Client, ForwardPolicy::default());
+        let server = ForwardMux::spawn(ForwardRole::Server, server_policy(true));
+        let (client_tx, client_rx) = channel(256);
+        let (server_tx, server_rx) = channel(256);
+        client.attach(client_tx).await;
+        server.attach(server_tx).await;
+        link(client_rx, server.frame_tx(), drop_every);
+        link(server_rx, client.frame_tx(), drop_every);
+        (client, server)
+    }
+
+    async fn echo_server() -> Result<u16> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let _handle = spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let _echo = spawn(async move {
+                    let (mut read, mut write) = stream.split();
+                    let _copied = tokio::io::copy(&mut read, &mut write).await;
+                    let _shutdown = write.shutdown().await;
+                });
+            }
+        });
+        Ok(port)
+    }
+
+    async fn round_trip(client: &ForwardMux, target_port: u16, payload: &[u8]) -> Result<Vec<u8>> {
+        let spec = parse_forward_spec(&format!("127.0.0.1:0:127.0.0.1:{target_port}"))?;
+        let bound = client.listen(&spec).await?;
+        let mut stream = TcpStream::connect(bound[0]).await?;
+        stream.write_all(payload).await?;
+        stream.shutdown().await?;
+        let mut echoed = Vec::new();
+        let _read = timeout(Duration::from_secs(30), stream.read_to_end(&mut echoed)).await??;
+        Ok(echoed)
+    }
+
+    #[tokio::test]
+    async fn forwarded_stream_round_trips() -> Result<()> {
+        let port = echo_server().await?;
+        let (client, _server) = connected_pair(0).await;
+        let payload: Vec<u8> = (0..300_000u32).map(|i| i.to_le_bytes()[0]).collect();
+        assert_eq!(round_trip(&client, port, &payload).await?, payload);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forwarded_stream_survives_lost_frames() -> Result<()> {
+        let port = echo_server().await?;
+        let (client, _server) = connected_pair(7).await;
+        let payload: Vec<u8> = (0..20_000u32).map(|i| i.to_le_bytes()[1]).collect();
+        assert_eq!(round_trip(&client, port, &payload).await?, payload);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forwarded_stream_survives_a_reconnect() -> Result<()> {
+        let port = echo_server().await?;
+        let client = ForwardMux::spawn(ForwardRole::Client, ForwardPolicy::default());
+        let server = ForwardMux::spawn(ForwardRole::Server, server_policy(true));
+        // First connection: the client's frames go nowhere.
+        let (dead_tx, dead_rx) = channel(256);
+        client.attach(dead_tx).await;
+        let spec = parse_forward_spec(&format!("127.0.0.1:0:127.0.0.1:{port}"))?;
+        let bound = client.listen(&spec).await?;
+        let mut stream = TcpStream::connect(bound[0]).await?;
+        stream.write_all(b"across the roam").await?;
+        stream.shutdown().await?;
+        sleep(Duration::from_millis(200)).await;
+        drop(dead_rx);
+
+        // Second connection carries everything that was never acknowledged.
+        let (client_tx, client_rx) = channel(256);
+        let (server_tx, server_rx) = channel(256);
+        server.attach(server_tx).await;
+        link(server_rx, client.frame_tx(), 0);
+        link(client_rx, server.frame_tx(), 0);
+        client.attach(client_tx).await;
+
+        let mut echoed = Vec::new();
+        let _read = timeout(Duration::from_secs(30), stream.read_to_end(&mut echoed)).await??;
+        assert_eq!(echoed, b"across the roam");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refused_open_closes_the_local_stream() -> Result<()> {
+        let port = echo_server().await?;
+        let client = ForwardMux::spawn(ForwardRole::Client, ForwardPolicy::default());
+        let server = ForwardMux::spawn(ForwardRole::Server, server_policy(false));
+        let (client_tx, client_rx) = channel(256);
+        let (server_tx, server_rx) = channel(256);
+        client.attach(client_tx).await;
+        server.attach(server_tx).await;
+        link(client_rx, server.frame_tx(), 0);
+        link(server_rx, client.frame_tx(), 0);
+
+        let spec = parse_forward_spec(&format!("127.0.0.1:0:127.0.0.1:{port}"))?;
+        let bound = client.listen(&spec).await?;
+        let mut stream = TcpStream::connect(bound[0]).await?;
+        let mut buf = Vec::new();
+        let read = timeout(Duration::from_secs(10), stream.read_to_end(&mut buf)).await?;
+        assert!(read.is_err() || buf.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remote_listener_forwards_back_and_closes_with_the_mux() -> Result<()> {
+        let target = echo_server().await?;
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        let (client, server) = connected_pair(0).await;
+        client
+            .request_listen(parse_forward_spec(&format!("{port}:127.0.0.1:{target}"))?)
+            .await;
+
+        let mut stream = timeout(Duration::from_secs(10), async {
+            loop {
+                if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)).await {
+                    return stream;
+                }
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await?;
+        stream.write_all(b"back to the client").await?;
+        stream.shutdown().await?;
+        let mut echoed = Vec::new();
+        let _read = timeout(Duration::from_secs(30), stream.read_to_end(&mut echoed)).await??;
+        assert_eq!(echoed, b"back to the client");
+
+        server.close();
+        timeout(Duration::from_secs(10), async {
+            while TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn remote_unix_socket_forwards_back_and_is_removed() -> Result<()> {
+        use tokio::net::UnixStream;
+
+        let target = echo_server().await?;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("remote.sock");
+        let (client, server) = connected_pair(0).await;
+        client
+            .request_listen(parse_forward_spec(&format!(
+                "{}:127.0.0.1:{target}",
+                path.display()
+            ))?)
+            .await;
+
+        let mut stream = timeout(Duration::from_secs(10), async {
+            loop {
+                if let Ok(stream) = UnixStream::connect(&path).await {
+                    return stream;
+                }
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await?;
+        stream.write_all(b"over the socket").await?;
+        stream.shutdown().await?;
+        let mut echoed = Vec::new();
+        let _read = timeout(Duration::from_secs(30), stream.read_to_end(&mut echoed)).await??;
+        assert_eq!(echoed, b"over the socket");
+
+        server.close();
+        timeout(Duration::from_secs(10), async {
+            while path.exists() {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn forwarded_agent_answers_through_the_client_agent() -> Result<()> {
+        use bincode_next::{config::standard, decode_from_slice, encode_to_vec};
+        use tokio::net::{UnixListener, UnixStream};
+
+        use crate::{AgentRequest, AgentResponse};
+
+        let dir = tempfile::tempdir()?;
+        let agent_path = dir.path().join("mpa.sock");
+        let agent = UnixListener::bind(&agent_path)?;
+        let _agent = spawn(async move {
+            while let Ok((mut stream, _)) = agent.accept().await {
+                let mut buf = vec![0u8; stream.read_u32().await? as usize];
+                let _ = stream.read_exact(&mut buf).await?;
+                let encoded = encode_to_vec(AgentResponse::PublicKey(vec![1, 2, 3]), standard())?;
+                stream
+                    .write_all(&u32::try_from(encoded.len())?.to_be_bytes())
+                    .await?;
+                stream.write_all(&encoded).await?;
+            }
+            anyhow::Ok(())
+        });
+
+        let client_policy = ForwardPolicy::builder().agent_socket(agent_path).build();
+        let client = ForwardMux::spawn(ForwardRole::Client, client_policy);
+        let server = ForwardMux::spawn(ForwardRole::Server, server_policy(false));
+        let (client_tx, client_rx) = channel(256);
+        let (server_tx, server_rx) = channel(256);
+        client.attach(client_tx).await;
+        server.attach(server_tx).await;
+        link(client_rx, server.frame_tx(), 0);
+        link(server_rx, client.frame_tx(), 0);
+
+        let socket = server.listen_agent(None)?;
+        let mut stream = UnixStream::connect(&socket).await?;
+        let request = encode_to_vec(
+            AgentRequest::GetPublicKey("SHA256:abcd".to_string()),
+            standard(),
+        )?;
+        stream
+            .write_all(&u32::try_from(request.len())?.to_be_bytes())
+            .await?;
+        stream.write_all(&request).await?;
+        let len = timeout(Duration::from_secs(30), stream.read_u32()).await??;
+        let mut buf = vec![0u8; len as usize];
+        let _ = stream.read_exact(&mut buf).await?;
+        let (response, _) = decode_from_slice::<AgentResponse, _>(&buf, standard())?;
+        assert!(matches!(response, AgentResponse::PublicKey(ref key) if key == &[1, 2, 3]));
+
+        server.close();
+        let private_dir = socket
+            .parent()
+            .map(std::path::Path::to_path_buf)
+            .unwrap_or_default();
+        timeout(Duration::from_secs(10), async {
+            while private_dir.exists() {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Greet the SOCKS proxy at `proxy` and ask it to connect to
+    /// `localhost:port`, returning the stream and the proxy's reply code.
+    async fn socks_connect(proxy: SocketAddr, port: u16) -> Result<(TcpStream, u8)> {
+        let mut stream = TcpStream::connect(proxy).await?;
+        stream.write_all(&[5, 1, 0]).await?;
+        stream.write_all(&[5, 1, 0, 3, 9]).await?;
+        stream.write_all(b"localhost").await?;
+        stream.write_all(&port.to_be_bytes()).await?;
+        let mut reply = [0; 12];
+        let _read = timeout(Duration::from_secs(10), stream.read_exact(&mut reply)).await??;
+        assert_eq!(reply[..3], [5, 0, 5]);
+        Ok((stream, reply[3]))
+    }
+
+    #[tokio::test]
+    async fn socks_connect_round_trips() -> Result<()> {
+        let port = echo_server().await?;
+        let (client, _server) = connected_pair(0).await;
+        let bound = client
+            .listen_socks(&parse_dynamic_forward_spec("127.0.0.1:0")?)
+            .await?;
+        let (mut stream, code) = socks_connect(bound[0], port).await?;
+        assert_eq!(code, 0);
+        stream.write_all(b"through the proxy").await?;
+        stream.shutdown().await?;
+        let mut echoed = Vec::new();
+        let _read = timeout(Duration::from_secs(30), stream.read_to_end(&mut echoed)).await??;
+        assert_eq!(echoed, b"through the proxy");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refused_socks_connect_is_reported_to_the_client() -> Result<()> {
+        let port = echo_server().await?;
+        let client = ForwardMux::spawn(ForwardRole::Client, ForwardPolicy::default());
+        // Plain opens are allowed, SOCKS opens are not.
+        let server = ForwardMux::spawn(
+            ForwardRole::Server,
+            ForwardPolicy::builder().allow_open(true).build(),
+        );
+        let (client_tx, client_rx) = channel(256);
+        let (server_tx, server_rx) = channel(256);
+        client.attach(client_tx).await;
+        server.attach(server_tx).await;
+        link(client_rx, server.frame_tx(), 0);
+        link(server_rx, client.frame_tx(), 0);
+
+        let bound = client
+            .listen_socks(&parse_dynamic_forward_spec("127.0.0.1:0")?)
+            .await?;
+        let (_stream, code) = socks_connect(bound[0], port).await?;
+        assert_eq!(code, 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stdio_channels_end_once_both_sides_close() -> Result<()> {
+        for allow_stdio in [true, false] {
+            let policy = ForwardPolicy::builder().allow_stdio(allow_stdio).build();
+            let client = ForwardMux::spawn(ForwardRole::Client, policy);
+            let server = ForwardMux::spawn(ForwardRole::Server, server_policy(false));
+            let (client_tx, client_rx) = channel(256);
+            let (server_tx, server_rx) = channel(256);
+            client.attach(client_tx).await;
+            server.attach(server_tx).await;
+            link(client_rx, server.frame_tx(), 0);
+            link(server_rx, client.frame_tx(), 0);
+
+            // An empty command output: the channel to the client's standard
+            // error ends as soon as it is open, or is refused.
+            let gone = server.open_stdio(2, Some(Box::new(&b""[..])), None).await;
+            let _gone = timeout(Duration::from_secs(10), gone).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn provided_stdio_streams_stand_in_for_our_own() -> Result<()> {
+        // No stdio in the policy: only the provided stream is served.
+        let client = ForwardMux::spawn(ForwardRole::Client, ForwardPolicy::default());
+        let server = ForwardMux::spawn(ForwardRole::Server, server_policy(false));
+        let (client_tx, client_rx) = channel(256);
+        let (server_tx, server_rx) = channel(256);
+        client.attach(client_tx).await;
+        server.attach(server_tx).await;
+        link(client_rx, server.frame_tx(), 0);
+        link(server_rx, client.frame_tx(), 0);
+
+        let (near, mut far) = tokio::io::duplex(1024);
+        client.provide_stdio(1, None, Some(Box::new(near))).await;
+        let gone = server
+            .open_stdio(1, Some(Box::new(&b"copied bytes"[..])), None)
+            .await;
+        let mut received = Vec::new();
+        let _read = timeout(Duration::from_secs(10), far.read_to_end(&mut received)).await??;
+        assert_eq!(received, b"copied bytes");
+        let _gone = timeout(Duration::from_secs(10), gone).await?;
+        Ok(())
+    }
+
+    #[test]
+    fn ids_alternate_by_role() {
+        assert!(ForwardRole::Client.owns(ForwardRole::Client.first_id()));
+        assert!(ForwardRole::Server.owns(ForwardRole::Server.first_id()));
+        assert!(!ForwardRole::Client.owns(ForwardRole::Server.first_id()));
+        assert!(!ForwardRole::Server.owns(ForwardRole::Client.first_id()));
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! What a [`ForwardMux`](crate::ForwardMux) lets its peer ask of it.
+
+use std::path::{Path, PathBuf};
+
+use bon::Builder;
+
+use crate::forward::listen::bind_address_or_default;
+
+/// Ports below this need root to bind and are never bound for a peer.
+const FIRST_UNPRIVILEGED_PORT: u16 = 1024;
+
+/// The requests from the peer a [`ForwardMux`](crate::ForwardMux) honours.
+///
+/// The default refuses everything: the peer can neither open channels to
+/// targets of its choosing, nor on behalf of a SOCKS client, nor to Unix
+/// sockets, the local agent or standard streams, nor have this side listen
+/// for it.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Builder, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ForwardPolicy {
+    /// Dial whatever `host:port` the peer names in an
+    /// [`EncryptedFrame::ForwardOpen`](crate::EncryptedFrame::ForwardOpen), as
+    /// moshpits does for `mp -L`.
+    #[builder(default)]
+    allow_open: bool,
+    /// Dial whatever `host:port` the peer names in an
+    /// [`EncryptedFrame::ForwardConnect`](crate::EncryptedFrame::ForwardConnect),
+    /// as moshpits does for `mp -D`.
+    #[builder(default)]
+    allow_dynamic: bool,
+    /// Dial the Unix sockets the peer names in an
+    /// [`EncryptedFrame::ForwardOpenUnix`](crate::EncryptedFrame::ForwardOpenUnix),
+    /// and listen on the ones it names in an
+    /// [`EncryptedFrame::ForwardListenUnix`](crate::EncryptedFrame::ForwardListenUnix).
+    /// Unix-only.
+    #[builder(default)]
+    allow_unix: bool,
+    /// The account targets are dialed and Unix sockets bound for, when it is
+    /// not the one this process runs as (moshpits running as root).  TCP
+    /// connections then come from sockets the account owns, and Unix sockets
+    /// are only dialed, and only bound in directories, the account could reach
+    /// itself, and the sockets bound are handed over to it.
+    session_user: Option<ForwardUser>,
+    /// Bind addresses the peer may ask this side to listen on with an
+    /// [`EncryptedFrame::ForwardListen`](crate::EncryptedFrame::ForwardListen),
+    /// as moshpits does for `mp -R`.  `"*"` allows any address.  `None` refuses
+    /// every listen request.
+    listen_addresses: Option<Vec<String>>,
+    /// The local `mpa` socket that channels the peer opens with an
+    /// [`EncryptedFrame::ForwardOpenAgent`](crate::EncryptedFrame::ForwardOpenAgent)
+    /// are relayed to, as moshpit does for `mp --forward-agent`.  Only
+    /// requests that use a loaded key are relayed.  `None` refuses every such
+    /// channel.  Unix-only.
+    agent_socket: Option<PathBuf>,
+    /// Also relay key agreement (`Agree`) on the channels relayed to
+    /// `agent_socket`, as moshpit does for `mp --forward-agent-agree`.
+    /// X25519, P-256 and P-384 identities prove possession with key agreement
+    /// rather than a signature, so only this lets them authenticate onward
+    /// through a forwarded agent; it also lets whoever reaches the forwarded
+    /// socket agree keys with every loaded identity, so it is off by default.
+    #[builder(default)]
+    allow_agent_agree: bool,
+    /// Carry this process's standard streams on the channels the peer opens
+    /// with an
+    /// [`EncryptedFrame::ForwardOpenStdio`](crate::EncryptedFrame::ForwardOpenStdio),
+    /// as moshpit does for a remote command run without a PTY.
+    #[builder(default)]
+    allow_stdio: bool,
+}
+
+impl ForwardPolicy {
+    /// Whether the peer may open a channel to any target.
+    pub(crate) fn allow_open(&self) -> bool {
+        self.allow_open
+    }
+
+    /// Whether the peer may open a channel for a SOCKS client.
+    pub(crate) fn allow_dynamic(&self) -> bool {
+        self.allow_dynamic
+    }
+
+    /// Whether the peer may dial and listen on Unix sockets.
+    pub(crate) fn allow_unix(&self) -> bool {
+        self.allow_unix
+    }
+
+    /// The account targets are dialed and Unix sockets bound for, if not this
+    /// process's own.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn session_user(&self) -> Option<&ForwardUser> {
+        self.session_user.as_ref()
+    }
+
+    /// The local agent the peer's agent channels are relayed to, if any.
+    pub(crate) fn agent_socket(&self) -> Option<&Path> {
+        self.agent_socket.as_deref()
+    }
+
+    /// Whether key agreement is relayed to the local agent as well.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn allow_agent_agree(&self) -> bool {
+        self.allow_agent_agree
+    }
+
+    /// Whether the peer may open channels to this process's standard streams.
+    pub(crate) fn allow_stdio(&self) -> bool {
+        self.allow_stdio
+    }
+
+    /// Whether the peer may have this side listen on `bind_address:port`.
+    /// An empty `bind_address` stands for `localhost`.
+    pub(crate) fn allows_listen(&self, bind_address: &str, port: u16) -> bool {
+        let bind_address = bind_address_or_default(bind_address);
+        (port == 0 || port >= FIRST_UNPRIVILEGED_PORT)
+            && self.listen_addresses.as_ref().is_some_and(|allowed| {
+                allowed
+                    .iter()
+                    .any(|address| address == "*" || address == bind_address)
+            })
+    }
+}
+
+/// An account the server acts for: the owner of the sockets it dials and of
+/// the Unix sockets it binds, and the credentials it dials them with.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ForwardUser {
+    uid: u32,
+    gid: u32,
+    groups: Vec<u32>,
+}
+
+impl ForwardUser {
+    /// The account with user id `uid`, primary group `gid`, and supplementary
+    /// `groups`.
+    #[must_use]
+    pub fn new(uid: u32, gid: u32, groups: Vec<u32>) -> Self {
+        Self { uid, gid, groups }
+    }
+
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn groups(&self) -> &[u32] {
+        &self.groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ForwardPolicy;
+
+    #[test]
+    fn default_refuses_everything() {
+        let policy = ForwardPolicy::default();
+        assert!(!policy.allow_open());
+        assert!(!policy.allow_dynamic());
+        assert!(!policy.allow_unix());
+        assert!(policy.session_user().is_none());
+        assert!(policy.agent_socket().is_none());
+        assert!(!policy.allow_agent_agree());
+        assert!(!policy.allows_listen("", 8080));
+    }
+
+    #[test]
+    fn listen_is_limited_to_listed_addresses_and_unprivileged_ports() {
+        let policy = ForwardPolicy::builder()
+            .listen_addresses(vec!["localhost".to_string(), "127.0.0.1".to_string()])
+            .build();
+        assert!(policy.allows_listen("", 8080));
+        assert!(policy.allows_listen("127.0.0.1", 0));
+        assert!(!policy.allows_listen("0.0.0.0", 8080));
+        assert!(!policy.allows_listen("", 80));
+
+        let policy = ForwardPolicy::builder()
+            .listen_addresses(vec!["*".to_string()])
+            .build();
+        assert!(policy.allows_listen("0.0.0.0", 8080));
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! The SOCKS5 (RFC 1928) front of `mp -D`: no authentication, `CONNECT` only,
+//! with domain names passed through unresolved so the server resolves them.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+
+use crate::MoshpitError;
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Reply code: the connection was made.
+pub(crate) const SUCCEEDED: u8 = 0;
+/// Reply code: the server refused or could not reach the target.
+pub(crate) const HOST_UNREACHABLE: u8 = 4;
+const COMMAND_NOT_SUPPORTED: u8 = 7;
+const ADDRESS_TYPE_NOT_SUPPORTED: u8 = 8;
+
+/// Read a SOCKS5 greeting and `CONNECT` request from `stream` and return the
+/// requested `(host, port)`.  The reply to the request is left to the caller,
+/// who sends it with [`reply`] once the channel is open or refused.
+///
+/// # Errors
+/// * The stream fails or ends early.
+/// * [`MoshpitError::InvalidSocksRequest`] when the client is not speaking
+///   SOCKS5, offers no acceptable authentication, or asks for anything but a
+///   `CONNECT`; the client is told why first where the protocol allows.
+pub(crate) async fn accept<S>(stream: &mut S) -> Result<(String, u16)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let [version, method_count] = read_array(stream).await?;
+    if version != VERSION {
+        return Err(MoshpitError::InvalidSocksRequest.into());
+    }
+    let mut methods = vec![0; usize::from(method_count)];
+    let _read = stream.read_exact(&mut methods).await?;
+    if !methods.contains(&NO_AUTHENTICATION) {
+        stream.write_all(&[VERSION, NO_ACCEPTABLE_METHODS]).await?;
+        return Err(MoshpitError::InvalidSocksRequest.into());
+    }
+    stream.write_all(&[VERSION, NO_AUTHENTICATION]).await?;
+
+    let [version, command, _reserved, address_type] = read_array(stream).await?;
+    if version != VERSION {
+        return Err(MoshpitError::InvalidSocksRequest.into());
+    }
+    let host = match address_type {
+        ATYP_IPV4 => Ipv4Addr::from(read_array::<_, 4>(stream).await?).to_string(),
+        ATYP_IPV6 => Ipv6Addr::from(read_array::<_, 16>(stream).await?).to_string(),
+        ATYP_DOMAIN => {
+            let [len] = read_array(stream).await?;
+            let mut name = vec![0; usize::from(len)];
+            let _read = stream.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|_| MoshpitError::InvalidSocksRequest)?
+        }
+        _ => {
+            reply(stream, ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Err(MoshpitError::InvalidSocksRequest.into());
+        }
+    };
+    let port = u16::from_be_bytes(read_array(stream).await?);
+    if command != CONNECT {
+        reply(stream, COMMAND_NOT_SUPPORTED).await?;
+        return Err(MoshpitError::InvalidSocksRequest.into());
+    }
+    if host.is_empty() || port == 0 {
+        reply(stream, HOST_UNREACHABLE).await?;
+        return Err(MoshpitError::InvalidSocksRequest.into());
+    }
+    Ok((host, port))
+}
+
+/// Send the reply to a `CONNECT` request.  The bound address is not known on
+/// this side of the session, so it is always reported as `0.0.0.0:0`.
+///
+/// # Errors
+/// * The stream fails.
+pub(crate) async fn reply<S>(stream: &mut S, code: u8) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream
+        .write_all(&[VERSION, code, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
+
+async fn read_array<S, const N: usize>(stream: &mut S) -> Result<[u8; N]>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = [0; N];
+    let _read = stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _, duplex};
+
+    use super::{SUCCEEDED, accept, reply};
+
+    #[tokio::test]
+    async fn connect_by_domain_name() -> Result<()> {
+        let (mut client, mut proxy) = duplex(64);
+        client.write_all(&[5, 2, 2, 0]).await?;
+        client.write_all(&[5, 1, 0, 3, 11]).await?;
+        client.write_all(b"example.com").await?;
+        client.write_all(&443u16.to_be_bytes()).await?;
+        assert_eq!(accept(&mut proxy).await?, ("example.com".to_string(), 443));
+        reply(&mut proxy, SUCCEEDED).await?;
+
+        let mut answer = [0; 12];
+        let _read = client.read_exact(&mut answer).await?;
+        assert_eq!(answer, [5, 0, 5, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connect_by_address() -> Result<()> {
+        let (mut client, mut proxy) = duplex(64);
+        client.write_all(&[5, 1, 0]).await?;
+        client
+            .write_all(&[5, 1, 0, 1, 127, 0, 0, 1, 0x1f, 0x90])
+            .await?;
+        assert_eq!(accept(&mut proxy).await?, ("127.0.0.1".to_string(), 8080));
+
+        let (mut client, mut proxy) = duplex(64);
+        client.write_all(&[5, 1, 0]).await?;
+        client.write_all(&[5, 1, 0, 4]).await?;
+        client.write_all(&[0; 15]).await?;
+        client.write_all(&[1, 0, 80]).await?;
+        assert_eq!(accept(&mut proxy).await?, ("::1".to_string(), 80));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_unauthenticated_connect_is_served() -> Result<()> {
+        // Username/password only.
+        let (mut client, mut proxy) = duplex(64);
+        client.write_all(&[5, 1, 2]).await?;
+        assert!(accept(&mut proxy).await.is_err());
+        let mut answer = [0; 2];
+        let _read = client.read_exact(&mut answer).await?;
+        assert_eq!(answer, [5, 0xff]);
+
+        // BIND.
+        let (mut client, mut proxy) = duplex(64);
+        client.write_all(&[5, 1, 0]).await?;
+        client.write_all(&[5, 2, 0, 1, 127, 0, 0, 1, 0, 80]).await?;
+        assert!(accept(&mut proxy).await.is_err());
+        let mut answer = [0; 4];
+        let _read = client.read_exact(&mut answer).await?;
+        assert_eq!(answer, [5, 0, 5, 7]);
+
+        // SOCKS4.
+        let (mut client, mut proxy) = duplex(64);
+        client.write_all(&[4, 1]).await?;
+        assert!(accept(&mut proxy).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! `[bind:]port:host:hostport` and `[bind:]port` forwarding specifications.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use anyhow::Result;
+use getset::{CopyGetters, Getters};
+
+use crate::MoshpitError;
+
+/// A parsed `[bind:]port:host:hostport` port-forwarding specification, as given
+/// to `mp -L` or `mp -R`.
+///
+/// For `-L` the bind end is on the client and `host:hostport` is dialed by the
+/// server; for `-R` it is the other way round.  Either end may instead be a
+/// Unix-domain socket path, in which case its TCP fields are unset (`None`,
+/// empty, or 0).  Addresses are kept as typed (minus the brackets around an
+/// IPv6 literal) and resolved when the listener is bound or the target is
+/// dialed.
+#[derive(Clone, CopyGetters, Debug, Eq, Getters, PartialEq)]
+pub struct ForwardSpec {
+    /// The address to listen on; `None` listens on the loopback interface only
+    #[getset(get = "pub")]
+    bind_address: Option<String>,
+    /// The port to listen on
+    #[getset(get_copy = "pub")]
+    bind_port: u16,
+    /// The Unix socket to listen on instead of a TCP port
+    #[getset(get = "pub")]
+    bind_path: Option<String>,
+    /// The host connected to from the far end of the session
+    #[getset(get = "pub")]
+    host: String,
+    /// The port connected to from the far end of the session
+    #[getset(get_copy = "pub")]
+    host_port: u16,
+    /// The Unix socket connected to from the far end instead of `host:host_port`
+    #[getset(get = "pub")]
+    host_path: Option<String>,
+}
+
+impl ForwardSpec {
+    /// The target end: `host:hostport`, or the socket path.
+    #[must_use]
+    pub fn target(&self) -> String {
+        match &self.host_path {
+            Some(path) => path.clone(),
+            None => format!("{}:{}", bracketed(&self.host), self.host_port),
+        }
+    }
+}
+
+impl Display for ForwardSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        if let Some(bind_path) = &self.bind_path {
+            write!(f, "{}:", bracketed(bind_path))?;
+        } else {
+            if let Some(bind_address) = &self.bind_address {
+                write!(f, "{}:", bracketed(bind_address))?;
+            }
+            write!(f, "{}:", self.bind_port)?;
+        }
+        match &self.host_path {
+            Some(host_path) => write!(f, "{}", bracketed(host_path)),
+            None => write!(f, "{}", self.target()),
+        }
+    }
+}
+
+/// A parsed `[bind:]port` dynamic-forwarding specification, as given to
+/// `mp -D`: where the local SOCKS5 proxy listens.
+#[derive(Clone, CopyGetters, Debug, Eq, Getters, PartialEq)]
+pub struct DynamicForwardSpec {
+    /// The address to listen on; `None` listens on the loopback interface only
+    #[getset(get = "pub")]
+    bind_address: Option<String>,
+    /// The port to listen on
+    #[getset(get_copy = "pub")]
+    bind_port: u16,
+}
+
+impl Display for DynamicForwardSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        if let Some(bind_address) = &self.bind_address {
+            write!(f, "{}:", bracketed(bind_address))?;
+        }
+        write!(f, "{}", self.bind_port)
+    }
+}
+
+/// Wrap an IPv6 literal in brackets so its colons do not split the spec.
+fn bracketed(host: &str) -> String {
+    if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    }
+}
+
+/// Split `spec` on the colons outside brackets, stripping the brackets from
+/// each field.  Returns `None` for unbalanced brackets.
+fn split_fields(spec: &str) -> Option<Vec<&str>> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut in_brackets = false;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '[' if !in_brackets && i == start => in_brackets = true,
+            ']' if in_brackets => in_brackets = false,
+            '[' | ']' => return None,
+            ':' if !in_brackets => {
+                fields.push(&spec[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if in_brackets {
+        return None;
+    }
+    fields.push(&spec[start..]);
+    fields
+        .into_iter()
+        .map(|field| match field.strip_prefix('[') {
+            Some(inner) => inner.strip_suffix(']'),
+            None => Some(field),
+        })
+        .collect()
+}
+
+/// Whether a spec field names a Unix-domain socket rather than a host or port.
+fn is_path(field: &str) -> bool {
+    field.contains('/')
+}
+
+/// Parse a port-forwarding specification into a [`ForwardSpec`].
+///
+/// Accepts `[bind:]port:host:hostport`, where `bind` and `host` are DNS names,
+/// dotted-quad IPv4 addresses, or bracketed IPv6 literals
+/// (`[::1]:8080:[2001:db8::1]:80`).  An omitted or empty `bind` listens on the
+/// loopback interface only.  `port` may be 0 to let the system pick one.
+///
+/// Either end may be replaced by a Unix-domain socket path, recognised by the
+/// `/` it contains: `/tmp/docker.sock:/var/run/docker.sock`,
+/// `8080:/run/app.sock`, or `/tmp/db.sock:localhost:5432`.  A path containing
+/// `:` goes in brackets.
+///
+/// # Errors
+/// * [`MoshpitError::InvalidForwardSpec`] when the spec is not in that form.
+///
+pub fn parse_forward_spec(spec: &str) -> Result<ForwardSpec> {
+    let fields = split_fields(spec).ok_or(MoshpitError::InvalidForwardSpec)?;
+    let target_len = if fields.last().is_some_and(|field| is_path(field)) {
+        1
+    } else {
+        2
+    };
+    let split = fields
+        .len()
+        .checked_sub(target_len)
+        .ok_or(MoshpitError::InvalidForwardSpec)?;
+    let (bind, target) = fields.split_at(split);
+
+    let (bind_address, bind_port, bind_path) = match bind {
+        [bind_path] if is_path(bind_path) => (None, 0, Some((*bind_path).to_string())),
+        [bind_port] => (None, parse_port(bind_port)?, None),
+        [bind_address, bind_port] => (
+            Some((*bind_address).to_string()).filter(|bind| !bind.is_empty()),
+            parse_port(bind_port)?,
+            None,
+        ),
+        _ => return Err(MoshpitError::InvalidForwardSpec.into()),
+    };
+    let (host, host_port, host_path) = match target {
+        [host_path] => (String::new(), 0, Some((*host_path).to_string())),
+        [host, host_port] if !host.is_empty() => (
+            (*host).to_string(),
+            Some(parse_port(host_port)?)
+                .filter(|port| *port != 0)
+                .ok_or(MoshpitError::InvalidForwardSpec)?,
+            None,
+        ),
+        _ => return Err(MoshpitError::InvalidForwardSpec.into()),
+    };
+    Ok(ForwardSpec {
+        bind_address,
+        bind_port,
+        bind_path,
+        host,
+        host_port,
+        host_path,
+    })
+}
+
+fn parse_port(port: &str) -> Result<u16> {
+    Ok(port
+        .parse::<u16>()
+        .map_err(|_| MoshpitError::InvalidForwardSpec)?)
+}
+
+/// Parse a dynamic-forwarding specification into a [`DynamicForwardSpec`].
+///
+/// Accepts `[bind:]port` with `bind` as for [`parse_forward_spec`]; an omitted
+/// or empty `bind` listens on the loopback interface only.
+///
+/// # Errors
+/// * [`MoshpitError::InvalidDynamicForwardSpec`] when the spec is not in that form.
+///
+pub fn parse_dynamic_forward_spec(spec: &str) -> Result<DynamicForwardSpec> {
+    let fields = split_fields(spec).ok_or(MoshpitError::InvalidDynamicForwardSpec)?;
+    let (bind_address, bind_port) = match fields.as_slice() {
+        [bind_port] => (None, bind_port),
+        [bind_address, bind_port] => (
+            Some((*bind_address).to_string()).filter(|bind| !bind.is_empty()),
+            bind_port,
+        ),
+        _ => return Err(MoshpitError::InvalidDynamicForwardSpec.into()),
+    };
+    let bind_port = bind_port
+        .parse::<u16>()
+        .map_err(|_| MoshpitError::InvalidDynamicForwardSpec)?;
+    Ok(DynamicForwardSpec {
+        bind_address,
+        bind_port,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::{parse_dynamic_forward_spec, parse_forward_spec};
+
+    #[test]
+    fn port_host_hostport() -> Result<()> {
+        let spec = parse_forward_spec("8080:db.internal:5432")?;
+        assert_eq!(spec.bind_address(), &None);
+        assert_eq!(spec.bind_port(), 8080);
+        assert_eq!(spec.host(), "db.internal");
+        assert_eq!(spec.host_port(), 5432);
+        assert_eq!(spec.to_string(), "8080:db.internal:5432");
+        Ok(())
+    }
+
+    #[test]
+    fn bind_address_is_optional_and_may_be_empty() -> Result<()> {
+        let spec = parse_forward_spec("0.0.0.0:8080:localhost:80")?;
+        assert_eq!(spec.bind_address().as_deref(), Some("0.0.0.0"));
+        let spec = parse_forward_spec(":8080:localhost:80")?;
+        assert_eq!(spec.bind_address(), &None);
+        Ok(())
+    }
+
+    #[test]
+    fn bracketed_ipv6_literals() -> Result<()> {
+        let spec = parse_forward_spec("[::1]:8080:[2001:db8::1]:80")?;
+        assert_eq!(spec.bind_address().as_deref(), Some("::1"));
+        assert_eq!(spec.host(), "2001:db8::1");
+        assert_eq!(spec.to_string(), "[::1]:8080:[2001:db8::1]:80");
+        Ok(())
+    }
+
+    #[test]
+    fn unix_socket_ends() -> Result<()> {
+        let spec = parse_forward_spec("/tmp/docker.sock:/var/run/docker.sock")?;
+        assert_eq!(spec.bind_path().as_deref(), Some("/tmp/docker.sock"));
+        assert_eq!(spec.host_path().as_deref(), Some("/var/run/docker.sock"));
+        assert_eq!(spec.target(), "/var/run/docker.sock");
+        assert_eq!(spec.to_string(), "/tmp/docker.sock:/var/run/docker.sock");
+
+        let spec = parse_forward_spec("127.0.0.1:8080:/run/app.sock")?;
+        assert_eq!(spec.bind_address().as_deref(), Some("127.0.0.1"));
+        assert_eq!(spec.bind_port(), 8080);
+        assert_eq!(spec.host_path().as_deref(), Some("/run/app.sock"));
+
+        let spec = parse_forward_spec("/tmp/db.sock:localhost:5432")?;
+        assert_eq!(spec.bind_path().as_deref(), Some("/tmp/db.sock"));
+        assert_eq!(spec.bind_address(), &None);
+        assert_eq!(spec.target(), "localhost:5432");
+        assert_eq!(spec.to_string(), "/tmp/db.sock:localhost:5432");
+
+        let spec = parse_forward_spec("[/tmp/odd:name.sock]:./rel/app.sock")?;
+        assert_eq!(spec.bind_path().as_deref(), Some("/tmp/odd:name.sock"));
+        assert_eq!(spec.to_string(), "[/tmp/odd:name.sock]:./rel/app.sock");
+
+        for spec in ["/tmp/a.sock", "/tmp/a.sock:host", "a:b:/tmp/x.sock"] {
+            assert!(
+                parse_forward_spec(spec).is_err(),
+                "{spec:?} should not parse"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn dynamic_specs() -> Result<()> {
+        let spec = parse_dynamic_forward_spec("1080")?;
+        assert_eq!(spec.bind_address(), &None);
+        assert_eq!(spec.bind_port(), 1080);
+        let spec = parse_dynamic_forward_spec("[::1]:1080")?;
+        assert_eq!(spec.bind_address().as_deref(), Some("::1"));
+        assert_eq!(spec.to_string(), "[::1]:1080");
+        for spec in ["", "socks", "a:b:1080", "70000"] {
+            assert!(
+                parse_dynamic_forward_spec(spec).is_err(),
+                "{spec:?} should not parse"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_specs_are_rejected() {
+        for spec in [
+            "",
+            "8080",
+            "8080:host",
+            "a:b:c:d:e",
+            "x:host:80",
+            "8080:host:0",
+            "8080::80",
+            "70000:host:80",
+            "8080:[::1:80",
+            "8080:host]:80",
+        ] {
+            assert!(
+                parse_forward_spec(spec).is_err(),
+                "{spec:?} should not parse"
+            );
+        }
+    }
+}
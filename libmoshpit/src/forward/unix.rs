@@ -0,0 +1,310 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Unix-domain socket ends of forwards (`mp -L /tmp/docker.sock:/var/run/docker.sock`).
+//!
+//! When the mux acts for another account (the `session_user` of its
+//! [`ForwardPolicy`](crate::ForwardPolicy)), which is moshpits running as root,
+//! every filesystem step on a socket path is taken by a child process running
+//! as that account: dialing, binding, restricting the socket to mode 0600, and
+//! removing it and its private directory again once its listener closes.  The
+//! kernel then checks the account's own access, the peer sees its credentials
+//! rather than root's, and a path the account swaps for a symlink leads nowhere
+//! the account could not reach itself.
+
+use std::{
+    ffi::CString,
+    io::{Error, ErrorKind, Result as IoResult},
+    os::{
+        fd::OwnedFd,
+        unix::{
+            ffi::OsStrExt as _,
+            net::{UnixListener as StdUnixListener, UnixStream as StdUnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use socket2::{Domain, SockAddr, Socket, Type};
+use tokio::{
+    net::{UnixListener, UnixStream},
+    task::spawn_blocking,
+};
+use tracing::debug;
+
+use crate::forward::{account::as_user, policy::ForwardUser};
+
+/// Pending connections queued on a bound socket.
+const BACKLOG: i32 = 128;
+
+/// A socket file bound for a forward, removed when dropped, along with the
+/// private directory created for it, if any.
+#[derive(Debug)]
+pub(crate) struct SocketFile {
+    path: PathBuf,
+    dir: Option<PathBuf>,
+    user: Option<ForwardUser>,
+}
+
+impl SocketFile {
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SocketFile {
+    #[allow(unsafe_code)]
+    fn drop(&mut self) {
+        let user = self.user.as_ref();
+        // SAFETY: `unlink` only reads the path, a valid C string.
+        let removed = c_path(&self.path)
+            .and_then(|path| as_user(user, || check(unsafe { libc::unlink(path.as_ptr()) })));
+        if let Err(e) = removed {
+            debug!("cannot remove {}: {e}", self.path.display());
+        }
+        if let Some(dir) = &self.dir {
+            // SAFETY: `rmdir` only reads the path, a valid C string.
+            let removed = c_path(dir)
+                .and_then(|dir| as_user(user, || check(unsafe { libc::rmdir(dir.as_ptr()) })));
+            if let Err(e) = removed {
+                debug!("cannot remove {}: {e}", dir.display());
+            }
+        }
+    }
+}
+
+/// Listen on the Unix socket `path`, on behalf of `user` when set.
+///
+/// An existing file at `path` is never replaced.
+///
+/// # Errors
+/// * `user` could not create a file at `path`.
+/// * The socket cannot be bound or restricted to 0600.
+pub(crate) fn bind(path: &str, user: Option<&ForwardUser>) -> Result<(UnixListener, SocketFile)> {
+    let path = PathBuf::from(path);
+    let listener = bind_as(&path, user)?;
+    let file = SocketFile {
+        path,
+        dir: None,
+        user: user.cloned(),
+    };
+    Ok((listener, file))
+}
+
+/// Listen on socket `name` inside `dir`, a directory created for it with mode
+/// 0700 on behalf of `user` when set, and removed along with the socket.
+///
+/// # Errors
+/// * `dir` exists already or cannot be created.
+/// * The socket cannot be bound.
+#[allow(unsafe_code)]
+pub(crate) fn bind_private(
+    dir: &Path,
+    name: &str,
+    user: Option<&ForwardUser>,
+) -> Result<(UnixListener, SocketFile)> {
+    let c_dir = c_path(dir)?;
+    // SAFETY: `mkdir` only reads the path, a valid C string.
+    as_user(user, || {
+        check(unsafe { libc::mkdir(c_dir.as_ptr(), 0o700) })
+    })?;
+    let path = dir.join(name);
+    match bind_as(&path, user) {
+        Ok(listener) => {
+            let file = SocketFile {
+                path,
+                dir: Some(dir.to_path_buf()),
+                user: user.cloned(),
+            };
+            Ok((listener, file))
+        }
+        Err(e) => {
+            // SAFETY: `rmdir` only reads the path, a valid C string.
+            drop(as_user(user, || {
+                check(unsafe { libc::rmdir(c_dir.as_ptr()) })
+            }));
+            Err(e.into())
+        }
+    }
+}
+
+/// Bind a listening socket at `path` as `user`, with mode 0600.
+#[allow(unsafe_code)]
+fn bind_as(path: &Path, user: Option<&ForwardUser>) -> IoResult<UnixListener> {
+    let address = SockAddr::unix(path)?;
+    let c_path = c_path(path)?;
+    let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+    as_user(user, || {
+        socket.bind(&address)?;
+        // SAFETY: `chmod` only reads the path, a valid C string.
+        check(unsafe { libc::chmod(c_path.as_ptr(), 0o600) })
+    })?;
+    socket.listen(BACKLOG)?;
+    socket.set_nonblocking(true)?;
+    UnixListener::from_std(StdUnixListener::from(OwnedFd::from(socket)))
+}
+
+/// Connect to the Unix socket `path`, as `user` when set.
+///
+/// # Errors
+/// * `user` may not connect to the socket.
+/// * The connection fails.
+pub(crate) async fn connect(path: &str, user: Option<&ForwardUser>) -> IoResult<UnixStream> {
+    if let Some(user) = user {
+        let (path, user) = (path.to_owned(), user.clone());
+        let stream = spawn_blocking(move || connect_as(&path, &user))
+            .await
+            .map_err(Error::other)??;
+        stream.set_nonblocking(true)?;
+        UnixStream::from_std(stream)
+    } else {
+        UnixStream::connect(path).await
+    }
+}
+
+/// Connect to `path` as `user`; the socket is created here and connected on
+/// the account's behalf.
+fn connect_as(path: &str, user: &ForwardUser) -> IoResult<StdUnixStream> {
+    let address = SockAddr::unix(path)?;
+    let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+    as_user(Some(user), || socket.connect(&address))?;
+    Ok(StdUnixStream::from(OwnedFd::from(socket)))
+}
+
+fn c_path(path: &Path) -> IoResult<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| Error::from(ErrorKind::InvalidInput))
+}
+
+/// The OS error behind a failed system call's `-1`.
+fn check(result: libc::c_int) -> IoResult<()> {
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::last_os_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{Permissions, create_dir, metadata, rename, set_permissions, symlink_metadata, write},
+        io::ErrorKind,
+        os::unix::fs::{FileTypeExt as _, MetadataExt as _, PermissionsExt as _, chown, symlink},
+    };
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+    use tokio::net::UnixListener;
+
+    use super::{bind, bind_private, connect};
+    use crate::forward::policy::ForwardUser;
+
+    #[tokio::test]
+    async fn bound_socket_is_private_and_removed_on_drop() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("fwd.sock");
+        let path = path.to_str().unwrap_or_default();
+        let meta = metadata(dir.path())?;
+        let user = ForwardUser::new(meta.uid(), meta.gid(), vec![]);
+
+        let (_listener, file) = bind(path, Some(&user))?;
+        let meta = symlink_metadata(path)?;
+        assert!(meta.file_type().is_socket());
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+        assert!(bind(path, Some(&user)).is_err());
+        let _stream = connect(path, Some(&user)).await?;
+
+        drop(file);
+        assert!(symlink_metadata(path).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connect_dials_as_the_session_user() -> Result<()> {
+        let dir = tempdir()?;
+        if metadata(dir.path())?.uid() != 0 {
+            // Only root can act for another account.
+            return Ok(());
+        }
+        let path = dir.path().join("peer.sock");
+        let path = path.to_str().unwrap_or_default();
+        let listener = UnixListener::bind(path)?;
+        let nobody = ForwardUser::new(65_534, 65_534, vec![]);
+
+        // The private temporary directory keeps the account out.
+        let denied = connect(path, Some(&nobody)).await.err();
+        assert_eq!(denied.map(|e| e.kind()), Some(ErrorKind::PermissionDenied));
+
+        set_permissions(dir.path(), Permissions::from_mode(0o755))?;
+        set_permissions(path, Permissions::from_mode(0o666))?;
+        let _stream = connect(path, Some(&nobody)).await?;
+        let (peer, _) = listener.accept().await?;
+        assert_eq!(peer.peer_cred()?.uid(), 65_534);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn private_directory_is_removed_with_its_socket() -> Result<()> {
+        let parent = tempdir()?;
+        let dir = parent.path().join("session");
+        let meta = metadata(parent.path())?;
+        let user = ForwardUser::new(meta.uid(), meta.gid(), vec![]);
+
+        let (_listener, file) = bind_private(&dir, "agent.sock", Some(&user))?;
+        assert_eq!(metadata(&dir)?.permissions().mode() & 0o777, 0o700);
+        assert!(bind_private(&dir, "agent.sock", Some(&user)).is_err());
+        let _stream = connect(file.path().to_str().unwrap_or_default(), Some(&user)).await?;
+
+        drop(file);
+        assert!(symlink_metadata(&dir).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn socket_files_are_handled_as_the_session_user() -> Result<()> {
+        let dir = tempdir()?;
+        if metadata(dir.path())?.uid() != 0 {
+            // Only root can act for another account.
+            return Ok(());
+        }
+        set_permissions(dir.path(), Permissions::from_mode(0o755))?;
+        let nobody = ForwardUser::new(65_534, 65_534, vec![]);
+        let home = dir.path().join("home");
+        create_dir(&home)?;
+        chown(&home, Some(65_534), Some(65_534))?;
+        let sockets = home.join("sockets");
+        create_dir(&sockets)?;
+        chown(&sockets, Some(65_534), Some(65_534))?;
+        let protected = dir.path().join("protected");
+        create_dir(&protected)?;
+        write(protected.join("fwd.sock"), "root's")?;
+
+        let path = sockets.join("fwd.sock");
+        let (_listener, file) = bind(path.to_str().unwrap_or_default(), Some(&nobody))?;
+        let meta = symlink_metadata(&path)?;
+        assert_eq!(meta.uid(), 65_534);
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+
+        // The account swaps the socket's directory for a link to root's.
+        rename(&sockets, home.join("moved"))?;
+        symlink(&protected, &sockets)?;
+        drop(file);
+        assert!(symlink_metadata(protected.join("fwd.sock")).is_ok());
+
+        // Nor can it get a socket bound where it could not create one.
+        let denied = bind(
+            protected.join("other.sock").to_str().unwrap_or_default(),
+            Some(&nobody),
+        )
+        .err();
+        assert!(denied.is_some());
+        assert!(symlink_metadata(protected.join("other.sock")).is_err());
+        Ok(())
+    }
+}
@@ -10,16 +10,17 @@ use std::io::Cursor;
 
 use anyhow::Result;
 use aws_lc_rs::{
-    aead::{Aad, LessSafeKey, Nonce},
+    aead::{Aad, LessSafeKey, NONCE_LEN, Nonce},
     error::Unspecified,
-    hmac::{Key, verify},
+    hmac::{Key, sign, verify},
+    rand,
 };
-use bincode_next::{Decode, Encode};
+use bincode_next::{Decode, Encode, config::standard, encode_to_vec};
 use tracing::error;
 use uuid::Uuid;
 
 use crate::{
-    MoshpitError, UuidWrapper,
+    DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION, ExitStatus, MoshpitError, UuidWrapper,
     error::Error,
     frames::{decode_frame, get_bytes, get_nonce, get_usize},
 };
@@ -37,6 +38,56 @@ const AEAD_TAG_LEN: usize = 16;
 /// still bounding the memory allocated per received UDP packet.
 pub(crate) const MAX_ENCFRAME_LENGTH: usize = 65536;
 
+/// How the AEAD nonce of a data-channel packet is chosen.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum NonceScheme {
+    /// A random 12-byte nonce is generated for every packet and sent in front of it.
+    /// Used with peers older than
+    /// [`DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION`](crate::DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION),
+    /// where both directions share one key.
+    #[default]
+    Random,
+    /// The nonce is the packet's authenticated 64-bit sequence number, left-padded
+    /// with zeros, and is not sent on the wire.  Only safe with per-direction keys:
+    /// each sender numbers its packets uniquely under its own key.
+    Sequence,
+}
+
+impl NonceScheme {
+    /// The scheme both peers use at the negotiated `protocol_version`.
+    #[must_use]
+    pub fn for_protocol_version(protocol_version: u16) -> Self {
+        if protocol_version >= DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION {
+            NonceScheme::Sequence
+        } else {
+            NonceScheme::Random
+        }
+    }
+}
+
+/// The implicit nonce for packet `seq` under [`NonceScheme::Sequence`].
+fn sequence_nonce(seq: u64) -> Nonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[NONCE_LEN - size_of::<u64>()..].copy_from_slice(&seq.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// The AEAD nonce of packet `seq`: the one read off the wire under
+/// [`NonceScheme::Random`], otherwise the sequence-derived one.
+fn packet_nonce(random_nonce: Option<&[u8]>, seq: u64) -> Result<Nonce> {
+    match random_nonce {
+        Some(nonce_bytes) => Ok(Nonce::try_assume_unique_for_key(nonce_bytes)?),
+        None => Ok(sequence_nonce(seq)),
+    }
+}
+
+/// Verify the HMAC `tag` over `seq_bytes || ciphertext`.
+fn hmac_verifies(hmac: &Key, seq_bytes: &[u8], ciphertext: &[u8], tag: &[u8]) -> bool {
+    let mut to_verify = seq_bytes.to_vec();
+    to_verify.extend_from_slice(ciphertext);
+    verify(hmac, &to_verify, tag).is_ok()
+}
+
 /// A moshpit frame — the bincode-serialized payload of an encrypted UDP datagram.
 ///
 /// # Wire compatibility
@@ -45,9 +96,10 @@ pub(crate) const MAX_ENCFRAME_LENGTH: usize = 65536;
 /// of its fields) is a **wire-format change**: bump
 /// [`PROTOCOL_VERSION`](crate::PROTOCOL_VERSION) and gate the new behaviour on the
 /// negotiated version (see that constant for the policy).  Keep
-/// [`EncryptedFrame::id`] in sync when adding a variant.  Note the negotiated
-/// version is not currently available in the UDP transport, so version-gated UDP
-/// frames also require threading it into `UdpSender` / `UdpReader`.
+/// [`EncryptedFrame::id`] in sync when adding a variant.  The data-channel
+/// transports do not see the negotiated version itself, only what session setup
+/// derives from it (such as the [`NonceScheme`]), so version-gated frames also
+/// require threading a setting into the senders and readers.
 #[derive(Clone, Debug, Decode, Encode, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum EncryptedFrame {
     /// An encrypted UDP packet.
@@ -102,6 +154,82 @@ pub enum EncryptedFrame {
     /// `seq == total - 1`, then concatenates in order and processes the assembled bytes
     /// identically to a [`EncryptedFrame::ScreenStateCompressed`] payload.
     StateChunk((u16, u16, Vec<u8>)),
+    /// Either direction: every frame after this one is sealed under key epoch `n`,
+    /// derived from the current keys via [`KeyRatchet::next`](crate::KeyRatchet::next).
+    /// Sent under the outgoing epoch's keys.  Only emitted when both peers negotiate
+    /// [`PROTOCOL_VERSION`](crate::PROTOCOL_VERSION) ≥ 4.
+    Rekey(u32),
+    /// Either direction: open forwarded channel `id` to `host:port` on the receiving side.
+    /// Retransmitted until answered; the receiver replies with
+    /// [`EncryptedFrame::ForwardAck`] once connected or [`EncryptedFrame::ForwardReset`]
+    /// when it refuses or cannot connect.  Client-opened ids are odd, server-opened even.
+    /// Only emitted when both peers negotiate
+    /// [`PORT_FORWARDING_MIN_PROTOCOL_VERSION`](crate::PORT_FORWARDING_MIN_PROTOCOL_VERSION).
+    ForwardOpen((u32, String, u16)),
+    /// Either direction: bytes of forwarded channel `id` starting at stream offset `offset`.
+    /// Retransmitted until a cumulative [`EncryptedFrame::ForwardAck`] covers them.
+    ForwardData((u32, u64, Vec<u8>)),
+    /// Either direction: every offset of forwarded channel `id` below `next` has been
+    /// written to the receiver's socket.  Frees the sender's flow-control window.
+    ForwardAck((u32, u64)),
+    /// Either direction: forwarded channel `id` ends at stream offset `offset`, which
+    /// the close itself occupies.  Half-closes the peer's socket once everything before
+    /// it is written.
+    ForwardClose((u32, u64)),
+    /// Either direction: forwarded channel `id` is gone; drop it without flushing.
+    ForwardReset(u32),
+    /// Client → server: listen on `bind_address:port` (empty for loopback) and open a
+    /// forwarded channel back to `host:hostport` on the client for every connection
+    /// accepted, as `(request, bind_address, port, host, hostport)`.  Retransmitted until
+    /// answered with [`EncryptedFrame::ForwardListening`] or
+    /// [`EncryptedFrame::ForwardListenRefused`].  Only emitted when both peers negotiate
+    /// [`REMOTE_FORWARDING_MIN_PROTOCOL_VERSION`](crate::REMOTE_FORWARDING_MIN_PROTOCOL_VERSION).
+    ForwardListen((u32, String, u16, String, u16)),
+    /// Server → client: listen `request` is bound to the given port.
+    ForwardListening((u32, u16)),
+    /// Server → client: listen `request` was refused by policy or could not be bound.
+    ForwardListenRefused(u32),
+    /// Client → server: open forwarded channel `id` to `host:port` as named by a SOCKS
+    /// client (`mp -D`), resolving `host` on the server.  Answered and carried exactly
+    /// like [`EncryptedFrame::ForwardOpen`], but governed by the server's dynamic
+    /// forwarding policy.  Only emitted when both peers negotiate
+    /// [`DYNAMIC_FORWARDING_MIN_PROTOCOL_VERSION`](crate::DYNAMIC_FORWARDING_MIN_PROTOCOL_VERSION).
+    ForwardConnect((u32, String, u16)),
+    /// Either direction: open forwarded channel `id` to the Unix-domain socket at `path` on
+    /// the receiving side.  Answered and carried exactly like [`EncryptedFrame::ForwardOpen`].
+    /// Only emitted when both peers negotiate
+    /// [`UNIX_FORWARDING_MIN_PROTOCOL_VERSION`](crate::UNIX_FORWARDING_MIN_PROTOCOL_VERSION).
+    ForwardOpenUnix((u32, String)),
+    /// Client → server: like [`EncryptedFrame::ForwardListen`], but listen on the Unix-domain
+    /// socket at `path`, as `(request, path, host, hostport)`.  The server creates the socket
+    /// with mode 0600 for the session user and removes it when the session closes.  Answered
+    /// with port 0 on success.  In both listen requests a `hostport` of 0 makes `host` the
+    /// path of a Unix-domain socket on the client.  Only emitted when both peers negotiate
+    /// [`UNIX_FORWARDING_MIN_PROTOCOL_VERSION`](crate::UNIX_FORWARDING_MIN_PROTOCOL_VERSION).
+    ForwardListenUnix((u32, String, String, u16)),
+    /// Server → client: open forwarded channel `id` to the client's `mpa`, for a connection
+    /// accepted on the session's agent socket (`mp --forward-agent`).  Answered and carried
+    /// exactly like [`EncryptedFrame::ForwardOpen`]; the client only relays the agent requests
+    /// that use a loaded key.  Only emitted when both peers negotiate
+    /// [`AGENT_FORWARDING_MIN_PROTOCOL_VERSION`](crate::AGENT_FORWARDING_MIN_PROTOCOL_VERSION).
+    ForwardOpenAgent(u32),
+    /// Server → client: open forwarded channel `id` to standard stream `fd` (0, 1 or 2) of
+    /// `mp`, for a remote command running without a PTY.  Standard input carries data from
+    /// the client, the output streams to it.  Answered and carried exactly like
+    /// [`EncryptedFrame::ForwardOpen`].  Only emitted when both peers negotiate
+    /// [`REMOTE_COMMAND_MIN_PROTOCOL_VERSION`](crate::REMOTE_COMMAND_MIN_PROTOCOL_VERSION).
+    ForwardOpenStdio((u32, u8)),
+    /// Either direction: the remote command has ended with this status.  The server
+    /// repeats it until the client echoes it back, and the client then exits with the
+    /// status instead of entering the reconnect loop.  Only emitted when both peers
+    /// negotiate
+    /// [`REMOTE_COMMAND_MIN_PROTOCOL_VERSION`](crate::REMOTE_COMMAND_MIN_PROTOCOL_VERSION).
+    CommandExit(ExitStatus),
+    /// Server → client: the remote PTY process has exited with this status.  Replaces
+    /// [`EncryptedFrame::PtyExit`] when both peers negotiate
+    /// [`PTY_EXIT_STATUS_MIN_PROTOCOL_VERSION`](crate::PTY_EXIT_STATUS_MIN_PROTOCOL_VERSION);
+    /// the client exits cleanly with the status instead of 0.
+    PtyExitStatus(ExitStatus),
 }
 
 impl EncryptedFrame {
@@ -124,12 +252,153 @@ impl EncryptedFrame {
             EncryptedFrame::ClientAck(_) => 12,
             EncryptedFrame::PtyExit => 13,
             EncryptedFrame::StateChunk(_) => 14,
+            EncryptedFrame::Rekey(_) => 15,
+            EncryptedFrame::ForwardOpen(_) => 16,
+            EncryptedFrame::ForwardData(_) => 17,
+            EncryptedFrame::ForwardAck(_) => 18,
+            EncryptedFrame::ForwardClose(_) => 19,
+            EncryptedFrame::ForwardReset(_) => 20,
+            EncryptedFrame::ForwardListen(_) => 21,
+            EncryptedFrame::ForwardListening(_) => 22,
+            EncryptedFrame::ForwardListenRefused(_) => 23,
+            EncryptedFrame::ForwardConnect(_) => 24,
+            EncryptedFrame::ForwardOpenUnix(_) => 25,
+            EncryptedFrame::ForwardListenUnix(_) => 26,
+            EncryptedFrame::ForwardOpenAgent(_) => 27,
+            EncryptedFrame::ForwardOpenStdio(_) => 28,
+            EncryptedFrame::CommandExit(_) => 29,
+            EncryptedFrame::PtyExitStatus(_) => 30,
         }
     }
 
+    /// Returns `true` for the frames of a forwarded channel, which the
+    /// data-channel readers hand to the session's [`ForwardMux`](crate::ForwardMux).
+    #[must_use]
+    pub(crate) fn is_forward(&self) -> bool {
+        matches!(
+            self,
+            EncryptedFrame::ForwardOpen(_)
+                | EncryptedFrame::ForwardData(_)
+                | EncryptedFrame::ForwardAck(_)
+                | EncryptedFrame::ForwardClose(_)
+                | EncryptedFrame::ForwardReset(_)
+                | EncryptedFrame::ForwardListen(_)
+                | EncryptedFrame::ForwardListening(_)
+                | EncryptedFrame::ForwardListenRefused(_)
+                | EncryptedFrame::ForwardConnect(_)
+                | EncryptedFrame::ForwardOpenUnix(_)
+                | EncryptedFrame::ForwardListenUnix(_)
+                | EncryptedFrame::ForwardOpenAgent(_)
+                | EncryptedFrame::ForwardOpenStdio(_)
+        )
+    }
+
+    /// Returns `true` when `src` holds a complete packet that authenticates under
+    /// `hmac`, or, when the session has no HMAC
+    /// ([`MAC_AEAD_IMPLICIT`](crate::MAC_AEAD_IMPLICIT)), whose AEAD tag verifies under `rnk`.
+    ///
+    /// Lets a receiver pick the key epoch a packet was sealed under before calling
+    /// [`EncryptedFrame::parse`], without logging a failure for every candidate key.
+    #[must_use]
+    pub fn authenticates(
+        src: &[u8],
+        hmac: Option<&Key>,
+        rnk: &LessSafeKey,
+        mac_tag_len: usize,
+        nonce_scheme: NonceScheme,
+    ) -> bool {
+        let mut src = Cursor::new(src);
+        let random_nonce = if nonce_scheme == NonceScheme::Random {
+            let Ok(Some(nonce_bytes)) = get_nonce(&mut src) else {
+                return false;
+            };
+            Some(nonce_bytes)
+        } else {
+            None
+        };
+        let Ok(Some(seq_bytes)) = get_usize(&mut src) else {
+            return false;
+        };
+        let Ok(Some(tag_bytes)) = get_bytes(&mut src, mac_tag_len) else {
+            return false;
+        };
+        let Ok(Some(length_slice)) = get_usize(&mut src) else {
+            return false;
+        };
+        let Ok(length_bytes) = length_slice.try_into() else {
+            return false;
+        };
+        let length = usize::from_be_bytes(length_bytes);
+        if length > MAX_ENCFRAME_LENGTH {
+            return false;
+        }
+        let Ok(Some(data)) = get_bytes(&mut src, length) else {
+            return false;
+        };
+        if let Some(hmac) = hmac {
+            return hmac_verifies(hmac, seq_bytes, data, tag_bytes);
+        }
+        let Ok(seq_bytes) = seq_bytes.try_into() else {
+            return false;
+        };
+        let seq = u64::from_be_bytes(seq_bytes);
+        let Ok(nonce) = packet_nonce(random_nonce, seq) else {
+            return false;
+        };
+        let mut data = data.to_vec();
+        rnk.open_in_place(nonce, Aad::from(seq.to_be_bytes()), &mut data)
+            .is_ok()
+    }
+
+    /// Seal this frame as packet `seq` — the inverse of [`EncryptedFrame::parse`].
+    ///
+    /// # Errors
+    /// * The frame cannot be encoded, or sealing fails.
+    ///
+    pub(crate) fn seal(
+        &self,
+        seq: u64,
+        id: Uuid,
+        rnk: &LessSafeKey,
+        hmac: Option<&Key>,
+        nonce_scheme: NonceScheme,
+    ) -> Result<Vec<u8>> {
+        // Encode the frame data
+        let data = encode_to_vec(self, standard())?;
+        let aad = Aad::from(seq.to_be_bytes());
+        // Encrypt the id, frame_id, and the data then MAC
+        let mut encrypted_part = id.as_bytes().to_vec();
+        encrypted_part.extend_from_slice(&data);
+        let mut packet = Vec::new();
+        let nonce = match nonce_scheme {
+            NonceScheme::Random => {
+                let mut nonce_bytes = [0u8; NONCE_LEN];
+                rand::fill(&mut nonce_bytes)?;
+                packet.extend_from_slice(&nonce_bytes);
+                Nonce::assume_unique_for_key(nonce_bytes)
+            }
+            NonceScheme::Sequence => sequence_nonce(seq),
+        };
+        rnk.seal_in_place_append_tag(nonce, aad, &mut encrypted_part)?;
+        let seq_bytes = seq.to_be_bytes();
+        packet.extend_from_slice(&seq_bytes);
+        // Sign seq_bytes || encrypted_part to authenticate the wire sequence number
+        if let Some(hmac) = hmac {
+            let mut to_sign = seq_bytes.to_vec();
+            to_sign.extend_from_slice(&encrypted_part);
+            packet.extend_from_slice(sign(hmac, &to_sign).as_ref());
+        }
+        packet.extend_from_slice(&encrypted_part.len().to_be_bytes());
+        packet.extend_from_slice(&encrypted_part);
+        Ok(packet)
+    }
+
     /// Parse a moshpit frame from the given byte source.
     ///
-    /// Wire format: `[nonce (12)] [seq (8)] [hmac_tag (64)] [length (8)] [ciphertext]`
+    /// Wire format: `[nonce (12)] [seq (8)] [hmac_tag (64)] [length (8)] [ciphertext]`.
+    /// Under [`NonceScheme::Sequence`] the nonce is omitted and derived from `seq`.
+    /// Without an `hmac` ([`MAC_AEAD_IMPLICIT`](crate::MAC_AEAD_IMPLICIT)) the HMAC tag
+    /// is omitted and `mac_tag_len` must be `0`; the AEAD tag alone authenticates the packet.
     ///
     /// The sequence number is authenticated (included in HMAC input) and used as AEAD AAD,
     /// which allows retransmitting the original wire bytes without re-encryption.
@@ -140,12 +409,18 @@ impl EncryptedFrame {
     pub fn parse(
         src: &mut Cursor<&[u8]>,
         id: Uuid,
-        hmac: &Key,
+        hmac: Option<&Key>,
         rnk: &LessSafeKey,
         mac_tag_len: usize,
+        nonce_scheme: NonceScheme,
     ) -> Result<Option<(Self, u64)>> {
-        let Some(nonce_bytes) = get_nonce(src)? else {
-            return Ok(None);
+        let random_nonce = if nonce_scheme == NonceScheme::Random {
+            let Some(nonce_bytes) = get_nonce(src)? else {
+                return Ok(None);
+            };
+            Some(nonce_bytes)
+        } else {
+            None
         };
         let Some(seq_bytes) = get_usize(src)? else {
             return Ok(None);
@@ -160,11 +435,9 @@ impl EncryptedFrame {
             }
             if let Some(data) = get_bytes(src, length)? {
                 // Verify HMAC over seq_bytes || ciphertext to authenticate the sequence number
-                let mut to_verify = seq_bytes.to_vec();
-                to_verify.extend_from_slice(data);
-                if let Ok(()) = verify(hmac, &to_verify, tag_bytes) {
+                if hmac.is_none_or(|hmac| hmac_verifies(hmac, seq_bytes, data, tag_bytes)) {
                     let mut data = data.to_vec();
-                    let nonce = Nonce::try_assume_unique_for_key(nonce_bytes)?;
+                    let nonce = packet_nonce(random_nonce, seq)?;
                     let aad = Aad::from(seq.to_be_bytes());
                     let _ = rnk.open_in_place(nonce, aad, &mut data)?;
                     let (uuid_bytes, rest) = data.split_at(UUID_LEN);
@@ -200,9 +473,9 @@ mod tests {
     use bincode_next::{config::standard, encode_to_vec};
     use uuid::Uuid;
 
-    use crate::UuidWrapper;
+    use crate::{ExitStatus, UuidWrapper};
 
-    use super::EncryptedFrame;
+    use super::{EncryptedFrame, NonceScheme};
 
     fn make_keys() -> anyhow::Result<(Uuid, LessSafeKey, Key)> {
         let id = Uuid::new_v4();
@@ -270,6 +543,42 @@ mod tests {
         assert_eq!(EncryptedFrame::ClientAck(0).id(), 12);
         assert_eq!(EncryptedFrame::PtyExit.id(), 13);
         assert_eq!(EncryptedFrame::StateChunk((0, 1, vec![])).id(), 14);
+        assert_eq!(EncryptedFrame::Rekey(1).id(), 15);
+        assert_eq!(
+            EncryptedFrame::ForwardOpen((1, "localhost".to_string(), 80)).id(),
+            16
+        );
+        assert_eq!(EncryptedFrame::ForwardData((1, 0, vec![])).id(), 17);
+        assert_eq!(EncryptedFrame::ForwardAck((1, 0)).id(), 18);
+        assert_eq!(EncryptedFrame::ForwardClose((1, 0)).id(), 19);
+        assert_eq!(EncryptedFrame::ForwardReset(1).id(), 20);
+        assert_eq!(
+            EncryptedFrame::ForwardListen((1, String::new(), 8080, "localhost".to_string(), 3000))
+                .id(),
+            21
+        );
+        assert_eq!(EncryptedFrame::ForwardListening((1, 8080)).id(), 22);
+        assert_eq!(EncryptedFrame::ForwardListenRefused(1).id(), 23);
+        assert_eq!(
+            EncryptedFrame::ForwardConnect((1, "example.com".to_string(), 443)).id(),
+            24
+        );
+        assert_eq!(
+            EncryptedFrame::ForwardOpenUnix((1, "/var/run/docker.sock".to_string())).id(),
+            25
+        );
+        assert_eq!(
+            EncryptedFrame::ForwardListenUnix((1, "/tmp/app.sock".to_string(), String::new(), 0))
+                .id(),
+            26
+        );
+        assert_eq!(EncryptedFrame::ForwardOpenAgent(2).id(), 27);
+        assert_eq!(EncryptedFrame::ForwardOpenStdio((2, 1)).id(), 28);
+        assert_eq!(EncryptedFrame::CommandExit(ExitStatus::Code(0)).id(), 29);
+        assert_eq!(
+            EncryptedFrame::PtyExitStatus(ExitStatus::Signal(9)).id(),
+            30
+        );
     }
 
     #[test]
@@ -278,13 +587,187 @@ mod tests {
         let ts = 1_234_567_890_u64;
         let packet = encrypt_frame(&EncryptedFrame::Keepalive(ts), 0, id, &rnk, &hmac)?;
         let mut cursor = Cursor::new(packet.as_slice());
-        let (parsed_frame, seq) = EncryptedFrame::parse(&mut cursor, id, &hmac, &rnk, 64)?
-            .ok_or_else(|| anyhow::anyhow!("expected parsed frame"))?;
+        let (parsed_frame, seq) =
+            EncryptedFrame::parse(&mut cursor, id, Some(&hmac), &rnk, 64, NonceScheme::Random)?
+                .ok_or_else(|| anyhow::anyhow!("expected parsed frame"))?;
         assert_eq!(parsed_frame, EncryptedFrame::Keepalive(ts));
         assert_eq!(seq, 0);
         Ok(())
     }
 
+    #[test]
+    fn parse_round_trip_rekey() -> anyhow::Result<()> {
+        let (id, rnk, hmac) = make_keys()?;
+        let packet = encrypt_frame(&EncryptedFrame::Rekey(3), 9, id, &rnk, &hmac)?;
+        let mut cursor = Cursor::new(packet.as_slice());
+        let (parsed_frame, seq) =
+            EncryptedFrame::parse(&mut cursor, id, Some(&hmac), &rnk, 64, NonceScheme::Random)?
+                .ok_or_else(|| anyhow::anyhow!("expected parsed frame"))?;
+        assert_eq!(parsed_frame, EncryptedFrame::Rekey(3));
+        assert_eq!(seq, 9);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_round_trip_forward_data() -> anyhow::Result<()> {
+        let (id, rnk, hmac) = make_keys()?;
+        let frame = EncryptedFrame::ForwardData((3, 4096, vec![7u8; 800]));
+        assert!(frame.is_forward());
+        let packet = encrypt_frame(&frame, 11, id, &rnk, &hmac)?;
+        let mut cursor = Cursor::new(packet.as_slice());
+        let (parsed_frame, seq) =
+            EncryptedFrame::parse(&mut cursor, id, Some(&hmac), &rnk, 64, NonceScheme::Random)?
+                .ok_or_else(|| anyhow::anyhow!("expected parsed frame"))?;
+        assert_eq!(parsed_frame, frame);
+        assert_eq!(seq, 11);
+        Ok(())
+    }
+
+    #[test]
+    fn authenticates_only_under_the_sealing_hmac_key() -> anyhow::Result<()> {
+        let (id, rnk, hmac) = make_keys()?;
+        let other = Key::new(HMAC_SHA512, &[3u8; 64]);
+        let packet = encrypt_frame(&EncryptedFrame::Keepalive(1), 0, id, &rnk, &hmac)?;
+        assert!(EncryptedFrame::authenticates(
+            &packet,
+            Some(&hmac),
+            &rnk,
+            64,
+            NonceScheme::Random
+        ));
+        assert!(!EncryptedFrame::authenticates(
+            &packet,
+            Some(&other),
+            &rnk,
+            64,
+            NonceScheme::Random
+        ));
+        assert!(!EncryptedFrame::authenticates(
+            &packet[..packet.len() - 1],
+            Some(&hmac),
+            &rnk,
+            64,
+            NonceScheme::Random
+        ));
+        assert!(!EncryptedFrame::authenticates(
+            &[],
+            Some(&hmac),
+            &rnk,
+            64,
+            NonceScheme::Random
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn seal_round_trips_under_each_nonce_scheme() -> anyhow::Result<()> {
+        let (id, rnk, hmac) = make_keys()?;
+        let frame = EncryptedFrame::Keepalive(7);
+        let random = frame.seal(3, id, &rnk, Some(&hmac), NonceScheme::Random)?;
+        let sequence = frame.seal(3, id, &rnk, Some(&hmac), NonceScheme::Sequence)?;
+        assert_eq!(random.len(), sequence.len() + NONCE_LEN);
+        for (packet, scheme) in [
+            (&random, NonceScheme::Random),
+            (&sequence, NonceScheme::Sequence),
+        ] {
+            let mut cursor = Cursor::new(packet.as_slice());
+            let (parsed_frame, seq) =
+                EncryptedFrame::parse(&mut cursor, id, Some(&hmac), &rnk, 64, scheme)?
+                    .ok_or_else(|| anyhow::anyhow!("expected parsed frame"))?;
+            assert_eq!(parsed_frame, frame);
+            assert_eq!(seq, 3);
+            assert!(EncryptedFrame::authenticates(
+                packet,
+                Some(&hmac),
+                &rnk,
+                64,
+                scheme
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sequence_nonce_binds_ciphertext_to_seq() -> anyhow::Result<()> {
+        let (id, rnk, hmac) = make_keys()?;
+        let frame = EncryptedFrame::Keepalive(7);
+        let first = frame.seal(1, id, &rnk, Some(&hmac), NonceScheme::Sequence)?;
+        let second = frame.seal(2, id, &rnk, Some(&hmac), NonceScheme::Sequence)?;
+        // Same plaintext under a different sequence number never repeats the ciphertext.
+        assert_ne!(first[8 + 64 + 8..], second[8 + 64 + 8..]);
+        // Deterministic: retransmitting seq 1 reproduces the original packet.
+        assert_eq!(
+            first,
+            frame.seal(1, id, &rnk, Some(&hmac), NonceScheme::Sequence)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn aead_implicit_round_trips_without_hmac_tag() -> anyhow::Result<()> {
+        let (id, rnk, hmac) = make_keys()?;
+        let frame = EncryptedFrame::Keepalive(7);
+        let tagged = frame.seal(3, id, &rnk, Some(&hmac), NonceScheme::Sequence)?;
+        let implicit = frame.seal(3, id, &rnk, None, NonceScheme::Sequence)?;
+        assert_eq!(implicit.len() + 64, tagged.len());
+        let mut cursor = Cursor::new(implicit.as_slice());
+        let (parsed_frame, seq) =
+            EncryptedFrame::parse(&mut cursor, id, None, &rnk, 0, NonceScheme::Sequence)?
+                .ok_or_else(|| anyhow::anyhow!("expected parsed frame"))?;
+        assert_eq!(parsed_frame, frame);
+        assert_eq!(seq, 3);
+        assert!(EncryptedFrame::authenticates(
+            &implicit,
+            None,
+            &rnk,
+            0,
+            NonceScheme::Sequence
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn aead_implicit_rejects_tampered_and_foreign_packets() -> anyhow::Result<()> {
+        let (id, rnk, _hmac) = make_keys()?;
+        let other = LessSafeKey::new(UnboundKey::new(&AES_256_GCM_SIV, &[3u8; 32])?);
+        let packet = EncryptedFrame::Keepalive(7).seal(3, id, &rnk, None, NonceScheme::Sequence)?;
+        assert!(!EncryptedFrame::authenticates(
+            &packet,
+            None,
+            &other,
+            0,
+            NonceScheme::Sequence
+        ));
+        // Rewriting the sequence number breaks the AAD and the implicit nonce.
+        let mut replayed = packet.clone();
+        replayed[7] ^= 1;
+        assert!(!EncryptedFrame::authenticates(
+            &replayed,
+            None,
+            &rnk,
+            0,
+            NonceScheme::Sequence
+        ));
+        let mut cursor = Cursor::new(replayed.as_slice());
+        assert!(
+            EncryptedFrame::parse(&mut cursor, id, None, &rnk, 0, NonceScheme::Sequence).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nonce_scheme_follows_protocol_version() {
+        use crate::DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION;
+        assert_eq!(
+            NonceScheme::for_protocol_version(DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION - 1),
+            NonceScheme::Random
+        );
+        assert_eq!(
+            NonceScheme::for_protocol_version(DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION),
+            NonceScheme::Sequence
+        );
+    }
+
     /// Verify that two independent `RandomizedNonceKey` instances constructed from the
     /// same key bytes can cross-encrypt/decrypt — this mirrors the real system where the
     /// UDP sender and UDP reader each hold separate instances.  Tested for each
@@ -322,7 +805,14 @@ mod tests {
             let packet = encrypt_frame(&EncryptedFrame::Keepalive(ts), 7, id, &enc_key, &hmac)
                 .unwrap_or_else(|e| panic!("encrypt_frame failed for {alg:?}: {e}"));
             let mut cursor = Cursor::new(packet.as_slice());
-            let result = EncryptedFrame::parse(&mut cursor, id, &hmac, &dec_key, 64);
+            let result = EncryptedFrame::parse(
+                &mut cursor,
+                id,
+                Some(&hmac),
+                &dec_key,
+                64,
+                NonceScheme::Random,
+            );
             let (parsed_frame, seq) = match result {
                 Ok(Some(inner)) => inner,
                 Ok(None) => panic!("parse returned None for algorithm {alg:?}"),
@@ -342,8 +832,9 @@ mod tests {
         let (id, rnk, hmac) = make_keys()?;
         let packet = encrypt_frame(&EncryptedFrame::Shutdown, 42, id, &rnk, &hmac)?;
         let mut cursor = Cursor::new(packet.as_slice());
-        let (parsed_frame, seq) = EncryptedFrame::parse(&mut cursor, id, &hmac, &rnk, 64)?
-            .ok_or_else(|| anyhow::anyhow!("expected parsed frame"))?;
+        let (parsed_frame, seq) =
+            EncryptedFrame::parse(&mut cursor, id, Some(&hmac), &rnk, 64, NonceScheme::Random)?
+                .ok_or_else(|| anyhow::anyhow!("expected parsed frame"))?;
         assert_eq!(parsed_frame, EncryptedFrame::Shutdown);
         assert_eq!(seq, 42);
         Ok(())
@@ -354,7 +845,8 @@ mod tests {
         let (id, rnk, hmac) = make_keys()?;
         let packet = [0u8; 4];
         let mut cursor = Cursor::new(packet.as_slice());
-        let result = EncryptedFrame::parse(&mut cursor, id, &hmac, &rnk, 64)?;
+        let result =
+            EncryptedFrame::parse(&mut cursor, id, Some(&hmac), &rnk, 64, NonceScheme::Random)?;
         assert!(result.is_none());
         Ok(())
     }
@@ -365,7 +857,17 @@ mod tests {
         let packet = encrypt_frame(&EncryptedFrame::Keepalive(0), 0, id, &rnk, &hmac)?;
         let wrong_id = Uuid::new_v4();
         let mut cursor = Cursor::new(packet.as_slice());
-        assert!(EncryptedFrame::parse(&mut cursor, wrong_id, &hmac, &rnk, 64).is_err());
+        assert!(
+            EncryptedFrame::parse(
+                &mut cursor,
+                wrong_id,
+                Some(&hmac),
+                &rnk,
+                64,
+                NonceScheme::Random
+            )
+            .is_err()
+        );
         Ok(())
     }
 
@@ -401,7 +903,8 @@ mod tests {
         packet.extend_from_slice(&encrypted_part);
 
         let mut cursor = Cursor::new(packet.as_slice());
-        let result = EncryptedFrame::parse(&mut cursor, id, &hmac, &rnk, 64);
+        let result =
+            EncryptedFrame::parse(&mut cursor, id, Some(&hmac), &rnk, 64, NonceScheme::Random);
         assert_eq!(
             result
                 .expect_err("expected FrameTooLarge error")
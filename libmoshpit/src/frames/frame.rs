@@ -19,7 +19,10 @@ use bytes::Buf as _;
 use crate::{
     error::Error,
     frames::{decode_frame, get_bytes, get_usize},
-    kex::negotiate::{AlgorithmList, ProtocolSupport},
+    kex::{
+        failure::KexFailureReason,
+        negotiate::{AlgorithmList, ProtocolSupport},
+    },
     uuid::UuidWrapper,
 };
 
@@ -44,6 +47,11 @@ pub enum Frame {
     /// Fields: (`identity_pk`, `ephemeral_pk`, `salt`)
     PeerInitialize(Vec<u8>, Vec<u8>, Vec<u8>),
     /// A check message from moshpit.
+    ///
+    /// From [`IDENTITY_HIDING_MIN_PROTOCOL_VERSION`](crate::IDENTITY_HIDING_MIN_PROTOCOL_VERSION)
+    /// the session options ([`ClientOptions`](Frame::ClientOptions),
+    /// [`ClientEnv`](Frame::ClientEnv) and the frames that follow them) are
+    /// sealed inside it instead of being sent in clear before it.
     Check([u8; 12], Vec<u8>),
     /// A key agreement message from moshpits.
     KeyAgreement(UuidWrapper),
@@ -56,6 +64,8 @@ pub enum Frame {
     SessionToken(UuidWrapper),
     /// A request from moshpit to resume a previous session.
     /// Contains (`session_uuid`, `user_bytes`, `ephemeral_public_key`, `full_public_key`).
+    /// Handled exactly like `Initialize` for key agreement (fresh ephemeral keys and a
+    /// fresh server salt), so a resumed session never reuses earlier session keys.
     ResumeRequest(UuidWrapper, Vec<u8>, Vec<u8>, Vec<u8>),
     /// Transport options sent by the client immediately after `Initialize` or
     /// `ResumeRequest` and before `Check`.  Allows the client to request a
@@ -75,10 +85,15 @@ pub enum Frame {
     /// so the peer can [`negotiate_protocol_version`](crate::kex::negotiate::negotiate_protocol_version).
     KexInit(AlgorithmList, ProtocolSupport),
     /// Experimental identity-key proof over the key-exchange transcript.
+    ///
+    /// From [`IDENTITY_HIDING_MIN_PROTOCOL_VERSION`](crate::IDENTITY_HIDING_MIN_PROTOCOL_VERSION)
+    /// moshpits also sends one to answer the client's host-key
+    /// [`IdentityChallenge`](Frame::IdentityChallenge), before the client reveals
+    /// its identity.
     IdentityProof(Vec<u8>),
     /// Environment variable passthrough and PATH additions from the client.
     /// Sent after [`ClientOptions`](Frame::ClientOptions) (if any) and before
-    /// [`Check`](Frame::Check).
+    /// [`Check`](Frame::Check), or from protocol v8 inside it.
     /// Fields: (`env_vars`, `extra_path`)
     /// - `env_vars`: `(name, value)` pairs filtered by the client's `send_env` config;
     ///   the server applies only those matching its own `accept_env` list.
@@ -97,6 +112,86 @@ pub enum Frame {
     /// falls back to UDP.  Only sent when both peers negotiate
     /// [`PROTOCOL_VERSION`](crate::PROTOCOL_VERSION) ≥ 2.
     TransportPreference(u8),
+    /// Identity proof-of-possession challenge sent by moshpits immediately after
+    /// [`PeerInitialize`](Frame::PeerInitialize) when the client's identity key is
+    /// an ECDH key (X25519, P-256, or P-384).
+    ///
+    /// The payload is a fresh server ephemeral public key on the client identity's
+    /// curve.  Both sides compute the static-ephemeral DH between it and the client
+    /// identity key and mix the result into the session KDF input, so a client that
+    /// only holds the `.pub` file cannot derive the session key.  Only sent when both
+    /// peers negotiate [`PROTOCOL_VERSION`](crate::PROTOCOL_VERSION) ≥ 3.
+    ///
+    /// From [`IDENTITY_HIDING_MIN_PROTOCOL_VERSION`](crate::IDENTITY_HIDING_MIN_PROTOCOL_VERSION)
+    /// the client first sends one the other way, challenging the host key named in
+    /// `PeerInitialize` (a random nonce for an ML-DSA host key).
+    IdentityChallenge(Vec<u8>),
+    /// Key exchange failure notification carrying the reason moshpits rejected
+    /// the client.  Replaces the bare [`KexFailure`](Frame::KexFailure) for clients
+    /// whose advertised protocol range reaches
+    /// [`KEX_FAILURE_REASON_MIN_PROTOCOL_VERSION`](crate::KEX_FAILURE_REASON_MIN_PROTOCOL_VERSION);
+    /// the server may have redacted the reason (see [`KexFailureReason::redacted`]).
+    KexFailureReason(KexFailureReason),
+    /// Identity-hiding replacement for [`Initialize`](Frame::Initialize): carries
+    /// only the client's ephemeral public key (or KEM encapsulation key), so
+    /// nothing in clear text names the user.  The username and identity key
+    /// follow in [`SealedIdentity`](Frame::SealedIdentity).  Only sent when both
+    /// peers negotiate
+    /// [`IDENTITY_HIDING_MIN_PROTOCOL_VERSION`](crate::IDENTITY_HIDING_MIN_PROTOCOL_VERSION).
+    HiddenInitialize(Vec<u8>),
+    /// The client's `Initialize` or `ResumeRequest` (with an empty ephemeral key
+    /// field) and, for ML-DSA identities, its `IdentityProof`, encrypted under the
+    /// handshake key derived once the server has proven it holds its host key.
+    /// Fields: (`nonce`, `ciphertext`)
+    SealedIdentity([u8; 12], Vec<u8>),
+    /// A session resumption ticket, sent by the server after `SessionToken`
+    /// from [`RESUMPTION_TICKET_MIN_PROTOCOL_VERSION`](crate::RESUMPTION_TICKET_MIN_PROTOCOL_VERSION).
+    /// Fields: (`ticket`, `lifetime_secs`)
+    ResumptionTicket(Vec<u8>, u64),
+    /// PSK-based replacement for [`Initialize`](Frame::Initialize) that redeems a
+    /// [`ResumptionTicket`](Frame::ResumptionTicket); the client's `Check`
+    /// follows without waiting for a reply.
+    /// Fields: (`ticket`, `client_nonce`)
+    TicketResume(Vec<u8>, [u8; 32]),
+    /// Speculative key share sent by the client right behind its
+    /// [`KexInit`](Frame::KexInit), before it has seen the server's, from
+    /// [`EARLY_KEY_SHARE_MIN_PROTOCOL_VERSION`](crate::EARLY_KEY_SHARE_MIN_PROTOCOL_VERSION).
+    /// Stands in for [`TransportPreference`](Frame::TransportPreference) and,
+    /// when the server negotiates the named key exchange, for
+    /// [`HiddenInitialize`](Frame::HiddenInitialize) too.
+    /// Fields: (`kex`, `exchange`, `transport_preference`)
+    EarlyKeyShare(String, Vec<u8>, u8),
+    /// Sealed by the client inside [`Check`](Frame::Check), after
+    /// [`ClientEnv`](Frame::ClientEnv) (if any), to ask for its agent to be forwarded
+    /// (`mp --forward-agent`): the server exports a per-session agent socket
+    /// to the shell it spawns.  Only sent when both peers negotiate
+    /// [`AGENT_FORWARDING_MIN_PROTOCOL_VERSION`](crate::AGENT_FORWARDING_MIN_PROTOCOL_VERSION).
+    AgentForward,
+    /// Sealed by the client inside [`Check`](Frame::Check), after
+    /// [`AgentForward`](Frame::AgentForward) and
+    /// [`SessionRequest`](Frame::SessionRequest) (if any), to run a command instead of the login
+    /// shell (`mp host -- command`), on a PTY or with its standard streams on
+    /// channels of their own.  Only sent when both peers negotiate
+    /// [`REMOTE_COMMAND_MIN_PROTOCOL_VERSION`](crate::REMOTE_COMMAND_MIN_PROTOCOL_VERSION).
+    /// Fields: (`command`, `pty`)
+    RemoteCommand(String, bool),
+    /// Sealed by the client inside [`Check`](Frame::Check) in place of
+    /// [`RemoteCommand`](Frame::RemoteCommand) to copy files over the session
+    /// (`mp cp`): the server runs its copy
+    /// helper instead of the login shell.  Only sent when both peers negotiate
+    /// [`FILE_COPY_MIN_PROTOCOL_VERSION`](crate::FILE_COPY_MIN_PROTOCOL_VERSION).
+    /// Fields: (`upload`, `paths`, `recursive`) — the destination when
+    /// uploading, otherwise the sources to send.
+    FileCopy(bool, Vec<String>, bool),
+    /// Sealed by the client inside [`Check`](Frame::Check), after
+    /// [`AgentForward`](Frame::AgentForward) (if any) and before
+    /// [`RemoteCommand`](Frame::RemoteCommand) or
+    /// [`FileCopy`](Frame::FileCopy), to name the session to start or resume
+    /// (`mp --session`, `mp attach`) or to list the user's sessions (`mp ls`).
+    /// Only sent when both peers negotiate
+    /// [`NAMED_SESSIONS_MIN_PROTOCOL_VERSION`](crate::NAMED_SESSIONS_MIN_PROTOCOL_VERSION).
+    /// Fields: (`kind`, `name`) — see [`SessionRequest::to_wire`](crate::SessionRequest::to_wire).
+    SessionRequest(u8, String),
 }
 
 impl Frame {
@@ -117,6 +212,17 @@ impl Frame {
             Frame::IdentityProof(_) => 10,
             Frame::ClientEnv(_, _) => 11,
             Frame::TransportPreference(_) => 12,
+            Frame::IdentityChallenge(_) => 13,
+            Frame::KexFailureReason(_) => 14,
+            Frame::HiddenInitialize(_) => 15,
+            Frame::SealedIdentity(_, _) => 16,
+            Frame::ResumptionTicket(_, _) => 17,
+            Frame::TicketResume(_, _) => 18,
+            Frame::EarlyKeyShare(_, _, _) => 19,
+            Frame::AgentForward => 20,
+            Frame::RemoteCommand(_, _) => 21,
+            Frame::FileCopy(_, _, _) => 22,
+            Frame::SessionRequest(_, _) => 23,
         }
     }
 
@@ -127,7 +233,7 @@ impl Frame {
     ///
     pub fn parse(src: &mut Cursor<&[u8]>) -> Result<Option<Self>> {
         match get_u8(src) {
-            Some(0..=12) => {
+            Some(0..=23) => {
                 if let Some(length_slice) = get_usize(src)? {
                     let length = usize::from_be_bytes(length_slice.try_into()?);
                     if length > MAX_FRAME_LENGTH {
@@ -202,6 +308,36 @@ impl Display for Frame {
                 extra_path.len()
             ),
             Frame::TransportPreference(pref) => write!(f, "TransportPreference({pref})"),
+            Frame::IdentityChallenge(epk) => write!(f, "IdentityChallenge({} bytes)", epk.len()),
+            Frame::KexFailureReason(reason) => write!(f, "KexFailureReason({reason})"),
+            Frame::HiddenInitialize(pk) => write!(f, "HiddenInitialize({} bytes)", pk.len()),
+            Frame::SealedIdentity(nonce, data) => write!(
+                f,
+                "SealedIdentity({} bytes, {} bytes)",
+                nonce.len(),
+                data.len()
+            ),
+            Frame::ResumptionTicket(ticket, lifetime_secs) => write!(
+                f,
+                "ResumptionTicket({} bytes, {lifetime_secs}s)",
+                ticket.len()
+            ),
+            Frame::TicketResume(ticket, _) => write!(f, "TicketResume({} bytes)", ticket.len()),
+            Frame::EarlyKeyShare(kex, exchange, pref) => {
+                write!(f, "EarlyKeyShare({kex}, {} bytes, {pref})", exchange.len())
+            }
+            Frame::AgentForward => write!(f, "AgentForward"),
+            Frame::RemoteCommand(command, pty) => {
+                write!(f, "RemoteCommand({} bytes, pty={pty})", command.len())
+            }
+            Frame::FileCopy(upload, paths, recursive) => write!(
+                f,
+                "FileCopy(upload={upload}, {} paths, recursive={recursive})",
+                paths.len()
+            ),
+            Frame::SessionRequest(kind, name) => {
+                write!(f, "SessionRequest({kind}, {} bytes)", name.len())
+            }
         }
     }
 }
@@ -213,7 +349,7 @@ mod tests {
     use anyhow::Result;
     use bincode_next::{config::standard, encode_to_vec};
 
-    use crate::frames::USIZE_LENGTH;
+    use crate::{KexFailureReason, frames::USIZE_LENGTH};
 
     use super::{Frame, get_bytes, get_u8, get_usize};
 
@@ -410,8 +546,8 @@ mod tests {
 
     #[test]
     fn test_parse_unknown_frame_id_returns_none() -> Result<()> {
-        // Frame IDs 0-12 are known; anything above 12 must be silently ignored (Ok(None)).
-        let all_data = [13u8, 0, 0, 0, 0, 0, 0, 0, 0]; // id=13, length=0, no payload
+        // Frame IDs 0-23 are known; anything above 23 must be silently ignored (Ok(None)).
+        let all_data = [24u8, 0, 0, 0, 0, 0, 0, 0, 0]; // id=24, length=0, no payload
         let mut cursor = Cursor::new(&all_data[..]);
         let result = Frame::parse(&mut cursor)?;
         assert!(result.is_none(), "unknown frame id must return Ok(None)");
@@ -522,4 +658,196 @@ mod tests {
         let frame = Frame::IdentityProof(vec![0u8; 42]);
         assert_eq!(format!("{frame}"), "IdentityProof(42 bytes)");
     }
+
+    #[test]
+    fn test_identity_challenge_round_trips() -> Result<()> {
+        let epk = vec![9u8; 32];
+        let frame = Frame::IdentityChallenge(epk.clone());
+        assert_eq!(frame.id(), 13);
+        let encoded_frame = encode_to_vec(&frame, standard())?;
+        let length = encoded_frame.len();
+        let length_bytes = length.to_be_bytes();
+
+        let mut all_data = vec![13u8]; // IdentityChallenge id=13
+        all_data.extend_from_slice(&length_bytes);
+        all_data.extend_from_slice(&encoded_frame);
+
+        let mut cursor = Cursor::new(&all_data[..]);
+        let Frame::IdentityChallenge(parsed_epk) = Frame::parse(&mut cursor)?
+            .ok_or_else(|| anyhow::anyhow!("expected IdentityChallenge frame"))?
+        else {
+            panic!("expected IdentityChallenge");
+        };
+        assert_eq!(parsed_epk, epk);
+        Ok(())
+    }
+
+    #[test]
+    fn test_identity_challenge_display() {
+        let frame = Frame::IdentityChallenge(vec![0u8; 65]);
+        assert_eq!(format!("{frame}"), "IdentityChallenge(65 bytes)");
+    }
+
+    #[test]
+    fn test_kex_failure_reason_round_trips() -> Result<()> {
+        let frame = Frame::KexFailureReason(KexFailureReason::UnauthorizedKey);
+        assert_eq!(frame.id(), 14);
+        let encoded_frame = encode_to_vec(&frame, standard())?;
+        let length = encoded_frame.len();
+        let length_bytes = length.to_be_bytes();
+
+        let mut all_data = vec![14u8]; // KexFailureReason id=14
+        all_data.extend_from_slice(&length_bytes);
+        all_data.extend_from_slice(&encoded_frame);
+
+        let mut cursor = Cursor::new(&all_data[..]);
+        let parsed = Frame::parse(&mut cursor)?
+            .ok_or_else(|| anyhow::anyhow!("expected KexFailureReason frame"))?;
+        assert_eq!(parsed, frame);
+        assert_eq!(
+            format!("{parsed}"),
+            "KexFailureReason(public key not authorized)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_identity_hiding_frames_round_trip() -> Result<()> {
+        for frame in [
+            Frame::HiddenInitialize(vec![3u8; 32]),
+            Frame::SealedIdentity([4u8; 12], vec![5u8; 272]),
+        ] {
+            let encoded_frame = encode_to_vec(&frame, standard())?;
+            let mut all_data = vec![frame.id()];
+            all_data.extend_from_slice(&encoded_frame.len().to_be_bytes());
+            all_data.extend_from_slice(&encoded_frame);
+
+            let mut cursor = Cursor::new(&all_data[..]);
+            let parsed = Frame::parse(&mut cursor)?
+                .ok_or_else(|| anyhow::anyhow!("expected identity-hiding frame"))?;
+            assert_eq!(parsed, frame);
+        }
+        assert_eq!(
+            format!("{}", Frame::SealedIdentity([0u8; 12], vec![0u8; 272])),
+            "SealedIdentity(12 bytes, 272 bytes)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resumption_ticket_frames_round_trip() -> Result<()> {
+        for frame in [
+            Frame::ResumptionTicket(vec![6u8; 120], 86_400),
+            Frame::TicketResume(vec![6u8; 120], [7u8; 32]),
+        ] {
+            let encoded_frame = encode_to_vec(&frame, standard())?;
+            let mut all_data = vec![frame.id()];
+            all_data.extend_from_slice(&encoded_frame.len().to_be_bytes());
+            all_data.extend_from_slice(&encoded_frame);
+
+            let mut cursor = Cursor::new(&all_data[..]);
+            let parsed = Frame::parse(&mut cursor)?
+                .ok_or_else(|| anyhow::anyhow!("expected resumption frame"))?;
+            assert_eq!(parsed, frame);
+        }
+        assert_eq!(
+            format!("{}", Frame::ResumptionTicket(vec![0u8; 120], 60)),
+            "ResumptionTicket(120 bytes, 60s)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_early_key_share_round_trips() -> Result<()> {
+        let frame = Frame::EarlyKeyShare("x25519-sha256".to_string(), vec![8u8; 32], 1);
+        let encoded_frame = encode_to_vec(&frame, standard())?;
+        let mut all_data = vec![frame.id()];
+        all_data.extend_from_slice(&encoded_frame.len().to_be_bytes());
+        all_data.extend_from_slice(&encoded_frame);
+
+        let mut cursor = Cursor::new(&all_data[..]);
+        let parsed =
+            Frame::parse(&mut cursor)?.ok_or_else(|| anyhow::anyhow!("expected EarlyKeyShare"))?;
+        assert_eq!(parsed, frame);
+        assert_eq!(
+            format!("{frame}"),
+            "EarlyKeyShare(x25519-sha256, 32 bytes, 1)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_agent_forward_round_trips() -> Result<()> {
+        let frame = Frame::AgentForward;
+        let encoded_frame = encode_to_vec(&frame, standard())?;
+        let mut all_data = vec![frame.id()];
+        all_data.extend_from_slice(&encoded_frame.len().to_be_bytes());
+        all_data.extend_from_slice(&encoded_frame);
+
+        let mut cursor = Cursor::new(&all_data[..]);
+        let parsed =
+            Frame::parse(&mut cursor)?.ok_or_else(|| anyhow::anyhow!("expected AgentForward"))?;
+        assert_eq!(parsed, frame);
+        assert_eq!(frame.id(), 20);
+        assert_eq!(format!("{frame}"), "AgentForward");
+        Ok(())
+    }
+
+    #[test]
+    fn test_remote_command_round_trips() -> Result<()> {
+        let frame = Frame::RemoteCommand("rsync --server .".to_string(), false);
+        let encoded_frame = encode_to_vec(&frame, standard())?;
+        let mut all_data = vec![frame.id()];
+        all_data.extend_from_slice(&encoded_frame.len().to_be_bytes());
+        all_data.extend_from_slice(&encoded_frame);
+
+        let mut cursor = Cursor::new(&all_data[..]);
+        let parsed =
+            Frame::parse(&mut cursor)?.ok_or_else(|| anyhow::anyhow!("expected RemoteCommand"))?;
+        assert_eq!(parsed, frame);
+        assert_eq!(frame.id(), 21);
+        assert_eq!(format!("{frame}"), "RemoteCommand(16 bytes, pty=false)");
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_copy_round_trips() -> Result<()> {
+        let frame = Frame::FileCopy(
+            false,
+            vec!["logs".to_string(), "notes.txt".to_string()],
+            true,
+        );
+        let encoded_frame = encode_to_vec(&frame, standard())?;
+        let mut all_data = vec![frame.id()];
+        all_data.extend_from_slice(&encoded_frame.len().to_be_bytes());
+        all_data.extend_from_slice(&encoded_frame);
+
+        let mut cursor = Cursor::new(&all_data[..]);
+        let parsed =
+            Frame::parse(&mut cursor)?.ok_or_else(|| anyhow::anyhow!("expected FileCopy"))?;
+        assert_eq!(parsed, frame);
+        assert_eq!(frame.id(), 22);
+        assert_eq!(
+            format!("{frame}"),
+            "FileCopy(upload=false, 2 paths, recursive=true)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_request_round_trips() -> Result<()> {
+        let frame = Frame::SessionRequest(1, "work".to_string());
+        let encoded_frame = encode_to_vec(&frame, standard())?;
+        let mut all_data = vec![frame.id()];
+        all_data.extend_from_slice(&encoded_frame.len().to_be_bytes());
+        all_data.extend_from_slice(&encoded_frame);
+
+        let mut cursor = Cursor::new(&all_data[..]);
+        let parsed =
+            Frame::parse(&mut cursor)?.ok_or_else(|| anyhow::anyhow!("expected SessionRequest"))?;
+        assert_eq!(parsed, frame);
+        assert_eq!(frame.id(), 23);
+        assert_eq!(format!("{frame}"), "SessionRequest(1, 4 bytes)");
+        Ok(())
+    }
 }
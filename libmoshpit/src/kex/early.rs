@@ -0,0 +1,39 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Early key shares: the ephemeral exchange rides in the client's first flight.
+//!
+//! Without them the client waits for the server's `KexInit` before it can pick
+//! a key exchange, then for the `TransportPreference` echo, then for
+//! `PeerInitialize`.  From [`EARLY_KEY_SHARE_MIN_PROTOCOL_VERSION`] `mp` guesses
+//! that the server will negotiate the first key exchange in its own list and
+//! sends a [`Frame::EarlyKeyShare`](crate::Frame::EarlyKeyShare) — that
+//! algorithm's ephemeral exchange value plus the transport preference — right
+//! behind its `KexInit`.  The server answers with `KexInit`, the transport echo,
+//! and, when the guess was right, `PeerInitialize` in a single flight.  When the
+//! server picks a different algorithm it ignores the share, and the client,
+//! which runs the same negotiation, follows up with a `HiddenInitialize` for the
+//! negotiated one: the retry costs one round trip, no more than before.
+//!
+//! Both sides absorb the share into the transcript after the server's
+//! `KexInit`, the order the server reads them in.  A server older than this
+//! version cannot parse the share and stalls waiting for `TransportPreference`;
+//! the client sees the older version in the server's `KexInit`, reports
+//! [`MoshpitError::EarlyKeyShareUnsupported`](crate::MoshpitError::EarlyKeyShareUnsupported),
+//! and `mp` reconnects without speculating.
+//!
+//! The share does not carry the client's options.  `ClientOptions`,
+//! `ClientEnv`, `AgentForward`, `SessionRequest` and `RemoteCommand` or
+//! `FileCopy` still follow `PeerInitialize`, sealed inside the client's
+//! `Check`.  The server only acts on them once `Check` has verified, so moving
+//! them into the first flight would not save a round trip.
+
+/// Lowest negotiated protocol version whose server reads a
+/// [`Frame::EarlyKeyShare`](crate::Frame::EarlyKeyShare) in place of
+/// `TransportPreference`.
+pub const EARLY_KEY_SHARE_MIN_PROTOCOL_VERSION: u16 = 10;
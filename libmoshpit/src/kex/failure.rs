@@ -0,0 +1,119 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Reason codes carried by [`Frame::KexFailureReason`](crate::Frame::KexFailureReason).
+//!
+//! Older peers only understand the payload-less [`Frame::KexFailure`](crate::Frame::KexFailure),
+//! so moshpits sends a reason only to clients whose advertised protocol range
+//! reaches [`KEX_FAILURE_REASON_MIN_PROTOCOL_VERSION`].  Reasons that would let a
+//! client probe which accounts exist are collapsed into
+//! [`KexFailureReason::AuthenticationFailed`] unless the operator enables
+//! `detailed_auth_failures`.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use bincode_next::{Decode, Encode};
+
+/// Lowest protocol version whose clients understand
+/// [`Frame::KexFailureReason`](crate::Frame::KexFailureReason).
+pub const KEX_FAILURE_REASON_MIN_PROTOCOL_VERSION: u16 = 7;
+
+/// Why moshpits rejected a key exchange.
+#[derive(Clone, Copy, Debug, Decode, Encode, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum KexFailureReason {
+    /// The client and server share no key exchange, AEAD, MAC, or KDF algorithm.
+    NoCommonAlgorithm,
+    /// The client's protocol range does not overlap the server's.
+    IncompatibleProtocolVersion,
+    /// Authentication failed; the precise cause is withheld by the server.
+    AuthenticationFailed,
+    /// The requested user is not a valid account on the server.
+    UnknownUser,
+    /// The client's public key is not listed in the user's `~/.mp/authorized_keys`.
+    UnauthorizedKey,
+    /// The user's `~/.mp` directory or `authorized_keys` file has permissions
+    /// that are too open, so the server ignores it.
+    AuthorizedKeysPermissions,
+    /// The client's `Check` frame did not decrypt under the server's session keys.
+    KeyMismatch,
+    /// The client's resumption ticket has expired, was already used, or was
+    /// issued before the server restarted; a full key exchange is needed.
+    TicketRejected,
+    /// The client asked to resume a remote command session that has already
+    /// ended; a command is never started again on its behalf.
+    SessionEnded,
+    /// The client asked to attach to a session by a name none of the user's
+    /// sessions has.
+    NoSuchSession,
+    /// The client asked to type into another user's session that is shared
+    /// with it read-only.
+    SessionReadOnly,
+}
+
+impl KexFailureReason {
+    /// The reason as it may be sent to a client when the server withholds
+    /// account details: every reason that reveals whether the user exists, or
+    /// how their account is set up, becomes
+    /// [`AuthenticationFailed`](KexFailureReason::AuthenticationFailed).
+    #[must_use]
+    pub fn redacted(self) -> Self {
+        match self {
+            Self::UnknownUser | Self::UnauthorizedKey | Self::AuthorizedKeysPermissions => {
+                Self::AuthenticationFailed
+            }
+            other => other,
+        }
+    }
+}
+
+impl Display for KexFailureReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let reason = match self {
+            Self::NoCommonAlgorithm => "no common algorithm",
+            Self::IncompatibleProtocolVersion => "incompatible wire protocol version",
+            Self::AuthenticationFailed => "authentication failed",
+            Self::UnknownUser => "unknown user",
+            Self::UnauthorizedKey => "public key not authorized",
+            Self::AuthorizedKeysPermissions => "authorized_keys permissions too open",
+            Self::KeyMismatch => "session key mismatch",
+            Self::TicketRejected => "resumption ticket not accepted",
+            Self::SessionEnded => "session already ended",
+            Self::NoSuchSession => "no session by that name",
+            Self::SessionReadOnly => "session shared read-only",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KexFailureReason;
+
+    #[test]
+    fn redacted_hides_account_details_only() {
+        for reason in [
+            KexFailureReason::UnknownUser,
+            KexFailureReason::UnauthorizedKey,
+            KexFailureReason::AuthorizedKeysPermissions,
+        ] {
+            assert_eq!(reason.redacted(), KexFailureReason::AuthenticationFailed);
+        }
+        for reason in [
+            KexFailureReason::NoCommonAlgorithm,
+            KexFailureReason::IncompatibleProtocolVersion,
+            KexFailureReason::AuthenticationFailed,
+            KexFailureReason::KeyMismatch,
+            KexFailureReason::TicketRejected,
+            KexFailureReason::SessionEnded,
+            KexFailureReason::NoSuchSession,
+            KexFailureReason::SessionReadOnly,
+        ] {
+            assert_eq!(reason.redacted(), reason);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Client identity hiding: the username and identity key travel encrypted.
+//!
+//! From [`IDENTITY_HIDING_MIN_PROTOCOL_VERSION`] the client opens with
+//! [`Frame::HiddenInitialize`], which carries only its ephemeral exchange value.
+//! The server answers with `PeerInitialize`, and before revealing anything the
+//! client challenges the host key it names (`IdentityChallenge`).  The server
+//! must answer with an `IdentityProof` only the holder of that key can produce:
+//! for an ECDH host key, an HKDF tag over the ephemeral secret, the secret
+//! agreed with the challenge, and the transcript; for an ML-DSA host key, a
+//! signature over the transcript.  The client checks the proof, then sends the
+//! `Initialize` or `ResumeRequest` it would otherwise have sent in clear — plus,
+//! for ML-DSA identities, its `IdentityProof` — inside
+//! [`Frame::SealedIdentity`], under a handshake key derived from the same
+//! secrets.  An active attacker who answers `HiddenInitialize` with its own
+//! ephemeral key therefore learns nothing about the client.
+//!
+//! The plaintext is zero-padded to a multiple of [`IDENTITY_PADDING_BLOCK`]
+//! bytes so the ciphertext length does not single out a username.  The much
+//! larger ML-DSA identity keys still show in the length.
+
+use anyhow::Result;
+use aws_lc_rs::{
+    aead::{Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    hkdf::{KeyType as _, Salt},
+    rand::fill,
+};
+use bincode_next::{config::standard, encode_to_vec};
+
+use crate::{
+    Frame, KEY_ALGORITHM_P256, KEY_ALGORITHM_P384, KEY_ALGORITHM_X25519, MoshpitError,
+    frames::decode_frame,
+    kex::{
+        negotiate::NegotiatedAlgorithms,
+        reader::{resolve_aead_alg, resolve_hkdf_alg},
+    },
+};
+
+/// Lowest negotiated protocol version that hides the client's username and
+/// identity key from passive observers.
+pub const IDENTITY_HIDING_MIN_PROTOCOL_VERSION: u16 = 8;
+
+/// HKDF info label for the key that seals the client identity.
+const HANDSHAKE_KEY_INFO: &[u8] = b"HANDSHAKE KEY";
+
+/// HKDF info label for the tag an ECDH host key answers the client's challenge with.
+const HOST_PROOF_INFO: &[u8] = b"HOST PROOF";
+
+/// Domain separation prefix for the message an ML-DSA host key signs.
+#[cfg(feature = "unstable")]
+const HOST_PROOF_LABEL: &[u8] = b"moshpit-host-proof-v1";
+
+/// The sealed identity plaintext is padded up to a multiple of this many bytes.
+const IDENTITY_PADDING_BLOCK: usize = 256;
+
+/// The algorithm of the raw host public key in `PeerInitialize`, told apart by
+/// its length.
+pub(crate) fn host_key_algorithm(public_key: &[u8]) -> Option<&'static str> {
+    match public_key.len() {
+        32 => Some(KEY_ALGORITHM_X25519),
+        65 => Some(KEY_ALGORITHM_P256),
+        97 => Some(KEY_ALGORITHM_P384),
+        #[cfg(feature = "unstable")]
+        1312 => Some(crate::KEY_ALGORITHM_ML_DSA_44),
+        #[cfg(feature = "unstable")]
+        1952 => Some(crate::KEY_ALGORITHM_ML_DSA_65),
+        #[cfg(feature = "unstable")]
+        2592 => Some(crate::KEY_ALGORITHM_ML_DSA_87),
+        _ => None,
+    }
+}
+
+/// Derive the AEAD key that seals the client identity from `ikm` (the ephemeral
+/// secret, followed by the host secret for an ECDH host key) and `salt` (the
+/// server salt followed by the transcript hash).
+pub(crate) fn handshake_key(
+    ikm: &[u8],
+    salt: &[u8],
+    negotiated: &NegotiatedAlgorithms,
+) -> Result<LessSafeKey> {
+    let hkdf_alg = resolve_hkdf_alg(&negotiated.kdf)?;
+    let aead_alg = resolve_aead_alg(&negotiated.aead)?;
+    let prk = Salt::new(hkdf_alg, salt).extract(ikm);
+    let okm = prk.expand(&[HANDSHAKE_KEY_INFO], aead_alg)?;
+    let mut key_bytes = vec![0u8; aead_alg.key_len()];
+    okm.fill(&mut key_bytes)?;
+    Ok(LessSafeKey::new(UnboundKey::new(aead_alg, &key_bytes)?))
+}
+
+/// The tag an ECDH host key answers the client's challenge with, derived from
+/// the same `ikm` and `salt` as [`handshake_key`].
+pub(crate) fn host_proof(
+    ikm: &[u8],
+    salt: &[u8],
+    negotiated: &NegotiatedAlgorithms,
+) -> Result<Vec<u8>> {
+    let hkdf_alg = resolve_hkdf_alg(&negotiated.kdf)?;
+    let prk = Salt::new(hkdf_alg, salt).extract(ikm);
+    let okm = prk.expand(&[HOST_PROOF_INFO], hkdf_alg)?;
+    let mut proof = vec![0u8; hkdf_alg.len()];
+    okm.fill(&mut proof)?;
+    Ok(proof)
+}
+
+/// The message an ML-DSA host key signs to answer the client's challenge.
+#[cfg(feature = "unstable")]
+pub(crate) fn host_proof_message(salt: &[u8]) -> Vec<u8> {
+    [HOST_PROOF_LABEL, salt].concat()
+}
+
+/// Encrypt `frames` under `key` as a [`Frame::SealedIdentity`].
+pub(crate) fn seal_identity(key: &LessSafeKey, frames: &[Frame]) -> Result<Frame> {
+    let mut plaintext = encode_to_vec(frames, standard())?;
+    plaintext.resize(plaintext.len().next_multiple_of(IDENTITY_PADDING_BLOCK), 0);
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    fill(&mut nonce_bytes)?;
+    let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes)?;
+    key.seal_in_place_append_tag(nonce, Aad::empty(), &mut plaintext)?;
+    Ok(Frame::SealedIdentity(nonce_bytes, plaintext))
+}
+
+/// Decrypt the frames sealed by [`seal_identity`].
+///
+/// # Errors
+/// * [`MoshpitError::DecryptionFailed`] when the ciphertext does not open
+///   under `key`.
+pub(crate) fn open_identity(
+    key: &LessSafeKey,
+    nonce_bytes: [u8; NONCE_LEN],
+    mut ciphertext: Vec<u8>,
+) -> Result<Vec<Frame>> {
+    let nonce = Nonce::from(&nonce_bytes);
+    let plaintext = key
+        .open_in_place(nonce, Aad::empty(), &mut ciphertext)
+        .map_err(|_| MoshpitError::DecryptionFailed)?;
+    // Trailing padding is ignored by the decoder.
+    decode_frame(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::{
+        IDENTITY_PADDING_BLOCK, handshake_key, host_key_algorithm, host_proof, open_identity,
+        seal_identity,
+    };
+    use crate::{Frame, MoshpitError, kex::negotiate::NegotiatedAlgorithms};
+
+    fn initialize(user: &str, key_len: usize) -> Frame {
+        Frame::Initialize(user.as_bytes().to_vec(), vec![], vec![b'k'; key_len])
+    }
+
+    #[test]
+    fn sealed_identity_round_trips() -> Result<()> {
+        let key = handshake_key(&[1u8; 32], &[2u8; 64], &NegotiatedAlgorithms::default())?;
+        let frames = vec![initialize("alice", 80), Frame::IdentityProof(vec![9; 16])];
+        let Frame::SealedIdentity(nonce, ciphertext) = seal_identity(&key, &frames)? else {
+            panic!("expected SealedIdentity");
+        };
+        assert_eq!(open_identity(&key, nonce, ciphertext)?, frames);
+        Ok(())
+    }
+
+    #[test]
+    fn sealed_identity_length_hides_username_length() -> Result<()> {
+        let key = handshake_key(&[1u8; 32], &[2u8; 64], &NegotiatedAlgorithms::default())?;
+        let short = seal_identity(&key, &[initialize("al", 80)])?;
+        let long = seal_identity(&key, &[initialize("alexandria-maintenance", 80)])?;
+        let (Frame::SealedIdentity(_, short), Frame::SealedIdentity(_, long)) = (short, long)
+        else {
+            panic!("expected SealedIdentity");
+        };
+        assert_eq!(short.len(), long.len());
+        // Padded plaintext plus the 16-byte AEAD tag.
+        assert_eq!((short.len() - 16) % IDENTITY_PADDING_BLOCK, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn sealed_identity_rejects_a_different_handshake_key() -> Result<()> {
+        let negotiated = NegotiatedAlgorithms::default();
+        let key = handshake_key(&[1u8; 32], &[2u8; 64], &negotiated)?;
+        let other = handshake_key(&[1u8; 32], &[3u8; 64], &negotiated)?;
+        let Frame::SealedIdentity(nonce, ciphertext) =
+            seal_identity(&key, &[initialize("alice", 80)])?
+        else {
+            panic!("expected SealedIdentity");
+        };
+        assert!(
+            open_identity(&other, nonce, ciphertext)
+                .expect_err("a transcript mismatch must not open")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(|e| *e == MoshpitError::DecryptionFailed)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn host_proof_depends_on_the_host_secret() -> Result<()> {
+        let negotiated = NegotiatedAlgorithms::default();
+        let proof = host_proof(&[1u8; 64], &[2u8; 64], &negotiated)?;
+        assert_eq!(proof.len(), 32);
+        assert_eq!(proof, host_proof(&[1u8; 64], &[2u8; 64], &negotiated)?);
+        let mut without_host_key = [1u8; 64];
+        without_host_key[32..].fill(0);
+        assert_ne!(
+            proof,
+            host_proof(&without_host_key, &[2u8; 64], &negotiated)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn host_key_algorithm_follows_the_key_length() {
+        assert_eq!(
+            host_key_algorithm(&[0u8; 32]),
+            Some(crate::KEY_ALGORITHM_X25519)
+        );
+        assert_eq!(
+            host_key_algorithm(&[0u8; 65]),
+            Some(crate::KEY_ALGORITHM_P256)
+        );
+        assert_eq!(
+            host_key_algorithm(&[0u8; 97]),
+            Some(crate::KEY_ALGORITHM_P384)
+        );
+        assert_eq!(host_key_algorithm(&[0u8; 33]), None);
+    }
+}
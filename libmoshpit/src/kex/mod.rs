@@ -42,6 +42,7 @@ use tokio::{
 use tracing::warn;
 use tracing::{debug, error, info, trace};
 use uuid::Uuid;
+use zeroize::Zeroize as _;
 
 #[cfg(unix)]
 use crate::AgentClient;
@@ -49,8 +50,12 @@ use crate::AgentClient;
 use crate::keygen::{SUPPORTED_IDENTITY_ALGORITHMS, algorithm_strength_rank};
 use crate::{
     ConnectionReader, ConnectionWriter, Frame, KexConfig, KexReader, KexSender, MoshpitError,
-    UuidWrapper,
+    NonceScheme, RemoteCommand, UuidWrapper,
+    kex::failure::KexFailureReason,
     kex::negotiate::NegotiatedAlgorithms,
+    kex::reader::derive_session_keys,
+    kex::rekey::{KeyRatchet, REKEY_MIN_PROTOCOL_VERSION},
+    kex::ticket::ResumptionTicket,
     load_identity_key, load_public_key,
     udp::{DiffMode, TransportMode},
 };
@@ -80,7 +85,38 @@ struct HostKeyCallbacks {
     host_key_mismatch_fn: Option<HostKeyMismatchFn>,
 }
 
+pub(crate) mod early;
+pub(crate) mod failure;
+pub(crate) mod identity;
 pub(crate) mod negotiate;
+pub(crate) mod options;
+
+/// Lowest negotiated protocol version that derives separate client→server and
+/// server→client data-channel keys and seals packets under
+/// [`NonceScheme::Sequence`].
+pub const DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION: u16 = 5;
+
+/// The direction of data-channel traffic a key protects.
+///
+/// From [`DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION`] each direction has its own keys,
+/// so a packet reflected back at its sender never authenticates there.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum KeyDirection {
+    /// Frames sent by `mp` and read by `mps`.
+    ClientToServer,
+    /// Frames sent by `mps` and read by `mp`.
+    ServerToClient,
+}
+
+impl KeyDirection {
+    /// HKDF salt separating this direction's keys from the other's.
+    fn salt(self) -> &'static [u8] {
+        match self {
+            KeyDirection::ClientToServer => b"moshpit-client-to-server-v1",
+            KeyDirection::ServerToClient => b"moshpit-server-to-client-v1",
+        }
+    }
+}
 
 /// Returns `true` if `name` matches any pattern in `patterns`.
 ///
@@ -97,7 +133,10 @@ pub fn env_var_matches(name: &str, patterns: &[String]) -> bool {
     })
 }
 pub(crate) mod reader;
+pub(crate) mod rekey;
 pub(crate) mod sender;
+pub(crate) mod ticket;
+pub(crate) mod transcript;
 
 /// The key exchange events
 #[derive(Clone, Debug)]
@@ -115,11 +154,19 @@ pub enum KexEvent {
     MoshpitsAddr(SocketAddr),
     /// Session information: (stable session UUID, `is_resume` flag)
     SessionInfo(Uuid, bool),
+    /// A resumption ticket for the next reconnect (client mode, protocol
+    /// version 9+); arrives between `SessionInfo` and `MoshpitsAddr`.
+    ResumptionTicket(ResumptionTicket),
     /// Key exchange failure
     Failure,
     /// No algorithm in common between client and server — client should exit,
     /// not retry.
     NoCommonAlgorithm,
+    /// The server rejected the key exchange and said why (protocol version 7+).
+    Rejected(KexFailureReason),
+    /// The server negotiated a protocol version that predates the early key
+    /// share this client already sent; reconnect without one.
+    EarlyKeyShareUnsupported,
     /// Negotiated data-channel transport mode — emitted in client mode after
     /// `NegotiatedAlgorithms` and before `KeyMaterial`.  Server mode always
     /// defaults to `TransportMode::Udp` and never emits this event.
@@ -189,6 +236,9 @@ pub struct Kex {
     /// in client mode; defaults to `Udp` for server mode.
     #[getset(get_copy = "pub")]
     transport_mode: TransportMode,
+    /// Ticket the server issued for resuming this session (client mode only).
+    #[getset(get = "pub")]
+    resumption_ticket: Option<ResumptionTicket>,
 }
 
 impl Kex {
@@ -198,55 +248,97 @@ impl Kex {
         UuidWrapper::new(self.uuid)
     }
 
-    /// Build a `LessSafeKey` for UDP encryption using the negotiated AEAD algorithm.
+    /// Build the `LessSafeKey` protecting `direction` using the negotiated AEAD algorithm.
     ///
-    /// Supports all negotiated algorithms including ChaCha20-Poly1305.  Callers are
-    /// responsible for generating a unique random nonce per packet (use
-    /// `aws_lc_rs::rand::fill` on a `[u8; NONCE_LEN]` buffer).
+    /// Supports all negotiated algorithms including ChaCha20-Poly1305.  Packets are
+    /// sealed with the session's [`NonceScheme`]; see [`Kex::nonce_scheme`].
     ///
     /// # Errors
     /// Returns an error if the negotiated AEAD algorithm is unknown or the key bytes are invalid.
-    pub fn build_aead_key(&self) -> Result<LessSafeKey> {
-        use negotiate::{
-            AEAD_AES128_GCM_SIV, AEAD_AES256_GCM, AEAD_AES256_GCM_SIV, AEAD_CHACHA20_POLY1305,
-        };
-        let alg: &'static Algorithm = match self.negotiated_algorithms.aead.as_str() {
-            AEAD_AES256_GCM_SIV => &AES_256_GCM_SIV,
-            AEAD_AES256_GCM => &AES_256_GCM,
-            AEAD_CHACHA20_POLY1305 => &CHACHA20_POLY1305,
-            AEAD_AES128_GCM_SIV => &AES_128_GCM_SIV,
-            _ => return Err(MoshpitError::NoCommonAlgorithm.into()),
-        };
+    pub fn build_aead_key(&self, direction: KeyDirection) -> Result<LessSafeKey> {
+        let (mut key_bytes, mut hmac_bytes) = self.direction_keys(direction)?;
         debug!(
             aead = %self.negotiated_algorithms.aead,
-            key_len = self.key.len(),
-            key_hex = %fmt_hex(&self.key),
+            ?direction,
+            key_len = key_bytes.len(),
+            key_hex = %fmt_hex(&key_bytes),
             "build_aead_key: constructing LessSafeKey"
         );
-        Ok(LessSafeKey::new(UnboundKey::new(alg, &self.key)?))
+        let rnk = aead_key(&self.negotiated_algorithms, &key_bytes);
+        key_bytes.zeroize();
+        hmac_bytes.zeroize();
+        rnk
     }
 
-    /// Build an HMAC `Key` for UDP packet authentication using the negotiated MAC algorithm.
+    /// Build the HMAC `Key` authenticating `direction` using the negotiated MAC algorithm.
+    ///
+    /// Returns `None` under [`MAC_AEAD_IMPLICIT`](crate::MAC_AEAD_IMPLICIT), where the
+    /// AEAD tag alone authenticates each packet.
+    ///
+    /// # Errors
+    /// Returns an error if the negotiated KDF, AEAD, or MAC algorithm is unknown.
+    pub fn build_hmac(&self, direction: KeyDirection) -> Result<Option<Key>> {
+        let (mut key_bytes, mut hmac_bytes) = self.direction_keys(direction)?;
+        let hmac = hmac_key(&self.negotiated_algorithms, &hmac_bytes);
+        key_bytes.zeroize();
+        hmac_bytes.zeroize();
+        Ok(hmac)
+    }
+
+    /// Seed a [`KeyRatchet`] from `direction`'s keys for in-session rekeying.
+    ///
+    /// Returns `None` when the peer negotiated a protocol version older than
+    /// [`REKEY_MIN_PROTOCOL_VERSION`]; such peers do not understand
+    /// [`EncryptedFrame::Rekey`](crate::EncryptedFrame::Rekey), so the session keeps
+    /// its handshake keys for its whole lifetime.
+    ///
+    /// # Errors
+    /// Returns an error if the negotiated KDF, AEAD, or MAC algorithm is unknown.
+    pub fn key_ratchet(&self, direction: KeyDirection) -> Result<Option<KeyRatchet>> {
+        if self.protocol_version() < REKEY_MIN_PROTOCOL_VERSION {
+            return Ok(None);
+        }
+        let (key, hmac_key) = self.direction_keys(direction)?;
+        Ok(Some(KeyRatchet::new(
+            key,
+            hmac_key,
+            self.negotiated_algorithms.clone(),
+        )))
+    }
+
+    /// How data-channel packets choose their AEAD nonce in this session.
     #[must_use]
-    pub fn build_hmac(&self) -> Key {
-        use negotiate::MAC_HMAC_SHA256;
-        if self.negotiated_algorithms.mac.as_str() == MAC_HMAC_SHA256 {
-            Key::new(HMAC_SHA256, &self.hmac_key)
-        } else {
-            Key::new(HMAC_SHA512, &self.hmac_key)
+    pub fn nonce_scheme(&self) -> NonceScheme {
+        NonceScheme::for_protocol_version(self.protocol_version())
+    }
+
+    /// Raw AEAD and HMAC key bytes for `direction`.
+    ///
+    /// Below [`DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION`] both directions share the
+    /// handshake keys.  From that version each direction's keys are expanded from
+    /// them through the negotiated HKDF under a per-direction salt.
+    fn direction_keys(&self, direction: KeyDirection) -> Result<(Vec<u8>, Vec<u8>)> {
+        if self.protocol_version() < DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION {
+            return Ok((self.key.clone(), self.hmac_key.clone()));
         }
+        let mut ikm = self.key.clone();
+        ikm.extend_from_slice(&self.hmac_key);
+        let derived = derive_session_keys(&ikm, direction.salt(), &self.negotiated_algorithms);
+        ikm.zeroize();
+        derived
     }
 
     /// Returns the byte length of the MAC tag produced by the negotiated MAC algorithm.
     ///
-    /// HMAC-SHA256 produces 32-byte tags; all others produce 64-byte tags.
+    /// HMAC-SHA256 produces 32-byte tags and `aead-implicit` sends none; all others
+    /// produce 64-byte tags.
     #[must_use]
     pub fn mac_tag_len(&self) -> usize {
-        use negotiate::MAC_HMAC_SHA256;
-        if self.negotiated_algorithms.mac.as_str() == MAC_HMAC_SHA256 {
-            32
-        } else {
-            64
+        use negotiate::{MAC_AEAD_IMPLICIT, MAC_HMAC_SHA256};
+        match self.negotiated_algorithms.mac.as_str() {
+            MAC_HMAC_SHA256 => 32,
+            MAC_AEAD_IMPLICIT => 0,
+            _ => 64,
         }
     }
 
@@ -254,14 +346,40 @@ impl Kex {
     ///
     /// This is the intended branch input for version-dependent behaviour — see
     /// [`PROTOCOL_VERSION`](crate::PROTOCOL_VERSION) for when to bump the version
-    /// and how to gate on it.  To gate the format of UDP frames, thread this value
-    /// from session setup into the UDP transport (it is not carried there today).
+    /// and how to gate on it.  The data-channel transports do not see this value;
+    /// session setup passes them what it implies, such as [`Kex::nonce_scheme`].
     #[must_use]
     pub fn protocol_version(&self) -> u16 {
         self.negotiated_algorithms.protocol_version
     }
 }
 
+/// Build a `LessSafeKey` from raw key bytes for the negotiated AEAD algorithm.
+pub(crate) fn aead_key(negotiated: &NegotiatedAlgorithms, key: &[u8]) -> Result<LessSafeKey> {
+    use negotiate::{
+        AEAD_AES128_GCM_SIV, AEAD_AES256_GCM, AEAD_AES256_GCM_SIV, AEAD_CHACHA20_POLY1305,
+    };
+    let alg: &'static Algorithm = match negotiated.aead.as_str() {
+        AEAD_AES256_GCM_SIV => &AES_256_GCM_SIV,
+        AEAD_AES256_GCM => &AES_256_GCM,
+        AEAD_CHACHA20_POLY1305 => &CHACHA20_POLY1305,
+        AEAD_AES128_GCM_SIV => &AES_128_GCM_SIV,
+        _ => return Err(MoshpitError::NoCommonAlgorithm.into()),
+    };
+    Ok(LessSafeKey::new(UnboundKey::new(alg, key)?))
+}
+
+/// Build an HMAC `Key` from raw key bytes for the negotiated MAC algorithm, or
+/// `None` when the session relies on the AEAD tag alone.
+pub(crate) fn hmac_key(negotiated: &NegotiatedAlgorithms, key: &[u8]) -> Option<Key> {
+    use negotiate::{MAC_AEAD_IMPLICIT, MAC_HMAC_SHA256};
+    match negotiated.mac.as_str() {
+        MAC_HMAC_SHA256 => Some(Key::new(HMAC_SHA256, key)),
+        MAC_AEAD_IMPLICIT => None,
+        _ => Some(Key::new(HMAC_SHA512, key)),
+    }
+}
+
 impl Default for Kex {
     fn default() -> Self {
         Self {
@@ -273,11 +391,13 @@ impl Default for Kex {
             is_resume: false,
             negotiated_algorithms: NegotiatedAlgorithms::default(),
             transport_mode: TransportMode::Udp,
+            resumption_ticket: None,
         }
     }
 }
 
 /// Extended key exchange for the moshpits side of the exchange
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Builder, Clone, Debug, CopyGetters, Getters)]
 pub struct ServerKex {
     /// The user associated with the key exchange
@@ -312,6 +432,29 @@ pub struct ServerKex {
     #[getset(get = "pub")]
     #[builder(default)]
     client_extra_path: Vec<String>,
+    /// Whether the client asked for its agent to be forwarded with an
+    /// `AgentForward` frame.
+    #[getset(get_copy = "pub")]
+    #[builder(default)]
+    forward_agent: bool,
+    /// The command the client asked to run in place of a login shell with a
+    /// `RemoteCommand` frame.
+    #[getset(get = "pub")]
+    remote_command: Option<RemoteCommand>,
+    /// Whether the client asked for a list of its sessions with a
+    /// `SessionRequest` frame instead of a session of its own.
+    #[getset(get_copy = "pub")]
+    #[builder(default)]
+    list_sessions: bool,
+    /// Whether the client joined its session alongside the clients already
+    /// attached to it, rather than taking it over.
+    #[getset(get_copy = "pub")]
+    #[builder(default)]
+    shared: bool,
+    /// Whether the client's keystrokes are to be dropped.
+    #[getset(get_copy = "pub")]
+    #[builder(default)]
+    read_only: bool,
 }
 
 impl ServerKex {
@@ -319,8 +462,8 @@ impl ServerKex {
     ///
     /// This is the intended branch input for version-dependent behaviour — see
     /// [`PROTOCOL_VERSION`](crate::PROTOCOL_VERSION) for when to bump the version
-    /// and how to gate on it.  To gate the format of UDP frames, thread this value
-    /// from session setup into the UDP transport (it is not carried there today).
+    /// and how to gate on it.  The data-channel transports do not see this value;
+    /// session setup passes them what it implies, such as [`Kex::nonce_scheme`].
     #[must_use]
     pub fn protocol_version(&self) -> u16 {
         self.negotiated_algorithms.protocol_version
@@ -378,6 +521,9 @@ impl KexStateMachine {
                     kex.is_resume = is_resume;
                     self.state = KexState::AwaitingMoshpitsAddr;
                 }
+                (KexState::AwaitingMoshpitsAddr, KexEvent::ResumptionTicket(ticket)) => {
+                    kex.resumption_ticket = Some(ticket);
+                }
                 (KexState::AwaitingMoshpitsAddr, KexEvent::MoshpitsAddr(addr)) => {
                     self.state = KexState::Complete;
                     kex.moshpits_addr = Some(addr);
@@ -386,6 +532,12 @@ impl KexStateMachine {
                 (_, KexEvent::NoCommonAlgorithm) => {
                     return Err(MoshpitError::NoCommonAlgorithm.into());
                 }
+                (_, KexEvent::Rejected(reason)) => {
+                    return Err(MoshpitError::KexRejected(reason).into());
+                }
+                (_, KexEvent::EarlyKeyShareUnsupported) => {
+                    return Err(MoshpitError::EarlyKeyShareUnsupported.into());
+                }
                 _ => {
                     return Err(MoshpitError::InvalidKexState.into());
                 }
@@ -459,9 +611,36 @@ pub async fn run_key_exchange<T: KexConfig>(
     host_key_mismatch_fn: Option<HostKeyMismatchFn>,
 ) -> Result<KexOutcome> {
     // Setup the TCP connection to the server for key exchange
-    let mode = config.mode();
     let reader = ConnectionReader::builder().reader(sock_read).build();
     let writer = ConnectionWriter::builder().writer(sock_write).build();
+    run_key_exchange_over(
+        config,
+        reader,
+        writer,
+        passphrase_fn,
+        tofu_fn,
+        host_key_mismatch_fn,
+    )
+    .await
+}
+
+/// Run the key exchange over an already established frame reader and writer,
+/// such as those returned by [`connect_udp_handshake`](crate::connect_udp_handshake)
+/// or [`UdpHandshakeListener::accept`](crate::UdpHandshakeListener::accept).
+///
+/// Behaves exactly like [`run_key_exchange`] otherwise.
+///
+/// # Errors
+/// See [`run_key_exchange`].
+pub async fn run_key_exchange_over<T: KexConfig>(
+    config: T,
+    reader: ConnectionReader,
+    writer: ConnectionWriter,
+    passphrase_fn: impl Fn() -> Result<Option<String>>,
+    tofu_fn: Option<TofuFn>,
+    host_key_mismatch_fn: Option<HostKeyMismatchFn>,
+) -> Result<KexOutcome> {
+    let mode = config.mode();
     let (tx, rx) = unbounded_channel();
     let (tx_event, rx_event) = unbounded_channel::<KexEvent>();
     let mut kex_sm = KexStateMachine::builder().rx_event(rx_event).build();
@@ -496,7 +675,13 @@ pub async fn run_key_exchange<T: KexConfig>(
             match run_server_kex(config, socket_addr, tx, tx_event, reader, kex_handle).await {
                 Ok(result) => Ok(result),
                 Err(e) => {
-                    let _blah = tx_c.send(Frame::KexFailure);
+                    // Rejections have already told the client why.
+                    if !matches!(
+                        e.downcast_ref::<MoshpitError>(),
+                        Some(MoshpitError::KexRejected(_))
+                    ) {
+                        let _blah = tx_c.send(Frame::KexFailure);
+                    }
                     Err(e)
                 }
             }
@@ -516,11 +701,19 @@ async fn run_client_kex<T: KexConfig>(
     callbacks: HostKeyCallbacks,
 ) -> Result<KexOutcome> {
     let agent_socket = config.agent_socket();
+    // A live resumption ticket stands in for the identity key, so neither the
+    // agent nor the key file (and its passphrase prompt) is needed.
+    let resumption_ticket = config
+        .resume_session_uuid()
+        .and(config.resumption_ticket())
+        .filter(|ticket| !ticket.is_expired());
 
     // Resolve identity: try agent first, fall back to key files if agent is
     // unavailable or has no compatible identities.
     #[cfg(unix)]
-    let agent_result: Option<(Vec<u8>, String)> = if let Some(ref socket) = agent_socket {
+    let agent_result: Option<(Vec<u8>, String)> = if resumption_ticket.is_none()
+        && let Some(ref socket) = agent_socket
+    {
         info!("Agent socket configured — loading identity from moshpit-agent");
         let client = AgentClient::new(socket.clone());
         match client
@@ -563,8 +756,18 @@ async fn run_client_kex<T: KexConfig>(
     #[cfg(not(unix))]
     let agent_result: Option<(Vec<u8>, String)> = None;
 
-    let (full_public_key_bytes, agent_fingerprint) = if let Some((pk_bytes, fp)) = agent_result {
-        (pk_bytes, Some(fp))
+    // The agent path leaves the private key in agent memory; identity-key
+    // operations (signing, identity DH) are delegated via `agent_fingerprint`.
+    let (
+        full_public_key_bytes,
+        agent_fingerprint,
+        client_identity_key_algorithm,
+        client_identity_private_key,
+    ) = if let Some((pk_bytes, fp)) = agent_result {
+        (pk_bytes, Some(fp), String::new(), vec![])
+    } else if resumption_ticket.is_some() {
+        info!("Resumption ticket available — skipping identity key");
+        (vec![], None, String::new(), vec![])
     } else {
         let (private_key_path, public_key_path) = config.key_pair_paths()?;
         info!(
@@ -618,42 +821,20 @@ async fn run_client_kex<T: KexConfig>(
             identity_key.key_algorithm()
         );
 
-        #[cfg(feature = "unstable")]
-        {
-            // Store algorithm and private key for later use below.
-            (full_pub_bytes, None)
-        }
-        #[cfg(not(feature = "unstable"))]
-        (full_pub_bytes, None)
+        (
+            full_pub_bytes,
+            None,
+            identity_key.key_algorithm().clone(),
+            identity_key.private_key().clone(),
+        )
     };
 
-    // For file-based path, we need the identity_key for unstable signing.
-    // Re-load it here (only in the non-agent branch that reaches this point).
-    #[cfg(feature = "unstable")]
-    let (client_identity_key_algorithm, client_identity_private_key) =
-        if agent_fingerprint.is_some() {
-            // Agent holds the private key; algorithm is looked up from fingerprint.
-            // The `agent_fingerprint` carries the algorithm at signing time.
-            (String::new(), vec![])
-        } else {
-            let (private_key_path, _) = config.key_pair_paths()?;
-            let identity_key = load_identity_key(&private_key_path, None).or_else(|_| {
-                let passphrase = passphrase_fn()?;
-                let p = passphrase.ok_or(MoshpitError::KeyCorrupt)?;
-                load_identity_key(&private_key_path, Some(&p))
-                    .map_err(|_| anyhow::anyhow!(MoshpitError::KeyCorrupt))
-            })?;
-            (
-                identity_key.key_algorithm().clone(),
-                identity_key.private_key().clone(),
-            )
-        };
-
     // Setup the TCP frame reader
     let tx_c = tx.clone();
     let tx_event_c = tx_event.clone();
     let requested = config.resume_session_uuid();
     let server_id = config.server_id();
+    let legacy_server_ids = config.legacy_server_ids();
     let HostKeyCallbacks {
         tofu_fn,
         host_key_mismatch_fn,
@@ -669,18 +850,36 @@ async fn run_client_kex<T: KexConfig>(
         .filter(|(k, _)| env_var_matches(k, &send_env_patterns))
         .collect();
     let send_path = config.send_path();
+    let early_key_share = config.early_key_share();
+    let forward_agent = config.forward_agent();
+    let remote_command = config.remote_command();
+    let session_request = config.session_request();
+
+    // Send KexInit before the reader starts — Initialize/ResumeRequest is sent
+    // inside client_kex() after reading the server's KexInit and generating the
+    // correct ephemeral key, and an early key share must follow the KexInit.
+    tx.send(Frame::KexInit(
+        config.preferred_algorithms(),
+        config.protocol_support(),
+    ))?;
+
     let _read_handle = spawn(async move {
-        #[cfg(feature = "unstable")]
         let mut frame_reader = KexReader::builder()
             .reader(reader)
             .tx(tx_c)
             .tx_event(tx_event_c)
             .maybe_requested_session_uuid(requested)
+            .maybe_resumption_ticket(resumption_ticket)
             .maybe_server_destination(server_id)
+            .legacy_server_destinations(legacy_server_ids)
             .maybe_tofu_fn(tofu_fn)
             .maybe_host_key_mismatch_fn(host_key_mismatch_fn)
             .diff_mode(diff_mode)
             .transport_preference(transport_preference)
+            .early_key_share(early_key_share)
+            .forward_agent(forward_agent)
+            .maybe_remote_command(remote_command)
+            .maybe_session_request(session_request)
             .client_algos(client_algos)
             .protocol_support(client_protocol_support)
             .user(user)
@@ -692,38 +891,11 @@ async fn run_client_kex<T: KexConfig>(
             .send_env(send_env)
             .send_path(send_path)
             .build();
-        #[cfg(not(feature = "unstable"))]
-        let mut frame_reader = KexReader::builder()
-            .reader(reader)
-            .tx(tx_c)
-            .tx_event(tx_event_c)
-            .maybe_requested_session_uuid(requested)
-            .maybe_server_destination(server_id)
-            .maybe_tofu_fn(tofu_fn)
-            .maybe_host_key_mismatch_fn(host_key_mismatch_fn)
-            .diff_mode(diff_mode)
-            .transport_preference(transport_preference)
-            .client_algos(client_algos)
-            .protocol_support(client_protocol_support)
-            .user(user)
-            .full_public_key_bytes(full_public_key_bytes)
-            .maybe_agent_socket(agent_socket)
-            .maybe_agent_fingerprint(agent_fingerprint)
-            .send_env(send_env)
-            .send_path(send_path)
-            .build();
         if let Err(e) = frame_reader.client_kex().await {
             error!("client_kex failed: {e}");
         }
     });
 
-    // Send KexInit only — Initialize/ResumeRequest is sent inside client_kex() after
-    // reading the server's KexInit and generating the correct ephemeral key.
-    tx.send(Frame::KexInit(
-        config.preferred_algorithms(),
-        config.protocol_support(),
-    ))?;
-
     let kex = kex_handle.await??;
 
     if let Some(moshpits_addr) = kex.moshpits_addr() {
@@ -784,7 +956,9 @@ async fn run_server_kex<T: KexConfig>(
 ) -> Result<KexOutcome> {
     let port_pool_opt = config.port_pool();
     let allow_tcp = config.allow_tcp_transport();
-    let (_private_key_path, public_key_path) = config.key_pair_paths()?;
+    let detailed_auth_failures = config.detailed_auth_failures();
+    let ticket_issuer = config.ticket_issuer();
+    let (private_key_path, public_key_path) = config.key_pair_paths()?;
     let session_registry = config.session_registry();
     trace!(
         "Loading identity public key from {}",
@@ -802,13 +976,15 @@ async fn run_server_kex<T: KexConfig>(
         .tx_event(tx_event_c)
         .server_preferred_algos(server_preferred_algos)
         .protocol_support(server_protocol_support)
+        .detailed_auth_failures(detailed_auth_failures)
+        .maybe_ticket_issuer(ticket_issuer)
         .build();
     if let Some(port_pool) = port_pool_opt {
         let (skex, transport) = frame_reader
             .server_kex(
                 socket_addr,
                 port_pool,
-                &public_key_path,
+                (&private_key_path, &public_key_path),
                 session_registry,
                 allow_tcp,
             )
@@ -834,7 +1010,9 @@ mod tests {
     use uuid::Uuid;
 
     use super::{
-        Kex, KexEvent, KexMode, KexStateMachine, MoshpitError, ServerKex, env_var_matches,
+        DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION, Kex, KexEvent, KexFailureReason, KexMode,
+        KexStateMachine, KeyDirection, MoshpitError, NonceScheme, REKEY_MIN_PROTOCOL_VERSION,
+        ServerKex, env_var_matches,
     };
     use crate::TransportMode;
 
@@ -933,6 +1111,41 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn kex_state_machine_client_mode_keeps_resumption_ticket() -> Result<()> {
+        use crate::{
+            TransportMode,
+            kex::{negotiate::NegotiatedAlgorithms, ticket::ResumptionTicket},
+        };
+
+        let (tx, rx) = unbounded_channel();
+        let mut sm = KexStateMachine::builder().rx_event(rx).build();
+        let ticket = ResumptionTicket::new(vec![1, 2, 3], vec![4u8; 32], 60);
+        tx.send(KexEvent::NegotiatedAlgorithms(
+            NegotiatedAlgorithms::default(),
+        ))
+        .expect("test channel send");
+        tx.send(KexEvent::TransportMode(TransportMode::Udp))
+            .expect("test channel send");
+        tx.send(KexEvent::KeyMaterial(vec![0u8; 32]))
+            .expect("test channel send");
+        tx.send(KexEvent::HMACKeyMaterial(vec![0u8; 64]))
+            .expect("test channel send");
+        tx.send(KexEvent::Uuid(Uuid::new_v4()))
+            .expect("test channel send");
+        tx.send(KexEvent::SessionInfo(Uuid::new_v4(), true))
+            .expect("test channel send");
+        tx.send(KexEvent::ResumptionTicket(ticket.clone()))
+            .expect("test channel send");
+        tx.send(KexEvent::MoshpitsAddr(
+            "127.0.0.1:51001".parse().expect("hardcoded test address"),
+        ))
+        .expect("test channel send");
+        let kex = sm.handle_events(true).await?;
+        assert_eq!(kex.resumption_ticket().as_ref(), Some(&ticket));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn kex_state_machine_wrong_event_order_returns_invalid_state() {
         let (tx, rx) = unbounded_channel();
@@ -1027,10 +1240,125 @@ mod tests {
                 ..NegotiatedAlgorithms::default()
             },
             transport_mode: TransportMode::Udp,
+            resumption_ticket: None,
         };
         assert_eq!(kex.protocol_version(), 42);
     }
 
+    #[test]
+    fn kex_key_ratchet_requires_rekey_protocol_version() -> Result<()> {
+        use crate::kex::negotiate::NegotiatedAlgorithms;
+        let kex_at = |protocol_version| Kex {
+            key: vec![0u8; 32],
+            hmac_key: vec![0u8; 64],
+            uuid: Uuid::nil(),
+            moshpits_addr: None,
+            session_uuid: None,
+            is_resume: false,
+            negotiated_algorithms: NegotiatedAlgorithms {
+                protocol_version,
+                ..NegotiatedAlgorithms::default()
+            },
+            transport_mode: TransportMode::Udp,
+            resumption_ticket: None,
+        };
+        let direction = KeyDirection::ClientToServer;
+        assert!(
+            kex_at(REKEY_MIN_PROTOCOL_VERSION - 1)
+                .key_ratchet(direction)?
+                .is_none()
+        );
+        let ratchet = kex_at(REKEY_MIN_PROTOCOL_VERSION).key_ratchet(direction)?;
+        assert_eq!(ratchet.map(|r| r.epoch()), Some(0));
+        Ok(())
+    }
+
+    fn directional_kex(protocol_version: u16) -> Kex {
+        use crate::kex::negotiate::NegotiatedAlgorithms;
+        Kex {
+            key: vec![1u8; 32],
+            hmac_key: vec![2u8; 64],
+            negotiated_algorithms: NegotiatedAlgorithms {
+                protocol_version,
+                ..NegotiatedAlgorithms::default()
+            },
+            ..Kex::default()
+        }
+    }
+
+    #[test]
+    fn directional_keys_are_shared_before_directional_protocol_version() -> Result<()> {
+        let kex = directional_kex(DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION - 1);
+        let c2s = kex.direction_keys(KeyDirection::ClientToServer)?;
+        let s2c = kex.direction_keys(KeyDirection::ServerToClient)?;
+        assert_eq!(c2s, s2c);
+        assert_eq!(c2s, (kex.key().clone(), kex.hmac_key().clone()));
+        assert_eq!(kex.nonce_scheme(), NonceScheme::Random);
+        Ok(())
+    }
+
+    #[test]
+    fn directional_keys_differ_per_direction() -> Result<()> {
+        let kex = directional_kex(DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION);
+        let (c2s_key, c2s_hmac) = kex.direction_keys(KeyDirection::ClientToServer)?;
+        let (s2c_key, s2c_hmac) = kex.direction_keys(KeyDirection::ServerToClient)?;
+        assert_ne!(c2s_key, s2c_key);
+        assert_ne!(c2s_hmac, s2c_hmac);
+        assert_ne!(&c2s_key, kex.key());
+        assert_eq!(c2s_key.len(), kex.key().len());
+        assert_eq!(c2s_hmac.len(), kex.hmac_key().len());
+        assert_eq!(kex.nonce_scheme(), NonceScheme::Sequence);
+        Ok(())
+    }
+
+    /// A packet the client sealed for the server must not be accepted if an
+    /// attacker reflects it back at the client.
+    #[test]
+    fn directional_keys_reject_reflected_packets() -> Result<()> {
+        use std::io::Cursor;
+
+        use crate::EncryptedFrame;
+
+        let kex = directional_kex(DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION);
+        let scheme = kex.nonce_scheme();
+        let c2s_rnk = kex.build_aead_key(KeyDirection::ClientToServer)?;
+        let c2s_hmac = kex.build_hmac(KeyDirection::ClientToServer)?;
+        let s2c_rnk = kex.build_aead_key(KeyDirection::ServerToClient)?;
+        let s2c_hmac = kex.build_hmac(KeyDirection::ServerToClient)?;
+        let packet = EncryptedFrame::Keepalive(1).seal(
+            0,
+            kex.uuid(),
+            &c2s_rnk,
+            c2s_hmac.as_ref(),
+            scheme,
+        )?;
+
+        let mut cursor = Cursor::new(packet.as_slice());
+        let parsed = EncryptedFrame::parse(
+            &mut cursor,
+            kex.uuid(),
+            c2s_hmac.as_ref(),
+            &c2s_rnk,
+            64,
+            scheme,
+        )?;
+        assert_eq!(parsed, Some((EncryptedFrame::Keepalive(1), 0)));
+
+        let mut cursor = Cursor::new(packet.as_slice());
+        assert!(
+            EncryptedFrame::parse(
+                &mut cursor,
+                kex.uuid(),
+                s2c_hmac.as_ref(),
+                &s2c_rnk,
+                64,
+                scheme
+            )
+            .is_err()
+        );
+        Ok(())
+    }
+
     #[test]
     fn server_kex_protocol_version_returns_negotiated() {
         use crate::kex::negotiate::NegotiatedAlgorithms;
@@ -1061,8 +1389,9 @@ mod tests {
                 ..NegotiatedAlgorithms::default()
             },
             transport_mode: TransportMode::Udp,
+            resumption_ticket: None,
         };
-        assert!(kex.build_aead_key().is_ok());
+        assert!(kex.build_aead_key(KeyDirection::ClientToServer).is_ok());
     }
 
     #[test]
@@ -1080,8 +1409,9 @@ mod tests {
                 ..NegotiatedAlgorithms::default()
             },
             transport_mode: TransportMode::Udp,
+            resumption_ticket: None,
         };
-        assert!(kex.build_aead_key().is_ok());
+        assert!(kex.build_aead_key(KeyDirection::ClientToServer).is_ok());
     }
 
     #[test]
@@ -1099,8 +1429,9 @@ mod tests {
                 ..NegotiatedAlgorithms::default()
             },
             transport_mode: TransportMode::Udp,
+            resumption_ticket: None,
         };
-        assert!(kex.build_aead_key().is_ok());
+        assert!(kex.build_aead_key(KeyDirection::ClientToServer).is_ok());
     }
 
     #[test]
@@ -1118,8 +1449,9 @@ mod tests {
                 ..NegotiatedAlgorithms::default()
             },
             transport_mode: TransportMode::Udp,
+            resumption_ticket: None,
         };
-        assert!(kex.build_aead_key().is_ok());
+        assert!(kex.build_aead_key(KeyDirection::ClientToServer).is_ok());
     }
 
     #[test]
@@ -1137,8 +1469,9 @@ mod tests {
                 ..NegotiatedAlgorithms::default()
             },
             transport_mode: TransportMode::Udp,
+            resumption_ticket: None,
         };
-        assert!(kex.build_aead_key().is_err());
+        assert!(kex.build_aead_key(KeyDirection::ClientToServer).is_err());
     }
 
     #[test]
@@ -1156,6 +1489,7 @@ mod tests {
                 ..NegotiatedAlgorithms::default()
             },
             transport_mode: TransportMode::Udp,
+            resumption_ticket: None,
         };
         assert_eq!(kex.mac_tag_len(), 32);
     }
@@ -1175,12 +1509,13 @@ mod tests {
                 ..NegotiatedAlgorithms::default()
             },
             transport_mode: TransportMode::Udp,
+            resumption_ticket: None,
         };
         assert_eq!(kex.mac_tag_len(), 64);
     }
 
     #[test]
-    fn build_hmac_sha256_produces_key() {
+    fn build_hmac_sha256_produces_key() -> Result<()> {
         use crate::kex::negotiate::{MAC_HMAC_SHA256, NegotiatedAlgorithms};
         let kex = Kex {
             key: Vec::new(),
@@ -1194,13 +1529,15 @@ mod tests {
                 ..NegotiatedAlgorithms::default()
             },
             transport_mode: TransportMode::Udp,
+            resumption_ticket: None,
         };
-        let _key = kex.build_hmac(); // verify it doesn't panic
+        assert!(kex.build_hmac(KeyDirection::ClientToServer)?.is_some());
         assert_eq!(kex.mac_tag_len(), 32);
+        Ok(())
     }
 
     #[test]
-    fn build_hmac_sha512_produces_key() {
+    fn build_hmac_sha512_produces_key() -> Result<()> {
         use crate::kex::negotiate::{MAC_HMAC_SHA512, NegotiatedAlgorithms};
         let kex = Kex {
             key: Vec::new(),
@@ -1214,9 +1551,33 @@ mod tests {
                 ..NegotiatedAlgorithms::default()
             },
             transport_mode: TransportMode::Udp,
+            resumption_ticket: None,
         };
-        let _key = kex.build_hmac(); // verify it doesn't panic
+        assert!(kex.build_hmac(KeyDirection::ClientToServer)?.is_some());
         assert_eq!(kex.mac_tag_len(), 64);
+        Ok(())
+    }
+
+    #[test]
+    fn aead_implicit_has_no_hmac_and_no_tag() -> Result<()> {
+        use crate::kex::negotiate::{MAC_AEAD_IMPLICIT, NegotiatedAlgorithms};
+        let kex = Kex {
+            key: vec![0u8; 32],
+            negotiated_algorithms: NegotiatedAlgorithms {
+                protocol_version: DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION,
+                mac: MAC_AEAD_IMPLICIT.to_string(),
+                ..NegotiatedAlgorithms::default()
+            },
+            ..Kex::default()
+        };
+        assert!(kex.build_hmac(KeyDirection::ClientToServer)?.is_none());
+        assert!(kex.build_aead_key(KeyDirection::ClientToServer).is_ok());
+        assert_eq!(kex.mac_tag_len(), 0);
+        let ratchet = kex
+            .key_ratchet(KeyDirection::ServerToClient)?
+            .ok_or_else(|| anyhow::anyhow!("expected a key ratchet"))?;
+        assert!(ratchet.next()?.keys()?.1.is_none());
+        Ok(())
     }
 
     #[tokio::test]
@@ -1234,4 +1595,38 @@ mod tests {
                 .is_some_and(|e| *e == MoshpitError::NoCommonAlgorithm),
         );
     }
+
+    #[tokio::test]
+    async fn kex_state_machine_rejected_returns_reason() {
+        let (tx, rx) = unbounded_channel();
+        let mut sm = KexStateMachine::builder().rx_event(rx).build();
+        tx.send(KexEvent::Rejected(KexFailureReason::UnauthorizedKey))
+            .expect("test channel send");
+        drop(tx);
+        let result = sm.handle_events(true).await;
+        assert!(
+            result
+                .expect_err("expected KexRejected error")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(
+                    |e| *e == MoshpitError::KexRejected(KexFailureReason::UnauthorizedKey)
+                ),
+        );
+    }
+
+    #[tokio::test]
+    async fn kex_state_machine_early_key_share_unsupported_returns_error() {
+        let (tx, rx) = unbounded_channel();
+        let mut sm = KexStateMachine::builder().rx_event(rx).build();
+        tx.send(KexEvent::EarlyKeyShareUnsupported)
+            .expect("test channel send");
+        drop(tx);
+        let result = sm.handle_events(true).await;
+        assert!(
+            result
+                .expect_err("expected EarlyKeyShareUnsupported error")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(|e| *e == MoshpitError::EarlyKeyShareUnsupported),
+        );
+    }
 }
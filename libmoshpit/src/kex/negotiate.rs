@@ -25,6 +25,9 @@ pub const KEX_ML_KEM_512_SHA256: &str = "ml-kem-512-sha256";
 pub const KEX_ML_KEM_768_SHA256: &str = "ml-kem-768-sha256";
 /// NIST FIPS 203 ML-KEM-1024 with HKDF-SHA256 key extraction
 pub const KEX_ML_KEM_1024_SHA256: &str = "ml-kem-1024-sha256";
+/// Hybrid ML-KEM-768 + X25519 with HKDF-SHA256 over both shared secrets
+/// (post-quantum protection while keeping a classical ECDH safety net)
+pub const KEX_MLKEM768_X25519_SHA256: &str = "mlkem768x25519-sha256";
 /// AES-256-GCM-SIV authenticated encryption (nonce-misuse resistant)
 pub const AEAD_AES256_GCM_SIV: &str = "aes256-gcm-siv";
 /// AES-256-GCM authenticated encryption
@@ -37,6 +40,9 @@ pub const AEAD_AES128_GCM_SIV: &str = "aes128-gcm-siv";
 pub const MAC_HMAC_SHA512: &str = "hmac-sha512";
 /// HMAC-SHA256 packet authentication (32-byte tag, saves 32 B/packet)
 pub const MAC_HMAC_SHA256: &str = "hmac-sha256";
+/// No separate MAC: rely on the AEAD tag, which already covers the ciphertext and
+/// the sequence number (AAD).  Saves the whole HMAC tag on every packet.
+pub const MAC_AEAD_IMPLICIT: &str = "aead-implicit";
 /// HKDF-SHA256 key expansion
 pub const KDF_HKDF_SHA256: &str = "hkdf-sha256";
 /// HKDF-SHA384 key expansion (natural pairing with P-384)
@@ -76,11 +82,12 @@ pub const KDF_HKDF_SHA512: &str = "hkdf-sha512";
 /// ```
 ///
 /// The negotiated version is first available on [`Kex`](crate::Kex) /
-/// [`ServerKex`](crate::ServerKex) once key exchange completes.  To gate the
-/// format of **UDP** frames it must additionally be threaded into the UDP
-/// transport (`UdpSender` / `UdpReader`), which does not receive it today; the
-/// `Kex` value at session setup is the single source to pass down.
-pub const PROTOCOL_VERSION: u16 = 2;
+/// [`ServerKex`](crate::ServerKex) once key exchange completes.  The data-channel
+/// transports (`UdpSender` / `UdpReader` and their TCP counterparts) never see the
+/// version itself: to gate the format of their frames, derive a setting from the
+/// `Kex` value at session setup and pass it down, as is done for
+/// [`NonceScheme`](crate::NonceScheme).
+pub const PROTOCOL_VERSION: u16 = 20;
 
 /// Lowest wire protocol version this build can implement.
 ///
@@ -102,6 +109,13 @@ pub const PROTOCOL_VERSION: u16 = 2;
 /// [`negotiate_protocol_version`].
 pub const MIN_PROTOCOL_VERSION: u16 = 1;
 
+/// First wire protocol version in which a client proves it holds its ECDH
+/// identity private key.
+///
+/// Older clients log in with the public key alone, so servers refuse them by
+/// default; an operator who still needs them lowers `--min-protocol-version`.
+pub const IDENTITY_PROOF_MIN_PROTOCOL_VERSION: u16 = 3;
+
 /// The inclusive range of wire protocol versions an endpoint supports, advertised
 /// in its [`Frame::KexInit`](crate::Frame) frame so the peer can negotiate a
 /// common version.
@@ -197,6 +211,7 @@ impl Default for NegotiatedAlgorithms {
 pub fn supported_algorithms() -> AlgorithmList {
     AlgorithmList {
         kex: vec![
+            KEX_MLKEM768_X25519_SHA256.to_string(),
             KEX_X25519_SHA256.to_string(),
             KEX_ML_KEM_768_SHA256.to_string(),
             KEX_ML_KEM_512_SHA256.to_string(),
@@ -210,7 +225,11 @@ pub fn supported_algorithms() -> AlgorithmList {
             AEAD_CHACHA20_POLY1305.to_string(),
             AEAD_AES128_GCM_SIV.to_string(),
         ],
-        mac: vec![MAC_HMAC_SHA512.to_string(), MAC_HMAC_SHA256.to_string()],
+        mac: vec![
+            MAC_HMAC_SHA512.to_string(),
+            MAC_HMAC_SHA256.to_string(),
+            MAC_AEAD_IMPLICIT.to_string(),
+        ],
         kdf: vec![
             KDF_HKDF_SHA256.to_string(),
             KDF_HKDF_SHA384.to_string(),
@@ -262,10 +281,10 @@ mod tests {
     use super::{
         AEAD_AES128_GCM_SIV, AEAD_AES256_GCM, AEAD_AES256_GCM_SIV, AEAD_CHACHA20_POLY1305,
         AlgorithmList, KDF_HKDF_SHA256, KDF_HKDF_SHA512, KEX_ML_KEM_512_SHA256,
-        KEX_ML_KEM_768_SHA256, KEX_ML_KEM_1024_SHA256, KEX_P256_SHA256, KEX_P384_SHA384,
-        KEX_X25519_SHA256, MAC_HMAC_SHA256, MAC_HMAC_SHA512, MIN_PROTOCOL_VERSION, MoshpitError,
-        PROTOCOL_VERSION, ProtocolSupport, local_protocol_support, negotiate,
-        negotiate_protocol_version, supported_algorithms,
+        KEX_ML_KEM_768_SHA256, KEX_ML_KEM_1024_SHA256, KEX_MLKEM768_X25519_SHA256, KEX_P256_SHA256,
+        KEX_P384_SHA384, KEX_X25519_SHA256, MAC_AEAD_IMPLICIT, MAC_HMAC_SHA256, MAC_HMAC_SHA512,
+        MIN_PROTOCOL_VERSION, MoshpitError, PROTOCOL_VERSION, ProtocolSupport,
+        local_protocol_support, negotiate, negotiate_protocol_version, supported_algorithms,
     };
 
     fn current() -> AlgorithmList {
@@ -277,12 +296,28 @@ mod tests {
         let client = current();
         let server = current();
         let negotiated = negotiate(&client, &server).expect("should succeed with identical lists");
-        assert_eq!(negotiated.kex, KEX_X25519_SHA256);
+        assert_eq!(negotiated.kex, KEX_MLKEM768_X25519_SHA256);
         assert_eq!(negotiated.aead, AEAD_AES256_GCM_SIV);
         assert_eq!(negotiated.mac, MAC_HMAC_SHA512);
         assert_eq!(negotiated.kdf, KDF_HKDF_SHA256);
     }
 
+    #[test]
+    fn default_peers_pick_hybrid_kex_and_fall_back_to_x25519() {
+        let negotiated = negotiate(&supported_algorithms(), &supported_algorithms())
+            .expect("default peers should negotiate");
+        assert_eq!(negotiated.kex, KEX_MLKEM768_X25519_SHA256);
+
+        // A default server still talks to a client that only offers X25519.
+        let classical_client = AlgorithmList {
+            kex: vec![KEX_X25519_SHA256.to_string()],
+            ..supported_algorithms()
+        };
+        let negotiated = negotiate(&classical_client, &supported_algorithms())
+            .expect("classical client should negotiate");
+        assert_eq!(negotiated.kex, KEX_X25519_SHA256);
+    }
+
     #[test]
     fn negotiate_picks_first_common_kex() {
         let client = AlgorithmList {
@@ -395,6 +430,19 @@ mod tests {
         assert_eq!(negotiated.kdf, KDF_HKDF_SHA256);
     }
 
+    #[test]
+    fn negotiate_aead_implicit_only_when_both_sides_allow_it() {
+        let mut client = current();
+        client.mac = vec![MAC_AEAD_IMPLICIT.to_string(), MAC_HMAC_SHA512.to_string()];
+        let negotiated = negotiate(&client, &current()).expect("aead-implicit is supported");
+        assert_eq!(negotiated.mac, MAC_AEAD_IMPLICIT);
+
+        let mut server = current();
+        server.mac = vec![MAC_HMAC_SHA512.to_string()];
+        let negotiated = negotiate(&client, &server).expect("falls back to an HMAC");
+        assert_eq!(negotiated.mac, MAC_HMAC_SHA512);
+    }
+
     #[test]
     fn negotiate_server_preference_order_wins() {
         // Server prefers x448 over x25519; client supports both but prefers x25519.
@@ -515,6 +563,7 @@ mod tests {
     fn supported_algorithms_contains_all_known_algorithms() {
         let algos = supported_algorithms();
         assert!(algos.kex.contains(&KEX_X25519_SHA256.to_string()));
+        assert!(algos.kex.contains(&KEX_MLKEM768_X25519_SHA256.to_string()));
         assert!(algos.kex.contains(&KEX_ML_KEM_512_SHA256.to_string()));
         assert!(algos.kex.contains(&KEX_ML_KEM_768_SHA256.to_string()));
         assert!(algos.kex.contains(&KEX_ML_KEM_1024_SHA256.to_string()));
@@ -526,6 +575,7 @@ mod tests {
         assert!(algos.aead.contains(&AEAD_AES128_GCM_SIV.to_string()));
         assert!(algos.mac.contains(&MAC_HMAC_SHA512.to_string()));
         assert!(algos.mac.contains(&MAC_HMAC_SHA256.to_string()));
+        assert!(algos.mac.contains(&MAC_AEAD_IMPLICIT.to_string()));
         assert!(algos.kdf.contains(&KDF_HKDF_SHA256.to_string()));
         assert!(algos.kdf.contains(&KDF_HKDF_SHA512.to_string()));
     }
@@ -0,0 +1,335 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Session options: what the client asks of its session besides logging in.
+//!
+//! Older clients send them in clear between the key exchange and `Check`.  From
+//! [`IDENTITY_HIDING_MIN_PROTOCOL_VERSION`](crate::IDENTITY_HIDING_MIN_PROTOCOL_VERSION)
+//! they travel inside the `Check` itself, after its fixed [`CHECK_VALUE`], so
+//! they are encrypted and authenticated under the session key: an observer
+//! cannot read them and an attacker cannot change them.
+
+use anyhow::Result;
+use bincode_next::{config::standard, encode_to_vec};
+use tracing::{error, trace};
+
+use crate::{
+    AGENT_FORWARDING_MIN_PROTOCOL_VERSION, FILE_COPY_MIN_PROTOCOL_VERSION, FileCopy, Frame,
+    MoshpitError, NAMED_SESSIONS_MIN_PROTOCOL_VERSION, REMOTE_COMMAND_MIN_PROTOCOL_VERSION,
+    RemoteCommand,
+    frames::decode_frame,
+    session::{SESSION_SHARING_MIN_PROTOCOL_VERSION, SessionRequest},
+    udp::DiffMode,
+};
+
+/// The plaintext every `Check` starts with.
+pub(crate) const CHECK_VALUE: &[u8] = b"Yoda";
+
+/// The `Check` plaintext carrying `options`.
+pub(crate) fn check_plaintext(options: &[Frame]) -> Result<Vec<u8>> {
+    let mut plaintext = CHECK_VALUE.to_vec();
+    plaintext.extend_from_slice(&encode_to_vec(options, standard())?);
+    Ok(plaintext)
+}
+
+/// The options sealed in an opened `Check` plaintext, or `None` when it does
+/// not start with [`CHECK_VALUE`].
+///
+/// # Errors
+/// * If the bytes after the check value do not decode as option frames.
+pub(crate) fn open_check_plaintext(plaintext: &[u8]) -> Result<Option<Vec<Frame>>> {
+    let Some(sealed) = plaintext.strip_prefix(CHECK_VALUE) else {
+        return Ok(None);
+    };
+    if sealed.is_empty() {
+        // Clients before protocol v8 seal nothing after the check value.
+        return Ok(Some(Vec::new()));
+    }
+    decode_frame(sealed).map(Some)
+}
+
+/// The session options one client asked for.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct SessionOptions {
+    /// The negotiated protocol version, which decides the options accepted.
+    protocol_version: u16,
+    /// Diff delivery mode, from `ClientOptions`.
+    pub(crate) diff_mode: DiffMode,
+    /// Environment variables, from `ClientEnv`.
+    pub(crate) env: Vec<(String, String)>,
+    /// Extra `PATH` entries, from `ClientEnv`.
+    pub(crate) extra_path: Vec<String>,
+    /// Whether the client sent `AgentForward`.
+    pub(crate) forward_agent: bool,
+    /// Which session the client asked for, from `SessionRequest`.
+    pub(crate) session_request: Option<SessionRequest>,
+    /// The command to run in place of the login shell, from `RemoteCommand`
+    /// or `FileCopy`.
+    pub(crate) remote_command: Option<RemoteCommand>,
+    /// How many of `ClientOptions`, `ClientEnv`, `AgentForward`,
+    /// `SessionRequest` and `RemoteCommand` (or `FileCopy`) are behind us.
+    stage: u8,
+}
+
+impl SessionOptions {
+    /// No options yet, for a session at `protocol_version`.
+    pub(crate) fn new(protocol_version: u16) -> Self {
+        Self {
+            protocol_version,
+            ..Self::default()
+        }
+    }
+
+    /// Apply one option frame.  Each may be sent at most once, in the order
+    /// `ClientOptions`, `ClientEnv`, `AgentForward`, `SessionRequest`, then
+    /// one of `RemoteCommand` and `FileCopy`.
+    ///
+    /// # Errors
+    /// * [`MoshpitError::InvalidFrame`] for any other frame, one out of order,
+    ///   or one the negotiated protocol version does not know.
+    pub(crate) fn apply(&mut self, frame: Frame) -> Result<()> {
+        match frame {
+            Frame::ClientOptions(mode_byte) if self.stage < 1 => {
+                self.diff_mode = match mode_byte {
+                    1 => {
+                        trace!("server_kex: client requested DiffMode::Datagram");
+                        DiffMode::Datagram
+                    }
+                    2 => {
+                        trace!("server_kex: client requested DiffMode::StateSync");
+                        DiffMode::StateSync
+                    }
+                    other => {
+                        trace!("server_kex: ClientOptions mode_byte={other}, using Reliable");
+                        DiffMode::Reliable
+                    }
+                };
+                self.stage = 1;
+            }
+            Frame::ClientEnv(env, path) if self.stage < 2 => {
+                trace!(
+                    "server_kex: received ClientEnv ({} vars, {} path entries)",
+                    env.len(),
+                    path.len()
+                );
+                self.env = env;
+                self.extra_path = path;
+                self.stage = 2;
+            }
+            Frame::AgentForward
+                if self.stage < 3
+                    && self.protocol_version >= AGENT_FORWARDING_MIN_PROTOCOL_VERSION =>
+            {
+                trace!("server_kex: client requested agent forwarding");
+                self.forward_agent = true;
+                self.stage = 3;
+            }
+            Frame::SessionRequest(kind, name)
+                if self.stage < 4
+                    && self.protocol_version >= NAMED_SESSIONS_MIN_PROTOCOL_VERSION =>
+            {
+                trace!("server_kex: received SessionRequest (kind={kind})");
+                let Some(request) = SessionRequest::from_wire(kind, name).filter(|request| {
+                    !matches!(request, SessionRequest::Join { .. })
+                        || self.protocol_version >= SESSION_SHARING_MIN_PROTOCOL_VERSION
+                }) else {
+                    error!("server_kex: SessionRequest of unknown kind {kind} or without a name");
+                    return Err(MoshpitError::InvalidFrame.into());
+                };
+                self.session_request = Some(request);
+                self.stage = 4;
+            }
+            Frame::RemoteCommand(command, pty)
+                if self.stage < 5
+                    && self.protocol_version >= REMOTE_COMMAND_MIN_PROTOCOL_VERSION =>
+            {
+                trace!("server_kex: client requested a remote command (pty={pty})");
+                self.remote_command = Some(RemoteCommand::new(command, pty));
+                self.stage = 5;
+            }
+            Frame::FileCopy(upload, mut paths, recursive)
+                if self.stage < 5 && self.protocol_version >= FILE_COPY_MIN_PROTOCOL_VERSION =>
+            {
+                trace!("server_kex: client requested a file copy (upload={upload})");
+                let copy = if upload {
+                    if paths.len() != 1 {
+                        error!("server_kex: file upload names {} destinations", paths.len());
+                        return Err(MoshpitError::InvalidFrame.into());
+                    }
+                    FileCopy::Upload {
+                        target: paths.remove(0),
+                    }
+                } else {
+                    FileCopy::Download {
+                        sources: paths,
+                        recursive,
+                    }
+                };
+                self.remote_command = Some(RemoteCommand::copying(copy));
+                self.stage = 5;
+            }
+            other => {
+                error!(
+                    "server_kex: expected ClientOptions, ClientEnv, AgentForward, SessionRequest, RemoteCommand, FileCopy, or Check but got frame id={}",
+                    other.id()
+                );
+                return Err(MoshpitError::InvalidFrame.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::{CHECK_VALUE, SessionOptions, check_plaintext, open_check_plaintext};
+    use crate::{
+        AGENT_FORWARDING_MIN_PROTOCOL_VERSION, FILE_COPY_MIN_PROTOCOL_VERSION, FileCopy, Frame,
+        MoshpitError, NAMED_SESSIONS_MIN_PROTOCOL_VERSION, REMOTE_COMMAND_MIN_PROTOCOL_VERSION,
+        RemoteCommand, SESSION_SHARING_MIN_PROTOCOL_VERSION, SessionRequest, udp::DiffMode,
+    };
+
+    #[test]
+    fn sealed_options_round_trip() -> Result<()> {
+        let options = vec![
+            Frame::ClientOptions(2),
+            Frame::ClientEnv(vec![("LANG".into(), "C".into())], vec![]),
+        ];
+        let plaintext = check_plaintext(&options)?;
+        assert!(plaintext.starts_with(CHECK_VALUE));
+        assert_eq!(open_check_plaintext(&plaintext)?, Some(options));
+        assert_eq!(open_check_plaintext(CHECK_VALUE)?, Some(vec![]));
+        assert_eq!(open_check_plaintext(b"Vader")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn options_apply_in_order_once() -> Result<()> {
+        let mut options = SessionOptions::default();
+        options.apply(Frame::ClientOptions(1))?;
+        options.apply(Frame::ClientEnv(vec![], vec!["/opt/bin".into()]))?;
+        assert_eq!(options.diff_mode, DiffMode::Datagram);
+        assert_eq!(options.extra_path, vec!["/opt/bin".to_string()]);
+        for frame in [Frame::ClientOptions(2), Frame::KexFailure] {
+            assert!(
+                options
+                    .apply(frame)
+                    .expect_err("out-of-order option")
+                    .downcast_ref::<MoshpitError>()
+                    .is_some_and(|e| *e == MoshpitError::InvalidFrame)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn agent_forward_needs_its_protocol_version() -> Result<()> {
+        let mut options = SessionOptions::new(AGENT_FORWARDING_MIN_PROTOCOL_VERSION - 1);
+        assert!(options.apply(Frame::AgentForward).is_err());
+        assert!(!options.forward_agent);
+
+        let mut options = SessionOptions::new(AGENT_FORWARDING_MIN_PROTOCOL_VERSION);
+        options.apply(Frame::ClientOptions(1))?;
+        options.apply(Frame::AgentForward)?;
+        assert!(options.forward_agent);
+        assert!(options.apply(Frame::AgentForward).is_err());
+        assert!(options.apply(Frame::ClientEnv(vec![], vec![])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn remote_command_needs_its_protocol_version() -> Result<()> {
+        let command = || Frame::RemoteCommand("uname -a".to_string(), false);
+        let mut options = SessionOptions::new(REMOTE_COMMAND_MIN_PROTOCOL_VERSION - 1);
+        assert!(options.apply(command()).is_err());
+        assert_eq!(options.remote_command, None);
+
+        let mut options = SessionOptions::new(REMOTE_COMMAND_MIN_PROTOCOL_VERSION);
+        options.apply(Frame::AgentForward)?;
+        options.apply(command())?;
+        assert_eq!(
+            options.remote_command,
+            Some(RemoteCommand::new("uname -a".to_string(), false))
+        );
+        assert!(options.apply(command()).is_err());
+        assert!(options.apply(Frame::AgentForward).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn file_copy_stands_in_for_the_remote_command() -> Result<()> {
+        let download = || Frame::FileCopy(false, vec!["notes".to_string()], true);
+        let mut options = SessionOptions::new(FILE_COPY_MIN_PROTOCOL_VERSION - 1);
+        assert!(options.apply(download()).is_err());
+
+        let mut options = SessionOptions::new(FILE_COPY_MIN_PROTOCOL_VERSION);
+        assert!(options.apply(Frame::FileCopy(true, vec![], false)).is_err());
+        options.apply(download())?;
+        assert_eq!(
+            options.remote_command,
+            Some(RemoteCommand::copying(FileCopy::Download {
+                sources: vec!["notes".to_string()],
+                recursive: true,
+            }))
+        );
+        assert!(
+            options
+                .apply(Frame::RemoteCommand("uptime".to_string(), false))
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn session_request_comes_before_the_command() -> Result<()> {
+        let attach = || Frame::SessionRequest(1, "work".to_string());
+        let mut options = SessionOptions::new(NAMED_SESSIONS_MIN_PROTOCOL_VERSION - 1);
+        assert!(options.apply(attach()).is_err());
+
+        let mut options = SessionOptions::new(NAMED_SESSIONS_MIN_PROTOCOL_VERSION);
+        assert!(
+            options
+                .apply(Frame::SessionRequest(9, String::new()))
+                .is_err()
+        );
+        options.apply(attach())?;
+        assert_eq!(
+            options.session_request,
+            Some(SessionRequest::Attach("work".to_string()))
+        );
+        assert!(
+            options
+                .apply(Frame::SessionRequest(2, String::new()))
+                .is_err()
+        );
+        options.apply(Frame::RemoteCommand("uptime".to_string(), false))?;
+        assert!(options.apply(attach()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn joining_needs_session_sharing() -> Result<()> {
+        let watch = || Frame::SessionRequest(4, "alice/work".to_string());
+        let mut options = SessionOptions::new(SESSION_SHARING_MIN_PROTOCOL_VERSION - 1);
+        assert!(options.apply(watch()).is_err());
+
+        let mut options = SessionOptions::new(SESSION_SHARING_MIN_PROTOCOL_VERSION);
+        options.apply(watch())?;
+        assert_eq!(
+            options.session_request,
+            Some(SessionRequest::Join {
+                owner: Some("alice".to_string()),
+                name: "work".to_string(),
+                read_only: true,
+            })
+        );
+        Ok(())
+    }
+}
@@ -30,6 +30,7 @@ use aws_lc_rs::{
     agreement::{
         ECDH_P256, ECDH_P384, ParsedPublicKey, PrivateKey, UnparsedPublicKey, X25519, agree,
     },
+    constant_time::verify_slices_are_equal,
     error::Unspecified,
     hkdf::{HKDF_SHA256, HKDF_SHA384, HKDF_SHA512, Salt},
     kem::{
@@ -40,10 +41,7 @@ use aws_lc_rs::{
 };
 use base64::{Engine as _, engine::general_purpose::STANDARD};
 use bon::Builder;
-#[cfg(feature = "unstable")]
-use bytes::Buf as _;
-#[cfg(feature = "unstable")]
-use bytes::BytesMut;
+use bytes::{Buf as _, BytesMut};
 use socket2::SockRef;
 use tokio::{
     net::{TcpListener, UdpSocket},
@@ -55,24 +53,48 @@ use uuid::Uuid;
 
 use crate::kex::HostKeyMismatchFn;
 use crate::{
-    ConnectionReader, ConnectionWriter, Frame, KexEvent, MoshpitError, NegotiatedTransport,
-    ServerKex, UuidWrapper,
+    AGENT_FORWARDING_MIN_PROTOCOL_VERSION, ConnectionReader, ConnectionWriter,
+    FILE_COPY_MIN_PROTOCOL_VERSION, FileCopy, Frame, KEY_ALGORITHM_P256, KEY_ALGORITHM_P384,
+    KEY_ALGORITHM_X25519, KexEvent, MoshpitError, NAMED_SESSIONS_MIN_PROTOCOL_VERSION,
+    NegotiatedTransport, REMOTE_COMMAND_MIN_PROTOCOL_VERSION, RemoteCommand, ServerKex,
+    UuidWrapper,
     kex::TofuFn,
+    kex::early::EARLY_KEY_SHARE_MIN_PROTOCOL_VERSION,
+    kex::failure::{KEX_FAILURE_REASON_MIN_PROTOCOL_VERSION, KexFailureReason},
+    kex::identity::{
+        IDENTITY_HIDING_MIN_PROTOCOL_VERSION, handshake_key, host_key_algorithm, host_proof,
+        open_identity, seal_identity,
+    },
     kex::negotiate::{
         AEAD_AES128_GCM_SIV, AEAD_AES256_GCM, AEAD_AES256_GCM_SIV, AEAD_CHACHA20_POLY1305,
-        AlgorithmList, KDF_HKDF_SHA256, KDF_HKDF_SHA384, KDF_HKDF_SHA512, KEX_ML_KEM_512_SHA256,
-        KEX_ML_KEM_768_SHA256, KEX_ML_KEM_1024_SHA256, KEX_P256_SHA256, KEX_P384_SHA384,
-        KEX_X25519_SHA256, MAC_HMAC_SHA256, MAC_HMAC_SHA512, NegotiatedAlgorithms, ProtocolSupport,
+        AlgorithmList, IDENTITY_PROOF_MIN_PROTOCOL_VERSION, KDF_HKDF_SHA256, KDF_HKDF_SHA384,
+        KDF_HKDF_SHA512, KEX_ML_KEM_512_SHA256, KEX_ML_KEM_768_SHA256, KEX_ML_KEM_1024_SHA256,
+        KEX_MLKEM768_X25519_SHA256, KEX_P256_SHA256, KEX_P384_SHA384, KEX_X25519_SHA256,
+        MAC_AEAD_IMPLICIT, MAC_HMAC_SHA256, MAC_HMAC_SHA512, NegotiatedAlgorithms, ProtocolSupport,
         local_protocol_support, negotiate, negotiate_protocol_version, supported_algorithms,
     },
-    load_public_key,
-    session::SessionRegistry,
+    kex::options::{CHECK_VALUE, SessionOptions, check_plaintext, open_check_plaintext},
+    kex::ticket::{
+        RESUMPTION_TICKET_LIFETIME, RESUMPTION_TICKET_MIN_PROTOCOL_VERSION, ResumptionTicket,
+        TicketIssuer, resumption_psk,
+    },
+    kex::transcript::Transcript,
+    load_identity_key, load_public_key,
+    session::{
+        SHARED_SESSIONS_FILE, SessionAccess, SessionEntry, SessionRegistry, SessionRequest,
+        session_access,
+    },
     udp::{DiffMode, TransportMode},
 };
 #[cfg(feature = "unstable")]
-use crate::{KEY_ALGORITHM_ML_DSA_44, KEY_ALGORITHM_ML_DSA_65, KEY_ALGORITHM_ML_DSA_87};
+use crate::{
+    KEY_ALGORITHM_ML_DSA_44, KEY_ALGORITHM_ML_DSA_65, KEY_ALGORITHM_ML_DSA_87,
+    kex::identity::host_proof_message,
+};
 
 const AEAD_KEY_INFO: &[u8] = b"AEAD KEY";
+/// Length of an X25519 public key, the trailing component of hybrid exchange values.
+const X25519_PUBLIC_KEY_LEN: usize = 32;
 const HMAC_KEY_INFO: &[u8] = b"HMAC KEY";
 
 fn fmt_hex(bytes: &[u8]) -> String {
@@ -85,14 +107,30 @@ fn fmt_hex(bytes: &[u8]) -> String {
         })
 }
 
+#[derive(Clone, Copy)]
 enum ResolvedKexAlgorithm {
     Dh(&'static aws_lc_rs::agreement::Algorithm),
     Kem(&'static KemAlgorithm),
+    /// ML-KEM combined with X25519.  Exchange values on the wire are the KEM
+    /// encapsulation key (or ciphertext) followed by the X25519 public key.
+    Hybrid(&'static KemAlgorithm),
 }
 
 enum ClientEphemeral {
     Dh(PrivateKey),
     Kem(DecapsulationKey),
+    Hybrid(DecapsulationKey, PrivateKey),
+}
+
+/// A key share the client sent with its `KexInit`, before it knew which key
+/// exchange the server would pick.
+struct EarlyKeyShare {
+    /// The key exchange the share was generated for.
+    kex: String,
+    ephemeral: ClientEphemeral,
+    exchange: Vec<u8>,
+    /// The frame as sent; it joins the transcript after the server's `KexInit`.
+    frame: Frame,
 }
 
 #[cfg(feature = "unstable")]
@@ -104,6 +142,9 @@ struct IdentityProofContext<'a> {
     salt: &'a [u8],
     negotiated: &'a NegotiatedAlgorithms,
     public_key_path: &'a PathBuf,
+    /// The `IdentityProof` signature when it arrived inside `SealedIdentity`;
+    /// otherwise it is read from the connection.
+    signature: Option<Vec<u8>>,
 }
 
 #[cfg(feature = "unstable")]
@@ -126,11 +167,105 @@ fn resolve_kex_alg(kex: &str) -> Result<ResolvedKexAlgorithm> {
         KEX_ML_KEM_512_SHA256 => Ok(ResolvedKexAlgorithm::Kem(&ML_KEM_512)),
         KEX_ML_KEM_768_SHA256 => Ok(ResolvedKexAlgorithm::Kem(&ML_KEM_768)),
         KEX_ML_KEM_1024_SHA256 => Ok(ResolvedKexAlgorithm::Kem(&ML_KEM_1024)),
+        KEX_MLKEM768_X25519_SHA256 => Ok(ResolvedKexAlgorithm::Hybrid(&ML_KEM_768)),
         _ => Err(MoshpitError::NoCommonAlgorithm.into()),
     }
 }
 
-fn resolve_hkdf_alg(kdf: &str) -> Result<aws_lc_rs::hkdf::Algorithm> {
+/// Generate the client's ephemeral key for `kex_alg`.
+///
+/// Returns the private half and the exchange value to send to the server.
+fn generate_ephemeral(kex_alg: ResolvedKexAlgorithm) -> Result<(ClientEphemeral, Vec<u8>)> {
+    match kex_alg {
+        ResolvedKexAlgorithm::Dh(agreement_alg) => {
+            let epk = PrivateKey::generate(agreement_alg)?;
+            let epk_pub = epk.compute_public_key()?;
+            Ok((ClientEphemeral::Dh(epk), epk_pub.as_ref().to_vec()))
+        }
+        ResolvedKexAlgorithm::Kem(kem_algorithm) => {
+            let decapsulation_key = DecapsulationKey::generate(kem_algorithm)?;
+            let encapsulation_key = decapsulation_key.encapsulation_key()?;
+            let encapsulation_key_bytes = encapsulation_key.key_bytes()?;
+            Ok((
+                ClientEphemeral::Kem(decapsulation_key),
+                encapsulation_key_bytes.as_ref().to_vec(),
+            ))
+        }
+        ResolvedKexAlgorithm::Hybrid(kem_algorithm) => {
+            let decapsulation_key = DecapsulationKey::generate(kem_algorithm)?;
+            let encapsulation_key = decapsulation_key.encapsulation_key()?;
+            let encapsulation_key_bytes = encapsulation_key.key_bytes()?;
+            let epk = PrivateKey::generate(&X25519)?;
+            let epk_pub = epk.compute_public_key()?;
+            let mut exchange = encapsulation_key_bytes.as_ref().to_vec();
+            exchange.extend_from_slice(epk_pub.as_ref());
+            Ok((ClientEphemeral::Hybrid(decapsulation_key, epk), exchange))
+        }
+    }
+}
+
+/// Split a hybrid exchange value into its `(kem, x25519)` components.
+fn split_hybrid_exchange(value: &[u8]) -> Result<(&[u8], &[u8])> {
+    let kem_len = value
+        .len()
+        .checked_sub(X25519_PUBLIC_KEY_LEN)
+        .filter(|len| *len > 0)
+        .ok_or(MoshpitError::KeyNotEstablished)?;
+    Ok(value.split_at(kem_len))
+}
+
+/// Server half of the hybrid exchange: encapsulate to the client's ML-KEM key and
+/// agree a fresh X25519 ephemeral with the client's X25519 key.
+///
+/// Returns `(ciphertext || server_x25519_public, kem_secret || x25519_secret)`.
+fn hybrid_server_exchange(
+    kem_algorithm: &'static KemAlgorithm,
+    client_exchange: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let (kem_pk, dh_pk) = split_hybrid_exchange(client_exchange)?;
+    let encapsulation_key = EncapsulationKey::new(kem_algorithm, kem_pk)?;
+    let (ciphertext, kem_secret) = encapsulation_key.encapsulate()?;
+    let ephemeral_priv = PrivateKey::generate(&X25519)?;
+    let ephemeral_pub = ephemeral_priv.compute_public_key()?;
+    let dh_secret = agree(
+        &ephemeral_priv,
+        UnparsedPublicKey::new(&X25519, dh_pk),
+        Unspecified,
+        |key_material| Ok(key_material.to_vec()),
+    )?;
+
+    let mut server_exchange = ciphertext.as_ref().to_vec();
+    server_exchange.extend_from_slice(ephemeral_pub.as_ref());
+    let mut shared_secret = kem_secret.as_ref().to_vec();
+    shared_secret.extend_from_slice(&dh_secret);
+    Ok((server_exchange, shared_secret))
+}
+
+/// Client half of the hybrid exchange: decapsulate the server's ciphertext and
+/// agree with the server's X25519 ephemeral.
+///
+/// Returns `kem_secret || x25519_secret`, matching [`hybrid_server_exchange`].
+fn hybrid_client_secret(
+    decapsulation_key: &DecapsulationKey,
+    ephemeral_key: &PrivateKey,
+    server_exchange: &[u8],
+) -> Result<Vec<u8>> {
+    let (ciphertext, dh_pk) = split_hybrid_exchange(server_exchange)?;
+    let mut shared_secret = decapsulation_key
+        .decapsulate(Ciphertext::from(ciphertext))?
+        .as_ref()
+        .to_vec();
+    let dh_secret = agree(
+        ephemeral_key,
+        UnparsedPublicKey::new(&X25519, dh_pk),
+        Unspecified,
+        |key_material| Ok(key_material.to_vec()),
+    )?;
+    shared_secret.extend_from_slice(&dh_secret);
+    Ok(shared_secret)
+}
+
+pub(crate) fn resolve_hkdf_alg(kdf: &str) -> Result<aws_lc_rs::hkdf::Algorithm> {
     match kdf {
         KDF_HKDF_SHA256 => Ok(HKDF_SHA256),
         KDF_HKDF_SHA384 => Ok(HKDF_SHA384),
@@ -139,7 +274,7 @@ fn resolve_hkdf_alg(kdf: &str) -> Result<aws_lc_rs::hkdf::Algorithm> {
     }
 }
 
-fn resolve_aead_alg(aead: &str) -> Result<&'static aws_lc_rs::aead::Algorithm> {
+pub(crate) fn resolve_aead_alg(aead: &str) -> Result<&'static aws_lc_rs::aead::Algorithm> {
     match aead {
         AEAD_AES256_GCM_SIV => Ok(&AES_256_GCM_SIV),
         AEAD_AES256_GCM => Ok(&AES_256_GCM),
@@ -149,15 +284,76 @@ fn resolve_aead_alg(aead: &str) -> Result<&'static aws_lc_rs::aead::Algorithm> {
     }
 }
 
-fn resolve_hmac_key_type(mac: &str) -> Result<aws_lc_rs::hmac::Algorithm> {
+/// The HMAC algorithm for `mac`, or `None` for [`MAC_AEAD_IMPLICIT`], which has no HMAC key.
+fn resolve_hmac_key_type(mac: &str) -> Result<Option<aws_lc_rs::hmac::Algorithm>> {
     match mac {
-        MAC_HMAC_SHA512 => Ok(HKDF_SHA512.hmac_algorithm()),
-        MAC_HMAC_SHA256 => Ok(HKDF_SHA256.hmac_algorithm()),
+        MAC_HMAC_SHA512 => Ok(Some(HKDF_SHA512.hmac_algorithm())),
+        MAC_HMAC_SHA256 => Ok(Some(HKDF_SHA256.hmac_algorithm())),
+        MAC_AEAD_IMPLICIT => Ok(None),
         _ => Err(MoshpitError::NoCommonAlgorithm.into()),
     }
 }
 
-fn derive_session_keys(
+fn resolve_identity_agreement_alg(
+    key_alg: &str,
+) -> Option<&'static aws_lc_rs::agreement::Algorithm> {
+    match key_alg {
+        KEY_ALGORITHM_X25519 => Some(&X25519),
+        KEY_ALGORITHM_P256 => Some(&ECDH_P256),
+        KEY_ALGORITHM_P384 => Some(&ECDH_P384),
+        _ => None,
+    }
+}
+
+/// Generate a fresh ephemeral key on the client identity's curve and agree it
+/// with the client's static identity public key.
+///
+/// Returns `(challenge_public_key, identity_secret)`.
+fn issue_identity_challenge(
+    agreement_alg: &'static aws_lc_rs::agreement::Algorithm,
+    identity_public_key: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let challenge_priv = PrivateKey::generate(agreement_alg)?;
+    let challenge_pub = challenge_priv.compute_public_key()?;
+    let identity_public_key = UnparsedPublicKey::new(agreement_alg, identity_public_key);
+    let identity_secret = agree(
+        &challenge_priv,
+        identity_public_key,
+        Unspecified,
+        |key_material| Ok(key_material.to_vec()),
+    )?;
+    Ok((challenge_pub.as_ref().to_vec(), identity_secret))
+}
+
+/// Answer an identity challenge with a locally held identity private key.
+fn answer_identity_challenge(
+    key_alg: &str,
+    private_key: &[u8],
+    challenge: &[u8],
+) -> Result<Vec<u8>> {
+    let agreement_alg =
+        resolve_identity_agreement_alg(key_alg).ok_or(MoshpitError::InvalidKeyHeader)?;
+    let private_key = PrivateKey::from_private_key(agreement_alg, private_key)?;
+    let challenge = UnparsedPublicKey::new(agreement_alg, challenge);
+    Ok(agree(
+        &private_key,
+        challenge,
+        Unspecified,
+        |key_material| Ok(key_material.to_vec()),
+    )?)
+}
+
+/// HKDF input keying material: the ephemeral exchange secret followed by the
+/// identity proof-of-possession secret, when one was exchanged.
+fn session_ikm(shared_secret: &[u8], identity_secret: Option<&[u8]>) -> Vec<u8> {
+    let mut ikm = shared_secret.to_vec();
+    if let Some(identity_secret) = identity_secret {
+        ikm.extend_from_slice(identity_secret);
+    }
+    ikm
+}
+
+pub(crate) fn derive_session_keys(
     shared_secret: &[u8],
     salt_bytes: &[u8],
     negotiated: &NegotiatedAlgorithms,
@@ -172,10 +368,14 @@ fn derive_session_keys(
     let mut key_bytes = vec![0u8; aead_alg.key_len()];
     okm_aead.fill(&mut key_bytes)?;
 
-    let mac_key_len = hmac_key_type.digest_algorithm().output_len();
-    let okm_hmac = prk.expand(&[HMAC_KEY_INFO], hmac_key_type)?;
-    let mut hmac_key_bytes = vec![0u8; mac_key_len];
-    okm_hmac.fill(&mut hmac_key_bytes)?;
+    // `aead-implicit` derives no HMAC key: the AEAD tag alone authenticates packets.
+    let mut hmac_key_bytes = Vec::new();
+    if let Some(hmac_key_type) = hmac_key_type {
+        let mac_key_len = hmac_key_type.digest_algorithm().output_len();
+        let okm_hmac = prk.expand(&[HMAC_KEY_INFO], hmac_key_type)?;
+        hmac_key_bytes.resize(mac_key_len, 0);
+        okm_hmac.fill(&mut hmac_key_bytes)?;
+    }
 
     Ok((key_bytes, hmac_key_bytes))
 }
@@ -214,7 +414,7 @@ fn is_ml_dsa_algorithm(key_alg: &str) -> bool {
     )
 }
 
-/// Parse an SSH-format ML-DSA public key blob into `(algorithm, key_bytes)`.
+/// Parse an SSH-format public key blob into `(algorithm, key_bytes)`.
 ///
 /// Splits on whitespace, base64-decodes the key part, then walks two
 /// length-prefixed fields (algorithm name, public key). Exposed
@@ -223,7 +423,6 @@ fn is_ml_dsa_algorithm(key_alg: &str) -> bool {
 ///
 /// # Errors
 /// If the blob is not a valid SSH-format public key.
-#[cfg(feature = "unstable")]
 #[doc(hidden)]
 pub fn parse_full_public_key(full_public_key: &[u8]) -> Result<(String, Vec<u8>)> {
     let pub_key_str = String::from_utf8_lossy(full_public_key);
@@ -292,6 +491,7 @@ fn verify_identity_transcript(
 
 /// The key exchange reader for the moshpit
 #[derive(Builder)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct KexReader {
     /// The connection reader
     reader: ConnectionReader,
@@ -301,8 +501,18 @@ pub struct KexReader {
     tx_event: UnboundedSender<KexEvent>,
     /// The session UUID the client is requesting to resume (None for fresh connections).
     requested_session_uuid: Option<Uuid>,
+    /// Ticket to redeem for `requested_session_uuid` instead of running the
+    /// asymmetric exchange (client mode only).
+    resumption_ticket: Option<ResumptionTicket>,
+    /// Seals and redeems resumption tickets (server mode only); `None` disables
+    /// them.
+    ticket_issuer: Option<TicketIssuer>,
     /// The server destination hostname or IP
     server_destination: Option<String>,
+    /// The `known_hosts` keys older releases used for this server, consulted
+    /// when `server_destination` has no entry.
+    #[builder(default)]
+    legacy_server_destinations: Vec<String>,
     /// The callback for TOFU interactive prompt
     tofu_fn: Option<TofuFn>,
     /// Callback for known-host key mismatch replacement prompt.
@@ -336,11 +546,10 @@ pub struct KexReader {
     #[builder(default)]
     full_public_key_bytes: Vec<u8>,
     /// Long-term identity key algorithm string (client mode only).
-    #[cfg(feature = "unstable")]
     #[builder(default)]
     client_identity_key_algorithm: String,
-    /// Long-term identity private key bytes for optional transcript proofs.
-    #[cfg(feature = "unstable")]
+    /// Long-term identity private key bytes for identity proofs (client mode
+    /// only; empty when the agent holds the key).
     #[builder(default)]
     client_identity_private_key: Vec<u8>,
     /// Environment variable pairs to send to the server via `ClientEnv` (client mode only).
@@ -361,11 +570,52 @@ pub struct KexReader {
     /// Defaults to `Udp` (current behavior, no change).
     #[builder(default)]
     transport_preference: TransportMode,
+    /// Whether to send a `Frame::EarlyKeyShare` for the first of
+    /// `client_algos.kex` right behind the `KexInit` (client mode only).
+    /// Defaults to `false`.
+    #[builder(default)]
+    early_key_share: bool,
+    /// Whether to ask the server to forward the agent with a
+    /// `Frame::AgentForward` (client mode only).  Defaults to `false`.
+    #[builder(default)]
+    forward_agent: bool,
+    /// The command to run in place of a login shell, sent as a
+    /// `Frame::RemoteCommand` or `Frame::FileCopy` (client mode only).
+    remote_command: Option<RemoteCommand>,
+    /// The named session to start or resume, or the request to list sessions,
+    /// sent as a `Frame::SessionRequest` (client mode only).
+    session_request: Option<SessionRequest>,
     /// Whether this server is willing to serve data over TCP (server mode only).
     /// When `true` and the client requests TCP, the server binds a TCP data port instead
     /// of a UDP port.  Defaults to `false`.
     #[builder(default)]
     allow_tcp_transport: bool,
+    /// Whether rejections tell the client exactly which account check failed
+    /// (server mode only).  When `false` those reasons are sent as
+    /// [`KexFailureReason::AuthenticationFailed`] so a client cannot probe which
+    /// users exist.  Defaults to `false`.
+    #[builder(default)]
+    detailed_auth_failures: bool,
+    /// Whether the peer's advertised protocol range lets it parse
+    /// `Frame::KexFailureReason` (server mode only; set on receipt of `KexInit`).
+    #[builder(skip)]
+    peer_reads_failure_reasons: bool,
+    /// Why the client will be rejected when its `Check` arrives (server mode,
+    /// protocol v8+ only), so that failed logins look the same on the wire.
+    #[builder(skip)]
+    pending_rejection: Option<KexFailureReason>,
+    /// PSK for the ticket that resumes this session, derived alongside the
+    /// session keys from [`RESUMPTION_TICKET_MIN_PROTOCOL_VERSION`].
+    #[builder(skip)]
+    resumption_psk: Option<Vec<u8>>,
+    /// Client identity key that authenticated this handshake (server mode
+    /// only), sealed into the ticket so a resume re-checks `authorized_keys`.
+    #[builder(skip)]
+    client_identity: Vec<u8>,
+    /// Running hash of the handshake frames exchanged so far, mixed into the
+    /// session keys from [`TRANSCRIPT_MIN_PROTOCOL_VERSION`](crate::TRANSCRIPT_MIN_PROTOCOL_VERSION).
+    #[builder(skip)]
+    transcript: Transcript,
 }
 
 impl Debug for KexReader {
@@ -376,7 +626,13 @@ impl Debug for KexReader {
             .field("tx", &self.tx)
             .field("tx_event", &self.tx_event)
             .field("requested_session_uuid", &self.requested_session_uuid)
+            .field("resumption_ticket", &self.resumption_ticket)
+            .field("ticket_issuer", &self.ticket_issuer)
             .field("server_destination", &self.server_destination)
+            .field(
+                "legacy_server_destinations",
+                &self.legacy_server_destinations,
+            )
             .field(
                 "tofu_fn",
                 &if self.tofu_fn.is_some() {
@@ -400,19 +656,29 @@ impl Debug for KexReader {
             .field("user", &self.user)
             .field("full_public_key_bytes", &"<redacted>")
             .field("send_env", &"<redacted>")
-            .field("send_path", &self.send_path);
-        #[cfg(feature = "unstable")]
-        let _ = debug
+            .field("send_path", &self.send_path)
             .field(
                 "client_identity_key_algorithm",
                 &self.client_identity_key_algorithm,
             )
-            .field("client_identity_private_key", &"<redacted>");
-        let _ = debug
+            .field("client_identity_private_key", &"<redacted>")
             .field("agent_socket", &self.agent_socket)
             .field("agent_fingerprint", &self.agent_fingerprint)
             .field("transport_preference", &self.transport_preference)
-            .field("allow_tcp_transport", &self.allow_tcp_transport);
+            .field("early_key_share", &self.early_key_share)
+            .field("forward_agent", &self.forward_agent)
+            .field("remote_command", &self.remote_command)
+            .field("session_request", &self.session_request)
+            .field("allow_tcp_transport", &self.allow_tcp_transport)
+            .field("detailed_auth_failures", &self.detailed_auth_failures)
+            .field(
+                "peer_reads_failure_reasons",
+                &self.peer_reads_failure_reasons,
+            )
+            .field("pending_rejection", &self.pending_rejection)
+            .field("resumption_psk", &"<redacted>")
+            .field("client_identity", &"<redacted>")
+            .field("transcript", &self.transcript);
         debug.finish()
     }
 }
@@ -424,8 +690,26 @@ impl KexReader {
     ///
     #[cfg_attr(nightly, allow(clippy::too_many_lines))]
     pub async fn client_kex(&mut self) -> Result<()> {
+        // `run_client_kex` sent our KexInit before this reader started; it opens
+        // the transcript.
+        self.transcript.absorb(&Frame::KexInit(
+            self.client_algos.clone(),
+            self.protocol_support,
+        ))?;
+        // Protocol v10+: guess the key exchange and send its share now.  A ticket
+        // resume needs no key share.
+        let early_key_share = if self.early_key_share
+            && self.resumption_ticket.is_none()
+            && self.protocol_support.max >= EARLY_KEY_SHARE_MIN_PROTOCOL_VERSION
+        {
+            self.send_early_key_share()?
+        } else {
+            None
+        };
         trace!("client_kex: waiting for KexInit from server");
-        let negotiated = match self.reader.read_frame().await? {
+        let server_init = self.reader.read_frame().await?;
+        self.absorb_received(server_init.as_ref())?;
+        let negotiated = match server_init {
             Some(Frame::KexInit(server_algos, server_proto)) => {
                 trace!(
                     "client_kex: KexInit received — server offered: kex={:?} aead={:?} mac={:?} kdf={:?} proto={}-{}",
@@ -489,6 +773,7 @@ impl KexReader {
                 drop(self.tx_event.send(KexEvent::NoCommonAlgorithm));
                 return Err(MoshpitError::NoCommonAlgorithm.into());
             }
+            Some(Frame::KexFailureReason(reason)) => return Err(self.rejected(reason)),
             None => {
                 error!("client_kex: server closed connection before sending KexInit");
                 drop(self.tx_event.send(KexEvent::Failure));
@@ -504,11 +789,22 @@ impl KexReader {
             }
         };
 
+        if let Some(share) = &early_key_share {
+            if negotiated.protocol_version < EARLY_KEY_SHARE_MIN_PROTOCOL_VERSION {
+                error!(
+                    "client_kex: server negotiated v{}, which cannot read the early key share",
+                    negotiated.protocol_version
+                );
+                drop(self.tx_event.send(KexEvent::EarlyKeyShareUnsupported));
+                return Err(MoshpitError::EarlyKeyShareUnsupported.into());
+            }
+            // The server read the share after sending its KexInit.
+            self.transcript.absorb(&share.frame)?;
+        }
+
         // Resolve algorithms from the negotiated names before generating the EPK.
         let kex_alg = resolve_kex_alg(&negotiated.kex)?;
-        let aead_alg = resolve_aead_alg(&negotiated.aead)?;
-        let kex_aead_log = negotiated.aead.clone();
-        let kex_mac_log = negotiated.mac.clone();
+        let _ = resolve_aead_alg(&negotiated.aead)?;
 
         // Emit the negotiated algorithm set so the runtime can construct the right crypto
         // primitives when it later receives KeyMaterial and HMACKeyMaterial.
@@ -519,14 +815,16 @@ impl KexReader {
 
         // Protocol v2+: exchange transport preference before Initialize so both sides
         // know whether to use TCP or UDP for the data channel.
+        // The early key share already carried the preference.
         let negotiated_transport = if negotiated.protocol_version >= 2 {
-            let pref_byte = match self.transport_preference {
-                TransportMode::Tcp => 1u8,
-                TransportMode::Udp => 0u8,
-            };
-            self.tx.send(Frame::TransportPreference(pref_byte))?;
-            trace!("client_kex: sent TransportPreference({pref_byte}), awaiting server echo");
-            match self.reader.read_frame().await? {
+            if early_key_share.is_none() {
+                let pref_byte = self.transport_preference_byte();
+                self.send_absorbed(Frame::TransportPreference(pref_byte))?;
+                trace!("client_kex: sent TransportPreference({pref_byte}), awaiting server echo");
+            }
+            let echo = self.reader.read_frame().await?;
+            self.absorb_received(echo.as_ref())?;
+            match echo {
                 Some(Frame::TransportPreference(1))
                     if self.transport_preference == TransportMode::Tcp =>
                 {
@@ -561,47 +859,62 @@ impl KexReader {
                 .send(KexEvent::TransportMode(negotiated_transport)),
         );
 
-        let (client_ephemeral, epk_pub_bytes) = match kex_alg {
-            ResolvedKexAlgorithm::Dh(agreement_alg) => {
-                let epk = PrivateKey::generate(agreement_alg)?;
-                let epk_pub = epk.compute_public_key()?;
-                (ClientEphemeral::Dh(epk), epk_pub.as_ref().to_vec())
+        if let Some(ticket) = self.resumption_ticket.take() {
+            if negotiated.protocol_version < RESUMPTION_TICKET_MIN_PROTOCOL_VERSION {
+                error!(
+                    "client_kex: negotiated v{} predates resumption tickets",
+                    negotiated.protocol_version
+                );
+                return Err(self.rejected(KexFailureReason::TicketRejected));
             }
-            ResolvedKexAlgorithm::Kem(kem_algorithm) => {
-                let decapsulation_key = DecapsulationKey::generate(kem_algorithm)?;
-                let encapsulation_key = decapsulation_key.encapsulation_key()?;
-                let encapsulation_key_bytes = encapsulation_key.key_bytes()?;
-                (
-                    ClientEphemeral::Kem(decapsulation_key),
-                    encapsulation_key_bytes.as_ref().to_vec(),
-                )
+            self.resume_with_ticket(&ticket, &negotiated)?;
+            return self.finish_client_kex().await;
+        }
+
+        // A share for the negotiated key exchange is already with the server,
+        // which answers it with PeerInitialize; otherwise send a fresh one.
+        let (client_ephemeral, epk_pub_bytes, share_accepted) = match early_key_share {
+            Some(share) if share.kex == negotiated.kex => {
+                trace!("client_kex: server accepted the early key share");
+                (share.ephemeral, share.exchange, true)
+            }
+            Some(share) => {
+                trace!(
+                    "client_kex: server negotiated {} instead of {}, sending a new key share",
+                    negotiated.kex, share.kex
+                );
+                let (ephemeral, exchange) = generate_ephemeral(kex_alg)?;
+                (ephemeral, exchange, false)
+            }
+            None => {
+                let (ephemeral, exchange) = generate_ephemeral(kex_alg)?;
+                (ephemeral, exchange, false)
             }
         };
 
-        // Send Initialize or ResumeRequest with our ephemeral public key + identity key.
-        let user_bytes = self.user.as_bytes().to_vec();
-        let identity_pk = self.full_public_key_bytes.clone();
         #[cfg(feature = "unstable")]
-        let transcript_user = user_bytes.clone();
+        let transcript_user = self.user.as_bytes().to_vec();
         #[cfg(feature = "unstable")]
         let transcript_client_exchange = epk_pub_bytes.clone();
-        if let Some(session_uuid) = self.requested_session_uuid {
-            self.tx.send(Frame::ResumeRequest(
-                UuidWrapper::new(session_uuid),
-                user_bytes,
-                epk_pub_bytes.clone(),
-                identity_pk,
-            ))?;
+        // Send Initialize or ResumeRequest with our ephemeral public key + identity key.
+        // Protocol v8+: send only the ephemeral key now; the user and identity key
+        // follow sealed under the handshake key once the server has answered.
+        let hidden_identity = if negotiated.protocol_version >= IDENTITY_HIDING_MIN_PROTOCOL_VERSION
+        {
+            if !share_accepted {
+                self.send_absorbed(Frame::HiddenInitialize(epk_pub_bytes))?;
+            }
+            Some(self.identity_request(vec![]))
         } else {
-            self.tx.send(Frame::Initialize(
-                user_bytes,
-                epk_pub_bytes.clone(),
-                identity_pk,
-            ))?;
-        }
+            let request = self.identity_request(epk_pub_bytes);
+            self.send_absorbed(request)?;
+            None
+        };
 
         trace!("client_kex: waiting for PeerInitialize");
-        match self.reader.read_frame().await? {
+        let peer_init = self.reader.read_frame().await?;
+        self.absorb_received(peer_init.as_ref())?;
+        match peer_init {
             Some(Frame::KexFailure) => {
                 error!(
                     "client_kex: server rejected key exchange (KexFailure before PeerInitialize)"
@@ -609,6 +922,7 @@ impl KexReader {
                 drop(self.tx_event.send(KexEvent::NoCommonAlgorithm));
                 return Err(MoshpitError::NoCommonAlgorithm.into());
             }
+            Some(Frame::KexFailureReason(reason)) => return Err(self.rejected(reason)),
             None => {
                 error!("client_kex: server closed connection before sending PeerInitialize");
                 return Err(anyhow::anyhow!(
@@ -626,6 +940,7 @@ impl KexReader {
                     trace!("client_kex: checking known_hosts for host '{host}'");
                     match check_known_hosts(
                         host,
+                        &self.legacy_server_destinations,
                         &identity_pk,
                         self.tofu_fn.as_ref(),
                         self.host_key_mismatch_fn.as_ref(),
@@ -648,8 +963,46 @@ impl KexReader {
                     trace!("client_kex: no server_destination set, skipping host-key check");
                 }
 
+                let shared_secret = match client_ephemeral {
+                    ClientEphemeral::Dh(epk) => {
+                        let ResolvedKexAlgorithm::Dh(agreement_alg) =
+                            resolve_kex_alg(&negotiated.kex)?
+                        else {
+                            return Err(MoshpitError::NoCommonAlgorithm.into());
+                        };
+                        let peer_public_key = UnparsedPublicKey::new(agreement_alg, &ephemeral_pk);
+                        trace!("client_kex: running ECDH agree()");
+                        agree(&epk, peer_public_key, Unspecified, |key_material| {
+                            Ok(key_material.to_vec())
+                        })?
+                    }
+                    ClientEphemeral::Kem(decapsulation_key) => {
+                        trace!("client_kex: running ML-KEM decapsulate()");
+                        decapsulation_key
+                            .decapsulate(Ciphertext::from(ephemeral_pk.as_slice()))?
+                            .as_ref()
+                            .to_vec()
+                    }
+                    ClientEphemeral::Hybrid(decapsulation_key, epk) => {
+                        trace!("client_kex: running hybrid ML-KEM decapsulate() + X25519 agree()");
+                        hybrid_client_secret(&decapsulation_key, &epk, &ephemeral_pk)?
+                    }
+                };
+
+                // Protocol v8+: make the server prove it holds the host key
+                // before anything names the client.
+                let hidden_identity = match hidden_identity {
+                    Some(request) => {
+                        let key = self
+                            .verify_host_key(&identity_pk, &shared_secret, &salt_bytes, &negotiated)
+                            .await?;
+                        Some((request, key))
+                    }
+                    None => None,
+                };
+
                 #[cfg(feature = "unstable")]
-                {
+                let identity_proof = {
                     // Derive the algorithm from the public-key bytes rather than from
                     // client_identity_key_algorithm, which is empty on the agent path.
                     let effective_alg = parse_full_public_key(&self.full_public_key_bytes)
@@ -687,76 +1040,44 @@ impl KexReader {
                             &self.client_identity_private_key,
                             &transcript,
                         )?;
-                        self.tx.send(Frame::IdentityProof(signature))?;
-                    }
-                }
-
-                let shared_secret = match client_ephemeral {
-                    ClientEphemeral::Dh(epk) => {
-                        let ResolvedKexAlgorithm::Dh(agreement_alg) =
-                            resolve_kex_alg(&negotiated.kex)?
-                        else {
-                            return Err(MoshpitError::NoCommonAlgorithm.into());
-                        };
-                        let peer_public_key = UnparsedPublicKey::new(agreement_alg, &ephemeral_pk);
-                        trace!("client_kex: running ECDH agree()");
-                        agree(&epk, peer_public_key, Unspecified, |key_material| {
-                            Ok(key_material.to_vec())
-                        })?
-                    }
-                    ClientEphemeral::Kem(decapsulation_key) => {
-                        trace!("client_kex: running ML-KEM decapsulate()");
-                        decapsulation_key
-                            .decapsulate(Ciphertext::from(ephemeral_pk.as_slice()))?
-                            .as_ref()
-                            .to_vec()
+                        if hidden_identity.is_some() {
+                            Some(signature)
+                        } else {
+                            self.tx.send(Frame::IdentityProof(signature))?;
+                            None
+                        }
+                    } else {
+                        None
                     }
                 };
 
-                let (key_bytes, hmac_key_bytes) =
-                    derive_session_keys(&shared_secret, &salt_bytes, &negotiated)?;
-                debug!(
-                    side = "client",
-                    aead = %kex_aead_log,
-                    key_len = key_bytes.len(),
-                    key_hex = %fmt_hex(&key_bytes),
-                    "kex: derived AEAD key"
-                );
-                debug!(
-                    side = "client",
-                    mac = %kex_mac_log,
-                    hmac_key_len = hmac_key_bytes.len(),
-                    hmac_key_hex = %fmt_hex(&hmac_key_bytes),
-                    "kex: derived HMAC key"
-                );
+                if let Some((request, key)) = hidden_identity {
+                    #[cfg(not(feature = "unstable"))]
+                    let sealed = vec![request];
+                    #[cfg(feature = "unstable")]
+                    let sealed: Vec<Frame> =
+                        [Some(request), identity_proof.map(Frame::IdentityProof)]
+                            .into_iter()
+                            .flatten()
+                            .collect();
+                    let sealed_identity = seal_identity(&key, &sealed)?;
+                    self.send_absorbed(sealed_identity)?;
+                    trace!("client_kex: sent SealedIdentity");
+                }
 
-                self.tx_event
-                    .send(KexEvent::KeyMaterial(key_bytes.clone()))
-                    .map_err(|_| Unspecified)?;
-                self.tx_event
-                    .send(KexEvent::HMACKeyMaterial(hmac_key_bytes))
-                    .map_err(|_| Unspecified)?;
+                // Prove possession of an ECDH identity key.
+                let identity_secret =
+                    if negotiated.protocol_version >= IDENTITY_PROOF_MIN_PROTOCOL_VERSION {
+                        self.answer_identity_challenge_if_required().await?
+                    } else {
+                        None
+                    };
 
-                let rnk = LessSafeKey::new(UnboundKey::new(aead_alg, &key_bytes)?);
-                let mut check = b"Yoda".to_vec();
-                let mut nonce_bytes = [0u8; NONCE_LEN];
-                fill(&mut nonce_bytes)?;
-                let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes)?;
-                rnk.seal_in_place_append_tag(nonce, Aad::empty(), &mut check)?;
-
-                match self.diff_mode {
-                    DiffMode::Datagram => self.tx.send(Frame::ClientOptions(1))?,
-                    DiffMode::StateSync => self.tx.send(Frame::ClientOptions(2))?,
-                    DiffMode::Reliable => {}
-                }
-                if !self.send_env.is_empty() || !self.send_path.is_empty() {
-                    self.tx.send(Frame::ClientEnv(
-                        self.send_env.clone(),
-                        self.send_path.clone(),
-                    ))?;
-                }
-                self.tx.send(Frame::Check(nonce_bytes, check))?;
-                trace!("client_kex: key exchange secret established, Check frame sent");
+                let ikm = session_ikm(&shared_secret, identity_secret.as_deref());
+                let session_salt = self
+                    .transcript
+                    .session_salt(&salt_bytes, negotiated.protocol_version);
+                self.send_check(&ikm, &session_salt, &negotiated)?;
             }
             Some(other) => {
                 error!(
@@ -768,6 +1089,135 @@ impl KexReader {
             }
         }
 
+        self.finish_client_kex().await
+    }
+
+    /// Derive the session keys from `ikm` and `session_salt`, report them to the
+    /// state machine, and send `ClientOptions`, `ClientEnv`, `AgentForward`,
+    /// `RemoteCommand` or `FileCopy`, and `Check`.
+    fn send_check(
+        &mut self,
+        ikm: &[u8],
+        session_salt: &[u8],
+        negotiated: &NegotiatedAlgorithms,
+    ) -> Result<()> {
+        let aead_alg = resolve_aead_alg(&negotiated.aead)?;
+        let (key_bytes, hmac_key_bytes) = derive_session_keys(ikm, session_salt, negotiated)?;
+        debug!(
+            side = "client",
+            aead = %negotiated.aead,
+            key_len = key_bytes.len(),
+            key_hex = %fmt_hex(&key_bytes),
+            "kex: derived AEAD key"
+        );
+        debug!(
+            side = "client",
+            mac = %negotiated.mac,
+            hmac_key_len = hmac_key_bytes.len(),
+            hmac_key_hex = %fmt_hex(&hmac_key_bytes),
+            "kex: derived HMAC key"
+        );
+        if negotiated.protocol_version >= RESUMPTION_TICKET_MIN_PROTOCOL_VERSION {
+            self.resumption_psk = Some(resumption_psk(&key_bytes, &hmac_key_bytes, negotiated)?);
+        }
+
+        self.tx_event
+            .send(KexEvent::KeyMaterial(key_bytes.clone()))
+            .map_err(|_| Unspecified)?;
+        self.tx_event
+            .send(KexEvent::HMACKeyMaterial(hmac_key_bytes))
+            .map_err(|_| Unspecified)?;
+
+        let mut options = Vec::new();
+        match self.diff_mode {
+            DiffMode::Datagram => options.push(Frame::ClientOptions(1)),
+            DiffMode::StateSync => options.push(Frame::ClientOptions(2)),
+            DiffMode::Reliable => {}
+        }
+        if !self.send_env.is_empty() || !self.send_path.is_empty() {
+            options.push(Frame::ClientEnv(
+                self.send_env.clone(),
+                self.send_path.clone(),
+            ));
+        }
+        if self.forward_agent
+            && negotiated.protocol_version >= AGENT_FORWARDING_MIN_PROTOCOL_VERSION
+        {
+            options.push(Frame::AgentForward);
+        }
+        if let Some(request) = &self.session_request
+            && negotiated.protocol_version >= NAMED_SESSIONS_MIN_PROTOCOL_VERSION
+        {
+            let (kind, name) = request.to_wire();
+            options.push(Frame::SessionRequest(kind, name));
+        }
+        match self
+            .remote_command
+            .as_ref()
+            .map(|command| (command, command.file_copy()))
+        {
+            Some((_, Some(copy)))
+                if negotiated.protocol_version >= FILE_COPY_MIN_PROTOCOL_VERSION =>
+            {
+                options.push(match copy {
+                    FileCopy::Upload { target } => {
+                        Frame::FileCopy(true, vec![target.clone()], false)
+                    }
+                    FileCopy::Download { sources, recursive } => {
+                        Frame::FileCopy(false, sources.clone(), *recursive)
+                    }
+                });
+            }
+            Some((command, None))
+                if negotiated.protocol_version >= REMOTE_COMMAND_MIN_PROTOCOL_VERSION =>
+            {
+                options.push(Frame::RemoteCommand(
+                    command.command().clone(),
+                    command.pty(),
+                ));
+            }
+            _ => {}
+        }
+        // Protocol v8+: the options travel sealed inside the Check.
+        let mut check = if negotiated.protocol_version >= IDENTITY_HIDING_MIN_PROTOCOL_VERSION {
+            check_plaintext(&options)?
+        } else {
+            for option in options {
+                self.tx.send(option)?;
+            }
+            CHECK_VALUE.to_vec()
+        };
+
+        let rnk = LessSafeKey::new(UnboundKey::new(aead_alg, &key_bytes)?);
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        fill(&mut nonce_bytes)?;
+        let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes)?;
+        rnk.seal_in_place_append_tag(nonce, Aad::empty(), &mut check)?;
+        self.tx.send(Frame::Check(nonce_bytes, check))?;
+        trace!("client_kex: key exchange secret established, Check frame sent");
+        Ok(())
+    }
+
+    /// Redeem `ticket` in place of `Initialize`: derive the session keys from its
+    /// PSK and send `Check` straight away, without waiting for the server.
+    fn resume_with_ticket(
+        &mut self,
+        ticket: &ResumptionTicket,
+        negotiated: &NegotiatedAlgorithms,
+    ) -> Result<()> {
+        let mut client_nonce = [0u8; 32];
+        fill(&mut client_nonce)?;
+        self.send_absorbed(Frame::TicketResume(ticket.ticket().clone(), client_nonce))?;
+        trace!("client_kex: sent TicketResume");
+        let session_salt = self
+            .transcript
+            .session_salt(&client_nonce, negotiated.protocol_version);
+        self.send_check(ticket.psk(), &session_salt, negotiated)
+    }
+
+    /// Wait for `KeyAgreement`, `SessionToken`, an optional `ResumptionTicket`,
+    /// and `MoshpitsAddr`, the server's answer to `Check`.
+    async fn finish_client_kex(&mut self) -> Result<()> {
         trace!("client_kex: waiting for KeyAgreement");
         match self.reader.read_frame().await? {
             Some(Frame::KeyAgreement(uuid)) => {
@@ -776,6 +1226,15 @@ impl KexReader {
                     .send(KexEvent::Uuid(*uuid.as_ref()))
                     .map_err(|_| Unspecified)?;
             }
+            Some(Frame::KexFailure) => {
+                error!(
+                    "client_kex: server rejected the Check frame — session keys do not match \
+                     (handshake frames altered in transit?)"
+                );
+                drop(self.tx_event.send(KexEvent::Failure));
+                return Err(MoshpitError::KeyNotEstablished.into());
+            }
+            Some(Frame::KexFailureReason(reason)) => return Err(self.rejected(reason)),
             Some(other) => {
                 error!(
                     "client_kex: expected KeyAgreement but got frame id={}",
@@ -794,6 +1253,7 @@ impl KexReader {
         // Receive stable session token (sent by server after KeyAgreement)
         trace!("client_kex: waiting for SessionToken");
         match self.reader.read_frame().await? {
+            Some(Frame::KexFailureReason(reason)) => return Err(self.rejected(reason)),
             Some(Frame::SessionToken(session_uuid_wrapper)) => {
                 let session_uuid = *session_uuid_wrapper.as_ref();
                 let is_resume = self.requested_session_uuid == Some(session_uuid);
@@ -818,7 +1278,22 @@ impl KexReader {
         }
 
         trace!("client_kex: waiting for MoshpitsAddr");
-        match self.reader.read_frame().await? {
+        let mut frame = self.reader.read_frame().await?;
+        if let Some(Frame::ResumptionTicket(ticket, lifetime_secs)) = frame {
+            trace!("client_kex: received ResumptionTicket valid for {lifetime_secs}s");
+            if let Some(psk) = self.resumption_psk.take() {
+                drop(
+                    self.tx_event
+                        .send(KexEvent::ResumptionTicket(ResumptionTicket::new(
+                            ticket,
+                            psk,
+                            lifetime_secs,
+                        ))),
+                );
+            }
+            frame = self.reader.read_frame().await?;
+        }
+        match frame {
             Some(Frame::MoshpitsAddr(addr)) => {
                 trace!("client_kex: received MoshpitsAddr {addr}");
                 self.tx_event
@@ -853,12 +1328,15 @@ impl KexReader {
         &mut self,
         socket_addr: SocketAddr,
         port_pool: Arc<Mutex<BTreeSet<u16>>>,
-        public_key_path: &PathBuf,
+        key_pair_paths: (&PathBuf, &PathBuf),
         session_registry: Option<SessionRegistry>,
         allow_tcp: bool,
     ) -> Result<(ServerKex, NegotiatedTransport)> {
+        let (private_key_path, public_key_path) = key_pair_paths;
         trace!("server_kex: waiting for KexInit from client");
-        let negotiated = match self.reader.read_frame().await? {
+        let client_init = self.reader.read_frame().await?;
+        self.absorb_received(client_init.as_ref())?;
+        let negotiated = match client_init {
             Some(Frame::KexInit(client_algos, client_proto)) => {
                 trace!(
                     "server_kex: KexInit received — client offered: kex={:?} aead={:?} mac={:?} kdf={:?} proto={}-{}",
@@ -869,12 +1347,14 @@ impl KexReader {
                     client_proto.min,
                     client_proto.max
                 );
+                self.peer_reads_failure_reasons =
+                    client_proto.max >= KEX_FAILURE_REASON_MIN_PROTOCOL_VERSION;
                 // Advertise our own capabilities first — before any negotiation —
                 // so the client always receives our KexInit and can self-diagnose a
                 // mismatch precisely (we still reject below via KexFailure when the
                 // ranges don't overlap).  The client needs this to pick the ephemeral
                 // key algorithm before sending Initialize.
-                self.tx.send(Frame::KexInit(
+                self.send_absorbed(Frame::KexInit(
                     self.server_preferred_algos.clone(),
                     self.protocol_support,
                 ))?;
@@ -896,24 +1376,23 @@ impl KexReader {
                         client_algos.mac,
                         client_algos.kdf,
                     );
-                    return Err(MoshpitError::NoCommonAlgorithm.into());
+                    return Err(self.reject(KexFailureReason::NoCommonAlgorithm));
                 };
                 // Negotiate the wire protocol version from both advertised ranges.
-                negotiated.protocol_version =
-                    match negotiate_protocol_version(self.protocol_support, client_proto) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            error!(
-                                "server_kex: incompatible wire protocol version — \
-                                 server supports [{}, {}], client supports [{}, {}]",
-                                self.protocol_support.min,
-                                self.protocol_support.max,
-                                client_proto.min,
-                                client_proto.max,
-                            );
-                            return Err(e);
-                        }
-                    };
+                let Ok(protocol_version) =
+                    negotiate_protocol_version(self.protocol_support, client_proto)
+                else {
+                    error!(
+                        "server_kex: incompatible wire protocol version — \
+                         server supports [{}, {}], client supports [{}, {}]",
+                        self.protocol_support.min,
+                        self.protocol_support.max,
+                        client_proto.min,
+                        client_proto.max,
+                    );
+                    return Err(self.reject(KexFailureReason::IncompatibleProtocolVersion));
+                };
+                negotiated.protocol_version = protocol_version;
                 trace!(
                     "server_kex: negotiated: kex={} aead={} mac={} kdf={} proto=v{}",
                     negotiated.kex,
@@ -938,16 +1417,39 @@ impl KexReader {
         };
 
         // Protocol v2+: exchange transport preference before Initialize.
+        // Protocol v10+: the preference may arrive inside an early key share,
+        // which stands in for HiddenInitialize when it matches the negotiated kex.
+        let mut early_exchange = None;
         let negotiated_transport_mode = if negotiated.protocol_version >= 2 {
-            match self.reader.read_frame().await? {
+            let preference = self.reader.read_frame().await?;
+            self.absorb_received(preference.as_ref())?;
+            let preference = match preference {
+                Some(Frame::EarlyKeyShare(kex, exchange, b))
+                    if negotiated.protocol_version >= EARLY_KEY_SHARE_MIN_PROTOCOL_VERSION =>
+                {
+                    if kex == negotiated.kex {
+                        trace!("server_kex: received EarlyKeyShare for {kex}, using it");
+                        early_exchange = Some(exchange);
+                    } else {
+                        trace!(
+                            "server_kex: received EarlyKeyShare for {kex}, negotiated {}, \
+                             ignoring it",
+                            negotiated.kex
+                        );
+                    }
+                    Some(Frame::TransportPreference(b))
+                }
+                other => other,
+            };
+            match preference {
                 Some(Frame::TransportPreference(1)) if allow_tcp => {
                     trace!("server_kex: client requested TCP transport, server agrees");
-                    self.tx.send(Frame::TransportPreference(1))?;
+                    self.send_absorbed(Frame::TransportPreference(1))?;
                     TransportMode::Tcp
                 }
                 Some(Frame::TransportPreference(b)) => {
                     trace!("server_kex: client requested transport byte={b}, server responds UDP");
-                    self.tx.send(Frame::TransportPreference(0))?;
+                    self.send_absorbed(Frame::TransportPreference(0))?;
                     TransportMode::Udp
                 }
                 None => {
@@ -966,238 +1468,259 @@ impl KexReader {
             TransportMode::Udp
         };
 
-        trace!("server_kex: waiting for Initialize/ResumeRequest from client");
-        let (rnk, user_str, shell, requested_session_uuid_opt) =
-            match self.reader.read_frame().await? {
-                None => {
-                    error!("server_kex: client closed connection before sending Initialize");
-                    return Err(MoshpitError::InvalidFrame.into());
-                }
-                Some(frame) => {
-                    let (user, pk, fpk, req_uuid) = match frame {
-                        Frame::Initialize(user, pk, fpk) => {
-                            trace!("server_kex: received Initialize from client");
-                            (user, pk, fpk, None)
-                        }
-                        Frame::ResumeRequest(session_uuid_wrapper, user, pk, fpk) => {
-                            trace!(
-                                "server_kex: received ResumeRequest for session {}",
-                                session_uuid_wrapper
-                            );
-                            (user, pk, fpk, Some(*session_uuid_wrapper.as_ref()))
-                        }
-                        other => {
-                            error!(
-                                "server_kex: expected Initialize/ResumeRequest but got frame id={}",
-                                other.id()
-                            );
-                            return Err(MoshpitError::InvalidFrame.into());
-                        }
-                    };
-                    let user_str = String::from_utf8_lossy(&user).to_string();
-                    trace!(
-                        "server_kex: validating system account for user '{}'",
-                        user_str
-                    );
-                    let (home_dir, shell) = if self.validate_user(&user_str).await? {
-                        trace!(
-                            "server_kex: user '{}' is valid, getting home/shell",
-                            user_str
-                        );
-                        self.get_home_dir_shell(&user_str).await?
-                    } else {
-                        error!("server_kex: '{}' is not a valid system account", user_str);
-                        return Err(MoshpitError::KeyNotEstablished.into());
-                    };
-                    trace!(
-                        "server_kex: home_dir='{}', checking authorized_keys",
-                        home_dir
-                    );
-                    if !check_authorized_keys(&home_dir, &fpk)? {
-                        error!(
-                            "server_kex: client pubkey not in '{home_dir}/.mp/authorized_keys' \
-                             (file missing, wrong permissions, or key not added)",
-                        );
-                        return Err(MoshpitError::KeyNotEstablished.into());
-                    }
-                    trace!("server_kex: authorized_keys OK, sending NegotiatedAlgorithms event");
-                    drop(
-                        self.tx_event
-                            .send(KexEvent::NegotiatedAlgorithms(negotiated.clone())),
-                    );
-                    let initialize_result = self.handle_initialize(
-                        &pk,
-                        &negotiated,
-                        &self.tx_event.clone(),
-                        public_key_path,
-                    )?;
-                    let rnk = initialize_result.0;
-                    #[cfg(feature = "unstable")]
-                    self.handle_identity_proof_if_required(IdentityProofContext {
-                        client_identity_full: &fpk,
-                        user: &user,
-                        client_exchange: &pk,
-                        server_exchange: &initialize_result.1,
-                        salt: &initialize_result.2,
-                        negotiated: &negotiated,
-                        public_key_path,
-                    })
-                    .await?;
-                    trace!("server_kex: PeerInitialize sent to client");
-                    (rnk, user_str, shell, req_uuid)
-                }
-            };
-
-        // Read the next frame: clients may send `ClientOptions` (diff mode),
-        // `ClientEnv` (env/path passthrough), both in that order, or go straight
-        // to `Check`.  Any other frame type is a protocol error.
-        trace!("server_kex: waiting for ClientOptions, ClientEnv, or Check frame");
-        let mut client_env: Vec<(String, String)> = Vec::new();
-        let mut client_extra_path: Vec<String> = Vec::new();
-        let negotiated_diff_mode = match self.reader.read_frame().await? {
-            Some(Frame::ClientOptions(mode_byte)) => {
-                let mode = match mode_byte {
-                    1 => {
-                        trace!("server_kex: client requested DiffMode::Datagram");
-                        DiffMode::Datagram
-                    }
-                    2 => {
-                        trace!("server_kex: client requested DiffMode::StateSync");
-                        DiffMode::StateSync
-                    }
-                    other => {
-                        trace!("server_kex: ClientOptions mode_byte={other}, using Reliable");
-                        DiffMode::Reliable
+        let opening = if let Some(exchange) = early_exchange {
+            // Already in the transcript as part of the EarlyKeyShare.
+            Some(Frame::HiddenInitialize(exchange))
+        } else {
+            trace!("server_kex: waiting for Initialize/ResumeRequest from client");
+            let opening = self.reader.read_frame().await?;
+            self.absorb_received(opening.as_ref())?;
+            opening
+        };
+        let (rnk, user_str, shell, requested_session_uuid_opt) = match opening {
+            None => {
+                error!("server_kex: client closed connection before sending Initialize");
+                return Err(MoshpitError::InvalidFrame.into());
+            }
+            Some(Frame::HiddenInitialize(pk))
+                if negotiated.protocol_version >= IDENTITY_HIDING_MIN_PROTOCOL_VERSION =>
+            {
+                trace!("server_kex: received HiddenInitialize from client");
+                self.handle_hidden_initialize(&pk, &negotiated, (private_key_path, public_key_path))
+                    .await?
+            }
+            Some(Frame::TicketResume(ticket, client_nonce))
+                if negotiated.protocol_version >= RESUMPTION_TICKET_MIN_PROTOCOL_VERSION =>
+            {
+                trace!("server_kex: received TicketResume from client");
+                self.handle_ticket_resume(
+                    &ticket,
+                    client_nonce,
+                    &negotiated,
+                    session_registry.as_ref(),
+                )
+                .await?
+            }
+            Some(frame) => {
+                let (user, pk, fpk, req_uuid) = match frame {
+                    Frame::Initialize(user, pk, fpk) => {
+                        trace!("server_kex: received Initialize from client");
+                        (user, pk, fpk, None)
                     }
-                };
-                // After ClientOptions, expect ClientEnv or Check
-                match self.reader.read_frame().await? {
-                    Some(Frame::ClientEnv(env, path)) => {
+                    Frame::ResumeRequest(session_uuid_wrapper, user, pk, fpk) => {
                         trace!(
-                            "server_kex: received ClientEnv ({} vars, {} path entries) after ClientOptions",
-                            env.len(),
-                            path.len()
+                            "server_kex: received ResumeRequest for session {}",
+                            session_uuid_wrapper
                         );
-                        client_env = env;
-                        client_extra_path = path;
-                        // Now read Check
-                        match self.reader.read_frame().await? {
-                            Some(Frame::Check(nonce, enc)) => {
-                                trace!(
-                                    "server_kex: received Check frame after ClientEnv, verifying"
-                                );
-                                self.handle_check(&rnk, nonce, enc, &self.tx_event.clone())?;
-                                trace!("server_kex: Check verified, KeyAgreement sent");
-                            }
-                            Some(other) => {
-                                error!(
-                                    "server_kex: expected Check after ClientEnv but got frame id={}",
-                                    other.id()
-                                );
-                                return Err(MoshpitError::InvalidFrame.into());
-                            }
-                            None => {
-                                error!("server_kex: client closed connection after ClientEnv");
-                                return Err(MoshpitError::InvalidFrame.into());
-                            }
-                        }
-                    }
-                    Some(Frame::Check(nonce, enc)) => {
-                        trace!("server_kex: received Check frame after ClientOptions, verifying");
-                        self.handle_check(&rnk, nonce, enc, &self.tx_event.clone())?;
-                        trace!("server_kex: Check verified, KeyAgreement sent");
+                        (user, pk, fpk, Some(*session_uuid_wrapper.as_ref()))
                     }
-                    Some(other) => {
+                    other => {
                         error!(
-                            "server_kex: expected ClientEnv or Check after ClientOptions but got frame id={}",
+                            "server_kex: expected Initialize/ResumeRequest but got frame id={}",
                             other.id()
                         );
                         return Err(MoshpitError::InvalidFrame.into());
                     }
-                    None => {
-                        error!("server_kex: client closed connection after ClientOptions");
-                        return Err(MoshpitError::InvalidFrame.into());
-                    }
-                }
-                mode
-            }
-            Some(Frame::ClientEnv(env, path)) => {
+                };
+                let user_str = String::from_utf8_lossy(&user).to_string();
                 trace!(
-                    "server_kex: received ClientEnv ({} vars, {} path entries)",
-                    env.len(),
-                    path.len()
+                    "server_kex: validating system account for user '{}'",
+                    user_str
                 );
-                client_env = env;
-                client_extra_path = path;
-                // Now read Check
-                match self.reader.read_frame().await? {
-                    Some(Frame::Check(nonce, enc)) => {
-                        trace!("server_kex: received Check frame after ClientEnv, verifying");
-                        self.handle_check(&rnk, nonce, enc, &self.tx_event.clone())?;
-                        trace!("server_kex: Check verified, KeyAgreement sent");
-                    }
-                    Some(other) => {
-                        error!(
-                            "server_kex: expected Check after ClientEnv but got frame id={}",
-                            other.id()
-                        );
-                        return Err(MoshpitError::InvalidFrame.into());
-                    }
-                    None => {
-                        error!("server_kex: client closed connection after ClientEnv");
-                        return Err(MoshpitError::InvalidFrame.into());
-                    }
+                let (home_dir, shell) = if self.validate_user(&user_str).await? {
+                    trace!(
+                        "server_kex: user '{}' is valid, getting home/shell",
+                        user_str
+                    );
+                    self.get_home_dir_shell(&user_str).await?
+                } else {
+                    error!("server_kex: '{}' is not a valid system account", user_str);
+                    return Err(self.reject(KexFailureReason::UnknownUser));
+                };
+                trace!(
+                    "server_kex: home_dir='{}', checking authorized_keys",
+                    home_dir
+                );
+                if let Some(reason) = check_authorized_keys(&home_dir, &fpk)? {
+                    error!(
+                        "server_kex: client pubkey rejected by \
+                             '{home_dir}/.mp/authorized_keys': {reason}",
+                    );
+                    return Err(self.reject(reason));
                 }
-                DiffMode::Reliable
-            }
-            Some(Frame::Check(nonce, enc)) => {
-                trace!("server_kex: received Check frame (no ClientOptions/ClientEnv), verifying");
-                self.handle_check(&rnk, nonce, enc, &self.tx_event.clone())?;
-                trace!("server_kex: Check verified, KeyAgreement sent");
-                DiffMode::Reliable
-            }
-            Some(other) => {
-                error!(
-                    "server_kex: expected ClientOptions, ClientEnv, or Check but got frame id={}",
-                    other.id()
+                trace!("server_kex: authorized_keys OK, sending NegotiatedAlgorithms event");
+                drop(
+                    self.tx_event
+                        .send(KexEvent::NegotiatedAlgorithms(negotiated.clone())),
                 );
-                return Err(MoshpitError::InvalidFrame.into());
-            }
-            None => {
-                error!("server_kex: client closed connection before sending Check");
-                return Err(MoshpitError::InvalidFrame.into());
+                self.client_identity.clone_from(&fpk);
+                let initialize_result = self.handle_initialize(
+                    &pk,
+                    &fpk,
+                    &negotiated,
+                    &self.tx_event.clone(),
+                    public_key_path,
+                )?;
+                let rnk = initialize_result.0;
+                #[cfg(feature = "unstable")]
+                self.handle_identity_proof_if_required(IdentityProofContext {
+                    client_identity_full: &fpk,
+                    user: &user,
+                    client_exchange: &pk,
+                    server_exchange: &initialize_result.1,
+                    salt: &initialize_result.2,
+                    negotiated: &negotiated,
+                    public_key_path,
+                    signature: None,
+                })
+                .await?;
+                trace!("server_kex: PeerInitialize sent to client");
+                (rnk, user_str, shell, req_uuid)
             }
         };
 
-        // Determine session UUID: reuse the requested session if user matches,
-        // else create new.  Any live connection on the same session will be
-        // displaced by `resolve_session` when it cancels the old conn_token.
-        let (session_uuid, is_resume) = match (requested_session_uuid_opt, &session_registry) {
-            (Some(req_uuid), Some(registry)) => {
-                let reg = registry.lock().await;
-                if let Some(stored_user) = reg.get(&req_uuid) {
-                    if *stored_user == user_str {
-                        (req_uuid, true)
-                    } else {
-                        (Uuid::new_v4(), false)
-                    }
-                } else {
-                    (Uuid::new_v4(), false)
+        // Read the frames up to `Check`.  Clients before protocol v8 may send
+        // `ClientOptions` (diff mode) and `ClientEnv` (env/path passthrough) in
+        // clear first, each at most once and in that order; from v8 those,
+        // `AgentForward`, `SessionRequest` and `RemoteCommand` or `FileCopy` are
+        // sealed inside the `Check`, so any other frame is a protocol error.
+        trace!("server_kex: waiting for ClientOptions, ClientEnv, or Check frame");
+        let mut options = SessionOptions::new(negotiated.protocol_version);
+        loop {
+            match self.reader.read_frame().await? {
+                Some(Frame::Check(nonce, enc)) => {
+                    trace!("server_kex: received Check frame, verifying");
+                    self.handle_check(&rnk, nonce, enc, &self.tx_event.clone(), &mut options)?;
+                    trace!("server_kex: Check verified, KeyAgreement sent");
+                    break;
+                }
+                Some(option)
+                    if negotiated.protocol_version < IDENTITY_HIDING_MIN_PROTOCOL_VERSION =>
+                {
+                    options.apply(option)?;
+                }
+                Some(other) => {
+                    error!(
+                        "server_kex: expected Check but got frame id={} in clear",
+                        other.id()
+                    );
+                    return Err(MoshpitError::InvalidFrame.into());
+                }
+                None => {
+                    error!("server_kex: client closed connection before sending Check");
+                    return Err(MoshpitError::InvalidFrame.into());
                 }
             }
-            _ => (Uuid::new_v4(), false),
+        }
+
+        // Determine session UUID: reuse the user's session by the requested
+        // name, or the owner's when joining another user's, else the requested
+        // session if the user matches, else create new.  A listing always gets
+        // a session of its own.  Unless this client joins alongside them, any
+        // live connections on the same session will be displaced by
+        // `resolve_session` when it cancels their conn_tokens.
+        let list_sessions = options.session_request == Some(SessionRequest::List);
+        let session_name = options
+            .session_request
+            .as_ref()
+            .and_then(SessionRequest::name);
+        let (shared, read_only) = match &options.session_request {
+            Some(SessionRequest::Join { read_only, .. }) => (true, *read_only),
+            _ => (false, false),
+        };
+        let owner = options
+            .session_request
+            .as_ref()
+            .and_then(SessionRequest::owner)
+            .unwrap_or(&user_str);
+        // Held until a new session is registered, so two clients starting the
+        // same name cannot both create it.
+        let mut registry = match &session_registry {
+            Some(registry) => Some(registry.lock().await),
+            None => None,
         };
+        let resumed = registry
+            .as_ref()
+            .and_then(|reg| {
+                let named = session_name.and_then(|name| {
+                    reg.iter()
+                        .find(|(_, entry)| entry.is_named(owner, name))
+                        .map(|(uuid, _)| *uuid)
+                });
+                named.or_else(|| {
+                    requested_session_uuid_opt
+                        .filter(|uuid| reg.get(uuid).is_some_and(|e| *e.user() == user_str))
+                })
+            })
+            .filter(|_| !list_sessions);
+        let is_resume = resumed.is_some();
+
+        if !is_resume
+            && matches!(
+                options.session_request,
+                Some(SessionRequest::Attach(_) | SessionRequest::Join { .. })
+            )
+        {
+            error!("server_kex: '{owner}' has no session by the requested name");
+            return Err(self.reject(KexFailureReason::NoSuchSession));
+        }
+
+        // A command is never run twice: once a command session is gone, a client
+        // still trying to resume it is told so instead of starting it again.
+        if options.remote_command.is_some() && requested_session_uuid_opt.is_some() && !is_resume {
+            return Err(self.reject(KexFailureReason::SessionEnded));
+        }
 
         // Register new sessions in the lightweight registry
-        if !is_resume && let Some(ref registry) = session_registry {
-            let mut reg = registry.lock().await;
-            drop(reg.insert(session_uuid, user_str.clone()));
+        let session_uuid = resumed.unwrap_or_else(Uuid::new_v4);
+        if !is_resume && let Some(reg) = registry.as_mut() {
+            drop(reg.insert(
+                session_uuid,
+                SessionEntry::new(user_str.clone(), session_name.map(str::to_owned)),
+            ));
+        }
+        drop(registry);
+
+        // Another user's session may only be joined as far as the owner's
+        // `~/.mp/shared_sessions` allows; a session that is not shared with
+        // this user is reported as missing.
+        if *owner != user_str
+            && let Some(name) = session_name
+        {
+            let (owner_home, _) = self.get_home_dir_shell(owner).await?;
+            match check_session_grant(&owner_home, &user_str, name)? {
+                None => {
+                    error!("server_kex: '{owner}' does not share '{name}' with '{user_str}'");
+                    return Err(self.reject(KexFailureReason::NoSuchSession));
+                }
+                Some(SessionAccess::ReadOnly) if !read_only => {
+                    error!("server_kex: '{owner}' shares '{name}' with '{user_str}' read-only");
+                    return Err(self.reject(KexFailureReason::SessionReadOnly));
+                }
+                Some(_) => {}
+            }
         }
 
         // Inform the client of its stable session UUID
         self.tx
             .send(Frame::SessionToken(UuidWrapper::new(session_uuid)))?;
 
+        // Protocol v9+: hand out a ticket that resumes this session without the
+        // asymmetric exchange.  A ticket only resumes the user's own sessions,
+        // so a client joining another user's gets none.
+        if *owner == user_str
+            && let (Some(issuer), Some(psk)) = (&self.ticket_issuer, self.resumption_psk.take())
+        {
+            let ticket = issuer
+                .issue(session_uuid, &user_str, &self.client_identity, psk)
+                .await?;
+            trace!("server_kex: sending ResumptionTicket for session {session_uuid}");
+            self.tx.send(Frame::ResumptionTicket(
+                ticket,
+                RESUMPTION_TICKET_LIFETIME.as_secs(),
+            ))?;
+        }
+
         let transport = match negotiated_transport_mode {
             TransportMode::Tcp => self.handle_tcp_setup(socket_addr, port_pool).await?,
             TransportMode::Udp => {
@@ -1211,15 +1734,153 @@ impl KexReader {
             .shell(shell)
             .session_uuid(session_uuid)
             .is_resume(is_resume)
-            .diff_mode(negotiated_diff_mode)
+            .diff_mode(options.diff_mode)
             .negotiated_algorithms(negotiated)
-            .client_env(client_env)
-            .client_extra_path(client_extra_path)
+            .client_env(options.env)
+            .client_extra_path(options.extra_path)
+            .forward_agent(options.forward_agent)
+            .maybe_remote_command(options.remote_command)
+            .list_sessions(list_sessions)
+            .shared(shared)
+            .read_only(read_only)
             .build();
 
         Ok((skex, transport))
     }
 
+    /// Protocol v8+: challenge the host key the server named in `PeerInitialize`
+    /// and check its `IdentityProof`.
+    ///
+    /// Returns the handshake key that seals the client identity.  It is derived
+    /// from the same secrets as the proof, so for an ECDH host key only the
+    /// holder of the host private key can open what the client sends next.
+    async fn verify_host_key(
+        &mut self,
+        host_public_key: &[u8],
+        shared_secret: &[u8],
+        salt_bytes: &[u8],
+        negotiated: &NegotiatedAlgorithms,
+    ) -> Result<LessSafeKey> {
+        let Some(host_alg) = host_key_algorithm(host_public_key) else {
+            error!(
+                "client_kex: cannot challenge a {}-byte host key",
+                host_public_key.len()
+            );
+            drop(self.tx_event.send(KexEvent::Failure));
+            return Err(MoshpitError::HostKeyRejected.into());
+        };
+        let (challenge, host_secret) =
+            if let Some(agreement_alg) = resolve_identity_agreement_alg(host_alg) {
+                let (challenge, host_secret) =
+                    issue_identity_challenge(agreement_alg, host_public_key)?;
+                (challenge, Some(host_secret))
+            } else {
+                let mut challenge = vec![0u8; 32];
+                fill(&mut challenge)?;
+                (challenge, None)
+            };
+        trace!("client_kex: sending IdentityChallenge for the {host_alg} host key");
+        self.send_absorbed(Frame::IdentityChallenge(challenge))?;
+        let ikm = session_ikm(shared_secret, host_secret.as_deref());
+        let handshake_salt = self
+            .transcript
+            .session_salt(salt_bytes, negotiated.protocol_version);
+
+        trace!("client_kex: waiting for the host IdentityProof");
+        let answer = self.reader.read_frame().await?;
+        self.absorb_received(answer.as_ref())?;
+        let proof = match answer {
+            Some(Frame::IdentityProof(proof)) => proof,
+            Some(Frame::KexFailureReason(reason)) => return Err(self.rejected(reason)),
+            None => {
+                error!("client_kex: server closed connection before proving its host key");
+                return Err(anyhow::anyhow!(
+                    "Server closed connection during key exchange"
+                ));
+            }
+            Some(other) => {
+                error!(
+                    "client_kex: expected IdentityProof but got frame id={}",
+                    other.id()
+                );
+                drop(self.tx_event.send(KexEvent::Failure));
+                return Err(MoshpitError::KeyNotEstablished.into());
+            }
+        };
+        let verified = match host_secret {
+            Some(_) => {
+                verify_slices_are_equal(&proof, &host_proof(&ikm, &handshake_salt, negotiated)?)
+                    .is_ok()
+            }
+            #[cfg(feature = "unstable")]
+            None => verify_identity_transcript(
+                host_alg,
+                host_public_key,
+                &host_proof_message(&handshake_salt),
+                &proof,
+            )
+            .is_ok(),
+            #[cfg(not(feature = "unstable"))]
+            None => false,
+        };
+        if !verified {
+            error!("client_kex: the server could not prove it holds its host key");
+            drop(self.tx_event.send(KexEvent::Failure));
+            return Err(MoshpitError::HostKeyRejected.into());
+        }
+        trace!("client_kex: host key proven");
+        handshake_key(&ikm, &handshake_salt, negotiated)
+    }
+
+    /// When this client's identity is an ECDH key, read the server's
+    /// `IdentityChallenge` and compute the identity secret, either locally or via
+    /// the agent.  Returns `None` for identities that cannot agree (e.g. ML-DSA).
+    async fn answer_identity_challenge_if_required(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(key_alg) = parse_full_public_key(&self.full_public_key_bytes)
+            .ok()
+            .map(|(alg, _)| alg)
+            .filter(|alg| resolve_identity_agreement_alg(alg).is_some())
+        else {
+            return Ok(None);
+        };
+
+        trace!("client_kex: waiting for IdentityChallenge");
+        let frame = self.reader.read_frame().await?;
+        self.absorb_received(frame.as_ref())?;
+        let challenge = match frame {
+            Some(Frame::IdentityChallenge(challenge)) => challenge,
+            None => {
+                error!("client_kex: server closed connection before sending IdentityChallenge");
+                return Err(anyhow::anyhow!(
+                    "Server closed connection during key exchange"
+                ));
+            }
+            Some(other) => {
+                error!(
+                    "client_kex: expected IdentityChallenge but got frame id={}",
+                    other.id()
+                );
+                drop(self.tx_event.send(KexEvent::Failure));
+                return Err(MoshpitError::KeyNotEstablished.into());
+            }
+        };
+
+        #[cfg(unix)]
+        let identity_secret =
+            if let (Some(socket), Some(fp)) = (&self.agent_socket, &self.agent_fingerprint) {
+                // Delegate the identity DH to the agent — private key stays in agent memory.
+                crate::agent::client::AgentClient::new(socket.clone())
+                    .agree(fp, &challenge)
+                    .await?
+            } else {
+                answer_identity_challenge(&key_alg, &self.client_identity_private_key, &challenge)?
+            };
+        #[cfg(not(unix))]
+        let identity_secret =
+            answer_identity_challenge(&key_alg, &self.client_identity_private_key, &challenge)?;
+        Ok(Some(identity_secret))
+    }
+
     #[cfg(feature = "unstable")]
     async fn handle_identity_proof_if_required(
         &mut self,
@@ -1230,7 +1891,11 @@ impl KexReader {
             return Ok(());
         }
 
-        let signature = match self.reader.read_frame().await? {
+        let received = match context.signature {
+            Some(signature) => Some(Frame::IdentityProof(signature)),
+            None => self.reader.read_frame().await?,
+        };
+        let signature = match received {
             Some(Frame::IdentityProof(signature)) => signature,
             Some(other) => {
                 error!(
@@ -1258,17 +1923,274 @@ impl KexReader {
         verify_identity_transcript(&key_alg, &public_key, &transcript, &signature)
     }
 
+    /// Protocol v8+: answer a `HiddenInitialize`, then read the client's user
+    /// and identity key from the `SealedIdentity` that follows.
+    ///
+    /// Unknown users and unauthorized keys are not rejected here.  The server
+    /// finishes the exchange as usual and rejects the client's `Check` instead,
+    /// so every failed login looks the same on the wire.
+    async fn handle_hidden_initialize(
+        &mut self,
+        pk: &[u8],
+        negotiated: &NegotiatedAlgorithms,
+        key_pair_paths: (&PathBuf, &PathBuf),
+    ) -> Result<(LessSafeKey, String, String, Option<Uuid>)> {
+        let (private_key_path, public_key_path) = key_pair_paths;
+        #[cfg_attr(not(feature = "unstable"), allow(unused_variables))]
+        let (shared_secret, server_exchange, salt_bytes) =
+            self.send_peer_initialize(pk, negotiated, public_key_path)?;
+        let key = self
+            .prove_host_key(&shared_secret, &salt_bytes, negotiated, private_key_path)
+            .await?;
+
+        trace!("server_kex: waiting for SealedIdentity from client");
+        let sealed = self.reader.read_frame().await?;
+        self.absorb_received(sealed.as_ref())?;
+        let identity = match sealed {
+            Some(Frame::SealedIdentity(nonce, ciphertext)) => {
+                match open_identity(&key, nonce, ciphertext) {
+                    Ok(identity) => identity,
+                    Err(e) => {
+                        error!("server_kex: SealedIdentity did not open: {e}");
+                        return Err(self.reject(KexFailureReason::KeyMismatch));
+                    }
+                }
+            }
+            None => {
+                error!("server_kex: client closed connection before sending SealedIdentity");
+                return Err(MoshpitError::InvalidFrame.into());
+            }
+            Some(other) => {
+                error!(
+                    "server_kex: expected SealedIdentity but got frame id={}",
+                    other.id()
+                );
+                return Err(MoshpitError::InvalidFrame.into());
+            }
+        };
+        let mut identity = identity.into_iter();
+        let (user, fpk, req_uuid) = match identity.next() {
+            Some(Frame::Initialize(user, _, fpk)) => {
+                trace!("server_kex: SealedIdentity carries Initialize");
+                (user, fpk, None)
+            }
+            Some(Frame::ResumeRequest(session_uuid_wrapper, user, _, fpk)) => {
+                trace!(
+                    "server_kex: SealedIdentity carries ResumeRequest for session {}",
+                    session_uuid_wrapper
+                );
+                (user, fpk, Some(*session_uuid_wrapper.as_ref()))
+            }
+            _ => {
+                error!("server_kex: SealedIdentity carries no Initialize/ResumeRequest");
+                return Err(MoshpitError::InvalidFrame.into());
+            }
+        };
+
+        let user_str = String::from_utf8_lossy(&user).to_string();
+        let (shell, rejection) = self.authenticate(&user_str, &fpk).await?;
+        if let Some(reason) = rejection {
+            error!("server_kex: rejecting '{user_str}' once the client sends Check: {reason}");
+            self.pending_rejection = Some(reason);
+        }
+        self.client_identity.clone_from(&fpk);
+        drop(
+            self.tx_event
+                .send(KexEvent::NegotiatedAlgorithms(negotiated.clone())),
+        );
+        let rnk = self.derive_server_keys(
+            &fpk,
+            &shared_secret,
+            &salt_bytes,
+            negotiated,
+            &self.tx_event.clone(),
+        )?;
+        #[cfg(feature = "unstable")]
+        self.handle_identity_proof_if_required(IdentityProofContext {
+            client_identity_full: &fpk,
+            user: &user,
+            client_exchange: pk,
+            server_exchange: &server_exchange,
+            salt: &salt_bytes,
+            negotiated,
+            public_key_path,
+            signature: match identity.next() {
+                Some(Frame::IdentityProof(signature)) => Some(signature),
+                _ => None,
+            },
+        })
+        .await?;
+        Ok((rnk, user_str, shell, req_uuid))
+    }
+
+    /// Protocol v8+: answer the client's `IdentityChallenge` with an
+    /// `IdentityProof` made with the host private key.
+    ///
+    /// Returns the handshake key that opens the client's `SealedIdentity`.
+    async fn prove_host_key(
+        &mut self,
+        shared_secret: &[u8],
+        salt_bytes: &[u8],
+        negotiated: &NegotiatedAlgorithms,
+        private_key_path: &PathBuf,
+    ) -> Result<LessSafeKey> {
+        let host_key = load_identity_key(private_key_path, None)?;
+
+        trace!("server_kex: waiting for the client to challenge the host key");
+        let challenge = self.reader.read_frame().await?;
+        self.absorb_received(challenge.as_ref())?;
+        let challenge = match challenge {
+            Some(Frame::IdentityChallenge(challenge)) => challenge,
+            None => {
+                error!("server_kex: client closed connection before challenging the host key");
+                return Err(MoshpitError::InvalidFrame.into());
+            }
+            Some(other) => {
+                error!(
+                    "server_kex: expected IdentityChallenge but got frame id={}",
+                    other.id()
+                );
+                return Err(MoshpitError::InvalidFrame.into());
+            }
+        };
+        let host_secret = if resolve_identity_agreement_alg(host_key.key_algorithm()).is_some() {
+            Some(answer_identity_challenge(
+                host_key.key_algorithm(),
+                host_key.private_key(),
+                &challenge,
+            )?)
+        } else {
+            None
+        };
+        let ikm = session_ikm(shared_secret, host_secret.as_deref());
+        let handshake_salt = self
+            .transcript
+            .session_salt(salt_bytes, negotiated.protocol_version);
+        let proof = match host_secret {
+            Some(_) => host_proof(&ikm, &handshake_salt, negotiated)?,
+            #[cfg(feature = "unstable")]
+            None => sign_identity_transcript(
+                host_key.key_algorithm(),
+                host_key.private_key(),
+                &host_proof_message(&handshake_salt),
+            )?,
+            #[cfg(not(feature = "unstable"))]
+            None => return Err(MoshpitError::InvalidKeyHeader.into()),
+        };
+        trace!("server_kex: sending the host IdentityProof");
+        self.send_absorbed(Frame::IdentityProof(proof))?;
+        handshake_key(&ikm, &handshake_salt, negotiated)
+    }
+
+    /// Protocol v9+: redeem a resumption ticket in place of the ephemeral
+    /// exchange.
+    ///
+    /// The ticket must have been issued by this server for a session that still
+    /// belongs to its user, and the identity key sealed inside it must still be
+    /// authorized.  Anything else is rejected with
+    /// [`KexFailureReason::TicketRejected`] so the client can fall back to a full
+    /// handshake.
+    async fn handle_ticket_resume(
+        &mut self,
+        ticket: &[u8],
+        client_nonce: [u8; 32],
+        negotiated: &NegotiatedAlgorithms,
+        session_registry: Option<&SessionRegistry>,
+    ) -> Result<(LessSafeKey, String, String, Option<Uuid>)> {
+        let Some(contents) = (match &self.ticket_issuer {
+            Some(issuer) => issuer.redeem(ticket).await,
+            None => None,
+        }) else {
+            error!("server_kex: resumption ticket is unknown, expired, or already used");
+            return Err(self.reject(KexFailureReason::TicketRejected));
+        };
+        let session_uuid = *contents.session_uuid.as_ref();
+        let session_live = match session_registry {
+            Some(registry) => registry
+                .lock()
+                .await
+                .get(&session_uuid)
+                .is_some_and(|entry| *entry.user() == contents.user),
+            None => false,
+        };
+        if !session_live {
+            error!("server_kex: resumption ticket names session {session_uuid}, which is gone");
+            return Err(self.reject(KexFailureReason::TicketRejected));
+        }
+        let (shell, rejection) = self
+            .authenticate(&contents.user, &contents.identity_key)
+            .await?;
+        if let Some(reason) = rejection {
+            error!(
+                "server_kex: resumption ticket for '{}' no longer authorized: {reason}",
+                contents.user
+            );
+            return Err(self.reject(KexFailureReason::TicketRejected));
+        }
+        trace!(
+            "server_kex: resumption ticket accepted for session {session_uuid} (user '{}')",
+            contents.user
+        );
+        drop(
+            self.tx_event
+                .send(KexEvent::NegotiatedAlgorithms(negotiated.clone())),
+        );
+        let session_salt = self
+            .transcript
+            .session_salt(&client_nonce, negotiated.protocol_version);
+        let rnk = self.emit_session_keys(
+            &contents.psk,
+            &session_salt,
+            negotiated,
+            &self.tx_event.clone(),
+        )?;
+        self.client_identity = contents.identity_key;
+        Ok((rnk, contents.user, shell, Some(session_uuid)))
+    }
+
+    /// Check that `user_str` is a valid account and lists `fpk` in its
+    /// `authorized_keys`.
+    ///
+    /// Returns the user's shell, and the reason to reject the client if either
+    /// check fails.
+    async fn authenticate(
+        &self,
+        user_str: &str,
+        fpk: &[u8],
+    ) -> Result<(String, Option<KexFailureReason>)> {
+        if !self.validate_user(user_str).await? {
+            return Ok((String::new(), Some(KexFailureReason::UnknownUser)));
+        }
+        let (home_dir, shell) = self.get_home_dir_shell(user_str).await?;
+        Ok((shell, check_authorized_keys(&home_dir, fpk)?))
+    }
+
     fn handle_initialize(
         &mut self,
         pk: &[u8],
+        fpk: &[u8],
         negotiated: &NegotiatedAlgorithms,
         tx_event: &UnboundedSender<KexEvent>,
         public_key_path: &PathBuf,
     ) -> Result<(LessSafeKey, Vec<u8>, Vec<u8>)> {
+        let (shared_secret, server_ephemeral_or_ciphertext, salt_bytes) =
+            self.send_peer_initialize(pk, negotiated, public_key_path)?;
+        let rnk =
+            self.derive_server_keys(fpk, &shared_secret, &salt_bytes, negotiated, tx_event)?;
+        Ok((rnk, server_ephemeral_or_ciphertext, salt_bytes))
+    }
+
+    /// Complete the ephemeral exchange against the client's `pk` and send
+    /// `PeerInitialize`.
+    ///
+    /// Returns `(shared_secret, server_exchange, salt)`.
+    fn send_peer_initialize(
+        &mut self,
+        pk: &[u8],
+        negotiated: &NegotiatedAlgorithms,
+        public_key_path: &PathBuf,
+    ) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>)> {
         let kex_alg = resolve_kex_alg(&negotiated.kex)?;
-        let aead_alg = resolve_aead_alg(&negotiated.aead)?;
-        let kex_aead_log = negotiated.aead.clone();
-        let kex_mac_log = negotiated.mac.clone();
 
         // Load only the server's identity public key bytes for host authentication
         let (_, identity_pub_key_bytes) = load_public_key(public_key_path)?;
@@ -1299,6 +2221,9 @@ impl KexReader {
                     shared_secret.as_ref().to_vec(),
                 )
             }
+            ResolvedKexAlgorithm::Hybrid(kem_algorithm) => {
+                hybrid_server_exchange(kem_algorithm, pk)?
+            }
         };
 
         // Send the server's identity public key, ephemeral public key or KEM ciphertext,
@@ -1308,24 +2233,80 @@ impl KexReader {
             server_ephemeral_or_ciphertext.clone(),
             salt_bytes.to_vec(),
         );
-        self.tx.send(peer_initialize)?;
+        self.send_absorbed(peer_initialize)?;
+        Ok((
+            shared_secret,
+            server_ephemeral_or_ciphertext,
+            salt_bytes.to_vec(),
+        ))
+    }
+
+    /// Challenge the client identity `fpk` if required and derive the session
+    /// keys, reporting them on `tx_event`.
+    fn derive_server_keys(
+        &mut self,
+        fpk: &[u8],
+        shared_secret: &[u8],
+        salt_bytes: &[u8],
+        negotiated: &NegotiatedAlgorithms,
+        tx_event: &UnboundedSender<KexEvent>,
+    ) -> Result<LessSafeKey> {
+        // Challenge an ECDH client identity so that only the holder of its
+        // private key can derive the session keys.
+        let identity_challenge = if negotiated.protocol_version
+            >= IDENTITY_PROOF_MIN_PROTOCOL_VERSION
+        {
+            let (identity_alg, identity_public_key) = parse_full_public_key(fpk)?;
+            resolve_identity_agreement_alg(&identity_alg)
+                .map(|agreement_alg| issue_identity_challenge(agreement_alg, &identity_public_key))
+                .transpose()?
+        } else {
+            None
+        };
+
+        let identity_secret = if let Some((challenge, identity_secret)) = identity_challenge {
+            trace!("server_kex: sending IdentityChallenge");
+            self.send_absorbed(Frame::IdentityChallenge(challenge))?;
+            Some(identity_secret)
+        } else {
+            None
+        };
+
+        let ikm = session_ikm(shared_secret, identity_secret.as_deref());
+        let session_salt = self
+            .transcript
+            .session_salt(salt_bytes, negotiated.protocol_version);
+        self.emit_session_keys(&ikm, &session_salt, negotiated, tx_event)
+    }
 
-        let (key_bytes, hmac_key_bytes) =
-            derive_session_keys(&shared_secret, &salt_bytes, negotiated)?;
+    /// Derive the session keys from `ikm` and `session_salt` and report them on
+    /// `tx_event`.
+    fn emit_session_keys(
+        &mut self,
+        ikm: &[u8],
+        session_salt: &[u8],
+        negotiated: &NegotiatedAlgorithms,
+        tx_event: &UnboundedSender<KexEvent>,
+    ) -> Result<LessSafeKey> {
+        let aead_alg = resolve_aead_alg(&negotiated.aead)?;
+        let (key_bytes, hmac_key_bytes) = derive_session_keys(ikm, session_salt, negotiated)?;
         debug!(
             side = "server",
-            aead = %kex_aead_log,
+            aead = %negotiated.aead,
             key_len = key_bytes.len(),
             key_hex = %fmt_hex(&key_bytes),
             "kex: derived AEAD key"
         );
         debug!(
             side = "server",
-            mac = %kex_mac_log,
+            mac = %negotiated.mac,
             hmac_key_len = hmac_key_bytes.len(),
             hmac_key_hex = %fmt_hex(&hmac_key_bytes),
             "kex: derived HMAC key"
         );
+        if negotiated.protocol_version >= RESUMPTION_TICKET_MIN_PROTOCOL_VERSION {
+            self.resumption_psk = Some(resumption_psk(&key_bytes, &hmac_key_bytes, negotiated)?);
+        }
 
         tx_event
             .send(KexEvent::KeyMaterial(key_bytes.clone()))
@@ -1333,28 +2314,119 @@ impl KexReader {
         tx_event
             .send(KexEvent::HMACKeyMaterial(hmac_key_bytes))
             .map_err(|_| Unspecified)?;
-        let rnk = LessSafeKey::new(UnboundKey::new(aead_alg, &key_bytes)?);
-        Ok((rnk, server_ephemeral_or_ciphertext, salt_bytes.to_vec()))
+        Ok(LessSafeKey::new(UnboundKey::new(aead_alg, &key_bytes)?))
     }
 
-    fn handle_check(
-        &mut self,
+    /// Add a received frame, if there was one, to the handshake transcript.
+    fn absorb_received(&mut self, frame: Option<&Frame>) -> Result<()> {
+        frame.map_or(Ok(()), |frame| self.transcript.absorb(frame))
+    }
+
+    /// Add `frame` to the handshake transcript and queue it for the peer.
+    fn send_absorbed(&mut self, frame: Frame) -> Result<()> {
+        self.transcript.absorb(&frame)?;
+        self.tx.send(frame)?;
+        Ok(())
+    }
+
+    /// Tell the client why the key exchange failed and build the matching error.
+    ///
+    /// Clients that cannot parse `Frame::KexFailureReason` get a bare
+    /// `Frame::KexFailure`; unless `detailed_auth_failures` is set the reason
+    /// they see is [`redacted`](KexFailureReason::redacted).  The returned error
+    /// keeps the full reason for the server log.
+    fn reject(&self, reason: KexFailureReason) -> anyhow::Error {
+        let frame = match (self.peer_reads_failure_reasons, self.detailed_auth_failures) {
+            (false, _) => Frame::KexFailure,
+            (true, true) => Frame::KexFailureReason(reason),
+            (true, false) => Frame::KexFailureReason(reason.redacted()),
+        };
+        drop(self.tx.send(frame));
+        MoshpitError::KexRejected(reason).into()
+    }
+
+    /// The `Initialize` or `ResumeRequest` naming this client, carrying the
+    /// ephemeral `exchange` value.
+    fn identity_request(&self, exchange: Vec<u8>) -> Frame {
+        let user = self.user.as_bytes().to_vec();
+        let identity_pk = self.full_public_key_bytes.clone();
+        if let Some(session_uuid) = self.requested_session_uuid {
+            Frame::ResumeRequest(UuidWrapper::new(session_uuid), user, exchange, identity_pk)
+        } else {
+            Frame::Initialize(user, exchange, identity_pk)
+        }
+    }
+
+    /// The `TransportPreference` byte for this client's preferred transport.
+    fn transport_preference_byte(&self) -> u8 {
+        match self.transport_preference {
+            TransportMode::Tcp => 1,
+            TransportMode::Udp => 0,
+        }
+    }
+
+    /// Protocol v10+: send a key share for the first key exchange this client
+    /// offers, ahead of the server's `KexInit`.
+    ///
+    /// The frame is not absorbed yet: both sides put it in the transcript after
+    /// the server's `KexInit`.
+    fn send_early_key_share(&self) -> Result<Option<EarlyKeyShare>> {
+        let Some(kex) = self.client_algos.kex.first() else {
+            return Ok(None);
+        };
+        let (ephemeral, exchange) = generate_ephemeral(resolve_kex_alg(kex)?)?;
+        let frame = Frame::EarlyKeyShare(
+            kex.clone(),
+            exchange.clone(),
+            self.transport_preference_byte(),
+        );
+        self.tx.send(frame.clone())?;
+        trace!("client_kex: sent EarlyKeyShare for {kex}");
+        Ok(Some(EarlyKeyShare {
+            kex: kex.clone(),
+            ephemeral,
+            exchange,
+            frame,
+        }))
+    }
+
+    /// Report a reasoned rejection from the server to the state machine.
+    fn rejected(&self, reason: KexFailureReason) -> anyhow::Error {
+        error!("client_kex: server rejected key exchange: {reason}");
+        drop(self.tx_event.send(KexEvent::Rejected(reason)));
+        MoshpitError::KexRejected(reason).into()
+    }
+
+    /// Verify the client's `Check` and apply the session options sealed in it
+    /// to `options`, then send `KeyAgreement`.
+    fn handle_check(
+        &mut self,
         rnk: &LessSafeKey,
         nonce_bytes: [u8; 12],
         mut check_bytes: Vec<u8>,
         tx_event: &UnboundedSender<KexEvent>,
+        options: &mut SessionOptions,
     ) -> Result<()> {
+        if let Some(reason) = self.pending_rejection {
+            return Err(self.reject(reason));
+        }
         let nonce = Nonce::from(&nonce_bytes);
-        let decrypted_data = rnk
-            .open_in_place(nonce, Aad::empty(), &mut check_bytes)
-            .map_err(|_| MoshpitError::DecryptionFailed)?;
-        if decrypted_data == b"Yoda" {
+        let sealed_options = match rnk.open_in_place(nonce, Aad::empty(), &mut check_bytes) {
+            Ok(decrypted_data) => open_check_plaintext(decrypted_data)?,
+            Err(_) => None,
+        };
+        if let Some(sealed_options) = sealed_options {
+            for option in sealed_options {
+                options.apply(option)?;
+            }
             let id = Uuid::new_v4();
             tx_event.send(KexEvent::Uuid(id)).map_err(|_| Unspecified)?;
             self.tx.send(Frame::KeyAgreement(UuidWrapper::new(id)))?;
         } else {
+            // Mismatched keys: most likely the handshake transcripts differ.  Tell
+            // the client instead of just dropping the connection.
             error!("Check frame verification failed");
-            return Err(MoshpitError::DecryptionFailed.into());
+            return Err(self.reject(KexFailureReason::KeyMismatch));
         }
         Ok(())
     }
@@ -1590,24 +2662,43 @@ impl KexReader {
     }
 }
 
-fn check_authorized_keys(home_dir: &str, fpk: &[u8]) -> Result<bool> {
+/// Check `fpk` against `<home_dir>/.mp/authorized_keys`, returning the reason
+/// to reject it, or `None` when the key is listed.
+fn check_authorized_keys(home_dir: &str, fpk: &[u8]) -> Result<Option<KexFailureReason>> {
     let moshpit_path = PathBuf::from(home_dir).join(".mp");
     let authorized_keys_path = moshpit_path.join("authorized_keys");
-    if check_permissions(&moshpit_path, &authorized_keys_path)? {
-        let authorized_keys_file = OpenOptions::new()
-            .read(true)
-            .open(&authorized_keys_path)
-            .map_err(|_e| MoshpitError::KeyNotEstablished)?;
-        let buffered_reader = BufReader::new(authorized_keys_file);
-        let fpk_str = String::from_utf8_lossy(fpk);
-
-        for line in buffered_reader.lines().map_while(Result::ok) {
-            if line == fpk_str {
-                return Ok(true);
-            }
+    if !authorized_keys_path.is_file() {
+        return Ok(Some(KexFailureReason::UnauthorizedKey));
+    }
+    if !check_permissions(&moshpit_path, &authorized_keys_path)? {
+        return Ok(Some(KexFailureReason::AuthorizedKeysPermissions));
+    }
+    let authorized_keys_file = OpenOptions::new()
+        .read(true)
+        .open(&authorized_keys_path)
+        .map_err(|_e| MoshpitError::KeyNotEstablished)?;
+    let buffered_reader = BufReader::new(authorized_keys_file);
+    let fpk_str = String::from_utf8_lossy(fpk);
+
+    for line in buffered_reader.lines().map_while(Result::ok) {
+        if line == fpk_str {
+            return Ok(None);
         }
     }
-    Ok(false)
+    Ok(Some(KexFailureReason::UnauthorizedKey))
+}
+
+/// The access `<home_dir>/.mp/shared_sessions` gives `user` to its owner's
+/// session called `session`.  The file is ignored, sharing nothing, when it
+/// is missing or its permissions are as open as `authorized_keys` may not be.
+fn check_session_grant(home_dir: &str, user: &str, session: &str) -> Result<Option<SessionAccess>> {
+    let moshpit_path = PathBuf::from(home_dir).join(".mp");
+    let grants_path = moshpit_path.join(SHARED_SESSIONS_FILE);
+    if !grants_path.is_file() || !check_permissions(&moshpit_path, &grants_path)? {
+        return Ok(None);
+    }
+    let grants = read_to_string(&grants_path)?;
+    Ok(session_access(&grants, user, session))
 }
 
 #[cfg_attr(windows, allow(clippy::unnecessary_wraps))]
@@ -1751,8 +2842,12 @@ fn windows_only_owner_has_access(path: &Path) -> bool {
     result
 }
 
+/// Check `pk` against the `known_hosts` entry for `host`.  Without one, an
+/// entry under one of the `legacy_hosts` keys older releases wrote stands in
+/// for it, and a key it vouches for is recorded under `host` as well.
 fn check_known_hosts(
     host: &str,
+    legacy_hosts: &[String],
     pk: &[u8],
     tofu_fn: Option<&TofuFn>,
     mismatch_fn: Option<&HostKeyMismatchFn>,
@@ -1765,25 +2860,40 @@ fn check_known_hosts(
 
     if known_hosts_path.exists() {
         let content = read_to_string(&known_hosts_path)?;
-        for line in content.lines() {
-            let mut parts = line.split_whitespace();
-            if let (Some(h), Some(k)) = (parts.next(), parts.next())
-                && h == host
-            {
-                if k == pk_b64 {
-                    return Ok(true);
-                }
-                let old_fingerprint = key_fingerprint_from_b64(k);
-                let new_fingerprint = STANDARD.encode(digest(&SHA256, pk));
-                error!("HOST KEY VERIFICATION FAILED for {host}!");
-                if let Some(prompt_replace) = mismatch_fn
-                    && prompt_replace(host, &old_fingerprint, &new_fingerprint)?
-                {
-                    replace_known_host_key(host, &pk_b64)?;
-                    return Ok(true);
+        let entries: Vec<(&str, &str)> = content
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                parts.next().zip(parts.next())
+            })
+            .collect();
+        let pinned = entries
+            .iter()
+            .find(|(h, _)| *h == host)
+            .map(|(_, k)| (*k, false))
+            .or_else(|| {
+                entries
+                    .iter()
+                    .find(|(h, _)| legacy_hosts.iter().any(|legacy| legacy == h))
+                    .map(|(_, k)| (*k, true))
+            });
+        if let Some((k, legacy)) = pinned {
+            if k == pk_b64 {
+                if legacy {
+                    append_known_host(&known_hosts_path, host, &pk_b64)?;
                 }
-                return Ok(false);
+                return Ok(true);
+            }
+            let old_fingerprint = key_fingerprint_from_b64(k);
+            let new_fingerprint = STANDARD.encode(digest(&SHA256, pk));
+            error!("HOST KEY VERIFICATION FAILED for {host}!");
+            if let Some(prompt_replace) = mismatch_fn
+                && prompt_replace(host, &old_fingerprint, &new_fingerprint)?
+            {
+                replace_known_host_key(host, &pk_b64)?;
+                return Ok(true);
             }
+            return Ok(false);
         }
     }
 
@@ -1791,16 +2901,7 @@ fn check_known_hosts(
     if let Some(tofu) = tofu_fn {
         let fingerprint = STANDARD.encode(digest(&SHA256, pk));
         if tofu(host, &fingerprint)? {
-            // Save it
-            use std::io::Write;
-            if let Some(parent) = known_hosts_path.parent() {
-                create_dir_all(parent)?;
-            }
-            let mut file = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&known_hosts_path)?;
-            writeln!(file, "{host} {pk_b64}")?;
+            append_known_host(&known_hosts_path, host, &pk_b64)?;
             Ok(true)
         } else {
             Ok(false)
@@ -1811,6 +2912,20 @@ fn check_known_hosts(
     }
 }
 
+fn append_known_host(known_hosts_path: &Path, host: &str, pk_b64: &str) -> Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = known_hosts_path.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(known_hosts_path)?;
+    writeln!(file, "{host} {pk_b64}")?;
+    Ok(())
+}
+
 fn key_fingerprint_from_b64(key_b64: &str) -> String {
     use aws_lc_rs::digest::{SHA256, digest};
 
@@ -1874,7 +2989,7 @@ mod tests {
         io::Write,
         iter::from_fn,
         net::SocketAddr,
-        os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+        os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt},
         path::PathBuf,
         sync::{Arc, Mutex, OnceLock},
     };
@@ -1889,12 +3004,21 @@ mod tests {
         Ciphertext, DecapsulationKey, EncapsulationKey, ML_KEM_512, ML_KEM_768, ML_KEM_1024,
     };
 
-    use super::{check_authorized_keys, check_known_hosts, derive_session_keys};
+    use super::{
+        answer_identity_challenge, check_authorized_keys, check_known_hosts, check_session_grant,
+        derive_session_keys, hybrid_client_secret, hybrid_server_exchange,
+        issue_identity_challenge, resolve_identity_agreement_alg, session_ikm,
+    };
+    use crate::kex::failure::KexFailureReason;
+    use crate::kex::identity::host_proof;
     use crate::kex::negotiate::{
         AEAD_AES256_GCM_SIV, KDF_HKDF_SHA256, KEX_ML_KEM_512_SHA256, KEX_ML_KEM_768_SHA256,
-        KEX_ML_KEM_1024_SHA256, MAC_HMAC_SHA512, NegotiatedAlgorithms,
+        KEX_ML_KEM_1024_SHA256, KEX_MLKEM768_X25519_SHA256, MAC_HMAC_SHA512, NegotiatedAlgorithms,
     };
+    use crate::kex::options::SessionOptions;
+    use crate::kex::transcript::Transcript;
     use crate::kex::{HostKeyMismatchFn, TofuFn};
+    use crate::session::SessionAccess;
 
     /// Tests that mutate the `HOME` environment variable must hold this lock
     /// to prevent races with concurrently-running tests in the same process.
@@ -1974,6 +3098,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn hybrid_round_trip_derives_same_session_keys() {
+        use aws_lc_rs::agreement::{PrivateKey, X25519};
+
+        let super::ResolvedKexAlgorithm::Hybrid(kem_algorithm) =
+            super::resolve_kex_alg(KEX_MLKEM768_X25519_SHA256).expect("resolve hybrid kex")
+        else {
+            panic!("{KEX_MLKEM768_X25519_SHA256} should map to a hybrid algorithm");
+        };
+        let decapsulation_key =
+            DecapsulationKey::generate(kem_algorithm).expect("test ML-KEM key generation");
+        let encapsulation_key = decapsulation_key
+            .encapsulation_key()
+            .expect("encapsulation key from decapsulation key");
+        let encapsulation_key_bytes = encapsulation_key
+            .key_bytes()
+            .expect("encapsulation key bytes");
+        let epk = PrivateKey::generate(&X25519).expect("X25519 key generation");
+        let epk_pub = epk.compute_public_key().expect("X25519 public key");
+        let mut client_exchange = encapsulation_key_bytes.as_ref().to_vec();
+        client_exchange.extend_from_slice(epk_pub.as_ref());
+
+        let (server_exchange, server_secret) =
+            hybrid_server_exchange(kem_algorithm, &client_exchange).expect("server exchange");
+        let client_secret = hybrid_client_secret(&decapsulation_key, &epk, &server_exchange)
+            .expect("client secret");
+        assert_eq!(server_secret, client_secret);
+        // ML-KEM-768 and X25519 each contribute a 32-byte secret.
+        assert_eq!(server_secret.len(), 64);
+
+        let negotiated = NegotiatedAlgorithms {
+            kex: KEX_MLKEM768_X25519_SHA256.to_string(),
+            ..NegotiatedAlgorithms::default()
+        };
+        let salt = [7u8; 32];
+        let server_keys = derive_session_keys(&server_secret, &salt, &negotiated)
+            .expect("derive server session keys");
+        let client_keys = derive_session_keys(&client_secret, &salt, &negotiated)
+            .expect("derive client session keys");
+        assert_eq!(server_keys, client_keys);
+    }
+
+    #[test]
+    fn hybrid_rejects_truncated_exchange_values() {
+        assert!(hybrid_server_exchange(&ML_KEM_768, &[0u8; 32]).is_err());
+        assert!(hybrid_server_exchange(&ML_KEM_768, &[0u8; 16]).is_err());
+        assert!(hybrid_server_exchange(&ML_KEM_768, &[0u8; 40]).is_err());
+    }
+
     #[test]
     fn ml_kem_rejects_mismatched_or_malformed_inputs() {
         let decapsulation_key =
@@ -1997,6 +3170,107 @@ mod tests {
         );
     }
 
+    #[test]
+    fn identity_challenge_round_trip_derives_same_session_keys() {
+        use aws_lc_rs::agreement::PrivateKey;
+        use aws_lc_rs::encoding::{AsBigEndian as _, Curve25519SeedBin, EcPrivateKeyBin};
+
+        for key_alg in [
+            crate::KEY_ALGORITHM_X25519,
+            crate::KEY_ALGORITHM_P256,
+            crate::KEY_ALGORITHM_P384,
+        ] {
+            let agreement_alg =
+                resolve_identity_agreement_alg(key_alg).expect("ECDH identity algorithm");
+            let identity = PrivateKey::generate(agreement_alg).expect("identity key");
+            let identity_pub = identity.compute_public_key().expect("identity public key");
+            let identity_priv = if key_alg == crate::KEY_ALGORITHM_X25519 {
+                let bytes: Curve25519SeedBin<'_> = identity.as_be_bytes().expect("seed bytes");
+                bytes.as_ref().to_vec()
+            } else {
+                let bytes: EcPrivateKeyBin<'_> = identity.as_be_bytes().expect("scalar bytes");
+                bytes.as_ref().to_vec()
+            };
+
+            let (challenge, server_secret) =
+                issue_identity_challenge(agreement_alg, identity_pub.as_ref())
+                    .expect("issue identity challenge");
+            let client_secret = answer_identity_challenge(key_alg, &identity_priv, &challenge)
+                .expect("answer identity challenge");
+            assert_eq!(server_secret, client_secret);
+
+            let negotiated = NegotiatedAlgorithms::default();
+            let salt = [3u8; 32];
+            let kex_secret = [9u8; 32];
+            let server_keys = derive_session_keys(
+                &session_ikm(&kex_secret, Some(&server_secret)),
+                &salt,
+                &negotiated,
+            )
+            .expect("derive server session keys");
+            let client_keys = derive_session_keys(
+                &session_ikm(&kex_secret, Some(&client_secret)),
+                &salt,
+                &negotiated,
+            )
+            .expect("derive client session keys");
+            assert_eq!(server_keys, client_keys);
+        }
+    }
+
+    #[test]
+    fn identity_challenge_without_private_key_derives_different_keys() {
+        use aws_lc_rs::agreement::{PrivateKey, X25519};
+        use aws_lc_rs::encoding::{AsBigEndian as _, Curve25519SeedBin};
+
+        // The real identity whose public key the attacker copied.
+        let identity = PrivateKey::generate(&X25519).expect("identity key");
+        let identity_pub = identity.compute_public_key().expect("identity public key");
+        // The attacker's own key, used in place of the missing private half.
+        let impostor = PrivateKey::generate(&X25519).expect("impostor key");
+        let impostor_seed: Curve25519SeedBin<'_> = impostor.as_be_bytes().expect("seed bytes");
+
+        let (challenge, server_secret) = issue_identity_challenge(&X25519, identity_pub.as_ref())
+            .expect("issue identity challenge");
+        let impostor_secret = answer_identity_challenge(
+            crate::KEY_ALGORITHM_X25519,
+            impostor_seed.as_ref(),
+            &challenge,
+        )
+        .expect("answer identity challenge");
+        assert_ne!(server_secret, impostor_secret);
+
+        let negotiated = NegotiatedAlgorithms::default();
+        let salt = [3u8; 32];
+        let kex_secret = [9u8; 32];
+        let server_keys = derive_session_keys(
+            &session_ikm(&kex_secret, Some(&server_secret)),
+            &salt,
+            &negotiated,
+        )
+        .expect("derive server session keys");
+        // Skipping the identity secret entirely must not match either.
+        let skipped_keys = derive_session_keys(&session_ikm(&kex_secret, None), &salt, &negotiated)
+            .expect("derive session keys without identity secret");
+        let impostor_keys = derive_session_keys(
+            &session_ikm(&kex_secret, Some(&impostor_secret)),
+            &salt,
+            &negotiated,
+        )
+        .expect("derive impostor session keys");
+        assert_ne!(server_keys, skipped_keys);
+        assert_ne!(server_keys, impostor_keys);
+    }
+
+    #[test]
+    fn resolve_identity_agreement_alg_only_accepts_ecdh_identities() {
+        assert!(resolve_identity_agreement_alg(crate::KEY_ALGORITHM_X25519).is_some());
+        assert!(resolve_identity_agreement_alg(crate::KEY_ALGORITHM_P256).is_some());
+        assert!(resolve_identity_agreement_alg(crate::KEY_ALGORITHM_P384).is_some());
+        assert!(resolve_identity_agreement_alg("ML-DSA-65").is_none());
+        assert!(answer_identity_challenge("ML-DSA-65", &[], &[]).is_err());
+    }
+
     #[cfg(feature = "unstable")]
     #[test]
     fn ml_dsa_identity_transcript_signature_verifies() {
@@ -2060,7 +3334,7 @@ mod tests {
         // Point HOME at the temp dir so check_known_hosts finds our file.
         // SAFETY: test-only; serialized via home_lock.
         unsafe { set_var("HOME", dir.path()) };
-        let result = check_known_hosts(host, pk, None, None).expect("check_known_hosts");
+        let result = check_known_hosts(host, &[], pk, None, None).expect("check_known_hosts");
         assert!(result, "matching key should be accepted");
     }
 
@@ -2075,7 +3349,8 @@ mod tests {
         write_known_hosts(&dir, host, pinned_pk);
         // SAFETY: test-only; serialized via home_lock.
         unsafe { set_var("HOME", dir.path()) };
-        let result = check_known_hosts(host, attacker_pk, None, None).expect("check_known_hosts");
+        let result =
+            check_known_hosts(host, &[], attacker_pk, None, None).expect("check_known_hosts");
         assert!(!result, "mismatched host key must be rejected");
     }
 
@@ -2092,8 +3367,8 @@ mod tests {
         unsafe { set_var("HOME", dir.path()) };
 
         let mismatch_fn: HostKeyMismatchFn = Arc::new(|_h, _old_fp, _new_fp| Ok(true));
-        let result =
-            check_known_hosts(host, new_pk, None, Some(&mismatch_fn)).expect("check_known_hosts");
+        let result = check_known_hosts(host, &[], new_pk, None, Some(&mismatch_fn))
+            .expect("check_known_hosts");
         assert!(result, "accepted replacement should return true");
 
         let content = read_to_string(dir.path().join(".mp").join("known_hosts"))
@@ -2116,8 +3391,8 @@ mod tests {
         unsafe { set_var("HOME", dir.path()) };
 
         let mismatch_fn: HostKeyMismatchFn = Arc::new(|_h, _old_fp, _new_fp| Ok(false));
-        let result =
-            check_known_hosts(host, new_pk, None, Some(&mismatch_fn)).expect("check_known_hosts");
+        let result = check_known_hosts(host, &[], new_pk, None, Some(&mismatch_fn))
+            .expect("check_known_hosts");
         assert!(!result, "rejected replacement should return false");
 
         let content = read_to_string(dir.path().join(".mp").join("known_hosts"))
@@ -2134,6 +3409,42 @@ mod tests {
         assert_ne!(fp, "");
     }
 
+    /// An entry under the key an older release used vouches for the host,
+    /// which is then recorded under its current key.
+    #[test]
+    fn check_known_hosts_migrates_a_legacy_entry() {
+        let _guard = home_lock().lock().expect("home mutex not poisoned");
+        let dir = TempDir::new().expect("temp dir creation");
+        let pk = b"server-public-key-bytes";
+        let legacy = "alice@192.0.2.30".to_string();
+        let host = "192.0.2.30";
+        write_known_hosts(&dir, &legacy, pk);
+        // SAFETY: test-only; serialized via home_lock.
+        unsafe { set_var("HOME", dir.path()) };
+
+        let result = check_known_hosts(host, std::slice::from_ref(&legacy), pk, None, None)
+            .expect("check_known_hosts");
+        assert!(result, "the legacy entry's key should be accepted");
+        let content = read_to_string(dir.path().join(".mp").join("known_hosts"))
+            .expect("read known_hosts file");
+        assert!(content.contains(&format!("{host} {}", STANDARD.encode(pk))));
+
+        // Another key is a mismatch, not an unknown host.
+        let tofu_fn: TofuFn = Arc::new(|_host, _fp| Ok(true));
+        let result = check_known_hosts(
+            "192.0.2.30:2222",
+            &[legacy],
+            b"mitm-attacker-key",
+            Some(&tofu_fn),
+            None,
+        )
+        .expect("check_known_hosts");
+        assert!(
+            !result,
+            "a key the legacy entry does not match must be rejected"
+        );
+    }
+
     /// Unknown host + TOFU callback that returns `true` → accepted and saved.
     #[test]
     fn check_known_hosts_tofu_accept() {
@@ -2146,7 +3457,8 @@ mod tests {
         // SAFETY: test-only; serialized via home_lock.
         unsafe { set_var("HOME", dir.path()) };
         let tofu_fn: TofuFn = Arc::new(|_host, _fp| Ok(true));
-        let result = check_known_hosts(host, pk, Some(&tofu_fn), None).expect("check_known_hosts");
+        let result =
+            check_known_hosts(host, &[], pk, Some(&tofu_fn), None).expect("check_known_hosts");
         assert!(result, "TOFU accept should return true");
         // Key should now be persisted.
         let kh_content = read_to_string(dir.path().join(".mp").join("known_hosts"))
@@ -2168,7 +3480,8 @@ mod tests {
         // SAFETY: test-only; serialized via home_lock.
         unsafe { set_var("HOME", dir.path()) };
         let tofu_fn: TofuFn = Arc::new(|_host, _fp| Ok(false));
-        let result = check_known_hosts(host, pk, Some(&tofu_fn), None).expect("check_known_hosts");
+        let result =
+            check_known_hosts(host, &[], pk, Some(&tofu_fn), None).expect("check_known_hosts");
         assert!(!result, "TOFU reject should return false");
     }
 
@@ -2182,7 +3495,7 @@ mod tests {
         create_dir_all(dir.path().join(".mp")).expect("create .mp dir");
         // SAFETY: test-only; serialized via home_lock.
         unsafe { set_var("HOME", dir.path()) };
-        let result = check_known_hosts(host, pk, None, None).expect("check_known_hosts");
+        let result = check_known_hosts(host, &[], pk, None, None).expect("check_known_hosts");
         assert!(!result, "no TOFU callback must fail closed");
     }
 
@@ -2198,7 +3511,10 @@ mod tests {
         write_authorized_keys(&dir, key_bytes, 0o600);
         let home_str = dir.path().to_str().expect("test path is valid UTF-8");
         let result = check_authorized_keys(home_str, key_bytes).expect("check_authorized_keys");
-        assert!(result, "matching key in authorized_keys should be accepted");
+        assert_eq!(
+            result, None,
+            "matching key in authorized_keys should be accepted"
+        );
     }
 
     /// Key NOT present in `authorized_keys` → rejected.
@@ -2210,7 +3526,11 @@ mod tests {
         write_authorized_keys(&dir, stored_key, 0o600);
         let home_str = dir.path().to_str().expect("test path is valid UTF-8");
         let result = check_authorized_keys(home_str, presented_key).expect("check_authorized_keys");
-        assert!(!result, "key not in authorized_keys should be rejected");
+        assert_eq!(
+            result,
+            Some(KexFailureReason::UnauthorizedKey),
+            "key not in authorized_keys should be rejected"
+        );
     }
 
     /// `authorized_keys` with 0o644 permissions (group/world-readable) → rejected.
@@ -2221,12 +3541,60 @@ mod tests {
         write_authorized_keys(&dir, key_bytes, 0o644);
         let home_str = dir.path().to_str().expect("test path is valid UTF-8");
         let result = check_authorized_keys(home_str, key_bytes).expect("check_authorized_keys");
-        assert!(
-            !result,
+        assert_eq!(
+            result,
+            Some(KexFailureReason::AuthorizedKeysPermissions),
             "world-readable authorized_keys must be rejected (permission check)"
         );
     }
 
+    /// No `authorized_keys` file at all → the key is simply not authorized.
+    #[test]
+    fn check_authorized_keys_missing_file() {
+        let dir = TempDir::new().expect("temp dir creation");
+        let home_str = dir.path().to_str().expect("test path is valid UTF-8");
+        let result = check_authorized_keys(home_str, b"any-key").expect("check_authorized_keys");
+        assert_eq!(result, Some(KexFailureReason::UnauthorizedKey));
+    }
+
+    /// `shared_sessions` is honoured only with the same permissions as
+    /// `authorized_keys`; without it nothing is shared.
+    #[test]
+    fn check_session_grant_needs_private_grants() {
+        let dir = TempDir::new().expect("temp dir creation");
+        let home_str = dir.path().to_str().expect("test path is valid UTF-8");
+        write_authorized_keys(&dir, b"key", 0o600);
+        assert_eq!(
+            check_session_grant(home_str, "bob", "work").expect("check_session_grant"),
+            None
+        );
+        let grants = dir.path().join(".mp").join("shared_sessions");
+        let mut f = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .mode(0o644)
+            .open(&grants)
+            .expect("create shared_sessions file");
+        f.write_all(b"bob work read-write\n")
+            .expect("write shared_sessions content");
+        assert_eq!(
+            check_session_grant(home_str, "bob", "work").expect("check_session_grant"),
+            None,
+            "world-readable shared_sessions must be ignored"
+        );
+        std::fs::set_permissions(&grants, std::fs::Permissions::from_mode(0o600))
+            .expect("restrict shared_sessions");
+        assert_eq!(
+            check_session_grant(home_str, "bob", "work").expect("check_session_grant"),
+            Some(SessionAccess::ReadWrite)
+        );
+        assert_eq!(
+            check_session_grant(home_str, "carol", "work").expect("check_session_grant"),
+            None
+        );
+    }
+
     // -----------------------------------------------------------------------
     // Algorithm resolution helper tests
     // -----------------------------------------------------------------------
@@ -2303,19 +3671,36 @@ mod tests {
 
     #[test]
     fn resolve_hmac_key_type_all_supported_succeed() {
-        use crate::kex::negotiate::MAC_HMAC_SHA256;
+        use crate::kex::negotiate::{MAC_AEAD_IMPLICIT, MAC_HMAC_SHA256};
         for mac in [MAC_HMAC_SHA512, MAC_HMAC_SHA256] {
             assert!(
-                super::resolve_hmac_key_type(mac).is_ok(),
+                super::resolve_hmac_key_type(mac).is_ok_and(|alg| alg.is_some()),
                 "{mac} should resolve OK"
             );
         }
+        assert!(
+            super::resolve_hmac_key_type(MAC_AEAD_IMPLICIT).is_ok_and(|alg| alg.is_none()),
+            "aead-implicit has no HMAC key"
+        );
         assert!(
             super::resolve_hmac_key_type("unknown-mac").is_err(),
             "unknown MAC must return an error"
         );
     }
 
+    #[test]
+    fn derive_session_keys_aead_implicit_has_no_hmac_key() {
+        use crate::kex::negotiate::MAC_AEAD_IMPLICIT;
+        let negotiated = NegotiatedAlgorithms {
+            mac: MAC_AEAD_IMPLICIT.to_string(),
+            ..NegotiatedAlgorithms::default()
+        };
+        let (key, hmac_key) =
+            derive_session_keys(&[9u8; 32], &[7u8; 32], &negotiated).expect("derive keys");
+        assert_eq!(key.len(), 32);
+        assert!(hmac_key.is_empty());
+    }
+
     // -----------------------------------------------------------------------
     // Helpers for KexReader / client_kex / handle_check / handle_udp_setup tests
     // -----------------------------------------------------------------------
@@ -2325,7 +3710,8 @@ mod tests {
 
     use aws_lc_rs::{
         aead::{AES_256_GCM_SIV, Aad, LessSafeKey, NONCE_LEN, UnboundKey},
-        agreement::{PrivateKey, X25519},
+        agreement::{ECDH_P256, PrivateKey, UnparsedPublicKey, X25519, agree},
+        error::Unspecified,
         rand::fill,
     };
     use tokio::sync::Mutex as TokioMutex;
@@ -2335,7 +3721,10 @@ mod tests {
     };
 
     use crate::{
-        ConnectionReader, ConnectionWriter, Frame, KexEvent, UuidWrapper, supported_algorithms,
+        AlgorithmList, ConnectionReader, ConnectionWriter, Frame, KexEvent, UuidWrapper,
+        kex::negotiate::{KEX_P256_SHA256, KEX_X25519_SHA256},
+        kex::ticket::{ResumptionTicket, TicketIssuer},
+        new_session_registry, supported_algorithms,
     };
 
     /// Create two connected TCP stream pairs.
@@ -2390,6 +3779,137 @@ mod tests {
         (kex_reader, rx_frames, rx_events)
     }
 
+    /// A mock server's side of a protocol v8+ handshake: records the transcript
+    /// and answers the client's host-key challenge as `prove_host_key` does.
+    struct MockHost {
+        frames: Vec<Frame>,
+        host_key: PrivateKey,
+        ephemeral: PrivateKey,
+    }
+
+    impl MockHost {
+        /// Start from the `KexInit` a default test client sends.
+        fn new() -> Self {
+            Self::for_client(supported_algorithms())
+        }
+
+        /// Start from the `KexInit` of a test client offering `client_algos`.
+        fn for_client(client_algos: AlgorithmList) -> Self {
+            Self {
+                frames: vec![Frame::KexInit(
+                    client_algos,
+                    crate::kex::negotiate::local_protocol_support(),
+                )],
+                host_key: PrivateKey::generate(&X25519).expect("generate test host key"),
+                ephemeral: PrivateKey::generate(&X25519).expect("generate test X25519 key"),
+            }
+        }
+
+        /// Record `frame` in the transcript and hand it back.
+        fn record(&mut self, frame: Option<Frame>) -> &Frame {
+            self.frames.push(frame.expect("handshake frame"));
+            self.frames.last().expect("just pushed")
+        }
+
+        /// The `PeerInitialize` naming the host key, recorded in the transcript.
+        fn peer_initialize(&mut self) -> Frame {
+            let peer_initialize = Frame::PeerInitialize(
+                self.host_key
+                    .compute_public_key()
+                    .expect("compute public key")
+                    .as_ref()
+                    .to_vec(),
+                self.ephemeral
+                    .compute_public_key()
+                    .expect("compute public key")
+                    .as_ref()
+                    .to_vec(),
+                vec![7u8; 32],
+            );
+            self.frames.push(peer_initialize.clone());
+            peer_initialize
+        }
+
+        /// The `IdentityProof` answering the last recorded frame, the client's
+        /// `IdentityChallenge`, recorded in the transcript.
+        fn host_proof(&mut self) -> Frame {
+            let mut transcript = Transcript::default();
+            for frame in &self.frames {
+                transcript.absorb(frame).expect("absorb frame");
+            }
+            let Some(client_exchange) = self.frames.iter().find_map(|f| match f {
+                Frame::HiddenInitialize(exchange) | Frame::EarlyKeyShare(_, exchange, _) => {
+                    Some(exchange)
+                }
+                _ => None,
+            }) else {
+                panic!("the client never sent HiddenInitialize");
+            };
+            let Some(Frame::IdentityChallenge(challenge)) = self.frames.last() else {
+                panic!("the client has not challenged the host key");
+            };
+            let agree_with = |key: &PrivateKey, peer: &[u8]| {
+                agree(
+                    key,
+                    UnparsedPublicKey::new(&X25519, peer),
+                    Unspecified,
+                    |key_material| Ok(key_material.to_vec()),
+                )
+                .expect("agree")
+            };
+            let ikm = session_ikm(
+                &agree_with(&self.ephemeral, client_exchange),
+                Some(&agree_with(&self.host_key, challenge)),
+            );
+            let negotiated = NegotiatedAlgorithms::default();
+            let salt = transcript.session_salt(&[7u8; 32], negotiated.protocol_version);
+            let proof = Frame::IdentityProof(
+                host_proof(&ikm, &salt, &negotiated).expect("derive host proof"),
+            );
+            self.frames.push(proof.clone());
+            proof
+        }
+    }
+
+    /// Write an unencrypted X25519 key pair into `dir`.
+    ///
+    /// Returns `(private_key_path, public_key_path)`.
+    fn write_key_pair(dir: &TempDir, name: &str) -> (PathBuf, PathBuf) {
+        let key_pair = crate::KeyPair::generate_key_pair(
+            None,
+            crate::KexMode::Server("0.0.0.0:0".parse().expect("hardcoded address")),
+            crate::KEY_ALGORITHM_X25519,
+        )
+        .expect("generate key pair");
+        let private_key_path = dir.path().join(name);
+        let public_key_path = dir.path().join(format!("{name}.pub"));
+        key_pair
+            .write_private_key(&mut File::create(&private_key_path).expect("create key file"))
+            .expect("write private key");
+        key_pair
+            .write_public_key(&mut File::create(&public_key_path).expect("create key file"))
+            .expect("write public key");
+        (private_key_path, public_key_path)
+    }
+
+    /// Forward the frames a `KexReader` queues on `rx` to `writer`, as the kex
+    /// sender task does.  Resolves to every frame sent once `rx` closes.
+    fn forward_frames(
+        mut rx: UnboundedReceiver<Frame>,
+        mut writer: ConnectionWriter,
+    ) -> tokio::task::JoinHandle<Vec<Frame>> {
+        spawn(async move {
+            let mut sent = Vec::new();
+            while let Some(frame) = rx.recv().await {
+                if writer.write_frame(&frame).await.is_err() {
+                    break;
+                }
+                sent.push(frame);
+            }
+            sent
+        })
+    }
+
     // -----------------------------------------------------------------------
     // handle_udp_setup tests
     // -----------------------------------------------------------------------
@@ -2519,7 +4039,13 @@ mod tests {
 
         let (tx_event_clone, _rx_event_clone) = unbounded_channel::<KexEvent>();
         kex_reader
-            .handle_check(&rnk, nonce_bytes, plaintext, &tx_event_clone)
+            .handle_check(
+                &rnk,
+                nonce_bytes,
+                plaintext,
+                &tx_event_clone,
+                &mut SessionOptions::default(),
+            )
             .expect("handle_check with valid Yoda payload");
 
         // Should have sent KeyAgreement frame via kex_reader's own tx
@@ -2531,78 +4057,391 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn handle_check_invalid_payload_returns_decryption_failed() {
-        use crate::MoshpitError;
+    async fn handle_check_applies_the_sealed_options() {
+        use crate::{MoshpitError, kex::options::check_plaintext, udp::DiffMode};
 
         let (client_reader, _cw, _sr, _sw) = make_bidirectional_loopback().await;
-        let (mut kex_reader, _rx_frames, _rx_events) = make_test_kex_reader(client_reader);
-
-        let key_bytes = [1u8; 32];
+        let (mut kex_reader, mut rx_frames, _rx_events) = make_test_kex_reader(client_reader);
         let rnk = LessSafeKey::new(
-            UnboundKey::new(&AES_256_GCM_SIV, &key_bytes).expect("test AES-256-GCM-SIV key setup"),
+            UnboundKey::new(&AES_256_GCM_SIV, &[1u8; 32]).expect("test AES-256-GCM-SIV key setup"),
         );
-
+        let mut check = check_plaintext(&[Frame::ClientOptions(2)]).expect("encode options");
         let nonce_bytes = [0u8; NONCE_LEN];
-        let garbage = vec![0u8; 32]; // not a valid ciphertext
+        let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes).expect("create nonce");
+        rnk.seal_in_place_append_tag(nonce, Aad::empty(), &mut check)
+            .expect("seal in place");
+        let (tx_event_clone, _rx_event_clone) = unbounded_channel::<KexEvent>();
 
-        let (tx_event_clone, _) = unbounded_channel::<KexEvent>();
-        let result = kex_reader.handle_check(&rnk, nonce_bytes, garbage, &tx_event_clone);
-        assert!(result.is_err());
+        // Flipping a bit in transit does not change the options: the Check fails.
+        let mut tampered = check.clone();
+        tampered[4] ^= 1;
+        let mut options = SessionOptions::default();
         assert!(
-            result
-                .expect_err("expected decryption error")
+            kex_reader
+                .handle_check(&rnk, nonce_bytes, tampered, &tx_event_clone, &mut options)
+                .expect_err("expected a key mismatch")
                 .downcast_ref::<MoshpitError>()
-                .is_some_and(|e| *e == MoshpitError::DecryptionFailed),
+                .is_some_and(|e| *e == MoshpitError::KexRejected(KexFailureReason::KeyMismatch)),
         );
-    }
+        assert_eq!(options, SessionOptions::default());
+        assert_eq!(rx_frames.recv().await, Some(Frame::KexFailure));
 
-    // -----------------------------------------------------------------------
-    // client_kex tests
-    // -----------------------------------------------------------------------
-
-    #[tokio::test]
-    async fn client_kex_server_closes_immediately_returns_error() {
-        let (client_reader, _client_writer, _server_reader, server_writer) =
-            make_bidirectional_loopback().await;
-        let (mut kex_reader, _rx_frames, _rx_events) = make_test_kex_reader(client_reader);
-
-        // Drop server writer immediately — client reads EOF
-        drop(server_writer);
-
-        let result = kex_reader.client_kex().await;
-        assert!(
-            result.is_err(),
-            "expected error when server closes immediately"
-        );
+        kex_reader
+            .handle_check(&rnk, nonce_bytes, check, &tx_event_clone, &mut options)
+            .expect("handle_check with sealed options");
+        assert_eq!(options.diff_mode, DiffMode::StateSync);
+        assert!(matches!(
+            rx_frames.recv().await,
+            Some(Frame::KeyAgreement(_))
+        ));
     }
 
     #[tokio::test]
-    async fn client_kex_kex_failure_before_kex_init_returns_no_common_algorithm() {
-        use crate::MoshpitError;
-
-        let (client_reader, _client_writer, _server_reader, mut server_writer) =
-            make_bidirectional_loopback().await;
-        let (mut kex_reader, _rx_frames, _rx_events) = make_test_kex_reader(client_reader);
+    async fn handle_check_refuses_a_tampered_agent_forward() {
+        use crate::{
+            AGENT_FORWARDING_MIN_PROTOCOL_VERSION, MoshpitError, kex::options::check_plaintext,
+        };
 
-        // Server rejects immediately with KexFailure (e.g. no common algorithm)
-        server_writer
-            .write_frame(&Frame::KexFailure)
-            .await
-            .expect("write KexFailure frame");
-        drop(server_writer);
+        let (client_reader, _cw, _sr, _sw) = make_bidirectional_loopback().await;
+        let (mut kex_reader, mut rx_frames, _rx_events) = make_test_kex_reader(client_reader);
+        let rnk = LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM_SIV, &[1u8; 32]).expect("test AES-256-GCM-SIV key setup"),
+        );
+        let mut check = check_plaintext(&[Frame::AgentForward]).expect("encode options");
+        let nonce_bytes = [0u8; NONCE_LEN];
+        let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes).expect("create nonce");
+        rnk.seal_in_place_append_tag(nonce, Aad::empty(), &mut check)
+            .expect("seal in place");
+        let (tx_event_clone, _rx_event_clone) = unbounded_channel::<KexEvent>();
 
-        let result = kex_reader.client_kex().await;
-        assert!(result.is_err());
+        // The opt-in cannot be flipped in transit: the Check fails instead.
+        let mut tampered = check.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        let mut options = SessionOptions::new(AGENT_FORWARDING_MIN_PROTOCOL_VERSION);
         assert!(
-            result
-                .expect_err("expected NoCommonAlgorithm error")
+            kex_reader
+                .handle_check(&rnk, nonce_bytes, tampered, &tx_event_clone, &mut options)
+                .expect_err("expected a key mismatch")
                 .downcast_ref::<MoshpitError>()
-                .is_some_and(|e| *e == MoshpitError::NoCommonAlgorithm),
+                .is_some_and(|e| *e == MoshpitError::KexRejected(KexFailureReason::KeyMismatch)),
         );
+        assert!(!options.forward_agent);
+        assert_eq!(rx_frames.recv().await, Some(Frame::KexFailure));
+
+        kex_reader
+            .handle_check(&rnk, nonce_bytes, check, &tx_event_clone, &mut options)
+            .expect("handle_check with a sealed AgentForward");
+        assert!(options.forward_agent);
     }
 
     #[tokio::test]
-    async fn client_kex_unexpected_initial_frame_returns_key_not_established() {
+    async fn handle_check_refuses_a_remote_command_changed_in_transit() {
+        use crate::{
+            MoshpitError, REMOTE_COMMAND_MIN_PROTOCOL_VERSION, RemoteCommand,
+            kex::options::{CHECK_VALUE, check_plaintext},
+        };
+
+        let (client_reader, _cw, _sr, _sw) = make_bidirectional_loopback().await;
+        let (mut kex_reader, mut rx_frames, _rx_events) = make_test_kex_reader(client_reader);
+        let rnk = LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM_SIV, &[1u8; 32]).expect("test AES-256-GCM-SIV key setup"),
+        );
+        let mut check = check_plaintext(&[Frame::RemoteCommand("uptime".to_string(), true)])
+            .expect("encode options");
+        let nonce_bytes = [0u8; NONCE_LEN];
+        let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes).expect("create nonce");
+        rnk.seal_in_place_append_tag(nonce, Aad::empty(), &mut check)
+            .expect("seal in place");
+        let (tx_event_clone, _rx_event_clone) = unbounded_channel::<KexEvent>();
+
+        // Changing the sealed command in transit fails the Check rather than
+        // running something the client never asked for.
+        let mut tampered = check.clone();
+        tampered[CHECK_VALUE.len() + 4] ^= 1;
+        let mut options = SessionOptions::new(REMOTE_COMMAND_MIN_PROTOCOL_VERSION);
+        assert!(
+            kex_reader
+                .handle_check(&rnk, nonce_bytes, tampered, &tx_event_clone, &mut options)
+                .expect_err("expected a key mismatch")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(|e| *e == MoshpitError::KexRejected(KexFailureReason::KeyMismatch)),
+        );
+        assert_eq!(options.remote_command, None);
+        assert_eq!(rx_frames.recv().await, Some(Frame::KexFailure));
+
+        kex_reader
+            .handle_check(&rnk, nonce_bytes, check, &tx_event_clone, &mut options)
+            .expect("handle_check with a sealed RemoteCommand");
+        assert_eq!(
+            options.remote_command,
+            Some(RemoteCommand::new("uptime".to_string(), true))
+        );
+    }
+
+    #[tokio::test]
+    async fn handle_check_refuses_a_file_copy_changed_in_transit() {
+        use crate::{
+            FILE_COPY_MIN_PROTOCOL_VERSION, FileCopy, MoshpitError, RemoteCommand,
+            kex::options::{CHECK_VALUE, check_plaintext},
+        };
+
+        let (client_reader, _cw, _sr, _sw) = make_bidirectional_loopback().await;
+        let (mut kex_reader, mut rx_frames, _rx_events) = make_test_kex_reader(client_reader);
+        let rnk = LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM_SIV, &[1u8; 32]).expect("test AES-256-GCM-SIV key setup"),
+        );
+        let mut check = check_plaintext(&[Frame::FileCopy(true, vec!["docs".to_string()], false)])
+            .expect("encode options");
+        let nonce_bytes = [0u8; NONCE_LEN];
+        let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes).expect("create nonce");
+        rnk.seal_in_place_append_tag(nonce, Aad::empty(), &mut check)
+            .expect("seal in place");
+        let (tx_event_clone, _rx_event_clone) = unbounded_channel::<KexEvent>();
+
+        // Redirecting the sealed copy in transit fails the Check rather than
+        // writing somewhere the client never named.
+        let mut tampered = check.clone();
+        tampered[CHECK_VALUE.len() + 4] ^= 1;
+        let mut options = SessionOptions::new(FILE_COPY_MIN_PROTOCOL_VERSION);
+        assert!(
+            kex_reader
+                .handle_check(&rnk, nonce_bytes, tampered, &tx_event_clone, &mut options)
+                .expect_err("expected a key mismatch")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(|e| *e == MoshpitError::KexRejected(KexFailureReason::KeyMismatch)),
+        );
+        assert_eq!(options.remote_command, None);
+        assert_eq!(rx_frames.recv().await, Some(Frame::KexFailure));
+
+        kex_reader
+            .handle_check(&rnk, nonce_bytes, check, &tx_event_clone, &mut options)
+            .expect("handle_check with a sealed FileCopy");
+        assert_eq!(
+            options.remote_command,
+            Some(RemoteCommand::copying(FileCopy::Upload {
+                target: "docs".to_string(),
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn handle_check_refuses_a_session_request_changed_in_transit() {
+        use crate::{
+            MoshpitError, NAMED_SESSIONS_MIN_PROTOCOL_VERSION, SessionRequest,
+            kex::options::{CHECK_VALUE, check_plaintext},
+        };
+
+        let (client_reader, _cw, _sr, _sw) = make_bidirectional_loopback().await;
+        let (mut kex_reader, mut rx_frames, _rx_events) = make_test_kex_reader(client_reader);
+        let rnk = LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM_SIV, &[1u8; 32]).expect("test AES-256-GCM-SIV key setup"),
+        );
+        let mut check = check_plaintext(&[Frame::SessionRequest(0, "work".to_string())])
+            .expect("encode options");
+        let nonce_bytes = [0u8; NONCE_LEN];
+        let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes).expect("create nonce");
+        rnk.seal_in_place_append_tag(nonce, Aad::empty(), &mut check)
+            .expect("seal in place");
+        let (tx_event_clone, _rx_event_clone) = unbounded_channel::<KexEvent>();
+
+        // Swapping the session name in transit fails the Check rather than
+        // resuming a session the client never named.
+        let mut tampered = check.clone();
+        tampered[CHECK_VALUE.len() + 3] ^= 1;
+        let mut options = SessionOptions::new(NAMED_SESSIONS_MIN_PROTOCOL_VERSION);
+        assert!(
+            kex_reader
+                .handle_check(&rnk, nonce_bytes, tampered, &tx_event_clone, &mut options)
+                .expect_err("expected a key mismatch")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(|e| *e == MoshpitError::KexRejected(KexFailureReason::KeyMismatch)),
+        );
+        assert_eq!(options.session_request, None);
+        assert_eq!(rx_frames.recv().await, Some(Frame::KexFailure));
+
+        kex_reader
+            .handle_check(&rnk, nonce_bytes, check, &tx_event_clone, &mut options)
+            .expect("handle_check with a sealed SessionRequest");
+        assert_eq!(
+            options.session_request,
+            Some(SessionRequest::Named("work".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn handle_check_refuses_a_join_upgraded_in_transit() {
+        use crate::{
+            MoshpitError, SESSION_SHARING_MIN_PROTOCOL_VERSION, SessionRequest,
+            kex::options::check_plaintext,
+        };
+
+        let (client_reader, _cw, _sr, _sw) = make_bidirectional_loopback().await;
+        let (mut kex_reader, mut rx_frames, _rx_events) = make_test_kex_reader(client_reader);
+        let rnk = LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM_SIV, &[1u8; 32]).expect("test AES-256-GCM-SIV key setup"),
+        );
+        let watch = SessionRequest::Join {
+            owner: None,
+            name: "work".to_string(),
+            read_only: true,
+        };
+        let (kind, name) = watch.to_wire();
+        let mut check =
+            check_plaintext(&[Frame::SessionRequest(kind, name)]).expect("encode options");
+        let nonce_bytes = [0u8; NONCE_LEN];
+        let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes).expect("create nonce");
+        rnk.seal_in_place_append_tag(nonce, Aad::empty(), &mut check)
+            .expect("seal in place");
+        let (tx_event_clone, _rx_event_clone) = unbounded_channel::<KexEvent>();
+
+        // Every byte of the sealed request is bound by the tag, the kind byte
+        // that says read-only among them: flipping any one fails the Check
+        // rather than letting a viewer in with full control.
+        let mut options = SessionOptions::new(SESSION_SHARING_MIN_PROTOCOL_VERSION);
+        for index in 0..check.len() {
+            let mut tampered = check.clone();
+            tampered[index] ^= 1;
+            assert!(
+                kex_reader
+                    .handle_check(&rnk, nonce_bytes, tampered, &tx_event_clone, &mut options)
+                    .expect_err("expected a key mismatch")
+                    .downcast_ref::<MoshpitError>()
+                    .is_some_and(|e| *e == MoshpitError::KexRejected(KexFailureReason::KeyMismatch)),
+            );
+            assert_eq!(rx_frames.recv().await, Some(Frame::KexFailure));
+        }
+        assert_eq!(options.session_request, None);
+
+        kex_reader
+            .handle_check(&rnk, nonce_bytes, check, &tx_event_clone, &mut options)
+            .expect("handle_check with a sealed read-only join");
+        assert_eq!(options.session_request, Some(watch));
+    }
+
+    #[tokio::test]
+    async fn handle_check_invalid_payload_rejects_with_key_mismatch() {
+        use crate::MoshpitError;
+
+        let (client_reader, _cw, _sr, _sw) = make_bidirectional_loopback().await;
+        let (mut kex_reader, mut rx_frames, _rx_events) = make_test_kex_reader(client_reader);
+
+        let key_bytes = [1u8; 32];
+        let rnk = LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM_SIV, &key_bytes).expect("test AES-256-GCM-SIV key setup"),
+        );
+
+        let nonce_bytes = [0u8; NONCE_LEN];
+        let garbage = vec![0u8; 32]; // not a valid ciphertext
+
+        let (tx_event_clone, _) = unbounded_channel::<KexEvent>();
+        let result = kex_reader.handle_check(
+            &rnk,
+            nonce_bytes,
+            garbage,
+            &tx_event_clone,
+            &mut SessionOptions::default(),
+        );
+        assert!(result.is_err());
+        assert!(
+            result
+                .expect_err("expected decryption error")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(|e| *e == MoshpitError::KexRejected(KexFailureReason::KeyMismatch)),
+        );
+        // The client is told, rather than left waiting for KeyAgreement.  This
+        // reader never saw a KexInit, so it falls back to the bare frame.
+        assert_eq!(rx_frames.recv().await, Some(Frame::KexFailure));
+    }
+
+    #[tokio::test]
+    async fn handle_check_pending_rejection_rejects_a_valid_check() {
+        use crate::MoshpitError;
+
+        let (client_reader, _cw, _sr, _sw) = make_bidirectional_loopback().await;
+        let (mut kex_reader, mut rx_frames, _rx_events) = make_test_kex_reader(client_reader);
+        kex_reader.peer_reads_failure_reasons = true;
+        kex_reader.pending_rejection = Some(KexFailureReason::UnknownUser);
+
+        let rnk = LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM_SIV, &[1u8; 32]).expect("test AES-256-GCM-SIV key setup"),
+        );
+        let mut check = b"Yoda".to_vec();
+        let nonce_bytes = [0u8; NONCE_LEN];
+        let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes).expect("create nonce");
+        rnk.seal_in_place_append_tag(nonce, Aad::empty(), &mut check)
+            .expect("seal in place");
+
+        let (tx_event_clone, _) = unbounded_channel::<KexEvent>();
+        let result = kex_reader.handle_check(
+            &rnk,
+            nonce_bytes,
+            check,
+            &tx_event_clone,
+            &mut SessionOptions::default(),
+        );
+        assert!(
+            result
+                .expect_err("expected the pending rejection")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(|e| *e == MoshpitError::KexRejected(KexFailureReason::UnknownUser)),
+        );
+        // The client only learns that authentication failed.
+        assert_eq!(
+            rx_frames.recv().await,
+            Some(Frame::KexFailureReason(
+                KexFailureReason::AuthenticationFailed
+            ))
+        );
+    }
+
+    // -----------------------------------------------------------------------
+    // client_kex tests
+    // -----------------------------------------------------------------------
+
+    #[tokio::test]
+    async fn client_kex_server_closes_immediately_returns_error() {
+        let (client_reader, _client_writer, _server_reader, server_writer) =
+            make_bidirectional_loopback().await;
+        let (mut kex_reader, _rx_frames, _rx_events) = make_test_kex_reader(client_reader);
+
+        // Drop server writer immediately — client reads EOF
+        drop(server_writer);
+
+        let result = kex_reader.client_kex().await;
+        assert!(
+            result.is_err(),
+            "expected error when server closes immediately"
+        );
+    }
+
+    #[tokio::test]
+    async fn client_kex_kex_failure_before_kex_init_returns_no_common_algorithm() {
+        use crate::MoshpitError;
+
+        let (client_reader, _client_writer, _server_reader, mut server_writer) =
+            make_bidirectional_loopback().await;
+        let (mut kex_reader, _rx_frames, _rx_events) = make_test_kex_reader(client_reader);
+
+        // Server rejects immediately with KexFailure (e.g. no common algorithm)
+        server_writer
+            .write_frame(&Frame::KexFailure)
+            .await
+            .expect("write KexFailure frame");
+        drop(server_writer);
+
+        let result = kex_reader.client_kex().await;
+        assert!(result.is_err());
+        assert!(
+            result
+                .expect_err("expected NoCommonAlgorithm error")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(|e| *e == MoshpitError::NoCommonAlgorithm),
+        );
+    }
+
+    #[tokio::test]
+    async fn client_kex_unexpected_initial_frame_returns_key_not_established() {
         use crate::MoshpitError;
 
         let (client_reader, _client_writer, _server_reader, mut server_writer) =
@@ -2656,226 +4495,841 @@ mod tests {
 
         let result = kex_reader.client_kex().await;
         assert!(
-            result.is_err(),
-            "expected error after server closes post-PeerInitialize"
+            result.is_err(),
+            "expected error after server closes post-PeerInitialize"
+        );
+    }
+
+    #[tokio::test]
+    async fn client_kex_wrong_frame_after_peer_initialize_returns_key_not_established() {
+        use crate::MoshpitError;
+
+        let (client_reader, _client_writer, _server_reader, mut server_writer) =
+            make_bidirectional_loopback().await;
+        let (mut kex_reader, _rx_frames, _rx_events) = make_test_kex_reader(client_reader);
+
+        let server_epk = PrivateKey::generate(&X25519).expect("generate test X25519 key");
+        let server_epk_pub = server_epk.compute_public_key().expect("compute public key");
+        let identity_key = vec![0u8; 32];
+        let salt = vec![0u8; 32];
+        server_writer
+            .write_frame(&Frame::KexInit(
+                supported_algorithms(),
+                crate::kex::negotiate::local_protocol_support(),
+            ))
+            .await
+            .expect("write KexInit frame");
+        server_writer
+            .write_frame(&Frame::PeerInitialize(
+                identity_key,
+                server_epk_pub.as_ref().to_vec(),
+                salt,
+            ))
+            .await
+            .expect("write PeerInitialize frame");
+        // Send wrong frame instead of KeyAgreement
+        server_writer
+            .write_frame(&Frame::KexFailure)
+            .await
+            .expect("write KexFailure frame");
+        drop(server_writer);
+
+        let result = kex_reader.client_kex().await;
+        assert!(result.is_err());
+        assert!(
+            result
+                .expect_err("expected KeyNotEstablished error")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(|e| *e == MoshpitError::KeyNotEstablished),
+        );
+    }
+
+    #[tokio::test]
+    async fn client_kex_incompatible_protocol_version_fails() {
+        use crate::MoshpitError;
+        use crate::kex::negotiate::{PROTOCOL_VERSION, ProtocolSupport};
+
+        let (client_reader, _client_writer, _server_reader, mut server_writer) =
+            make_bidirectional_loopback().await;
+        let (mut kex_reader, _rx_frames, mut rx_events) = make_test_kex_reader(client_reader);
+
+        // Server advertises a protocol range strictly above the client's support
+        // (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION), so version negotiation must
+        // fail even though the algorithm lists agree.
+        server_writer
+            .write_frame(&Frame::KexInit(
+                supported_algorithms(),
+                ProtocolSupport {
+                    min: PROTOCOL_VERSION + 1,
+                    max: PROTOCOL_VERSION + 1,
+                },
+            ))
+            .await
+            .expect("write KexInit frame");
+        drop(server_writer);
+
+        let result = kex_reader.client_kex().await;
+        assert!(
+            result
+                .expect_err("expected IncompatibleProtocolVersion error")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(|e| *e == MoshpitError::IncompatibleProtocolVersion),
+        );
+        // A Failure event is emitted so the state machine can tear the session down.
+        assert!(
+            from_fn(|| rx_events.try_recv().ok()).any(|e| matches!(e, KexEvent::Failure)),
+            "expected a KexEvent::Failure to be sent",
+        );
+    }
+
+    #[tokio::test]
+    async fn server_kex_incompatible_protocol_version_fails() {
+        use crate::MoshpitError;
+        use crate::kex::negotiate::{PROTOCOL_VERSION, ProtocolSupport};
+
+        // The server-side reader reads from the server end of the loopback while
+        // the client end writes the KexInit.
+        let (_client_reader, mut client_writer, server_reader, _server_writer) =
+            make_bidirectional_loopback().await;
+        // `rx_frames` must stay bound: server_kex sends its own KexInit on this
+        // channel before negotiating, and a dropped receiver would fail that send
+        // first, masking the version error we are testing for.
+        let (mut kex_reader, mut rx_frames, _rx_events) = make_test_kex_reader(server_reader);
+
+        // Client advertises a protocol range disjoint from the server's default
+        // (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).
+        client_writer
+            .write_frame(&Frame::KexInit(
+                supported_algorithms(),
+                ProtocolSupport {
+                    min: PROTOCOL_VERSION + 1,
+                    max: PROTOCOL_VERSION + 1,
+                },
+            ))
+            .await
+            .expect("write KexInit frame");
+        drop(client_writer);
+
+        let mut pool = BTreeSet::new();
+        let _ = pool.insert(50123u16);
+        let port_pool = StdArc::new(TokioMutex::new(pool));
+        let socket_addr: SocketAddr = "127.0.0.1:9000".parse().expect("hardcoded test address");
+        // The version error returns before the host key files are read, so these
+        // paths never have to exist.
+        let dummy_key = PathBuf::from("/nonexistent/key");
+        let dummy_pubkey = PathBuf::from("/nonexistent/pubkey");
+
+        let result = kex_reader
+            .server_kex(
+                socket_addr,
+                port_pool,
+                (&dummy_key, &dummy_pubkey),
+                None,
+                false,
+            )
+            .await;
+        assert!(
+            result
+                .expect_err("expected IncompatibleProtocolVersion error")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(|e| *e
+                    == MoshpitError::KexRejected(KexFailureReason::IncompatibleProtocolVersion)),
+        );
+        // The client advertised a range that understands reasoned failures.
+        assert!(matches!(rx_frames.recv().await, Some(Frame::KexInit(_, _))));
+        assert_eq!(
+            rx_frames.recv().await,
+            Some(Frame::KexFailureReason(
+                KexFailureReason::IncompatibleProtocolVersion
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn client_kex_kex_failure_reason_returns_kex_rejected() {
+        use crate::MoshpitError;
+
+        let (client_reader, _client_writer, _server_reader, mut server_writer) =
+            make_bidirectional_loopback().await;
+        let (mut kex_reader, _rx_frames, mut rx_events) = make_test_kex_reader(client_reader);
+
+        // Server completes negotiation, then rejects the Initialize.
+        server_writer
+            .write_frame(&Frame::KexInit(
+                supported_algorithms(),
+                crate::kex::negotiate::local_protocol_support(),
+            ))
+            .await
+            .expect("write KexInit frame");
+        server_writer
+            .write_frame(&Frame::TransportPreference(0))
+            .await
+            .expect("write TransportPreference frame");
+        server_writer
+            .write_frame(&Frame::KexFailureReason(KexFailureReason::UnauthorizedKey))
+            .await
+            .expect("write KexFailureReason frame");
+        drop(server_writer);
+
+        let result = kex_reader.client_kex().await;
+        assert!(
+            result
+                .expect_err("expected KexRejected error")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(
+                    |e| *e == MoshpitError::KexRejected(KexFailureReason::UnauthorizedKey)
+                ),
+        );
+        assert!(
+            from_fn(|| rx_events.try_recv().ok())
+                .any(|e| matches!(e, KexEvent::Rejected(KexFailureReason::UnauthorizedKey))),
+            "expected a KexEvent::Rejected to be sent",
+        );
+    }
+
+    #[tokio::test]
+    async fn client_kex_happy_path_sends_all_events() {
+        use uuid::Uuid;
+
+        let (client_reader, _client_writer, _server_reader, mut server_writer) =
+            make_bidirectional_loopback().await;
+        // We need a separate tx/rx so we can observe the frames KexReader tries to send
+        let (tx_out, mut rx_out) = unbounded_channel::<Frame>();
+        let (tx_event_out, mut rx_event_out) = unbounded_channel::<KexEvent>();
+        let kex_reader = super::super::KexReader::builder()
+            .reader(client_reader)
+            .tx(tx_out)
+            .tx_event(tx_event_out)
+            .build();
+
+        let conn_uuid = Uuid::new_v4();
+        let session_uuid = Uuid::new_v4();
+        let moshpits_addr: SocketAddr = "127.0.0.1:50002".parse().expect("hardcoded test address");
+
+        // Spawn mock server task
+        let server_handle = spawn(async move {
+            let mut host = MockHost::new();
+            // 1. Send KexInit so client can negotiate and generate its ephemeral key.
+            //    Offer only X25519: the mock answers with an X25519 ephemeral key.
+            let kex_init = Frame::KexInit(
+                AlgorithmList {
+                    kex: vec![KEX_X25519_SHA256.to_string()],
+                    ..supported_algorithms()
+                },
+                crate::kex::negotiate::local_protocol_support(),
+            );
+            server_writer
+                .write_frame(host.record(Some(kex_init)))
+                .await
+                .expect("write KexInit frame");
+            // 1b. Drain the TransportPreference frame the client sends after NegotiatedAlgorithms
+            //     (protocol v2+) and echo back our capability (UDP = 0).
+            let _ = host.record(rx_out.recv().await);
+            server_writer
+                .write_frame(host.record(Some(Frame::TransportPreference(0))))
+                .await
+                .expect("write TransportPreference echo");
+            // 2. Protocol v8+: the client opens with only its ephemeral key.
+            assert!(matches!(
+                host.record(rx_out.recv().await),
+                Frame::HiddenInitialize(_)
+            ));
+            // 3. Send PeerInitialize with the server's identity key, ephemeral key, and salt.
+            server_writer
+                .write_frame(&host.peer_initialize())
+                .await
+                .expect("write PeerInitialize frame");
+            // 4. The client challenges the host key before revealing anything.
+            assert!(matches!(
+                host.record(rx_out.recv().await),
+                Frame::IdentityChallenge(_)
+            ));
+            server_writer
+                .write_frame(&host.host_proof())
+                .await
+                .expect("write IdentityProof frame");
+            // 5. The user and identity key follow encrypted, then the Check frame.
+            assert!(matches!(
+                rx_out.recv().await,
+                Some(Frame::SealedIdentity(..))
+            ));
+            drop(rx_out.recv().await);
+            // 6. Send KeyAgreement, SessionToken, MoshpitsAddr.
+            server_writer
+                .write_frame(&Frame::KeyAgreement(UuidWrapper::new(conn_uuid)))
+                .await
+                .expect("write KeyAgreement frame");
+            server_writer
+                .write_frame(&Frame::SessionToken(UuidWrapper::new(session_uuid)))
+                .await
+                .expect("write SessionToken frame");
+            server_writer
+                .write_frame(&Frame::MoshpitsAddr(moshpits_addr))
+                .await
+                .expect("write MoshpitsAddr frame");
+        });
+
+        let mut kex_reader = kex_reader;
+        kex_reader
+            .client_kex()
+            .await
+            .expect("client_kex happy path");
+        server_handle.await.expect("server task panicked");
+
+        // Collect all events
+        let mut events = Vec::new();
+        while let Ok(e) = rx_event_out.try_recv() {
+            events.push(e);
+        }
+
+        // Protocol v2 emits 7 events:
+        // NegotiatedAlgorithms, TransportMode, KeyMaterial, HMACKeyMaterial, Uuid, SessionInfo, MoshpitsAddr
+        assert_eq!(events.len(), 7, "expected 7 kex events, got: {events:?}");
+        assert!(matches!(events[0], KexEvent::NegotiatedAlgorithms(_)));
+        assert!(matches!(events[1], KexEvent::TransportMode(_)));
+        assert!(matches!(events[2], KexEvent::KeyMaterial(_)));
+        assert!(matches!(events[3], KexEvent::HMACKeyMaterial(_)));
+        assert!(matches!(events[4], KexEvent::Uuid(_)));
+        assert!(matches!(events[5], KexEvent::SessionInfo(_, false)));
+        assert!(matches!(events[6], KexEvent::MoshpitsAddr(_)));
+    }
+
+    #[tokio::test]
+    async fn client_kex_with_ticket_sends_check_without_waiting_for_server() {
+        use uuid::Uuid;
+
+        let (client_reader, _client_writer, _server_reader, mut server_writer) =
+            make_bidirectional_loopback().await;
+        let (tx_out, mut rx_out) = unbounded_channel::<Frame>();
+        let (tx_event_out, mut rx_event_out) = unbounded_channel::<KexEvent>();
+        let session_uuid = Uuid::new_v4();
+        let ticket = ResumptionTicket::new(vec![1, 2, 3], vec![4; 32], 60);
+        let mut kex_reader = super::super::KexReader::builder()
+            .reader(client_reader)
+            .tx(tx_out)
+            .tx_event(tx_event_out)
+            .requested_session_uuid(session_uuid)
+            .resumption_ticket(ticket)
+            .build();
+
+        let server_handle = spawn(async move {
+            server_writer
+                .write_frame(&Frame::KexInit(
+                    supported_algorithms(),
+                    crate::kex::negotiate::local_protocol_support(),
+                ))
+                .await
+                .expect("write KexInit frame");
+            drop(rx_out.recv().await);
+            server_writer
+                .write_frame(&Frame::TransportPreference(0))
+                .await
+                .expect("write TransportPreference echo");
+            // The ticket and the Check arrive in one flight: no PeerInitialize.
+            assert!(matches!(
+                rx_out.recv().await,
+                Some(Frame::TicketResume(ticket, _)) if ticket == vec![1, 2, 3]
+            ));
+            assert!(matches!(rx_out.recv().await, Some(Frame::Check(..))));
+            server_writer
+                .write_frame(&Frame::KeyAgreement(UuidWrapper::new(Uuid::new_v4())))
+                .await
+                .expect("write KeyAgreement frame");
+            server_writer
+                .write_frame(&Frame::SessionToken(UuidWrapper::new(session_uuid)))
+                .await
+                .expect("write SessionToken frame");
+            server_writer
+                .write_frame(&Frame::ResumptionTicket(vec![5, 6], 3600))
+                .await
+                .expect("write ResumptionTicket frame");
+            server_writer
+                .write_frame(&Frame::MoshpitsAddr(
+                    "127.0.0.1:50002".parse().expect("hardcoded test address"),
+                ))
+                .await
+                .expect("write MoshpitsAddr frame");
+        });
+
+        kex_reader
+            .client_kex()
+            .await
+            .expect("client_kex ticket resume");
+        server_handle.await.expect("server task panicked");
+
+        let events = from_fn(|| rx_event_out.try_recv().ok()).collect::<Vec<_>>();
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, KexEvent::SessionInfo(_, true)))
+        );
+        assert!(
+            events.iter().any(|e| matches!(
+                e,
+                KexEvent::ResumptionTicket(t) if *t.ticket() == vec![5, 6] && !t.is_expired()
+            )),
+            "expected the replacement ticket to be reported, got: {events:?}",
         );
     }
 
     #[tokio::test]
-    async fn client_kex_wrong_frame_after_peer_initialize_returns_key_not_established() {
+    async fn server_kex_rejects_an_unknown_ticket() {
         use crate::MoshpitError;
 
-        let (client_reader, _client_writer, _server_reader, mut server_writer) =
+        let (server_reader, _server_writer, _client_reader, mut client_writer) =
             make_bidirectional_loopback().await;
-        let (mut kex_reader, _rx_frames, _rx_events) = make_test_kex_reader(client_reader);
+        let (tx_frames, mut rx_frames) = unbounded_channel::<Frame>();
+        let (tx_event, _rx_event) = unbounded_channel::<KexEvent>();
+        let mut kex_reader = super::super::KexReader::builder()
+            .reader(server_reader)
+            .tx(tx_frames)
+            .tx_event(tx_event)
+            .ticket_issuer(TicketIssuer::new().expect("ticket issuer"))
+            .build();
 
-        let server_epk = PrivateKey::generate(&X25519).expect("generate test X25519 key");
-        let server_epk_pub = server_epk.compute_public_key().expect("compute public key");
-        let identity_key = vec![0u8; 32];
-        let salt = vec![0u8; 32];
-        server_writer
+        client_writer
             .write_frame(&Frame::KexInit(
                 supported_algorithms(),
                 crate::kex::negotiate::local_protocol_support(),
             ))
             .await
             .expect("write KexInit frame");
-        server_writer
-            .write_frame(&Frame::PeerInitialize(
-                identity_key,
-                server_epk_pub.as_ref().to_vec(),
-                salt,
-            ))
+        client_writer
+            .write_frame(&Frame::TransportPreference(0))
             .await
-            .expect("write PeerInitialize frame");
-        // Send wrong frame instead of KeyAgreement
-        server_writer
-            .write_frame(&Frame::KexFailure)
+            .expect("write TransportPreference frame");
+        client_writer
+            .write_frame(&Frame::TicketResume(vec![0u8; 64], [0u8; 32]))
             .await
-            .expect("write KexFailure frame");
-        drop(server_writer);
+            .expect("write TicketResume frame");
+        drop(client_writer);
 
-        let result = kex_reader.client_kex().await;
-        assert!(result.is_err());
+        let result = kex_reader
+            .server_kex(
+                "127.0.0.1:0".parse().expect("hardcoded test address"),
+                StdArc::new(TokioMutex::new(BTreeSet::new())),
+                (
+                    &PathBuf::from("/nonexistent/key"),
+                    &PathBuf::from("/nonexistent/pubkey"),
+                ),
+                Some(new_session_registry()),
+                false,
+            )
+            .await;
         assert!(
             result
-                .expect_err("expected KeyNotEstablished error")
+                .expect_err("expected KexRejected error")
                 .downcast_ref::<MoshpitError>()
-                .is_some_and(|e| *e == MoshpitError::KeyNotEstablished),
+                .is_some_and(|e| *e == MoshpitError::KexRejected(KexFailureReason::TicketRejected)),
+        );
+        assert!(
+            from_fn(|| rx_frames.try_recv().ok())
+                .any(|f| matches!(f, Frame::KexFailureReason(KexFailureReason::TicketRejected))),
+            "a rejected ticket must be reported so the client can fall back",
         );
     }
 
     #[tokio::test]
-    async fn client_kex_incompatible_protocol_version_fails() {
+    async fn client_kex_early_key_share_answers_peer_initialize_in_one_flight() {
+        use uuid::Uuid;
+
+        let (client_reader, _client_writer, _server_reader, mut server_writer) =
+            make_bidirectional_loopback().await;
+        let (tx_out, mut rx_out) = unbounded_channel::<Frame>();
+        let (tx_event_out, mut rx_event_out) = unbounded_channel::<KexEvent>();
+        let client_algos = AlgorithmList {
+            kex: vec![KEX_X25519_SHA256.to_string()],
+            ..supported_algorithms()
+        };
+        let mut kex_reader = super::super::KexReader::builder()
+            .reader(client_reader)
+            .tx(tx_out)
+            .tx_event(tx_event_out)
+            .client_algos(client_algos.clone())
+            .early_key_share(true)
+            .build();
+
+        let server_handle = spawn(async move {
+            let mut host = MockHost::for_client(client_algos);
+            // The share arrives before the server has said anything.
+            let share = rx_out.recv().await;
+            assert!(matches!(
+                &share,
+                Some(Frame::EarlyKeyShare(kex, exchange, 0))
+                    if kex == KEX_X25519_SHA256 && exchange.len() == 32
+            ));
+            let kex_init = Frame::KexInit(
+                supported_algorithms(),
+                crate::kex::negotiate::local_protocol_support(),
+            );
+            server_writer
+                .write_frame(host.record(Some(kex_init)))
+                .await
+                .expect("write KexInit frame");
+            // Both sides put the share in the transcript after the server's KexInit.
+            let _ = host.record(share);
+            server_writer
+                .write_frame(host.record(Some(Frame::TransportPreference(0))))
+                .await
+                .expect("write TransportPreference echo");
+            server_writer
+                .write_frame(&host.peer_initialize())
+                .await
+                .expect("write PeerInitialize frame");
+            // No HiddenInitialize: the client goes straight to challenging the host key.
+            assert!(matches!(
+                host.record(rx_out.recv().await),
+                Frame::IdentityChallenge(..)
+            ));
+            server_writer
+                .write_frame(&host.host_proof())
+                .await
+                .expect("write IdentityProof frame");
+            assert!(matches!(
+                rx_out.recv().await,
+                Some(Frame::SealedIdentity(..))
+            ));
+            assert!(matches!(rx_out.recv().await, Some(Frame::Check(..))));
+            for frame in [
+                Frame::KeyAgreement(UuidWrapper::new(Uuid::new_v4())),
+                Frame::SessionToken(UuidWrapper::new(Uuid::new_v4())),
+                Frame::MoshpitsAddr("127.0.0.1:50002".parse().expect("hardcoded test address")),
+            ] {
+                server_writer
+                    .write_frame(&frame)
+                    .await
+                    .expect("write server answer");
+            }
+        });
+
+        kex_reader
+            .client_kex()
+            .await
+            .expect("client_kex with early key share");
+        server_handle.await.expect("server task panicked");
+        assert!(
+            from_fn(|| rx_event_out.try_recv().ok())
+                .any(|e| matches!(e, KexEvent::MoshpitsAddr(_))),
+        );
+    }
+
+    #[tokio::test]
+    async fn client_kex_early_key_share_to_an_older_server_fails() {
         use crate::MoshpitError;
-        use crate::kex::negotiate::ProtocolSupport;
+        use crate::kex::{early::EARLY_KEY_SHARE_MIN_PROTOCOL_VERSION, negotiate::ProtocolSupport};
 
         let (client_reader, _client_writer, _server_reader, mut server_writer) =
             make_bidirectional_loopback().await;
-        let (mut kex_reader, _rx_frames, mut rx_events) = make_test_kex_reader(client_reader);
+        let (tx_out, _rx_out) = unbounded_channel::<Frame>();
+        let (tx_event_out, mut rx_event_out) = unbounded_channel::<KexEvent>();
+        let mut kex_reader = super::super::KexReader::builder()
+            .reader(client_reader)
+            .tx(tx_out)
+            .tx_event(tx_event_out)
+            .early_key_share(true)
+            .build();
 
-        // Server advertises a protocol range that does not overlap the client's
-        // support (min:1, max:2 after the v2 bump), so version negotiation must fail
-        // even though the algorithm lists agree.
         server_writer
             .write_frame(&Frame::KexInit(
                 supported_algorithms(),
-                ProtocolSupport { min: 3, max: 3 },
+                ProtocolSupport {
+                    min: 1,
+                    max: EARLY_KEY_SHARE_MIN_PROTOCOL_VERSION - 1,
+                },
             ))
             .await
             .expect("write KexInit frame");
-        drop(server_writer);
 
         let result = kex_reader.client_kex().await;
         assert!(
             result
-                .expect_err("expected IncompatibleProtocolVersion error")
+                .expect_err("expected EarlyKeyShareUnsupported error")
                 .downcast_ref::<MoshpitError>()
-                .is_some_and(|e| *e == MoshpitError::IncompatibleProtocolVersion),
+                .is_some_and(|e| *e == MoshpitError::EarlyKeyShareUnsupported),
         );
-        // A Failure event is emitted so the state machine can tear the session down.
         assert!(
-            from_fn(|| rx_events.try_recv().ok()).any(|e| matches!(e, KexEvent::Failure)),
-            "expected a KexEvent::Failure to be sent",
+            from_fn(|| rx_event_out.try_recv().ok())
+                .any(|e| matches!(e, KexEvent::EarlyKeyShareUnsupported)),
+            "the state machine must learn to reconnect without the share",
         );
     }
 
     #[tokio::test]
-    async fn server_kex_incompatible_protocol_version_fails() {
+    async fn server_kex_answers_a_matching_early_key_share() {
+        let dir = TempDir::new().expect("create temp dir");
+        let (private_key_path, public_key_path) = write_key_pair(&dir, "server");
+        for (kex, expect_peer_initialize) in [(KEX_X25519_SHA256, true), (KEX_P256_SHA256, false)] {
+            let (server_reader, _server_writer, _client_reader, mut client_writer) =
+                make_bidirectional_loopback().await;
+            let (mut kex_reader, mut rx_frames, _rx_events) = make_test_kex_reader(server_reader);
+            let exchange = match kex {
+                KEX_X25519_SHA256 => PrivateKey::generate(&X25519),
+                _ => PrivateKey::generate(&ECDH_P256),
+            }
+            .expect("generate test key")
+            .compute_public_key()
+            .expect("compute public key")
+            .as_ref()
+            .to_vec();
+            // The server prefers the hybrid exchange, so offer only the two
+            // classical ones: it picks X25519.
+            client_writer
+                .write_frame(&Frame::KexInit(
+                    AlgorithmList {
+                        kex: vec![KEX_X25519_SHA256.to_string(), KEX_P256_SHA256.to_string()],
+                        ..supported_algorithms()
+                    },
+                    crate::kex::negotiate::local_protocol_support(),
+                ))
+                .await
+                .expect("write KexInit frame");
+            client_writer
+                .write_frame(&Frame::EarlyKeyShare(kex.to_string(), exchange, 0))
+                .await
+                .expect("write EarlyKeyShare frame");
+            // Close before the client could have seen any reply.
+            drop(client_writer);
+
+            let result = kex_reader
+                .server_kex(
+                    "127.0.0.1:0".parse().expect("hardcoded test address"),
+                    StdArc::new(TokioMutex::new(BTreeSet::new())),
+                    (&private_key_path, &public_key_path),
+                    None,
+                    false,
+                )
+                .await;
+            assert!(result.is_err(), "the client never sent its identity");
+            let frames = from_fn(|| rx_frames.try_recv().ok()).collect::<Vec<_>>();
+            assert!(matches!(frames[0], Frame::KexInit(..)));
+            assert_eq!(frames[1], Frame::TransportPreference(0));
+            assert_eq!(
+                frames
+                    .iter()
+                    .any(|f| matches!(f, Frame::PeerInitialize(..))),
+                expect_peer_initialize,
+                "{kex}: {frames:?}",
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn client_kex_kex_failure_after_check_returns_key_not_established() {
         use crate::MoshpitError;
-        use crate::kex::negotiate::ProtocolSupport;
 
-        // The server-side reader reads from the server end of the loopback while
-        // the client end writes the KexInit.
-        let (_client_reader, mut client_writer, server_reader, _server_writer) =
+        let (client_reader, _client_writer, _server_reader, mut server_writer) =
             make_bidirectional_loopback().await;
-        // `_rx_frames` must stay bound: server_kex sends its own KexInit on this
-        // channel before negotiating, and a dropped receiver would fail that send
-        // first, masking the version error we are testing for.
-        let (mut kex_reader, _rx_frames, _rx_events) = make_test_kex_reader(server_reader);
-
-        // Client advertises a protocol range disjoint from the server's default (min:1, max:2).
-        client_writer
-            .write_frame(&Frame::KexInit(
-                supported_algorithms(),
-                ProtocolSupport { min: 3, max: 3 },
-            ))
-            .await
-            .expect("write KexInit frame");
-        drop(client_writer);
+        let (mut kex_reader, mut rx_frames, mut rx_events) = make_test_kex_reader(client_reader);
 
-        let mut pool = BTreeSet::new();
-        let _ = pool.insert(50123u16);
-        let port_pool = StdArc::new(TokioMutex::new(pool));
-        let socket_addr: SocketAddr = "127.0.0.1:9000".parse().expect("hardcoded test address");
-        // The version error returns before the public key file is read, so this
-        // path never has to exist.
-        let dummy_pubkey = PathBuf::from("/nonexistent/pubkey");
+        // The server derived different keys (e.g. its KexInit was rewritten in
+        // transit), so it rejects the client's Check.
+        let server_handle = spawn(async move {
+            let mut host = MockHost::new();
+            let kex_init = Frame::KexInit(
+                AlgorithmList {
+                    kex: vec![KEX_X25519_SHA256.to_string()],
+                    ..supported_algorithms()
+                },
+                crate::kex::negotiate::local_protocol_support(),
+            );
+            server_writer
+                .write_frame(host.record(Some(kex_init)))
+                .await
+                .expect("write KexInit frame");
+            let _ = host.record(rx_frames.recv().await);
+            server_writer
+                .write_frame(host.record(Some(Frame::TransportPreference(0))))
+                .await
+                .expect("write TransportPreference echo");
+            let _ = host.record(rx_frames.recv().await);
+            server_writer
+                .write_frame(&host.peer_initialize())
+                .await
+                .expect("write PeerInitialize frame");
+            let _ = host.record(rx_frames.recv().await);
+            server_writer
+                .write_frame(&host.host_proof())
+                .await
+                .expect("write IdentityProof frame");
+            // The client seals its identity before sending Check.
+            let sealed = rx_frames.recv().await;
+            assert!(
+                matches!(sealed, Some(Frame::SealedIdentity(_, _))),
+                "got {sealed:?}"
+            );
+            let check = rx_frames.recv().await;
+            assert!(matches!(check, Some(Frame::Check(_, _))), "got {check:?}");
+            server_writer
+                .write_frame(&Frame::KexFailure)
+                .await
+                .expect("write KexFailure frame");
+        });
 
-        let result = kex_reader
-            .server_kex(socket_addr, port_pool, &dummy_pubkey, None, false)
-            .await;
+        let result = kex_reader.client_kex().await;
+        server_handle.await.expect("server task panicked");
         assert!(
             result
-                .expect_err("expected IncompatibleProtocolVersion error")
+                .expect_err("expected KeyNotEstablished error")
                 .downcast_ref::<MoshpitError>()
-                .is_some_and(|e| *e == MoshpitError::IncompatibleProtocolVersion),
+                .is_some_and(|e| *e == MoshpitError::KeyNotEstablished),
         );
+        let mut events = Vec::new();
+        while let Ok(e) = rx_events.try_recv() {
+            events.push(e);
+        }
+        assert!(matches!(events.last(), Some(KexEvent::Failure)));
     }
 
     #[tokio::test]
-    async fn client_kex_happy_path_sends_all_events() {
-        use uuid::Uuid;
+    async fn client_kex_wrong_host_proof_keeps_the_identity_sealed() {
+        use crate::MoshpitError;
 
         let (client_reader, _client_writer, _server_reader, mut server_writer) =
             make_bidirectional_loopback().await;
-        // We need a separate tx/rx so we can observe the frames KexReader tries to send
-        let (tx_out, mut rx_out) = unbounded_channel::<Frame>();
-        let (tx_event_out, mut rx_event_out) = unbounded_channel::<KexEvent>();
-        let kex_reader = super::super::KexReader::builder()
-            .reader(client_reader)
-            .tx(tx_out)
-            .tx_event(tx_event_out)
-            .build();
-
-        let server_epk = PrivateKey::generate(&X25519).expect("generate test X25519 key");
-        let server_epk_pub = server_epk.compute_public_key().expect("compute public key");
-        let identity_key = vec![0u8; 32]; // fixed identity bytes (not used for ECDH)
-        let mut salt_bytes = [0u8; 32];
-        fill(&mut salt_bytes).expect("fill salt bytes");
-
-        let conn_uuid = Uuid::new_v4();
-        let session_uuid = Uuid::new_v4();
-        let moshpits_addr: SocketAddr = "127.0.0.1:50002".parse().expect("hardcoded test address");
+        let (mut kex_reader, mut rx_frames, _rx_events) = make_test_kex_reader(client_reader);
 
-        // Spawn mock server task
+        // A man in the middle can answer HiddenInitialize with its own ephemeral
+        // key, but cannot prove it holds the host key it names.
         let server_handle = spawn(async move {
-            // 1. Send KexInit so client can negotiate and generate its ephemeral key.
+            let mut host = MockHost::new();
+            let kex_init = Frame::KexInit(
+                AlgorithmList {
+                    kex: vec![KEX_X25519_SHA256.to_string()],
+                    ..supported_algorithms()
+                },
+                crate::kex::negotiate::local_protocol_support(),
+            );
             server_writer
-                .write_frame(&Frame::KexInit(
-                    supported_algorithms(),
-                    crate::kex::negotiate::local_protocol_support(),
-                ))
+                .write_frame(host.record(Some(kex_init)))
                 .await
                 .expect("write KexInit frame");
-            // 1b. Drain the TransportPreference frame the client sends after NegotiatedAlgorithms
-            //     (protocol v2+) and echo back our capability (UDP = 0).
-            drop(rx_out.recv().await);
+            let _ = host.record(rx_frames.recv().await);
             server_writer
-                .write_frame(&Frame::TransportPreference(0))
+                .write_frame(host.record(Some(Frame::TransportPreference(0))))
                 .await
                 .expect("write TransportPreference echo");
-            // 2. Drain the Initialize frame that client_kex sends after negotiation.
-            drop(rx_out.recv().await);
-            // 3. Send PeerInitialize with the server's identity key, ephemeral key, and salt.
+            let _ = host.record(rx_frames.recv().await);
             server_writer
-                .write_frame(&Frame::PeerInitialize(
-                    identity_key,
-                    server_epk_pub.as_ref().to_vec(),
-                    salt_bytes.to_vec(),
-                ))
+                .write_frame(&host.peer_initialize())
                 .await
                 .expect("write PeerInitialize frame");
-            // 4. Drain the Check frame that client_kex sends after ECDH.
-            drop(rx_out.recv().await);
-            // 5. Send KeyAgreement, SessionToken, MoshpitsAddr.
-            server_writer
-                .write_frame(&Frame::KeyAgreement(UuidWrapper::new(conn_uuid)))
-                .await
-                .expect("write KeyAgreement frame");
-            server_writer
-                .write_frame(&Frame::SessionToken(UuidWrapper::new(session_uuid)))
-                .await
-                .expect("write SessionToken frame");
+            let _ = host.record(rx_frames.recv().await);
             server_writer
-                .write_frame(&Frame::MoshpitsAddr(moshpits_addr))
+                .write_frame(&Frame::IdentityProof(vec![0u8; 32]))
                 .await
-                .expect("write MoshpitsAddr frame");
+                .expect("write IdentityProof frame");
+            rx_frames
         });
 
-        let mut kex_reader = kex_reader;
-        kex_reader
-            .client_kex()
-            .await
-            .expect("client_kex happy path");
-        server_handle.await.expect("server task panicked");
+        let result = kex_reader.client_kex().await;
+        drop(kex_reader);
+        let mut rx_frames = server_handle.await.expect("server task panicked");
+        assert!(
+            result
+                .expect_err("expected HostKeyRejected error")
+                .downcast_ref::<MoshpitError>()
+                .is_some_and(|e| *e == MoshpitError::HostKeyRejected),
+        );
+        assert!(
+            !from_fn(|| rx_frames.try_recv().ok()).any(|f| matches!(f, Frame::SealedIdentity(..))),
+            "the identity must not leave the client",
+        );
+    }
 
-        // Collect all events
-        let mut events = Vec::new();
-        while let Ok(e) = rx_event_out.try_recv() {
-            events.push(e);
-        }
+    #[tokio::test]
+    async fn server_kex_proves_its_host_key_before_reading_the_identity() {
+        use crate::MoshpitError;
 
-        // Protocol v2 emits 7 events:
-        // NegotiatedAlgorithms, TransportMode, KeyMaterial, HMACKeyMaterial, Uuid, SessionInfo, MoshpitsAddr
-        assert_eq!(events.len(), 7, "expected 7 kex events, got: {events:?}");
-        assert!(matches!(events[0], KexEvent::NegotiatedAlgorithms(_)));
-        assert!(matches!(events[1], KexEvent::TransportMode(_)));
-        assert!(matches!(events[2], KexEvent::KeyMaterial(_)));
-        assert!(matches!(events[3], KexEvent::HMACKeyMaterial(_)));
-        assert!(matches!(events[4], KexEvent::Uuid(_)));
-        assert!(matches!(events[5], KexEvent::SessionInfo(_, false)));
-        assert!(matches!(events[6], KexEvent::MoshpitsAddr(_)));
+        let dir = TempDir::new().expect("create temp dir");
+        let (host_private, host_public) = write_key_pair(&dir, "host");
+        let host_public = &host_public;
+        let (other_private, _) = write_key_pair(&dir, "other");
+        let (client_private, client_public) = write_key_pair(&dir, "client");
+        let client_key = crate::load_identity_key(&client_private, None).expect("load client key");
+        let client_public = std::fs::read(&client_public).expect("read client public key");
+
+        // The server holding the host key proves it and opens the sealed identity;
+        // one announcing a key it does not hold never sees the identity.
+        for (private_key_path, expected) in [
+            (
+                &host_private,
+                MoshpitError::KexRejected(KexFailureReason::UnknownUser),
+            ),
+            (&other_private, MoshpitError::HostKeyRejected),
+        ] {
+            let (client_reader, client_writer, server_reader, server_writer) =
+                make_bidirectional_loopback().await;
+            let (tx, rx) = unbounded_channel::<Frame>();
+            let (tx_event, _rx_event) = unbounded_channel::<KexEvent>();
+            // `run_client_kex` sends the client's KexInit before `client_kex` runs.
+            tx.send(Frame::KexInit(
+                supported_algorithms(),
+                crate::kex::negotiate::local_protocol_support(),
+            ))
+            .expect("queue KexInit frame");
+            let mut client = super::super::KexReader::builder()
+                .reader(client_reader)
+                .tx(tx)
+                .tx_event(tx_event)
+                .user("moshpit-no-such-user".to_string())
+                .full_public_key_bytes(client_public.clone())
+                .client_identity_private_key(client_key.private_key().clone())
+                .build();
+            let client_sent = forward_frames(rx, client_writer);
+            let (tx, rx) = unbounded_channel::<Frame>();
+            let (tx_event, _rx_event) = unbounded_channel::<KexEvent>();
+            let mut server = super::super::KexReader::builder()
+                .reader(server_reader)
+                .tx(tx)
+                .tx_event(tx_event)
+                .detailed_auth_failures(true)
+                .build();
+            let server_sent = forward_frames(rx, server_writer);
+
+            let (client_result, server_result) = tokio::join!(
+                async move {
+                    let result = client.client_kex().await;
+                    drop(client);
+                    result
+                },
+                async move {
+                    let result = server
+                        .server_kex(
+                            "127.0.0.1:0".parse().expect("hardcoded test address"),
+                            StdArc::new(TokioMutex::new(BTreeSet::new())),
+                            (private_key_path, host_public),
+                            None,
+                            false,
+                        )
+                        .await;
+                    drop(server);
+                    result
+                },
+            );
+            assert!(server_result.is_err(), "the test user does not exist");
+            assert!(
+                client_result
+                    .expect_err("expected a failed key exchange")
+                    .downcast_ref::<MoshpitError>()
+                    .is_some_and(|e| *e == expected),
+                "{expected:?}",
+            );
+            assert!(
+                server_sent
+                    .await
+                    .expect("server sender panicked")
+                    .iter()
+                    .any(|f| matches!(f, Frame::IdentityProof(_))),
+            );
+            assert_eq!(
+                client_sent
+                    .await
+                    .expect("client sender panicked")
+                    .iter()
+                    .any(|f| matches!(f, Frame::SealedIdentity(..))),
+                expected != MoshpitError::HostKeyRejected,
+            );
+        }
     }
 
     #[tokio::test]
@@ -3017,10 +5471,11 @@ mod tests {
     fn resolve_kex_alg_all_known_variants() {
         use super::resolve_kex_alg;
         use crate::kex::negotiate::{
-            KEX_ML_KEM_512_SHA256, KEX_ML_KEM_768_SHA256, KEX_ML_KEM_1024_SHA256, KEX_P256_SHA256,
-            KEX_P384_SHA384, KEX_X25519_SHA256,
+            KEX_ML_KEM_512_SHA256, KEX_ML_KEM_768_SHA256, KEX_ML_KEM_1024_SHA256,
+            KEX_MLKEM768_X25519_SHA256, KEX_P256_SHA256, KEX_P384_SHA384, KEX_X25519_SHA256,
         };
         assert!(resolve_kex_alg(KEX_X25519_SHA256).is_ok());
+        assert!(resolve_kex_alg(KEX_MLKEM768_X25519_SHA256).is_ok());
         assert!(resolve_kex_alg(KEX_P384_SHA384).is_ok());
         assert!(resolve_kex_alg(KEX_P256_SHA256).is_ok());
         assert!(resolve_kex_alg(KEX_ML_KEM_512_SHA256).is_ok());
@@ -3098,7 +5553,6 @@ mod tests {
         assert!(!super::is_ml_dsa_algorithm("unknown"));
     }
 
-    #[cfg(feature = "unstable")]
     #[test]
     fn parse_full_public_key_valid() {
         let alg = b"X25519";
@@ -3124,14 +5578,12 @@ mod tests {
         assert_eq!(key_bytes, pubkey);
     }
 
-    #[cfg(feature = "unstable")]
     #[test]
     fn parse_full_public_key_wrong_part_count() {
         let result = super::parse_full_public_key(b"moshpit only-two-parts");
         assert!(result.is_err());
     }
 
-    #[cfg(feature = "unstable")]
     #[test]
     fn parse_full_public_key_truncated_payload() {
         let b64 = STANDARD.encode(b"abc");
@@ -0,0 +1,316 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! In-session rekeying: a one-way HKDF ratchet over the data-channel keys.
+//!
+//! Each send direction ratchets independently.  When the sender's
+//! [`RekeyPolicy`] is exhausted it emits [`EncryptedFrame::Rekey`] under the
+//! current key, then seals everything after it under the next epoch's keys.
+//! Receivers follow on the `Rekey` frame (or on the first packet that only
+//! authenticates under the next epoch, should the `Rekey` frame be lost) and
+//! keep the previous epoch's keys for [`REKEY_GRACE_PERIOD`] so reordered and
+//! retransmitted datagrams sealed before the switch are still accepted.
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use aws_lc_rs::{aead::LessSafeKey, hmac::Key};
+use bon::Builder;
+use getset::CopyGetters;
+use zeroize::Zeroize as _;
+
+use crate::{
+    MoshpitError,
+    kex::{aead_key, hmac_key, negotiate::NegotiatedAlgorithms, reader::derive_session_keys},
+};
+
+/// Lowest negotiated protocol version whose peers understand
+/// [`EncryptedFrame::Rekey`](crate::EncryptedFrame::Rekey).
+pub const REKEY_MIN_PROTOCOL_VERSION: u16 = 4;
+
+/// How long a receiver keeps accepting packets sealed under the previous epoch
+/// after moving to the next one.  Comfortably longer than the NAK retry budget so
+/// retransmits of pre-rekey packets still land.
+pub const REKEY_GRACE_PERIOD: Duration = Duration::from_mins(1);
+
+/// HKDF salt prefix for epoch ratcheting; the big-endian target epoch is appended.
+const REKEY_SALT_LABEL: &[u8] = b"moshpit-rekey-v1";
+
+/// Thresholds after which a sender moves to a fresh key epoch.
+///
+/// Whichever limit is reached first triggers the rekey; the counters reset at
+/// every epoch boundary.
+#[derive(Builder, Clone, Copy, CopyGetters, Debug, Eq, PartialEq)]
+#[getset(get_copy = "pub")]
+pub struct RekeyPolicy {
+    /// Maximum lifetime of one key epoch.
+    #[builder(default = Duration::from_hours(1))]
+    interval: Duration,
+    /// Maximum number of wire bytes sealed under one key epoch.
+    #[builder(default = 1 << 30)]
+    max_bytes: u64,
+    /// Maximum number of packets sealed under one key epoch.  Kept well below the
+    /// 2^32 random-nonce bound for the 96-bit nonce AEADs.
+    #[builder(default = 1 << 24)]
+    max_packets: u64,
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// Key material for one epoch of a session's data channel, plus the means to
+/// derive the next epoch.
+///
+/// Epoch `n + 1` is `HKDF(salt = "moshpit-rekey-v1" || n + 1, ikm = aead_key || hmac_key)`
+/// over the negotiated KDF, so it can only be computed forwards.
+#[derive(Clone)]
+pub struct KeyRatchet {
+    epoch: u32,
+    key: Vec<u8>,
+    hmac_key: Vec<u8>,
+    negotiated: NegotiatedAlgorithms,
+}
+
+impl KeyRatchet {
+    pub(crate) fn new(key: Vec<u8>, hmac_key: Vec<u8>, negotiated: NegotiatedAlgorithms) -> Self {
+        Self {
+            epoch: 0,
+            key,
+            hmac_key,
+            negotiated,
+        }
+    }
+
+    /// The current epoch; `0` holds the handshake keys.
+    #[must_use]
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Build the AEAD and HMAC keys for the current epoch.  The HMAC key is `None`
+    /// under [`MAC_AEAD_IMPLICIT`](crate::MAC_AEAD_IMPLICIT).
+    ///
+    /// # Errors
+    /// Returns an error if the negotiated AEAD algorithm is unknown or the key bytes are invalid.
+    pub fn keys(&self) -> Result<(LessSafeKey, Option<Key>)> {
+        Ok((
+            aead_key(&self.negotiated, &self.key)?,
+            hmac_key(&self.negotiated, &self.hmac_key),
+        ))
+    }
+
+    /// Derive the ratchet for the following epoch.
+    ///
+    /// # Errors
+    /// * [`MoshpitError::RekeyEpochExhausted`] — the epoch counter would overflow.
+    /// * The negotiated KDF, AEAD, or MAC algorithm is unknown.
+    pub fn next(&self) -> Result<Self> {
+        let epoch = self
+            .epoch
+            .checked_add(1)
+            .ok_or(MoshpitError::RekeyEpochExhausted)?;
+        let mut salt = REKEY_SALT_LABEL.to_vec();
+        salt.extend_from_slice(&epoch.to_be_bytes());
+        let mut ikm = self.key.clone();
+        ikm.extend_from_slice(&self.hmac_key);
+        let derived = derive_session_keys(&ikm, &salt, &self.negotiated);
+        ikm.zeroize();
+        let (key, hmac_key) = derived?;
+        Ok(Self {
+            epoch,
+            key,
+            hmac_key,
+            negotiated: self.negotiated.clone(),
+        })
+    }
+}
+
+impl Debug for KeyRatchet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyRatchet")
+            .field("epoch", &self.epoch)
+            .field("aead", &self.negotiated.aead)
+            .field("mac", &self.negotiated.mac)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for KeyRatchet {
+    fn drop(&mut self) {
+        self.key.zeroize();
+        self.hmac_key.zeroize();
+    }
+}
+
+/// Send-side rekey bookkeeping shared by the UDP and TCP data-channel senders.
+#[derive(Debug)]
+pub(crate) struct SendRatchet {
+    ratchet: KeyRatchet,
+    policy: RekeyPolicy,
+    epoch_started: Instant,
+    bytes: u64,
+    packets: u64,
+}
+
+impl SendRatchet {
+    pub(crate) fn new(ratchet: KeyRatchet, policy: RekeyPolicy) -> Self {
+        Self {
+            ratchet,
+            policy,
+            epoch_started: Instant::now(),
+            bytes: 0,
+            packets: 0,
+        }
+    }
+
+    /// Account for one packet of `wire_len` bytes sealed under the current epoch.
+    pub(crate) fn record(&mut self, wire_len: usize) {
+        self.bytes = self
+            .bytes
+            .saturating_add(u64::try_from(wire_len).unwrap_or(u64::MAX));
+        self.packets = self.packets.saturating_add(1);
+    }
+
+    /// Whether any threshold of the policy has been reached.
+    pub(crate) fn due(&self) -> bool {
+        self.packets >= self.policy.max_packets()
+            || self.bytes >= self.policy.max_bytes()
+            || self.epoch_started.elapsed() >= self.policy.interval()
+    }
+
+    /// Move to the next epoch, returning its number and keys.
+    pub(crate) fn advance(&mut self) -> Result<(u32, LessSafeKey, Option<Key>)> {
+        let next = self.ratchet.next()?;
+        let (rnk, hmac) = next.keys()?;
+        self.ratchet = next;
+        self.epoch_started = Instant::now();
+        self.bytes = 0;
+        self.packets = 0;
+        Ok((self.ratchet.epoch(), rnk, hmac))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use aws_lc_rs::{
+        aead::{Aad, NONCE_LEN, Nonce},
+        hmac::{sign, verify},
+    };
+
+    use super::{KeyRatchet, RekeyPolicy, SendRatchet};
+    use crate::kex::negotiate::NegotiatedAlgorithms;
+
+    fn ratchet() -> KeyRatchet {
+        KeyRatchet::new(
+            vec![1u8; 32],
+            vec![2u8; 64],
+            NegotiatedAlgorithms::default(),
+        )
+    }
+
+    fn seal(rnk: &aws_lc_rs::aead::LessSafeKey, plaintext: &[u8]) -> Vec<u8> {
+        let mut data = plaintext.to_vec();
+        let nonce = Nonce::assume_unique_for_key([0u8; NONCE_LEN]);
+        rnk.seal_in_place_append_tag(nonce, Aad::empty(), &mut data)
+            .expect("seal");
+        data
+    }
+
+    fn open(rnk: &aws_lc_rs::aead::LessSafeKey, sealed: &[u8]) -> Option<Vec<u8>> {
+        let mut data = sealed.to_vec();
+        let nonce = Nonce::assume_unique_for_key([0u8; NONCE_LEN]);
+        rnk.open_in_place(nonce, Aad::empty(), &mut data)
+            .ok()
+            .map(|plain| plain.to_vec())
+    }
+
+    #[test]
+    fn next_is_deterministic_and_advances_epoch() -> anyhow::Result<()> {
+        let a = ratchet().next()?;
+        let b = ratchet().next()?;
+        assert_eq!(a.epoch(), 1);
+        assert_eq!(a.key, b.key);
+        assert_eq!(a.hmac_key, b.hmac_key);
+        assert_ne!(a.key, ratchet().key);
+        assert_ne!(a.hmac_key, ratchet().hmac_key);
+        let c = a.next()?;
+        assert_eq!(c.epoch(), 2);
+        assert_ne!(c.key, a.key);
+        Ok(())
+    }
+
+    #[test]
+    fn next_epoch_keys_reject_old_epoch_traffic() -> anyhow::Result<()> {
+        let (old_rnk, old_hmac) = ratchet().keys()?;
+        let (new_rnk, new_hmac) = ratchet().next()?.keys()?;
+        let sealed = seal(&old_rnk, b"hello");
+        assert!(open(&new_rnk, &sealed).is_none());
+        assert_eq!(open(&old_rnk, &sealed).as_deref(), Some(&b"hello"[..]));
+        let (old_hmac, new_hmac) = old_hmac
+            .zip(new_hmac)
+            .ok_or_else(|| anyhow::anyhow!("expected HMAC keys"))?;
+        let tag = sign(&old_hmac, b"seq");
+        assert!(verify(&new_hmac, b"seq", tag.as_ref()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn exhausted_epoch_counter_is_an_error() {
+        let mut exhausted = ratchet();
+        exhausted.epoch = u32::MAX;
+        assert!(exhausted.next().is_err());
+    }
+
+    #[test]
+    fn debug_omits_key_material() {
+        let rendered = format!("{:?}", ratchet());
+        assert!(rendered.contains("epoch"));
+        assert!(!rendered.contains("key:"));
+    }
+
+    #[test]
+    fn send_ratchet_is_due_on_each_threshold() -> anyhow::Result<()> {
+        let packets = RekeyPolicy::builder().max_packets(2).build();
+        let mut send = SendRatchet::new(ratchet(), packets);
+        send.record(10);
+        assert!(!send.due());
+        send.record(10);
+        assert!(send.due());
+        let (epoch, _, _) = send.advance()?;
+        assert_eq!(epoch, 1);
+        assert!(!send.due());
+
+        let bytes = RekeyPolicy::builder().max_bytes(100).build();
+        let mut send = SendRatchet::new(ratchet(), bytes);
+        send.record(99);
+        assert!(!send.due());
+        send.record(1);
+        assert!(send.due());
+
+        let time = RekeyPolicy::builder().interval(Duration::ZERO).build();
+        let send = SendRatchet::new(ratchet(), time);
+        assert!(send.due());
+        Ok(())
+    }
+
+    #[test]
+    fn default_policy_limits() {
+        let policy = RekeyPolicy::default();
+        assert_eq!(policy.interval(), Duration::from_hours(1));
+        assert_eq!(policy.max_bytes(), 1 << 30);
+        assert_eq!(policy.max_packets(), 1 << 24);
+    }
+}
@@ -0,0 +1,401 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Session resumption tickets: reconnect without the asymmetric key exchange.
+//!
+//! From [`RESUMPTION_TICKET_MIN_PROTOCOL_VERSION`] moshpits follows the
+//! `SessionToken` of every completed handshake with a
+//! [`Frame::ResumptionTicket`](crate::Frame::ResumptionTicket): an opaque blob,
+//! sealed under a key that never leaves the server process, naming the session,
+//! the user, the client's identity key, an expiry time, and a resumption PSK.
+//! Both peers derive that PSK from the session keys they just agreed, so it
+//! never crosses the wire.
+//!
+//! To reconnect, `mp` sends the ticket and a fresh nonce in
+//! [`Frame::TicketResume`](crate::Frame::TicketResume) instead of
+//! `Initialize`, and immediately follows it with its `Check`: the session keys
+//! come from the PSK, the nonce, and the transcript, so neither side performs a
+//! Diffie-Hellman, KEM, or identity-key operation and the server answers in a
+//! single flight.  Each ticket is good for one resume — redeeming it retires
+//! it, and the server issues a replacement — and a server restart invalidates
+//! them all, unless the old server hands its [`TicketIssuerState`] to the new
+//! one.  A rejected ticket is reported as
+//! [`KexFailureReason::TicketRejected`](crate::KexFailureReason::TicketRejected);
+//! the client then forgets it and runs a full handshake.
+
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use aws_lc_rs::{
+    aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    hkdf::Salt,
+    rand::fill,
+};
+use bincode_next::{Decode, Encode, config::standard, encode_to_vec};
+use getset::{CopyGetters, Getters};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use crate::{
+    UuidWrapper,
+    frames::decode_frame,
+    kex::{negotiate::NegotiatedAlgorithms, reader::resolve_hkdf_alg},
+};
+
+/// Lowest negotiated protocol version whose peers issue and redeem resumption
+/// tickets.
+pub const RESUMPTION_TICKET_MIN_PROTOCOL_VERSION: u16 = 9;
+
+/// How long a freshly issued ticket stays redeemable.
+pub const RESUMPTION_TICKET_LIFETIME: Duration = Duration::from_hours(24);
+
+/// HKDF info label for the resumption PSK.
+const RESUMPTION_PSK_INFO: &[u8] = b"RESUMPTION PSK";
+
+/// Seconds since the Unix epoch, saturating at zero for a clock set before 1970.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Derive the resumption PSK from a session's AEAD and HMAC key bytes.
+///
+/// The PSK is as long as the negotiated KDF's digest.
+pub(crate) fn resumption_psk(
+    key: &[u8],
+    hmac_key: &[u8],
+    negotiated: &NegotiatedAlgorithms,
+) -> Result<Vec<u8>> {
+    let hkdf_alg = resolve_hkdf_alg(&negotiated.kdf)?;
+    let mut ikm = key.to_vec();
+    ikm.extend_from_slice(hmac_key);
+    let prk = Salt::new(hkdf_alg, &[]).extract(&ikm);
+    ikm.zeroize();
+    let okm = prk.expand(&[RESUMPTION_PSK_INFO], hkdf_alg)?;
+    let mut psk = vec![0u8; hkdf_alg.hmac_algorithm().digest_algorithm().output_len()];
+    okm.fill(&mut psk)?;
+    Ok(psk)
+}
+
+/// A resumption ticket as held by the client, persisted next to its session file.
+#[derive(Clone, CopyGetters, Decode, Encode, Eq, Getters, PartialEq)]
+pub struct ResumptionTicket {
+    /// The opaque ticket to present in `TicketResume`.
+    #[getset(get = "pub")]
+    ticket: Vec<u8>,
+    /// The PSK this client derived when the ticket was issued.
+    #[getset(get = "pub")]
+    psk: Vec<u8>,
+    /// Expiry, in seconds since the Unix epoch.
+    #[getset(get_copy = "pub")]
+    expires_at: u64,
+}
+
+impl ResumptionTicket {
+    /// Build the client's copy of a ticket that expires `lifetime_secs` from now.
+    pub(crate) fn new(ticket: Vec<u8>, psk: Vec<u8>, lifetime_secs: u64) -> Self {
+        Self {
+            ticket,
+            psk,
+            expires_at: unix_now().saturating_add(lifetime_secs),
+        }
+    }
+
+    /// Whether the ticket's lifetime has run out.
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        unix_now() >= self.expires_at
+    }
+
+    /// Serialize the ticket for storage on disk.
+    ///
+    /// # Errors
+    /// Returns an error if the ticket cannot be encoded.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(encode_to_vec(self, standard())?)
+    }
+
+    /// Read a ticket written by [`to_bytes`](Self::to_bytes).
+    ///
+    /// # Errors
+    /// Returns an error if `bytes` is not an encoded ticket.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        decode_frame(bytes)
+    }
+}
+
+impl Debug for ResumptionTicket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("ResumptionTicket")
+            .field("ticket", &format_args!("{} bytes", self.ticket.len()))
+            .field("psk", &"<redacted>")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// What the server seals inside a ticket.
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub(crate) struct TicketContents {
+    /// Distinguishes this ticket from earlier ones for the same session.
+    pub(crate) ticket_id: [u8; 16],
+    /// The session the ticket resumes.
+    pub(crate) session_uuid: UuidWrapper,
+    /// The account the session belongs to.
+    pub(crate) user: String,
+    /// The client identity key that authenticated the original handshake.
+    pub(crate) identity_key: Vec<u8>,
+    /// The resumption PSK.
+    pub(crate) psk: Vec<u8>,
+    /// Expiry, in seconds since the Unix epoch.
+    pub(crate) expires_at: u64,
+}
+
+/// Server-side ticket state: the sealing key and the one live ticket per session.
+///
+/// Cheap to clone; every clone shares the same key and state.  The key is
+/// generated when the issuer is created and is never written anywhere, so
+/// restarting moshpits invalidates every outstanding ticket; only a hand-off
+/// to a new moshpits carries it over, in a [`TicketIssuerState`].
+#[derive(Clone)]
+pub struct TicketIssuer {
+    key: Arc<LessSafeKey>,
+    key_bytes: Arc<Zeroizing<[u8; 32]>>,
+    live: Arc<Mutex<HashMap<Uuid, [u8; 16]>>>,
+}
+
+/// A [`TicketIssuer`]'s sealing key and live tickets, as one moshpits hands
+/// them to the next so the tickets already issued stay redeemable.
+#[derive(Clone, Decode, Encode, Zeroize, ZeroizeOnDrop)]
+pub struct TicketIssuerState {
+    key: [u8; 32],
+    #[zeroize(skip)]
+    live: Vec<(UuidWrapper, [u8; 16])>,
+}
+
+impl Debug for TicketIssuerState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("TicketIssuerState")
+            .field("key", &"<redacted>")
+            .field("live", &self.live.len())
+            .finish()
+    }
+}
+
+impl TicketIssuer {
+    /// Create an issuer with a fresh random sealing key.
+    ///
+    /// # Errors
+    /// Returns an error if the system random number generator fails.
+    pub fn new() -> Result<Self> {
+        let mut key_bytes = Zeroizing::new([0u8; 32]);
+        fill(key_bytes.as_mut())?;
+        Self::with_key(key_bytes, HashMap::new())
+    }
+
+    /// Recreate the issuer a previous moshpits exported with
+    /// [`export`](Self::export).
+    ///
+    /// # Errors
+    /// Returns an error if the key cannot be used for sealing.
+    pub fn import(state: &TicketIssuerState) -> Result<Self> {
+        let live = state
+            .live
+            .iter()
+            .map(|(session_uuid, ticket_id)| (*session_uuid.as_ref(), *ticket_id))
+            .collect();
+        Self::with_key(Zeroizing::new(state.key), live)
+    }
+
+    fn with_key(key_bytes: Zeroizing<[u8; 32]>, live: HashMap<Uuid, [u8; 16]>) -> Result<Self> {
+        Ok(Self {
+            key: Arc::new(LessSafeKey::new(UnboundKey::new(
+                &AES_256_GCM,
+                key_bytes.as_ref(),
+            )?)),
+            key_bytes: Arc::new(key_bytes),
+            live: Arc::new(Mutex::new(live)),
+        })
+    }
+
+    /// The sealing key and live tickets, for handing to a new moshpits.
+    pub async fn export(&self) -> TicketIssuerState {
+        let live = self
+            .live
+            .lock()
+            .await
+            .iter()
+            .map(|(session_uuid, ticket_id)| (UuidWrapper::new(*session_uuid), *ticket_id))
+            .collect();
+        TicketIssuerState {
+            key: **self.key_bytes,
+            live,
+        }
+    }
+
+    /// Seal a ticket for `session_uuid`, replacing any earlier ticket for it.
+    ///
+    /// Returns `nonce || ciphertext`.
+    pub(crate) async fn issue(
+        &self,
+        session_uuid: Uuid,
+        user: &str,
+        identity_key: &[u8],
+        psk: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        let mut ticket_id = [0u8; 16];
+        fill(&mut ticket_id)?;
+        let contents = TicketContents {
+            ticket_id,
+            session_uuid: UuidWrapper::new(session_uuid),
+            user: user.to_string(),
+            identity_key: identity_key.to_vec(),
+            psk,
+            expires_at: unix_now().saturating_add(RESUMPTION_TICKET_LIFETIME.as_secs()),
+        };
+        let mut sealed = encode_to_vec(&contents, standard())?;
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        fill(&mut nonce_bytes)?;
+        let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes)?;
+        self.key
+            .seal_in_place_append_tag(nonce, Aad::empty(), &mut sealed)?;
+        let _ = self.live.lock().await.insert(session_uuid, ticket_id);
+        let mut ticket = nonce_bytes.to_vec();
+        ticket.extend_from_slice(&sealed);
+        Ok(ticket)
+    }
+
+    /// Open `ticket` and retire it.
+    ///
+    /// Returns `None` if the ticket was not sealed by this issuer, has expired,
+    /// or is not the latest ticket issued for its session.
+    pub(crate) async fn redeem(&self, ticket: &[u8]) -> Option<TicketContents> {
+        if ticket.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce_bytes, sealed) = ticket.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce_bytes).ok()?;
+        let mut sealed = sealed.to_vec();
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut sealed)
+            .ok()?;
+        let contents: TicketContents = decode_frame(plaintext).ok()?;
+        if unix_now() >= contents.expires_at {
+            return None;
+        }
+        let session_uuid = *contents.session_uuid.as_ref();
+        let mut live = self.live.lock().await;
+        if live.get(&session_uuid) != Some(&contents.ticket_id) {
+            return None;
+        }
+        let _ = live.remove(&session_uuid);
+        Some(contents)
+    }
+}
+
+impl Debug for TicketIssuer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("TicketIssuer")
+            .field("key", &"<redacted>")
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use uuid::Uuid;
+
+    use super::{ResumptionTicket, TicketIssuer, resumption_psk};
+    use crate::kex::negotiate::NegotiatedAlgorithms;
+
+    #[tokio::test]
+    async fn issued_ticket_redeems_once() -> Result<()> {
+        let issuer = TicketIssuer::new()?;
+        let session_uuid = Uuid::new_v4();
+        let ticket = issuer
+            .issue(session_uuid, "alice", b"identity", vec![7; 32])
+            .await?;
+        let contents = issuer.redeem(&ticket).await.expect("fresh ticket redeems");
+        assert_eq!(*contents.session_uuid.as_ref(), session_uuid);
+        assert_eq!(contents.user, "alice");
+        assert_eq!(contents.psk, vec![7; 32]);
+        assert!(
+            issuer.redeem(&ticket).await.is_none(),
+            "tickets are single-use"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reissue_retires_the_previous_ticket() -> Result<()> {
+        let issuer = TicketIssuer::new()?;
+        let session_uuid = Uuid::new_v4();
+        let first = issuer
+            .issue(session_uuid, "alice", b"identity", vec![1; 32])
+            .await?;
+        let second = issuer
+            .issue(session_uuid, "alice", b"identity", vec![2; 32])
+            .await?;
+        assert!(issuer.redeem(&first).await.is_none());
+        assert!(issuer.redeem(&second).await.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ticket_from_another_issuer_is_rejected() -> Result<()> {
+        let ticket = TicketIssuer::new()?
+            .issue(Uuid::new_v4(), "alice", b"identity", vec![1; 32])
+            .await?;
+        assert!(TicketIssuer::new()?.redeem(&ticket).await.is_none());
+        assert!(TicketIssuer::new()?.redeem(&[0u8; 4]).await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exported_issuer_redeems_its_tickets() -> Result<()> {
+        let issuer = TicketIssuer::new()?;
+        let session_uuid = Uuid::new_v4();
+        let ticket = issuer
+            .issue(session_uuid, "alice", b"identity", vec![7; 32])
+            .await?;
+        let imported = TicketIssuer::import(&issuer.export().await)?;
+        let contents = imported
+            .redeem(&ticket)
+            .await
+            .expect("handed-off ticket redeems");
+        assert_eq!(*contents.session_uuid.as_ref(), session_uuid);
+        Ok(())
+    }
+
+    #[test]
+    fn client_ticket_round_trips_through_bytes() -> Result<()> {
+        let ticket = ResumptionTicket::new(vec![1, 2, 3], vec![4; 32], 60);
+        assert!(!ticket.is_expired());
+        assert_eq!(ResumptionTicket::from_bytes(&ticket.to_bytes()?)?, ticket);
+        assert!(ResumptionTicket::new(vec![1], vec![4; 32], 0).is_expired());
+        Ok(())
+    }
+
+    #[test]
+    fn resumption_psk_differs_per_session() -> Result<()> {
+        let negotiated = NegotiatedAlgorithms::default();
+        let psk = resumption_psk(&[1; 32], &[2; 64], &negotiated)?;
+        assert_eq!(psk, resumption_psk(&[1; 32], &[2; 64], &negotiated)?);
+        assert_ne!(psk, resumption_psk(&[3; 32], &[2; 64], &negotiated)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Handshake transcript hash: binds the cleartext negotiation into the session keys.
+//!
+//! Both peers hash every [`Frame`] exchanged up to key derivation — the two
+//! `KexInit` algorithm lists and protocol ranges, the transport preference echo,
+//! `Initialize`/`ResumeRequest` (from protocol v8 `HiddenInitialize`, the host-key
+//! `IdentityChallenge` and `IdentityProof`, and `SealedIdentity`),
+//! `PeerInitialize`, and `IdentityChallenge` — in
+//! the order they appear on the connection.  From
+//! [`TRANSCRIPT_MIN_PROTOCOL_VERSION`] the digest is appended to the HKDF salt,
+//! so an active attacker who rewrites any of those frames (for example to strip
+//! post-quantum algorithms from a `KexInit`) leaves the two sides with different
+//! keys, and the server rejects the client's `Check` with
+//! [`KexFailureReason::KeyMismatch`](crate::KexFailureReason::KeyMismatch).
+
+use std::fmt::{self, Debug, Formatter};
+
+use anyhow::Result;
+use aws_lc_rs::digest::{Context, SHA256};
+use bincode_next::{config::standard, encode_to_vec};
+
+use crate::Frame;
+
+/// Lowest negotiated protocol version that mixes the handshake transcript hash
+/// into the session key derivation.
+///
+/// The protocol range itself travels in the (hashed) `KexInit` frames, but an
+/// attacker can still rewrite both ranges down to an older version that predates
+/// the transcript.  Operators who need to rule that out raise the effective
+/// floor to this version (`mps --min-protocol-version`).
+pub const TRANSCRIPT_MIN_PROTOCOL_VERSION: u16 = 6;
+
+/// Domain separation prefix fed into the hash before the first frame.
+const TRANSCRIPT_LABEL: &[u8] = b"moshpit-transcript-v1";
+
+/// Running SHA-256 over the key-exchange frames of one handshake.
+#[derive(Clone)]
+pub(crate) struct Transcript {
+    context: Context,
+    frames: usize,
+}
+
+impl Transcript {
+    /// Append `frame` to the transcript, encoded exactly as it travels on the wire:
+    /// `[id (1)] [length (8)] [bincode payload]`.
+    pub(crate) fn absorb(&mut self, frame: &Frame) -> Result<()> {
+        let encoded = encode_to_vec(frame, standard())?;
+        self.context.update(&[frame.id()]);
+        self.context
+            .update(&u64::try_from(encoded.len())?.to_be_bytes());
+        self.context.update(&encoded);
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// The digest of every frame absorbed so far.
+    pub(crate) fn hash(&self) -> Vec<u8> {
+        self.context.clone().finish().as_ref().to_vec()
+    }
+
+    /// HKDF salt for the session keys: the server's random `salt`, followed from
+    /// [`TRANSCRIPT_MIN_PROTOCOL_VERSION`] by the transcript hash.
+    pub(crate) fn session_salt(&self, salt: &[u8], protocol_version: u16) -> Vec<u8> {
+        let mut session_salt = salt.to_vec();
+        if protocol_version >= TRANSCRIPT_MIN_PROTOCOL_VERSION {
+            session_salt.extend_from_slice(&self.hash());
+        }
+        session_salt
+    }
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        let mut context = Context::new(&SHA256);
+        context.update(TRANSCRIPT_LABEL);
+        Self { context, frames: 0 }
+    }
+}
+
+impl Debug for Transcript {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transcript")
+            .field("frames", &self.frames)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::{TRANSCRIPT_MIN_PROTOCOL_VERSION, Transcript};
+    use crate::{
+        Frame,
+        kex::negotiate::{KEX_X25519_SHA256, local_protocol_support, supported_algorithms},
+    };
+
+    fn transcript_of(frames: &[Frame]) -> Result<Transcript> {
+        let mut transcript = Transcript::default();
+        for frame in frames {
+            transcript.absorb(frame)?;
+        }
+        Ok(transcript)
+    }
+
+    #[test]
+    fn same_frames_give_same_hash() -> Result<()> {
+        let frames = [
+            Frame::KexInit(supported_algorithms(), local_protocol_support()),
+            Frame::TransportPreference(0),
+        ];
+        assert_eq!(
+            transcript_of(&frames)?.hash(),
+            transcript_of(&frames)?.hash()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stripped_algorithm_list_changes_hash() -> Result<()> {
+        let offered = Frame::KexInit(supported_algorithms(), local_protocol_support());
+        let mut stripped_algos = supported_algorithms();
+        stripped_algos.kex = vec![KEX_X25519_SHA256.to_string()];
+        let stripped = Frame::KexInit(stripped_algos, local_protocol_support());
+        assert_ne!(
+            transcript_of(&[offered])?.hash(),
+            transcript_of(&[stripped])?.hash()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn frame_order_changes_hash() -> Result<()> {
+        let a = Frame::TransportPreference(0);
+        let b = Frame::TransportPreference(1);
+        assert_ne!(
+            transcript_of(&[a.clone(), b.clone()])?.hash(),
+            transcript_of(&[b, a])?.hash()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn session_salt_includes_hash_from_transcript_protocol_version() -> Result<()> {
+        let transcript = transcript_of(&[Frame::TransportPreference(0)])?;
+        let salt = [7u8; 32];
+        assert_eq!(
+            transcript.session_salt(&salt, TRANSCRIPT_MIN_PROTOCOL_VERSION - 1),
+            salt.to_vec()
+        );
+        let bound = transcript.session_salt(&salt, TRANSCRIPT_MIN_PROTOCOL_VERSION);
+        assert_eq!(&bound[..salt.len()], &salt[..]);
+        assert_eq!(&bound[salt.len()..], transcript.hash().as_slice());
+        Ok(())
+    }
+}
@@ -21,7 +21,9 @@
 //!
 //! **Phase 1 — TCP key exchange**: the client opens a TCP connection and performs mutual
 //! asymmetric-key authentication.  A per-session AEAD key is derived via KDF.
-//! See [`run_key_exchange`], [`Kex`], [`KexStateMachine`].
+//! See [`run_key_exchange`], [`Kex`], [`KexStateMachine`].  Where TCP is blocked the same
+//! exchange can run over UDP datagrams instead ([`connect_udp_handshake`],
+//! [`UdpHandshakeListener`], [`run_key_exchange_over`]).
 //!
 //! **Phase 2 — Data session**: all terminal I/O is encrypted with the negotiated AEAD cipher.
 //! Two transports are supported, negotiated during Phase 1 via [`TransportMode`]:
@@ -39,9 +41,9 @@
 //!
 //! | Layer | Default | Alternatives |
 //! |-------|---------|--------------|
-//! | Identity key exchange | X25519 ([`KEX_X25519_SHA256`]) | P-384, P-256, ML-KEM-512/768/1024 |
+//! | Identity key exchange | ML-KEM-768 + X25519 hybrid ([`KEX_MLKEM768_X25519_SHA256`]) | X25519, P-384, P-256, ML-KEM-512/768/1024 |
 //! | Session encryption (AEAD) | AES-256-GCM-SIV ([`AEAD_AES256_GCM_SIV`]) | AES-256-GCM, ChaCha20-Poly1305, AES-128-GCM-SIV |
-//! | Frame authentication (MAC) | HMAC-SHA-512 ([`MAC_HMAC_SHA512`]) | HMAC-SHA-256 |
+//! | Frame authentication (MAC) | HMAC-SHA-512 ([`MAC_HMAC_SHA512`]) | HMAC-SHA-256, AEAD tag only ([`MAC_AEAD_IMPLICIT`]) |
 //! | Key derivation (KDF) | HKDF-SHA-256 ([`KDF_HKDF_SHA256`]) | HKDF-SHA-384, HKDF-SHA-512 |
 //!
 //! Algorithm negotiation follows SSH "first-match-wins" semantics ([`negotiate`]).
@@ -53,15 +55,58 @@
 //! (`frames/encframe.rs`) is the data-channel payload for both UDP and TCP transport.
 //! The wire format is identical for both: `[nonce (12)] [seq (8)] [hmac tag] [length (8)]
 //! [ciphertext]`.  The sequence number is authenticated by the HMAC and reused as the AEAD AAD,
-//! so a frame can be retransmitted verbatim without re-encryption.  Over UDP each frame is a
+//! so a frame can be retransmitted verbatim without re-encryption.  From
+//! [`DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION`] each direction has its own keys
+//! ([`KeyDirection`]) and the nonce is derived from the sequence number instead of
+//! being sent ([`NonceScheme::Sequence`]).  Under [`MAC_AEAD_IMPLICIT`] the HMAC tag is
+//! omitted and the AEAD tag, whose AAD covers the sequence number, authenticates the
+//! packet on its own.  Over UDP each frame is a
 //! datagram; over TCP it is wrapped in a length-prefixed blob via
 //! `ConnectionWriter::write_data` / `ConnectionReader::read_data`.
 //! See [`EncryptedFrame::parse`].
 //!
+//! From protocol version 4 each direction of the data channel rekeys itself
+//! periodically (see [`RekeyPolicy`]): the sender emits [`EncryptedFrame::Rekey`] and
+//! moves to keys ratcheted forward from the current ones ([`KeyRatchet`]); the receiver
+//! follows and keeps accepting the previous keys for [`REKEY_GRACE_PERIOD`].
+//!
 //! Both peers advertise a [`ProtocolSupport`] range (min/max) in their
 //! [`Frame::KexInit`] frame, and [`negotiate_protocol_version`] picks the highest
-//! commonly supported [`PROTOCOL_VERSION`]. Any change to a [`Frame`] or
-//! [`EncryptedFrame`] variant is a wire-format change: bump [`PROTOCOL_VERSION`]
+//! commonly supported [`PROTOCOL_VERSION`]. From [`TRANSCRIPT_MIN_PROTOCOL_VERSION`]
+//! a hash of every handshake frame up to key derivation, including both `KexInit`
+//! frames, is mixed into the session keys, so tampering with negotiation leaves
+//! the peers with mismatched keys. From [`KEX_FAILURE_REASON_MIN_PROTOCOL_VERSION`]
+//! a rejecting server sends [`Frame::KexFailureReason`] with a [`KexFailureReason`]
+//! instead of a bare [`Frame::KexFailure`]. From [`IDENTITY_HIDING_MIN_PROTOCOL_VERSION`]
+//! the client sends its username and identity key only after an ephemeral
+//! handshake key is in place, inside [`Frame::SealedIdentity`]. From
+//! [`RESUMPTION_TICKET_MIN_PROTOCOL_VERSION`] the server hands out a
+//! [`ResumptionTicket`] that lets the client reconnect with a pre-shared key
+//! instead of a fresh asymmetric exchange. From
+//! [`EARLY_KEY_SHARE_MIN_PROTOCOL_VERSION`] the client sends a speculative
+//! [`Frame::EarlyKeyShare`] with its `KexInit`, so a fresh handshake no longer
+//! waits a round trip for the server's algorithm list. From
+//! [`PORT_FORWARDING_MIN_PROTOCOL_VERSION`] either side can carry forwarded TCP
+//! streams over the data channel as `EncryptedFrame::Forward*` channels, multiplexed
+//! by a [`ForwardMux`]. From [`REMOTE_FORWARDING_MIN_PROTOCOL_VERSION`] the client
+//! can also ask the server to listen on its behalf, within the server's
+//! [`ForwardPolicy`], and from [`DYNAMIC_FORWARDING_MIN_PROTOCOL_VERSION`] open
+//! channels to targets named by a local SOCKS5 client. From
+//! [`UNIX_FORWARDING_MIN_PROTOCOL_VERSION`] either end of a forward may be a
+//! Unix-domain socket, which the server checks and creates as the session's
+//! [`ForwardUser`], and from [`AGENT_FORWARDING_MIN_PROTOCOL_VERSION`] the
+//! client can have its agent forwarded with a [`Frame::AgentForward`]. From
+//! [`REMOTE_COMMAND_MIN_PROTOCOL_VERSION`] the client can name a
+//! [`RemoteCommand`] to run instead of the login shell with a
+//! [`Frame::RemoteCommand`], and gets its [`ExitStatus`] back, as it does for a
+//! login shell from [`PTY_EXIT_STATUS_MIN_PROTOCOL_VERSION`]. From
+//! [`FILE_COPY_MIN_PROTOCOL_VERSION`] a [`Frame::FileCopy`] runs a [`FileCopy`]
+//! instead, moving files with [`send_files`] and [`receive_files`]. From
+//! [`NAMED_SESSIONS_MIN_PROTOCOL_VERSION`] a [`Frame::SessionRequest`] names the
+//! session to start or resume, or asks for a list of them, and from
+//! [`SESSION_SHARING_MIN_PROTOCOL_VERSION`] it can join a session that other
+//! clients are attached to, read-only if it likes. Any change to a
+//! [`Frame`] or [`EncryptedFrame`] variant is a wire-format change: bump [`PROTOCOL_VERSION`]
 //! (leaving [`MIN_PROTOCOL_VERSION`] in place so older peers still negotiate) and
 //! gate the new behaviour on the negotiated value, reachable via
 //! [`Kex::protocol_version`] / [`ServerKex::protocol_version`] — never on the
@@ -78,7 +123,9 @@
 //! `mpa` is an optional key-agent daemon.  The [`agent`] module provides the Unix-socket
 //! protocol types ([`AgentRequest`], [`AgentResponse`]) and an async client (`AgentClient`,
 //! Unix-only) that `mp` uses to delegate identity-key operations without reading key files
-//! directly.
+//! directly.  With `mp --forward-agent` the agent is also reachable from the remote shell,
+//! through a per-session socket relayed over the session; only listing identities, fetching
+//! public keys, and signing are forwarded.
 //!
 //! # Feature flags
 //!
@@ -311,8 +358,11 @@
 #![cfg_attr(coverage_nightly, feature(coverage_attribute))]
 
 pub mod agent;
+mod command;
 mod config;
+mod copy;
 mod error;
+mod forward;
 mod frames;
 mod kex;
 mod keygen;
@@ -322,6 +372,7 @@ mod tcp_transport;
 mod term;
 mod tracing;
 mod udp;
+mod udp_handshake;
 mod utils;
 mod uuid;
 
@@ -330,6 +381,10 @@ pub use self::agent::AgentClient;
 pub use self::agent::AgentIdentityInfo;
 pub use self::agent::AgentRequest;
 pub use self::agent::AgentResponse;
+pub use self::command::ExitStatus;
+pub use self::command::PTY_EXIT_STATUS_MIN_PROTOCOL_VERSION;
+pub use self::command::REMOTE_COMMAND_MIN_PROTOCOL_VERSION;
+pub use self::command::RemoteCommand;
 pub use self::config::KexConfig;
 pub use self::config::PathDefaults;
 pub use self::config::config_file_path;
@@ -338,11 +393,31 @@ pub use self::config::mps::Mps;
 pub use self::config::tracing::FileLayer;
 pub use self::config::tracing::Layer;
 pub use self::config::tracing::Tracing;
+pub use self::copy::CopyOutcome;
+pub use self::copy::FILE_COPY_MIN_PROTOCOL_VERSION;
+pub use self::copy::FileCopy;
+pub use self::copy::receive_files;
+pub use self::copy::send_files;
 pub use self::error::Error as MoshpitError;
 pub use self::error::clap_or_error;
 pub use self::error::success;
+pub use self::forward::AGENT_FORWARDING_MIN_PROTOCOL_VERSION;
+pub use self::forward::DYNAMIC_FORWARDING_MIN_PROTOCOL_VERSION;
+pub use self::forward::ForwardMux;
+pub use self::forward::ForwardRole;
+pub use self::forward::PORT_FORWARDING_MIN_PROTOCOL_VERSION;
+pub use self::forward::REMOTE_FORWARDING_MIN_PROTOCOL_VERSION;
+pub use self::forward::UNIX_FORWARDING_MIN_PROTOCOL_VERSION;
+pub use self::forward::policy::ForwardPolicy;
+pub use self::forward::policy::ForwardUser;
+pub use self::forward::spec::DynamicForwardSpec;
+pub use self::forward::spec::ForwardSpec;
+pub use self::forward::spec::parse_dynamic_forward_spec;
+pub use self::forward::spec::parse_forward_spec;
 pub use self::frames::encframe::EncryptedFrame;
+pub use self::frames::encframe::NonceScheme;
 pub use self::frames::frame::Frame;
+pub use self::kex::DIRECTIONAL_KEYS_MIN_PROTOCOL_VERSION;
 pub use self::kex::HostKeyMismatchFn;
 pub use self::kex::Kex;
 pub use self::kex::KexEvent;
@@ -350,24 +425,32 @@ pub use self::kex::KexMode;
 pub use self::kex::KexOutcome;
 pub use self::kex::KexState;
 pub use self::kex::KexStateMachine;
+pub use self::kex::KeyDirection;
 pub use self::kex::NegotiatedTransport;
 pub use self::kex::ServerKex;
 pub use self::kex::TofuFn;
+pub use self::kex::early::EARLY_KEY_SHARE_MIN_PROTOCOL_VERSION;
 pub use self::kex::env_var_matches;
+pub use self::kex::failure::KEX_FAILURE_REASON_MIN_PROTOCOL_VERSION;
+pub use self::kex::failure::KexFailureReason;
+pub use self::kex::identity::IDENTITY_HIDING_MIN_PROTOCOL_VERSION;
 pub use self::kex::negotiate::AEAD_AES128_GCM_SIV;
 pub use self::kex::negotiate::AEAD_AES256_GCM;
 pub use self::kex::negotiate::AEAD_AES256_GCM_SIV;
 pub use self::kex::negotiate::AEAD_CHACHA20_POLY1305;
 pub use self::kex::negotiate::AlgorithmList;
+pub use self::kex::negotiate::IDENTITY_PROOF_MIN_PROTOCOL_VERSION;
 pub use self::kex::negotiate::KDF_HKDF_SHA256;
 pub use self::kex::negotiate::KDF_HKDF_SHA384;
 pub use self::kex::negotiate::KDF_HKDF_SHA512;
 pub use self::kex::negotiate::KEX_ML_KEM_512_SHA256;
 pub use self::kex::negotiate::KEX_ML_KEM_768_SHA256;
 pub use self::kex::negotiate::KEX_ML_KEM_1024_SHA256;
+pub use self::kex::negotiate::KEX_MLKEM768_X25519_SHA256;
 pub use self::kex::negotiate::KEX_P256_SHA256;
 pub use self::kex::negotiate::KEX_P384_SHA384;
 pub use self::kex::negotiate::KEX_X25519_SHA256;
+pub use self::kex::negotiate::MAC_AEAD_IMPLICIT;
 pub use self::kex::negotiate::MAC_HMAC_SHA256;
 pub use self::kex::negotiate::MAC_HMAC_SHA512;
 pub use self::kex::negotiate::MIN_PROTOCOL_VERSION;
@@ -379,10 +462,19 @@ pub use self::kex::negotiate::negotiate;
 pub use self::kex::negotiate::negotiate_protocol_version;
 pub use self::kex::negotiate::supported_algorithms;
 pub use self::kex::reader::KexReader;
-#[cfg(feature = "unstable")]
 pub use self::kex::reader::parse_full_public_key;
+pub use self::kex::rekey::KeyRatchet;
+pub use self::kex::rekey::REKEY_GRACE_PERIOD;
+pub use self::kex::rekey::REKEY_MIN_PROTOCOL_VERSION;
+pub use self::kex::rekey::RekeyPolicy;
 pub use self::kex::run_key_exchange;
+pub use self::kex::run_key_exchange_over;
 pub use self::kex::sender::KexSender;
+pub use self::kex::ticket::RESUMPTION_TICKET_LIFETIME;
+pub use self::kex::ticket::RESUMPTION_TICKET_MIN_PROTOCOL_VERSION;
+pub use self::kex::ticket::ResumptionTicket;
+pub use self::kex::ticket::{TicketIssuer, TicketIssuerState};
+pub use self::kex::transcript::TRANSCRIPT_MIN_PROTOCOL_VERSION;
 pub use self::keygen::AEADCipher;
 pub use self::keygen::EncryptedKeyPair;
 pub use self::keygen::IdentityKeyPair;
@@ -408,16 +500,24 @@ pub use self::keygen::pk::fingerprint;
 pub use self::keygen::pk::randomart;
 pub use self::keygen::pk::verify_fingerprint;
 pub use self::keygen::validate_identity_key_pair;
+pub use self::session::NAMED_SESSIONS_MIN_PROTOCOL_VERSION;
+pub use self::session::SESSION_SHARING_MIN_PROTOCOL_VERSION;
+pub use self::session::SHARED_SESSIONS_FILE;
+pub use self::session::SessionAccess;
+pub use self::session::SessionEntry;
 pub use self::session::SessionRegistry;
+pub use self::session::SessionRequest;
 pub use self::session::new_session_registry;
+pub use self::session::session_access;
 pub use self::tcp::reader::ConnectionReader;
 pub use self::tcp::writer::ConnectionWriter;
 pub use self::tcp_transport::TcpTransportReader;
 pub use self::tcp_transport::TcpTransportSender;
 pub use self::term::TerminalMessage;
 pub use self::term::{
-    DisplayPreference, Emulator, OverlayCell, OverlayCursor, PredictionEngine, Renderer,
-    paint_overlays_to_ansi, render_prediction_update, render_server_update,
+    DisplayPreference, Emulator, OverlayCell, OverlayCursor, Playback, PredictionEngine,
+    RECORDING_MAGIC, RecordedFrame, Renderer, ScreenChange, ScreenRecorder, paint_overlays_to_ansi,
+    read_recording, render_prediction_update, render_server_update,
 };
 pub use self::tracing::{TracingConfigExt, init_tracing};
 pub use self::udp::DiffMode;
@@ -429,6 +529,12 @@ pub use self::udp::reader::{
 pub use self::udp::sender::MAX_UDP_PAYLOAD;
 pub use self::udp::sender::UdpSender;
 pub use self::udp::statesync::fuzz_statesync_drive;
+pub use self::udp_handshake::connect_udp_handshake;
+pub use self::udp_handshake::listener::UdpHandshakeListener;
+pub use self::utils::HAPPY_EYEBALLS_ATTEMPT_DELAY;
+pub use self::utils::ServerDestination;
+pub use self::utils::connect_happy_eyeballs;
+pub use self::utils::happy_eyeballs_order;
 pub use self::utils::is_exit_title;
 pub use self::utils::parse_server_destination;
 pub use self::utils::to_path_buf;
@@ -6,17 +6,42 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
+//! The key-exchange view of the server's sessions, and the names clients may
+//! give them.
+//!
+//! From [`NAMED_SESSIONS_MIN_PROTOCOL_VERSION`] the client can send a
+//! [`SessionRequest`] during key exchange to start or resume a session by
+//! name, resume only an existing one, or list the sessions it could resume.
+//! From [`SESSION_SHARING_MIN_PROTOCOL_VERSION`] it can also join a session
+//! alongside the clients already attached to it, its own or one another user
+//! shares with it in their [`SHARED_SESSIONS_FILE`].
+
 use std::{collections::HashMap, sync::Arc};
 
+use getset::Getters;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
+/// First wire protocol version in which the client may send a
+/// [`Frame::SessionRequest`](crate::Frame::SessionRequest).
+pub const NAMED_SESSIONS_MIN_PROTOCOL_VERSION: u16 = 19;
+
+/// First wire protocol version in which the client may ask to join a session
+/// with [`SessionRequest::Join`].
+pub const SESSION_SHARING_MIN_PROTOCOL_VERSION: u16 = 20;
+
+/// The file in a user's `~/.mp` naming the other users who may join their
+/// sessions.  Each line is a user, a session name or `*` for all of them, and
+/// optionally `read-write`; a grant is read-only otherwise.  The first line
+/// matching a user and session wins.
+pub const SHARED_SESSIONS_FILE: &str = "shared_sessions";
+
 /// Minimal session registry used during key exchange.
 ///
-/// Maps session UUID → username. This lightweight registry lives in libmoshpit so the
+/// Maps session UUID → owner and name. This lightweight registry lives in libmoshpit so the
 /// key-exchange layer can validate resume requests without depending on higher-level
 /// session state (channels, scrollback, etc.) that lives in the server binary.
-pub type SessionRegistry = Arc<Mutex<HashMap<Uuid, String>>>;
+pub type SessionRegistry = Arc<Mutex<HashMap<Uuid, SessionEntry>>>;
 
 /// Create a new, empty [`SessionRegistry`].
 #[must_use]
@@ -24,11 +49,165 @@ pub fn new_session_registry() -> SessionRegistry {
     Arc::new(Mutex::new(HashMap::new()))
 }
 
+/// A session as the key exchange sees it.
+#[derive(Clone, Debug, Eq, Getters, PartialEq)]
+pub struct SessionEntry {
+    /// The user the session belongs to.
+    #[getset(get = "pub")]
+    user: String,
+    /// The name the client gave the session, if any.  Unique per user.
+    #[getset(get = "pub")]
+    name: Option<String>,
+}
+
+impl SessionEntry {
+    /// A session of `user`, optionally called `name`.
+    #[must_use]
+    pub fn new(user: String, name: Option<String>) -> Self {
+        Self { user, name }
+    }
+
+    /// Whether this is `user`'s session called `name`.
+    #[must_use]
+    pub fn is_named(&self, user: &str, name: &str) -> bool {
+        self.user == user && self.name.as_deref() == Some(name)
+    }
+}
+
+/// What the client asked for with a
+/// [`Frame::SessionRequest`](crate::Frame::SessionRequest).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SessionRequest {
+    /// Resume the user's session called this, or start it (`mp --session`).
+    Named(String),
+    /// Resume the user's session called this, failing when there is none
+    /// (`mp attach`).
+    Attach(String),
+    /// Print the user's sessions instead of starting one (`mp ls`).
+    List,
+    /// Join the session called `name` alongside the clients already attached
+    /// to it instead of taking it over (`mp attach --shared`).  The session
+    /// is `owner`'s, or the user's own when `None`; keystrokes from a
+    /// `read_only` client are dropped.
+    Join {
+        /// The user the session belongs to, when it is not the user's own.
+        owner: Option<String>,
+        /// The session's name.
+        name: String,
+        /// Whether the client only watches.
+        read_only: bool,
+    },
+}
+
+impl SessionRequest {
+    /// The wire form of this request: its kind byte and the session name,
+    /// empty when listing and prefixed with `owner/` when joining another
+    /// user's session.
+    #[must_use]
+    pub fn to_wire(&self) -> (u8, String) {
+        match self {
+            Self::Named(name) => (0, name.clone()),
+            Self::Attach(name) => (1, name.clone()),
+            Self::List => (2, String::new()),
+            Self::Join {
+                owner,
+                name,
+                read_only,
+            } => {
+                let kind = if *read_only { 4 } else { 3 };
+                match owner {
+                    Some(owner) => (kind, format!("{owner}/{name}")),
+                    None => (kind, name.clone()),
+                }
+            }
+        }
+    }
+
+    /// The request sent as `kind` and `name`, or `None` for an unknown kind
+    /// or a missing name.
+    #[must_use]
+    pub fn from_wire(kind: u8, name: String) -> Option<Self> {
+        match kind {
+            0 if !name.is_empty() => Some(Self::Named(name)),
+            1 if !name.is_empty() => Some(Self::Attach(name)),
+            2 => Some(Self::List),
+            3 | 4 => {
+                let (owner, name) = match name.split_once('/') {
+                    Some((owner, name)) if !owner.is_empty() => {
+                        (Some(owner.to_owned()), name.to_owned())
+                    }
+                    Some(_) => return None,
+                    None => (None, name),
+                };
+                (!name.is_empty()).then_some(Self::Join {
+                    owner,
+                    name,
+                    read_only: kind == 4,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The session name this request resumes or starts, if any.
+    #[must_use]
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Self::Named(name) | Self::Attach(name) | Self::Join { name, .. } => Some(name),
+            Self::List => None,
+        }
+    }
+
+    /// The user whose session this request joins, when it is not the
+    /// user's own.
+    #[must_use]
+    pub fn owner(&self) -> Option<&str> {
+        match self {
+            Self::Join { owner, .. } => owner.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// How far another user may go in a session shared with them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SessionAccess {
+    /// The user may watch the session but not type into it.
+    ReadOnly,
+    /// The user may type into the session as well.
+    ReadWrite,
+}
+
+/// The access `grants`, the contents of a [`SHARED_SESSIONS_FILE`], give
+/// `user` to the session called `session`, or `None` when no line does.
+///
+/// Blank lines and lines starting with `#` are skipped, as is a line whose
+/// access is neither `read-only` nor `read-write`.
+#[must_use]
+pub fn session_access(grants: &str, user: &str, session: &str) -> Option<SessionAccess> {
+    grants
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .find_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (grantee, name) = (fields.next()?, fields.next()?);
+            let access = match (fields.next(), fields.next()) {
+                (None | Some("read-only"), None) => SessionAccess::ReadOnly,
+                (Some("read-write"), None) => SessionAccess::ReadWrite,
+                _ => return None,
+            };
+            (grantee == user && (name == "*" || name == session)).then_some(access)
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use uuid::Uuid;
 
-    use super::new_session_registry;
+    use super::{
+        SessionAccess, SessionEntry, SessionRequest, new_session_registry, session_access,
+    };
 
     #[test]
     fn new_session_registry_starts_empty() {
@@ -40,7 +219,68 @@ mod tests {
     fn new_session_registry_insert_and_lookup() {
         let reg = new_session_registry();
         let uuid = Uuid::new_v4();
-        drop(reg.blocking_lock().insert(uuid, "alice".to_owned()));
+        drop(
+            reg.blocking_lock()
+                .insert(uuid, SessionEntry::new("alice".to_owned(), None)),
+        );
         assert!(reg.blocking_lock().contains_key(&uuid));
     }
+
+    #[test]
+    fn names_belong_to_one_user() {
+        let entry = SessionEntry::new("alice".to_owned(), Some("work".to_owned()));
+        assert!(entry.is_named("alice", "work"));
+        assert!(!entry.is_named("bob", "work"));
+        assert!(!entry.is_named("alice", "play"));
+        assert!(!SessionEntry::new("alice".to_owned(), None).is_named("alice", ""));
+    }
+
+    #[test]
+    fn session_requests_survive_the_wire() {
+        for request in [
+            SessionRequest::Named("work".to_owned()),
+            SessionRequest::Attach("work".to_owned()),
+            SessionRequest::List,
+            SessionRequest::Join {
+                owner: None,
+                name: "work".to_owned(),
+                read_only: false,
+            },
+            SessionRequest::Join {
+                owner: Some("alice".to_owned()),
+                name: "work".to_owned(),
+                read_only: true,
+            },
+        ] {
+            let (kind, name) = request.to_wire();
+            assert_eq!(SessionRequest::from_wire(kind, name), Some(request));
+        }
+        assert_eq!(SessionRequest::from_wire(0, String::new()), None);
+        assert_eq!(SessionRequest::from_wire(3, "alice/".to_owned()), None);
+        assert_eq!(SessionRequest::from_wire(4, "/work".to_owned()), None);
+        assert_eq!(SessionRequest::from_wire(7, "work".to_owned()), None);
+    }
+
+    #[test]
+    fn shared_sessions_grant_the_first_matching_line() {
+        let grants = "# who  session  access\n\
+                      bob    work     read-write\n\
+                      bob    *\n\
+                      carol  *        read-write\n\
+                      dave   work     everything\n";
+        assert_eq!(
+            session_access(grants, "bob", "work"),
+            Some(SessionAccess::ReadWrite)
+        );
+        assert_eq!(
+            session_access(grants, "bob", "play"),
+            Some(SessionAccess::ReadOnly)
+        );
+        assert_eq!(
+            session_access(grants, "carol", "play"),
+            Some(SessionAccess::ReadWrite)
+        );
+        assert_eq!(session_access(grants, "dave", "work"), None);
+        assert_eq!(session_access(grants, "erin", "work"), None);
+    }
 }
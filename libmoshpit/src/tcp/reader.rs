@@ -6,25 +6,43 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
-use std::io::Cursor;
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::Cursor,
+};
 
 use anyhow::Result;
 use bon::Builder;
 use bytes::{Buf as _, BytesMut};
-use tokio::{io::AsyncReadExt as _, net::tcp::OwnedReadHalf};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _},
+    net::tcp::OwnedReadHalf,
+};
 
 use crate::{Frame, error::Error, frames::encframe::MAX_ENCFRAME_LENGTH};
 
+/// The byte stream a [`ConnectionReader`] reads from.
+type ReadStream = Box<dyn AsyncRead + Send + Sync + Unpin>;
+
 /// A reader over a `ReadHalf` and `BytesMut` buffer.
-#[derive(Builder, Debug)]
+#[derive(Builder)]
 pub struct ConnectionReader {
-    /// The `ReadHalf` of a TCP stream.
-    reader: OwnedReadHalf,
+    /// The `ReadHalf` of a TCP stream, or of the UDP handshake stream.
+    #[builder(with = |reader: OwnedReadHalf| Box::new(reader))]
+    reader: ReadStream,
     // The buffer for reading frames.
     #[builder(default = BytesMut::with_capacity(4096))]
     buffer: BytesMut,
 }
 
+impl Debug for ConnectionReader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("ConnectionReader")
+            .field("buffer", &self.buffer)
+            .finish_non_exhaustive()
+    }
+}
+
 /// Decode and validate the 8-byte big-endian length prefix of a data blob.
 ///
 /// Pure helper extracted from [`ConnectionReader::read_data`] so the length
@@ -40,6 +58,14 @@ fn decode_data_length(len_buf: [u8; 8]) -> Result<usize> {
 }
 
 impl ConnectionReader {
+    /// Read frames from any byte stream rather than a TCP read half.
+    pub(crate) fn from_stream(reader: impl AsyncRead + Send + Sync + Unpin + 'static) -> Self {
+        Self {
+            reader: Box::new(reader),
+            buffer: BytesMut::with_capacity(4096),
+        }
+    }
+
     /// Read a length-prefixed data blob written by `ConnectionWriter::write_data`.
     ///
     /// Returns `None` when the stream closes cleanly between blobs.
@@ -6,21 +6,43 @@
 // option. All files in the project carrying such notice may not be copied,
 // modified, or distributed except according to those terms.
 
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+
 use anyhow::Result;
 use bincode_next::{config::standard, encode_to_vec};
 use bon::Builder;
-use tokio::{io::AsyncWriteExt as _, net::tcp::OwnedWriteHalf};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt as _},
+    net::tcp::OwnedWriteHalf,
+};
 
 use crate::Frame;
 
+/// The byte stream a [`ConnectionWriter`] writes to.
+type WriteStream = Box<dyn AsyncWrite + Send + Sync + Unpin>;
+
 /// A writer over a `WriteHalf` and `BytesMut` buffer.
-#[derive(Builder, Debug)]
+#[derive(Builder)]
 pub struct ConnectionWriter {
-    /// The `WriteHalf` of a TCP stream.
-    writer: OwnedWriteHalf,
+    /// The `WriteHalf` of a TCP stream, or of the UDP handshake stream.
+    #[builder(with = |writer: OwnedWriteHalf| Box::new(writer))]
+    writer: WriteStream,
+}
+
+impl Debug for ConnectionWriter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("ConnectionWriter").finish_non_exhaustive()
+    }
 }
 
 impl ConnectionWriter {
+    /// Write frames to any byte stream rather than a TCP write half.
+    pub(crate) fn from_stream(writer: impl AsyncWrite + Send + Sync + Unpin + 'static) -> Self {
+        Self {
+            writer: Box::new(writer),
+        }
+    }
+
     /// Write a single `Frame` value to the underlying stream.
     ///
     /// The `Frame` value is written to the socket using the various `write_*`
@@ -30,18 +30,13 @@ use std::{
     process,
     sync::{
         Arc, Mutex, PoisonError,
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
     },
     time::Duration,
 };
 
 use anyhow::Result;
-use aws_lc_rs::{
-    aead::{Aad, LessSafeKey, NONCE_LEN, Nonce},
-    hmac::{Key, sign},
-    rand,
-};
-use bincode_next::{config::standard, encode_to_vec};
+use aws_lc_rs::{aead::LessSafeKey, hmac::Key};
 use bon::Builder;
 use bytes::BytesMut;
 use tokio::{
@@ -50,11 +45,13 @@ use tokio::{
     time::{Instant as TokioInstant, sleep_until},
 };
 use tokio_util::sync::CancellationToken;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::{
-    ConnectionReader, ConnectionWriter, Emulator, EncryptedFrame, TerminalMessage, UuidWrapper,
+    ConnectionReader, ConnectionWriter, Emulator, EncryptedFrame, ExitStatus, KeyRatchet,
+    MoshpitError, NonceScheme, RekeyPolicy, TerminalMessage, UuidWrapper,
+    kex::rekey::SendRatchet,
     udp::{
         reader::{
             ClientRenderCtx, decode_all_capped, intercept_queries_core,
@@ -76,8 +73,9 @@ pub struct TcpTransportSender {
     id: Uuid,
     /// AEAD key for encrypting frame payloads.
     rnk: LessSafeKey,
-    /// HMAC key for authenticating the wire sequence number.
-    hmac: Key,
+    /// HMAC key for authenticating the wire sequence number; `None` under
+    /// `aead-implicit`, where the AEAD tag covers it through the AAD.
+    hmac: Option<Key>,
     /// TCP writer for the data channel.
     writer: ConnectionWriter,
     /// High-priority channel for Keepalive and Shutdown frames.
@@ -87,6 +85,15 @@ pub struct TcpTransportSender {
     /// Next outgoing sequence number.
     #[builder(default)]
     send_seq: u64,
+    /// Key ratchet for this send direction.  `None` (peers older than protocol
+    /// version 4) keeps the handshake keys for the whole session.
+    ratchet: Option<KeyRatchet>,
+    /// When to move to the next key epoch; only consulted when `ratchet` is set.
+    #[builder(default)]
+    rekey_policy: RekeyPolicy,
+    /// How each frame's AEAD nonce is chosen; must match the peer's reader.
+    #[builder(default)]
+    nonce_scheme: NonceScheme,
 }
 
 impl TcpTransportSender {
@@ -96,6 +103,10 @@ impl TcpTransportSender {
     /// * I/O error writing to the TCP stream.
     pub async fn frame_loop(&mut self, token: CancellationToken) -> Result<()> {
         let mut control_active = true;
+        let mut rekey = self
+            .ratchet
+            .take()
+            .map(|ratchet| SendRatchet::new(ratchet, self.rekey_policy));
         loop {
             select! {
                 biased;
@@ -113,6 +124,7 @@ impl TcpTransportSender {
                             self.send_seq += 1;
                             let wire = self.encrypt(&frame, seq)?;
                             self.writer.write_data(&wire).await?;
+                            self.rekey_if_due(rekey.as_mut(), wire.len()).await?;
                         }
                         None => control_active = false,
                     }
@@ -124,6 +136,7 @@ impl TcpTransportSender {
                             self.send_seq += 1;
                             let wire = self.encrypt(&frame, seq)?;
                             self.writer.write_data(&wire).await?;
+                            self.rekey_if_due(rekey.as_mut(), wire.len()).await?;
                         }
                         None => break,
                     }
@@ -133,28 +146,39 @@ impl TcpTransportSender {
         Ok(())
     }
 
+    /// Account for a `wire_len`-byte frame sealed under the current key epoch and,
+    /// once the rekey policy is exhausted, announce and switch to the next epoch.
+    async fn rekey_if_due(
+        &mut self,
+        rekey: Option<&mut SendRatchet>,
+        wire_len: usize,
+    ) -> Result<()> {
+        let Some(rekey) = rekey else {
+            return Ok(());
+        };
+        rekey.record(wire_len);
+        if !rekey.due() {
+            return Ok(());
+        }
+        let (epoch, rnk, hmac) = rekey.advance()?;
+        let seq = self.send_seq;
+        self.send_seq += 1;
+        let wire = self.encrypt(&EncryptedFrame::Rekey(epoch), seq)?;
+        self.writer.write_data(&wire).await?;
+        self.rnk = rnk;
+        self.hmac = hmac;
+        debug!(epoch, "TCP transport sender moved to a new key epoch");
+        Ok(())
+    }
+
     fn encrypt(&self, frame: &EncryptedFrame, seq: u64) -> Result<Vec<u8>> {
-        let data = encode_to_vec(frame, standard())?;
-        let aad = Aad::from(seq.to_be_bytes());
-        let mut encrypted_part = self.id.as_bytes().to_vec();
-        encrypted_part.extend_from_slice(&data);
-        let mut nonce_bytes = [0u8; NONCE_LEN];
-        rand::fill(&mut nonce_bytes)?;
-        let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes)?;
-        self.rnk
-            .seal_in_place_append_tag(nonce, aad, &mut encrypted_part)?;
-        let seq_bytes = seq.to_be_bytes();
-        let mut to_sign = seq_bytes.to_vec();
-        to_sign.extend_from_slice(&encrypted_part);
-        let tag = sign(&self.hmac, &to_sign);
-        let tag_bytes = tag.as_ref();
-        let len = encrypted_part.len().to_be_bytes();
-        let mut packet = nonce_bytes.to_vec();
-        packet.extend_from_slice(&seq_bytes);
-        packet.extend_from_slice(tag_bytes);
-        packet.extend_from_slice(&len);
-        packet.extend_from_slice(&encrypted_part);
-        Ok(packet)
+        frame.seal(
+            seq,
+            self.id,
+            &self.rnk,
+            self.hmac.as_ref(),
+            self.nonce_scheme,
+        )
     }
 }
 
@@ -168,9 +192,9 @@ pub struct TcpTransportReader {
     id: Uuid,
     /// AEAD key for decrypting frame payloads.
     rnk: LessSafeKey,
-    /// HMAC key for verifying the wire sequence number.
-    hmac: Key,
-    /// Length of the HMAC tag in bytes (32 for SHA-256, 64 for SHA-512).
+    /// HMAC key for verifying the wire sequence number; `None` under `aead-implicit`.
+    hmac: Option<Key>,
+    /// Length of the HMAC tag in bytes (32 for SHA-256, 64 for SHA-512, 0 for `aead-implicit`).
     mac_tag_len: usize,
     /// TCP reader for the data channel.
     reader: ConnectionReader,
@@ -187,6 +211,15 @@ pub struct TcpTransportReader {
     repaint_tx: Option<Sender<()>>,
     /// Channel to forward `ClientAck` frames to the `StateSync` task (server mode).
     client_ack_tx: Option<Sender<u64>>,
+    /// Channel to hand forwarded-channel frames to the session's
+    /// [`ForwardMux`](crate::ForwardMux); `None` drops them.
+    forward_tx: Option<Sender<EncryptedFrame>>,
+    /// Where a remote command's exit status is stored when the server reports
+    /// it (client mode).
+    exit_status: Option<Arc<Mutex<Option<ExitStatus>>>>,
+    /// Set once the client acknowledges a remote command's exit status
+    /// (server mode).
+    exit_acked: Option<Arc<AtomicBool>>,
     /// Whether to use legacy raw-passthrough rendering (client mode).
     #[builder(default)]
     passthrough: bool,
@@ -195,6 +228,12 @@ pub struct TcpTransportReader {
     /// builder — it always starts from [`StateSyncClient::default`].
     #[builder(skip)]
     statesync: StateSyncClient,
+    /// Key ratchet for the peer's send direction.  `None` (peers older than
+    /// protocol version 4) keeps the handshake keys for the whole session.
+    ratchet: Option<KeyRatchet>,
+    /// How the peer chose each frame's AEAD nonce.
+    #[builder(default)]
+    nonce_scheme: NonceScheme,
 }
 
 impl TcpTransportReader {
@@ -324,6 +363,20 @@ impl TcpTransportReader {
                                     exit_token.cancel();
                                     break 'session;
                                 }
+                                EncryptedFrame::PtyExitStatus(status) => {
+                                    self.shell_exited(status);
+                                    *exit_msg
+                                        .lock()
+                                        .unwrap_or_else(PoisonError::into_inner) =
+                                        Some(b"[moshpit] Remote session ended.\r\n");
+                                    exit_token.cancel();
+                                    break 'session;
+                                }
+                                EncryptedFrame::CommandExit(status) => {
+                                    self.command_exited(status);
+                                    exit_token.cancel();
+                                    break 'session;
+                                }
                                 EncryptedFrame::ScrollbackStart => {
                                     scrollback_mode = true;
                                 }
@@ -352,10 +405,24 @@ impl TcpTransportReader {
                                         .apply_chunk(seq, total, data, &ctx, nak_out_tx.as_ref())
                                         .await;
                                 }
+                                frame @ (EncryptedFrame::ForwardOpen(_)
+                                | EncryptedFrame::ForwardData(_)
+                                | EncryptedFrame::ForwardAck(_)
+                                | EncryptedFrame::ForwardClose(_)
+                                | EncryptedFrame::ForwardReset(_)
+                                | EncryptedFrame::ForwardListen(_)
+                                | EncryptedFrame::ForwardListening(_)
+                                | EncryptedFrame::ForwardListenRefused(_)
+                                | EncryptedFrame::ForwardOpenUnix(_)
+                                | EncryptedFrame::ForwardListenUnix(_)
+                                | EncryptedFrame::ForwardOpenAgent(_)
+                                | EncryptedFrame::ForwardOpenStdio(_)
+                                | EncryptedFrame::ForwardConnect(_)) => self.deliver_forward(frame),
                                 EncryptedFrame::Resize(_)
                                 | EncryptedFrame::Nak(_)
                                 | EncryptedFrame::RepaintRequest
-                                | EncryptedFrame::ClientAck(_) => {}
+                                | EncryptedFrame::ClientAck(_)
+                                | EncryptedFrame::Rekey(_) => {}
                             }
                         }
                         Ok(None) => {
@@ -447,6 +514,12 @@ impl TcpTransportReader {
                                         warn!("TCP transport: failed to forward ClientAck: {e}");
                                     }
                                 }
+                                EncryptedFrame::CommandExit(_) => {
+                                    if let Some(ref acked) = self.exit_acked {
+                                        acked.store(true, Ordering::Relaxed);
+                                    }
+                                }
+                                frame if frame.is_forward() => self.deliver_forward(frame),
                                 _ => {}
                             }
                         }
@@ -465,6 +538,36 @@ impl TcpTransportReader {
         Ok(())
     }
 
+    /// Hand a forwarded-channel frame to the session's [`ForwardMux`](crate::ForwardMux).
+    fn deliver_forward(&self, frame: EncryptedFrame) {
+        if let Some(ref tx) = self.forward_tx
+            && let Err(e) = tx.try_send(frame)
+        {
+            debug!("TCP transport: dropping forwarded-channel frame: {e}");
+        }
+    }
+
+    /// Record the exit status of the remote login shell.
+    fn shell_exited(&self, status: ExitStatus) {
+        info!("TCP transport: remote shell ended with {status}");
+        if let Some(ref slot) = self.exit_status {
+            *slot.lock().unwrap_or_else(PoisonError::into_inner) = Some(status);
+        }
+    }
+
+    /// Record a remote command's exit status and acknowledge it to the server.
+    fn command_exited(&self, status: ExitStatus) {
+        info!("TCP transport: remote command ended with {status}");
+        if let Some(ref slot) = self.exit_status {
+            *slot.lock().unwrap_or_else(PoisonError::into_inner) = Some(status);
+        }
+        if let Some(ref tx) = self.nak_out_tx
+            && let Err(e) = tx.try_send(EncryptedFrame::CommandExit(status))
+        {
+            warn!("TCP transport: failed to acknowledge command exit: {e}");
+        }
+    }
+
     fn signal_reconnect_or_exit(&self, code: i32) {
         if let Some(ref tx) = self.reconnect_tx {
             let _ = tx.try_send(());
@@ -482,12 +585,16 @@ impl TcpTransportReader {
                 match EncryptedFrame::parse(
                     &mut cursor,
                     self.id,
-                    &self.hmac,
+                    self.hmac.as_ref(),
                     &self.rnk,
                     self.mac_tag_len,
+                    self.nonce_scheme,
                 ) {
                     Ok(Some((frame, _seq))) => {
                         buf.clear();
+                        if let EncryptedFrame::Rekey(epoch) = frame {
+                            self.follow_rekey(epoch)?;
+                        }
                         Ok(Some(frame))
                     }
                     Ok(None) => {
@@ -501,6 +608,29 @@ impl TcpTransportReader {
             }
         }
     }
+
+    /// Move to the key epoch announced by the peer's [`EncryptedFrame::Rekey`].
+    ///
+    /// TCP delivers frames in order, so every frame after the announcement is
+    /// sealed under the new keys and the previous epoch's keys are dropped at once.
+    fn follow_rekey(&mut self, epoch: u32) -> Result<()> {
+        let Some(ratchet) = self.ratchet.as_mut() else {
+            warn!("TCP transport: ignoring Rekey frame, in-session rekeying was not negotiated");
+            return Ok(());
+        };
+        if epoch != ratchet.epoch().saturating_add(1) {
+            error!(
+                "TCP transport: Rekey to epoch {epoch} does not follow epoch {}",
+                ratchet.epoch()
+            );
+            return Err(MoshpitError::InvalidFrame.into());
+        }
+        let next = ratchet.next()?;
+        (self.rnk, self.hmac) = next.keys()?;
+        *ratchet = next;
+        debug!(epoch, "TCP transport reader moved to a new key epoch");
+        Ok(())
+    }
 }
 
 fn now_micros() -> u64 {
@@ -535,7 +665,8 @@ mod tests {
     use super::{TcpTransportReader, TcpTransportSender};
     use crate::{
         ClientRenderCtx, ConnectionReader, ConnectionWriter, DisplayPreference, Emulator,
-        EncryptedFrame, PredictionEngine, Renderer, TerminalMessage, UuidWrapper,
+        EncryptedFrame, ExitStatus, KeyRatchet, NonceScheme, PredictionEngine, RekeyPolicy,
+        Renderer, TerminalMessage, UuidWrapper, kex::negotiate::NegotiatedAlgorithms,
     };
 
     /// Wire-format HMAC tag length for HMAC-SHA512 (64 bytes).  The TCP transport
@@ -797,6 +928,74 @@ mod tests {
         let _joined = handle.await;
     }
 
+    fn seed_ratchet() -> KeyRatchet {
+        KeyRatchet::new(
+            AEAD_BYTES.to_vec(),
+            HMAC_BYTES.to_vec(),
+            NegotiatedAlgorithms::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn sender_rekey_is_followed_by_reader() {
+        let (writer, reader) = make_link().await;
+        let id = Uuid::new_v4();
+        let (_control_tx, control_rx) = channel::<EncryptedFrame>(16);
+        let (data_tx, rx) = channel::<EncryptedFrame>(16);
+        let mut sender = TcpTransportSender::builder()
+            .id(id)
+            .rnk(aead())
+            .hmac(hmac())
+            .writer(writer)
+            .control_rx(control_rx)
+            .rx(rx)
+            .ratchet(seed_ratchet())
+            .rekey_policy(RekeyPolicy::builder().max_packets(1).build())
+            .nonce_scheme(NonceScheme::Sequence)
+            .build();
+        let token = CancellationToken::new();
+        let loop_token = token.clone();
+        let handle = tokio::spawn(async move { sender.frame_loop(loop_token).await });
+
+        for payload in [b"one", b"two"] {
+            data_tx
+                .send(EncryptedFrame::Bytes((
+                    UuidWrapper::new(id),
+                    payload.to_vec(),
+                )))
+                .await
+                .expect("send data");
+        }
+
+        let mut rdr = TcpTransportReader::builder()
+            .id(id)
+            .rnk(aead())
+            .hmac(hmac())
+            .mac_tag_len(MAC_TAG_LEN)
+            .reader(reader)
+            .ratchet(seed_ratchet())
+            .nonce_scheme(NonceScheme::Sequence)
+            .build();
+        let mut frames = Vec::new();
+        for _ in 0..4 {
+            frames.push(rdr.read_frame().await.expect("read").expect("some"));
+        }
+        assert_eq!(
+            frames,
+            vec![
+                EncryptedFrame::Bytes((UuidWrapper::new(id), b"one".to_vec())),
+                EncryptedFrame::Rekey(1),
+                EncryptedFrame::Bytes((UuidWrapper::new(id), b"two".to_vec())),
+                EncryptedFrame::Rekey(2),
+            ]
+        );
+        assert_eq!(rdr.ratchet.as_ref().map(KeyRatchet::epoch), Some(2));
+
+        token.cancel();
+        drop(data_tx);
+        let _joined = handle.await;
+    }
+
     // ── Server frame loop ───────────────────────────────────────────────────
 
     #[tokio::test]
@@ -1186,6 +1385,48 @@ mod tests {
         let _joined = sender_handle.await;
     }
 
+    #[tokio::test]
+    async fn client_frame_loop_pty_exit_status_records_status() {
+        let (writer, reader) = make_link().await;
+        let id = Uuid::new_v4();
+        let (mut sender, _control_tx, data_tx) = make_sender(writer, id);
+        let sender_token = CancellationToken::new();
+        let st = sender_token.clone();
+        let sender_handle = tokio::spawn(async move { sender.frame_loop(st).await });
+
+        let (mut client, _reconnect_rx, _nak_rx) = make_client_reader(reader, id);
+        let slot = Arc::new(Mutex::new(None));
+        client.exit_status = Some(Arc::clone(&slot));
+        let (ctx, _stdout_rx) = make_render_ctx();
+        let token = CancellationToken::new();
+        let exit_token = CancellationToken::new();
+        let exit_msg: Arc<Mutex<Option<&'static [u8]>>> = Arc::new(Mutex::new(None));
+        let et = exit_token.clone();
+        let em = Arc::clone(&exit_msg);
+        let client_handle = tokio::spawn(async move {
+            client.client_frame_loop(token, et, em, ctx).await;
+        });
+
+        data_tx
+            .send(EncryptedFrame::PtyExitStatus(ExitStatus::Code(3)))
+            .await
+            .expect("send pty exit status");
+
+        timeout(Duration::from_secs(2), client_handle)
+            .await
+            .expect("client loop should finish")
+            .expect("client join");
+        assert!(
+            exit_token.is_cancelled(),
+            "PtyExitStatus cancels the exit token"
+        );
+        assert!(exit_msg.lock().unwrap().is_some());
+        assert_eq!(*slot.lock().unwrap(), Some(ExitStatus::Code(3)));
+
+        sender_token.cancel();
+        let _joined = sender_handle.await;
+    }
+
     #[tokio::test]
     async fn client_frame_loop_shutdown_signals_reconnect() {
         let (writer, reader) = make_link().await;
@@ -8,10 +8,14 @@
 
 pub(crate) mod emulator;
 pub(crate) mod prediction;
+pub(crate) mod recording;
 pub(crate) mod renderer;
 
 pub use self::emulator::Emulator;
 pub use self::prediction::{DisplayPreference, OverlayCell, OverlayCursor, PredictionEngine};
+pub use self::recording::{
+    Playback, RECORDING_MAGIC, RecordedFrame, ScreenChange, ScreenRecorder, read_recording,
+};
 pub use self::renderer::{
     Renderer, paint_overlays_to_ansi, render_prediction_update, render_server_update,
 };
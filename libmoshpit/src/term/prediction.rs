@@ -33,6 +33,7 @@
 
 use std::time::Instant;
 
+use bincode_next::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 
 /// How aggressively to display local-echo predictions.
@@ -172,7 +173,7 @@ impl ConditionalCursorMove {
 // ── a single rendered prediction overlay ────────────────────────────────────
 
 /// A cell to be painted on top of the real screen when rendering.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Decode, Encode, Eq, PartialEq)]
 pub struct OverlayCell {
     /// Screen row (0-based).
     pub row: u16,
@@ -185,7 +186,7 @@ pub struct OverlayCell {
 }
 
 /// Predicted cursor position to be applied after rendering overlay cells.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Decode, Encode, Eq, PartialEq)]
 pub struct OverlayCursor {
     /// Screen row (0-based).
     pub row: u16,
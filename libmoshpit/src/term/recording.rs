@@ -0,0 +1,567 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Client-side session recordings (`mp --record`) and their playback
+//! (`mp replay`).
+//!
+//! A [`ScreenRecorder`] attached to the client's [`Renderer`] writes down every
+//! frame the renderer draws: the emulator's screen — in full now and then, as
+//! a [`vt100::Screen::contents_diff`] from the previous frame otherwise — and
+//! the prediction overlays painted on top of it, with the time since the
+//! recording started.  A [`Playback`] rebuilds the screen of any moment in an
+//! [`Emulator`], and handing that screen and overlays to a fresh [`Renderer`]
+//! draws exactly what the user saw.
+//!
+//! A recording file starts with [`RECORDING_MAGIC`], followed by the frames,
+//! each a little-endian `u32` length and a bincode-encoded [`RecordedFrame`].
+//!
+//! [`Renderer`]: super::Renderer
+
+use std::{
+    fs::{File, read},
+    io::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, bail};
+use bincode_next::{Decode, Encode, config::standard, decode_from_slice, encode_to_vec};
+use getset::{CopyGetters, Getters};
+
+use super::emulator::Emulator;
+use super::prediction::{OverlayCell, OverlayCursor};
+
+/// The first bytes of every recording file.
+pub const RECORDING_MAGIC: &[u8; 8] = b"MPREC01\n";
+
+/// Longest stretch of recording time between two full screens, which bounds
+/// how much of a recording playback replays to seek.
+const KEYFRAME_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Largest frame a recording may hold (16 MiB).
+const FRAME_LIMIT: usize = 16 * 1024 * 1024;
+
+/// How the emulator's screen changed for a [`RecordedFrame`].
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub enum ScreenChange {
+    /// The whole screen: in the first frame, after a resize or a switch to or
+    /// from the alternate screen, and every few seconds so playback can seek.
+    Full {
+        /// Number of rows
+        rows: u16,
+        /// Number of columns
+        cols: u16,
+        /// Whether the alternate screen is shown.
+        alternate: bool,
+        /// The screen as [`vt100::Screen::contents_formatted`] bytes.
+        contents: Vec<u8>,
+    },
+    /// The changes since the previous frame, as
+    /// [`vt100::Screen::contents_diff`] bytes.
+    Diff(Vec<u8>),
+}
+
+/// One frame the client's renderer drew.
+#[derive(Clone, CopyGetters, Debug, Decode, Encode, Eq, Getters, PartialEq)]
+pub struct RecordedFrame {
+    /// Milliseconds since the recording started.
+    #[getset(get_copy = "pub")]
+    at_ms: u64,
+    /// The emulator's screen.
+    #[getset(get = "pub")]
+    screen: ScreenChange,
+    /// Predicted cells painted on top of the screen.
+    #[getset(get = "pub")]
+    overlays: Vec<OverlayCell>,
+    /// The predicted cursor position, if any.
+    #[getset(get_copy = "pub")]
+    cursor: Option<OverlayCursor>,
+}
+
+impl RecordedFrame {
+    /// When the frame was drawn, since the recording started.
+    #[must_use]
+    pub fn at(&self) -> Duration {
+        Duration::from_millis(self.at_ms)
+    }
+}
+
+/// A parser showing `contents` on a `rows` by `cols` screen, on the alternate
+/// screen if `alternate`.
+fn screen_parser(rows: u16, cols: u16, alternate: bool, contents: &[u8]) -> vt100::Parser {
+    let mut parser = vt100::Parser::new(rows, cols, 0);
+    if alternate {
+        parser.process(b"\x1b[?1049h");
+    }
+    parser.process(contents);
+    parser
+}
+
+/// Writes the frames a [`Renderer`](super::Renderer) draws to a recording.
+pub struct ScreenRecorder {
+    out: Box<dyn Write + Send>,
+    started: Instant,
+    /// The screen as the recording has it so far.
+    recorded: Option<vt100::Parser>,
+    /// When the last full screen was recorded.
+    last_full: Duration,
+    last_overlays: Vec<OverlayCell>,
+    last_cursor: Option<OverlayCursor>,
+}
+
+impl std::fmt::Debug for ScreenRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScreenRecorder")
+            .field("started", &self.started)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ScreenRecorder {
+    /// Start a recording in a new file at `path`, replacing any file there.
+    ///
+    /// # Errors
+    /// * The file cannot be created or written.
+    pub fn create(path: &Path) -> Result<Self> {
+        Self::new(Box::new(File::create(path)?))
+    }
+
+    /// Start a recording written to `out`.
+    ///
+    /// # Errors
+    /// * `out` cannot be written.
+    pub fn new(mut out: Box<dyn Write + Send>) -> Result<Self> {
+        out.write_all(RECORDING_MAGIC)?;
+        out.flush()?;
+        Ok(Self {
+            out,
+            started: Instant::now(),
+            recorded: None,
+            last_full: Duration::ZERO,
+            last_overlays: Vec::new(),
+            last_cursor: None,
+        })
+    }
+
+    /// Record a frame showing `screen` with `overlays` and `cursor` on top,
+    /// unless it looks the same as the previous one.
+    ///
+    /// # Errors
+    /// * The recording cannot be written.
+    pub fn record(
+        &mut self,
+        screen: &vt100::Screen,
+        overlays: &[OverlayCell],
+        cursor: Option<OverlayCursor>,
+    ) -> Result<()> {
+        self.record_at(self.started.elapsed(), screen, overlays, cursor)
+    }
+
+    fn record_at(
+        &mut self,
+        at: Duration,
+        screen: &vt100::Screen,
+        overlays: &[OverlayCell],
+        cursor: Option<OverlayCursor>,
+    ) -> Result<()> {
+        let (rows, cols) = screen.size();
+        let alternate = screen.alternate_screen();
+        let full = at.saturating_sub(self.last_full) >= KEYFRAME_INTERVAL
+            || self.recorded.as_ref().is_none_or(|recorded| {
+                recorded.screen().size() != (rows, cols)
+                    || recorded.screen().alternate_screen() != alternate
+            });
+        let change = match self.recorded.as_mut() {
+            Some(recorded) if !full => {
+                let diff = screen.contents_diff(recorded.screen());
+                if diff.is_empty() && overlays == self.last_overlays && cursor == self.last_cursor {
+                    return Ok(());
+                }
+                recorded.process(&diff);
+                ScreenChange::Diff(diff)
+            }
+            _ => {
+                let contents = screen.contents_formatted();
+                self.recorded = Some(screen_parser(rows, cols, alternate, &contents));
+                self.last_full = at;
+                ScreenChange::Full {
+                    rows,
+                    cols,
+                    alternate,
+                    contents,
+                }
+            }
+        };
+        let frame = RecordedFrame {
+            at_ms: u64::try_from(at.as_millis()).unwrap_or(u64::MAX),
+            screen: change,
+            overlays: overlays.to_vec(),
+            cursor,
+        };
+        let encoded = encode_to_vec(&frame, standard())?;
+        let mut record = Vec::with_capacity(4 + encoded.len());
+        record.extend_from_slice(&u32::try_from(encoded.len())?.to_le_bytes());
+        record.extend_from_slice(&encoded);
+        self.out.write_all(&record)?;
+        self.out.flush()?;
+        self.last_overlays = frame.overlays;
+        self.last_cursor = cursor;
+        Ok(())
+    }
+}
+
+/// Read the frames of the recording at `path`.  A frame cut short, as by the
+/// client being killed mid-write, ends the recording.
+///
+/// # Errors
+/// * The file cannot be read or is not a recording.
+pub fn read_recording(path: &Path) -> Result<Vec<RecordedFrame>> {
+    parse_recording(&read(path)?)
+}
+
+fn parse_recording(data: &[u8]) -> Result<Vec<RecordedFrame>> {
+    let Some(mut rest) = data.strip_prefix(RECORDING_MAGIC.as_slice()) else {
+        bail!("not a moshpit recording");
+    };
+    let mut frames = Vec::new();
+    while let Some((len, after)) = rest.split_first_chunk::<4>() {
+        let len = usize::try_from(u32::from_le_bytes(*len))?;
+        if len > FRAME_LIMIT {
+            bail!("recording frame of {len} bytes is too large");
+        }
+        let Some(encoded) = after.get(..len) else {
+            break;
+        };
+        let config = standard().with_limit::<FRAME_LIMIT>();
+        let (frame, _) = decode_from_slice::<RecordedFrame, _>(encoded, config)?;
+        frames.push(frame);
+        rest = &after[len..];
+    }
+    Ok(frames)
+}
+
+/// Plays a recording back: the screen and overlays of any moment of it.
+#[derive(Debug)]
+pub struct Playback {
+    frames: Vec<RecordedFrame>,
+    /// The frames applied to `emulator` so far.
+    applied: usize,
+    emulator: Emulator,
+}
+
+impl Playback {
+    /// A playback of `frames`, positioned before the first.
+    #[must_use]
+    pub fn new(frames: Vec<RecordedFrame>) -> Self {
+        Self {
+            frames,
+            applied: 0,
+            emulator: Emulator::new(24, 80),
+        }
+    }
+
+    /// How long the recording runs.
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        self.frames.last().map_or(Duration::ZERO, RecordedFrame::at)
+    }
+
+    /// When the frame on show was drawn.
+    #[must_use]
+    pub fn position(&self) -> Duration {
+        self.current().map_or(Duration::ZERO, RecordedFrame::at)
+    }
+
+    /// When the next frame is drawn, or `None` at the end of the recording.
+    #[must_use]
+    pub fn next_at(&self) -> Option<Duration> {
+        self.frames.get(self.applied).map(RecordedFrame::at)
+    }
+
+    /// The screen of the frame on show.
+    #[must_use]
+    pub fn screen(&self) -> &vt100::Screen {
+        self.emulator.screen()
+    }
+
+    /// The predicted cells painted on the frame on show.
+    #[must_use]
+    pub fn overlays(&self) -> &[OverlayCell] {
+        self.current()
+            .map_or(&[], |frame| frame.overlays.as_slice())
+    }
+
+    /// The predicted cursor position of the frame on show.
+    #[must_use]
+    pub fn cursor(&self) -> Option<OverlayCursor> {
+        self.current().and_then(RecordedFrame::cursor)
+    }
+
+    fn current(&self) -> Option<&RecordedFrame> {
+        self.applied.checked_sub(1).and_then(|i| self.frames.get(i))
+    }
+
+    /// Show the last frame drawn at or before `at`, returning whether that
+    /// changed the frame on show.
+    pub fn seek(&mut self, at: Duration) -> bool {
+        let target = self.frames.partition_point(|frame| frame.at() <= at);
+        if target == self.applied {
+            return false;
+        }
+        // A full screen stands on its own, so start from the last one before
+        // the target whenever the frames on show cannot simply be added to.
+        let keyframe = self.frames[..target]
+            .iter()
+            .rposition(|frame| matches!(frame.screen, ScreenChange::Full { .. }))
+            .unwrap_or(0);
+        if target < self.applied || keyframe > self.applied {
+            self.applied = keyframe;
+        }
+        while self.applied < target && self.step() {}
+        true
+    }
+
+    /// Show the next frame, returning `false` at the end of the recording.
+    pub fn step(&mut self) -> bool {
+        if self.applied == self.frames.len() {
+            return false;
+        }
+        self.apply(self.applied);
+        self.applied += 1;
+        true
+    }
+
+    fn apply(&mut self, index: usize) {
+        match &self.frames[index].screen {
+            ScreenChange::Full {
+                rows,
+                cols,
+                alternate,
+                contents,
+            } => self
+                .emulator
+                .replace_parser(screen_parser(*rows, *cols, *alternate, contents)),
+            ScreenChange::Diff(diff) => self.emulator.process(diff),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex, PoisonError},
+        time::Duration,
+    };
+
+    use super::{
+        Playback, RECORDING_MAGIC, RecordedFrame, ScreenChange, ScreenRecorder, parse_recording,
+    };
+    use crate::term::{
+        emulator::Emulator,
+        prediction::{OverlayCell, OverlayCursor},
+        renderer::Renderer,
+    };
+
+    /// A writer whose bytes stay readable after the recorder takes it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn bytes(&self) -> Vec<u8> {
+            self.0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone()
+        }
+    }
+
+    fn record(screens: &[&[u8]]) -> anyhow::Result<Vec<RecordedFrame>> {
+        let out = Shared::default();
+        let mut recorder = ScreenRecorder::new(Box::new(out.clone()))?;
+        let mut parser = vt100::Parser::new(24, 80, 0);
+        for (second, bytes) in (0..).zip(screens) {
+            parser.process(bytes);
+            recorder.record_at(Duration::from_secs(second), parser.screen(), &[], None)?;
+        }
+        parse_recording(&out.bytes())
+    }
+
+    #[test]
+    fn recording_starts_full_then_diffs() -> anyhow::Result<()> {
+        let frames = record(&[b"hello", b" world"])?;
+        assert_eq!(frames.len(), 2);
+        assert!(matches!(
+            frames[0].screen(),
+            ScreenChange::Full {
+                rows: 24,
+                cols: 80,
+                ..
+            }
+        ));
+        assert!(matches!(frames[1].screen(), ScreenChange::Diff(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn recording_repeats_the_full_screen_now_and_then() -> anyhow::Result<()> {
+        let frames = record(&[
+            b"0", b"1", b"2", b"3", b"4", b"5", b"6", b"7", b"8", b"9", b"10",
+        ])?;
+        assert!(matches!(frames[9].screen(), ScreenChange::Diff(_)));
+        assert!(matches!(frames[10].screen(), ScreenChange::Full { .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn recording_skips_unchanged_frames() -> anyhow::Result<()> {
+        let frames = record(&[b"hello", b""])?;
+        assert_eq!(frames.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn recording_keeps_overlays() -> anyhow::Result<()> {
+        let out = Shared::default();
+        let mut recorder = ScreenRecorder::new(Box::new(out.clone()))?;
+        let parser = vt100::Parser::new(24, 80, 0);
+        let overlay = OverlayCell {
+            row: 0,
+            col: 0,
+            ch: 'x',
+            flagged: true,
+        };
+        let cursor = OverlayCursor { row: 0, col: 1 };
+        recorder.record(parser.screen(), &[], None)?;
+        recorder.record(parser.screen(), &[overlay], Some(cursor))?;
+        let frames = parse_recording(&out.bytes())?;
+        assert_eq!(frames.len(), 2, "an overlay alone is a new frame");
+        assert_eq!(frames[1].overlays(), &[overlay]);
+        assert_eq!(frames[1].cursor(), Some(cursor));
+        Ok(())
+    }
+
+    #[test]
+    fn recording_without_magic_is_rejected() {
+        assert!(parse_recording(b"not a recording").is_err());
+    }
+
+    #[test]
+    fn recording_cut_short_keeps_whole_frames() -> anyhow::Result<()> {
+        let out = Shared::default();
+        let mut recorder = ScreenRecorder::new(Box::new(out.clone()))?;
+        let mut parser = vt100::Parser::new(24, 80, 0);
+        parser.process(b"hello");
+        recorder.record(parser.screen(), &[], None)?;
+        let mut bytes = out.bytes();
+        bytes.extend_from_slice(&100u32.to_le_bytes());
+        bytes.extend_from_slice(b"partial");
+        assert_eq!(parse_recording(&bytes)?.len(), 1);
+        assert_eq!(
+            parse_recording(RECORDING_MAGIC)?.len(),
+            0,
+            "an empty recording"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn playback_rebuilds_the_recorded_screens() -> anyhow::Result<()> {
+        let frames = record(&[b"hello", b"\r\nworld", b"\x1b[2J\x1b[Hagain"])?;
+        let mut playback = Playback::new(frames.clone());
+        assert!(playback.seek(frames[1].at()));
+        assert_eq!(playback.screen().contents(), "hello\nworld");
+        assert_eq!(playback.next_at(), Some(frames[2].at()));
+        assert!(playback.seek(Duration::MAX));
+        assert_eq!(playback.screen().contents(), "again");
+        assert!(playback.next_at().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn playback_seeks_backwards() -> anyhow::Result<()> {
+        let frames = record(&[b"one", b" two", b" three"])?;
+        let mut playback = Playback::new(frames);
+        assert!(playback.seek(Duration::MAX));
+        assert_eq!(playback.screen().contents(), "one two three");
+        assert!(playback.seek(Duration::ZERO));
+        assert!(playback.screen().contents().starts_with("one"));
+        assert!(!playback.seek(Duration::ZERO), "already on show");
+        Ok(())
+    }
+
+    #[test]
+    fn replay_draws_what_the_renderer_drew() -> anyhow::Result<()> {
+        let out = Shared::default();
+        let recorder = ScreenRecorder::new(Box::new(out.clone()))?;
+        let mut renderer = Renderer::new(24, 80);
+        renderer.set_recorder(Arc::new(Mutex::new(recorder)));
+        let mut emulator = Emulator::new(24, 80);
+        let mut terminal = vt100::Parser::new(24, 80, 0);
+        let predicted = OverlayCell {
+            row: 0,
+            col: 2,
+            ch: 'l',
+            flagged: true,
+        };
+        let steps: [(&[u8], Vec<OverlayCell>); 3] = [
+            (b"$ ", vec![]),
+            (b"", vec![predicted]),
+            (b"ls\r\nfile\r\n$ ", vec![]),
+        ];
+        let mut seen = Vec::new();
+        for (bytes, overlays) in steps {
+            emulator.process(bytes);
+            terminal.process(&renderer.render(emulator.screen(), &overlays, None));
+            seen.push(terminal.screen().contents_formatted());
+        }
+
+        let mut playback = Playback::new(parse_recording(&out.bytes())?);
+        let mut replayer = Renderer::new(24, 80);
+        let mut replay_terminal = vt100::Parser::new(24, 80, 0);
+        for seen in seen {
+            assert!(playback.step());
+            let out = replayer.render(playback.screen(), playback.overlays(), playback.cursor());
+            replay_terminal.process(&out);
+            assert_eq!(replay_terminal.screen().contents_formatted(), seen);
+        }
+        assert!(!playback.step());
+        Ok(())
+    }
+
+    #[test]
+    fn playback_follows_the_alternate_screen() -> anyhow::Result<()> {
+        let frames = record(&[b"shell", b"\x1b[?1049hvi"])?;
+        assert!(matches!(
+            frames[1].screen(),
+            ScreenChange::Full {
+                alternate: true,
+                ..
+            }
+        ));
+        let mut playback = Playback::new(frames);
+        assert!(playback.seek(Duration::MAX));
+        assert!(playback.screen().alternate_screen());
+        assert_eq!(playback.screen().contents(), "vi");
+        Ok(())
+    }
+}
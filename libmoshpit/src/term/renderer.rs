@@ -39,8 +39,11 @@ use std::{
     sync::{Arc, Mutex, PoisonError},
 };
 
+use tracing::warn;
+
 use super::emulator::Emulator;
 use super::prediction::{OverlayCell, OverlayCursor, PredictionEngine};
+use super::recording::ScreenRecorder;
 
 /// A stateful differential renderer.
 pub struct Renderer {
@@ -48,12 +51,15 @@ pub struct Renderer {
     displayed: vt100::Parser,
     /// True after the first render — before that we must do a full refresh.
     initialized: bool,
+    /// Where every rendered frame is recorded (`mp --record`), if anywhere.
+    recorder: Option<Arc<Mutex<ScreenRecorder>>>,
 }
 
 impl fmt::Debug for Renderer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Renderer")
             .field("initialized", &self.initialized)
+            .field("recording", &self.recorder.is_some())
             .finish_non_exhaustive()
     }
 }
@@ -65,9 +71,16 @@ impl Renderer {
         Self {
             displayed: vt100::Parser::new(rows, cols, 0),
             initialized: false,
+            recorder: None,
         }
     }
 
+    /// Record every frame rendered from now on with `recorder`.  The recorder
+    /// outlives the renderer, so one recording spans reconnects.
+    pub fn set_recorder(&mut self, recorder: Arc<Mutex<ScreenRecorder>>) {
+        self.recorder = Some(recorder);
+    }
+
     /// Resize the renderer's view of the physical terminal.
     pub fn set_size(&mut self, rows: u16, cols: u16) {
         // Resizing forces a full refresh on the next render.
@@ -92,6 +105,17 @@ impl Renderer {
         overlays: &[OverlayCell],
         cursor: Option<OverlayCursor>,
     ) -> Vec<u8> {
+        if let Some(recorder) = &self.recorder {
+            let recorded = recorder
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .record(screen, overlays, cursor);
+            if let Err(e) = recorded {
+                warn!("cannot write the session recording, no longer recording: {e}");
+                self.recorder = None;
+            }
+        }
+
         let new_alt = screen.alternate_screen();
         let old_alt = self.displayed.screen().alternate_screen();
         // An alt-screen buffer swap discards the previous buffer's contents, so
@@ -11,7 +11,7 @@ use std::{
     fmt,
     future::pending,
     io::Cursor,
-    mem::take,
+    mem::{replace, take},
     net::SocketAddr,
     process,
     sync::{
@@ -41,9 +41,9 @@ use uuid::Uuid;
 
 use super::DiffMode;
 use crate::{
-    Emulator, EncryptedFrame, MoshpitError, PredictionEngine, Renderer, TerminalMessage,
-    UuidWrapper, paint_overlays_to_ansi, render_server_update, udp::sender::RETRANSMIT_WINDOW,
-    utils::is_exit_title,
+    Emulator, EncryptedFrame, ExitStatus, KeyRatchet, MoshpitError, NonceScheme, PredictionEngine,
+    REKEY_GRACE_PERIOD, Renderer, TerminalMessage, UuidWrapper, paint_overlays_to_ansi,
+    render_server_update, udp::sender::RETRANSMIT_WINDOW, utils::is_exit_title,
 };
 
 /// Floor for the adaptive NAK check interval.  On LAN paths where `nak_timeout`
@@ -84,10 +84,11 @@ pub struct UdpReader {
     id: Uuid,
     /// Key for decrypting UDP packets
     rnk: LessSafeKey,
-    /// Key for verifying UDP packet HMAC
-    hmac: Key,
+    /// Key for verifying UDP packet HMAC; `None` when the AEAD tag alone authenticates
+    /// packets ([`MAC_AEAD_IMPLICIT`](crate::MAC_AEAD_IMPLICIT))
+    hmac: Option<Key>,
     /// Byte length of the MAC tag written by the peer's HMAC algorithm.
-    /// 64 for HMAC-SHA512 (default), 32 for HMAC-SHA256.
+    /// 64 for HMAC-SHA512 (default), 32 for HMAC-SHA256, 0 for `aead-implicit`.
     #[builder(default = 64)]
     mac_tag_len: usize,
     /// Injects NAK frames into the outbound stream when gaps are detected
@@ -205,11 +206,36 @@ pub struct UdpReader {
     /// receive loop to the state-sync task in `moshpits/src/runtime.rs`, which uses
     /// them to advance the server's ack baseline.
     client_ack_tx: Option<Sender<u64>>,
+    /// Hands the peer's forwarded-channel frames (`EncryptedFrame::Forward*`) to the
+    /// session's [`ForwardMux`](crate::ForwardMux).  `None` drops them.
+    forward_tx: Option<Sender<EncryptedFrame>>,
+    /// Client-mode: where a remote command's exit status is stored when the server
+    /// reports it in [`EncryptedFrame::CommandExit`].
+    exit_status: Option<Arc<Mutex<Option<ExitStatus>>>>,
+    /// Server-mode: set once the client echoes [`EncryptedFrame::CommandExit`] back,
+    /// acknowledging the remote command's exit status.
+    exit_acked: Option<Arc<AtomicBool>>,
     /// Timestamp (µs since UNIX epoch) of the last authenticated UDP frame received from
     /// the peer.  Updated on every successful parse in server mode.  The server-side silence
     /// watchdog in `moshpits` polls this counter and cancels zombie connections after 30 s
     /// of client silence.
     last_rx_us: Option<Arc<AtomicU64>>,
+    /// Key ratchet for the peer's send direction, seeded from the session keys.
+    /// `None` (peers older than protocol version 4) keeps the handshake keys for
+    /// the whole session.
+    ratchet: Option<KeyRatchet>,
+    /// Keys for the epoch after `ratchet`'s, derived on first use so that a packet
+    /// sealed after a lost or reordered [`EncryptedFrame::Rekey`] still authenticates.
+    #[builder(skip)]
+    next_keys: Option<(LessSafeKey, Option<Key>)>,
+    /// Keys for the previous epoch and the instant they stop being accepted
+    /// ([`REKEY_GRACE_PERIOD`] after the switch), so in-flight and retransmitted
+    /// packets sealed before a rekey are not dropped.
+    #[builder(skip)]
+    previous_keys: Option<(LessSafeKey, Option<Key>, Instant)>,
+    /// How the peer chose each packet's AEAD nonce.
+    #[builder(default)]
+    nonce_scheme: NonceScheme,
 }
 
 /// Hard cap on the size of any single decompressed server payload (16 MiB).
@@ -738,6 +764,35 @@ impl UdpReader {
         }
     }
 
+    /// Server-mode: note the client's acknowledgement of a remote command's exit status.
+    fn command_exit_acked(&self) {
+        if let Some(ref acked) = self.exit_acked {
+            acked.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Client-mode: record the exit status of the remote login shell.
+    fn shell_exited(&self, status: ExitStatus) {
+        info!("Remote shell ended with {status}");
+        if let Some(ref slot) = self.exit_status {
+            *slot.lock().unwrap_or_else(PoisonError::into_inner) = Some(status);
+        }
+    }
+
+    /// Client-mode: record a remote command's exit status and acknowledge it to the
+    /// server.
+    fn command_exited(&self, status: ExitStatus) {
+        info!("Remote command ended with {status}");
+        if let Some(ref slot) = self.exit_status {
+            *slot.lock().unwrap_or_else(PoisonError::into_inner) = Some(status);
+        }
+        if let Some(ref tx) = self.nak_out_tx
+            && let Err(e) = tx.try_send(EncryptedFrame::CommandExit(status))
+        {
+            warn!("Failed to acknowledge command exit: {e}");
+        }
+    }
+
     /// Route a NAK frame to the retransmit channel (consuming it and returning
     /// `None`), or pass any other frame through as `Some(frame)`.
     ///
@@ -757,6 +812,14 @@ impl UdpReader {
             }
             return None;
         }
+        if frame.is_forward() {
+            if let Some(ref tx) = self.forward_tx
+                && let Err(e) = tx.try_send(frame)
+            {
+                debug!("Dropping forwarded-channel frame: {e}");
+            }
+            return None;
+        }
         Some(frame)
     }
 
@@ -932,7 +995,22 @@ impl UdpReader {
                             | EncryptedFrame::CompressedBytes(_)
                             | EncryptedFrame::StateSyncDiff(_)
                             | EncryptedFrame::PtyExit
-                            | EncryptedFrame::StateChunk(_) => {}
+                            | EncryptedFrame::PtyExitStatus(_)
+                            | EncryptedFrame::StateChunk(_)
+                            | EncryptedFrame::Rekey(_)
+                            | EncryptedFrame::ForwardOpen(_)
+                            | EncryptedFrame::ForwardData(_)
+                            | EncryptedFrame::ForwardAck(_)
+                            | EncryptedFrame::ForwardClose(_)
+                            | EncryptedFrame::ForwardReset(_)
+                            | EncryptedFrame::ForwardListen(_)
+                            | EncryptedFrame::ForwardListening(_)
+                            | EncryptedFrame::ForwardListenRefused(_)
+                            | EncryptedFrame::ForwardOpenUnix(_)
+                            | EncryptedFrame::ForwardListenUnix(_)
+                            | EncryptedFrame::ForwardOpenAgent(_)
+                            | EncryptedFrame::ForwardOpenStdio(_)
+                            | EncryptedFrame::ForwardConnect(_) => {}
                             EncryptedFrame::ClientAck(diff_id) => {
                                 if let Some(ref tx) = self.client_ack_tx
                                     && let Err(e) = tx.try_send(diff_id)
@@ -940,6 +1018,7 @@ impl UdpReader {
                                     warn!("Failed to forward ClientAck: {e}");
                                 }
                             }
+                            EncryptedFrame::CommandExit(_) => self.command_exit_acked(),
                         }
                     }
                 }
@@ -991,8 +1070,24 @@ impl UdpReader {
                             | EncryptedFrame::CompressedBytes(_)
                             | EncryptedFrame::StateSyncDiff(_)
                             | EncryptedFrame::PtyExit
+                            | EncryptedFrame::PtyExitStatus(_)
                             | EncryptedFrame::StateChunk(_)
-                            | EncryptedFrame::ClientAck(_) => {}
+                            | EncryptedFrame::ClientAck(_)
+                            | EncryptedFrame::Rekey(_)
+                            | EncryptedFrame::ForwardOpen(_)
+                            | EncryptedFrame::ForwardData(_)
+                            | EncryptedFrame::ForwardAck(_)
+                            | EncryptedFrame::ForwardClose(_)
+                            | EncryptedFrame::ForwardReset(_)
+                            | EncryptedFrame::ForwardListen(_)
+                            | EncryptedFrame::ForwardListening(_)
+                            | EncryptedFrame::ForwardListenRefused(_)
+                            | EncryptedFrame::ForwardOpenUnix(_)
+                            | EncryptedFrame::ForwardListenUnix(_)
+                            | EncryptedFrame::ForwardOpenAgent(_)
+                            | EncryptedFrame::ForwardOpenStdio(_)
+                            | EncryptedFrame::ForwardConnect(_) => {}
+                            EncryptedFrame::CommandExit(_) => self.command_exit_acked(),
                         }
                     }
                     nak_check_deadline = TokioInstant::now() + if self.gap_first_seen.is_empty() {
@@ -1046,7 +1141,22 @@ impl UdpReader {
                                     | EncryptedFrame::CompressedBytes(_)
                                     | EncryptedFrame::StateSyncDiff(_)
                                     | EncryptedFrame::PtyExit
-                                    | EncryptedFrame::StateChunk(_) => {}
+                                    | EncryptedFrame::PtyExitStatus(_)
+                                    | EncryptedFrame::StateChunk(_)
+                                    | EncryptedFrame::Rekey(_)
+                                    | EncryptedFrame::ForwardOpen(_)
+                                    | EncryptedFrame::ForwardData(_)
+                                    | EncryptedFrame::ForwardAck(_)
+                                    | EncryptedFrame::ForwardClose(_)
+                                    | EncryptedFrame::ForwardReset(_)
+                                    | EncryptedFrame::ForwardListen(_)
+                                    | EncryptedFrame::ForwardListening(_)
+                                    | EncryptedFrame::ForwardListenRefused(_)
+                                    | EncryptedFrame::ForwardOpenUnix(_)
+                                    | EncryptedFrame::ForwardListenUnix(_)
+                                    | EncryptedFrame::ForwardOpenAgent(_)
+                                    | EncryptedFrame::ForwardOpenStdio(_)
+                                    | EncryptedFrame::ForwardConnect(_) => {}
                                     EncryptedFrame::ClientAck(diff_id) => {
                                         if let Some(ref tx) = self.client_ack_tx
                                             && let Err(e) = tx.try_send(diff_id)
@@ -1054,6 +1164,7 @@ impl UdpReader {
                                             warn!("Failed to forward ClientAck: {e}");
                                         }
                                     }
+                                    EncryptedFrame::CommandExit(_) => self.command_exit_acked(),
                                 }
                             }
                             // A new frame may have opened gaps — rearm the NAK deadline so
@@ -1274,7 +1385,21 @@ impl UdpReader {
                             EncryptedFrame::Nak(_)
                             | EncryptedFrame::RepaintRequest
                             | EncryptedFrame::StateSyncDiff(_)
-                            | EncryptedFrame::ClientAck(_) => {}
+                            | EncryptedFrame::ClientAck(_)
+                            | EncryptedFrame::Rekey(_)
+                            | EncryptedFrame::ForwardOpen(_)
+                            | EncryptedFrame::ForwardData(_)
+                            | EncryptedFrame::ForwardAck(_)
+                            | EncryptedFrame::ForwardClose(_)
+                            | EncryptedFrame::ForwardReset(_)
+                            | EncryptedFrame::ForwardListen(_)
+                            | EncryptedFrame::ForwardListening(_)
+                            | EncryptedFrame::ForwardListenRefused(_)
+                            | EncryptedFrame::ForwardOpenUnix(_)
+                            | EncryptedFrame::ForwardListenUnix(_)
+                            | EncryptedFrame::ForwardOpenAgent(_)
+                            | EncryptedFrame::ForwardOpenStdio(_)
+                            | EncryptedFrame::ForwardConnect(_) => {}
                             EncryptedFrame::Shutdown => {
                                 info!("Server is shutting down, reconnecting");
                                 self.signal_reconnect_or_exit(0);
@@ -1340,6 +1465,20 @@ impl UdpReader {
                                 exit_token.cancel();
                                 break 'session;
                             }
+                            EncryptedFrame::PtyExitStatus(status) => {
+                                self.shell_exited(status);
+                                *exit_msg
+                                    .lock()
+                                    .unwrap_or_else(PoisonError::into_inner) =
+                                    Some(b"[moshpit] Remote session ended.\r\n");
+                                exit_token.cancel();
+                                break 'session;
+                            }
+                            EncryptedFrame::CommandExit(status) => {
+                                self.command_exited(status);
+                                exit_token.cancel();
+                                break 'session;
+                            }
                             EncryptedFrame::StateChunk((seq, total, data)) => {
                                 self.handle_state_chunk(seq, total, data, &ctx)
                                     .await;
@@ -1411,7 +1550,21 @@ impl UdpReader {
                                     }
                                     EncryptedFrame::Nak(_)
                                     | EncryptedFrame::RepaintRequest
-                                    | EncryptedFrame::ClientAck(_) => {}
+                                    | EncryptedFrame::ClientAck(_)
+                                    | EncryptedFrame::Rekey(_)
+                                    | EncryptedFrame::ForwardOpen(_)
+                                    | EncryptedFrame::ForwardData(_)
+                                    | EncryptedFrame::ForwardAck(_)
+                                    | EncryptedFrame::ForwardClose(_)
+                                    | EncryptedFrame::ForwardReset(_)
+                                    | EncryptedFrame::ForwardListen(_)
+                                    | EncryptedFrame::ForwardListening(_)
+                                    | EncryptedFrame::ForwardListenRefused(_)
+                                    | EncryptedFrame::ForwardOpenUnix(_)
+                                    | EncryptedFrame::ForwardListenUnix(_)
+                                    | EncryptedFrame::ForwardOpenAgent(_)
+                                    | EncryptedFrame::ForwardOpenStdio(_)
+                                    | EncryptedFrame::ForwardConnect(_) => {}
                                     EncryptedFrame::Shutdown => {
                                         info!("Server is shutting down, reconnecting");
                                         self.signal_reconnect_or_exit(0);
@@ -1583,6 +1736,20 @@ impl UdpReader {
                                         exit_token.cancel();
                                         break 'session;
                                     }
+                                    EncryptedFrame::PtyExitStatus(status) => {
+                                        self.shell_exited(status);
+                                        *exit_msg
+                                            .lock()
+                                            .unwrap_or_else(PoisonError::into_inner) =
+                                            Some(b"[moshpit] Remote session ended.\r\n");
+                                        exit_token.cancel();
+                                        break 'session;
+                                    }
+                                    EncryptedFrame::CommandExit(status) => {
+                                        self.command_exited(status);
+                                        exit_token.cancel();
+                                        break 'session;
+                                    }
                                     EncryptedFrame::StateChunk((seq, total, data)) => {
                                         self.handle_state_chunk(seq, total, data, &ctx)
                                             .await;
@@ -1861,7 +2028,7 @@ impl UdpReader {
     /// returns the decoded frame, its sequence number, and the sender's address.
     /// Used by `server_frame_loop` so that the source address of every packet is
     /// visible for NAT roam detection without connecting the socket.
-    async fn recv_frame_from(&self) -> Result<Option<(EncryptedFrame, u64, SocketAddr)>> {
+    async fn recv_frame_from(&mut self) -> Result<Option<(EncryptedFrame, u64, SocketAddr)>> {
         let mut buf = vec![0u8; 65535];
         loop {
             let (len, src) = self.socket.recv_from(&mut buf).await?;
@@ -1881,22 +2048,142 @@ impl UdpReader {
 
     /// Tries to parse a frame from the buffer. Returns the frame and its sequence number on
     /// success, `Ok(None)` when the buffer has insufficient data, or `Err` on a bad frame.
+    ///
+    /// When rekeying was negotiated, the packet is opened under whichever key epoch it
+    /// authenticates with: the current one, the previous one during its grace period, or
+    /// the next one (which moves this reader forward).  A [`EncryptedFrame::Rekey`]
+    /// announcement also moves the reader forward.
     fn parse_encrypted_frame(
-        &self,
+        &mut self,
         buffer: &mut BytesMut,
     ) -> Result<Option<(EncryptedFrame, u64)>> {
         let mut buf = Cursor::new(&buffer[..]);
         buf.set_position(0);
 
-        match EncryptedFrame::parse(&mut buf, self.id, &self.hmac, &self.rnk, self.mac_tag_len) {
+        let (id, mac_tag_len, scheme) = (self.id, self.mac_tag_len, self.nonce_scheme);
+        let parsed = if self.ratchet.is_none()
+            || EncryptedFrame::authenticates(
+                &buffer[..],
+                self.hmac.as_ref(),
+                &self.rnk,
+                mac_tag_len,
+                scheme,
+            ) {
+            EncryptedFrame::parse(
+                &mut buf,
+                id,
+                self.hmac.as_ref(),
+                &self.rnk,
+                mac_tag_len,
+                scheme,
+            )
+        } else if let Some((rnk, hmac)) = self.previous_epoch_keys()
+            && EncryptedFrame::authenticates(&buffer[..], hmac, rnk, mac_tag_len, scheme)
+        {
+            EncryptedFrame::parse(&mut buf, id, hmac, rnk, mac_tag_len, scheme)
+        } else {
+            if self.next_epoch_authenticates(&buffer[..])? {
+                // The peer rekeyed and this packet overtook (or replaced a lost)
+                // Rekey announcement.
+                self.advance_epoch()?;
+            }
+            EncryptedFrame::parse(
+                &mut buf,
+                id,
+                self.hmac.as_ref(),
+                &self.rnk,
+                mac_tag_len,
+                scheme,
+            )
+        };
+
+        match parsed {
             Ok(Some((frame, seq))) => {
                 buffer.clear();
+                if let EncryptedFrame::Rekey(epoch) = frame {
+                    self.follow_rekey(epoch)?;
+                }
                 Ok(Some((frame, seq)))
             }
             Ok(None) => Ok(None),
             Err(err) => Err(err),
         }
     }
+
+    /// Keys for the previous epoch while its grace period lasts.
+    fn previous_epoch_keys(&mut self) -> Option<(&LessSafeKey, Option<&Key>)> {
+        if self
+            .previous_keys
+            .as_ref()
+            .is_some_and(|(_, _, expires)| Instant::now() >= *expires)
+        {
+            self.previous_keys = None;
+        }
+        self.previous_keys
+            .as_ref()
+            .map(|(rnk, hmac, _)| (rnk, hmac.as_ref()))
+    }
+
+    /// Whether `packet` authenticates under the next epoch's keys, deriving and
+    /// caching them on first use.
+    fn next_epoch_authenticates(&mut self, packet: &[u8]) -> Result<bool> {
+        let Some(ratchet) = self.ratchet.as_ref() else {
+            return Ok(false);
+        };
+        if self.next_keys.is_none() {
+            self.next_keys = Some(ratchet.next()?.keys()?);
+        }
+        Ok(self.next_keys.as_ref().is_some_and(|(rnk, hmac)| {
+            EncryptedFrame::authenticates(
+                packet,
+                hmac.as_ref(),
+                rnk,
+                self.mac_tag_len,
+                self.nonce_scheme,
+            )
+        }))
+    }
+
+    /// Handle the peer's [`EncryptedFrame::Rekey`] announcement for `epoch`.
+    ///
+    /// Announcements for the current or an older epoch (retransmits, or a packet
+    /// under the new keys having already moved this reader forward) are ignored.
+    fn follow_rekey(&mut self, epoch: u32) -> Result<()> {
+        let Some(current) = self.ratchet.as_ref().map(KeyRatchet::epoch) else {
+            warn!("Ignoring Rekey frame: in-session rekeying was not negotiated");
+            return Ok(());
+        };
+        if epoch == current.saturating_add(1) {
+            self.advance_epoch()
+        } else {
+            if epoch > current {
+                warn!("Ignoring Rekey frame for epoch {epoch}: current epoch is {current}");
+            }
+            Ok(())
+        }
+    }
+
+    /// Move to the next key epoch, keeping the outgoing keys for
+    /// [`REKEY_GRACE_PERIOD`].
+    fn advance_epoch(&mut self) -> Result<()> {
+        let Some(ratchet) = self.ratchet.as_mut() else {
+            return Ok(());
+        };
+        let next = ratchet.next()?;
+        let (rnk, hmac) = match self.next_keys.take() {
+            Some(keys) => keys,
+            None => next.keys()?,
+        };
+        *ratchet = next;
+        let old_rnk = replace(&mut self.rnk, rnk);
+        let old_hmac = replace(&mut self.hmac, hmac);
+        self.previous_keys = Some((old_rnk, old_hmac, Instant::now() + REKEY_GRACE_PERIOD));
+        debug!(
+            epoch = ratchet.epoch(),
+            "UDP reader moved to a new key epoch"
+        );
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -1936,7 +2223,7 @@ mod tests {
         process_bytes_with_prediction,
     };
     use crate::udp::sender::RETRANSMIT_WINDOW;
-    use crate::{Emulator, PredictionEngine, Renderer, TerminalMessage};
+    use crate::{Emulator, NonceScheme, PredictionEngine, Renderer, TerminalMessage};
 
     #[tokio::test]
     async fn test_handle_arrival_seq_jump() -> Result<()> {
@@ -2919,6 +3206,32 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn route_or_deliver_hands_forward_frames_to_forward_tx() -> Result<()> {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+        let (forward_tx, mut forward_rx) = channel(4);
+        let mut reader = UdpReader::builder()
+            .socket(socket)
+            .id(Uuid::new_v4())
+            .rnk(LessSafeKey::new(
+                UnboundKey::new(&AES_256_GCM_SIV, &[0u8; 32])
+                    .expect("test AES-256-GCM-SIV key setup"),
+            ))
+            .hmac(Key::new(HMAC_SHA512, &[0u8; 64]))
+            .forward_tx(forward_tx)
+            .build();
+
+        let data = EncryptedFrame::ForwardData((1, 0, b"abc".to_vec()));
+        assert!(reader.handle_arrival(data.clone(), 0).is_empty());
+        assert_eq!(forward_rx.try_recv()?, data);
+        assert_eq!(
+            reader.handle_arrival(EncryptedFrame::Keepalive(0), 1),
+            vec![EncryptedFrame::Keepalive(0)]
+        );
+        assert!(forward_rx.try_recv().is_err());
+        Ok(())
+    }
+
     #[test]
     fn nak_check_interval_clamped_to_min() {
         let mut reader = make_reader_sync();
@@ -3918,4 +4231,128 @@ mod tests {
         handle.await.expect("client_frame_loop task");
         Ok(())
     }
+
+    fn seed_ratchet() -> crate::KeyRatchet {
+        crate::KeyRatchet::new(
+            vec![0u8; 32],
+            vec![0u8; 64],
+            crate::kex::negotiate::NegotiatedAlgorithms::default(),
+        )
+    }
+
+    /// Seal `frame` exactly as `UdpSender::encrypt` does for a protocol version 5 peer.
+    fn seal_packet(
+        frame: &EncryptedFrame,
+        seq: u64,
+        id: Uuid,
+        ratchet: &crate::KeyRatchet,
+    ) -> Result<BytesMut> {
+        let (rnk, hmac) = ratchet.keys()?;
+        let packet = frame.seal(seq, id, &rnk, hmac.as_ref(), NonceScheme::Sequence)?;
+        Ok(BytesMut::from(packet.as_slice()))
+    }
+
+    async fn make_rekeying_reader(id: Uuid) -> Result<UdpReader> {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+        let (rnk, hmac) = seed_ratchet().keys()?;
+        Ok(UdpReader::builder()
+            .socket(socket)
+            .id(id)
+            .rnk(rnk)
+            .maybe_hmac(hmac)
+            .ratchet(seed_ratchet())
+            .nonce_scheme(NonceScheme::Sequence)
+            .build())
+    }
+
+    fn reader_epoch(reader: &UdpReader) -> Option<u32> {
+        reader.ratchet.as_ref().map(crate::KeyRatchet::epoch)
+    }
+
+    #[tokio::test]
+    async fn rekey_announcement_moves_reader_and_keeps_previous_epoch() -> Result<()> {
+        let id = Uuid::new_v4();
+        let mut reader = make_rekeying_reader(id).await?;
+        let epoch0 = seed_ratchet();
+        let epoch1 = epoch0.next()?;
+
+        let mut rekey = seal_packet(&EncryptedFrame::Rekey(1), 0, id, &epoch0)?;
+        assert_eq!(
+            reader.parse_encrypted_frame(&mut rekey)?,
+            Some((EncryptedFrame::Rekey(1), 0))
+        );
+        assert_eq!(reader_epoch(&reader), Some(1));
+
+        let mut fresh = seal_packet(&EncryptedFrame::Keepalive(2), 2, id, &epoch1)?;
+        assert_eq!(
+            reader.parse_encrypted_frame(&mut fresh)?,
+            Some((EncryptedFrame::Keepalive(2), 2))
+        );
+
+        // A packet sealed before the switch that arrives late is still accepted.
+        let mut late = seal_packet(&EncryptedFrame::Keepalive(1), 1, id, &epoch0)?;
+        assert_eq!(
+            reader.parse_encrypted_frame(&mut late)?,
+            Some((EncryptedFrame::Keepalive(1), 1))
+        );
+
+        // A retransmitted announcement does not ratchet again.
+        let mut again = seal_packet(&EncryptedFrame::Rekey(1), 0, id, &epoch0)?;
+        drop(reader.parse_encrypted_frame(&mut again)?);
+        assert_eq!(reader_epoch(&reader), Some(1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rekey_lost_announcement_follows_first_new_epoch_packet() -> Result<()> {
+        let id = Uuid::new_v4();
+        let mut reader = make_rekeying_reader(id).await?;
+        let epoch1 = seed_ratchet().next()?;
+
+        let mut fresh = seal_packet(&EncryptedFrame::Keepalive(7), 5, id, &epoch1)?;
+        assert_eq!(
+            reader.parse_encrypted_frame(&mut fresh)?,
+            Some((EncryptedFrame::Keepalive(7), 5))
+        );
+        assert_eq!(reader_epoch(&reader), Some(1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rekey_previous_epoch_rejected_after_grace() -> Result<()> {
+        let id = Uuid::new_v4();
+        let mut reader = make_rekeying_reader(id).await?;
+        let epoch0 = seed_ratchet();
+
+        let mut rekey = seal_packet(&EncryptedFrame::Rekey(1), 0, id, &epoch0)?;
+        drop(reader.parse_encrypted_frame(&mut rekey)?);
+        if let Some((_, _, expires)) = reader.previous_keys.as_mut() {
+            *expires = Instant::now();
+        }
+
+        let mut late = seal_packet(&EncryptedFrame::Keepalive(1), 1, id, &epoch0)?;
+        assert!(reader.parse_encrypted_frame(&mut late).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rekey_ignored_without_negotiated_ratchet() -> Result<()> {
+        let id = Uuid::new_v4();
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+        let (rnk, hmac) = seed_ratchet().keys()?;
+        let mut reader = UdpReader::builder()
+            .socket(socket)
+            .id(id)
+            .rnk(rnk)
+            .maybe_hmac(hmac)
+            .nonce_scheme(NonceScheme::Sequence)
+            .build();
+        let epoch0 = seed_ratchet();
+
+        let mut rekey = seal_packet(&EncryptedFrame::Rekey(1), 0, id, &epoch0)?;
+        drop(reader.parse_encrypted_frame(&mut rekey)?);
+        let mut fresh = seal_packet(&EncryptedFrame::Keepalive(2), 1, id, &epoch0.next()?)?;
+        assert!(reader.parse_encrypted_frame(&mut fresh).is_err());
+        Ok(())
+    }
 }
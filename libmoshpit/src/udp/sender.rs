@@ -14,12 +14,7 @@ use std::{
 };
 
 use anyhow::Result;
-use aws_lc_rs::{
-    aead::{Aad, LessSafeKey, NONCE_LEN, Nonce},
-    hmac::{Key, sign},
-    rand,
-};
-use bincode_next::{config::standard, encode_to_vec};
+use aws_lc_rs::{aead::LessSafeKey, hmac::Key};
 use bon::Builder;
 use getset::MutGetters;
 use tokio::{
@@ -29,10 +24,11 @@ use tokio::{
     time::{Instant as TokioInstant, sleep, sleep_until},
 };
 use tokio_util::sync::CancellationToken;
+use tracing::debug;
 use uuid::Uuid;
 
 use super::DiffMode;
-use crate::EncryptedFrame;
+use crate::{EncryptedFrame, KeyRatchet, NonceScheme, RekeyPolicy, kex::rekey::SendRatchet};
 
 /// Current time as microseconds since the UNIX epoch.
 /// Keepalive frames are re-stamped with this value at actual send time so that
@@ -66,8 +62,8 @@ pub struct UdpSender {
     id: Uuid,
     /// Key for encrypting/decrypting UDP packets
     rnk: LessSafeKey,
-    /// Key for signing UDP packet HMAC
-    hmac: Key,
+    /// Key for signing UDP packet HMAC; `None` under `aead-implicit`
+    hmac: Option<Key>,
     /// Underlying UDP socket
     socket: Arc<UdpSocket>,
     /// Channel receiver for high-priority control frames (Keepalive, Shutdown).
@@ -105,6 +101,16 @@ pub struct UdpSender {
     /// are silently drained.
     #[builder(default)]
     diff_mode: DiffMode,
+    /// Key ratchet for this send direction, seeded from the session keys.
+    /// `None` (peers older than protocol version 4) keeps the handshake keys for
+    /// the whole session.
+    ratchet: Option<KeyRatchet>,
+    /// When to move to the next key epoch; only consulted when `ratchet` is set.
+    #[builder(default)]
+    rekey_policy: RekeyPolicy,
+    /// How each packet's AEAD nonce is chosen; must match the peer's reader.
+    #[builder(default)]
+    nonce_scheme: NonceScheme,
 }
 
 impl UdpSender {
@@ -136,6 +142,10 @@ impl UdpSender {
         // wakeup from the old unconditional interval when no retransmits are pending.
         let retransmit_park = Duration::from_hours(24);
         let mut retransmit_deadline = TokioInstant::now() + retransmit_park;
+        let mut rekey = self
+            .ratchet
+            .take()
+            .map(|ratchet| SendRatchet::new(ratchet, self.rekey_policy));
         loop {
             select! {
                 // biased poll order: cancel > control > retransmit > data.
@@ -158,6 +168,8 @@ impl UdpSender {
                             let wire = self.encrypt(&frame, seq)?;
                             self.drain_roam_updates(&mut current_peer);
                             self.send_wire(&wire, current_peer).await?;
+                            self.rekey_if_due(rekey.as_mut(), wire.len(), current_peer)
+                                .await?;
                         }
                         None => control_active = false,
                     }
@@ -202,14 +214,11 @@ impl UdpSender {
                             let seq = self.send_seq;
                             self.send_seq += 1;
                             let wire = self.encrypt(&frame, seq)?;
-                            if self.diff_mode == DiffMode::Reliable {
-                                let _prev = self.retransmit_buffer.insert(seq, wire.clone());
-                                // Evict packets that fell outside the retransmit window
-                                let cutoff = seq.saturating_sub(RETRANSMIT_WINDOW);
-                                self.retransmit_buffer.retain(|&s, _| s >= cutoff);
-                            }
+                            self.buffer_for_retransmit(seq, &wire);
                             self.drain_roam_updates(&mut current_peer);
                             self.send_wire(&wire, current_peer).await?;
+                            self.rekey_if_due(rekey.as_mut(), wire.len(), current_peer)
+                                .await?;
                         }
                         None => break,
                     }
@@ -219,6 +228,48 @@ impl UdpSender {
         Ok(())
     }
 
+    /// Keep `wire` for selective retransmission (`Reliable` mode only), evicting
+    /// packets that fell outside the retransmit window.
+    fn buffer_for_retransmit(&mut self, seq: u64, wire: &[u8]) {
+        if self.diff_mode == DiffMode::Reliable {
+            let _prev = self.retransmit_buffer.insert(seq, wire.to_vec());
+            let cutoff = seq.saturating_sub(RETRANSMIT_WINDOW);
+            self.retransmit_buffer.retain(|&s, _| s >= cutoff);
+        }
+    }
+
+    /// Account for a `wire_len`-byte packet sealed under the current key epoch and,
+    /// once the rekey policy is exhausted, announce and switch to the next epoch.
+    ///
+    /// The [`EncryptedFrame::Rekey`] frame is sealed under the outgoing keys and
+    /// buffered for retransmission like a data frame, so a lost announcement is
+    /// recovered by the normal NAK path; the peer also follows on the first packet
+    /// that only authenticates under the new keys.
+    async fn rekey_if_due(
+        &mut self,
+        rekey: Option<&mut SendRatchet>,
+        wire_len: usize,
+        peer: Option<SocketAddr>,
+    ) -> Result<()> {
+        let Some(rekey) = rekey else {
+            return Ok(());
+        };
+        rekey.record(wire_len);
+        if !rekey.due() {
+            return Ok(());
+        }
+        let (epoch, rnk, hmac) = rekey.advance()?;
+        let seq = self.send_seq;
+        self.send_seq += 1;
+        let wire = self.encrypt(&EncryptedFrame::Rekey(epoch), seq)?;
+        self.buffer_for_retransmit(seq, &wire);
+        self.send_wire(&wire, peer).await?;
+        self.rnk = rnk;
+        self.hmac = hmac;
+        debug!(epoch, "UDP sender moved to a new key epoch");
+        Ok(())
+    }
+
     /// Drain pending NAT roam notifications from `peer_addr_rx`, updating `peer`
     /// to the latest address.  Called immediately before each outgoing send so that
     /// address changes take effect on the very next packet.
@@ -242,31 +293,13 @@ impl UdpSender {
     }
 
     fn encrypt(&self, frame: &EncryptedFrame, seq: u64) -> Result<Vec<u8>> {
-        // Encode the frame data
-        let data = encode_to_vec(frame, standard())?;
-        let aad = Aad::from(seq.to_be_bytes());
-        // Encrypt the id, frame_id, and the data then MAC
-        let mut encrypted_part = self.id.as_bytes().to_vec();
-        encrypted_part.extend_from_slice(&data);
-        let mut nonce_bytes = [0u8; NONCE_LEN];
-        rand::fill(&mut nonce_bytes)?;
-        let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes)?;
-        self.rnk
-            .seal_in_place_append_tag(nonce, aad, &mut encrypted_part)?;
-        // Sign seq_bytes || encrypted_part to authenticate the wire sequence number
-        let seq_bytes = seq.to_be_bytes();
-        let mut to_sign = seq_bytes.to_vec();
-        to_sign.extend_from_slice(&encrypted_part);
-        let tag = sign(&self.hmac, &to_sign);
-        let tag_bytes = tag.as_ref();
-        let len = encrypted_part.len().to_be_bytes();
-        // Wire format: [nonce (12)] [seq (8)] [hmac_tag (64)] [length (8)] [encrypted_part]
-        let mut packet = nonce_bytes.to_vec();
-        packet.extend_from_slice(&seq_bytes);
-        packet.extend_from_slice(tag_bytes);
-        packet.extend_from_slice(&len);
-        packet.extend_from_slice(&encrypted_part);
-        Ok(packet)
+        frame.seal(
+            seq,
+            self.id,
+            &self.rnk,
+            self.hmac.as_ref(),
+            self.nonce_scheme,
+        )
     }
 }
 
@@ -278,7 +311,7 @@ mod tests {
     };
     use tokio::sync::mpsc::channel;
 
-    use std::{net::SocketAddr, sync::Arc, time::Duration};
+    use std::{io::Cursor, net::SocketAddr, sync::Arc, time::Duration};
 
     use anyhow::Result;
     use aws_lc_rs::aead::LessSafeKey;
@@ -291,6 +324,9 @@ mod tests {
     use uuid::Uuid;
 
     use super::{EncryptedFrame, UdpSender, now_micros};
+    use crate::{
+        KeyRatchet, NonceScheme, RekeyPolicy, UuidWrapper, kex::negotiate::NegotiatedAlgorithms,
+    };
 
     fn make_sender(
         socket: Arc<UdpSocket>,
@@ -544,4 +580,76 @@ mod tests {
         );
         Ok(())
     }
+
+    fn seed_ratchet() -> KeyRatchet {
+        KeyRatchet::new(
+            vec![0u8; 32],
+            vec![0u8; 64],
+            NegotiatedAlgorithms::default(),
+        )
+    }
+
+    /// With a one-packet rekey policy every frame is followed by a `Rekey`
+    /// announcement sealed under the outgoing keys, and the next frame is sealed
+    /// under the ratcheted keys.
+    #[tokio::test]
+    async fn rekey_policy_announces_and_switches_epoch() -> Result<()> {
+        let server = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+        let send_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+        send_socket.connect(server.local_addr()?).await?;
+
+        let (_ctrl_tx, ctrl_rx) = channel::<EncryptedFrame>(4);
+        let (frame_tx, frame_rx) = channel::<EncryptedFrame>(4);
+        let (_retransmit_tx, retransmit_rx) = channel::<Vec<u64>>(4);
+        let token = CancellationToken::new();
+        let id = Uuid::new_v4();
+        let (rnk, hmac) = seed_ratchet().keys()?;
+        let mut sender = UdpSender::builder()
+            .id(id)
+            .rnk(rnk)
+            .maybe_hmac(hmac)
+            .socket(send_socket)
+            .control_rx(ctrl_rx)
+            .rx(frame_rx)
+            .retransmit_rx(retransmit_rx)
+            .ratchet(seed_ratchet())
+            .rekey_policy(RekeyPolicy::builder().max_packets(1).build())
+            .nonce_scheme(NonceScheme::Sequence)
+            .build();
+        let token2 = token.clone();
+        let handle = spawn(async move { drop(sender.frame_loop(token2).await) });
+
+        let first = EncryptedFrame::Bytes((UuidWrapper::new(id), b"a".to_vec()));
+        let second = EncryptedFrame::Bytes((UuidWrapper::new(id), b"b".to_vec()));
+        frame_tx.send(first.clone()).await?;
+        frame_tx.send(second.clone()).await?;
+
+        let epoch0 = seed_ratchet();
+        let epoch1 = epoch0.next()?;
+        let expected = [
+            (&epoch0, first, 0),
+            (&epoch0, EncryptedFrame::Rekey(1), 1),
+            (&epoch1, second, 2),
+            (&epoch1, EncryptedFrame::Rekey(2), 3),
+        ];
+        let mut buf = vec![0u8; 65535];
+        for (ratchet, frame, seq) in expected {
+            let n = timeout(Duration::from_millis(500), server.recv(&mut buf)).await??;
+            let (rnk, hmac) = ratchet.keys()?;
+            let mut cursor = Cursor::new(&buf[..n]);
+            let parsed = EncryptedFrame::parse(
+                &mut cursor,
+                id,
+                hmac.as_ref(),
+                &rnk,
+                64,
+                NonceScheme::Sequence,
+            )?;
+            assert_eq!(parsed, Some((frame, seq)));
+        }
+
+        token.cancel();
+        drop(handle.await);
+        Ok(())
+    }
 }
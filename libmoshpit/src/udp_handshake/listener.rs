@@ -0,0 +1,223 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Server side of the UDP handshake transport.
+
+#[cfg(unix)]
+use std::os::fd::{AsFd, BorrowedFd};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket as StdUdpSocket},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use tokio::{
+    net::UdpSocket,
+    spawn,
+    sync::mpsc::{Receiver, Sender, channel},
+    task::JoinHandle,
+};
+use tracing::{debug, trace};
+
+use crate::{
+    ConnectionReader, ConnectionWriter, MoshpitError,
+    udp_handshake::{
+        CONNECTION_QUEUE,
+        packet::{CookieJar, HELLO_MIN_LEN, MAX_DATAGRAM_LEN, Packet},
+        stream::spawn_carrier,
+    },
+};
+
+/// Connections tracked at once by one listener.  `Hello`s for new connections
+/// beyond this are dropped until earlier ones finish.
+const MAX_CONNECTIONS: usize = 256;
+
+/// Accepted connections waiting for [`UdpHandshakeListener::accept`].
+const ACCEPT_BACKLOG: usize = 16;
+
+/// An accepted UDP handshake connection: the frame reader and writer, and the
+/// client's address.
+type Accepted = (ConnectionReader, ConnectionWriter, SocketAddr);
+
+/// A UDP socket that accepts key exchanges from `mp --transport udp-only`,
+/// the datagram counterpart of a `TcpListener`.
+///
+/// A client must echo a stateless cookie before the listener keeps any state
+/// for it, and a `Hello` shorter than a full datagram is ignored, so the
+/// listener cannot be used to reflect or amplify traffic at a spoofed address.
+#[derive(Debug)]
+pub struct UdpHandshakeListener {
+    socket: Arc<UdpSocket>,
+    local_addr: SocketAddr,
+    accepted: Receiver<Accepted>,
+    demux: JoinHandle<()>,
+}
+
+impl UdpHandshakeListener {
+    /// Bind a listener to `addr`.
+    ///
+    /// # Errors
+    /// * The socket cannot be bound.
+    /// * The system random number generator fails.
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        Self::listen(UdpSocket::bind(addr).await?)
+    }
+
+    /// Listen on `socket`, already bound, such as one handed over by another
+    /// process.
+    ///
+    /// # Errors
+    /// * The socket cannot be registered with the runtime.
+    /// * The system random number generator fails.
+    pub fn from_std(socket: StdUdpSocket) -> Result<Self> {
+        socket.set_nonblocking(true)?;
+        Self::listen(UdpSocket::from_std(socket)?)
+    }
+
+    fn listen(socket: UdpSocket) -> Result<Self> {
+        let socket = Arc::new(socket);
+        let local_addr = socket.local_addr()?;
+        let cookies = CookieJar::new()?;
+        let (tx_accepted, accepted) = channel(ACCEPT_BACKLOG);
+        let demux_socket = socket.clone();
+        let demux =
+            spawn(async move { route_datagrams(&demux_socket, &cookies, &tx_accepted).await });
+        Ok(Self {
+            socket,
+            local_addr,
+            accepted,
+            demux,
+        })
+    }
+
+    /// Wait for the next client to complete the cookie exchange.
+    ///
+    /// Returns the key-exchange frame reader and writer, and the client's address.
+    ///
+    /// # Errors
+    /// * The listener task has stopped.
+    pub async fn accept(&mut self) -> Result<(ConnectionReader, ConnectionWriter, SocketAddr)> {
+        self.accepted
+            .recv()
+            .await
+            .ok_or_else(|| MoshpitError::ConnectionResetByPeer.into())
+    }
+
+    /// The address the listener is bound to.
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The local address `peer` reaches this listener on.
+    ///
+    /// When the listener is bound to a wildcard address this asks the routing
+    /// table which interface address replies to `peer` leave from, just as
+    /// `TcpStream::local_addr` reports for an accepted TCP connection.
+    ///
+    /// # Errors
+    /// * The routing lookup fails.
+    pub fn local_addr_for(&self, peer: SocketAddr) -> Result<SocketAddr> {
+        if !self.local_addr.ip().is_unspecified() {
+            return Ok(self.local_addr);
+        }
+        let unspecified = match peer {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let probe = StdUdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+        probe.connect(peer)?;
+        Ok(SocketAddr::new(
+            probe.local_addr()?.ip(),
+            self.local_addr.port(),
+        ))
+    }
+}
+
+#[cfg(unix)]
+impl AsFd for UdpHandshakeListener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.socket.as_fd()
+    }
+}
+
+impl Drop for UdpHandshakeListener {
+    fn drop(&mut self) {
+        self.demux.abort();
+    }
+}
+
+/// Route incoming datagrams: answer `Hello`s, start a carrier for each newly
+/// verified connection, and hand every other datagram to its connection.
+async fn route_datagrams(
+    socket: &Arc<UdpSocket>,
+    cookies: &CookieJar,
+    tx_accepted: &Sender<Accepted>,
+) {
+    let mut connections: HashMap<(SocketAddr, u64), Sender<Packet>> = HashMap::new();
+    let mut buf = vec![0u8; MAX_DATAGRAM_LEN];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            // Windows reports ICMP port-unreachable for an earlier send here.
+            Err(e) => {
+                trace!("UDP handshake receive failed: {e}");
+                continue;
+            }
+        };
+        let Some(packet) = Packet::decode(&buf[..len]) else {
+            continue;
+        };
+        let conn_id = packet.conn_id();
+        match packet {
+            Packet::Hello { .. } if len < HELLO_MIN_LEN => {
+                trace!("ignoring short UDP handshake hello from {peer}");
+            }
+            Packet::Hello { cookie: None, .. } => {
+                let reply = Packet::Cookie {
+                    conn_id,
+                    cookie: cookies.cookie(peer, conn_id),
+                };
+                let _sent = socket.send_to(&reply.encode(), peer).await;
+            }
+            Packet::Hello {
+                cookie: Some(cookie),
+                ..
+            } => {
+                if !cookies.verify(peer, conn_id, &cookie) {
+                    trace!("ignoring UDP handshake hello with a bad cookie from {peer}");
+                    continue;
+                }
+                let key = (peer, conn_id);
+                if connections.get(&key).is_none_or(Sender::is_closed) {
+                    connections.retain(|_, tx| !tx.is_closed());
+                    if connections.len() >= MAX_CONNECTIONS {
+                        debug!("too many UDP handshake connections, dropping hello from {peer}");
+                        continue;
+                    }
+                    let (tx, rx) = channel(CONNECTION_QUEUE);
+                    let (reader, writer) = spawn_carrier(socket.clone(), peer, conn_id, rx);
+                    if tx_accepted.try_send((reader, writer, peer)).is_err() {
+                        debug!("UDP handshake accept backlog full, dropping {peer}");
+                        continue;
+                    }
+                    let _old = connections.insert(key, tx);
+                }
+                // Also answers a retransmitted hello whose first ack was lost.
+                let ack = Packet::Ack { conn_id, next: 0 };
+                let _sent = socket.send_to(&ack.encode(), peer).await;
+            }
+            packet => {
+                if let Some(tx) = connections.get(&(peer, conn_id)) {
+                    let _queued = tx.try_send(packet);
+                }
+            }
+        }
+    }
+}
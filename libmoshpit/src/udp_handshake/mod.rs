@@ -0,0 +1,291 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! UDP handshake transport: the key exchange for networks that block TCP.
+//!
+//! `mp --transport udp-only` runs the same [`Frame`](crate::Frame) sequence it
+//! would send over TCP, but over UDP datagrams to the server's listen port,
+//! where moshpits answers when `udp_handshake` is enabled.
+//!
+//! The client opens with a `Hello` padded to a full datagram; the server
+//! answers with a stateless cookie bound to the client's address, and only
+//! once the client echoes it in a second `Hello` does the server allocate any
+//! state.  The frame stream is then cut into `Data` segments that fit the IPv6
+//! minimum MTU — the ML-KEM key shares span several — each retransmitted with
+//! exponential backoff until cumulatively acknowledged.  The result is handed
+//! to the key exchange as an ordinary [`ConnectionReader`] and
+//! [`ConnectionWriter`]; see [`connect_udp_handshake`] and
+//! [`UdpHandshakeListener`].
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use aws_lc_rs::rand::fill;
+use tokio::{
+    net::UdpSocket,
+    select, spawn,
+    sync::mpsc::{Sender, channel},
+    time::timeout,
+};
+use tracing::trace;
+
+use crate::{
+    ConnectionReader, ConnectionWriter, MoshpitError,
+    udp_handshake::{
+        packet::{MAX_DATAGRAM_LEN, Packet},
+        stream::{INITIAL_RTO, MAX_RETRANSMITS, MAX_RTO, spawn_carrier},
+    },
+};
+
+pub(crate) mod listener;
+pub(crate) mod packet;
+pub(crate) mod stream;
+
+/// Datagrams queued for one connection's carrier.
+const CONNECTION_QUEUE: usize = 64;
+
+/// Open a UDP handshake connection to the moshpits listener at `addr`.
+///
+/// Returns the frame reader and writer to run the key exchange over.
+///
+/// # Errors
+/// * The local socket cannot be bound.
+/// * [`MoshpitError::UdpHandshakeTimedOut`] when the server never completes
+///   the cookie exchange.
+pub async fn connect_udp_handshake(
+    addr: SocketAddr,
+) -> Result<(ConnectionReader, ConnectionWriter)> {
+    let unspecified = match addr {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = Arc::new(UdpSocket::bind(SocketAddr::new(unspecified, 0)).await?);
+    let mut conn_id_bytes = [0u8; 8];
+    fill(&mut conn_id_bytes)?;
+    let conn_id = u64::from_be_bytes(conn_id_bytes);
+    let (tx, rx) = channel(CONNECTION_QUEUE);
+
+    let mut hello = Packet::Hello {
+        conn_id,
+        cookie: None,
+    };
+    let mut rto = INITIAL_RTO;
+    let mut buf = vec![0u8; MAX_DATAGRAM_LEN];
+    let mut attempts = 0;
+    loop {
+        if attempts > MAX_RETRANSMITS {
+            return Err(MoshpitError::UdpHandshakeTimedOut.into());
+        }
+        attempts += 1;
+        let _sent = socket.send_to(&hello.encode(), addr).await?;
+        let Ok(received) = timeout(rto, async {
+            loop {
+                let Ok((len, from)) = socket.recv_from(&mut buf).await else {
+                    continue;
+                };
+                if from != addr {
+                    continue;
+                }
+                if let Some(packet) = Packet::decode(&buf[..len])
+                    && packet.conn_id() == conn_id
+                {
+                    return packet;
+                }
+            }
+        })
+        .await
+        else {
+            rto = (rto * 2).min(MAX_RTO);
+            continue;
+        };
+        match (&hello, received) {
+            (Packet::Hello { cookie: None, .. }, Packet::Cookie { cookie, .. }) => {
+                trace!("received UDP handshake cookie from {addr}");
+                hello = Packet::Hello {
+                    conn_id,
+                    cookie: Some(cookie),
+                };
+                attempts = 0;
+                rto = INITIAL_RTO;
+            }
+            (
+                Packet::Hello {
+                    cookie: Some(_), ..
+                },
+                Packet::Ack { .. },
+            ) => break,
+            // The server's first segment can overtake a lost ack.
+            (
+                Packet::Hello {
+                    cookie: Some(_), ..
+                },
+                packet @ (Packet::Data { .. } | Packet::Fin { .. }),
+            ) => {
+                let _queued = tx.try_send(packet);
+                break;
+            }
+            _ => {}
+        }
+    }
+    trace!("UDP handshake connection to {addr} established");
+
+    let forward_socket = socket.clone();
+    let _handle =
+        spawn(async move { forward_datagrams(&forward_socket, addr, conn_id, &tx).await });
+    Ok(spawn_carrier(socket, addr, conn_id, rx))
+}
+
+/// Pass the server's datagrams for this connection to its carrier until the
+/// carrier finishes.
+async fn forward_datagrams(
+    socket: &UdpSocket,
+    peer: SocketAddr,
+    conn_id: u64,
+    tx: &Sender<Packet>,
+) {
+    let mut buf = vec![0u8; MAX_DATAGRAM_LEN];
+    loop {
+        select! {
+            () = tx.closed() => break,
+            received = socket.recv_from(&mut buf) => {
+                let Ok((len, from)) = received else {
+                    continue;
+                };
+                if from != peer {
+                    continue;
+                }
+                if let Some(packet) = Packet::decode(&buf[..len])
+                    && packet.conn_id() == conn_id
+                {
+                    let _queued = tx.try_send(packet);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use anyhow::Result;
+    use tokio::{net::UdpSocket, time::timeout};
+
+    use super::{connect_udp_handshake, listener::UdpHandshakeListener, packet::Packet};
+    use crate::Frame;
+
+    async fn listener() -> Result<UdpHandshakeListener> {
+        UdpHandshakeListener::bind("127.0.0.1:0".parse()?).await
+    }
+
+    #[tokio::test]
+    async fn frames_larger_than_a_datagram_cross_in_both_directions() -> Result<()> {
+        let mut listener = listener().await?;
+        let addr = listener.local_addr();
+        let (client, server) = tokio::join!(connect_udp_handshake(addr), listener.accept());
+        let (mut client_reader, mut client_writer) = client?;
+        let (mut server_reader, mut server_writer, peer) = server?;
+        assert!(peer.ip().is_loopback());
+        assert_eq!(listener.local_addr_for(peer)?, addr);
+
+        let big = Frame::SealedIdentity(
+            [1u8; 12],
+            (0..20_000u32).map(|i| i.to_le_bytes()[0]).collect(),
+        );
+        client_writer.write_frame(&big).await?;
+        assert_eq!(server_reader.read_frame().await?, Some(big.clone()));
+
+        server_writer
+            .write_frame(&Frame::TransportPreference(1))
+            .await?;
+        server_writer.write_frame(&big).await?;
+        assert_eq!(
+            client_reader.read_frame().await?,
+            Some(Frame::TransportPreference(1))
+        );
+        assert_eq!(client_reader.read_frame().await?, Some(big));
+
+        drop(client_reader);
+        drop(client_writer);
+        assert_eq!(server_reader.read_frame().await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn listener_on_a_handed_over_socket_accepts() -> Result<()> {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        let mut listener = UdpHandshakeListener::from_std(socket)?;
+        let addr = listener.local_addr();
+        let (client, server) = tokio::join!(connect_udp_handshake(addr), listener.accept());
+        let _client = client?;
+        let (_reader, _writer, peer) = server?;
+        assert!(peer.ip().is_loopback());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hello_without_cookie_draws_only_a_small_reply() -> Result<()> {
+        let listener = listener().await?;
+        let probe = UdpSocket::bind("127.0.0.1:0").await?;
+        let hello = Packet::Hello {
+            conn_id: 7,
+            cookie: None,
+        }
+        .encode();
+        let _sent = probe.send_to(&hello, listener.local_addr()).await?;
+        let mut buf = [0u8; 2048];
+        let (len, _from) = timeout(Duration::from_secs(5), probe.recv_from(&mut buf)).await??;
+        assert!(matches!(
+            Packet::decode(&buf[..len]),
+            Some(Packet::Cookie { conn_id: 7, .. })
+        ));
+        assert!(len < hello.len() / 10);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn short_hello_is_ignored() -> Result<()> {
+        let listener = listener().await?;
+        let probe = UdpSocket::bind("127.0.0.1:0").await?;
+        let hello = Packet::Hello {
+            conn_id: 7,
+            cookie: None,
+        }
+        .encode();
+        let _sent = probe.send_to(&hello[..64], listener.local_addr()).await?;
+        let mut buf = [0u8; 2048];
+        assert!(
+            timeout(Duration::from_millis(300), probe.recv_from(&mut buf))
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hello_with_a_forged_cookie_is_ignored() -> Result<()> {
+        let listener = listener().await?;
+        let probe = UdpSocket::bind("127.0.0.1:0").await?;
+        let hello = Packet::Hello {
+            conn_id: 7,
+            cookie: Some([0u8; 32]),
+        };
+        let addr: SocketAddr = listener.local_addr();
+        let _sent = probe.send_to(&hello.encode(), addr).await?;
+        let mut buf = [0u8; 2048];
+        assert!(
+            timeout(Duration::from_millis(300), probe.recv_from(&mut buf))
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Datagram codec and stateless cookies for the UDP handshake transport.
+//!
+//! Every datagram is `[magic (4)] [kind (1)] [connection id (8)] [body]`:
+//!
+//! | Kind | Body |
+//! |------|------|
+//! | `Hello` | `[cookie length (1)] [cookie]`, zero-padded to [`HELLO_MIN_LEN`] |
+//! | `Cookie` | `[cookie (32)]` |
+//! | `Data` | `[sequence (4)] [segment]` |
+//! | `Fin` | `[sequence (4)]` |
+//! | `Ack` | `[next expected sequence (4)]` |
+
+use std::{
+    net::SocketAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use aws_lc_rs::{
+    hmac::{HMAC_SHA256, Key, sign, verify},
+    rand::fill,
+};
+
+/// Identifies a UDP handshake datagram.
+const MAGIC: [u8; 4] = *b"MPHS";
+
+/// Length of the magic, kind, and connection id that open every datagram.
+pub(crate) const HEADER_LEN: usize = MAGIC.len() + 1 + 8;
+
+/// Length of a cookie, as carried in a `Hello`.
+const COOKIE_LEN_BYTE: u8 = 32;
+
+/// Length of a cookie.
+pub(crate) const COOKIE_LEN: usize = COOKIE_LEN_BYTE as usize;
+
+/// A `Hello` shorter than this is ignored, so a spoofed source address can
+/// never draw a reply larger than the request.
+pub(crate) const HELLO_MIN_LEN: usize = 1200;
+
+/// Largest stream segment carried by one `Data` datagram.  Together with the
+/// header this keeps every datagram under the IPv6 minimum MTU.
+pub(crate) const MAX_SEGMENT_LEN: usize = 1152;
+
+/// Largest datagram either peer sends.
+pub(crate) const MAX_DATAGRAM_LEN: usize = HELLO_MIN_LEN;
+
+/// How long a cookie's time bucket lasts, in seconds.  A cookie is accepted in
+/// its own bucket and the one after it.
+const COOKIE_EPOCH_SECS: u64 = 60;
+
+const KIND_HELLO: u8 = 0;
+const KIND_COOKIE: u8 = 1;
+const KIND_DATA: u8 = 2;
+const KIND_FIN: u8 = 3;
+const KIND_ACK: u8 = 4;
+
+/// One UDP handshake datagram.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Packet {
+    /// Client request to open a connection, with the server's cookie once it has one.
+    Hello {
+        conn_id: u64,
+        cookie: Option<[u8; COOKIE_LEN]>,
+    },
+    /// Server reply to an un-cookied `Hello`.
+    Cookie {
+        conn_id: u64,
+        cookie: [u8; COOKIE_LEN],
+    },
+    /// A segment of the frame stream.
+    Data {
+        conn_id: u64,
+        seq: u32,
+        payload: Vec<u8>,
+    },
+    /// End of the sender's frame stream; occupies sequence number `seq`.
+    Fin { conn_id: u64, seq: u32 },
+    /// Cumulative acknowledgement of every sequence number below `next`.
+    Ack { conn_id: u64, next: u32 },
+}
+
+impl Packet {
+    /// The connection this datagram belongs to.
+    pub(crate) fn conn_id(&self) -> u64 {
+        match self {
+            Packet::Hello { conn_id, .. }
+            | Packet::Cookie { conn_id, .. }
+            | Packet::Data { conn_id, .. }
+            | Packet::Fin { conn_id, .. }
+            | Packet::Ack { conn_id, .. } => *conn_id,
+        }
+    }
+
+    /// Encode the datagram for the wire.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let (kind, conn_id) = match self {
+            Packet::Hello { conn_id, .. } => (KIND_HELLO, conn_id),
+            Packet::Cookie { conn_id, .. } => (KIND_COOKIE, conn_id),
+            Packet::Data { conn_id, .. } => (KIND_DATA, conn_id),
+            Packet::Fin { conn_id, .. } => (KIND_FIN, conn_id),
+            Packet::Ack { conn_id, .. } => (KIND_ACK, conn_id),
+        };
+        let mut bytes = Vec::with_capacity(MAX_DATAGRAM_LEN);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(kind);
+        bytes.extend_from_slice(&conn_id.to_be_bytes());
+        match self {
+            Packet::Hello { cookie, .. } => {
+                if let Some(cookie) = cookie {
+                    bytes.push(COOKIE_LEN_BYTE);
+                    bytes.extend_from_slice(cookie);
+                } else {
+                    bytes.push(0);
+                }
+                bytes.resize(HELLO_MIN_LEN, 0);
+            }
+            Packet::Cookie { cookie, .. } => bytes.extend_from_slice(cookie),
+            Packet::Data { seq, payload, .. } => {
+                bytes.extend_from_slice(&seq.to_be_bytes());
+                bytes.extend_from_slice(payload);
+            }
+            Packet::Fin { seq, .. } => bytes.extend_from_slice(&seq.to_be_bytes()),
+            Packet::Ack { next, .. } => bytes.extend_from_slice(&next.to_be_bytes()),
+        }
+        bytes
+    }
+
+    /// Decode a datagram, or `None` if it is not a well-formed handshake datagram.
+    pub(crate) fn decode(bytes: &[u8]) -> Option<Self> {
+        let (header, body) = bytes.split_at_checked(HEADER_LEN)?;
+        let (magic, rest) = header.split_at(MAGIC.len());
+        if magic != MAGIC {
+            return None;
+        }
+        let kind = rest[0];
+        let conn_id = u64::from_be_bytes(rest[1..].try_into().ok()?);
+        let be_u32 = |body: &[u8]| -> Option<u32> {
+            Some(u32::from_be_bytes(body.get(..4)?.try_into().ok()?))
+        };
+        match kind {
+            KIND_HELLO => {
+                let (&cookie_len, rest) = body.split_first()?;
+                let cookie = match usize::from(cookie_len) {
+                    0 => None,
+                    COOKIE_LEN => Some(rest.get(..COOKIE_LEN)?.try_into().ok()?),
+                    _ => return None,
+                };
+                Some(Packet::Hello { conn_id, cookie })
+            }
+            KIND_COOKIE => Some(Packet::Cookie {
+                conn_id,
+                cookie: body.try_into().ok()?,
+            }),
+            KIND_DATA => {
+                let seq = be_u32(body)?;
+                let payload = body[4..].to_vec();
+                if payload.len() > MAX_SEGMENT_LEN {
+                    return None;
+                }
+                Some(Packet::Data {
+                    conn_id,
+                    seq,
+                    payload,
+                })
+            }
+            KIND_FIN => Some(Packet::Fin {
+                conn_id,
+                seq: be_u32(body)?,
+            }),
+            KIND_ACK => Some(Packet::Ack {
+                conn_id,
+                next: be_u32(body)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Mints and checks the stateless cookies a server hands out before it
+/// allocates any per-connection state.
+///
+/// A cookie is an HMAC over the client's address, its connection id, and a
+/// coarse time bucket, under a key generated when the listener starts.
+pub(crate) struct CookieJar {
+    key: Key,
+}
+
+impl CookieJar {
+    /// Create a jar with a fresh random key.
+    pub(crate) fn new() -> Result<Self> {
+        let mut key_bytes = [0u8; 32];
+        fill(&mut key_bytes)?;
+        Ok(Self {
+            key: Key::new(HMAC_SHA256, &key_bytes),
+        })
+    }
+
+    /// The cookie for `peer` and `conn_id` in the current time bucket.
+    pub(crate) fn cookie(&self, peer: SocketAddr, conn_id: u64) -> [u8; COOKIE_LEN] {
+        self.cookie_at(peer, conn_id, current_epoch())
+    }
+
+    /// Whether `cookie` was minted for `peer` and `conn_id` in the current or
+    /// previous time bucket.
+    pub(crate) fn verify(&self, peer: SocketAddr, conn_id: u64, cookie: &[u8]) -> bool {
+        self.verify_at(peer, conn_id, cookie, current_epoch())
+    }
+
+    fn cookie_at(&self, peer: SocketAddr, conn_id: u64, epoch: u64) -> [u8; COOKIE_LEN] {
+        let mut cookie = [0u8; COOKIE_LEN];
+        cookie.copy_from_slice(sign(&self.key, &cookie_input(peer, conn_id, epoch)).as_ref());
+        cookie
+    }
+
+    fn verify_at(&self, peer: SocketAddr, conn_id: u64, cookie: &[u8], epoch: u64) -> bool {
+        [epoch, epoch.saturating_sub(1)]
+            .iter()
+            .any(|&epoch| verify(&self.key, &cookie_input(peer, conn_id, epoch), cookie).is_ok())
+    }
+}
+
+fn cookie_input(peer: SocketAddr, conn_id: u64, epoch: u64) -> Vec<u8> {
+    let mut input = match peer {
+        SocketAddr::V4(v4) => v4.ip().octets().to_vec(),
+        SocketAddr::V6(v6) => v6.ip().octets().to_vec(),
+    };
+    input.extend_from_slice(&peer.port().to_be_bytes());
+    input.extend_from_slice(&conn_id.to_be_bytes());
+    input.extend_from_slice(&epoch.to_be_bytes());
+    input
+}
+
+fn current_epoch() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / COOKIE_EPOCH_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use anyhow::Result;
+
+    use super::{CookieJar, HEADER_LEN, HELLO_MIN_LEN, MAX_SEGMENT_LEN, Packet};
+
+    #[test]
+    fn packets_round_trip() {
+        let packets = [
+            Packet::Hello {
+                conn_id: 1,
+                cookie: None,
+            },
+            Packet::Hello {
+                conn_id: 2,
+                cookie: Some([7u8; 32]),
+            },
+            Packet::Cookie {
+                conn_id: 3,
+                cookie: [9u8; 32],
+            },
+            Packet::Data {
+                conn_id: 4,
+                seq: 5,
+                payload: vec![6u8; MAX_SEGMENT_LEN],
+            },
+            Packet::Fin { conn_id: 7, seq: 8 },
+            Packet::Ack {
+                conn_id: 9,
+                next: 10,
+            },
+        ];
+        for packet in packets {
+            assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+        }
+    }
+
+    #[test]
+    fn hello_is_padded_and_cookie_reply_is_small() {
+        let hello = Packet::Hello {
+            conn_id: 1,
+            cookie: None,
+        }
+        .encode();
+        let cookie = Packet::Cookie {
+            conn_id: 1,
+            cookie: [0u8; 32],
+        }
+        .encode();
+        assert_eq!(hello.len(), HELLO_MIN_LEN);
+        assert!(cookie.len() * 10 < hello.len());
+    }
+
+    #[test]
+    fn malformed_datagrams_do_not_decode() {
+        let mut bad_magic = Packet::Ack {
+            conn_id: 1,
+            next: 2,
+        }
+        .encode();
+        bad_magic[0] = b'X';
+        assert_eq!(Packet::decode(&bad_magic), None);
+        assert_eq!(Packet::decode(&[0u8; HEADER_LEN - 1]), None);
+        let mut truncated = Packet::Fin { conn_id: 1, seq: 2 }.encode();
+        let _last = truncated.pop();
+        assert_eq!(Packet::decode(&truncated), None);
+        let mut unknown_kind = Packet::Fin { conn_id: 1, seq: 2 }.encode();
+        unknown_kind[4] = 99;
+        assert_eq!(Packet::decode(&unknown_kind), None);
+    }
+
+    #[test]
+    fn cookie_is_bound_to_peer_connection_and_time() -> Result<()> {
+        let jar = CookieJar::new()?;
+        let peer: SocketAddr = "192.0.2.1:4000".parse()?;
+        let other: SocketAddr = "192.0.2.1:4001".parse()?;
+        let cookie = jar.cookie_at(peer, 42, 100);
+        assert!(jar.verify_at(peer, 42, &cookie, 100));
+        assert!(jar.verify_at(peer, 42, &cookie, 101));
+        assert!(!jar.verify_at(peer, 42, &cookie, 102));
+        assert!(!jar.verify_at(peer, 43, &cookie, 100));
+        assert!(!jar.verify_at(other, 42, &cookie, 100));
+        assert!(!CookieJar::new()?.verify_at(peer, 42, &cookie, 100));
+        assert!(jar.verify(peer, 42, &jar.cookie(peer, 42)));
+        Ok(())
+    }
+}
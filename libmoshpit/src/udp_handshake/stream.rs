@@ -0,0 +1,258 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Reliable, ordered byte stream over handshake datagrams.
+//!
+//! The key exchange reads and writes one end of an in-memory duplex pipe; a
+//! carrier task owns the other end, cuts what the key exchange writes into
+//! `Data` segments, retransmits each until the peer acknowledges it, and
+//! reassembles the peer's segments in order.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _, DuplexStream, WriteHalf, duplex, split},
+    net::UdpSocket,
+    select, spawn,
+    sync::mpsc::Receiver,
+    time::{Instant, sleep_until},
+};
+use tracing::debug;
+
+use crate::{
+    ConnectionReader, ConnectionWriter, MoshpitError,
+    udp_handshake::packet::{MAX_SEGMENT_LEN, Packet},
+};
+
+/// Capacity of the in-memory pipe between the key exchange and the carrier.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Unacknowledged segments in flight, and how far ahead of the next expected
+/// sequence number received segments are buffered.
+const WINDOW: usize = 32;
+
+/// Delay before the first retransmission of a segment.
+pub(crate) const INITIAL_RTO: Duration = Duration::from_millis(250);
+
+/// Ceiling for the doubling retransmission delay.
+pub(crate) const MAX_RTO: Duration = Duration::from_secs(2);
+
+/// Retransmissions of one datagram before the peer is given up on.
+pub(crate) const MAX_RETRANSMITS: u32 = 10;
+
+/// How long a connection may go without hearing from the peer.  Generous,
+/// because the client may be waiting on a passphrase prompt mid-handshake.
+const IDLE_TIMEOUT: Duration = Duration::from_mins(2);
+
+/// How long a finished connection keeps acknowledging the peer's final
+/// segments in case its last `Ack` was lost.
+const LINGER: Duration = Duration::from_secs(2);
+
+/// A segment awaiting acknowledgement.
+struct InFlight {
+    seq: u32,
+    datagram: Vec<u8>,
+    rto: Duration,
+    retransmits: u32,
+    deadline: Instant,
+}
+
+/// Start a carrier for an established connection and return the frame
+/// reader and writer for the key exchange.
+///
+/// `incoming` delivers the datagrams the peer sends on this connection.
+pub(crate) fn spawn_carrier(
+    socket: Arc<UdpSocket>,
+    peer: SocketAddr,
+    conn_id: u64,
+    incoming: Receiver<Packet>,
+) -> (ConnectionReader, ConnectionWriter) {
+    let (outer, inner) = duplex(PIPE_CAPACITY);
+    let (outer_read, outer_write) = split(outer);
+    let carrier = Carrier {
+        socket,
+        peer,
+        conn_id,
+        incoming,
+        next_seq: 0,
+        in_flight: VecDeque::new(),
+        fin_sent: false,
+        next_expected: 0,
+        reorder: BTreeMap::new(),
+        peer_fin: false,
+    };
+    let _handle = spawn(async move {
+        if let Err(e) = carrier.run(inner).await {
+            debug!("UDP handshake connection to {peer} closed: {e}");
+        }
+    });
+    (
+        ConnectionReader::from_stream(outer_read),
+        ConnectionWriter::from_stream(outer_write),
+    )
+}
+
+struct Carrier {
+    socket: Arc<UdpSocket>,
+    peer: SocketAddr,
+    conn_id: u64,
+    incoming: Receiver<Packet>,
+    // Sending half.
+    next_seq: u32,
+    in_flight: VecDeque<InFlight>,
+    fin_sent: bool,
+    // Receiving half.  `None` marks the peer's `Fin`.
+    next_expected: u32,
+    reorder: BTreeMap<u32, Option<Vec<u8>>>,
+    peer_fin: bool,
+}
+
+impl Carrier {
+    async fn run(mut self, pipe: DuplexStream) -> Result<()> {
+        let (mut pipe_read, mut pipe_write) = split(pipe);
+        let mut buf = vec![0u8; MAX_SEGMENT_LEN];
+        let mut last_heard = Instant::now();
+        let mut linger_until: Option<Instant> = None;
+
+        loop {
+            if linger_until.is_none() && self.fin_sent && self.in_flight.is_empty() && self.peer_fin
+            {
+                linger_until = Some(Instant::now() + LINGER);
+            }
+            let deadline = self
+                .in_flight
+                .iter()
+                .map(|segment| segment.deadline)
+                .chain(linger_until)
+                .fold(last_heard + IDLE_TIMEOUT, Instant::min);
+
+            select! {
+                read = pipe_read.read(&mut buf), if !self.fin_sent && self.in_flight.len() < WINDOW => {
+                    match read {
+                        Ok(0) | Err(_) => self.send_segment(None).await?,
+                        Ok(n) => self.send_segment(Some(buf[..n].to_vec())).await?,
+                    }
+                }
+                packet = self.incoming.recv() => {
+                    let Some(packet) = packet else {
+                        return Ok(());
+                    };
+                    last_heard = Instant::now();
+                    self.receive(packet, &mut pipe_write).await?;
+                }
+                () = sleep_until(deadline) => {
+                    let now = Instant::now();
+                    if linger_until.is_some_and(|linger| now >= linger) {
+                        return Ok(());
+                    }
+                    if now >= last_heard + IDLE_TIMEOUT {
+                        return Err(MoshpitError::UdpHandshakeTimedOut.into());
+                    }
+                    self.retransmit(now).await?;
+                }
+            }
+        }
+    }
+
+    /// Queue and send the next segment; `None` sends the `Fin`.
+    async fn send_segment(&mut self, payload: Option<Vec<u8>>) -> Result<()> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let packet = if let Some(payload) = payload {
+            Packet::Data {
+                conn_id: self.conn_id,
+                seq,
+                payload,
+            }
+        } else {
+            self.fin_sent = true;
+            Packet::Fin {
+                conn_id: self.conn_id,
+                seq,
+            }
+        };
+        let datagram = packet.encode();
+        let _sent = self.socket.send_to(&datagram, self.peer).await?;
+        self.in_flight.push_back(InFlight {
+            seq,
+            datagram,
+            rto: INITIAL_RTO,
+            retransmits: 0,
+            deadline: Instant::now() + INITIAL_RTO,
+        });
+        Ok(())
+    }
+
+    /// Resend every segment whose retransmission timer has fired.
+    async fn retransmit(&mut self, now: Instant) -> Result<()> {
+        for segment in &mut self.in_flight {
+            if segment.deadline > now {
+                continue;
+            }
+            if segment.retransmits >= MAX_RETRANSMITS {
+                return Err(MoshpitError::UdpHandshakeTimedOut.into());
+            }
+            let _sent = self.socket.send_to(&segment.datagram, self.peer).await?;
+            segment.retransmits += 1;
+            segment.rto = (segment.rto * 2).min(MAX_RTO);
+            segment.deadline = now + segment.rto;
+        }
+        Ok(())
+    }
+
+    /// Handle one datagram from the peer.
+    async fn receive(
+        &mut self,
+        packet: Packet,
+        pipe_write: &mut WriteHalf<DuplexStream>,
+    ) -> Result<()> {
+        let (seq, segment) = match packet {
+            Packet::Ack { next, .. } => {
+                while self
+                    .in_flight
+                    .front()
+                    .is_some_and(|segment| segment.seq < next)
+                {
+                    let _acked = self.in_flight.pop_front();
+                }
+                return Ok(());
+            }
+            Packet::Data { seq, payload, .. } => (seq, Some(payload)),
+            Packet::Fin { seq, .. } => (seq, None),
+            Packet::Hello { .. } | Packet::Cookie { .. } => return Ok(()),
+        };
+        let ahead = seq.wrapping_sub(self.next_expected);
+        if !self.peer_fin && usize::try_from(ahead).is_ok_and(|ahead| ahead < WINDOW) {
+            let _previous = self.reorder.insert(seq, segment);
+            while let Some(segment) = self.reorder.remove(&self.next_expected) {
+                self.next_expected += 1;
+                if let Some(bytes) = segment {
+                    // The key exchange may already have dropped its reader.
+                    let _written = pipe_write.write_all(&bytes).await;
+                } else {
+                    self.peer_fin = true;
+                    self.reorder.clear();
+                    let _shutdown = pipe_write.shutdown().await;
+                    break;
+                }
+            }
+        }
+        let ack = Packet::Ack {
+            conn_id: self.conn_id,
+            next: self.next_expected,
+        };
+        let _sent = self.socket.send_to(&ack.encode(), self.peer).await?;
+        Ok(())
+    }
+}
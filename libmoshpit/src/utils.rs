@@ -8,10 +8,26 @@
 
 //! Utility functions shared across the moshpit crates.
 
-use std::{net::SocketAddr, path::PathBuf, sync::LazyLock};
+use std::{
+    collections::VecDeque,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io,
+    net::{Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    sync::LazyLock,
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
+use getset::{CopyGetters, Getters};
 use regex::Regex;
+use tokio::{
+    net::{TcpStream, lookup_host},
+    select,
+    task::JoinSet,
+    time::sleep,
+};
+use tracing::trace;
 use whoami::username;
 
 use crate::MoshpitError;
@@ -27,31 +43,189 @@ pub fn to_path_buf(path: &String) -> Result<PathBuf> {
 }
 
 static SERVER_DEST_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"^((.*)@)?((25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)(\.(25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)){3})(:(\d{1,5}))?$").expect("invalid regex literal")
+    Regex::new(r"^((.*)@)?(\[([0-9A-Fa-f:.]+)\]|([A-Za-z0-9]([A-Za-z0-9-]{0,61}[A-Za-z0-9])?(\.[A-Za-z0-9]([A-Za-z0-9-]{0,61}[A-Za-z0-9])?)*\.?))(:(\d{1,5}))?$").expect("invalid regex literal")
 });
 
-/// Parse the server destination command line option into a `SocketAddr`
+/// Delay between starting successive connection attempts when racing the
+/// resolved addresses of a host (RFC 8305 "Connection Attempt Delay").
+pub const HAPPY_EYEBALLS_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// A parsed `[user@]host[:port]` server destination.
+///
+/// The host is kept exactly as the user typed it (minus the brackets around an
+/// IPv6 literal) rather than as a resolved address, so it can be re-resolved on
+/// every connection attempt and used as the `known_hosts` key.
+#[derive(Clone, CopyGetters, Debug, Eq, Getters, PartialEq)]
+pub struct ServerDestination {
+    /// The user to log in as on the server
+    #[getset(get = "pub")]
+    user: String,
+    /// The host name or IP literal as typed by the user
+    #[getset(get = "pub")]
+    host: String,
+    /// The server TCP port
+    #[getset(get_copy = "pub")]
+    port: u16,
+}
+
+impl ServerDestination {
+    /// Resolve the host (A and AAAA records, or an IP literal) to the list of
+    /// socket addresses to try, in Happy Eyeballs order.
+    ///
+    /// # Errors
+    /// * The name lookup fails.
+    /// * The name resolves to no addresses.
+    ///
+    pub async fn resolve(&self) -> Result<Vec<SocketAddr>> {
+        let resolved = lookup_host((self.host.as_str(), self.port))
+            .await
+            .with_context(|| format!("failed to resolve {}", self.host))?;
+        let ordered = happy_eyeballs_order(resolved);
+        if ordered.is_empty() {
+            Err(MoshpitError::HostResolutionFailed.into())
+        } else {
+            Ok(ordered)
+        }
+    }
+}
+
+impl Display for ServerDestination {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Parse the server destination command line option into a [`ServerDestination`]
+///
+/// Accepts `[user@]host[:port]` where `host` is a DNS name, a dotted-quad IPv4
+/// address, or a bracketed IPv6 literal (`[2001:db8::1]:40404`).  A bare IPv6
+/// literal without a port is accepted as well.  The user defaults to the
+/// current login name and the port to `port`.
 ///
 /// # Errors
-/// * This function never errors, but is wrapped to use with `map_or_else` and similar
+/// * The destination is not in one of the accepted forms.
+/// * The current user name cannot be determined when no user is given.
 ///
-pub fn parse_server_destination(dest: &str, port: u16) -> Result<(String, SocketAddr)> {
-    if let Some(captures) = SERVER_DEST_REGEX.captures(dest) {
-        let user = captures
-            .get(2)
-            .map_or(username()?, |m| m.as_str().to_string());
-        let ip_str = captures.get(3).map_or("", |m| m.as_str());
-        let port_str = captures.get(8).map_or("", |m| m.as_str());
-        let port_num = if port_str.is_empty() {
-            port
-        } else {
-            port_str.parse().unwrap_or(port)
+pub fn parse_server_destination(dest: &str, port: u16) -> Result<ServerDestination> {
+    let (user, host, port_str) = if let Some(captures) = SERVER_DEST_REGEX.captures(dest) {
+        let user = captures.get(2).map(|m| m.as_str().to_string());
+        let host = match (captures.get(4), captures.get(5)) {
+            (Some(v6), _) => {
+                let _addr: Ipv6Addr = v6
+                    .as_str()
+                    .parse()
+                    .map_err(|_| MoshpitError::InvalidServerDestination)?;
+                v6.as_str().to_string()
+            }
+            (None, Some(name)) => name.as_str().to_string(),
+            (None, None) => return Err(MoshpitError::InvalidServerDestination.into()),
         };
-        let socket_addr = format!("{ip_str}:{port_num}");
-        Ok((user, socket_addr.parse()?))
+        let port_str = captures.get(10).map(|m| m.as_str().to_string());
+        (user, host, port_str)
     } else {
-        Err(MoshpitError::InvalidServerDestination.into())
+        // A bare IPv6 literal cannot carry a port, since the colons are ambiguous.
+        let (user, host) = match dest.rsplit_once('@') {
+            Some((user, host)) => (Some(user.to_string()), host),
+            None => (None, dest),
+        };
+        let _addr: Ipv6Addr = host
+            .parse()
+            .map_err(|_| MoshpitError::InvalidServerDestination)?;
+        (user, host.to_string(), None)
+    };
+    let user = match user {
+        Some(user) => user,
+        None => username()?,
+    };
+    let port = match port_str {
+        Some(port_str) => port_str
+            .parse()
+            .map_err(|_| MoshpitError::InvalidServerDestination)?,
+        None => port,
+    };
+    Ok(ServerDestination { user, host, port })
+}
+
+/// Order resolved addresses for Happy Eyeballs connection racing (RFC 8305).
+///
+/// Duplicates are dropped and the two address families are interleaved,
+/// starting with the family of the first address the resolver returned.
+pub fn happy_eyeballs_order(addrs: impl IntoIterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+    let mut unique: Vec<SocketAddr> = Vec::new();
+    for addr in addrs {
+        if !unique.contains(&addr) {
+            unique.push(addr);
+        }
+    }
+    let prefer_v6 = unique.first().is_some_and(SocketAddr::is_ipv6);
+    let (mut preferred, mut other): (VecDeque<SocketAddr>, VecDeque<SocketAddr>) = unique
+        .iter()
+        .copied()
+        .partition(|addr| addr.is_ipv6() == prefer_v6);
+    let mut ordered = Vec::with_capacity(unique.len());
+    while !preferred.is_empty() || !other.is_empty() {
+        ordered.extend(preferred.pop_front());
+        ordered.extend(other.pop_front());
+    }
+    ordered
+}
+
+/// Connect to the first reachable address in `addrs`, Happy Eyeballs style.
+///
+/// A new attempt is started every [`HAPPY_EYEBALLS_ATTEMPT_DELAY`], or as soon as
+/// the previous one fails, while earlier attempts keep running.  The first
+/// connection to complete wins and every other attempt is aborted.
+///
+/// # Errors
+/// * Every connection attempt failed; the last failure is returned.
+/// * `addrs` is empty.
+///
+pub async fn connect_happy_eyeballs(addrs: &[SocketAddr]) -> Result<TcpStream> {
+    let mut remaining = addrs.iter().copied();
+    let mut attempts = JoinSet::new();
+    let mut last_err: Option<anyhow::Error> = None;
+
+    loop {
+        if attempts.is_empty() {
+            match remaining.next() {
+                Some(addr) => start_attempt(&mut attempts, addr),
+                None => break,
+            }
+        }
+        select! {
+            biased;
+            Some(joined) = attempts.join_next() => match joined {
+                Ok((addr, Ok(stream))) => {
+                    trace!("connected to {addr}");
+                    // Dropping the JoinSet aborts the attempts still in flight.
+                    return Ok(stream);
+                }
+                Ok((addr, Err(e))) => {
+                    trace!("connection to {addr} failed: {e}");
+                    last_err = Some(e.into());
+                    if let Some(next) = remaining.next() {
+                        start_attempt(&mut attempts, next);
+                    }
+                }
+                Err(e) => last_err = Some(e.into()),
+            },
+            () = sleep(HAPPY_EYEBALLS_ATTEMPT_DELAY) => {
+                if let Some(next) = remaining.next() {
+                    start_attempt(&mut attempts, next);
+                }
+            }
+        }
     }
+    Err(last_err.unwrap_or_else(|| MoshpitError::HostResolutionFailed.into()))
+}
+
+fn start_attempt(attempts: &mut JoinSet<(SocketAddr, io::Result<TcpStream>)>, addr: SocketAddr) {
+    trace!("connecting to {addr}");
+    let _handle = attempts.spawn(async move { (addr, TcpStream::connect(addr).await) });
 }
 
 static EXIT_TITLE_RE: LazyLock<Regex> =
@@ -76,14 +250,19 @@ pub(crate) trait Mock {
 
 #[cfg(test)]
 pub(crate) mod test {
+    use std::net::SocketAddr;
+
     use anyhow::Result;
+    use tokio::net::TcpListener;
     use tracing::Level;
     use tracing_subscriber_init::TracingConfig;
     use whoami::username;
 
     use crate::TracingConfigExt;
 
-    use super::{parse_server_destination, to_path_buf};
+    use super::{
+        connect_happy_eyeballs, happy_eyeballs_order, parse_server_destination, to_path_buf,
+    };
 
     pub(crate) struct TestConfig {
         verbose: u8,
@@ -164,23 +343,132 @@ pub(crate) mod test {
         let dest = "user@192.168.1.1:12345";
         let port = 40404;
         let result = parse_server_destination(dest, port)?;
-        assert_eq!(result.0, "user");
-        assert_eq!(result.1.to_string(), "192.168.1.1:12345");
+        assert_eq!(result.user(), "user");
+        assert_eq!(result.to_string(), "192.168.1.1:12345");
 
         let dest_no_port = "user@192.168.1.1";
         let result_no_port = parse_server_destination(dest_no_port, port)?;
-        assert_eq!(result_no_port.0, "user");
-        assert_eq!(result_no_port.1.to_string(), "192.168.1.1:40404");
+        assert_eq!(result_no_port.user(), "user");
+        assert_eq!(result_no_port.to_string(), "192.168.1.1:40404");
 
         let dest_no_user = "192.168.1.1:12345";
         let result_no_user = parse_server_destination(dest_no_user, port)?;
-        assert_eq!(result_no_user.0, username()?);
-        assert_eq!(result_no_user.1.to_string(), "192.168.1.1:12345");
+        assert_eq!(*result_no_user.user(), username()?);
+        assert_eq!(result_no_user.to_string(), "192.168.1.1:12345");
 
         let dest_no_user_no_port = "192.168.1.1";
         let result_no_user_no_port = parse_server_destination(dest_no_user_no_port, port)?;
-        assert_eq!(result_no_user_no_port.0, username()?);
-        assert_eq!(result_no_user_no_port.1.to_string(), "192.168.1.1:40404");
+        assert_eq!(*result_no_user_no_port.user(), username()?);
+        assert_eq!(result_no_user_no_port.to_string(), "192.168.1.1:40404");
+        Ok(())
+    }
+
+    #[test]
+    fn hostname_server_destination_is_parsed() -> Result<()> {
+        let result = parse_server_destination("alice@build01.corp", 40404)?;
+        assert_eq!(result.user(), "alice");
+        assert_eq!(result.host(), "build01.corp");
+        assert_eq!(result.port(), 40404);
+
+        let result = parse_server_destination("alice@build-01.example.com.:2222", 40404)?;
+        assert_eq!(result.host(), "build-01.example.com.");
+        assert_eq!(result.port(), 2222);
+
+        let result = parse_server_destination("localhost", 40404)?;
+        assert_eq!(*result.user(), username()?);
+        assert_eq!(result.host(), "localhost");
+        Ok(())
+    }
+
+    #[test]
+    fn ipv6_server_destination_is_parsed() -> Result<()> {
+        let result = parse_server_destination("[2001:db8::1]:40405", 40404)?;
+        assert_eq!(*result.user(), username()?);
+        assert_eq!(result.host(), "2001:db8::1");
+        assert_eq!(result.port(), 40405);
+        assert_eq!(result.to_string(), "[2001:db8::1]:40405");
+
+        let result = parse_server_destination("bob@[::1]", 40404)?;
+        assert_eq!(result.user(), "bob");
+        assert_eq!(result.host(), "::1");
+        assert_eq!(result.port(), 40404);
+
+        let result = parse_server_destination("bob@fe80::1", 40404)?;
+        assert_eq!(result.user(), "bob");
+        assert_eq!(result.host(), "fe80::1");
+        assert_eq!(result.port(), 40404);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_server_destinations_are_err() {
+        for dest in [
+            "user@[not:an:address]",
+            "user@[2001:db8::1",
+            "user@-leading-dash.example",
+            "user@host:99999",
+            "user@host:",
+            "user@",
+            "",
+        ] {
+            assert!(
+                parse_server_destination(dest, 40404).is_err(),
+                "{dest:?} should be rejected"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn ip_literal_resolves_without_lookup() -> Result<()> {
+        let dest = parse_server_destination("[::1]:40404", 40404)?;
+        assert_eq!(dest.resolve().await?, vec!["[::1]:40404".parse()?]);
+        let dest = parse_server_destination("127.0.0.1", 40404)?;
+        assert_eq!(dest.resolve().await?, vec!["127.0.0.1:40404".parse()?]);
+        Ok(())
+    }
+
+    #[test]
+    fn happy_eyeballs_order_interleaves_families() -> Result<()> {
+        let v6a: SocketAddr = "[2001:db8::1]:1".parse()?;
+        let v6b: SocketAddr = "[2001:db8::2]:1".parse()?;
+        let v6c: SocketAddr = "[2001:db8::3]:1".parse()?;
+        let v4a: SocketAddr = "192.0.2.1:1".parse()?;
+        let v4b: SocketAddr = "192.0.2.2:1".parse()?;
+
+        let ordered = happy_eyeballs_order([v6a, v6b, v6c, v4a, v6a, v4b]);
+        assert_eq!(ordered, vec![v6a, v4a, v6b, v4b, v6c]);
+
+        let ordered = happy_eyeballs_order([v4a, v6a, v4b]);
+        assert_eq!(ordered, vec![v4a, v6a, v4b]);
+
+        assert!(happy_eyeballs_order(Vec::<SocketAddr>::new()).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connect_happy_eyeballs_skips_unreachable_addresses() -> Result<()> {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let closed_addr = closed.local_addr()?;
+        drop(closed);
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let open_addr = listener.local_addr()?;
+
+        let stream = connect_happy_eyeballs(&[closed_addr, open_addr]).await?;
+        assert_eq!(stream.peer_addr()?, open_addr);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connect_happy_eyeballs_reports_last_error() -> Result<()> {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let closed_addr = closed.local_addr()?;
+        drop(closed);
+
+        let err = connect_happy_eyeballs(&[closed_addr])
+            .await
+            .expect_err("connecting to a closed port must fail");
+        assert!(err.to_string().to_lowercase().contains("refused"));
+        assert!(connect_happy_eyeballs(&[]).await.is_err());
         Ok(())
     }
 }
@@ -39,8 +39,82 @@ pub(crate) enum Commands {
         #[clap(long, help = "Emit machine-readable JSON instead of a table")]
         json: bool,
     },
+    /// Copy files to or from a server over a session, e.g.
+    /// `mp cp notes.txt user@host:docs/` or `mp cp -r user@host:src .`.
+    ///
+    /// Either every source is local and the destination is `[user@]host:path`,
+    /// or every source is on the same host and the destination is local.
+    /// Remote paths are relative to the remote home directory.  Partial files
+    /// left by an interrupted copy are completed instead of sent again.
+    Cp {
+        /// Copy directories and their contents.
+        #[clap(short, long, help = "Copy directories and their contents")]
+        recursive: bool,
+        /// The sources followed by the destination.
+        #[clap(
+            num_args = 2..,
+            required = true,
+            value_name = "PATH",
+            help = "Sources followed by the destination; remote paths are [user@]host:path"
+        )]
+        paths: Vec<String>,
+    },
+    /// List your sessions on a server, live and detached, with their name,
+    /// size, creation and last attach times, and window title, e.g.
+    /// `mp ls user@host`.
+    Ls {
+        /// The server whose sessions to list.
+        #[clap(help = "The server whose sessions to list: [user@]host[:port]")]
+        destination: String,
+    },
+    /// Attach to a named session on a server, e.g. `mp attach user@host work`.
+    ///
+    /// Unlike `--session`, this fails instead of starting the session when
+    /// there is none by that name.  With `--shared` or `--read-only` the
+    /// session's other clients stay attached alongside this one, and
+    /// `owner/name` joins a session another user shares with you in their
+    /// `~/.mp/shared_sessions` on the server.
+    Attach {
+        /// Join alongside the session's other clients instead of taking it over.
+        #[clap(long, help = "Join alongside the session's other clients")]
+        shared: bool,
+        /// Join alongside the session's other clients, only watching.
+        #[clap(
+            long,
+            help = "Join alongside the session's other clients, only watching"
+        )]
+        read_only: bool,
+        /// The server running the session.
+        #[clap(help = "The server running the session: [user@]host[:port]")]
+        destination: String,
+        /// The session's name, prefixed with `owner/` for another user's.
+        #[clap(help = "The name of the session to attach to, as owner/name for another user's")]
+        name: String,
+    },
+    /// Play back a session recorded with `--record`, e.g.
+    /// `mp replay --speed 2 session.mprec`.
+    ///
+    /// Space pauses and resumes, `.` steps a frame while paused, left and
+    /// right seek five seconds back and forth, up and down (or `+` and `-`)
+    /// double and halve the speed, and `q` quits.
+    Replay {
+        /// Playback speed, as a multiple of the recorded speed.
+        #[clap(
+            long,
+            default_value_t = 1.0,
+            help = "Playback speed, as a multiple of the recorded speed"
+        )]
+        speed: f64,
+        /// Paint the recorded prediction overlays as the user saw them.
+        #[clap(long, help = "Show the local echo predictions as they were seen")]
+        predictions: bool,
+        /// The recording to play.
+        #[clap(help = "The recording to play")]
+        file: String,
+    },
 }
 
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Clone, CopyGetters, Debug, Getters, Parser)]
 #[command(author, version, about, long_version = LONG_VERSION.as_str(), long_about = None)]
 pub(crate) struct Cli {
@@ -87,12 +161,13 @@ pub(crate) struct Cli {
     #[getset(get_copy = "pub(crate)")]
     server_port: u16,
     /// The destination of the server to connect to
-    /// This takes the form of 'user@ip address' where the user is optional
-    /// and will default to the user executing the command.
+    /// This takes the form of '[user@]host[:port]' where the user is optional
+    /// and will default to the user executing the command.  The host may be a
+    /// DNS name, an IPv4 address, or a bracketed IPv6 literal.
     ///
     /// Optional at the parse level so subcommands (e.g. `ec`) can run without a
     /// destination; the connect flow validates that it is present.
-    #[clap(help = "The IP address of the server to connect to")]
+    #[clap(help = "The server to connect to: [user@]host[:port]")]
     #[getset(get = "pub(crate)")]
     server_destination: Option<String>,
     /// Local-echo prediction preference: adaptive (default), always, or never.
@@ -157,11 +232,13 @@ pub(crate) struct Cli {
     /// Data-channel transport mode.  `udp` (default) uses encrypted UDP datagrams;
     /// `tcp` connects to the server's TCP data port (fallback for UDP-blocking firewalls).
     /// Requires the server to have `allow_tcp_transport = true` in its config.
+    /// `udp-only` also runs the key exchange over UDP, for networks that block TCP.
+    /// Requires the server to have `udp_handshake = true` in its config.
     #[clap(
         long,
         value_name = "MODE",
         default_value = "udp",
-        help = "Data-channel transport: udp (default) or tcp"
+        help = "Transport: udp (default), tcp, or udp-only (no TCP at all)"
     )]
     #[getset(get = "pub(crate)")]
     transport: String,
@@ -170,7 +247,7 @@ pub(crate) struct Cli {
     #[clap(
         long,
         value_name = "ALGOS",
-        help = "Ordered KEX algorithms to offer, comma-separated [supported: x25519-sha256 (default), ml-kem-768-sha256, ml-kem-512-sha256, ml-kem-1024-sha256, p384-sha384, p256-sha256]"
+        help = "Ordered KEX algorithms to offer, comma-separated [supported: mlkem768x25519-sha256 (default), x25519-sha256, ml-kem-768-sha256, ml-kem-512-sha256, ml-kem-1024-sha256, p384-sha384, p256-sha256]"
     )]
     #[getset(get = "pub(crate)")]
     kex_algos: Option<String>,
@@ -188,7 +265,7 @@ pub(crate) struct Cli {
     #[clap(
         long,
         value_name = "ALGOS",
-        help = "Ordered MAC algorithms to offer, comma-separated [supported: hmac-sha512 (default), hmac-sha256]"
+        help = "Ordered MAC algorithms to offer, comma-separated [supported: hmac-sha512 (default), hmac-sha256, aead-implicit]"
     )]
     #[getset(get = "pub(crate)")]
     mac_algos: Option<String>,
@@ -210,6 +287,103 @@ pub(crate) struct Cli {
     )]
     #[getset(get = "pub(crate)")]
     escape_key: Option<String>,
+    /// Forward a local TCP port to a host reachable from the server, e.g.
+    /// `-L 8080:localhost:80`.  Either end may instead be a Unix socket path,
+    /// e.g. `-L /tmp/docker.sock:/var/run/docker.sock`.  May be given more than
+    /// once.
+    #[clap(
+        short = 'L',
+        long,
+        value_name = "[BIND:]PORT:HOST:HOSTPORT",
+        help = "Forward local [BIND:]PORT (or a socket path) to HOST:HOSTPORT (or a socket path) as seen from the server; may be repeated"
+    )]
+    #[getset(get = "pub(crate)")]
+    local_forward: Vec<String>,
+    /// Forward a port on the server to a host reachable from this machine, e.g.
+    /// `-R 8080:localhost:3000`.  Either end may instead be a Unix socket path,
+    /// e.g. `-R /tmp/gpg.sock:/run/user/1000/gnupg/S.gpg-agent`.  May be given
+    /// more than once.
+    #[clap(
+        short = 'R',
+        long,
+        value_name = "[BIND:]PORT:HOST:HOSTPORT",
+        help = "Forward server-side [BIND:]PORT (or a socket path) to HOST:HOSTPORT (or a socket path) as seen from this machine; may be repeated"
+    )]
+    #[getset(get = "pub(crate)")]
+    remote_forward: Vec<String>,
+    /// Run a SOCKS5 proxy on `[BIND:]PORT` whose connections are made from the
+    /// server, e.g. `-D 1080`.  May be given more than once.
+    #[clap(
+        short = 'D',
+        long,
+        value_name = "[BIND:]PORT",
+        help = "Run a SOCKS5 proxy on [BIND:]PORT that connects from the server; may be repeated"
+    )]
+    #[getset(get = "pub(crate)")]
+    dynamic_forward: Vec<String>,
+    /// Make the local `mpa` agent usable from the remote shell, through a
+    /// per-session socket the server exports as `MOSHPIT_AGENT_SOCK`.  Only
+    /// listing identities, fetching public keys and signing are forwarded.
+    #[clap(
+        short = 'A',
+        long,
+        help = "Forward the local agent (MOSHPIT_AGENT_SOCK) to the remote shell"
+    )]
+    #[getset(get_copy = "pub(crate)")]
+    forward_agent: bool,
+    /// Relay key agreement through the forwarded agent too, which X25519,
+    /// P-256 and P-384 identities need to authenticate from the remote host.
+    /// Off by default, since it lets anyone who reaches the forwarded socket
+    /// agree keys with every loaded identity.
+    #[clap(
+        long,
+        help = "Also relay key agreement through the forwarded agent, for X25519 and ECDH identities"
+    )]
+    #[getset(get_copy = "pub(crate)")]
+    forward_agent_agree: bool,
+    /// Run a remote command without a PTY: its standard output and error reach
+    /// `mp`'s own, binary-clean and apart, and the end of `mp`'s standard input
+    /// reaches the command.  Only meaningful with a remote command.
+    #[clap(
+        short = 'T',
+        long,
+        help = "Run the remote command without a PTY, with separate binary-clean standard streams"
+    )]
+    #[getset(get_copy = "pub(crate)")]
+    no_pty: bool,
+    /// Resume the session with this name, or start it under this name when
+    /// there is none, instead of resuming the last session with the server.
+    /// Named sessions can be resumed from any machine with `mp attach` and
+    /// are listed by `mp ls`.
+    #[clap(
+        long,
+        value_name = "NAME",
+        help = "Resume the session called NAME, or start it if there is none"
+    )]
+    #[getset(get = "pub(crate)")]
+    session: Option<String>,
+    /// Record everything `mp` draws, with its timing, to FILE for `mp replay`.
+    /// The screen is recorded as rendered, local echo predictions included.
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Record what is drawn to FILE, for playing back with mp replay"
+    )]
+    #[getset(get = "pub(crate)")]
+    record: Option<String>,
+    /// A command to run on the server in place of the login shell, e.g.
+    /// `mp host -- ls -l`.  Everything after the destination belongs to it, so
+    /// `mp` options must come first.  The words are joined with spaces and run
+    /// by the remote user's shell, and the command's exit status becomes
+    /// `mp`'s.
+    #[clap(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "COMMAND",
+        help = "Run COMMAND on the server instead of a login shell; must follow the destination"
+    )]
+    #[getset(get = "pub(crate)")]
+    remote_command: Vec<String>,
     /// Set of clap argument ids the user actually supplied on the command line
     /// (`ValueSource::CommandLine`), populated by [`Cli::parse_argv`].  This is
     /// the source of truth for "came from the command line": it lets
@@ -388,6 +562,96 @@ impl Source for Cli {
                 Value::new(Some(&origin), ValueKind::String(escape_key.clone())),
             );
         }
+        if on("local_forward") {
+            let _old = map.insert(
+                "local_forward".to_string(),
+                Value::new(
+                    Some(&origin),
+                    ValueKind::Array(
+                        self.local_forward
+                            .iter()
+                            .map(|spec| Value::new(Some(&origin), ValueKind::String(spec.clone())))
+                            .collect(),
+                    ),
+                ),
+            );
+        }
+        if on("remote_forward") {
+            let _old = map.insert(
+                "remote_forward".to_string(),
+                Value::new(
+                    Some(&origin),
+                    ValueKind::Array(
+                        self.remote_forward
+                            .iter()
+                            .map(|spec| Value::new(Some(&origin), ValueKind::String(spec.clone())))
+                            .collect(),
+                    ),
+                ),
+            );
+        }
+        if on("dynamic_forward") {
+            let _old = map.insert(
+                "dynamic_forward".to_string(),
+                Value::new(
+                    Some(&origin),
+                    ValueKind::Array(
+                        self.dynamic_forward
+                            .iter()
+                            .map(|spec| Value::new(Some(&origin), ValueKind::String(spec.clone())))
+                            .collect(),
+                    ),
+                ),
+            );
+        }
+        if on("forward_agent") {
+            let _old = map.insert(
+                "forward_agent".to_string(),
+                Value::new(Some(&origin), ValueKind::Boolean(self.forward_agent)),
+            );
+        }
+        if on("forward_agent_agree") {
+            let _old = map.insert(
+                "forward_agent_agree".to_string(),
+                Value::new(Some(&origin), ValueKind::Boolean(self.forward_agent_agree)),
+            );
+        }
+        if on("no_pty") {
+            let _old = map.insert(
+                "no_pty".to_string(),
+                Value::new(Some(&origin), ValueKind::Boolean(self.no_pty)),
+            );
+        }
+        if on("session")
+            && let Some(session) = &self.session
+        {
+            let _old = map.insert(
+                "session".to_string(),
+                Value::new(Some(&origin), ValueKind::String(session.clone())),
+            );
+        }
+        if on("record")
+            && let Some(record) = &self.record
+        {
+            let _old = map.insert(
+                "record".to_string(),
+                Value::new(Some(&origin), ValueKind::String(record.clone())),
+            );
+        }
+        if on("remote_command") {
+            let _old = map.insert(
+                "remote_command".to_string(),
+                Value::new(
+                    Some(&origin),
+                    ValueKind::Array(
+                        self.remote_command
+                            .iter()
+                            .map(|word| Value::new(Some(&origin), ValueKind::String(word.clone())))
+                            .collect(),
+                    ),
+                ),
+            );
+        }
         if let Some(table) = build_algo_table(
             self.kex_algos.as_deref().filter(|_| on("kex_algos")),
             self.aead_algos.as_deref().filter(|_| on("aead_algos")),
@@ -511,6 +775,90 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_cp_subcommand_parses() -> anyhow::Result<()> {
+        let cli = Cli::parse_argv(["moshpit", "-s", "4000", "cp", "-r", "src", "host:dst"])?;
+        assert_eq!(cli.server_port(), 4000);
+        let Some(Commands::Cp { recursive, paths }) = cli.command() else {
+            anyhow::bail!("expected the cp subcommand");
+        };
+        assert!(*recursive);
+        assert_eq!(paths, &["src", "host:dst"]);
+        assert!(Cli::parse_argv(["moshpit", "cp", "host:only"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_subcommands_parse() -> anyhow::Result<()> {
+        let cli = Cli::parse_argv(["moshpit", "ls", "user@host"])?;
+        let Some(Commands::Ls { destination }) = cli.command() else {
+            anyhow::bail!("expected the ls subcommand");
+        };
+        assert_eq!(destination, "user@host");
+
+        let cli = Cli::parse_argv(["moshpit", "attach", "host", "work"])?;
+        let Some(Commands::Attach {
+            shared,
+            read_only,
+            destination,
+            name,
+        }) = cli.command()
+        else {
+            anyhow::bail!("expected the attach subcommand");
+        };
+        assert_eq!((destination.as_str(), name.as_str()), ("host", "work"));
+        assert!(!shared && !read_only);
+        assert!(Cli::parse_argv(["moshpit", "attach", "host"]).is_err());
+
+        let cli = Cli::parse_argv(["moshpit", "attach", "--read-only", "host", "alice/work"])?;
+        let Some(Commands::Attach {
+            read_only, name, ..
+        }) = cli.command()
+        else {
+            anyhow::bail!("expected the attach subcommand");
+        };
+        assert!(*read_only);
+        assert_eq!(name, "alice/work");
+
+        let cli = Cli::parse_argv(["moshpit", "--session", "work", "host"])?;
+        assert_eq!(cli.session().as_deref(), Some("work"));
+        assert!(cli.explicit_args().contains("session"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_and_replay_parse() -> anyhow::Result<()> {
+        let cli = Cli::parse_argv(["moshpit", "--record", "out.mprec", "host"])?;
+        assert_eq!(cli.record().as_deref(), Some("out.mprec"));
+        assert!(cli.explicit_args().contains("record"));
+        assert!(cli.collect()?.contains_key("record"));
+
+        let cli = Cli::parse_argv(["moshpit", "replay", "out.mprec"])?;
+        let Some(Commands::Replay {
+            speed,
+            predictions,
+            file,
+        }) = cli.command()
+        else {
+            anyhow::bail!("expected the replay subcommand");
+        };
+        assert!((speed - 1.0).abs() < f64::EPSILON);
+        assert!(!predictions);
+        assert_eq!(file, "out.mprec");
+
+        let cli = Cli::parse_argv(["moshpit", "replay", "--speed", "2.5", "--predictions", "x"])?;
+        let Some(Commands::Replay {
+            speed, predictions, ..
+        }) = cli.command()
+        else {
+            anyhow::bail!("expected the replay subcommand");
+        };
+        assert!((speed - 2.5).abs() < f64::EPSILON);
+        assert!(*predictions);
+        assert!(Cli::parse_argv(["moshpit", "replay"]).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_ec_rejects_destination() {
         // `ec` and a connect destination are mutually exclusive.
@@ -718,6 +1066,105 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn collect_emits_repeated_local_forward() -> anyhow::Result<()> {
+        let cli = Cli::parse_argv([
+            "moshpit",
+            "-L",
+            "8080:localhost:80",
+            "--local-forward",
+            "127.0.0.1:5433:db:5432",
+            "host",
+        ])?;
+        assert_eq!(
+            cli.local_forward(),
+            &["8080:localhost:80", "127.0.0.1:5433:db:5432"]
+        );
+        let map = cli.collect()?;
+        if let ValueKind::Array(ref specs) = map
+            .get("local_forward")
+            .ok_or_else(|| anyhow::anyhow!("\"local_forward\" not found in map"))?
+            .kind
+        {
+            assert_eq!(specs.len(), 2);
+        } else {
+            panic!("Expected Array for local_forward");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn collect_emits_remote_forward() -> anyhow::Result<()> {
+        let cli = Cli::parse_argv(["moshpit", "-R", "8080:localhost:3000", "host"])?;
+        assert_eq!(cli.remote_forward(), &["8080:localhost:3000"]);
+        assert!(cli.local_forward().is_empty());
+        let map = cli.collect()?;
+        assert!(map.contains_key("remote_forward"));
+        assert!(!map.contains_key("local_forward"));
+        Ok(())
+    }
+
+    #[test]
+    fn collect_emits_dynamic_forward() -> anyhow::Result<()> {
+        let cli = Cli::parse_argv(["moshpit", "-D", "1080", "-D", "[::1]:1081", "host"])?;
+        assert_eq!(cli.dynamic_forward(), &["1080", "[::1]:1081"]);
+        let map = cli.collect()?;
+        assert!(map.contains_key("dynamic_forward"));
+        assert!(!map.contains_key("remote_forward"));
+        Ok(())
+    }
+
+    #[test]
+    fn collect_emits_forward_agent_only_when_given() -> anyhow::Result<()> {
+        let cli = Cli::parse_argv(["moshpit", "-A", "host"])?;
+        assert!(cli.forward_agent());
+        let map = cli.collect()?;
+        assert!(map.contains_key("forward_agent"));
+
+        let cli = Cli::parse_argv(["moshpit", "host"])?;
+        assert!(!cli.forward_agent());
+        assert!(!cli.collect()?.contains_key("forward_agent"));
+        Ok(())
+    }
+
+    #[test]
+    fn collect_emits_forward_agent_agree_only_when_given() -> anyhow::Result<()> {
+        let cli = Cli::parse_argv(["moshpit", "-A", "--forward-agent-agree", "host"])?;
+        assert!(cli.forward_agent_agree());
+        assert!(cli.collect()?.contains_key("forward_agent_agree"));
+
+        let cli = Cli::parse_argv(["moshpit", "-A", "host"])?;
+        assert!(!cli.forward_agent_agree());
+        assert!(!cli.collect()?.contains_key("forward_agent_agree"));
+        Ok(())
+    }
+
+    #[test]
+    fn remote_command_takes_everything_after_the_destination() -> anyhow::Result<()> {
+        let cli = Cli::parse_argv(["moshpit", "-T", "host", "--", "ls", "-l", "/tmp"])?;
+        assert_eq!(cli.server_destination().as_deref(), Some("host"));
+        assert_eq!(cli.remote_command(), &["ls", "-l", "/tmp"]);
+        assert!(cli.no_pty());
+        let map = cli.collect()?;
+        assert!(map.contains_key("remote_command"));
+        assert!(map.contains_key("no_pty"));
+
+        // As `rsync -e mp` and `git` invoke it: no `--`, options included.
+        let cli = Cli::parse_argv(["moshpit", "host", "rsync", "--server", "-vlogDtpre.iLsfxC"])?;
+        assert_eq!(
+            cli.remote_command(),
+            &["rsync", "--server", "-vlogDtpre.iLsfxC"]
+        );
+        assert!(!cli.no_pty());
+
+        let cli = Cli::parse_argv(["moshpit", "host"])?;
+        assert!(cli.remote_command().is_empty());
+        let map = cli.collect()?;
+        assert!(!map.contains_key("remote_command"));
+        assert!(!map.contains_key("no_pty"));
+        Ok(())
+    }
+
     #[test]
     fn collect_emits_algo_table() -> anyhow::Result<()> {
         // Surrounding spaces exercise the `trim` in the parse closure.
@@ -11,8 +11,9 @@ use std::{collections::BTreeSet, env::var, path::PathBuf, sync::Arc};
 use anyhow::Result;
 use getset::{CopyGetters, Getters, Setters};
 use libmoshpit::{
-    AlgorithmList, DiffMode, DisplayPreference, FileLayer, KEY_ALGORITHM_X25519, KexConfig,
-    KexMode, KeyPair, supported_algorithms,
+    AlgorithmList, DiffMode, DisplayPreference, FileCopy, FileLayer, KEY_ALGORITHM_X25519,
+    KexConfig, KexMode, KeyPair, RemoteCommand, ResumptionTicket, ServerDestination,
+    SessionRequest, supported_algorithms,
 };
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
@@ -62,6 +63,21 @@ pub(crate) enum DiffModePref {
     Statesync,
 }
 
+/// Client transport preference parsed from `--transport` / `MOSHPIT_TRANSPORT` /
+/// TOML.  `UdpOnly` selects the UDP data channel and also runs the key exchange
+/// over UDP datagrams, for networks that block TCP entirely.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum TransportPref {
+    /// TCP key exchange, encrypted UDP data channel.
+    #[default]
+    Udp,
+    /// TCP key exchange, TCP data channel.
+    Tcp,
+    /// UDP key exchange, encrypted UDP data channel.
+    UdpOnly,
+}
+
 /// Client-side tracing configuration — file layer only.
 /// The mp client never writes to stdout, so there is no stdout layer.
 #[derive(Clone, Debug, Default, Deserialize, Eq, Getters, PartialEq, Serialize)]
@@ -70,6 +86,7 @@ pub(crate) struct ClientTracing {
     file: FileLayer,
 }
 
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Clone, CopyGetters, Debug, Deserialize, Eq, Getters, PartialEq, Serialize, Setters)]
 pub(crate) struct Config {
     #[serde(skip_deserializing)]
@@ -85,8 +102,15 @@ pub(crate) struct Config {
     #[getset(get_copy = "pub(crate)")]
     server_port: u16,
     #[serde(default)]
-    #[getset(get = "pub(crate)")]
+    #[getset(get = "pub(crate)", set = "pub(crate)")]
     server_destination: String,
+    /// The server's `known_hosts` key (not persisted to config file): the host
+    /// as typed, so that a host whose address changes keeps its recorded key,
+    /// and the port when it is not the default, so that servers on one host
+    /// keep apart.
+    #[serde(skip)]
+    #[getset(get = "pub(crate)")]
+    known_host: String,
     #[getset(get = "pub(crate)")]
     private_key_path: Option<String>,
     #[getset(get = "pub(crate)")]
@@ -95,6 +119,16 @@ pub(crate) struct Config {
     #[serde(skip)]
     #[getset(get_copy = "pub(crate)", set = "pub(crate)")]
     resume_session_uuid: Option<Uuid>,
+    /// Ticket that resumes `resume_session_uuid` without the asymmetric key
+    /// exchange (not persisted to config file).
+    #[serde(skip)]
+    #[getset(get = "pub(crate)", set = "pub(crate)")]
+    resumption_ticket: Option<ResumptionTicket>,
+    /// Set once the server has turned out too old for an early key share, so
+    /// later reconnects wait for its `KexInit` (not persisted to config file).
+    #[serde(skip)]
+    #[getset(get_copy = "pub(crate)", set = "pub(crate)")]
+    early_key_share_unsupported: bool,
     /// Maximum backoff interval between reconnect attempts, in seconds.
     /// Clamped to [2, 86400] (24 hours).  Defaults to 3600 (1 hour).
     #[serde(default = "Config::default_max_reconnect_backoff_secs")]
@@ -102,7 +136,7 @@ pub(crate) struct Config {
     max_reconnect_backoff_secs: u64,
     /// Local-echo prediction display preference.
     #[serde(default)]
-    #[getset(get_copy = "pub(crate)")]
+    #[getset(get_copy = "pub(crate)", set = "pub(crate)")]
     predict: DisplayPreference,
     /// Send NAT warmup keepalives before the UDP session loop starts.
     /// Off by default; enable with `--nat-warmup` / `MOSHPIT_NAT_WARMUP=true`.
@@ -118,12 +152,12 @@ pub(crate) struct Config {
     /// Set explicitly via `--diff-mode <mode>` / `MOSHPIT_DIFF_MODE=<mode>`.
     #[serde(default)]
     diff_mode: DiffModePref,
-    /// Data-channel transport mode.  `udp` (default) uses encrypted UDP;
-    /// `tcp` uses the server's TCP data port (fallback for UDP-blocking firewalls).
+    /// Transport preference.  `udp` (default) uses encrypted UDP;
+    /// `tcp` uses the server's TCP data port (fallback for UDP-blocking firewalls);
+    /// `udp-only` also runs the key exchange over UDP (see [`Config::udp_only`]).
     /// Set via `--transport tcp` / `MOSHPIT_TRANSPORT=tcp`.
     #[serde(default)]
-    #[getset(get_copy = "pub(crate)")]
-    transport: libmoshpit::TransportMode,
+    transport: TransportPref,
     /// Legacy escape hatch: drive the terminal by forwarding raw server PTY
     /// bytes straight to stdout instead of rendering exclusively through the
     /// differential renderer.  Defaults to `false` (the artifact-free rendered
@@ -151,6 +185,67 @@ pub(crate) struct Config {
     #[serde(default = "Config::default_escape_key")]
     #[getset(get = "pub(crate)")]
     escape_key: String,
+    /// Local port forwards, each `[bind:]port:host:hostport` as for `-L`, where
+    /// either end may be a Unix socket path instead.
+    /// Parsed at startup by `libmoshpit::parse_forward_spec`.
+    #[serde(default)]
+    #[getset(get = "pub(crate)")]
+    local_forward: Vec<String>,
+    /// Remote port forwards, each `[bind:]port:host:hostport` as for `-R`: the
+    /// server listens on `bind:port` and connections come back to `host:hostport`.
+    /// Either end may be a Unix socket path instead.
+    #[serde(default)]
+    #[getset(get = "pub(crate)")]
+    remote_forward: Vec<String>,
+    /// Dynamic forwards, each `[bind:]port` as for `-D`: a local SOCKS5 proxy
+    /// whose connections are made from the server.
+    #[serde(default)]
+    #[getset(get = "pub(crate)")]
+    dynamic_forward: Vec<String>,
+    /// Forward the local agent named by `MOSHPIT_AGENT_SOCK` to the remote
+    /// shell, as for `-A`.  Defaults to `false`.
+    #[serde(default)]
+    #[getset(get_copy = "pub(crate)")]
+    forward_agent: bool,
+    /// Relay key agreement through the forwarded agent as well, as for
+    /// `--forward-agent-agree`, so X25519, P-256 and P-384 identities can
+    /// authenticate onward from the remote host.  Anyone who can reach the
+    /// forwarded socket can then agree keys with every loaded identity.
+    /// Defaults to `false`.
+    #[serde(default)]
+    #[getset(get_copy = "pub(crate)")]
+    forward_agent_agree: bool,
+    /// Command to run on the server in place of the login shell, as the words
+    /// after the destination on the command line.  Empty (the default) starts
+    /// a login shell.
+    #[serde(default)]
+    #[getset(get = "pub(crate)")]
+    remote_command: Vec<String>,
+    /// Run `remote_command` without a PTY, as for `-T`.  Defaults to `false`.
+    #[serde(default)]
+    #[getset(get_copy = "pub(crate)")]
+    no_pty: bool,
+    /// The server's half of an `mp cp`, run in place of `remote_command`
+    /// (not persisted to config file).
+    #[serde(skip)]
+    #[getset(get = "pub(crate)", set = "pub(crate)")]
+    file_copy: Option<FileCopy>,
+    /// Name of the session to resume, or start when there is none, as for
+    /// `--session`.  Unset (the default) resumes the last session with this
+    /// server instead.
+    #[serde(default)]
+    #[getset(get = "pub(crate)")]
+    session: Option<String>,
+    /// What `mp ls` or `mp attach` asks of the server's sessions, in place of
+    /// `session` (not persisted to config file).
+    #[serde(skip)]
+    #[getset(get = "pub(crate)", set = "pub(crate)")]
+    session_request: Option<SessionRequest>,
+    /// File to record what the client draws to, for `mp replay`, as for
+    /// `--record`.  Unset (the default) records nothing.
+    #[serde(default)]
+    #[getset(get = "pub(crate)")]
+    record: Option<String>,
 }
 
 impl Config {
@@ -158,6 +253,16 @@ impl Config {
         40404
     }
 
+    /// Key the server's `known_hosts` entry by `destination`.
+    pub(crate) fn set_known_host(&mut self, destination: &ServerDestination) -> &mut Self {
+        self.known_host = if destination.port() == Self::default_server_port() {
+            destination.host().clone()
+        } else {
+            destination.to_string()
+        };
+        self
+    }
+
     fn default_max_reconnect_backoff_secs() -> u64 {
         3600
     }
@@ -174,6 +279,34 @@ impl Config {
         "ctrl-^".to_string()
     }
 
+    /// The data-channel transport to request during key exchange.
+    pub(crate) fn transport(&self) -> libmoshpit::TransportMode {
+        match self.transport {
+            TransportPref::Udp | TransportPref::UdpOnly => libmoshpit::TransportMode::Udp,
+            TransportPref::Tcp => libmoshpit::TransportMode::Tcp,
+        }
+    }
+
+    /// Whether a remote command is to run without a PTY, with its standard
+    /// streams carried on channels of their own.  A file copy always is.
+    pub(crate) fn piped_command(&self) -> bool {
+        self.file_copy.is_some()
+            || self.session_request == Some(SessionRequest::List)
+            || (!self.remote_command.is_empty() && self.no_pty)
+    }
+
+    /// Whether this connects to the session saved for the server, whose resume
+    /// state is kept on disk.  Remote commands and named sessions keep theirs
+    /// in memory instead.
+    pub(crate) fn saved_session(&self) -> bool {
+        KexConfig::remote_command(self).is_none() && KexConfig::session_request(self).is_none()
+    }
+
+    /// Whether the key exchange runs over UDP datagrams instead of TCP.
+    pub(crate) fn udp_only(&self) -> bool {
+        self.transport == TransportPref::UdpOnly
+    }
+
     /// Resolve the configured [`DiffModePref`] to a concrete [`DiffMode`].
     ///
     /// `Auto` picks `StateSync` over the TCP transport (incremental diffs keep
@@ -184,7 +317,7 @@ impl Config {
             DiffModePref::Reliable => DiffMode::Reliable,
             DiffModePref::Datagram => DiffMode::Datagram,
             DiffModePref::Statesync => DiffMode::StateSync,
-            DiffModePref::Auto => match self.transport {
+            DiffModePref::Auto => match self.transport() {
                 libmoshpit::TransportMode::Tcp => DiffMode::StateSync,
                 libmoshpit::TransportMode::Udp => DiffMode::Reliable,
             },
@@ -214,20 +347,34 @@ impl Default for Config {
             tracing: ClientTracing::default(),
             server_port: Self::default_server_port(),
             server_destination: String::new(),
+            known_host: String::new(),
             private_key_path: None,
             public_key_path: None,
             resume_session_uuid: None,
+            resumption_ticket: None,
+            early_key_share_unsupported: false,
             max_reconnect_backoff_secs: Self::default_max_reconnect_backoff_secs(),
             predict: DisplayPreference::default(),
             nat_warmup: false,
             nat_warmup_count: Self::default_nat_warmup_count(),
             diff_mode: DiffModePref::default(),
-            transport: libmoshpit::TransportMode::default(),
+            transport: TransportPref::default(),
             legacy_passthrough: false,
             preferred_algorithms: AlgorithmPreferences::default(),
             send_env: Self::default_send_env(),
             send_path: Vec::new(),
             escape_key: Self::default_escape_key(),
+            local_forward: Vec::new(),
+            remote_forward: Vec::new(),
+            dynamic_forward: Vec::new(),
+            forward_agent: false,
+            forward_agent_agree: false,
+            remote_command: Vec::new(),
+            no_pty: false,
+            file_copy: None,
+            session: None,
+            session_request: None,
+            record: None,
         }
     }
 }
@@ -253,8 +400,31 @@ impl KexConfig for Config {
         self.resume_session_uuid
     }
 
+    fn resumption_ticket(&self) -> Option<ResumptionTicket> {
+        self.resumption_ticket.clone()
+    }
+
     fn server_id(&self) -> Option<String> {
-        Some(self.server_destination().clone())
+        Some(self.known_host().clone())
+    }
+
+    fn legacy_server_ids(&self) -> Vec<String> {
+        // Releases before host names were accepted keyed the server by the
+        // destination as typed, user included.
+        let typed = self.server_destination();
+        if typed.is_empty() || typed == self.known_host() {
+            Vec::new()
+        } else {
+            vec![typed.clone()]
+        }
+    }
+
+    fn early_key_share(&self) -> bool {
+        !self.early_key_share_unsupported
+    }
+
+    fn forward_agent(&self) -> bool {
+        self.forward_agent && KexConfig::agent_socket(self).is_some()
     }
 
     fn diff_mode(&self) -> DiffMode {
@@ -263,7 +433,7 @@ impl KexConfig for Config {
     }
 
     fn transport_preference(&self) -> libmoshpit::TransportMode {
-        self.transport
+        self.transport()
     }
 
     fn preferred_algorithms(&self) -> AlgorithmList {
@@ -281,6 +451,20 @@ impl KexConfig for Config {
     fn agent_socket(&self) -> Option<PathBuf> {
         var("MOSHPIT_AGENT_SOCK").ok().map(PathBuf::from)
     }
+
+    fn remote_command(&self) -> Option<RemoteCommand> {
+        if let Some(copy) = &self.file_copy {
+            return Some(RemoteCommand::copying(copy.clone()));
+        }
+        (!self.remote_command.is_empty())
+            .then(|| RemoteCommand::from_args(&self.remote_command, !self.no_pty))
+    }
+
+    fn session_request(&self) -> Option<SessionRequest> {
+        self.session_request
+            .clone()
+            .or_else(|| self.session.clone().map(SessionRequest::Named))
+    }
 }
 
 #[cfg(test)]
@@ -290,7 +474,7 @@ mod tests {
     use anyhow::Result;
     use uuid::Uuid;
 
-    use libmoshpit::{DiffMode, TransportMode};
+    use libmoshpit::{DiffMode, FileCopy, SessionRequest, TransportMode, parse_server_destination};
 
     use super::{Config, DisplayPreference, KexConfig, KexMode};
 
@@ -314,6 +498,21 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_transport_udp_only_from_toml() -> Result<()> {
+        let toml = r#"
+            private_key_path = "/tmp/priv"
+            public_key_path = "/tmp/pub"
+            transport = "udp-only"
+        "#;
+        let config: Config = toml::from_str(toml)?;
+        assert!(config.udp_only());
+        assert_eq!(config.transport_preference(), TransportMode::Udp);
+        assert_eq!(config.diff_mode(), DiffMode::Reliable);
+        assert!(!Config::default().udp_only());
+        Ok(())
+    }
+
     #[test]
     fn test_transport_absent_in_toml_defaults_udp() -> Result<()> {
         let toml = r#"
@@ -393,9 +592,84 @@ mod tests {
         assert_eq!(config.private_key_path(), &None);
         assert_eq!(config.public_key_path(), &None);
         assert_eq!(config.resume_session_uuid(), None);
+        assert_eq!(config.resumption_ticket(), &None);
         assert_eq!(config.max_reconnect_backoff_secs(), 3600);
         assert_eq!(config.predict(), DisplayPreference::default());
         assert_eq!(config.escape_key(), "ctrl-^");
+        assert!(config.local_forward().is_empty());
+        assert!(config.remote_forward().is_empty());
+        assert!(config.dynamic_forward().is_empty());
+        assert!(!config.forward_agent());
+        assert!(!config.forward_agent_agree());
+        assert!(config.remote_command().is_empty());
+        assert!(!config.no_pty());
+        assert!(KexConfig::remote_command(&config).is_none());
+        assert_eq!(config.session(), &None);
+        assert!(KexConfig::session_request(&config).is_none());
+        assert_eq!(config.record(), &None);
+    }
+
+    #[test]
+    fn session_names_the_session_unless_listing_or_attaching() {
+        let mut config = Config {
+            session: Some("work".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(
+            KexConfig::session_request(&config),
+            Some(SessionRequest::Named("work".to_string()))
+        );
+        assert!(!config.piped_command());
+
+        let _ = config.set_session_request(Some(SessionRequest::List));
+        assert_eq!(
+            KexConfig::session_request(&config),
+            Some(SessionRequest::List)
+        );
+        assert!(config.piped_command());
+        assert!(!config.saved_session());
+        assert!(Config::default().saved_session());
+    }
+
+    #[test]
+    fn remote_command_runs_on_a_pty_unless_told_otherwise() {
+        let mut config = Config {
+            remote_command: vec!["uname".to_string(), "-a".to_string()],
+            ..Config::default()
+        };
+        let command = KexConfig::remote_command(&config);
+        assert_eq!(
+            command.as_ref().map(|c| c.command().as_str()),
+            Some("uname -a")
+        );
+        assert_eq!(command.map(|c| c.pty()), Some(true));
+
+        config.no_pty = true;
+        assert_eq!(
+            KexConfig::remote_command(&config).map(|c| c.pty()),
+            Some(false)
+        );
+        assert!(config.piped_command());
+        assert!(!Config::default().piped_command());
+    }
+
+    #[test]
+    fn file_copy_replaces_the_remote_command() {
+        let mut config = Config {
+            remote_command: vec!["uname".to_string()],
+            ..Config::default()
+        };
+        let copy = FileCopy::Upload {
+            target: "docs".to_string(),
+        };
+        let _ = config.set_file_copy(Some(copy.clone()));
+        let command = KexConfig::remote_command(&config);
+        assert_eq!(
+            command.as_ref().and_then(|c| c.file_copy().clone()),
+            Some(copy)
+        );
+        assert_eq!(command.map(|c| c.pty()), Some(false));
+        assert!(config.piped_command());
     }
 
     #[test]
@@ -416,6 +690,52 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn early_key_share_until_the_server_turns_out_too_old() {
+        let mut config = Config::default();
+        assert!(KexConfig::early_key_share(&config));
+        let _ = config.set_early_key_share_unsupported(true);
+        assert!(!KexConfig::early_key_share(&config));
+    }
+
+    #[test]
+    fn server_id_is_typed_host() -> Result<()> {
+        let mut config = Config::default();
+        let _ = config.set_known_host(&parse_server_destination("alice@build01.corp", 40404)?);
+        assert_eq!(
+            KexConfig::server_id(&config).as_deref(),
+            Some("build01.corp")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn server_id_keeps_a_non_default_port() -> Result<()> {
+        let mut config = Config::default();
+        let _ = config.set_known_host(&parse_server_destination("build01.corp:2222", 40404)?);
+        assert_eq!(
+            KexConfig::server_id(&config).as_deref(),
+            Some("build01.corp:2222")
+        );
+        let _ = config.set_known_host(&parse_server_destination("[2001:db8::1]:2222", 40404)?);
+        assert_eq!(
+            KexConfig::server_id(&config).as_deref(),
+            Some("[2001:db8::1]:2222")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_server_id_is_the_typed_destination() -> Result<()> {
+        let mut config = Config::default();
+        let _ = config.set_server_destination("alice@192.0.2.1".to_string());
+        let _ = config.set_known_host(&parse_server_destination("alice@192.0.2.1", 40404)?);
+        assert_eq!(KexConfig::legacy_server_ids(&config), ["alice@192.0.2.1"]);
+        let _ = config.set_server_destination("192.0.2.1".to_string());
+        assert!(KexConfig::legacy_server_ids(&config).is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_load_key_paths() -> Result<()> {
         // Without explicit paths, it should fall back to default
@@ -0,0 +1,303 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! The client's half of `mp cp`: which side of the copy is remote, and the
+//! local end of the transfer, run over the session's standard-stream
+//! channels while the server runs its copy helper.
+
+use std::{
+    io::{IsTerminal as _, stderr},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use getset::Getters;
+use libmoshpit::{FileCopy, ForwardMux, MoshpitError, receive_files, send_files};
+use tokio::{io::duplex, spawn, task::JoinHandle};
+
+/// Bytes buffered in each direction between the copy and the session.
+const PIPE_BUFFER: usize = 256 * 1024;
+
+/// Shortest time between two progress updates of the same file.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What `mp cp` was asked to do.
+#[derive(Clone, Debug, Eq, Getters, PartialEq)]
+pub(crate) struct CopyPlan {
+    /// The `[user@]host` to copy to or from.
+    #[getset(get = "pub(crate)")]
+    destination: String,
+    /// The server's half of the copy.
+    #[getset(get = "pub(crate)")]
+    file_copy: FileCopy,
+    /// Our half of the copy.
+    local: LocalEnd,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum LocalEnd {
+    Send {
+        sources: Vec<PathBuf>,
+        recursive: bool,
+    },
+    Receive {
+        target: PathBuf,
+    },
+}
+
+impl CopyPlan {
+    /// Work out the copy from the `mp cp` operands: sources followed by the
+    /// destination, with the remote ones written `[user@]host:path`.
+    ///
+    /// # Errors
+    /// * [`MoshpitError::InvalidCopyOperands`] unless every source is local
+    ///   and the destination remote, or every source is on one host and the
+    ///   destination local.
+    pub(crate) fn parse(paths: &[String], recursive: bool) -> Result<Self> {
+        let Some((destination, sources)) = paths.split_last() else {
+            return Err(MoshpitError::InvalidCopyOperands.into());
+        };
+        if sources.is_empty() {
+            return Err(MoshpitError::InvalidCopyOperands.into());
+        }
+        let remote_sources: Vec<_> = sources.iter().filter_map(|s| split_remote(s)).collect();
+        match split_remote(destination) {
+            Some((host, target)) if remote_sources.is_empty() => Ok(Self {
+                destination: host.to_string(),
+                file_copy: FileCopy::Upload {
+                    target: target.to_string(),
+                },
+                local: LocalEnd::Send {
+                    sources: sources.iter().map(PathBuf::from).collect(),
+                    recursive,
+                },
+            }),
+            None if remote_sources.len() == sources.len()
+                && remote_sources
+                    .iter()
+                    .all(|(host, _)| *host == remote_sources[0].0) =>
+            {
+                Ok(Self {
+                    destination: remote_sources[0].0.to_string(),
+                    file_copy: FileCopy::Download {
+                        sources: remote_sources
+                            .iter()
+                            .map(|(_, path)| (*path).to_string())
+                            .collect(),
+                        recursive,
+                    },
+                    local: LocalEnd::Receive {
+                        target: PathBuf::from(destination),
+                    },
+                })
+            }
+            _ => Err(MoshpitError::InvalidCopyOperands.into()),
+        }
+    }
+
+    /// Serve the server's standard input and output from our half of the
+    /// copy, which runs in the background from now on, reporting progress on
+    /// standard error when it is a terminal.  Standard error stays our own,
+    /// where the server's half reports what it could not copy.
+    pub(crate) async fn start(self, mux: &ForwardMux) -> JoinHandle<()> {
+        // We write to `to_server`, which is the helper's standard input, and
+        // read its standard output from `from_server`.
+        let (to_server, stdin_source) = duplex(PIPE_BUFFER);
+        let (stdout_sink, from_server) = duplex(PIPE_BUFFER);
+        mux.provide_stdio(0, Some(Box::new(stdin_source)), None)
+            .await;
+        mux.provide_stdio(1, None, Some(Box::new(stdout_sink)))
+            .await;
+        spawn(async move {
+            let mut progress = Progress::new(stderr().is_terminal());
+            let update = |name: &str, done, size| progress.update(name, done, size);
+            let outcome = match self.local {
+                LocalEnd::Send { sources, recursive } => {
+                    send_files(&sources, recursive, from_server, to_server, update).await
+                }
+                LocalEnd::Receive { target } => {
+                    receive_files(&target, from_server, to_server, update).await
+                }
+            };
+            match outcome {
+                Ok(outcome) => {
+                    for error in outcome.errors() {
+                        eprintln!("mp cp: {error}");
+                    }
+                    match outcome.not_copied() {
+                        0 => {}
+                        failed => eprintln!("mp cp: {failed} entries not copied"),
+                    }
+                }
+                Err(e) => eprintln!("mp cp: {e:#}"),
+            }
+        })
+    }
+}
+
+/// Split a remote operand into its `[user@]host` and path.  It is remote
+/// when a colon comes before any slash, outside an IPv6 literal's brackets;
+/// on Windows a single letter before the colon is a drive instead.
+fn split_remote(operand: &str) -> Option<(&str, &str)> {
+    let host_start = operand.find('@').map_or(0, |at| at + 1);
+    let search_from = if operand[host_start..].starts_with('[') {
+        host_start + operand[host_start..].find(']')?
+    } else {
+        host_start
+    };
+    let colon = search_from + operand[search_from..].find(':')?;
+    let (host, path) = (&operand[..colon], &operand[colon + 1..]);
+    if host.is_empty() || host.contains('/') {
+        return None;
+    }
+    if cfg!(windows) && host.len() == 1 && host.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some((host, path))
+}
+
+/// A one-line progress meter for the file being copied.
+#[derive(Debug)]
+struct Progress {
+    enabled: bool,
+    last: Option<Instant>,
+}
+
+impl Progress {
+    fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            last: None,
+        }
+    }
+
+    /// Show that `done` of `size` bytes of `name` are copied, at most every
+    /// [`PROGRESS_INTERVAL`], ending the line once the file is complete.
+    fn update(&mut self, name: &str, done: u64, size: u64) {
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        let complete = done >= size;
+        if !complete
+            && self
+                .last
+                .is_some_and(|last| now.duration_since(last) < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        let percent = if size == 0 {
+            100
+        } else {
+            u128::from(done) * 100 / u128::from(size)
+        };
+        eprint!(
+            "\r\x1b[K{name}  {percent:>3}%  {} / {}",
+            human_bytes(done),
+            human_bytes(size)
+        );
+        if complete {
+            eprintln!();
+            self.last = None;
+        } else {
+            self.last = Some(now);
+        }
+    }
+}
+
+/// `bytes` in the largest binary unit that keeps it at least 1.
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut unit = 0;
+    let mut scaled = bytes;
+    let mut tenths = 0;
+    while scaled >= 1024 && unit + 1 < UNITS.len() {
+        tenths = (scaled % 1024) * 10 / 1024;
+        scaled /= 1024;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{scaled}.{tenths} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+    use libmoshpit::FileCopy;
+
+    use super::{CopyPlan, LocalEnd, human_bytes, split_remote};
+
+    fn operands(words: &[&str]) -> Vec<String> {
+        words.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn remote_operands_are_recognised() {
+        assert_eq!(split_remote("host:docs"), Some(("host", "docs")));
+        assert_eq!(split_remote("me@host:"), Some(("me@host", "")));
+        assert_eq!(
+            split_remote("me@[::1]:/tmp/x"),
+            Some(("me@[::1]", "/tmp/x"))
+        );
+        assert_eq!(split_remote("notes.txt"), None);
+        assert_eq!(split_remote("./a:b"), None);
+        assert_eq!(split_remote("/srv/a:b"), None);
+        assert_eq!(split_remote(":x"), None);
+    }
+
+    #[test]
+    fn uploads_send_local_sources_to_the_remote_destination() -> Result<()> {
+        let plan = CopyPlan::parse(&operands(&["a", "b", "me@host:in"]), true)?;
+        assert_eq!(plan.destination(), "me@host");
+        assert_eq!(
+            plan.file_copy(),
+            &FileCopy::Upload {
+                target: "in".to_string()
+            }
+        );
+        assert_eq!(
+            plan.local,
+            LocalEnd::Send {
+                sources: vec![PathBuf::from("a"), PathBuf::from("b")],
+                recursive: true
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn downloads_need_every_source_on_one_host() -> Result<()> {
+        let plan = CopyPlan::parse(&operands(&["host:a", "host:b", "."]), false)?;
+        assert_eq!(plan.destination(), "host");
+        assert_eq!(
+            plan.file_copy(),
+            &FileCopy::Download {
+                sources: vec!["a".to_string(), "b".to_string()],
+                recursive: false
+            }
+        );
+        assert!(CopyPlan::parse(&operands(&["host:a", "other:b", "."]), false).is_err());
+        assert!(CopyPlan::parse(&operands(&["host:a", "b", "."]), false).is_err());
+        assert!(CopyPlan::parse(&operands(&["a", "b"]), false).is_err());
+        assert!(CopyPlan::parse(&operands(&["host:a", "host:b"]), false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sizes_are_shown_in_binary_units() {
+        assert_eq!(human_bytes(512), "512 B");
+        assert_eq!(human_bytes(1536), "1.5 KiB");
+        assert_eq!(human_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+}
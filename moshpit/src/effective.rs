@@ -184,8 +184,8 @@ impl Ctx<'_> {
 ///
 /// Path rows (`config_path`, `tracing_path`) consult only the CLI flag and the
 /// default — path resolution never reads the environment.  The
-/// `preferred_algorithms.*` rows and the list fields (`send_env`, `send_path`)
-/// are not settable via a single env var, so they pass `None` for the env
+/// `preferred_algorithms.*` rows and the list fields (`send_env`, `send_path`,
+/// `local_forward`, `remote_forward`, `dynamic_forward`, `remote_command`) are not settable via a single env var, so they pass `None` for the env
 /// signal.
 #[allow(clippy::too_many_lines)] // a flat enumeration of every config field
 pub(crate) fn resolve_effective(
@@ -343,6 +343,69 @@ pub(crate) fn resolve_effective(
             None,
             Some("send_path"),
         ),
+        ctx.row(
+            "local_forward",
+            list(config.local_forward()),
+            Some("local_forward"),
+            None,
+            Some("local_forward"),
+        ),
+        ctx.row(
+            "remote_forward",
+            list(config.remote_forward()),
+            Some("remote_forward"),
+            None,
+            Some("remote_forward"),
+        ),
+        ctx.row(
+            "dynamic_forward",
+            list(config.dynamic_forward()),
+            Some("dynamic_forward"),
+            None,
+            Some("dynamic_forward"),
+        ),
+        ctx.row(
+            "forward_agent",
+            config.forward_agent().to_string(),
+            Some("forward_agent"),
+            Some("FORWARD_AGENT"),
+            Some("forward_agent"),
+        ),
+        ctx.row(
+            "forward_agent_agree",
+            config.forward_agent_agree().to_string(),
+            Some("forward_agent_agree"),
+            Some("FORWARD_AGENT_AGREE"),
+            Some("forward_agent_agree"),
+        ),
+        ctx.row(
+            "remote_command",
+            list(config.remote_command()),
+            Some("remote_command"),
+            None,
+            Some("remote_command"),
+        ),
+        ctx.row(
+            "no_pty",
+            config.no_pty().to_string(),
+            Some("no_pty"),
+            Some("NO_PTY"),
+            Some("no_pty"),
+        ),
+        ctx.row(
+            "session",
+            opt(config.session().as_deref()),
+            Some("session"),
+            Some("SESSION"),
+            Some("session"),
+        ),
+        ctx.row(
+            "record",
+            opt(config.record().as_deref()),
+            Some("record"),
+            Some("RECORD"),
+            Some("record"),
+        ),
         ctx.row("tracing", tracing, None, None, Some("tracing")),
     ]
 }
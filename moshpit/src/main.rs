@@ -239,7 +239,9 @@ use crate::runtime::run;
 
 mod cli;
 mod config;
+mod copy;
 mod effective;
+mod replay;
 mod runtime;
 
 #[cfg_attr(coverage_nightly, coverage(off))]
@@ -0,0 +1,303 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! `mp replay`: play a recording made with `--record` back in the terminal.
+//!
+//! Every frame goes through a [`Renderer`] just as it did while recording, so
+//! the terminal shows what the user saw, prediction overlays included when
+//! asked for.
+
+use std::{
+    io::{Write, stdout},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context as _, Result, bail};
+use crossterm::{
+    event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, poll, read},
+    terminal::{disable_raw_mode, enable_raw_mode},
+};
+use libmoshpit::{Playback, RecordedFrame, Renderer, read_recording};
+
+/// How far the arrow keys seek.
+const SEEK_STEP: Duration = Duration::from_secs(5);
+
+/// Slowest and fastest playback speeds the speed keys reach.
+const MIN_SPEED: f64 = 1.0 / 64.0;
+const MAX_SPEED: f64 = 64.0;
+
+/// Longest wait for a key before looking at the clock again.
+const IDLE_POLL: Duration = Duration::from_millis(250);
+
+/// Play the recording at `path` at `speed` times the recorded speed, painting
+/// the recorded prediction overlays if `predictions`.
+///
+/// # Errors
+/// * `speed` is not a positive number.
+/// * The recording cannot be read or holds no frames.
+/// * The terminal cannot be read or written.
+pub(crate) fn run(path: &Path, speed: f64, predictions: bool) -> Result<()> {
+    if !(speed.is_finite() && speed > 0.0) {
+        bail!("--speed must be a positive number, got {speed}");
+    }
+    let frames = read_recording(path)
+        .with_context(|| format!("cannot read recording {}", path.display()))?;
+    let Some(start) = frames.first().map(RecordedFrame::at) else {
+        bail!("{} holds no frames", path.display());
+    };
+    let mut player = Player {
+        playback: Playback::new(frames),
+        start,
+        renderer: Renderer::new(24, 80),
+        size: (24, 80),
+        predictions,
+        clock: Clock::new(speed.clamp(MIN_SPEED, MAX_SPEED), start, Instant::now()),
+    };
+
+    enable_raw_mode()?;
+    let mut out = stdout();
+    let result = player.play(&mut out);
+    // Leave alt-screen, show cursor, clear the visible screen, home + reset SGR.
+    drop(out.write_all(b"\x1b[?1049l\x1b[?25h\x1b[2J\x1b[H\x1b[0m"));
+    drop(out.flush());
+    drop(disable_raw_mode());
+    result
+}
+
+/// The recording time on show: runs at `speed` times real time unless paused.
+#[derive(Clone, Copy, Debug)]
+struct Clock {
+    /// The recording time at `since`.
+    base: Duration,
+    since: Instant,
+    speed: f64,
+    paused: bool,
+}
+
+impl Clock {
+    fn new(speed: f64, at: Duration, now: Instant) -> Self {
+        Self {
+            base: at,
+            since: now,
+            speed,
+            paused: false,
+        }
+    }
+
+    /// The recording time at `now`.
+    fn at(&self, now: Instant) -> Duration {
+        if self.paused {
+            self.base
+        } else {
+            self.base + now.duration_since(self.since).mul_f64(self.speed)
+        }
+    }
+
+    /// How long until the recording reaches `at`, or `None` while paused.
+    fn until(&self, at: Duration, now: Instant) -> Option<Duration> {
+        (!self.paused).then(|| at.saturating_sub(self.at(now)).div_f64(self.speed))
+    }
+
+    /// Move to recording time `at` as of `now`.
+    fn set(&mut self, at: Duration, now: Instant) {
+        self.base = at;
+        self.since = now;
+    }
+
+    fn set_paused(&mut self, paused: bool, now: Instant) {
+        let at = self.at(now);
+        self.paused = paused;
+        self.set(at, now);
+    }
+
+    fn set_speed(&mut self, speed: f64, now: Instant) {
+        let at = self.at(now);
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        self.set(at, now);
+    }
+}
+
+/// What a key asks of the player.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Control {
+    TogglePause,
+    Step,
+    Back,
+    Forward,
+    Faster,
+    Slower,
+    Quit,
+}
+
+impl Control {
+    fn from_key(key: KeyEvent) -> Option<Self> {
+        if key.kind == KeyEventKind::Release {
+            return None;
+        }
+        Some(match key.code {
+            KeyCode::Char(' ') => Self::TogglePause,
+            KeyCode::Char('.') => Self::Step,
+            KeyCode::Left => Self::Back,
+            KeyCode::Right => Self::Forward,
+            KeyCode::Up | KeyCode::Char('+' | '=') => Self::Faster,
+            KeyCode::Down | KeyCode::Char('-') => Self::Slower,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Self::Quit,
+            KeyCode::Char('q') | KeyCode::Esc => Self::Quit,
+            _ => return None,
+        })
+    }
+}
+
+struct Player {
+    playback: Playback,
+    /// When the first frame was drawn; seeking never goes further back.
+    start: Duration,
+    renderer: Renderer,
+    /// The screen size `renderer` draws at.
+    size: (u16, u16),
+    predictions: bool,
+    clock: Clock,
+}
+
+impl Player {
+    fn play(&mut self, out: &mut impl Write) -> Result<()> {
+        loop {
+            let now = Instant::now();
+            if self.playback.seek(self.clock.at(now)) {
+                self.draw(out)?;
+            }
+            let next = self.playback.next_at();
+            if next.is_none() && !self.clock.paused {
+                // Hold the last frame, so it can be looked at or seeked back from.
+                self.clock.set_paused(true, now);
+                self.clock.set(self.playback.duration(), now);
+            }
+            let wait = next
+                .and_then(|next| self.clock.until(next, now))
+                .map_or(IDLE_POLL, |wait| wait.min(IDLE_POLL));
+            if !poll(wait)? {
+                continue;
+            }
+            let Event::Key(key) = read()? else {
+                continue;
+            };
+            match Control::from_key(key) {
+                Some(Control::Quit) => return Ok(()),
+                Some(control) => self.control(control, out)?,
+                None => {}
+            }
+        }
+    }
+
+    fn control(&mut self, control: Control, out: &mut impl Write) -> Result<()> {
+        let now = Instant::now();
+        let at = self.clock.at(now);
+        match control {
+            Control::TogglePause => {
+                if self.playback.next_at().is_none() {
+                    // Play again from the start.
+                    self.clock.set(self.start, now);
+                }
+                self.clock.set_paused(!self.clock.paused, now);
+            }
+            Control::Step => {
+                if self.clock.paused && self.playback.step() {
+                    self.clock.set(self.playback.position(), now);
+                    self.draw(out)?;
+                }
+            }
+            Control::Back => self
+                .clock
+                .set(at.saturating_sub(SEEK_STEP).max(self.start), now),
+            Control::Forward => self
+                .clock
+                .set((at + SEEK_STEP).min(self.playback.duration()), now),
+            Control::Faster => self.clock.set_speed(self.clock.speed * 2.0, now),
+            Control::Slower => self.clock.set_speed(self.clock.speed / 2.0, now),
+            Control::Quit => {}
+        }
+        Ok(())
+    }
+
+    /// Draw the frame on show.
+    fn draw(&mut self, out: &mut impl Write) -> Result<()> {
+        let screen = self.playback.screen();
+        if screen.size() != self.size {
+            self.size = screen.size();
+            self.renderer.set_size(self.size.0, self.size.1);
+        }
+        let bytes = if self.predictions {
+            self.renderer
+                .render(screen, self.playback.overlays(), self.playback.cursor())
+        } else {
+            self.renderer.render(screen, &[], None)
+        };
+        out.write_all(&bytes)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use super::{Clock, Control, MAX_SPEED};
+
+    #[test]
+    fn clock_runs_at_speed_and_stops_when_paused() {
+        let start = Instant::now();
+        let mut clock = Clock::new(2.0, Duration::from_secs(1), start);
+        let later = start + Duration::from_secs(3);
+        assert_eq!(clock.at(later), Duration::from_secs(7));
+        assert_eq!(
+            clock.until(Duration::from_secs(9), later),
+            Some(Duration::from_secs(1))
+        );
+
+        clock.set_paused(true, later);
+        let much_later = later + Duration::from_mins(1);
+        assert_eq!(clock.at(much_later), Duration::from_secs(7));
+        assert_eq!(clock.until(Duration::from_secs(9), much_later), None);
+
+        clock.set_paused(false, much_later);
+        clock.set_speed(1000.0, much_later);
+        assert!((clock.speed - MAX_SPEED).abs() < f64::EPSILON);
+        assert_eq!(
+            clock.at(much_later + Duration::from_secs(1)),
+            Duration::from_secs(71)
+        );
+    }
+
+    #[test]
+    fn keys_map_to_controls() {
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        assert_eq!(
+            Control::from_key(key(KeyCode::Char(' '))),
+            Some(Control::TogglePause)
+        );
+        assert_eq!(Control::from_key(key(KeyCode::Left)), Some(Control::Back));
+        assert_eq!(
+            Control::from_key(key(KeyCode::Char('+'))),
+            Some(Control::Faster)
+        );
+        assert_eq!(Control::from_key(key(KeyCode::Down)), Some(Control::Slower));
+        assert_eq!(
+            Control::from_key(key(KeyCode::Char('q'))),
+            Some(Control::Quit)
+        );
+        assert_eq!(
+            Control::from_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            Some(Control::Quit)
+        );
+        assert_eq!(Control::from_key(key(KeyCode::Char('c'))), None);
+    }
+}
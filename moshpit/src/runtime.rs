@@ -11,8 +11,8 @@ use std::{
     error::Error,
     ffi::OsString,
     fmt::{Display, Formatter, Result as FmtResult},
-    fs::{DirBuilder, File, OpenOptions, create_dir_all},
-    io::{Read as _, Write as _, stdin, stdout},
+    fs::{DirBuilder, File, OpenOptions, create_dir_all, read, remove_file},
+    io::{ErrorKind, Read as _, Write as _, stdin, stdout},
     net::SocketAddr,
     path::{Path, PathBuf},
     process::exit,
@@ -31,17 +31,24 @@ use anyhow::{Context as _, Result, bail};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
 use dialoguer::{Confirm, Password};
 use libmoshpit::{
-    ClientRenderCtx, DiffMode, DisplayPreference, Emulator, EncryptedFrame, FileLayer,
-    KEY_ALGORITHM_X25519, Kex, KexConfig as _, KexMode, KeyPair, MoshpitError, NegotiatedTransport,
-    PredictionEngine, Renderer, TcpTransportReader, TcpTransportSender, UdpReader, UdpSender,
-    UuidWrapper, config_file_path, init_tracing, load, paint_overlays_to_ansi,
-    parse_server_destination, render_prediction_update, run_key_exchange,
+    AGENT_FORWARDING_MIN_PROTOCOL_VERSION, ClientRenderCtx, ConnectionReader, ConnectionWriter,
+    DYNAMIC_FORWARDING_MIN_PROTOCOL_VERSION, DiffMode, DisplayPreference, Emulator, EncryptedFrame,
+    ExitStatus, FILE_COPY_MIN_PROTOCOL_VERSION, FileLayer, ForwardMux, ForwardPolicy, ForwardRole,
+    KEY_ALGORITHM_X25519, Kex, KexConfig, KexFailureReason, KexMode, KeyDirection, KeyPair,
+    MoshpitError, NAMED_SESSIONS_MIN_PROTOCOL_VERSION, NegotiatedTransport,
+    PORT_FORWARDING_MIN_PROTOCOL_VERSION, PredictionEngine, REMOTE_COMMAND_MIN_PROTOCOL_VERSION,
+    REMOTE_FORWARDING_MIN_PROTOCOL_VERSION, Renderer, ResumptionTicket,
+    SESSION_SHARING_MIN_PROTOCOL_VERSION, ScreenRecorder, ServerDestination, SessionRequest,
+    TcpTransportReader, TcpTransportSender, UNIX_FORWARDING_MIN_PROTOCOL_VERSION, UdpReader,
+    UdpSender, UuidWrapper, config_file_path, connect_happy_eyeballs, connect_udp_handshake,
+    init_tracing, load, paint_overlays_to_ansi, parse_dynamic_forward_spec, parse_forward_spec,
+    parse_server_destination, render_prediction_update, run_key_exchange_over,
 };
 use terminal_size::terminal_size;
 #[cfg(unix)]
 use tokio::signal::unix::{SignalKind, signal};
 use tokio::{
-    net::{TcpStream, UdpSocket},
+    net::UdpSocket,
     select, spawn,
     sync::{
         Mutex,
@@ -58,7 +65,8 @@ use uuid::Uuid;
 use crate::{
     cli::{Cli, Commands},
     config::Config,
-    effective,
+    copy::CopyPlan,
+    effective, replay,
 };
 
 #[cfg_attr(coverage_nightly, coverage(off))]
@@ -90,8 +98,48 @@ where
         return Ok(());
     }
 
+    // `mp replay`: play a recording back in this terminal; no server involved.
+    if let Some(Commands::Replay {
+        speed,
+        predictions,
+        file,
+    }) = cli.command()
+    {
+        return replay::run(Path::new(file), *speed, *predictions);
+    }
+
+    // `mp cp`: connect to the host named by the remote operands and run the
+    // server's copy helper in place of a shell.
+    let copy = match cli.command() {
+        Some(Commands::Cp { recursive, paths }) => Some(CopyPlan::parse(paths, *recursive)?),
+        _ => None,
+    };
+    // `mp ls` and `mp attach`: connect to the named host to list the user's
+    // sessions there, or to attach to one of them by name.
+    let session_request = match cli.command() {
+        Some(Commands::Ls { destination }) => Some((destination.clone(), SessionRequest::List)),
+        Some(Commands::Attach {
+            shared,
+            read_only,
+            destination,
+            name,
+        }) => Some((
+            destination.clone(),
+            attach_request(name, *shared, *read_only)?,
+        )),
+        _ => None,
+    };
+
     let mut config =
         load::<Cli, Config, Cli>(&cli, &cli, false).with_context(|| MoshpitError::ConfigLoad)?;
+    if let Some(plan) = &copy {
+        let _ = config.set_server_destination(plan.destination().clone());
+        let _ = config.set_file_copy(Some(plan.file_copy().clone()));
+    }
+    if let Some((destination, request)) = session_request {
+        let _ = config.set_server_destination(destination);
+        let _ = config.set_session_request(Some(request));
+    }
     init_tracing(&FileLayer::default(), config.tracing().file(), &cli, None)
         .with_context(|| MoshpitError::TracingInit)?;
     maybe_generate_keypair(&config)?;
@@ -101,17 +149,159 @@ where
             "a server destination is required, e.g. `mp user@host` (run `mp ec` to inspect config)"
         );
     }
+    if config.session().is_some() && KexConfig::remote_command(&config).is_some() {
+        bail!("a named session runs a login shell; --session cannot be given a remote command");
+    }
+    if config.record().is_some() && (config.legacy_passthrough() || config.piped_command()) {
+        bail!(
+            "--record needs a rendered terminal; it cannot be used with --legacy-passthrough or --no-pty"
+        );
+    }
+    if let Some(SessionRequest::Join { read_only, .. }) = config.session_request().clone() {
+        if !config.local_forward().is_empty()
+            || !config.remote_forward().is_empty()
+            || !config.dynamic_forward().is_empty()
+        {
+            bail!("a shared session carries no port forwards; attach without --shared to use them");
+        }
+        // Keystrokes are dropped by the server, so never echo them locally.
+        if read_only {
+            let _ = config.set_predict(DisplayPreference::Never);
+        }
+    }
     // Resolve and validate the force-quit prefix key up front so a bad value
     // fails fast with a clear message instead of mid-session.
     let escape_byte = parse_escape_key(config.escape_key())
         .with_context(|| format!("invalid escape_key {:?}", config.escape_key()))?;
-    let (user, socket_addr) =
-        parse_server_destination(config.server_destination(), config.server_port())?;
-    let server_ip = socket_addr.ip().to_string();
-    let server_port = config.server_port();
-    let _ = config.set_user(user);
+    let destination = parse_server_destination(config.server_destination(), config.server_port())?;
+    let _ = config.set_user(destination.user().clone());
+    let _ = config.set_known_host(&destination);
+    let forwards = start_forwards(&config).await?;
+    if let (Some(plan), Some(mux)) = (copy, &forwards) {
+        let _copy = plan.start(mux).await;
+    }
+
+    run_session_loop(config, destination, escape_byte, forwards).await
+}
+
+/// The request `mp attach` sends for `name`: a plain attach takes the session
+/// over, while `--shared`, `--read-only` or an `owner/` prefix joins it
+/// alongside its other clients.
+fn attach_request(name: &str, shared: bool, read_only: bool) -> Result<SessionRequest> {
+    let (owner, session) = match name.split_once('/') {
+        Some((owner, session)) => (Some(owner), session),
+        None => (None, name),
+    };
+    if session.is_empty() || owner.is_some_and(str::is_empty) {
+        bail!("expected a session NAME or OWNER/NAME, got {name:?}");
+    }
+    Ok(if shared || read_only || owner.is_some() {
+        SessionRequest::Join {
+            owner: owner.map(str::to_string),
+            name: session.to_string(),
+            read_only,
+        }
+    } else {
+        SessionRequest::Attach(session.to_string())
+    })
+}
+
+/// Parse the configured `local_forward` and `dynamic_forward` specs and start
+/// listening on each, and queue a listen request with the server for each
+/// `remote_forward` spec.
+///
+/// Returns `None` when no forwards are configured.  The listeners accept
+/// connections from the start; they and the listen requests are carried to the
+/// server once a session that negotiates forwarding attaches to the returned
+/// mux.  The server never gets to open channels of its choosing: only the
+/// targets named by `remote_forward` are dialed, with `forward_agent` the
+/// local agent, for the requests that use a loaded key, and for a remote
+/// command run without a PTY our own standard streams, or for `mp cp` the
+/// local end of the copy in place of standard input and output.
+async fn start_forwards(config: &Config) -> Result<Option<ForwardMux>> {
+    if config.local_forward().is_empty()
+        && config.remote_forward().is_empty()
+        && config.dynamic_forward().is_empty()
+        && !config.forward_agent()
+        && !config.piped_command()
+    {
+        return Ok(None);
+    }
+    let agent_socket = if config.forward_agent() {
+        let socket = KexConfig::agent_socket(config);
+        if socket.is_none() {
+            warn!("forward_agent is set but MOSHPIT_AGENT_SOCK is not; no agent to forward");
+        }
+        socket
+    } else {
+        None
+    };
+    let policy = ForwardPolicy::builder()
+        .maybe_agent_socket(agent_socket)
+        .allow_agent_agree(config.forward_agent_agree())
+        .allow_stdio(config.piped_command())
+        .build();
+    let mux = ForwardMux::spawn(ForwardRole::Client, policy);
+    for spec in config.local_forward() {
+        let parsed =
+            parse_forward_spec(spec).with_context(|| format!("invalid local_forward {spec:?}"))?;
+        if parsed.bind_path().is_some() {
+            let path = mux
+                .listen_unix(&parsed)
+                .with_context(|| format!("cannot listen for local_forward {spec:?}"))?;
+            info!("forwarding {} to {}", path.display(), parsed.target());
+            continue;
+        }
+        let bound = mux
+            .listen(&parsed)
+            .await
+            .with_context(|| format!("cannot listen for local_forward {spec:?}"))?;
+        for addr in bound {
+            info!("forwarding {addr} to {}", parsed.target());
+        }
+    }
+    for spec in config.dynamic_forward() {
+        let parsed = parse_dynamic_forward_spec(spec)
+            .with_context(|| format!("invalid dynamic_forward {spec:?}"))?;
+        let bound = mux
+            .listen_socks(&parsed)
+            .await
+            .with_context(|| format!("cannot listen for dynamic_forward {spec:?}"))?;
+        for addr in bound {
+            info!("SOCKS5 proxy listening on {addr}");
+        }
+    }
+    for spec in config.remote_forward() {
+        let parsed =
+            parse_forward_spec(spec).with_context(|| format!("invalid remote_forward {spec:?}"))?;
+        mux.request_listen(parsed).await;
+    }
+    Ok(Some(mux))
+}
 
-    run_session_loop(config, socket_addr, server_ip, server_port, escape_byte).await
+/// The lowest protocol version whose servers understand every kind of forward
+/// configured, including the standard streams of a piped remote command or
+/// file copy.
+fn forwarding_min_version(config: &Config) -> u16 {
+    let unix_socket = config
+        .local_forward()
+        .iter()
+        .chain(config.remote_forward())
+        .filter_map(|spec| parse_forward_spec(spec).ok())
+        .any(|spec| spec.bind_path().is_some() || spec.host_path().is_some());
+    if config.file_copy().is_some() {
+        FILE_COPY_MIN_PROTOCOL_VERSION
+    } else if config.piped_command() {
+        REMOTE_COMMAND_MIN_PROTOCOL_VERSION
+    } else if unix_socket {
+        UNIX_FORWARDING_MIN_PROTOCOL_VERSION
+    } else if !config.dynamic_forward().is_empty() {
+        DYNAMIC_FORWARDING_MIN_PROTOCOL_VERSION
+    } else if !config.remote_forward().is_empty() {
+        REMOTE_FORWARDING_MIN_PROTOCOL_VERSION
+    } else {
+        PORT_FORWARDING_MIN_PROTOCOL_VERSION
+    }
 }
 
 /// Cached passphrase state, avoiding re-prompting across reconnects.
@@ -709,7 +899,7 @@ fn stdin_reader_loop(kb_tx: &Sender<Vec<u8>>, paused: &AtomicBool) {
             }
             0 => break, // EOF
             _ => {
-                if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+                if std::io::Error::last_os_error().kind() != ErrorKind::Interrupted {
                     break;
                 }
             }
@@ -813,19 +1003,27 @@ async fn countdown_with_escape(
 /// exit-triggering path; read by [`restore_terminal_and_exit`].
 type ExitMsg = Arc<std::sync::Mutex<Option<&'static [u8]>>>;
 
+/// Shared holder for a remote command's exit status.  Set by the reader when
+/// the server reports it; becomes the exit status of `mp`.
+type ExitStatusSlot = Arc<std::sync::Mutex<Option<ExitStatus>>>;
+
+/// Shared recorder for `--record`, handed to each connection's renderer so a
+/// recording carries on across reconnects.
+type SharedRecorder = Arc<std::sync::Mutex<ScreenRecorder>>;
+
 /// Restore the terminal and terminate the process.
 ///
 /// Leaves the alternate screen (a server-side app may have entered it), shows
 /// the cursor, clears the *visible* screen, and homes the cursor so the next
 /// shell prompt starts cleanly at the top.  Scrollback is preserved (`\x1b[2J`,
 /// not `\x1b[3J`).  `exit_msg`, if present, is printed after the clear so it
-/// sits at the top of the fresh screen.
+/// sits at the top of the fresh screen.  The process exits with `code`.
 ///
 /// Everything is written directly to stdout in one flushed sequence so the
 /// ordering is deterministic (the async stdout writer thread is bypassed); the
 /// clear also wipes any residual diff bytes that thread may have flushed.
 #[cfg_attr(coverage_nightly, coverage(off))]
-fn restore_terminal_and_exit(exit_msg: Option<&[u8]>) -> ! {
+fn restore_terminal_and_exit(exit_msg: Option<&[u8]>, code: i32) -> ! {
     let mut out = stdout();
     // Leave alt-screen, show cursor, clear the visible screen, home + reset SGR.
     drop(out.write_all(b"\x1b[?1049l\x1b[?25h\x1b[2J\x1b[H\x1b[0m"));
@@ -834,7 +1032,55 @@ fn restore_terminal_and_exit(exit_msg: Option<&[u8]>) -> ! {
     }
     drop(out.flush());
     drop(disable_raw_mode());
-    exit(0);
+    exit(code);
+}
+
+/// What the user can do about a key exchange the server rejected for `reason`.
+fn kex_rejection_hint(reason: KexFailureReason) -> &'static str {
+    match reason {
+        KexFailureReason::NoCommonAlgorithm => {
+            "check --kex-algos, --aead-algos, --mac-algos, and --kdf-algos settings on both \
+             client and server"
+        }
+        KexFailureReason::IncompatibleProtocolVersion => {
+            "upgrade moshpit on whichever side is older (the server may have raised its \
+             minimum supported version)"
+        }
+        KexFailureReason::AuthenticationFailed => {
+            "check the user name, that your public key is listed in ~/.mp/authorized_keys on \
+             the server, and that ~/.mp is mode 700 and authorized_keys mode 600"
+        }
+        KexFailureReason::UnknownUser => {
+            "the user does not exist on the server; pass the right one as user@host"
+        }
+        KexFailureReason::UnauthorizedKey => {
+            "add your public key (the .pub file next to your private key) to \
+             ~/.mp/authorized_keys on the server"
+        }
+        KexFailureReason::AuthorizedKeysPermissions => {
+            "on the server, run `chmod 700 ~/.mp` and `chmod 600 ~/.mp/authorized_keys`"
+        }
+        KexFailureReason::KeyMismatch => {
+            "the handshake may have been altered in transit; retry, and check for a proxy or \
+             middlebox rewriting the connection"
+        }
+        KexFailureReason::TicketRejected => {
+            "the saved resumption ticket is no longer valid; delete the .ticket file under \
+             ~/.mp/sessions and reconnect"
+        }
+        KexFailureReason::SessionEnded => {
+            "the remote command finished while disconnected and its exit status was lost; \
+             run it again if needed"
+        }
+        KexFailureReason::NoSuchSession => {
+            "run `mp ls` to see your sessions on the server, or start this one with --session; \
+             another user's session must be shared with you in their ~/.mp/shared_sessions"
+        }
+        KexFailureReason::SessionReadOnly => {
+            "attach with --read-only, or ask the session's owner to grant read-write in their \
+             ~/.mp/shared_sessions"
+        }
+    }
 }
 
 /// Persistent reconnect loop.  Runs until the shell exits (via `process::exit`).
@@ -842,23 +1088,28 @@ fn restore_terminal_and_exit(exit_msg: Option<&[u8]>) -> ! {
 #[cfg_attr(coverage_nightly, coverage(off))]
 async fn run_session_loop(
     config: Config,
-    socket_addr: SocketAddr,
-    server_ip: String,
-    server_port: u16,
+    destination: ServerDestination,
     escape_byte: u8,
+    forwards: Option<ForwardMux>,
 ) -> Result<()> {
     // Clamp to [2 s, 24 h].
     let max_backoff = Duration::from_secs(config.max_reconnect_backoff_secs().clamp(2, 86_400));
     // Human-readable label (e.g. "Ctrl-^") for the reconnect-countdown hint.
     let escape_label = ctrl_label(escape_byte);
 
+    // A remote command run without a PTY owns our standard streams: the
+    // terminal is left alone and nothing is rendered to it.
+    let interactive = !config.piped_command();
+
     // Persistent stdout writer — survives reconnects.
     let (stdout_tx, mut stdout_rx) = channel::<Vec<u8>>(256);
     let _stdout_thread = thread::spawn(move || {
         let mut out = stdout();
         while let Some(msg) = stdout_rx.blocking_recv() {
-            drop(out.write_all(&msg));
-            drop(out.flush());
+            if interactive {
+                drop(out.write_all(&msg));
+                drop(out.flush());
+            }
         }
     });
 
@@ -866,6 +1117,17 @@ async fn run_session_loop(
     let pass_cache: Arc<std::sync::Mutex<PassCache>> =
         Arc::new(std::sync::Mutex::new(PassCache::Uncached));
 
+    // `--record`: one recording for the whole run, created before the terminal
+    // goes raw so a bad path fails plainly.
+    let recorder: Option<SharedRecorder> = match config.record() {
+        Some(path) => {
+            let recorder = ScreenRecorder::create(Path::new(path))
+                .with_context(|| format!("cannot record to {path}"))?;
+            Some(Arc::new(std::sync::Mutex::new(recorder)))
+        }
+        None => None,
+    };
+
     let mut config = config;
     let mut backoff = Duration::from_secs(2);
     let mut reconnect_attempt: u32 = 0;
@@ -876,29 +1138,30 @@ async fn run_session_loop(
     // the reader on a server PtyExit, or the reconnect countdown. `None` exits
     // silently (e.g. OSC-title exit) but still clears the screen.
     let exit_msg: ExitMsg = Arc::new(std::sync::Mutex::new(None));
+    // Exit status of the remote command or shell, reported by the server when
+    // it ends.
+    let exit_status: ExitStatusSlot = Arc::new(std::sync::Mutex::new(None));
 
     // Start the stdin reader before the first KEX so Ctrl-^ . is always
     // detectable.  with_cooked_term pauses it around interactive prompts.
+    // A piped command reads standard input itself; its keyboard channel just
+    // stays open and empty.
     let stdin_paused = Arc::new(AtomicBool::new(false));
-    enable_raw_mode()?;
     let (kb_tx, kb_rx) = channel::<Vec<u8>>(64);
-    let paused_for_reader = stdin_paused.clone();
-    let _stdin_thread = thread::spawn(move || stdin_reader_loop(&kb_tx, &paused_for_reader));
+    let _idle_kb_tx = if interactive {
+        enable_raw_mode()?;
+        let paused_for_reader = stdin_paused.clone();
+        let _stdin_thread = thread::spawn(move || stdin_reader_loop(&kb_tx, &paused_for_reader));
+        None
+    } else {
+        Some(kb_tx)
+    };
     let kb_rx_shared = Arc::new(Mutex::new(kb_rx));
 
     let mut had_successful_kex = false;
 
     loop {
-        match connect_and_kex(
-            &mut config,
-            socket_addr,
-            &server_ip,
-            server_port,
-            &pass_cache,
-            stdin_paused.clone(),
-        )
-        .await
-        {
+        match connect_and_kex(&mut config, &destination, &pass_cache, stdin_paused.clone()).await {
             Ok((kex, transport, nak_timeout)) => {
                 backoff = Duration::from_secs(2);
                 clear_reconnect_banner(&stdout_tx).await;
@@ -906,6 +1169,61 @@ async fn run_session_loop(
                 // Informational: the wire protocol version both ends agreed on.
                 // Future wire-format changes should branch on kex.protocol_version().
                 info!("negotiated wire protocol v{}", kex.protocol_version());
+                // An older server would have started a login shell instead.
+                if KexConfig::remote_command(&config).is_some()
+                    && kex.protocol_version() < REMOTE_COMMAND_MIN_PROTOCOL_VERSION
+                {
+                    drop(disable_raw_mode());
+                    bail!(
+                        "server does not support remote commands (protocol v{})",
+                        kex.protocol_version()
+                    );
+                }
+                if config.file_copy().is_some()
+                    && kex.protocol_version() < FILE_COPY_MIN_PROTOCOL_VERSION
+                {
+                    bail!(
+                        "server does not support file copy (protocol v{})",
+                        kex.protocol_version()
+                    );
+                }
+                if KexConfig::session_request(&config).is_some()
+                    && kex.protocol_version() < NAMED_SESSIONS_MIN_PROTOCOL_VERSION
+                {
+                    drop(disable_raw_mode());
+                    bail!(
+                        "server does not support named sessions (protocol v{})",
+                        kex.protocol_version()
+                    );
+                }
+                if matches!(config.session_request(), Some(SessionRequest::Join { .. }))
+                    && kex.protocol_version() < SESSION_SHARING_MIN_PROTOCOL_VERSION
+                {
+                    drop(disable_raw_mode());
+                    bail!(
+                        "server does not support shared sessions (protocol v{})",
+                        kex.protocol_version()
+                    );
+                }
+                // Listen requests and SOCKS opens are frames an older server
+                // cannot decode, so the whole mux waits for a server that
+                // understands every kind of forward configured.
+                let forwarding = kex.protocol_version() >= forwarding_min_version(&config);
+                if !forwarding && forwards.is_some() {
+                    warn!(
+                        "server does not support port forwarding (protocol v{}); forwarded connections will wait",
+                        kex.protocol_version()
+                    );
+                }
+                if KexConfig::forward_agent(&config)
+                    && kex.protocol_version() < AGENT_FORWARDING_MIN_PROTOCOL_VERSION
+                {
+                    warn!(
+                        "server does not support agent forwarding (protocol v{})",
+                        kex.protocol_version()
+                    );
+                }
+                let session_forwards = forwards.clone().filter(|_| forwarding);
 
                 let session_result = match transport {
                     NegotiatedTransport::Udp(udp_arc) => {
@@ -924,6 +1242,9 @@ async fn run_session_loop(
                             escape_byte,
                             exit_token.clone(),
                             exit_msg.clone(),
+                            exit_status.clone(),
+                            session_forwards,
+                            recorder.clone(),
                         )
                         .await
                     }
@@ -941,6 +1262,9 @@ async fn run_session_loop(
                             escape_byte,
                             exit_token.clone(),
                             exit_msg.clone(),
+                            exit_status.clone(),
+                            session_forwards,
+                            recorder.clone(),
                         )
                         .await
                     }
@@ -950,18 +1274,29 @@ async fn run_session_loop(
                     return Err(e);
                 }
                 if exit_token.is_cancelled() {
+                    let code = exit_status
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .map_or(0, ExitStatus::code);
+                    if !interactive {
+                        // A passphrase prompt may have left a terminal in raw mode.
+                        drop(disable_raw_mode());
+                        exit(code);
+                    }
                     // Let the stdout channel settle before the direct teardown write.
                     time::sleep(Duration::from_millis(100)).await;
                     let msg = *exit_msg.lock().unwrap_or_else(PoisonError::into_inner);
-                    restore_terminal_and_exit(msg);
+                    restore_terminal_and_exit(msg, code);
                 }
                 // Session dropped — restore the terminal (the server-side app may
                 // have left us in alternate-screen mode) then show the reconnect banner.
-                drop(crossterm::execute!(
-                    stdout(),
-                    crossterm::terminal::LeaveAlternateScreen,
-                    crossterm::cursor::Show,
-                ));
+                if interactive {
+                    drop(crossterm::execute!(
+                        stdout(),
+                        crossterm::terminal::LeaveAlternateScreen,
+                        crossterm::cursor::Show,
+                    ));
+                }
                 show_reconnect_banner(&stdout_tx).await;
                 time::sleep(Duration::from_millis(500)).await;
             }
@@ -1001,6 +1336,40 @@ async fn run_session_loop(
                             drop(disable_raw_mode());
                             return Err(e);
                         }
+                        // A stale ticket is not fatal: forget it and run a full
+                        // handshake straight away.  The ticket of a command or
+                        // named session only lives in memory.
+                        MoshpitError::KexRejected(KexFailureReason::TicketRejected)
+                            if config.resumption_ticket().is_some() && !config.saved_session() =>
+                        {
+                            info!(
+                                "Resumption ticket rejected, falling back to a full key exchange"
+                            );
+                            let _ = config.set_resumption_ticket(None);
+                            continue;
+                        }
+                        MoshpitError::KexRejected(KexFailureReason::TicketRejected)
+                            if remove_resumption_ticket(destination.host(), destination.port())
+                                .is_ok() =>
+                        {
+                            info!(
+                                "Resumption ticket rejected, falling back to a full key exchange"
+                            );
+                            continue;
+                        }
+                        // An older server cannot read the early key share and is
+                        // stuck waiting; reconnect and let it speak first.
+                        MoshpitError::EarlyKeyShareUnsupported => {
+                            info!("Server predates early key shares, reconnecting without one");
+                            let _ = config.set_early_key_share_unsupported(true);
+                            continue;
+                        }
+                        MoshpitError::KexRejected(reason) => {
+                            eprintln!("mp: server rejected the key exchange: {reason}");
+                            eprintln!("mp: {}", kex_rejection_hint(reason));
+                            drop(disable_raw_mode());
+                            return Err(e);
+                        }
                         MoshpitError::IncompatibleProtocolVersion => {
                             eprintln!(
                                 "mp: server's wire protocol is incompatible with this client"
@@ -1016,7 +1385,7 @@ async fn run_session_loop(
                     }
                 }
                 reconnect_attempt = reconnect_attempt.saturating_add(1);
-                error!("Failed to connect to {socket_addr}: {e}, retrying in {backoff:?}");
+                error!("Failed to connect to {destination}: {e}, retrying in {backoff:?}");
                 // Reset passphrase cache on early failures so the user can
                 // re-enter it on the next attempt.
                 if !had_successful_kex {
@@ -1038,7 +1407,7 @@ async fn run_session_loop(
                     clear_reconnect_banner(&stdout_tx).await;
                     // Let the stdout channel settle before the direct teardown write.
                     time::sleep(Duration::from_millis(100)).await;
-                    restore_terminal_and_exit(Some(b"[moshpit] Disconnected.\r\n"));
+                    restore_terminal_and_exit(Some(b"[moshpit] Disconnected.\r\n"), 0);
                 }
                 backoff = (backoff * 2).min(max_backoff);
             }
@@ -1046,23 +1415,72 @@ async fn run_session_loop(
     }
 }
 
-/// Connect via TCP, run the key exchange, and persist the session UUID.
+/// Open a UDP handshake connection to the first of `addrs` that completes the
+/// cookie exchange (`--transport udp-only`).
+async fn connect_udp_only(addrs: &[SocketAddr]) -> Result<(ConnectionReader, ConnectionWriter)> {
+    let mut last_err = None;
+    for &addr in addrs {
+        match connect_udp_handshake(addr).await {
+            Ok(conn) => {
+                info!("UDP handshake connected to {addr}");
+                return Ok(conn);
+            }
+            Err(e) => {
+                debug!("UDP handshake with {addr} failed: {e}");
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| MoshpitError::HostResolutionFailed.into()))
+}
+
+/// Resolve the destination, connect via TCP, run the key exchange, and persist
+/// the session UUID.
+///
+/// The host name is re-resolved on every call so a reconnect reaches a server
+/// whose address has changed; the resolved addresses are raced Happy-Eyeballs
+/// style and the first to accept the connection is used.
 #[cfg_attr(nightly, allow(clippy::too_many_lines))]
 async fn connect_and_kex(
     config: &mut Config,
-    socket_addr: SocketAddr,
-    server_ip: &str,
-    server_port: u16,
+    destination: &ServerDestination,
     pass_cache: &Arc<std::sync::Mutex<PassCache>>,
     stdin_paused: Arc<AtomicBool>,
 ) -> Result<(Kex, NegotiatedTransport, Duration)> {
-    // Refresh resume UUID from disk (may have been updated by previous connection).
-    let _ = config.set_resume_session_uuid(read_session_uuid(server_ip, server_port));
-
-    let socket = time::timeout(KEX_TIMEOUT, TcpStream::connect(socket_addr))
+    let server_host = destination.host();
+    let server_port = destination.port();
+    // A remote command's or named session is its own: it never attaches to
+    // the saved shell session, and its resume state only lives in this process.
+    let persist = config.saved_session();
+    if persist {
+        // Refresh resume UUID from disk (may have been updated by previous connection).
+        let _ = config.set_resume_session_uuid(read_session_uuid(server_host, server_port));
+        let _ = config.set_resumption_ticket(read_resumption_ticket(server_host, server_port));
+    }
+
+    let (conn_reader, conn_writer) = if config.udp_only() {
+        time::timeout(KEX_TIMEOUT, async {
+            let addrs = destination.resolve().await?;
+            debug!("{server_host} resolved to {addrs:?}");
+            connect_udp_only(&addrs).await
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("UDP handshake timed out after {KEX_TIMEOUT:?}"))??
+    } else {
+        let socket = time::timeout(KEX_TIMEOUT, async {
+            let addrs = destination.resolve().await?;
+            debug!("{server_host} resolved to {addrs:?}");
+            connect_happy_eyeballs(&addrs).await
+        })
         .await
         .map_err(|_| anyhow::anyhow!("TCP connection timed out after {KEX_TIMEOUT:?}"))??;
-    info!("Connected to {}", socket.peer_addr()?);
+        info!("Connected to {}", socket.peer_addr()?);
+        let (sock_read, sock_write) = socket.into_split();
+        (
+            ConnectionReader::builder().reader(sock_read).build(),
+            ConnectionWriter::builder().writer(sock_write).build(),
+        )
+    };
 
     let cache = pass_cache.clone();
     let paused_pass = stdin_paused.clone();
@@ -1092,8 +1510,6 @@ async fn connect_and_kex(
         result
     };
 
-    let (sock_read, sock_write) = socket.into_split();
-
     let paused_tofu = stdin_paused.clone();
     let tofu_fn: libmoshpit::TofuFn =
         Arc::new(move |host: &str, fingerprint: &str| -> Result<bool> {
@@ -1141,10 +1557,10 @@ async fn connect_and_kex(
     let kex_start = Instant::now();
     let kex_result = time::timeout(
         KEX_TIMEOUT,
-        run_key_exchange(
+        run_key_exchange_over(
             config.clone(),
-            sock_read,
-            sock_write,
+            conn_reader,
+            conn_writer,
             pass_fn,
             Some(tofu_fn),
             Some(mismatch_fn),
@@ -1159,7 +1575,7 @@ async fn connect_and_kex(
         Err(_elapsed) => {
             return Err(anyhow::anyhow!(
                 "key exchange timed out after {KEX_TIMEOUT:?} — \
-                 server accepted TCP connection but sent no data"
+                 server accepted the connection but sent no data"
             ));
         }
         Ok(inner) => inner,
@@ -1190,8 +1606,21 @@ async fn connect_and_kex(
     })?;
 
     if let Some(session_uuid) = kex.session_uuid() {
-        if let Err(e) = write_session_uuid(server_ip, server_port, session_uuid) {
-            trace!("Failed to write session file: {e}");
+        if persist {
+            if let Err(e) = write_session_uuid(server_host, server_port, session_uuid) {
+                trace!("Failed to write session file: {e}");
+            }
+            // A redeemed ticket is spent; keep only the replacement, if any.
+            let ticket_result = match kex.resumption_ticket() {
+                Some(ticket) => write_resumption_ticket(server_host, server_port, ticket),
+                None => remove_resumption_ticket(server_host, server_port),
+            };
+            if let Err(e) = ticket_result {
+                trace!("Failed to update resumption ticket file: {e}");
+            }
+        } else {
+            let _ = config.set_resume_session_uuid(Some(session_uuid));
+            let _ = config.set_resumption_ticket(kex.resumption_ticket().clone());
         }
         if kex.is_resume() {
             info!("Session {session_uuid} resumed");
@@ -1229,6 +1658,9 @@ async fn run_udp_session(
     escape_byte: u8,
     exit_token: CancellationToken,
     exit_msg: ExitMsg,
+    exit_status: ExitStatusSlot,
+    forwards: Option<ForwardMux>,
+    recorder: Option<SharedRecorder>,
 ) -> Result<()> {
     let (reconnect_tx, mut reconnect_rx) = channel::<()>(1);
     let token = CancellationToken::new();
@@ -1246,8 +1678,10 @@ async fn run_udp_session(
     let mut udp_reader = UdpReader::builder()
         .socket(udp_arc.clone())
         .id(kex.uuid())
-        .hmac(kex.build_hmac())
-        .rnk(kex.build_aead_key()?)
+        .maybe_hmac(kex.build_hmac(KeyDirection::ServerToClient)?)
+        .rnk(kex.build_aead_key(KeyDirection::ServerToClient)?)
+        .maybe_ratchet(kex.key_ratchet(KeyDirection::ServerToClient)?)
+        .nonce_scheme(kex.nonce_scheme())
         .mac_tag_len(mac_tag_len)
         .nak_out_tx(tx.clone())
         .retransmit_tx(retransmit_tx)
@@ -1257,6 +1691,8 @@ async fn run_udp_session(
         .query_response_tx(tx.clone())
         .diff_mode(diff_mode)
         .passthrough(legacy_passthrough)
+        .maybe_forward_tx(forwards.as_ref().map(ForwardMux::frame_tx))
+        .exit_status(exit_status)
         .build();
 
     let mut udp_sender = UdpSender::builder()
@@ -1265,8 +1701,10 @@ async fn run_udp_session(
         .rx(rx)
         .retransmit_rx(retransmit_rx)
         .id(kex.uuid())
-        .hmac(kex.build_hmac())
-        .rnk(kex.build_aead_key()?)
+        .maybe_hmac(kex.build_hmac(KeyDirection::ClientToServer)?)
+        .rnk(kex.build_aead_key(KeyDirection::ClientToServer)?)
+        .maybe_ratchet(kex.key_ratchet(KeyDirection::ClientToServer)?)
+        .nonce_scheme(kex.nonce_scheme())
         .diff_mode(diff_mode)
         .build();
 
@@ -1276,6 +1714,11 @@ async fn run_udp_session(
     let (cols, rows) = terminal_size().map_or((80, 24), |(w, h)| (w.0, h.0));
     tx.send(EncryptedFrame::Resize((kex.uuid_wrapper(), cols, rows)))
         .await?;
+    // Carry forwarded streams over this connection, resending whatever the
+    // previous one left unacknowledged.
+    if let Some(forwards) = &forwards {
+        forwards.attach(tx.clone()).await;
+    }
 
     // NAT warmup: send keepalive frames before the session loop begins so that
     // a bidirectional NAT binding is established before the server starts
@@ -1304,7 +1747,11 @@ async fn run_udp_session(
     let prediction = Arc::new(std::sync::Mutex::new(PredictionEngine::new(
         display_preference,
     )));
-    let renderer = Arc::new(std::sync::Mutex::new(Renderer::new(rows, cols)));
+    let mut renderer = Renderer::new(rows, cols);
+    if let Some(recorder) = recorder {
+        renderer.set_recorder(recorder);
+    }
+    let renderer = Arc::new(std::sync::Mutex::new(renderer));
     let in_alt_screen = Arc::new(AtomicBool::new(false));
 
     let reader_token = token.clone();
@@ -1475,8 +1922,8 @@ async fn run_udp_session(
 #[cfg_attr(coverage_nightly, coverage(off))]
 async fn run_tcp_session(
     kex: Kex,
-    tcp_reader: libmoshpit::ConnectionReader,
-    tcp_writer: libmoshpit::ConnectionWriter,
+    tcp_reader: ConnectionReader,
+    tcp_writer: ConnectionWriter,
     kb_rx: Arc<Mutex<Receiver<Vec<u8>>>>,
     stdout_tx: Sender<Vec<u8>>,
     display_preference: DisplayPreference,
@@ -1485,6 +1932,9 @@ async fn run_tcp_session(
     escape_byte: u8,
     exit_token: CancellationToken,
     exit_msg: ExitMsg,
+    exit_status: ExitStatusSlot,
+    forwards: Option<ForwardMux>,
+    recorder: Option<SharedRecorder>,
 ) -> Result<()> {
     let (reconnect_tx, mut reconnect_rx) = channel::<()>(1);
     let token = CancellationToken::new();
@@ -1497,20 +1947,26 @@ async fn run_tcp_session(
     let mac_tag_len = kex.mac_tag_len();
     let mut tcp_transport_reader = TcpTransportReader::builder()
         .id(kex.uuid())
-        .hmac(kex.build_hmac())
-        .rnk(kex.build_aead_key()?)
+        .maybe_hmac(kex.build_hmac(KeyDirection::ServerToClient)?)
+        .rnk(kex.build_aead_key(KeyDirection::ServerToClient)?)
+        .maybe_ratchet(kex.key_ratchet(KeyDirection::ServerToClient)?)
+        .nonce_scheme(kex.nonce_scheme())
         .mac_tag_len(mac_tag_len)
         .reader(tcp_reader)
         .nak_out_tx(tx.clone())
         .silence_timeout(silence_timeout)
         .reconnect_tx(reconnect_tx)
         .passthrough(legacy_passthrough)
+        .maybe_forward_tx(forwards.as_ref().map(ForwardMux::frame_tx))
+        .exit_status(exit_status)
         .build();
 
     let mut tcp_transport_sender = TcpTransportSender::builder()
         .id(kex.uuid())
-        .hmac(kex.build_hmac())
-        .rnk(kex.build_aead_key()?)
+        .maybe_hmac(kex.build_hmac(KeyDirection::ClientToServer)?)
+        .rnk(kex.build_aead_key(KeyDirection::ClientToServer)?)
+        .maybe_ratchet(kex.key_ratchet(KeyDirection::ClientToServer)?)
+        .nonce_scheme(kex.nonce_scheme())
         .writer(tcp_writer)
         .control_rx(control_rx)
         .rx(rx)
@@ -1522,13 +1978,22 @@ async fn run_tcp_session(
     let (cols, rows) = terminal_size().map_or((80, 24), |(w, h)| (w.0, h.0));
     tx.send(EncryptedFrame::Resize((kex.uuid_wrapper(), cols, rows)))
         .await?;
+    // Carry forwarded streams over this connection, resending whatever the
+    // previous one left unacknowledged.
+    if let Some(forwards) = &forwards {
+        forwards.attach(tx.clone()).await;
+    }
 
     // ── Prediction / emulator shared state ──────────────────────────────────
     let emulator = Arc::new(std::sync::Mutex::new(Emulator::new(rows, cols)));
     let prediction = Arc::new(std::sync::Mutex::new(PredictionEngine::new(
         display_preference,
     )));
-    let renderer = Arc::new(std::sync::Mutex::new(Renderer::new(rows, cols)));
+    let mut renderer = Renderer::new(rows, cols);
+    if let Some(recorder) = recorder {
+        renderer.set_recorder(recorder);
+    }
+    let renderer = Arc::new(std::sync::Mutex::new(renderer));
     let in_alt_screen = Arc::new(AtomicBool::new(false));
 
     let reader_token = token.clone();
@@ -2029,6 +2494,12 @@ fn read_uuid_from_path(path: &Path) -> Option<Uuid> {
 }
 
 fn write_uuid_to_path(path: &Path, uuid: Uuid) -> Result<()> {
+    write_private_file(path, uuid.to_string().as_bytes())
+}
+
+/// Write `contents` to `path`, readable only by the current user, creating
+/// parent directories as needed.
+fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
     if let Some(parent) = path.parent() {
         #[cfg(unix)]
         {
@@ -2058,10 +2529,33 @@ fn write_uuid_to_path(path: &Path, uuid: Uuid) -> Result<()> {
             File::create(path)?
         }
     };
-    write!(file, "{uuid}")?;
+    file.write_all(contents)?;
     Ok(())
 }
 
+/// The resumption ticket file that sits next to the session file at `session_path`.
+fn ticket_path(session_path: &Path) -> PathBuf {
+    let mut name = session_path.as_os_str().to_owned();
+    name.push(".ticket");
+    PathBuf::from(name)
+}
+
+/// Read an unexpired resumption ticket from `path`, if any.
+fn read_ticket_from_path(path: &Path) -> Option<ResumptionTicket> {
+    let bytes = read(path).ok()?;
+    ResumptionTicket::from_bytes(&bytes)
+        .ok()
+        .filter(|ticket| !ticket.is_expired())
+}
+
+/// Delete the ticket file at `path`; a missing file is not an error.
+fn remove_ticket_at_path(path: &Path) -> Result<()> {
+    match remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
 /// Read a persisted session UUID from disk, if any.
 fn read_session_uuid(host: &str, port: u16) -> Option<Uuid> {
     read_uuid_from_path(&session_file_path(host, port)?)
@@ -2073,6 +2567,23 @@ fn write_session_uuid(host: &str, port: u16, session_uuid: Uuid) -> Result<()> {
     write_uuid_to_path(&path, session_uuid)
 }
 
+/// Read the persisted resumption ticket for the session with `host`, if any.
+fn read_resumption_ticket(host: &str, port: u16) -> Option<ResumptionTicket> {
+    read_ticket_from_path(&ticket_path(&session_file_path(host, port)?))
+}
+
+/// Write (or overwrite) the resumption ticket next to the session file.
+fn write_resumption_ticket(host: &str, port: u16, ticket: &ResumptionTicket) -> Result<()> {
+    let path = session_file_path(host, port).ok_or_else(|| anyhow::anyhow!("no home dir"))?;
+    write_private_file(&ticket_path(&path), &ticket.to_bytes()?)
+}
+
+/// Forget the resumption ticket for the session with `host`.
+fn remove_resumption_ticket(host: &str, port: u16) -> Result<()> {
+    let path = session_file_path(host, port).ok_or_else(|| anyhow::anyhow!("no home dir"))?;
+    remove_ticket_at_path(&ticket_path(&path))
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
@@ -2091,10 +2602,12 @@ mod tests {
     #[cfg(not(unix))]
     use super::key_event_to_bytes;
     use super::{
-        Cli, Config, FatalKexError, PassCache, clear_reconnect_banner, client_id_in_home,
-        client_id_path, connect_and_kex, countdown_reconnect_banner, create_key_dir, load,
-        maybe_generate_keypair, read_uuid_from_path, session_file_path_in_home,
-        show_reconnect_banner, write_uuid_to_path,
+        Cli, Config, FatalKexError, PassCache, attach_request, clear_reconnect_banner,
+        client_id_in_home, client_id_path, connect_and_kex, countdown_reconnect_banner,
+        create_key_dir, kex_rejection_hint, load, maybe_generate_keypair, parse_server_destination,
+        read_ticket_from_path, read_uuid_from_path, remove_ticket_at_path,
+        session_file_path_in_home, show_reconnect_banner, ticket_path, write_private_file,
+        write_uuid_to_path,
     };
 
     struct TestHome {
@@ -2165,6 +2678,58 @@ mod tests {
         unsafe { remove_var(KEY) };
     }
 
+    #[test]
+    fn attach_joins_when_shared_read_only_or_another_users() -> Result<()> {
+        use libmoshpit::SessionRequest;
+
+        assert_eq!(
+            attach_request("work", false, false)?,
+            SessionRequest::Attach("work".to_string())
+        );
+        assert_eq!(
+            attach_request("work", true, false)?,
+            SessionRequest::Join {
+                owner: None,
+                name: "work".to_string(),
+                read_only: false,
+            }
+        );
+        assert_eq!(
+            attach_request("alice/work", false, true)?,
+            SessionRequest::Join {
+                owner: Some("alice".to_string()),
+                name: "work".to_string(),
+                read_only: true,
+            }
+        );
+        assert!(attach_request("alice/", false, false).is_err());
+        assert!(attach_request("/work", false, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn kex_rejection_hint_is_specific_to_each_reason() {
+        use libmoshpit::KexFailureReason;
+
+        let reasons = [
+            KexFailureReason::NoCommonAlgorithm,
+            KexFailureReason::IncompatibleProtocolVersion,
+            KexFailureReason::AuthenticationFailed,
+            KexFailureReason::UnknownUser,
+            KexFailureReason::UnauthorizedKey,
+            KexFailureReason::AuthorizedKeysPermissions,
+            KexFailureReason::KeyMismatch,
+            KexFailureReason::TicketRejected,
+            KexFailureReason::SessionEnded,
+            KexFailureReason::NoSuchSession,
+            KexFailureReason::SessionReadOnly,
+        ];
+        let hints: std::collections::BTreeSet<_> =
+            reasons.iter().map(|r| kex_rejection_hint(*r)).collect();
+        assert_eq!(hints.len(), reasons.len());
+        assert!(kex_rejection_hint(KexFailureReason::UnauthorizedKey).contains("authorized_keys"));
+    }
+
     #[test]
     fn test_pass_cache() {
         let mut cache = PassCache::Uncached;
@@ -2261,6 +2826,35 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn resumption_ticket_file_sits_next_to_session_file() -> Result<()> {
+        let home = TestHome::new();
+        let session = session_file_path_in_home(home.path(), "example.com", 40404)
+            .ok_or_else(|| anyhow::anyhow!("no session file path"))?;
+        let ticket = ticket_path(&session);
+        assert_eq!(ticket.parent(), session.parent());
+        // Appended rather than swapped in, so the dots in a host name survive.
+        assert_eq!(
+            ticket.to_string_lossy(),
+            format!("{}.ticket", session.to_string_lossy())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resumption_ticket_file_garbage_or_missing_returns_none() -> Result<()> {
+        let dir = temp_dir().join(Uuid::new_v4().to_string());
+        let path = dir.join("session.ticket");
+        assert!(read_ticket_from_path(&path).is_none());
+        write_private_file(&path, b"not a ticket")?;
+        assert!(read_ticket_from_path(&path).is_none());
+        remove_ticket_at_path(&path)?;
+        assert!(!path.exists());
+        // Removing a ticket that is already gone is not an error.
+        remove_ticket_at_path(&path)?;
+        Ok(())
+    }
+
     #[test]
     fn client_id_path_is_under_dot_mp() {
         let home = TestHome::new();
@@ -2383,14 +2977,12 @@ mod tests {
         let port = listener.local_addr()?.port();
         drop(listener);
 
-        let addr = format!("127.0.0.1:{port}").parse()?;
+        let destination = parse_server_destination(&format!("127.0.0.1:{port}"), port)?;
 
         // This should fail with ConnectionRefused
         let result = connect_and_kex(
             &mut config,
-            addr,
-            "127.0.0.1",
-            port,
+            &destination,
             &pass_cache,
             Arc::new(AtomicBool::new(false)),
         )
@@ -2470,14 +3062,12 @@ mod tests {
             }
         }));
 
-        let addr = format!("127.0.0.1:{port}").parse()?;
+        let destination = parse_server_destination(&format!("127.0.0.1:{port}"), port)?;
 
         // TcpStream::connect will succeed, but run_key_exchange will fail
         let result = connect_and_kex(
             &mut config,
-            addr,
-            "127.0.0.1",
-            port,
+            &destination,
             &pass_cache,
             Arc::new(AtomicBool::new(false)),
         )
@@ -2557,12 +3147,10 @@ mod tests {
             if let Ok((_, _)) = listener.accept().await {}
         }));
 
-        let addr = format!("127.0.0.1:{port}").parse()?;
+        let destination = parse_server_destination(&format!("127.0.0.1:{port}"), port)?;
         let result = connect_and_kex(
             &mut config,
-            addr,
-            "127.0.0.1",
-            port,
+            &destination,
             &pass_cache,
             Arc::new(AtomicBool::new(false)),
         )
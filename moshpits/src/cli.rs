@@ -8,7 +8,7 @@
 
 use std::{collections::BTreeSet, ffi::OsString, io::Cursor, sync::LazyLock};
 
-use clap::{ArgAction, ArgMatches, CommandFactory, Parser, parser::ValueSource};
+use clap::{ArgAction, ArgMatches, CommandFactory, Parser, Subcommand, parser::ValueSource};
 use config::{ConfigError, Map, Source, Value, ValueKind};
 use getset::{CopyGetters, Getters};
 use libmoshpit::PathDefaults;
@@ -26,9 +26,49 @@ static LONG_VERSION: LazyLock<String> = LazyLock::new(|| {
     output
 });
 
+/// Server subcommands.  When absent, `mps` runs the daemon.
+#[derive(Clone, Debug, Subcommand)]
+pub(crate) enum Commands {
+    /// Receive files from `mp cp` into `target`.  Run by the daemon as the
+    /// session user, never by hand.
+    #[command(hide = true)]
+    CopyTo {
+        /// Where the files go; relative to the home directory.
+        target: String,
+    },
+    /// Send `sources` to `mp cp`.  Run by the daemon as the session user,
+    /// never by hand.
+    #[command(hide = true)]
+    CopyFrom {
+        /// Send the contents of directories.
+        #[clap(short, long)]
+        recursive: bool,
+        /// What to send; relative to the home directory.
+        #[clap(required = true)]
+        sources: Vec<String>,
+    },
+    /// Reap the programs of the sessions handed to a new `mps`.  Execed by
+    /// the old daemon in its own place, never run by hand.
+    #[cfg(unix)]
+    #[command(hide = true)]
+    Reap {
+        /// The hand-off connection to the new `mps`.
+        successor: i32,
+        /// The hand-off connection from the `mps` before, whose reaper's
+        /// reports go on to the new one.
+        #[clap(long)]
+        predecessor: Option<i32>,
+    },
+}
+
 #[derive(Clone, CopyGetters, Debug, Getters, Parser)]
 #[command(author, version, about, long_version = LONG_VERSION.as_str(), long_about = None)]
+#[allow(clippy::struct_excessive_bools)]
 pub(crate) struct Cli {
+    /// Optional subcommand.  When `None`, the daemon runs.
+    #[command(subcommand)]
+    #[getset(get = "pub(crate)")]
+    command: Option<Commands>,
     /// Set logging verbosity.  More v's, more verbose.
     #[clap(
         short,
@@ -126,7 +166,7 @@ pub(crate) struct Cli {
     #[clap(
         long,
         value_name = "ALGOS",
-        help = "Ordered KEX algorithms to prefer, comma-separated [supported: x25519-sha256 (default), ml-kem-768-sha256, ml-kem-512-sha256, ml-kem-1024-sha256, p384-sha384, p256-sha256]"
+        help = "Ordered KEX algorithms to prefer, comma-separated [supported: mlkem768x25519-sha256 (default), x25519-sha256, ml-kem-768-sha256, ml-kem-512-sha256, ml-kem-1024-sha256, p384-sha384, p256-sha256]"
     )]
     #[getset(get = "pub(crate)")]
     kex_algos: Option<String>,
@@ -144,7 +184,7 @@ pub(crate) struct Cli {
     #[clap(
         long,
         value_name = "ALGOS",
-        help = "Ordered MAC algorithms to prefer, comma-separated [supported: hmac-sha512 (default), hmac-sha256]"
+        help = "Ordered MAC algorithms to prefer, comma-separated [supported: hmac-sha512 (default), hmac-sha256, aead-implicit]"
     )]
     #[getset(get = "pub(crate)")]
     mac_algos: Option<String>,
@@ -165,6 +205,57 @@ pub(crate) struct Cli {
     )]
     #[getset(get_copy = "pub(crate)")]
     allow_tcp_transport: bool,
+    /// Tell rejected clients which account check failed instead of a generic
+    /// authentication failure.  Reveals which users exist.  Default: off.
+    #[clap(
+        long,
+        help = "Tell rejected clients which account check failed (reveals which users exist)"
+    )]
+    #[getset(get_copy = "pub(crate)")]
+    detailed_auth_failures: bool,
+    /// Also accept key exchanges over UDP on the listen port, for clients
+    /// using `mp --transport udp-only` on networks that block TCP.  Default: off.
+    #[clap(
+        long,
+        help = "Also accept key exchanges over UDP on the listen port (TCP-blocked networks)"
+    )]
+    #[getset(get_copy = "pub(crate)")]
+    udp_handshake: bool,
+    /// Record every session with a terminal as asciicast v2 files in this
+    /// directory, which must be owned by the daemon's user and writable by
+    /// nobody else.  Default: no recording.
+    #[clap(
+        long,
+        value_name = "DIR",
+        help = "Record sessions as asciicast v2 files in DIR (owned by the daemon user, mode 0700)"
+    )]
+    #[getset(get = "pub(crate)")]
+    record_dir: Option<String>,
+    /// Also record what clients type, passwords included.  Default: off.
+    #[clap(
+        long,
+        requires = "record_dir",
+        help = "Also record client keyboard input (includes passwords)"
+    )]
+    #[getset(get_copy = "pub(crate)")]
+    record_input: bool,
+    /// Unix socket on which to hand the listening sockets and live sessions
+    /// to a new moshpits started with `--take-over`.  Default: no hand-off.
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Hand live sessions to a new mps started with --take-over over this Unix socket"
+    )]
+    #[getset(get = "pub(crate)")]
+    handoff_socket: Option<String>,
+    /// Take the listening sockets and live sessions over from the moshpits
+    /// serving on `handoff_socket` instead of binding afresh.
+    #[clap(
+        long,
+        help = "Take listening sockets and live sessions over from the mps on the hand-off socket"
+    )]
+    #[getset(get_copy = "pub(crate)")]
+    take_over: bool,
     /// Set of clap argument ids the user actually supplied on the command line
     /// (`ValueSource::CommandLine`), populated by [`Cli::parse_argv`].  This lets
     /// [`Source::collect`] emit only user-provided values so clap defaults no
@@ -335,6 +426,43 @@ impl Source for Cli {
                 Value::new(Some(&origin), ValueKind::Boolean(self.allow_tcp_transport)),
             );
         }
+        if on("detailed_auth_failures") {
+            let _old = map.insert(
+                "detailed_auth_failures".to_string(),
+                Value::new(
+                    Some(&origin),
+                    ValueKind::Boolean(self.detailed_auth_failures),
+                ),
+            );
+        }
+        if on("udp_handshake") {
+            let _old = map.insert(
+                "udp_handshake".to_string(),
+                Value::new(Some(&origin), ValueKind::Boolean(self.udp_handshake)),
+            );
+        }
+        if on("record_dir")
+            && let Some(record_dir) = &self.record_dir
+        {
+            let _old = map.insert(
+                "record_dir".to_string(),
+                Value::new(Some(&origin), ValueKind::String(record_dir.clone())),
+            );
+        }
+        if on("record_input") {
+            let _old = map.insert(
+                "record_input".to_string(),
+                Value::new(Some(&origin), ValueKind::Boolean(self.record_input)),
+            );
+        }
+        if on("handoff_socket")
+            && let Some(handoff_socket) = &self.handoff_socket
+        {
+            let _old = map.insert(
+                "handoff_socket".to_string(),
+                Value::new(Some(&origin), ValueKind::String(handoff_socket.clone())),
+            );
+        }
         if let Some(table) = build_algo_table(
             self.kex_algos.as_deref().filter(|_| on("kex_algos")),
             self.aead_algos.as_deref().filter(|_| on("aead_algos")),
@@ -385,6 +513,8 @@ mod test {
     use config::Source as _;
 
     use super::Cli;
+    #[cfg(unix)]
+    use super::Commands;
 
     fn parse(args: &[&str]) -> Cli {
         Cli::parse_argv(args).expect("args parse")
@@ -557,6 +687,115 @@ mod test {
         assert_eq!(value.clone().into_bool().ok(), Some(true));
     }
 
+    #[test]
+    fn cli_udp_handshake_absent_by_default() {
+        let cli = parse(&["mps"]);
+        assert!(!cli.udp_handshake());
+        let map = cli.collect().expect("collect should succeed");
+        assert!(!map.contains_key("udp_handshake"));
+    }
+
+    #[test]
+    fn cli_udp_handshake_collected() {
+        let cli = parse(&["mps", "--udp-handshake"]);
+        assert!(cli.udp_handshake());
+        let map = cli.collect().expect("collect should succeed");
+        let value = map
+            .get("udp_handshake")
+            .expect("udp_handshake should be in map");
+        assert_eq!(value.clone().into_bool().ok(), Some(true));
+    }
+
+    #[test]
+    fn cli_record_dir_absent_by_default() {
+        let cli = parse(&["mps"]);
+        assert!(cli.record_dir().is_none());
+        assert!(!cli.record_input());
+        let map = cli.collect().expect("collect should succeed");
+        assert!(!map.contains_key("record_dir"));
+        assert!(!map.contains_key("record_input"));
+    }
+
+    #[test]
+    fn cli_record_dir_collected() {
+        let cli = parse(&["mps", "--record-dir", "/var/log/mps", "--record-input"]);
+        let map = cli.collect().expect("collect should succeed");
+        let dir = map.get("record_dir").expect("record_dir should be in map");
+        assert_eq!(
+            dir.clone().into_string().ok(),
+            Some("/var/log/mps".to_string())
+        );
+        let input = map
+            .get("record_input")
+            .expect("record_input should be in map");
+        assert_eq!(input.clone().into_bool().ok(), Some(true));
+    }
+
+    #[test]
+    fn cli_record_input_requires_record_dir() {
+        assert!(Cli::parse_argv(["mps", "--record-input"]).is_err());
+    }
+
+    #[test]
+    fn cli_handoff_socket_collected() {
+        let cli = parse(&["mps"]);
+        assert!(!cli.take_over());
+        let map = cli.collect().expect("collect should succeed");
+        assert!(!map.contains_key("handoff_socket"));
+
+        let cli = parse(&["mps", "--handoff-socket", "/run/mps.handoff", "--take-over"]);
+        assert!(cli.take_over());
+        let map = cli.collect().expect("collect should succeed");
+        let socket = map
+            .get("handoff_socket")
+            .expect("handoff_socket should be in map");
+        assert_eq!(
+            socket.clone().into_string().ok(),
+            Some("/run/mps.handoff".to_string())
+        );
+        assert!(!map.contains_key("take_over"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cli_reap_takes_its_connections() {
+        let cli = parse(&["mps", "reap", "5", "--predecessor", "7"]);
+        assert!(matches!(
+            cli.command(),
+            Some(Commands::Reap {
+                successor: 5,
+                predecessor: Some(7),
+            })
+        ));
+        let cli = parse(&["mps", "reap", "5"]);
+        assert!(matches!(
+            cli.command(),
+            Some(Commands::Reap {
+                successor: 5,
+                predecessor: None,
+            })
+        ));
+    }
+
+    #[test]
+    fn cli_detailed_auth_failures_absent_by_default() {
+        let cli = parse(&["mps"]);
+        assert!(!cli.detailed_auth_failures());
+        let map = cli.collect().expect("collect should succeed");
+        assert!(!map.contains_key("detailed_auth_failures"));
+    }
+
+    #[test]
+    fn cli_detailed_auth_failures_collected() {
+        let cli = parse(&["mps", "--detailed-auth-failures"]);
+        assert!(cli.detailed_auth_failures());
+        let map = cli.collect().expect("collect should succeed");
+        let value = map
+            .get("detailed_auth_failures")
+            .expect("detailed_auth_failures should be in map");
+        assert_eq!(value.clone().into_bool().ok(), Some(true));
+    }
+
     #[test]
     fn cli_source_collect_algo_table() {
         let cli = parse(&[
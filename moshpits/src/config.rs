@@ -16,7 +16,7 @@ use anyhow::Result;
 use getset::{CloneGetters, CopyGetters, Getters, Setters};
 use libmoshpit::{
     AlgorithmList, KEY_ALGORITHM_X25519, KexConfig, KexMode, KeyPair, Mps, SessionRegistry,
-    Tracing, TracingConfigExt, supported_algorithms,
+    TicketIssuer, Tracing, TracingConfigExt, supported_algorithms,
 };
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
@@ -60,6 +60,9 @@ pub(crate) struct Config {
     #[serde(skip)]
     #[getset(get_clone = "pub(crate)", set = "pub(crate)")]
     session_registry: SessionRegistry,
+    #[serde(skip)]
+    #[getset(get_clone = "pub(crate)", set = "pub(crate)")]
+    ticket_issuer: Option<TicketIssuer>,
     #[serde(default)]
     #[getset(get_copy = "pub(crate)")]
     verbose: u8,
@@ -90,7 +93,8 @@ pub(crate) struct Config {
     pacing_delay_us: Option<u64>,
     /// Minimum wire protocol version this server accepts.  Older clients are
     /// rejected during key exchange.  Clamped to the range this build implements.
-    /// `None` means the build's default floor.
+    /// `None` means the first version with identity proofs; set it lower to let
+    /// older clients log in with their public key alone.
     #[serde(default)]
     #[getset(get_copy = "pub(crate)")]
     min_protocol_version: Option<u16>,
@@ -149,6 +153,100 @@ pub(crate) struct Config {
     #[serde(default)]
     #[getset(get_copy = "pub(crate)")]
     allow_tcp_transport: bool,
+    /// Tell rejected clients exactly which account check failed: unknown user,
+    /// key not in `~/.mp/authorized_keys`, or `~/.mp` permissions too open.
+    ///
+    /// Useful on single-user or internal hosts, but it lets anyone who can
+    /// reach the port probe which accounts exist, so the default (`false`)
+    /// reports all of these as a generic authentication failure.
+    #[serde(default)]
+    #[getset(get_copy = "pub(crate)")]
+    detailed_auth_failures: bool,
+    /// Issue session resumption tickets, so a reconnecting client can skip the
+    /// asymmetric key exchange.  Tickets are single-use, expire after 24 hours,
+    /// and are invalidated when the server restarts.  Default: `true`.
+    #[serde(default = "default_true")]
+    #[getset(get_copy = "pub(crate)")]
+    resumption_tickets: bool,
+    /// Also accept key exchanges over UDP datagrams on the listen port, for
+    /// clients on networks that block TCP (`mp --transport udp-only`).
+    /// Default: `false` (opt-in).
+    #[serde(default)]
+    #[getset(get_copy = "pub(crate)")]
+    udp_handshake: bool,
+    /// Allow clients to forward local ports through the session (`mp -L`), with
+    /// moshpits connecting to the target on their behalf as the session's user:
+    /// when running as root, from a socket created by a helper process running
+    /// as that user, so rules matching a socket's owner see the user rather
+    /// than root.  Refused when that user cannot be resolved.  Default: `true`.
+    #[serde(default = "default_true")]
+    #[getset(get_copy = "pub(crate)")]
+    allow_local_forwarding: bool,
+    /// Allow clients to have moshpits listen on a port and carry accepted
+    /// connections back to their machine (`mp -R`).  Default: `true`.
+    #[serde(default = "default_true")]
+    #[getset(get_copy = "pub(crate)")]
+    allow_remote_forwarding: bool,
+    /// Allow clients to run a SOCKS5 proxy through the session (`mp -D`), with
+    /// moshpits resolving and connecting to whatever target each proxied
+    /// connection names, as the session's user: when running as root, from a
+    /// socket created by a helper process running as that user, as for
+    /// `mp -L`.  Refused when that user cannot be resolved.  Default: `true`.
+    #[serde(default = "default_true")]
+    #[getset(get_copy = "pub(crate)")]
+    allow_dynamic_forwarding: bool,
+    /// Allow clients to forward Unix domain sockets in either direction
+    /// (`mp -L /tmp/docker.sock:/var/run/docker.sock`).  When running as root,
+    /// moshpits connects to sockets as the session's user, only creates them in
+    /// directories that user could, and hands every socket it creates to that
+    /// user with mode 0600.  Default: `false`.
+    #[serde(default)]
+    #[getset(get_copy = "pub(crate)")]
+    allow_unix_forwarding: bool,
+    /// Allow clients to forward their agent (`mp --forward-agent`): each
+    /// session that asks gets a socket in a private directory, owned by the
+    /// session's user and exported to its shell as `MOSHPIT_AGENT_SOCK`.
+    /// Default: `true`.
+    #[serde(default = "default_true")]
+    #[getset(get_copy = "pub(crate)")]
+    allow_agent_forwarding: bool,
+    /// Addresses a client may ask moshpits to listen on for `mp -R`; `"*"`
+    /// allows any.  Ports below 1024 are always refused.  Default: loopback
+    /// only (`localhost`, `127.0.0.1`, `::1`).
+    #[serde(default = "Config::default_remote_forward_bind_addresses")]
+    #[getset(get = "pub(crate)")]
+    remote_forward_bind_addresses: Vec<String>,
+    /// Record every session with a terminal — login shells and remote
+    /// commands run with a PTY — as asciicast v2 files in this directory.
+    /// It is created with mode 0700 if missing, and must be owned by the
+    /// daemon's user and writable by nobody else.  A session whose recording
+    /// cannot be started is refused.  Default: unset (no recording).
+    #[serde(default)]
+    #[getset(get = "pub(crate)")]
+    record_dir: Option<PathBuf>,
+    /// Also record what the client types, passwords included.
+    /// Default: `false`.
+    #[serde(default)]
+    #[getset(get_copy = "pub(crate)")]
+    record_input: bool,
+    /// Size in bytes at which a recording moves on to a new file, numbered
+    /// `.1.cast`, `.2.cast`, ….  Default: 64 MiB.
+    #[serde(default = "Config::default_record_file_max_bytes")]
+    #[getset(get_copy = "pub(crate)")]
+    record_file_max_bytes: u64,
+    /// Size in bytes, over all its files, at which a session's recording
+    /// stops.  The session itself carries on.  Default: 1 GiB.
+    #[serde(default = "Config::default_record_session_max_bytes")]
+    #[getset(get_copy = "pub(crate)")]
+    record_session_max_bytes: u64,
+    /// Unix socket on which moshpits hands its listening sockets and live
+    /// sessions to a new moshpits started with `--take-over`, so upgrading
+    /// the package does not end the sessions.  Created with mode 0600; only
+    /// a process of the daemon's own user may take over.  Default: unset
+    /// (no hand-off).
+    #[serde(default)]
+    #[getset(get = "pub(crate)")]
+    handoff_socket: Option<PathBuf>,
 }
 
 fn default_term_type() -> String {
@@ -165,6 +263,7 @@ impl Default for Config {
             mode: KexMode::default(),
             port_pool: Arc::new(Mutex::new(BTreeSet::new())),
             session_registry: Arc::new(Mutex::new(HashMap::new())),
+            ticket_issuer: None,
             verbose: 0,
             quiet: 0,
             enable_std_output: false,
@@ -184,6 +283,20 @@ impl Default for Config {
             use_logind: true,
             use_utmp: true,
             allow_tcp_transport: false,
+            detailed_auth_failures: false,
+            resumption_tickets: true,
+            udp_handshake: false,
+            allow_local_forwarding: true,
+            allow_remote_forwarding: true,
+            allow_dynamic_forwarding: true,
+            allow_unix_forwarding: false,
+            allow_agent_forwarding: true,
+            remote_forward_bind_addresses: Self::default_remote_forward_bind_addresses(),
+            record_dir: None,
+            record_input: false,
+            record_file_max_bytes: Self::default_record_file_max_bytes(),
+            record_session_max_bytes: Self::default_record_session_max_bytes(),
+            handoff_socket: None,
         }
     }
 }
@@ -193,6 +306,18 @@ impl Config {
         vec!["LANG".into(), "LC_*".into(), "TZ".into()]
     }
 
+    fn default_remote_forward_bind_addresses() -> Vec<String> {
+        vec!["localhost".into(), "127.0.0.1".into(), "::1".into()]
+    }
+
+    fn default_record_file_max_bytes() -> u64 {
+        64 * 1024 * 1024
+    }
+
+    fn default_record_session_max_bytes() -> u64 {
+        1024 * 1024 * 1024
+    }
+
     fn default_server_path() -> Vec<String> {
         vec![
             "/usr/local/sbin".into(),
@@ -236,6 +361,10 @@ impl KexConfig for Config {
         Some(self.session_registry.clone())
     }
 
+    fn ticket_issuer(&self) -> Option<TicketIssuer> {
+        self.ticket_issuer.clone()
+    }
+
     fn user(&self) -> Option<String> {
         None
     }
@@ -245,15 +374,20 @@ impl KexConfig for Config {
     }
 
     fn min_protocol_version(&self) -> u16 {
-        // `None` → the build's default floor; the trait's protocol_support()
-        // clamps the result to the implementable range.
+        // `None` → refuse clients that cannot prove they hold their identity
+        // key; the trait's protocol_support() clamps the result to the
+        // implementable range.
         self.min_protocol_version
-            .unwrap_or(libmoshpit::MIN_PROTOCOL_VERSION)
+            .unwrap_or(libmoshpit::IDENTITY_PROOF_MIN_PROTOCOL_VERSION)
     }
 
     fn allow_tcp_transport(&self) -> bool {
         self.allow_tcp_transport()
     }
+
+    fn detailed_auth_failures(&self) -> bool {
+        self.detailed_auth_failures()
+    }
 }
 
 impl TracingConfig for Config {
@@ -346,6 +480,93 @@ mod test {
         assert!(KexConfig::allow_tcp_transport(&config));
     }
 
+    #[test]
+    fn config_detailed_auth_failures_defaults_false() {
+        use libmoshpit::KexConfig;
+        let config = Config::default();
+        assert!(!config.detailed_auth_failures(), "inherent getter default");
+        assert!(
+            !KexConfig::detailed_auth_failures(&config),
+            "trait method default"
+        );
+    }
+
+    #[test]
+    fn config_detailed_auth_failures_when_enabled() {
+        use libmoshpit::KexConfig;
+        let config = Config {
+            detailed_auth_failures: true,
+            ..Config::default()
+        };
+        assert!(config.detailed_auth_failures());
+        assert!(KexConfig::detailed_auth_failures(&config));
+    }
+
+    #[test]
+    fn config_resumption_tickets_default_true() {
+        let config = Config::default();
+        assert!(config.resumption_tickets());
+    }
+
+    #[test]
+    fn config_udp_handshake_defaults_false() {
+        assert!(!Config::default().udp_handshake());
+    }
+
+    #[test]
+    fn config_allow_local_forwarding_defaults_true() {
+        assert!(Config::default().allow_local_forwarding());
+    }
+
+    #[test]
+    fn config_allow_dynamic_forwarding_defaults_true() {
+        assert!(Config::default().allow_dynamic_forwarding());
+    }
+
+    #[test]
+    fn config_allow_unix_forwarding_defaults_false() {
+        assert!(!Config::default().allow_unix_forwarding());
+    }
+
+    #[test]
+    fn config_allow_agent_forwarding_defaults_true() {
+        assert!(Config::default().allow_agent_forwarding());
+    }
+
+    #[test]
+    fn config_remote_forwarding_defaults_to_loopback() {
+        let config = Config::default();
+        assert!(config.allow_remote_forwarding());
+        assert_eq!(
+            config.remote_forward_bind_addresses(),
+            &["localhost", "127.0.0.1", "::1"]
+        );
+    }
+
+    #[test]
+    fn config_recording_defaults_off() {
+        let config = Config::default();
+        assert!(config.record_dir().is_none());
+        assert!(!config.record_input());
+        assert_eq!(config.record_file_max_bytes(), 64 * 1024 * 1024);
+        assert_eq!(config.record_session_max_bytes(), 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn config_handoff_socket_defaults_unset() {
+        assert!(Config::default().handoff_socket().is_none());
+    }
+
+    #[test]
+    fn config_ticket_issuer_is_passed_to_kex() -> anyhow::Result<()> {
+        use libmoshpit::{KexConfig, TicketIssuer};
+        let mut config = Config::default();
+        assert!(KexConfig::ticket_issuer(&config).is_none());
+        let _ = config.set_ticket_issuer(Some(TicketIssuer::new()?));
+        assert!(KexConfig::ticket_issuer(&config).is_some());
+        Ok(())
+    }
+
     #[test]
     fn config_tracing_config_delegates() {
         let config = Config::default();
@@ -443,12 +664,12 @@ mod test {
     }
 
     #[test]
-    fn config_min_protocol_version_defaults_to_build_floor() {
+    fn config_min_protocol_version_defaults_to_identity_proofs() {
         let config = Config::default();
-        // `None` → the trait impl falls back to the build floor.
+        // `None` → the trait impl refuses clients without identity proofs.
         assert_eq!(
             libmoshpit::KexConfig::min_protocol_version(&config),
-            libmoshpit::MIN_PROTOCOL_VERSION
+            libmoshpit::IDENTITY_PROOF_MIN_PROTOCOL_VERSION
         );
         assert!(config.min_protocol_version().is_none());
     }
@@ -0,0 +1,109 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! The server's half of `mp cp`: the hidden `copy-to` and `copy-from`
+//! subcommands, which the daemon runs as the session user in place of a
+//! shell, with their standard streams carried to the client.
+
+#[cfg(unix)]
+use std::process::Command;
+use std::{
+    env::var_os,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use anyhow::Result;
+#[cfg(unix)]
+use anyhow::bail;
+#[cfg(unix)]
+use libmoshpit::FileCopy;
+use libmoshpit::{receive_files, send_files};
+use tokio::io::{stdin, stdout};
+
+use crate::cli::Commands;
+
+/// Run the copy named by `command` over standard input and output, reporting
+/// each entry that could not be copied on standard error.  Exits with status
+/// 1 when anything could not be copied.
+///
+/// # Errors
+/// * The client went away or broke the copy protocol.
+pub(crate) async fn run(command: &Commands) -> Result<()> {
+    let outcome = match command {
+        Commands::CopyTo { target } => {
+            receive_files(&resolve(target), stdin(), stdout(), |_, _, _| {}).await?
+        }
+        Commands::CopyFrom { recursive, sources } => {
+            let sources: Vec<PathBuf> = sources.iter().map(|source| resolve(source)).collect();
+            send_files(&sources, *recursive, stdin(), stdout(), |_, _, _| {}).await?
+        }
+        #[cfg(unix)]
+        Commands::Reap { .. } => bail!("the reaper copies nothing"),
+    };
+    for error in outcome.errors() {
+        eprintln!("{error}");
+    }
+    if outcome.not_copied() > 0 {
+        exit(1);
+    }
+    Ok(())
+}
+
+/// The command running this build's half of `copy`.
+#[cfg(unix)]
+pub(crate) fn helper_command(copy: &FileCopy) -> Command {
+    // Fall back to the name on `PATH` when the executable cannot be found,
+    // e.g. after it was replaced on disk.
+    let exe = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("mps"));
+    let mut cmd = Command::new(exe);
+    let _ = match copy {
+        FileCopy::Upload { target } => cmd.arg("copy-to").arg("--").arg(target),
+        FileCopy::Download { sources, recursive } => {
+            let _ = cmd.arg("copy-from");
+            if *recursive {
+                let _ = cmd.arg("--recursive");
+            }
+            cmd.arg("--").args(sources)
+        }
+    };
+    cmd
+}
+
+/// A path from the client, where `~` stands for the home directory as it
+/// does for `scp`.  Other relative paths are relative to the home directory,
+/// the helper's working directory, and an empty one is the directory itself.
+fn resolve(path: &str) -> PathBuf {
+    let home = || var_os("HOME").map_or_else(|| PathBuf::from("."), PathBuf::from);
+    match path {
+        "" => PathBuf::from("."),
+        "~" => home(),
+        _ => match path.strip_prefix("~/") {
+            Some(rest) => home().join(rest),
+            None => Path::new(path).to_path_buf(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::resolve;
+
+    #[test]
+    fn paths_resolve_against_the_home_directory() {
+        assert_eq!(resolve(""), PathBuf::from("."));
+        assert_eq!(resolve("notes.txt"), PathBuf::from("notes.txt"));
+        assert_eq!(resolve("/etc/hosts"), PathBuf::from("/etc/hosts"));
+        if let Some(home) = std::env::var_os("HOME") {
+            assert_eq!(resolve("~/src"), PathBuf::from(&home).join("src"));
+            assert_eq!(resolve("~"), PathBuf::from(home));
+        }
+    }
+}
@@ -0,0 +1,1165 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Handing the listening sockets and live sessions to a new `mps`, so that
+//! upgrading the package does not end them.
+//!
+//! With `handoff_socket` set, `mps` listens on that Unix socket.  A new `mps`
+//! started with `--take-over` connects and greets it; the old one pauses
+//! every PTY reader, so the snapshot misses none of the output read so far,
+//! and sends
+//!
+//! 1. a manifest with the ticket issuer's key and live tickets, carrying the
+//!    TCP listener and any UDP handshake socket, then
+//! 2. one [`HandedOffSession`] per PTY session — its screen, scrollback and
+//!    login records — carrying the PTY master and its logind session's fd.
+//!
+//! Each message is a little-endian `u32` length and a bincode body; the file
+//! descriptors ride along with the length as `SCM_RIGHTS`.  Once the new
+//! `mps` acknowledges, the old one tells its clients to reconnect and execs
+//! `mps reap`, letting go of everything but the sessions' programs, which
+//! now belong to the new one; the clients resume there, their resumption
+//! tickets still good.  If the hand-off fails before the acknowledgement,
+//! the old `mps` carries on.
+//!
+//! The programs stay the old process's children, so the reaper stays behind
+//! to wait for them, sending the new `mps` one [`Reaped`] message on the
+//! hand-off connection as it lets go of its sockets and one as each program
+//! ends.  It passes on what the reaper before it reports, so the sessions of
+//! several upgrades in a row still learn how their programs end.
+//!
+//! Piped remote commands, which have no PTY, cannot be handed over: the old
+//! `mps` refuses to hand off while any run.
+
+use std::{
+    collections::BTreeMap,
+    env::current_exe,
+    fmt,
+    fs::{DirBuilder, File, Permissions, remove_dir, remove_file, rename, set_permissions},
+    io::{self, IoSlice, IoSliceMut, Read, Write as _},
+    mem,
+    net::{TcpListener as StdTcpListener, UdpSocket as StdUdpSocket},
+    os::{
+        fd::{AsFd as _, AsRawFd, FromRawFd as _, OwnedFd, RawFd},
+        unix::{
+            fs::{DirBuilderExt as _, PermissionsExt as _},
+            net::{UnixListener as StdUnixListener, UnixStream},
+            process::{CommandExt as _, ExitStatusExt as _},
+        },
+    },
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus as StdExitStatus, id},
+    sync::{
+        Arc, Mutex as StdMutex, MutexGuard, PoisonError,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread::{self, sleep},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context as _, Result, bail};
+use bincode_next::{Decode, Encode, config::standard, decode_from_slice, encode_to_vec};
+use libmoshpit::{
+    DiffMode, ExitStatus, SessionRegistry, TicketIssuer, TicketIssuerState, UdpHandshakeListener,
+    UuidWrapper,
+};
+#[cfg(target_os = "linux")]
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+use nix::unistd::getpeereid;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+use nix::unistd::getuid;
+use nix::{
+    cmsg_space,
+    errno::Errno,
+    sys::socket::{ControlMessage, ControlMessageOwned, MsgFlags, recv, recvmsg, sendmsg},
+};
+use tokio::{
+    net::{TcpListener, UnixListener},
+    runtime::Handle,
+    sync::{Mutex, oneshot},
+    task::spawn_blocking,
+    time::timeout,
+};
+use tracing::{info, warn};
+
+#[cfg(target_os = "linux")]
+use crate::{logind::LogindSession, utmp::UtmpSession};
+use crate::{
+    record::{Recorder, RecordingHandoff},
+    session::FullSessionRegistry,
+};
+
+/// What the new `mps` says first, so the old one knows what it is talking to.
+const GREETING: &[u8; 8] = b"mps-hof1";
+/// What the new `mps` says once it holds everything.
+const ACK: u8 = 0x06;
+/// Most file descriptors one message carries.
+const MAX_FDS: usize = 2;
+/// Largest message body either side accepts.
+const MAX_MESSAGE_BYTES: u32 = 16 * 1024 * 1024;
+/// Longest either side waits on the other.
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest the old `mps` waits for its PTY readers to pause.
+const PAUSE_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a PTY reader waits for output before looking for a pause.
+const READER_POLL_MS: i32 = 100;
+
+/// Set while the old `mps` takes its snapshot; PTY readers stop reading.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+/// How many PTY readers have stopped for the pause.
+static STOPPED: AtomicUsize = AtomicUsize::new(0);
+/// Where the ends of the sessions' programs go.
+static ENDS: StdMutex<Ends> = StdMutex::new(Ends {
+    successor: None,
+    predecessor: None,
+    adopted: BTreeMap::new(),
+});
+
+/// Who hears how the sessions' programs end.
+#[derive(Debug)]
+struct Ends {
+    /// The `mps` the sessions were handed to, once they were.
+    successor: Option<UnixStream>,
+    /// The hand-off connection from the old `mps`, while its reaper reports.
+    predecessor: Option<RawFd>,
+    /// The PTY readers of the adopted sessions, by the pid of their program.
+    adopted: BTreeMap<u32, mpsc::Sender<ExitStatus>>,
+}
+
+/// What the reaper left behind by a hand-off tells the new `mps`.
+#[derive(Debug, Decode, Encode)]
+enum Reaped {
+    /// It has let go of the old `mps`'s sockets.
+    Released,
+    /// The program `pid` ended with `status`.
+    Exited { pid: u32, status: ExitStatus },
+}
+
+/// What a PTY session's thread shares with a hand-off: the PTY, the program
+/// on it and the records of its login.
+pub(crate) struct PtyState {
+    /// The PTY master, owned by the session's thread.
+    pub(crate) master: RawFd,
+    pub(crate) child_pid: u32,
+    /// The PTY's terminal name relative to `/dev`, e.g. `pts/3`.
+    pub(crate) tty: String,
+    pub(crate) diff_mode: DiffMode,
+    pub(crate) remote_command: bool,
+    #[cfg(target_os = "linux")]
+    pub(crate) logind: Option<LogindSession>,
+    #[cfg(target_os = "linux")]
+    pub(crate) utmp: Option<UtmpSession>,
+    pub(crate) recorder: Option<Arc<Mutex<Recorder>>>,
+}
+
+impl PtyState {
+    /// The program has ended: write its logout records, then release its
+    /// logind session.
+    pub(crate) fn release(self) {
+        #[cfg(target_os = "linux")]
+        if let Some(session) = &self.utmp
+            && let Err(e) = crate::utmp::logout(session)
+        {
+            warn!("utmp/wtmp logout record failed: {e:#}");
+        }
+        // Closing the fifo releases the logind session.
+        drop(self);
+    }
+}
+
+impl fmt::Debug for PtyState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PtyState")
+            .field("master", &self.master)
+            .field("child_pid", &self.child_pid)
+            .field("tty", &self.tty)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A session's [`PtyState`], filled in by its PTY thread once the program
+/// runs and taken back when it ends.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PtySlot(Arc<StdMutex<Option<PtyState>>>);
+
+impl PtySlot {
+    pub(crate) fn fill(&self, state: PtyState) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(state);
+    }
+
+    pub(crate) fn take(&self) -> Option<PtyState> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).take()
+    }
+
+    fn is_filled(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+    }
+}
+
+/// Reads a PTY master, stopping while a hand-off takes its snapshot.
+pub(crate) struct PausableReader {
+    inner: Box<dyn Read + Send>,
+    fd: RawFd,
+}
+
+impl PausableReader {
+    /// Read `inner`, a reader of the PTY master `fd`.
+    pub(crate) fn new(inner: Box<dyn Read + Send>, fd: RawFd) -> Self {
+        Self { inner, fd }
+    }
+}
+
+impl Read for PausableReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if PAUSED.load(Ordering::Acquire) {
+                let _ = STOPPED.fetch_add(1, Ordering::AcqRel);
+                while PAUSED.load(Ordering::Acquire) {
+                    sleep(Duration::from_millis(10));
+                }
+                let _ = STOPPED.fetch_sub(1, Ordering::AcqRel);
+            } else if wait_readable(self.fd, READER_POLL_MS)? {
+                return self.inner.read(buf);
+            }
+        }
+    }
+}
+
+/// Wait up to `timeout_ms` for `fd` to have something to read, returning
+/// whether it has.  A hang-up or error counts: the read reports it.
+#[allow(unsafe_code)]
+fn wait_readable(fd: RawFd, timeout_ms: i32) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `pollfd` is one valid entry that outlives the call.
+    match unsafe { libc::poll(&raw mut pollfd, 1, timeout_ms) } {
+        0 => Ok(false),
+        n if n < 0 => {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(e)
+            }
+        }
+        _ => Ok(true),
+    }
+}
+
+/// Set the terminal size of the PTY master `fd`.
+#[allow(unsafe_code)]
+pub(crate) fn resize(fd: RawFd, rows: u16, cols: u16) -> io::Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: `size` is a valid winsize that outlives the call.
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &raw const size) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Bytes that redraw `parser`'s screen, and the input modes its program
+/// set, on a fresh parser of the same size.
+pub(crate) fn screen_state(parser: &vt100::Parser) -> Vec<u8> {
+    let screen = parser.screen();
+    let mut state = Vec::new();
+    if screen.alternate_screen() {
+        state.extend_from_slice(b"\x1b[?1049h");
+    }
+    state.extend(screen.contents_formatted());
+    state.extend(screen.input_mode_formatted());
+    state
+}
+
+/// The first message: everything the new `mps` needs besides the sessions.
+#[derive(Debug, Decode, Encode)]
+struct Manifest {
+    tickets: Option<TicketIssuerState>,
+    /// Whether a UDP handshake socket follows the TCP listener.
+    udp_handshake: bool,
+    /// How many [`HandedOffSession`] messages follow.
+    sessions: u32,
+}
+
+/// One PTY session as the old `mps` hands it over.
+#[derive(Debug, Decode, Encode)]
+pub(crate) struct HandedOffSession {
+    pub(crate) uuid: UuidWrapper,
+    pub(crate) user: String,
+    pub(crate) name: Option<String>,
+    pub(crate) rows: u16,
+    pub(crate) cols: u16,
+    /// Redraws the screen on a fresh emulator; see [`screen_state`].
+    pub(crate) screen: Vec<u8>,
+    pub(crate) scrollback: Vec<u8>,
+    /// Seconds since the Unix epoch.
+    pub(crate) created: u64,
+    /// Seconds since the Unix epoch.
+    pub(crate) last_attach: u64,
+    pub(crate) title: String,
+    child_pid: u32,
+    tty: String,
+    diff_mode: u8,
+    pub(crate) remote_command: bool,
+    /// The logind session's id and runtime path; its fd rides along.
+    logind: Option<(String, String)>,
+    /// Whether the login is in utmp.
+    utmp: bool,
+    recording: Option<RecordingHandoff>,
+}
+
+impl HandedOffSession {
+    pub(crate) fn diff_mode(&self) -> DiffMode {
+        match self.diff_mode {
+            1 => DiffMode::Datagram,
+            2 => DiffMode::StateSync,
+            _ => DiffMode::Reliable,
+        }
+    }
+}
+
+/// A session the new `mps` took over.
+#[derive(Debug)]
+pub(crate) struct AdoptedSession {
+    pub(crate) session: HandedOffSession,
+    pub(crate) master: File,
+    /// For the session's [`PtySlot`]; its `master` is `master`'s.
+    pub(crate) state: PtyState,
+}
+
+/// Everything the new `mps` took over.
+#[derive(Debug)]
+pub(crate) struct TakenOver {
+    pub(crate) listener: StdTcpListener,
+    pub(crate) udp_socket: Option<StdUdpSocket>,
+    pub(crate) tickets: Option<TicketIssuerState>,
+    pub(crate) sessions: Vec<AdoptedSession>,
+    /// The connection to the old `mps`, closed when it exits.
+    pub(crate) old: UnixStream,
+}
+
+/// Seconds since the Unix epoch of `time`.
+pub(crate) fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// The time `secs` seconds after the Unix epoch.
+pub(crate) fn from_epoch_secs(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Listen for a new `mps` on `path`, replacing a socket left behind by an
+/// `mps` that is gone.
+///
+/// The socket is bound inside a directory only this user can enter and
+/// narrowed to mode 0600 there before it is moved to `path`, so nobody else
+/// can connect in the moment between binding it and restricting it.
+///
+/// # Errors
+/// * Another `mps` is listening on `path`.
+/// * The socket cannot be created.
+pub(crate) fn listen(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            bail!(
+                "another mps is listening on {}; start this one with --take-over",
+                path.display()
+            );
+        }
+        remove_file(path)?;
+    }
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let private = parent.join(format!(".mps-handoff.{}", id()));
+    DirBuilder::new().mode(0o700).create(&private)?;
+    let staged = private.join("socket");
+    let bound = StdUnixListener::bind(&staged).and_then(|listener| {
+        set_permissions(&staged, Permissions::from_mode(0o600))?;
+        rename(&staged, path)?;
+        Ok(listener)
+    });
+    if bound.is_err() {
+        let _removed = remove_file(&staged);
+    }
+    remove_dir(&private)?;
+    let listener = bound?;
+    listener.set_nonblocking(true)?;
+    Ok(UnixListener::from_std(listener)?)
+}
+
+/// Hand everything to the new `mps` on `stream`.  On success the caller
+/// must become the reaper of its sessions' programs, which the new `mps`
+/// now runs, with [`exec_reaper`].
+///
+/// # Errors
+/// * The peer is not an `mps` of this user.
+/// * A piped remote command is running.
+/// * The PTY readers do not pause in time.
+/// * The peer does not acknowledge.
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub(crate) async fn serve(
+    stream: tokio::net::UnixStream,
+    listener: &TcpListener,
+    udp_listener: Option<&UdpHandshakeListener>,
+    tickets: Option<TicketIssuer>,
+    session_registry: SessionRegistry,
+    full_registry: FullSessionRegistry,
+) -> Result<()> {
+    let stream = stream.into_std()?;
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(HANDOFF_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDOFF_TIMEOUT))?;
+    let listener = listener.as_fd().try_clone_to_owned()?;
+    let udp_socket = udp_listener
+        .map(|udp| udp.as_fd().try_clone_to_owned())
+        .transpose()?;
+    let runtime = Handle::current();
+    spawn_blocking(move || {
+        hand_off(
+            &stream,
+            &listener,
+            udp_socket.as_ref(),
+            tickets.as_ref(),
+            &session_registry,
+            &full_registry,
+            &runtime,
+        )
+    })
+    .await?
+}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+fn hand_off(
+    stream: &UnixStream,
+    listener: &OwnedFd,
+    udp_socket: Option<&OwnedFd>,
+    tickets: Option<&TicketIssuer>,
+    session_registry: &SessionRegistry,
+    full_registry: &FullSessionRegistry,
+    runtime: &Handle,
+) -> Result<()> {
+    check_peer(stream)?;
+    let mut greeting = [0u8; GREETING.len()];
+    (&*stream).read_exact(&mut greeting)?;
+    if &greeting != GREETING {
+        bail!("the peer is not an mps taking over");
+    }
+    let piped = full_registry
+        .blocking_lock()
+        .values()
+        .filter(|record| !record.pty.is_filled())
+        .count();
+    if piped > 0 {
+        bail!("{piped} sessions without a PTY cannot be handed over; try again once they end");
+    }
+    let successor = stream.try_clone()?;
+
+    let pause = Pause::start();
+    let readers = full_registry
+        .blocking_lock()
+        .values()
+        .filter(|record| record.pty.is_filled())
+        .count();
+    Pause::wait_for(readers)?;
+
+    let sessions = snapshot(session_registry, full_registry);
+    let manifest = Manifest {
+        tickets: tickets.map(|tickets| runtime.block_on(tickets.export())),
+        udp_handshake: udp_socket.is_some(),
+        sessions: u32::try_from(sessions.len())?,
+    };
+    let mut fds = vec![listener.as_raw_fd()];
+    fds.extend(udp_socket.map(AsRawFd::as_raw_fd));
+    send_message(stream, &manifest, &fds)?;
+    for (session, fds) in &sessions {
+        send_message(stream, session, fds)?;
+    }
+
+    let mut ack = [0u8];
+    (&*stream).read_exact(&mut ack)?;
+    if ack[0] != ACK {
+        bail!("the new mps did not take the sessions");
+    }
+    info!("handed {} sessions to the new mps", sessions.len());
+    ends().successor = Some(successor);
+    pause.keep();
+    Ok(())
+}
+
+/// Every PTY session with the file descriptors that go with it.
+fn snapshot(
+    session_registry: &SessionRegistry,
+    full_registry: &FullSessionRegistry,
+) -> Vec<(HandedOffSession, Vec<RawFd>)> {
+    let entries = session_registry.blocking_lock().clone();
+    let records = full_registry.blocking_lock();
+    let mut sessions = Vec::new();
+    for (uuid, record) in records.iter() {
+        let Some(entry) = entries.get(uuid) else {
+            continue;
+        };
+        let state = record.pty.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(state) = state.as_ref() else {
+            continue;
+        };
+        let (rows, cols, screen) = {
+            let emulator = record.server_emulator.blocking_lock();
+            let (rows, cols) = emulator.screen().size();
+            (rows, cols, screen_state(&emulator))
+        };
+        let mut fds = vec![state.master];
+        #[cfg(target_os = "linux")]
+        let logind = state.logind.as_ref().map(|logind| {
+            fds.push(logind.fifo().as_raw_fd());
+            (logind.session_id.clone(), logind.runtime_path.clone())
+        });
+        #[cfg(not(target_os = "linux"))]
+        let logind = None;
+        #[cfg(target_os = "linux")]
+        let utmp = state.utmp.is_some();
+        #[cfg(not(target_os = "linux"))]
+        let utmp = false;
+        let session = HandedOffSession {
+            uuid: UuidWrapper::new(*uuid),
+            user: entry.user().clone(),
+            name: entry.name().clone(),
+            rows,
+            cols,
+            screen,
+            scrollback: record.scrollback.blocking_lock().iter().copied().collect(),
+            created: epoch_secs(record.created),
+            last_attach: epoch_secs(record.last_attach),
+            title: record.title.clone(),
+            child_pid: state.child_pid,
+            tty: state.tty.clone(),
+            diff_mode: match state.diff_mode {
+                DiffMode::Reliable => 0,
+                DiffMode::Datagram => 1,
+                DiffMode::StateSync => 2,
+            },
+            remote_command: state.remote_command,
+            logind,
+            utmp,
+            recording: state
+                .recorder
+                .as_ref()
+                .and_then(|recorder| recorder.blocking_lock().handoff()),
+        };
+        sessions.push((session, fds));
+    }
+    sessions
+}
+
+/// Keeps the PTY readers paused until dropped, or for good once kept.
+struct Pause {
+    kept: bool,
+}
+
+impl Pause {
+    fn start() -> Self {
+        PAUSED.store(true, Ordering::Release);
+        Self { kept: false }
+    }
+
+    /// Wait for `readers` PTY readers to stop.
+    fn wait_for(readers: usize) -> Result<()> {
+        let deadline = Instant::now() + PAUSE_TIMEOUT;
+        while STOPPED.load(Ordering::Acquire) < readers {
+            if Instant::now() >= deadline {
+                bail!(
+                    "only {} of {readers} sessions paused in time",
+                    STOPPED.load(Ordering::Acquire)
+                );
+            }
+            sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    /// Leave the readers paused: their sessions are the new `mps`'s now.
+    fn keep(mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for Pause {
+    fn drop(&mut self) {
+        if !self.kept {
+            PAUSED.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Take everything over from the `mps` listening on `path`.
+///
+/// # Errors
+/// * Nothing is listening on `path`, or not an `mps` of this user.
+/// * The old `mps` gives up, e.g. because its sessions did not pause.
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub(crate) async fn take_over(path: &Path) -> Result<TakenOver> {
+    let path = path.to_path_buf();
+    spawn_blocking(move || {
+        let old = UnixStream::connect(&path)
+            .with_context(|| format!("no mps to take over on {}", path.display()))?;
+        receive(old)
+    })
+    .await?
+}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+fn receive(old: UnixStream) -> Result<TakenOver> {
+    old.set_read_timeout(Some(HANDOFF_TIMEOUT))?;
+    old.set_write_timeout(Some(HANDOFF_TIMEOUT))?;
+    check_peer(&old)?;
+    (&old).write_all(GREETING)?;
+
+    let (manifest, fds): (Manifest, _) =
+        receive_message(&old).context("the old mps refused the hand-off; its log says why")?;
+    let mut fds = fds.into_iter();
+    let listener = StdTcpListener::from(fds.next().context("no listening socket handed over")?);
+    let udp_socket = if manifest.udp_handshake {
+        let socket = fds.next().context("no UDP handshake socket handed over")?;
+        Some(StdUdpSocket::from(socket))
+    } else {
+        None
+    };
+    let mut sessions = Vec::new();
+    for _ in 0..manifest.sessions {
+        let (session, fds) = receive_message(&old)?;
+        sessions.push(adopt(session, fds)?);
+    }
+
+    (&old).write_all(&[ACK])?;
+    info!("took over {} sessions", sessions.len());
+    Ok(TakenOver {
+        listener,
+        udp_socket,
+        tickets: manifest.tickets,
+        sessions,
+        old,
+    })
+}
+
+/// Take over `session`'s PTY and login records.  A recording that cannot
+/// carry on is dropped rather than the session.
+fn adopt(mut session: HandedOffSession, fds: Vec<OwnedFd>) -> Result<AdoptedSession> {
+    let mut fds = fds.into_iter();
+    let master = File::from(fds.next().context("session handed over without its PTY")?);
+    let uuid = session.uuid.as_uuid();
+    let recorder =
+        session
+            .recording
+            .take()
+            .and_then(|recording| match Recorder::resume(recording) {
+                Ok(recorder) => Some(Arc::new(Mutex::new(recorder))),
+                Err(e) => {
+                    warn!(session = %uuid, "cannot carry on recording: {e:#}");
+                    None
+                }
+            });
+    #[cfg(target_os = "linux")]
+    let logind = session
+        .logind
+        .take()
+        .zip(fds.next())
+        .map(|((session_id, runtime_path), fifo)| {
+            LogindSession::adopt(session_id, runtime_path, fifo)
+        });
+    #[cfg(target_os = "linux")]
+    let utmp = session
+        .utmp
+        .then(|| crate::utmp::adopt(&session.tty, session.child_pid));
+    let state = PtyState {
+        master: master.as_raw_fd(),
+        child_pid: session.child_pid,
+        tty: session.tty.clone(),
+        diff_mode: session.diff_mode(),
+        remote_command: session.remote_command,
+        #[cfg(target_os = "linux")]
+        logind,
+        #[cfg(target_os = "linux")]
+        utmp,
+        recorder,
+    };
+    Ok(AdoptedSession {
+        session,
+        master,
+        state,
+    })
+}
+
+/// Hear from the old `mps`'s reaper on `old` how the adopted sessions'
+/// programs end, returning once it has let go of the old `mps`'s sockets.
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub(crate) async fn follow(old: UnixStream) {
+    let (released_tx, released_rx) = oneshot::channel();
+    let _relay = thread::spawn(move || relay(old, released_tx));
+    // An old `mps` without a reaper lets go by exiting, which drops the
+    // sender as well.
+    if timeout(HANDOFF_TIMEOUT, released_rx).await.is_err() {
+        warn!("the old mps did not let go of its sockets in time; carrying on");
+    }
+}
+
+/// Pass on what the reaper on `predecessor` reports: to the readers of the
+/// adopted sessions, or to the next `mps` once they are its.
+fn relay(predecessor: UnixStream, released: oneshot::Sender<()>) {
+    let mut released = Some(released);
+    if let Err(e) = predecessor.set_read_timeout(None) {
+        warn!("the old mps's reports may time out: {e}");
+    }
+    ends().predecessor = Some(predecessor.as_raw_fd());
+    while let Some((reaped, mut ends)) = next_reaped(&predecessor) {
+        match reaped {
+            Reaped::Released => {
+                if let Some(released) = released.take() {
+                    let _ = released.send(());
+                }
+            }
+            Reaped::Exited { pid, status } => {
+                if let Some(successor) = &ends.successor {
+                    if let Err(e) = send_message(successor, &reaped, &[]) {
+                        warn!("cannot tell the new mps that program {pid} ended: {e:#}");
+                    }
+                } else if let Some(reader) = ends.adopted.remove(&pid) {
+                    let _ = reader.send(status);
+                }
+            }
+        }
+    }
+    let mut ends = ends();
+    ends.predecessor = None;
+    drop(predecessor);
+    // Nothing is left to report how the remaining programs end.
+    ends.adopted.clear();
+}
+
+/// The next report from `predecessor`, with [`ENDS`] locked to act on it.
+fn next_reaped(predecessor: &UnixStream) -> Option<(Reaped, MutexGuard<'static, Ends>)> {
+    // Peek before locking: a report that comes while this `mps` execs its
+    // own reaper stays on the connection for that reaper to pass on.
+    let mut byte = [0u8];
+    loop {
+        match recv(predecessor.as_raw_fd(), &mut byte, MsgFlags::MSG_PEEK) {
+            Ok(0) => return None,
+            Ok(_) => break,
+            Err(Errno::EINTR) => {}
+            Err(_) => return None,
+        }
+    }
+    let ends = ends();
+    let (reaped, _) = receive_message(predecessor).ok()?;
+    Some((reaped, ends))
+}
+
+/// How the adopted program `pid` ends, as the old `mps`'s reaper reports it.
+/// The sender is dropped if that is never known.
+pub(crate) fn program_end(pid: u32) -> mpsc::Receiver<ExitStatus> {
+    let (status_tx, status_rx) = mpsc::channel();
+    drop(ends().adopted.insert(pid, status_tx));
+    status_rx
+}
+
+/// Wait for `child`, a session's program, to end and reap it.  Once its
+/// session has been handed off, how it ended goes to the new `mps` instead
+/// and this returns `None`.
+pub(crate) fn reap(mut child: Child) -> Option<ExitStatus> {
+    let pid = child.id();
+    // Wait without reaping first: a program that ends while this `mps` execs
+    // its reaper is left for the reaper.
+    wait_exited(pid).ok()?;
+    let ends = ends();
+    let status = ExitStatus::from(child.wait().ok()?);
+    let Some(successor) = &ends.successor else {
+        return Some(status);
+    };
+    if let Err(e) = send_message(successor, &Reaped::Exited { pid, status }, &[]) {
+        warn!("cannot tell the new mps that program {pid} ended: {e:#}");
+    }
+    None
+}
+
+/// Wait for the child `pid` to end, leaving it to be reaped.
+#[allow(unsafe_code)]
+fn wait_exited(pid: u32) -> io::Result<()> {
+    loop {
+        // SAFETY: `siginfo_t` is plain data, which `waitid` fills in.
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        // SAFETY: `info` is valid for writes.
+        let waited = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid,
+                &raw mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if waited == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Become the reaper of the programs just handed off by execing `mps reap`,
+/// which lets go of every other file descriptor, the sockets above all.
+/// Returns only when there is nothing to reap or the exec fails, in which
+/// case the programs are left to `init`.
+///
+/// # Errors
+/// * The reaper cannot be started.
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub(crate) fn exec_reaper() -> Result<()> {
+    // Held through the exec, so each program's end is reported either by
+    // this `mps` or by its reaper.
+    let ends = ends();
+    let Some(successor) = &ends.successor else {
+        return Ok(());
+    };
+    let mut reaper = Command::new(reaper_exe());
+    let _ = reaper
+        .arg0("mps")
+        .arg("reap")
+        .arg(inherit(successor.as_raw_fd())?.to_string());
+    if let Some(predecessor) = ends.predecessor {
+        let _ = reaper
+            .arg("--predecessor")
+            .arg(inherit(predecessor)?.to_string());
+    }
+    Err(reaper.exec().into())
+}
+
+/// The executable to exec as the reaper: this very one, even if it has been
+/// replaced on disk since.
+fn reaper_exe() -> PathBuf {
+    if cfg!(target_os = "linux") {
+        PathBuf::from("/proc/self/exe")
+    } else {
+        current_exe().unwrap_or_else(|_| PathBuf::from("mps"))
+    }
+}
+
+/// Let `fd` through an exec.
+#[allow(unsafe_code)]
+fn inherit(fd: RawFd) -> io::Result<RawFd> {
+    // SAFETY: `fd` is open; clearing its flags touches nothing else.
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+/// Run as the reaper left behind by a hand-off: reap this process's
+/// children, the handed-off sessions' programs, reporting each end on
+/// `successor`, and pass on what the reaper before reports on `predecessor`.
+/// Returns once both are done.
+///
+/// # Errors
+/// * `successor` is not an open hand-off connection.
+#[allow(unsafe_code)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub(crate) fn reap_handed_off(successor: RawFd, predecessor: Option<RawFd>) -> Result<()> {
+    let successor = UnixStream::from(own_fd(successor)?);
+    let predecessor = predecessor.map(own_fd).transpose()?.map(UnixStream::from);
+    send_message(&successor, &Reaped::Released, &[])?;
+    let successor = StdMutex::new(successor);
+    let report = |reaped: &Reaped| {
+        let successor = successor.lock().unwrap_or_else(PoisonError::into_inner);
+        // Nobody is left to tell if the new `mps` has gone, but the
+        // programs still need reaping.
+        drop(send_message(&successor, reaped, &[]));
+    };
+    thread::scope(|scope| {
+        if let Some(predecessor) = &predecessor {
+            let _relay = scope.spawn(|| {
+                while let Ok((reaped, _)) = receive_message::<Reaped>(predecessor) {
+                    if matches!(reaped, Reaped::Exited { .. }) {
+                        report(&reaped);
+                    }
+                }
+            });
+        }
+        loop {
+            let mut status = 0;
+            // SAFETY: `status` is valid for writes.
+            let pid = unsafe { libc::waitpid(-1, &raw mut status, 0) };
+            if let Ok(pid) = u32::try_from(pid) {
+                let status = ExitStatus::from(StdExitStatus::from_raw(status));
+                report(&Reaped::Exited { pid, status });
+            } else if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                // No children are left.
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// The hand-off state, whichever thread poisoned it.
+fn ends() -> MutexGuard<'static, Ends> {
+    ENDS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Refuse a peer running as another user.
+#[cfg(target_os = "linux")]
+fn check_peer(stream: &UnixStream) -> Result<()> {
+    let peer = getsockopt(stream, PeerCredentials)?;
+    if peer.uid() != getuid().as_raw() {
+        bail!("refusing a hand-off with a process of uid {}", peer.uid());
+    }
+    Ok(())
+}
+
+/// Refuse a peer running as another user.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn check_peer(stream: &UnixStream) -> Result<()> {
+    let (uid, _gid) = getpeereid(stream)?;
+    if uid != getuid() {
+        bail!("refusing a hand-off with a process of uid {uid}");
+    }
+    Ok(())
+}
+
+/// Only this user can reach the socket, which is created with mode 0600.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+#[allow(clippy::unnecessary_wraps)]
+fn check_peer(_stream: &UnixStream) -> Result<()> {
+    Ok(())
+}
+
+/// Send `message` with `fds` attached.
+fn send_message<T: Encode>(stream: &UnixStream, message: &T, fds: &[RawFd]) -> Result<()> {
+    let body = encode_to_vec(message, standard())?;
+    let len = u32::try_from(body.len())?.to_le_bytes();
+    let rights = [ControlMessage::ScmRights(fds)];
+    let cmsgs: &[ControlMessage<'_>] = if fds.is_empty() { &[] } else { &rights };
+    let sent = sendmsg::<()>(
+        stream.as_raw_fd(),
+        &[IoSlice::new(&len)],
+        cmsgs,
+        MsgFlags::empty(),
+        None,
+    )?;
+    (&*stream).write_all(&len[sent..])?;
+    (&*stream).write_all(&body)?;
+    Ok(())
+}
+
+/// Receive a message sent by [`send_message`] and the file descriptors that
+/// came with it.
+fn receive_message<T: Decode<()>>(stream: &UnixStream) -> Result<(T, Vec<OwnedFd>)> {
+    let mut len = [0u8; 4];
+    let mut cmsg = cmsg_space!([RawFd; MAX_FDS]);
+    let mut fds = Vec::new();
+    let read = {
+        let mut iov = [IoSliceMut::new(&mut len)];
+        let message = recvmsg::<()>(
+            stream.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg),
+            MsgFlags::empty(),
+        )?;
+        for cmsg in message.cmsgs()? {
+            if let ControlMessageOwned::ScmRights(received) = cmsg {
+                for fd in received {
+                    fds.push(own_fd(fd)?);
+                }
+            }
+        }
+        if message.flags.contains(MsgFlags::MSG_CTRUNC) {
+            bail!("too many file descriptors in a hand-off message");
+        }
+        message.bytes
+    };
+    if read == 0 {
+        bail!("the other mps closed the hand-off socket");
+    }
+    (&*stream).read_exact(&mut len[read..])?;
+    let len = u32::from_le_bytes(len);
+    if len > MAX_MESSAGE_BYTES {
+        bail!("hand-off message of {len} bytes is too large");
+    }
+    let mut body = vec![0u8; usize::try_from(len)?];
+    (&*stream).read_exact(&mut body)?;
+    let (message, _) = decode_from_slice(&body, standard())?;
+    Ok((message, fds))
+}
+
+/// Own a file descriptor received with a message or inherited by the
+/// reaper, keeping it out of the programs this `mps` starts.
+#[allow(unsafe_code)]
+fn own_fd(fd: RawFd) -> Result<OwnedFd> {
+    // SAFETY: the kernel just installed `fd` in this process, and nothing
+    // else knows of it.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    // SAFETY: `fd` is open.
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(fd)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env::temp_dir,
+        fs::{create_dir_all, metadata, read_dir, remove_dir_all},
+        io::{Read as _, Write as _},
+        os::{
+            fd::AsRawFd as _,
+            unix::{fs::PermissionsExt as _, net::UnixStream},
+        },
+        process::{Command, id},
+        thread,
+    };
+
+    use anyhow::Result;
+    use libmoshpit::ExitStatus;
+    use tokio::sync::oneshot;
+
+    use super::{
+        Reaped, listen, program_end, reap, receive_message, relay, screen_state, send_message,
+    };
+
+    #[test]
+    fn screen_state_redraws_the_screen_and_its_modes() {
+        let mut parser = vt100::Parser::new(24, 80, 0);
+        parser.process(b"\x1b[?1049h\x1b[?1h\x1b[31mhello\x1b[0m\r\nworld");
+        let mut restored = vt100::Parser::new(24, 80, 0);
+        restored.process(&screen_state(&parser));
+
+        let (before, after) = (parser.screen(), restored.screen());
+        assert!(after.alternate_screen());
+        assert!(after.application_cursor());
+        assert_eq!(after.contents(), before.contents());
+        assert_eq!(after.cursor_position(), before.cursor_position());
+        assert_eq!(
+            after.cell(0, 0).map(vt100::Cell::fgcolor),
+            before.cell(0, 0).map(vt100::Cell::fgcolor)
+        );
+    }
+
+    #[tokio::test]
+    async fn listen_creates_a_private_socket_in_place() -> Result<()> {
+        let dir = temp_dir().join(format!("moshpit-handoff-test-{}", id()));
+        create_dir_all(&dir)?;
+        let path = dir.join("handoff.sock");
+        let listener = listen(&path)?;
+
+        assert_eq!(metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        assert_eq!(read_dir(&dir)?.count(), 1, "the staging directory is gone");
+        let _client = UnixStream::connect(&path)?;
+        let _accepted = listener.accept().await?;
+        assert!(listen(&path).is_err(), "a live socket is not replaced");
+
+        drop(listener);
+        remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn messages_carry_their_file_descriptors() -> Result<()> {
+        let (old, new) = UnixStream::pair()?;
+        let (passed, kept) = UnixStream::pair()?;
+        send_message(&old, &String::from("hello"), &[passed.as_raw_fd()])?;
+        drop(passed);
+
+        let (message, mut fds): (String, _) = receive_message(&new)?;
+        assert_eq!(message, "hello");
+        assert_eq!(fds.len(), 1);
+        let mut received = UnixStream::from(fds.remove(0));
+        received.write_all(b"ping")?;
+        let mut buf = [0u8; 4];
+        (&kept).read_exact(&mut buf)?;
+        assert_eq!(&buf, b"ping");
+        Ok(())
+    }
+
+    #[test]
+    fn reap_returns_how_the_program_ended() -> Result<()> {
+        let child = Command::new("/bin/sh").args(["-c", "exit 3"]).spawn()?;
+        assert_eq!(reap(child), Some(ExitStatus::Code(3)));
+        Ok(())
+    }
+
+    #[test]
+    fn relay_tells_each_adopted_reader_how_its_program_ended() -> Result<()> {
+        let (reaper, new) = UnixStream::pair()?;
+        let ended = program_end(4_000_001);
+        let running = program_end(4_000_002);
+        let (released_tx, released_rx) = oneshot::channel();
+        let relay = thread::spawn(move || relay(new, released_tx));
+
+        send_message(&reaper, &Reaped::Released, &[])?;
+        released_rx.blocking_recv()?;
+        let status = ExitStatus::Signal(9);
+        send_message(
+            &reaper,
+            &Reaped::Exited {
+                pid: 4_000_001,
+                status,
+            },
+            &[],
+        )?;
+        assert_eq!(ended.recv()?, status);
+
+        // Once the reaper is gone, no end is coming.
+        drop(reaper);
+        relay.join().expect("the relay does not panic");
+        assert!(running.recv().is_err());
+        Ok(())
+    }
+}
@@ -19,6 +19,8 @@
 //! `libpam`, so this works in a fully static MUSL binary (`libpam` `dlopen`s
 //! glibc modules and cannot be statically linked).
 
+use std::os::fd::{AsFd as _, BorrowedFd, OwnedFd as StdOwnedFd};
+
 use anyhow::{Context as _, Result};
 use zbus::zvariant::{OwnedFd, OwnedObjectPath, Value};
 
@@ -64,14 +66,32 @@ trait Manager {
 
 /// A live logind session.
 ///
-/// The session stays registered for as long as `_fifo` is held open; dropping
+/// The session stays registered for as long as `fifo` is held open; dropping
 /// this value closes the fd, which tells logind to release the session (the same
 /// lifetime contract `pam_systemd` relies on).  `mps` keeps it alive in the PTY
-/// thread for the duration of the login shell.
+/// thread for the duration of the login shell, and passes the fd on to a new
+/// `mps` taking the session over.
 pub(crate) struct LogindSession {
     pub(crate) session_id: String,
     pub(crate) runtime_path: String,
-    _fifo: OwnedFd,
+    fifo: OwnedFd,
+}
+
+impl LogindSession {
+    /// Take over a session a previous `mps` registered, along with the fd
+    /// that keeps it registered.
+    pub(crate) fn adopt(session_id: String, runtime_path: String, fifo: StdOwnedFd) -> Self {
+        Self {
+            session_id,
+            runtime_path,
+            fifo: OwnedFd::from(fifo),
+        }
+    }
+
+    /// The fd that keeps the session registered.
+    pub(crate) fn fifo(&self) -> BorrowedFd<'_> {
+        self.fifo.as_fd()
+    }
 }
 
 /// Register a logind session whose scope leader is `leader_pid` (the login
@@ -116,7 +136,7 @@ pub(crate) fn create_session(
     Ok(LogindSession {
         session_id,
         runtime_path,
-        _fifo: fifo,
+        fifo,
     })
 }
 
@@ -240,8 +240,12 @@ use crate::runtime::run;
 
 mod cli;
 mod config;
+mod copy;
+#[cfg(unix)]
+mod handoff;
 #[cfg(target_os = "linux")]
 mod logind;
+mod record;
 mod runtime;
 mod session;
 #[cfg(target_os = "linux")]
@@ -0,0 +1,608 @@
+// Copyright (c) 2025 moshpit developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Session recording: each session's terminal output, resizes and —
+//! optionally — keyboard input, written as [asciicast v2] files that
+//! `asciinema play` can replay.
+//!
+//! A recording is split into parts of at most `record_file_max_bytes`, each
+//! a complete asciicast file with its own header, and stops for good once the
+//! session has written `record_session_max_bytes`.  A recording handed to a
+//! new `mps` carries on there in a part of its own.
+//!
+//! [asciicast v2]: https://docs.asciinema.org/manual/asciicast/v2/
+
+#[cfg(unix)]
+use std::{
+    ffi::OsString,
+    os::unix::{
+        ffi::{OsStrExt as _, OsStringExt as _},
+        fs::{DirBuilderExt as _, MetadataExt as _, OpenOptionsExt as _},
+    },
+};
+use std::{
+    fs::{DirBuilder, File, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+    str::from_utf8,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, bail};
+#[cfg(unix)]
+use bincode_next::{Decode, Encode};
+use serde_json::json;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::Config;
+
+/// Where and how much of each session to record.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct RecordSettings {
+    /// The directory the recordings go in.
+    pub dir: PathBuf,
+    /// Whether keyboard input is recorded along with the output.
+    pub input: bool,
+    /// Size at which a recording moves on to its next part.
+    pub file_max_bytes: u64,
+    /// Size at which a session's recording stops.
+    pub session_max_bytes: u64,
+}
+
+impl RecordSettings {
+    /// The recording settings of `config`, or `None` when recording is off.
+    pub(crate) fn from_config(config: &Config) -> Option<Self> {
+        config.record_dir().as_ref().map(|dir| Self {
+            dir: dir.clone(),
+            input: config.record_input(),
+            file_max_bytes: config.record_file_max_bytes(),
+            session_max_bytes: config.record_session_max_bytes(),
+        })
+    }
+}
+
+/// Who and what a recording is of, written to the header of every part.
+#[derive(Clone, Debug)]
+pub(crate) struct RecordedSession {
+    /// The session's user.
+    pub user: String,
+    /// The address of the client that started the session.
+    pub client_ip: Option<String>,
+    /// The session.
+    pub session_uuid: Uuid,
+    /// The shell the session runs.
+    pub shell: String,
+    /// The session's `TERM`.
+    pub term_type: String,
+}
+
+/// Create the recording directory if it is missing and check that only the
+/// daemon's user can write to it, so nobody else can read, alter or remove
+/// the recordings.
+///
+/// # Errors
+/// * The directory cannot be created or examined.
+/// * It is owned by another user, or group or others can write to it.
+#[allow(unsafe_code)]
+pub(crate) fn prepare_dir(dir: &Path) -> Result<()> {
+    let mut builder = DirBuilder::new();
+    let _ = builder.recursive(true);
+    #[cfg(unix)]
+    let _ = builder.mode(0o700);
+    builder.create(dir)?;
+    let metadata = dir.metadata()?;
+    if !metadata.is_dir() {
+        bail!("{} is not a directory", dir.display());
+    }
+    #[cfg(unix)]
+    {
+        let daemon_uid = unsafe { libc::getuid() };
+        if metadata.uid() != daemon_uid {
+            bail!(
+                "{} is owned by uid {}, not the daemon's uid {daemon_uid}",
+                dir.display(),
+                metadata.uid()
+            );
+        }
+        if metadata.mode() & 0o022 != 0 {
+            bail!("{} is writable by group or others", dir.display());
+        }
+    }
+    Ok(())
+}
+
+/// A recording as one `mps` hands it to the next.
+#[cfg(unix)]
+#[derive(Clone, Debug, Decode, Encode, Eq, PartialEq)]
+pub(crate) struct RecordingHandoff {
+    dir: Vec<u8>,
+    input: bool,
+    file_max_bytes: u64,
+    session_max_bytes: u64,
+    user: String,
+    client_ip: Option<String>,
+    session_uuid: [u8; 16],
+    shell: String,
+    term_type: String,
+    /// The part being written when the recording was handed over.
+    part: u32,
+    session_bytes: u64,
+    rows: u16,
+    cols: u16,
+}
+
+/// Records one session.  Fed by the session's PTY threads; a write error
+/// stops the recording rather than the session.
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    settings: RecordSettings,
+    session: RecordedSession,
+    /// The part being written, `None` once the recording has stopped.
+    file: Option<File>,
+    part: u32,
+    part_start: Instant,
+    file_bytes: u64,
+    /// The size of the current part's header.
+    header_bytes: u64,
+    session_bytes: u64,
+    /// The terminal size as rows and columns, for the next part's header.
+    size: (u16, u16),
+    /// The start of a UTF-8 sequence split across PTY reads.
+    output_carry: Vec<u8>,
+    /// The start of a UTF-8 sequence split across input messages.
+    input_carry: Vec<u8>,
+}
+
+impl Recorder {
+    /// Start recording `session`, whose terminal is `rows` by `cols`.
+    ///
+    /// # Errors
+    /// * The recording directory is unusable (see [`prepare_dir`]).
+    /// * The first part cannot be created.
+    pub(crate) fn start(
+        settings: RecordSettings,
+        session: RecordedSession,
+        rows: u16,
+        cols: u16,
+    ) -> Result<Self> {
+        prepare_dir(&settings.dir)?;
+        let mut recorder = Self {
+            settings,
+            session,
+            file: None,
+            part: 0,
+            part_start: Instant::now(),
+            file_bytes: 0,
+            header_bytes: 0,
+            session_bytes: 0,
+            size: (rows, cols),
+            output_carry: Vec::new(),
+            input_carry: Vec::new(),
+        };
+        recorder.open_part()?;
+        Ok(recorder)
+    }
+
+    /// The recording's state for handing to a new `mps`, or `None` once it
+    /// has stopped.
+    #[cfg(unix)]
+    pub(crate) fn handoff(&self) -> Option<RecordingHandoff> {
+        let _ = self.file.as_ref()?;
+        Some(RecordingHandoff {
+            dir: self.settings.dir.as_os_str().as_bytes().to_vec(),
+            input: self.settings.input,
+            file_max_bytes: self.settings.file_max_bytes,
+            session_max_bytes: self.settings.session_max_bytes,
+            user: self.session.user.clone(),
+            client_ip: self.session.client_ip.clone(),
+            session_uuid: self.session.session_uuid.into_bytes(),
+            shell: self.session.shell.clone(),
+            term_type: self.session.term_type.clone(),
+            part: self.part,
+            session_bytes: self.session_bytes,
+            rows: self.size.0,
+            cols: self.size.1,
+        })
+    }
+
+    /// Carry on a recording handed over by a previous `mps`, starting the
+    /// part after the one it was writing.
+    ///
+    /// # Errors
+    /// * The recording directory has become unusable (see [`prepare_dir`]).
+    /// * The next part cannot be created.
+    #[cfg(unix)]
+    pub(crate) fn resume(state: RecordingHandoff) -> Result<Self> {
+        let settings = RecordSettings {
+            dir: PathBuf::from(OsString::from_vec(state.dir)),
+            input: state.input,
+            file_max_bytes: state.file_max_bytes,
+            session_max_bytes: state.session_max_bytes,
+        };
+        prepare_dir(&settings.dir)?;
+        let mut recorder = Self {
+            settings,
+            session: RecordedSession {
+                user: state.user,
+                client_ip: state.client_ip,
+                session_uuid: Uuid::from_bytes(state.session_uuid),
+                shell: state.shell,
+                term_type: state.term_type,
+            },
+            file: None,
+            part: state.part + 1,
+            part_start: Instant::now(),
+            file_bytes: 0,
+            header_bytes: 0,
+            session_bytes: state.session_bytes,
+            size: (state.rows, state.cols),
+            output_carry: Vec::new(),
+            input_carry: Vec::new(),
+        };
+        recorder.open_part()?;
+        Ok(recorder)
+    }
+
+    /// Record terminal output.
+    pub(crate) fn output(&mut self, data: &[u8]) {
+        let text = decode_utf8(&mut self.output_carry, data);
+        self.event("o", &text);
+    }
+
+    /// Record keyboard input, if the settings ask for it.
+    pub(crate) fn input(&mut self, data: &[u8]) {
+        if self.settings.input {
+            let text = decode_utf8(&mut self.input_carry, data);
+            self.event("i", &text);
+        }
+    }
+
+    /// Record a resize of the terminal to `rows` by `cols`.
+    pub(crate) fn resize(&mut self, rows: u16, cols: u16) {
+        self.size = (rows, cols);
+        self.event("r", &format!("{cols}x{rows}"));
+    }
+
+    /// The file the recording currently goes to.
+    fn part_path(&self) -> PathBuf {
+        let user: String = self
+            .session
+            .user
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let uuid = self.session.session_uuid;
+        if self.part == 0 {
+            self.settings.dir.join(format!("{user}-{uuid}.cast"))
+        } else {
+            self.settings
+                .dir
+                .join(format!("{user}-{uuid}.{}.cast", self.part))
+        }
+    }
+
+    /// Create the current part and write its header.
+    fn open_part(&mut self) -> Result<()> {
+        let path = self.part_path();
+        let mut options = OpenOptions::new();
+        let _ = options.write(true).create_new(true);
+        #[cfg(unix)]
+        let _ = options.mode(0o600);
+        let mut file = options.open(&path)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let (rows, cols) = self.size;
+        let header = json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": timestamp,
+            "env": { "SHELL": self.session.shell, "TERM": self.session.term_type },
+            "title": format!("{} session {}", self.session.user, self.session.session_uuid),
+            "moshpit": {
+                "user": self.session.user,
+                "client_ip": self.session.client_ip,
+                "session": self.session.session_uuid.to_string(),
+                "part": self.part,
+            },
+        });
+        let line = format!("{header}\n");
+        file.write_all(line.as_bytes())?;
+        info!(session = %self.session.session_uuid, "recording to {}", path.display());
+        let len = byte_len(&line);
+        self.file = Some(file);
+        self.part_start = Instant::now();
+        self.file_bytes = len;
+        self.header_bytes = len;
+        self.session_bytes += len;
+        Ok(())
+    }
+
+    /// Append one event, moving on to the next part or stopping the recording
+    /// when it would outgrow its limits.
+    fn event(&mut self, kind: &str, data: &str) {
+        if self.file.is_none() || data.is_empty() {
+            return;
+        }
+        let line = self.event_line(kind, data);
+        let len = byte_len(&line);
+        if self.session_bytes + len > self.settings.session_max_bytes {
+            let marker = self.event_line("m", "recording stopped: session size limit reached");
+            warn!(
+                session = %self.session.session_uuid,
+                "recording reached its size limit; no longer recording"
+            );
+            self.write(&marker);
+            self.file = None;
+            return;
+        }
+        // A part holds at least one event, however low the limit.
+        if self.file_bytes + len > self.settings.file_max_bytes
+            && self.file_bytes > self.header_bytes
+        {
+            self.part += 1;
+            if let Err(e) = self.open_part() {
+                warn!(
+                    session = %self.session.session_uuid,
+                    "cannot start the next recording part: {e}; no longer recording"
+                );
+                self.file = None;
+                return;
+            }
+        }
+        let line = self.event_line(kind, data);
+        self.write(&line);
+    }
+
+    /// An event line, timed from the start of the current part.
+    fn event_line(&self, kind: &str, data: &str) -> String {
+        let elapsed = (self.part_start.elapsed().as_secs_f64() * 1e6).round() / 1e6;
+        format!("{}\n", json!([elapsed, kind, data]))
+    }
+
+    fn write(&mut self, line: &str) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            warn!(
+                session = %self.session.session_uuid,
+                "cannot write recording: {e}; no longer recording"
+            );
+            self.file = None;
+            return;
+        }
+        let len = byte_len(line);
+        self.file_bytes += len;
+        self.session_bytes += len;
+    }
+}
+
+fn byte_len(line: &str) -> u64 {
+    u64::try_from(line.len()).unwrap_or(u64::MAX)
+}
+
+/// Decode `data` after the bytes `carry` held back from the previous call,
+/// holding back a UTF-8 sequence cut off at the end.  Invalid bytes become
+/// U+FFFD.
+fn decode_utf8(carry: &mut Vec<u8>, data: &[u8]) -> String {
+    carry.extend_from_slice(data);
+    let mut text = String::new();
+    let mut rest = carry.as_slice();
+    loop {
+        match from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                rest = &[];
+                break;
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                text.push_str(from_utf8(valid).unwrap_or_default());
+                if let Some(invalid) = e.error_len() {
+                    text.push(char::REPLACEMENT_CHARACTER);
+                    rest = &after[invalid..];
+                } else {
+                    rest = after;
+                    break;
+                }
+            }
+        }
+    }
+    let held = rest.to_vec();
+    *carry = held;
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env::temp_dir,
+        fs::{read_dir, read_to_string, remove_dir_all},
+        path::Path,
+        process::id,
+    };
+
+    use uuid::Uuid;
+
+    use super::{RecordSettings, RecordedSession, Recorder, decode_utf8};
+
+    fn settings(name: &str, file_max_bytes: u64, session_max_bytes: u64) -> RecordSettings {
+        let dir = temp_dir().join(format!("moshpit-record-test-{name}-{}", id()));
+        drop(remove_dir_all(&dir));
+        RecordSettings {
+            dir,
+            input: false,
+            file_max_bytes,
+            session_max_bytes,
+        }
+    }
+
+    fn session() -> RecordedSession {
+        RecordedSession {
+            user: "alice".to_string(),
+            client_ip: Some("192.0.2.7".to_string()),
+            session_uuid: Uuid::nil(),
+            shell: "/bin/bash".to_string(),
+            term_type: "xterm-256color".to_string(),
+        }
+    }
+
+    fn lines(path: &Path) -> Vec<serde_json::Value> {
+        read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn decode_utf8_holds_back_a_split_sequence() {
+        let mut carry = Vec::new();
+        let euro = "€".as_bytes();
+        assert_eq!(decode_utf8(&mut carry, &[b'a', euro[0], euro[1]]), "a");
+        assert_eq!(carry, &euro[..2]);
+        assert_eq!(decode_utf8(&mut carry, &[euro[2], b'b']), "€b");
+        assert!(carry.is_empty());
+    }
+
+    #[test]
+    fn decode_utf8_replaces_invalid_bytes() {
+        let mut carry = Vec::new();
+        assert_eq!(decode_utf8(&mut carry, b"a\xffb"), "a\u{fffd}b");
+        assert!(carry.is_empty());
+    }
+
+    #[test]
+    fn recording_has_header_and_events() {
+        let settings = settings("events", 1 << 20, 1 << 20);
+        let dir = settings.dir.clone();
+        let mut recorder = Recorder::start(settings, session(), 24, 80).unwrap();
+        recorder.output(b"hello\r\n");
+        recorder.input(b"ls\r");
+        recorder.resize(40, 120);
+        drop(recorder);
+
+        let path = dir.join(format!("alice-{}.cast", Uuid::nil()));
+        let lines = lines(&path);
+        assert_eq!(lines.len(), 3, "input is not recorded unless asked for");
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(lines[0]["width"], 80);
+        assert_eq!(lines[0]["height"], 24);
+        assert_eq!(lines[0]["env"]["TERM"], "xterm-256color");
+        assert_eq!(lines[0]["moshpit"]["user"], "alice");
+        assert_eq!(lines[0]["moshpit"]["client_ip"], "192.0.2.7");
+        assert_eq!(lines[0]["moshpit"]["session"], Uuid::nil().to_string());
+        assert_eq!(lines[1][1], "o");
+        assert_eq!(lines[1][2], "hello\r\n");
+        assert_eq!(lines[2][1], "r");
+        assert_eq!(lines[2][2], "120x40");
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recording_input_when_asked() {
+        let mut settings = settings("input", 1 << 20, 1 << 20);
+        settings.input = true;
+        let dir = settings.dir.clone();
+        let mut recorder = Recorder::start(settings, session(), 24, 80).unwrap();
+        recorder.input(b"ls\r");
+        drop(recorder);
+
+        let lines = lines(&dir.join(format!("alice-{}.cast", Uuid::nil())));
+        assert_eq!(lines[1][1], "i");
+        assert_eq!(lines[1][2], "ls\r");
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recording_moves_to_a_new_part_with_its_own_header() {
+        let settings = settings("parts", 400, 1 << 20);
+        let dir = settings.dir.clone();
+        let mut recorder = Recorder::start(settings, session(), 24, 80).unwrap();
+        recorder.resize(30, 100);
+        for _ in 0..10 {
+            recorder.output(&[b'x'; 64]);
+        }
+        drop(recorder);
+
+        assert!(read_dir(&dir).unwrap().count() > 1);
+        let second = lines(&dir.join(format!("alice-{}.1.cast", Uuid::nil())));
+        assert_eq!(second[0]["version"], 2);
+        assert_eq!(second[0]["width"], 100);
+        assert_eq!(second[0]["height"], 30);
+        assert_eq!(second[0]["moshpit"]["part"], 1);
+        assert_eq!(second[1][1], "o");
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn handed_off_recording_carries_on_in_the_next_part() {
+        let settings = settings("handoff", 1 << 20, 1 << 20);
+        let dir = settings.dir.clone();
+        let mut recorder = Recorder::start(settings, session(), 24, 80).unwrap();
+        recorder.resize(30, 100);
+        let state = recorder.handoff().unwrap();
+        drop(recorder);
+
+        let mut recorder = Recorder::resume(state).unwrap();
+        recorder.output(b"still here");
+        drop(recorder);
+
+        let second = lines(&dir.join(format!("alice-{}.1.cast", Uuid::nil())));
+        assert_eq!(second[0]["width"], 100);
+        assert_eq!(second[0]["height"], 30);
+        assert_eq!(second[0]["moshpit"]["part"], 1);
+        assert_eq!(second[0]["moshpit"]["client_ip"], "192.0.2.7");
+        assert_eq!(second[1][2], "still here");
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recording_stops_at_the_session_limit() {
+        let settings = settings("limit", 1 << 20, 600);
+        let dir = settings.dir.clone();
+        let mut recorder = Recorder::start(settings, session(), 24, 80).unwrap();
+        for _ in 0..20 {
+            recorder.output(&[b'x'; 64]);
+        }
+        drop(recorder);
+
+        let lines = lines(&dir.join(format!("alice-{}.cast", Uuid::nil())));
+        let last = lines.last().unwrap();
+        assert_eq!(last[1], "m");
+        assert!(lines.len() < 21);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn recording_refuses_a_directory_others_can_write() {
+        use std::{
+            fs::{Permissions, create_dir_all, set_permissions},
+            os::unix::fs::PermissionsExt as _,
+        };
+
+        let settings = settings("open", 1 << 20, 1 << 20);
+        let dir = settings.dir.clone();
+        create_dir_all(&dir).unwrap();
+        set_permissions(&dir, Permissions::from_mode(0o777)).unwrap();
+        assert!(Recorder::start(settings, session(), 24, 80).is_err());
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -10,14 +10,17 @@ use std::{
     collections::{BTreeSet, VecDeque},
     env::args_os,
     ffi::OsString,
-    io::Read,
+    future::pending,
+    io::{Cursor, Read, Write},
     net::SocketAddr,
+    path::PathBuf,
     sync::{
         Arc,
         atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc,
     },
-    thread::{self, sleep},
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    thread::{self, JoinHandle, sleep},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 #[cfg(unix)]
@@ -27,26 +30,38 @@ use std::{
     io::Error,
     os::unix::{fs::OpenOptionsExt as _, process::CommandExt},
     path::Path,
-    process::{Command, Stdio},
+    process::{ChildStderr, ChildStdin, ChildStdout, Command, Stdio},
 };
 
+#[cfg(not(unix))]
+use std::convert::Infallible;
 #[cfg(target_os = "linux")]
-use std::fs::{File, metadata};
+use std::fs::metadata;
+#[cfg(unix)]
+use std::{fs::File, os::fd::AsRawFd as _};
 
-use anyhow::{Context as _, Result};
+use anyhow::{Context as _, Result, bail};
 use bytes::{Buf as _, BytesMut};
 use libmoshpit::{
-    DiffMode, EncryptedFrame, KexMode, MAX_UDP_PAYLOAD, MoshpitError, NegotiatedTransport,
-    SessionRegistry, TcpTransportReader, TcpTransportSender, TerminalMessage, UdpReader, UdpSender,
+    ConnectionReader, ConnectionWriter, DiffMode, EncryptedFrame, ExitStatus, ForwardMux,
+    ForwardPolicy, ForwardRole, KexMode, KeyDirection, MAX_UDP_PAYLOAD, MoshpitError,
+    NegotiatedTransport, PORT_FORWARDING_MIN_PROTOCOL_VERSION,
+    PTY_EXIT_STATUS_MIN_PROTOCOL_VERSION, RemoteCommand, SessionRegistry, TcpTransportReader,
+    TcpTransportSender, TerminalMessage, TicketIssuer, UdpHandshakeListener, UdpReader, UdpSender,
     UuidWrapper, env_var_matches, init_tracing, is_exit_title, load, new_session_registry,
-    run_key_exchange,
+    run_key_exchange_over,
 };
+#[cfg(unix)]
+use libmoshpit::{ForwardUser, SessionEntry};
 #[cfg(windows)]
 use portable_pty::CommandBuilder;
 use portable_pty::{PtySize, native_pty_system};
 
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::runtime::Handle;
 use tokio::{
-    net::{TcpListener, TcpStream},
+    net::TcpListener,
     select,
     signal::ctrl_c,
     spawn,
@@ -65,9 +80,17 @@ use zstd::encode_all;
 use crate::{
     cli::Cli,
     config::Config,
+    record::{RecordSettings, RecordedSession, Recorder, prepare_dir},
     session::{
-        FullSessionRegistry, SCROLLBACK_CAPACITY, SessionOutputHandle, SessionRecord,
-        new_full_registry,
+        ClientOutput, FullSessionRegistry, SCROLLBACK_CAPACITY, SessionOutputHandle, SessionRecord,
+        SessionSummary, TitleTracker, format_session_list, new_full_registry,
+    },
+};
+#[cfg(unix)]
+use crate::{
+    cli::Commands,
+    handoff::{
+        AdoptedSession, PausableReader, PtySlot, PtyState, TakenOver, from_epoch_secs, resize,
     },
 };
 
@@ -135,6 +158,14 @@ const MAX_STATESYNC_DIFF_BYTES: usize = 900;
 const STATE_CHUNK_SIZE: usize = 800;
 /// How long with no UDP frame received from the client before the server cancels the connection.
 const CLIENT_SILENCE_TIMEOUT_US: u64 = 30_000_000;
+/// Interval between resends of a remote command's `CommandExit` until the client acks it.
+const COMMAND_EXIT_RESEND_INTERVAL: Duration = Duration::from_millis(200);
+/// How long a finished remote command's session waits for a disconnected client to
+/// come back for its exit status before ending anyway.
+const COMMAND_EXIT_LINGER: Duration = Duration::from_mins(2);
+/// How long a shell that ended its session with an exit title gets to exit before the
+/// client is told the session ended without a status.
+const SHELL_EXIT_WAIT: Duration = Duration::from_secs(2);
 
 /// Current time as microseconds since the UNIX epoch.
 fn now_micros() -> u64 {
@@ -148,6 +179,7 @@ fn now_micros() -> u64 {
 }
 
 #[allow(unsafe_code)]
+#[cfg_attr(nightly, allow(clippy::too_many_lines))]
 #[cfg_attr(coverage_nightly, coverage(off))]
 pub(crate) async fn run<I, T>(args: Option<I>) -> Result<()>
 where
@@ -162,6 +194,21 @@ where
     };
     let cli = Cli::parse_argv(command_line)?;
 
+    // The copy helpers talk to `mp cp` over standard streams: no config or
+    // tracing, whose output would end up in the copy.
+    if let Some(command) = cli.command() {
+        // Nor does the reaper a hand-off leaves behind need either.
+        #[cfg(unix)]
+        if let Commands::Reap {
+            successor,
+            predecessor,
+        } = command
+        {
+            return crate::handoff::reap_handed_off(*successor, *predecessor);
+        }
+        return crate::copy::run(command).await;
+    }
+
     #[cfg(unix)]
     if unsafe { libc::getuid() } == 0 {
         info!("Running as root (multi-user mode enabled)");
@@ -198,12 +245,26 @@ where
                 libmoshpit::PROTOCOL_VERSION,
             );
         }
+        if support.min < libmoshpit::IDENTITY_PROOF_MIN_PROTOCOL_VERSION {
+            warn!(
+                "accepting clients older than v{}, which log in without proving \
+                 they hold their identity key",
+                libmoshpit::IDENTITY_PROOF_MIN_PROTOCOL_VERSION,
+            );
+        }
         info!(
             "accepting wire protocol versions v{}..=v{}",
             support.min, support.max
         );
     }
 
+    // Refuse to start with a recording directory sessions could not use.
+    if let Some(dir) = config.record_dir() {
+        prepare_dir(dir)
+            .with_context(|| format!("unusable recording directory {}", dir.display()))?;
+        info!("recording sessions in {}", dir.display());
+    }
+
     let socket_addr = SocketAddr::new(
         config
             .mps()
@@ -213,7 +274,58 @@ where
         config.mps().port(),
     );
     let _ = config.set_mode(KexMode::Server(socket_addr));
-    let listener = TcpListener::bind(socket_addr).await?;
+
+    // A new `mps` takes the sockets and sessions over from the running one
+    // rather than binding afresh.
+    #[cfg(unix)]
+    let taken_over = if cli.take_over() {
+        let Some(path) = config.handoff_socket() else {
+            bail!("--take-over needs the handoff_socket of the mps to take over from");
+        };
+        Some(crate::handoff::take_over(path).await?)
+    } else {
+        None
+    };
+    #[cfg(not(unix))]
+    if cli.take_over() {
+        bail!("--take-over is not supported on this platform");
+    }
+
+    #[cfg(unix)]
+    let (listener, mut udp_listener, handed_tickets, adopted, old_mps) = match taken_over {
+        Some(TakenOver {
+            listener,
+            udp_socket,
+            tickets,
+            sessions,
+            old,
+        }) => {
+            listener.set_nonblocking(true)?;
+            let udp_listener = match udp_socket.filter(|_| config.udp_handshake()) {
+                Some(socket) => Some(UdpHandshakeListener::from_std(socket)?),
+                None => bind_udp_handshake(&config, socket_addr).await?,
+            };
+            (
+                TcpListener::from_std(listener)?,
+                udp_listener,
+                tickets,
+                sessions,
+                Some(old),
+            )
+        }
+        None => (
+            TcpListener::bind(socket_addr).await?,
+            bind_udp_handshake(&config, socket_addr).await?,
+            None,
+            Vec::new(),
+            None,
+        ),
+    };
+    #[cfg(not(unix))]
+    let (listener, mut udp_listener) = (
+        TcpListener::bind(socket_addr).await?,
+        bind_udp_handshake(&config, socket_addr).await?,
+    );
 
     let mut port_pool = BTreeSet::new();
     for i in 50000..60000 {
@@ -224,9 +336,47 @@ where
 
     let session_registry = new_session_registry();
     let _ = config.set_session_registry(session_registry);
+    if config.resumption_tickets() {
+        // Tickets issued by the previous `mps` stay good.
+        #[cfg(unix)]
+        let issuer = match &handed_tickets {
+            Some(state) => TicketIssuer::import(state)?,
+            None => TicketIssuer::new()?,
+        };
+        #[cfg(not(unix))]
+        let issuer = TicketIssuer::new()?;
+        let _ = config.set_ticket_issuer(Some(issuer));
+    }
     let full_registry = new_full_registry();
 
+    #[cfg(unix)]
+    {
+        adopt_sessions(adopted, &config, &full_registry).await;
+        // The old `mps` holds its connections' UDP ports until it lets go.
+        if let Some(old) = old_mps {
+            crate::handoff::follow(old).await;
+        }
+    }
+    #[cfg(unix)]
+    let handoff_listener = match config.handoff_socket() {
+        Some(path) => {
+            let handoff_listener = crate::handoff::listen(path)
+                .with_context(|| format!("cannot listen for hand-offs on {}", path.display()))?;
+            info!("accepting hand-offs on {}", path.display());
+            Some(handoff_listener)
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    let handoff_listener: Option<Infallible> = None;
+    #[cfg(not(unix))]
+    if config.handoff_socket().is_some() {
+        warn!("handoff_socket is not supported on this platform, ignoring it");
+    }
+
     let server_token = CancellationToken::new();
+    #[cfg(unix)]
+    let mut handed_off = false;
 
     loop {
         let config_c = config.clone();
@@ -252,10 +402,44 @@ where
                             Ok(a) => a,
                             Err(e) => { error!("local_addr: {e}"); continue; }
                         };
+                        // Client IP for the logind session's remote-host marker (best-effort).
+                        let remote_host = socket.peer_addr().ok().map(|addr| addr.ip().to_string());
+                        let (sock_read, sock_write) = socket.into_split();
+                        let reader = ConnectionReader::builder().reader(sock_read).build();
+                        let writer = ConnectionWriter::builder().writer(sock_write).build();
                         let mut config_conn = config_c;
                         let _ = config_conn.set_mode(KexMode::Server(tcp_local_addr));
                         let _conn = spawn(async move {
-                            if let Err(e) = handle_connection(config_conn, socket, st, fr_c).await {
+                            if let Err(e) = handle_connection(config_conn, reader, writer, remote_host, st, fr_c).await {
+                                error!("{e}");
+                            }
+                        });
+                    }
+                    Err(e) => error!("{e}"),
+                }
+            }
+            handoff = accept_handoff(handoff_listener.as_ref()) => {
+                if hand_off(handoff, &listener, udp_listener.as_ref(), &config, &full_registry).await {
+                    // The clients reconnect to the new `mps`, where the
+                    // sessions' programs carry on.
+                    server_token.cancel();
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    #[cfg(unix)]
+                    {
+                        handed_off = true;
+                    }
+                    break;
+                }
+            }
+            accept_res = accept_udp_handshake(udp_listener.as_mut()) => {
+                match accept_res {
+                    Ok((reader, writer, peer, local_addr)) => {
+                        info!("UDP handshake from {peer}");
+                        let remote_host = Some(peer.ip().to_string());
+                        let mut config_conn = config_c;
+                        let _ = config_conn.set_mode(KexMode::Server(local_addr));
+                        let _conn = spawn(async move {
+                            if let Err(e) = handle_connection(config_conn, reader, writer, remote_host, st, fr_c).await {
                                 error!("{e}");
                             }
                         });
@@ -265,14 +449,114 @@ where
             }
         }
     }
+    #[cfg(unix)]
+    if handed_off && let Err(e) = crate::handoff::exec_reaper() {
+        error!("cannot stay behind to reap the handed-off sessions' programs: {e:#}");
+    }
     Ok(())
 }
 
+/// Bind the UDP handshake socket on `socket_addr` if the config asks for one.
+async fn bind_udp_handshake(
+    config: &Config,
+    socket_addr: SocketAddr,
+) -> Result<Option<UdpHandshakeListener>> {
+    if !config.udp_handshake() {
+        return Ok(None);
+    }
+    let udp_listener = UdpHandshakeListener::bind(socket_addr).await?;
+    info!("accepting UDP handshakes on {}", udp_listener.local_addr());
+    Ok(Some(udp_listener))
+}
+
+/// Accept the next `mps` taking over from this one, or wait forever without
+/// a hand-off socket.
+#[cfg(unix)]
+async fn accept_handoff(listener: Option<&UnixListener>) -> Result<UnixStream> {
+    let Some(listener) = listener else {
+        return pending().await;
+    };
+    let (stream, _addr) = listener.accept().await?;
+    Ok(stream)
+}
+
+#[cfg(not(unix))]
+async fn accept_handoff(_listener: Option<&Infallible>) -> Result<Infallible> {
+    pending().await
+}
+
+/// Hand the sockets and sessions to the `mps` taking over, returning whether
+/// it took them.  On failure this `mps` carries on as before.
+#[cfg(unix)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+async fn hand_off(
+    stream: Result<UnixStream>,
+    listener: &TcpListener,
+    udp_listener: Option<&UdpHandshakeListener>,
+    config: &Config,
+    full_registry: &FullSessionRegistry,
+) -> bool {
+    let handed_off = match stream {
+        Ok(stream) => {
+            info!("a new mps is taking over");
+            crate::handoff::serve(
+                stream,
+                listener,
+                udp_listener,
+                config.ticket_issuer(),
+                config.session_registry(),
+                full_registry.clone(),
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    match handed_off {
+        Ok(()) => true,
+        Err(e) => {
+            error!("hand-off failed, carrying on: {e:#}");
+            false
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn hand_off(
+    stream: Result<Infallible>,
+    _listener: &TcpListener,
+    _udp_listener: Option<&UdpHandshakeListener>,
+    _config: &Config,
+    _full_registry: &FullSessionRegistry,
+) -> bool {
+    match stream {
+        Ok(never) => match never {},
+        Err(e) => {
+            error!("{e}");
+            false
+        }
+    }
+}
+
+/// Accept the next UDP handshake connection along with the local address the
+/// client reached, or wait forever when UDP handshakes are disabled.
+async fn accept_udp_handshake(
+    listener: Option<&mut UdpHandshakeListener>,
+) -> Result<(ConnectionReader, ConnectionWriter, SocketAddr, SocketAddr)> {
+    let Some(listener) = listener else {
+        return pending().await;
+    };
+    let (reader, writer, peer) = listener.accept().await?;
+    let local_addr = listener.local_addr_for(peer)?;
+    Ok((reader, writer, peer, local_addr))
+}
+
 /// Resolve which session to use for this connection.
 ///
-/// On resume, reconnects to the existing session and sends a `ScreenState` frame
-/// for an instant clean repaint.  On new or expired sessions, creates a fresh
+/// On resume, attaches to the existing session, displacing its other clients
+/// unless this one joins shared, and sends a `ScreenState` frame for an
+/// instant clean repaint.  On new or expired sessions, creates a fresh
 /// session via [`new_session`].
+#[cfg_attr(nightly, allow(clippy::too_many_arguments))]
 async fn resolve_session(
     kex: &libmoshpit::Kex,
     skex: &libmoshpit::ServerKex,
@@ -280,6 +564,7 @@ async fn resolve_session(
     udp_port: u16,
     data_tx: Sender<EncryptedFrame>,
     control_tx: Sender<EncryptedFrame>,
+    port_pool: &Arc<Mutex<BTreeSet<u16>>>,
     full_registry: &FullSessionRegistry,
 ) -> Result<(
     Sender<TerminalMessage>,
@@ -293,8 +578,9 @@ async fn resolve_session(
 )> {
     let session_uuid = skex.session_uuid();
     if skex.is_resume() {
-        let reg = full_registry.lock().await;
-        if let Some(record) = reg.get(&session_uuid) {
+        let mut reg = full_registry.lock().await;
+        if let Some(record) = reg.get_mut(&session_uuid) {
+            record.last_attach = SystemTime::now();
             let term_tx = record.term_tx.clone();
             let output_handle = record.output_handle.clone();
             let scrollback = record.scrollback.clone();
@@ -307,18 +593,21 @@ async fn resolve_session(
             // first tick correctly senses whether diffs are flowing.
             diff_in_flight.store(false, Ordering::Relaxed);
 
-            // Replace the output handle with the new connection's channels.
-            {
-                let mut h = output_handle.lock().await;
-                // Shut down the stale reader/sender for the previous connection.
-                if let Some(old_token) = h.conn_token.take() {
-                    old_token.cancel();
-                }
-                h.kex_uuid = kex.uuid();
-                h.data_tx = Some(data_tx.clone());
-                h.control_tx = Some(control_tx.clone());
-                h.conn_token = Some(conn_token.clone());
-                h.udp_port = Some(udp_port);
+            // Attach the new connection's channels to the output handle.
+            let displaced = output_handle.lock().await.attach(ClientOutput {
+                kex_uuid: kex.uuid(),
+                data_tx: data_tx.clone(),
+                control_tx,
+                conn_token: conn_token.clone(),
+                udp_port,
+                pty_exit_status: kex.protocol_version() >= PTY_EXIT_STATUS_MIN_PROTOCOL_VERSION,
+                shared: skex.shared(),
+                size: None,
+            });
+            // Shut down the stale readers/senders of the connections displaced.
+            for client in displaced {
+                client.conn_token.cancel();
+                let _ = port_pool.lock().await.insert(client.udp_port);
             }
 
             // Send current screen state for an instant clean repaint on reconnect.
@@ -336,6 +625,8 @@ async fn resolve_session(
                 user = skex.user(),
                 session = %session_uuid,
                 screen_state_bytes,
+                shared = skex.shared(),
+                read_only = skex.read_only(),
                 "session resumed"
             );
 
@@ -349,6 +640,10 @@ async fn resolve_session(
                 diff_in_flight,
                 effective_mtu,
             ))
+        } else if skex.shared() {
+            // A session is never started on behalf of someone joining it.
+            drop(reg);
+            Err(anyhow::anyhow!("shared session {session_uuid} has ended"))
         } else {
             // Session expired; start fresh.
             drop(reg);
@@ -392,13 +687,12 @@ async fn resolve_session(
 #[cfg_attr(coverage_nightly, coverage(off))]
 async fn handle_connection(
     config: Config,
-    socket: TcpStream,
+    reader: ConnectionReader,
+    writer: ConnectionWriter,
+    remote_host: Option<String>,
     server_token: CancellationToken,
     full_registry: FullSessionRegistry,
 ) -> Result<()> {
-    // Client IP for the logind session's remote-host marker (best-effort).
-    let remote_host = socket.peer_addr().ok().map(|addr| addr.ip().to_string());
-    let (sock_read, sock_write) = socket.into_split();
     let port_pool = config.port_pool();
     let session_registry = config.session_registry();
     let warmup_delay = config.warmup_delay_ms().map(Duration::from_millis);
@@ -411,7 +705,15 @@ async fn handle_connection(
     let namespace_escape = config.namespace_escape();
     let use_logind = config.use_logind();
     let use_utmp = config.use_utmp();
-    let outcome = run_key_exchange(config, sock_read, sock_write, || Ok(None), None, None).await?;
+    let record = RecordSettings::from_config(&config);
+    let allow_local_forwarding = config.allow_local_forwarding();
+    let allow_dynamic_forwarding = config.allow_dynamic_forwarding();
+    let allow_unix_forwarding = config.allow_unix_forwarding();
+    let allow_agent_forwarding = config.allow_agent_forwarding();
+    let listen_addresses = config
+        .allow_remote_forwarding()
+        .then(|| config.remote_forward_bind_addresses().clone());
+    let outcome = run_key_exchange_over(config, reader, writer, || Ok(None), None, None).await?;
     info!("Key exchange completed with moshpit");
     let libmoshpit::KexOutcome {
         kex,
@@ -426,6 +728,43 @@ async fn handle_connection(
     let session_uuid = skex.session_uuid();
     let diff_mode = skex.diff_mode();
 
+    // Forwarded targets are dialed, and Unix sockets, the agent's included,
+    // created as the session's user when running as root; refuse forwarding
+    // that would act as root outright if that user cannot be resolved.
+    #[cfg(unix)]
+    let (
+        allow_local_forwarding,
+        allow_dynamic_forwarding,
+        allow_unix_forwarding,
+        allow_agent_forwarding,
+        session_user,
+    ) = match forward_user(skex.user()) {
+        Ok(session_user) => (
+            allow_local_forwarding,
+            allow_dynamic_forwarding,
+            allow_unix_forwarding,
+            allow_agent_forwarding,
+            session_user,
+        ),
+        Err(e) => {
+            warn!(
+                user = skex.user(),
+                "disabling local, dynamic, Unix socket and agent forwarding: {e}"
+            );
+            (false, false, false, false, None)
+        }
+    };
+    #[cfg(not(unix))]
+    let session_user = None;
+    let agent_user = session_user.clone();
+    let forward_policy = ForwardPolicy::builder()
+        .allow_open(allow_local_forwarding)
+        .allow_dynamic(allow_dynamic_forwarding)
+        .allow_unix(allow_unix_forwarding)
+        .maybe_listen_addresses(listen_addresses)
+        .maybe_session_user(session_user)
+        .build();
+
     let accepted_client_env: Vec<(String, String)> = skex
         .client_env()
         .iter()
@@ -472,10 +811,45 @@ async fn handle_connection(
         data_port,
         data_tx.clone(),
         control_tx.clone(),
+        &port_pool,
         &full_registry,
     )
     .await?;
 
+    // Forwarded channels belong to the session, not the connection: attach
+    // this connection's data channel so streams opened over an earlier one
+    // carry on over it.  A client joining shared never carries them, so the
+    // session's forwards and agent stay with the client that started it.
+    let forwards =
+        if skex.protocol_version() >= PORT_FORWARDING_MIN_PROTOCOL_VERSION && !skex.shared() {
+            let mut registry = full_registry.lock().await;
+            registry.get_mut(&session_uuid).map(|record| {
+                record
+                    .forwards
+                    .get_or_insert_with(|| ForwardMux::spawn(ForwardRole::Server, forward_policy))
+                    .clone()
+            })
+        } else {
+            None
+        };
+    if let Some(forwards) = &forwards {
+        forwards.attach(data_tx.clone()).await;
+    }
+    let forward_tx = forwards.as_ref().map(ForwardMux::frame_tx);
+    let exit_acked = output_handle.lock().await.exit_acked.clone();
+
+    // The connection's keystrokes and size reach the session through a relay
+    // that applies its read-only mode and the session's size policy.
+    let (client_term_tx, client_term_rx) = channel::<TerminalMessage>(256);
+    let _relay = spawn(relay_client_input(
+        client_term_rx,
+        term_tx.clone(),
+        output_handle.clone(),
+        port_pool.clone(),
+        kex.uuid(),
+        skex.read_only(),
+    ));
+
     let (repaint_tx, mut repaint_rx) = channel::<()>(1);
     let (client_ack_tx, mut client_ack_rx) = channel::<u64>(16);
     let nak_received_count = Arc::new(AtomicU64::new(0));
@@ -495,8 +869,10 @@ async fn handle_connection(
             let mut udp_reader = UdpReader::builder()
                 .socket(udp_recv)
                 .id(kex.uuid())
-                .hmac(kex.build_hmac())
-                .rnk(kex.build_aead_key()?)
+                .maybe_hmac(kex.build_hmac(KeyDirection::ClientToServer)?)
+                .rnk(kex.build_aead_key(KeyDirection::ClientToServer)?)
+                .maybe_ratchet(kex.key_ratchet(KeyDirection::ClientToServer)?)
+                .nonce_scheme(kex.nonce_scheme())
                 .mac_tag_len(mac_tag_len)
                 .nak_out_tx(data_tx.clone())
                 .retransmit_tx(retransmit_tx)
@@ -507,6 +883,8 @@ async fn handle_connection(
                 .diff_mode(diff_mode)
                 .client_ack_tx(client_ack_tx)
                 .last_rx_us(last_rx_us.clone())
+                .maybe_forward_tx(forward_tx)
+                .exit_acked(exit_acked)
                 .build();
             let mut udp_sender = UdpSender::builder()
                 .socket(udp_send)
@@ -514,15 +892,17 @@ async fn handle_connection(
                 .rx(data_rx)
                 .retransmit_rx(retransmit_rx)
                 .id(kex.uuid())
-                .hmac(kex.build_hmac())
-                .rnk(kex.build_aead_key()?)
+                .maybe_hmac(kex.build_hmac(KeyDirection::ServerToClient)?)
+                .rnk(kex.build_aead_key(KeyDirection::ServerToClient)?)
+                .maybe_ratchet(kex.key_ratchet(KeyDirection::ServerToClient)?)
+                .nonce_scheme(kex.nonce_scheme())
                 .peer_discovered_rx(peer_discovered_rx)
                 .peer_addr_rx(peer_addr_rx)
                 .maybe_warmup_delay(warmup_delay)
                 .diff_mode(diff_mode)
                 .build();
             let reader_token = conn_token.clone();
-            let term_tx_c = term_tx.clone();
+            let term_tx_c = client_term_tx;
             let _udp_reader_handle = spawn(async move {
                 if let Err(e) = udp_reader.server_frame_loop(reader_token, term_tx_c).await {
                     error!("{e}");
@@ -534,25 +914,31 @@ async fn handle_connection(
         NegotiatedTransport::Tcp { reader, writer } => {
             let mut tcp_reader = TcpTransportReader::builder()
                 .id(kex.uuid())
-                .hmac(kex.build_hmac())
-                .rnk(kex.build_aead_key()?)
+                .maybe_hmac(kex.build_hmac(KeyDirection::ClientToServer)?)
+                .rnk(kex.build_aead_key(KeyDirection::ClientToServer)?)
+                .maybe_ratchet(kex.key_ratchet(KeyDirection::ClientToServer)?)
+                .nonce_scheme(kex.nonce_scheme())
                 .mac_tag_len(mac_tag_len)
                 .reader(reader)
                 .nak_out_tx(data_tx.clone())
                 .repaint_tx(repaint_tx)
                 .client_ack_tx(client_ack_tx)
                 .last_rx_us(last_rx_us.clone())
+                .maybe_forward_tx(forward_tx)
+                .exit_acked(exit_acked)
                 .build();
             let mut tcp_sender = TcpTransportSender::builder()
                 .id(kex.uuid())
-                .hmac(kex.build_hmac())
-                .rnk(kex.build_aead_key()?)
+                .maybe_hmac(kex.build_hmac(KeyDirection::ServerToClient)?)
+                .rnk(kex.build_aead_key(KeyDirection::ServerToClient)?)
+                .maybe_ratchet(kex.key_ratchet(KeyDirection::ServerToClient)?)
+                .nonce_scheme(kex.nonce_scheme())
                 .writer(writer)
                 .control_rx(control_rx)
                 .rx(data_rx)
                 .build();
             let reader_token = conn_token.clone();
-            let term_tx_c = term_tx.clone();
+            let term_tx_c = client_term_tx;
             let _tcp_reader_handle = spawn(async move {
                 if let Err(e) = tcp_reader.server_frame_loop(reader_token, term_tx_c).await {
                     error!("{e}");
@@ -837,8 +1223,39 @@ async fn handle_connection(
         server_emulator.clone(),
     );
 
+    // A listing prints the user's other sessions in place of a program.
+    if skex.list_sessions() {
+        spawn_session_list(
+            session_uuid,
+            skex.user().to_owned(),
+            forwards,
+            output_handle,
+            port_pool,
+            session_registry,
+            full_registry,
+        );
+        return Ok(());
+    }
+
     // For new sessions, spawn the long-lived PTY thread.
     if let Some(term_rx) = maybe_term_rx {
+        // The forwarded agent's socket lives as long as the session's mux, so
+        // the shell keeps reaching it across reconnects.
+        let agent_socket = match &forwards {
+            Some(forwards) if skex.forward_agent() && allow_agent_forwarding => {
+                match forwards.listen_agent(agent_user.as_ref()) {
+                    Ok(path) => {
+                        info!(session = %session_uuid, "forwarding agent on {}", path.display());
+                        Some(path)
+                    }
+                    Err(e) => {
+                        warn!(session = %session_uuid, "cannot forward agent: {e}");
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
         spawn_pty(
             session_uuid,
             skex.user().to_owned(),
@@ -863,6 +1280,10 @@ async fn handle_connection(
             use_logind,
             use_utmp,
             remote_host,
+            agent_socket,
+            skex.remote_command().clone(),
+            forwards,
+            record,
         );
     }
 
@@ -1145,13 +1566,16 @@ async fn new_session(
     Arc<AtomicUsize>,
 )> {
     let (term_tx, term_rx) = channel::<TerminalMessage>(256);
-    let output_handle = Arc::new(Mutex::new(SessionOutputHandle {
+    let output_handle = Arc::new(Mutex::new(SessionOutputHandle::new(ClientOutput {
         kex_uuid: kex.uuid(),
-        data_tx: Some(data_tx),
-        control_tx: Some(control_tx),
-        conn_token: Some(conn_token.clone()),
-        udp_port: Some(udp_port),
-    }));
+        data_tx,
+        control_tx,
+        conn_token: conn_token.clone(),
+        udp_port,
+        pty_exit_status: kex.protocol_version() >= PTY_EXIT_STATUS_MIN_PROTOCOL_VERSION,
+        shared: false,
+        size: None,
+    })));
     let scrollback = Arc::new(Mutex::new(VecDeque::with_capacity(SCROLLBACK_CAPACITY)));
     let server_emulator = Arc::new(Mutex::new(vt100::Parser::new(24, 80, 0)));
     // Start at 1 so the first sync tick always sends an initial screen state.
@@ -1171,6 +1595,12 @@ async fn new_session(
                 dirty_counter: dirty_counter.clone(),
                 diff_in_flight: diff_in_flight.clone(),
                 effective_mtu: effective_mtu.clone(),
+                forwards: None,
+                created: SystemTime::now(),
+                last_attach: SystemTime::now(),
+                title: String::new(),
+                #[cfg(unix)]
+                pty: PtySlot::default(),
             },
         ));
     }
@@ -1238,7 +1668,8 @@ fn server_intercept_queries(buf: &[u8], rows: u16, cols: u16) -> Vec<u8> {
 }
 
 /// Spawn the background thread that reads PTY output, writes scrollback, and forwards
-/// frames to the currently connected client.  Cleans up session state when the shell exits.
+/// frames to the currently connected client.  Cleans up session state when the shell exits,
+/// and yields the shell's exit status when the reaper reported one.
 #[cfg_attr(nightly, allow(clippy::too_many_arguments, clippy::too_many_lines))]
 #[cfg_attr(coverage_nightly, coverage(off))]
 fn spawn_pty_reader(
@@ -1256,8 +1687,12 @@ fn spawn_pty_reader(
     full_registry: FullSessionRegistry,
     effective_mtu: Arc<AtomicUsize>,
     diff_mode: DiffMode,
-) {
-    let _read_handle = thread::spawn(move || {
+    exit_status: mpsc::Receiver<ExitStatus>,
+    remote_command: bool,
+    recorder: Option<Arc<Mutex<Recorder>>>,
+) -> JoinHandle<Option<ExitStatus>> {
+    thread::spawn(move || {
+        let mut title_tracker = TitleTracker::default();
         loop {
             let mut buffer = BytesMut::zeroed(4096);
             match term_out.read(&mut buffer) {
@@ -1280,10 +1715,20 @@ fn spawn_pty_reader(
                         sb.extend(buf_slice.iter().copied());
                     }
 
+                    if let Some(recorder) = &recorder {
+                        recorder.blocking_lock().output(buf_slice);
+                    }
+
+                    if let Some(title) = title_tracker.feed(buf_slice)
+                        && let Some(record) = full_registry.blocking_lock().get_mut(&session_uuid)
+                    {
+                        record.title = title;
+                    }
+
                     server_emulator.blocking_lock().process(buf_slice);
                     let _ = dirty_counter.fetch_add(1, Ordering::Relaxed);
 
-                    let send_ok = {
+                    let failed = {
                         let h = output_handle.blocking_lock();
                         if diff_mode == DiffMode::StateSync {
                             // StateSync: statesync task handles delivery; only feed emulator.
@@ -1296,55 +1741,42 @@ fn spawn_pty_reader(
                                 drop(term_tx.try_send(TerminalMessage::Input(resp)));
                             }
                             drop(h);
-                            true
-                        } else if let Some(ref sender) = h.data_tx {
-                            let uuid_wrapper = UuidWrapper::new(h.kex_uuid);
-                            let sender_clone = sender.clone();
+                            Vec::new()
+                        } else if h.clients.is_empty() {
                             drop(h);
-                            // Signal the screen-sync task that diffs are flowing.
-                            diff_in_flight.store(true, Ordering::Relaxed);
-                            // zstd level-1: if smaller, fits in one datagram (no burst).
-                            // Otherwise chunk by MTU with adaptive inter-packet pacing.
-                            if let Ok(compressed) = encode_all(buf_slice, 1)
-                                && compressed.len() < buf_slice.len()
-                            {
-                                let frame =
-                                    EncryptedFrame::CompressedBytes((uuid_wrapper, compressed));
-                                sender_clone.blocking_send(frame).is_ok()
-                            } else {
-                                let mut ok = true;
-                                let mtu = effective_mtu.load(Ordering::Relaxed);
-                                // Bursts > 10 chunks (e.g. htop redraws) get 3× pacing.
-                                let n = buf_slice.len().div_ceil(mtu);
-                                let burst_pacing = pacing_delay * if n > 10 { 3 } else { 1 };
-                                let mut chunks = buf_slice.chunks(mtu).peekable();
-                                while let Some(chunk) = chunks.next() {
-                                    let more = chunks.peek().is_some();
-                                    let frame =
-                                        EncryptedFrame::Bytes((uuid_wrapper, chunk.to_vec()));
-                                    ok = sender_clone.blocking_send(frame).is_ok();
-                                    if !ok {
-                                        break;
-                                    }
-                                    if more && !burst_pacing.is_zero() {
-                                        sleep(burst_pacing);
-                                    }
-                                }
-                                ok
-                            }
+                            Vec::new() // headless: just buffer
                         } else {
+                            let targets: Vec<(Uuid, Sender<EncryptedFrame>)> = h
+                                .clients
+                                .iter()
+                                .map(|c| (c.kex_uuid, c.data_tx.clone()))
+                                .collect();
                             drop(h);
-                            true // headless: just buffer
+                            // Signal the screen-sync task that diffs are flowing.
+                            diff_in_flight.store(true, Ordering::Relaxed);
+                            send_pty_output(
+                                &targets,
+                                buf_slice,
+                                effective_mtu.load(Ordering::Relaxed),
+                                pacing_delay,
+                            )
                         }
                     };
-                    if !send_ok {
-                        // Client dropped; clear both channels but keep the PTY running.
+                    if !failed.is_empty() {
+                        // Clients dropped; detach them but keep the PTY running.
                         let mut h = output_handle.blocking_lock();
-                        h.data_tx = None;
-                        h.control_tx = None;
+                        for kex_uuid in failed {
+                            if let Some(client) = h.detach(kex_uuid) {
+                                let _ = port_pool.blocking_lock().insert(client.udp_port);
+                            }
+                        }
+                        if let Some((rows, columns)) = h.pty_size() {
+                            drop(term_tx.try_send(TerminalMessage::Resize { columns, rows }));
+                        }
                     }
 
-                    if is_exit_title(&utf8_buf, true) {
+                    // A remote command ends with its process, never on a title.
+                    if !remote_command && is_exit_title(&utf8_buf, true) {
                         sleep(Duration::from_millis(500));
                         break;
                     }
@@ -1357,45 +1789,209 @@ fn spawn_pty_reader(
             }
         }
 
-        // Notify the connected client that the PTY process has exited so it can exit
-        // immediately instead of waiting for the silence timeout and entering the retry loop.
+        // The status comes from the reaper once the PTY has drained.  A shell
+        // that ended the session with its title may still be on its way out.
+        let status = if remote_command {
+            exit_status.recv().ok()
+        } else {
+            exit_status.recv_timeout(SHELL_EXIT_WAIT).ok()
+        };
+        announce_session_end(&output_handle, status, remote_command);
+        end_session(
+            session_uuid,
+            &output_handle,
+            &port_pool,
+            &session_registry,
+            &full_registry,
+        );
+        status
+    })
+}
+
+/// Send a PTY read to every client in `targets`, returning the connections of
+/// those whose data channel has closed.
+///
+/// zstd level-1: if smaller, fits in one datagram (no burst).  Otherwise
+/// chunk by `mtu` with adaptive inter-packet pacing, each chunk going to
+/// every client before the pause.
+#[cfg_attr(coverage_nightly, coverage(off))]
+fn send_pty_output(
+    targets: &[(Uuid, Sender<EncryptedFrame>)],
+    buf: &[u8],
+    mtu: usize,
+    pacing_delay: Duration,
+) -> Vec<Uuid> {
+    let mut failed = Vec::new();
+    if let Ok(compressed) = encode_all(buf, 1)
+        && compressed.len() < buf.len()
+    {
+        for (kex_uuid, sender) in targets {
+            let frame =
+                EncryptedFrame::CompressedBytes((UuidWrapper::new(*kex_uuid), compressed.clone()));
+            if sender.blocking_send(frame).is_err() {
+                failed.push(*kex_uuid);
+            }
+        }
+        return failed;
+    }
+    // Bursts > 10 chunks (e.g. htop redraws) get 3× pacing.
+    let n = buf.len().div_ceil(mtu);
+    let burst_pacing = pacing_delay * if n > 10 { 3 } else { 1 };
+    let mut chunks = buf.chunks(mtu).peekable();
+    while let Some(chunk) = chunks.next() {
+        let more = chunks.peek().is_some();
+        for (kex_uuid, sender) in targets {
+            if failed.contains(kex_uuid) {
+                continue;
+            }
+            let frame = EncryptedFrame::Bytes((UuidWrapper::new(*kex_uuid), chunk.to_vec()));
+            if sender.blocking_send(frame).is_err() {
+                failed.push(*kex_uuid);
+            }
+        }
+        if failed.len() == targets.len() {
+            break;
+        }
+        if more && !burst_pacing.is_zero() {
+            sleep(burst_pacing);
+        }
+    }
+    failed
+}
+
+/// Tell the connected clients that the session's program has ended so they can
+/// exit immediately instead of waiting for the silence timeout and entering the
+/// retry loop.
+///
+/// A shell's end is a single `PtyExitStatus`, or a bare `PtyExit` when its status
+/// is unknown or the client predates [`PTY_EXIT_STATUS_MIN_PROTOCOL_VERSION`].  A
+/// remote command's [`ExitStatus`] is resent until the client acknowledges it, for up
+/// to [`COMMAND_EXIT_LINGER`] so a client that is reconnecting still learns it.
+#[cfg_attr(coverage_nightly, coverage(off))]
+fn announce_session_end(
+    output_handle: &Arc<Mutex<SessionOutputHandle>>,
+    exit_status: Option<ExitStatus>,
+    remote_command: bool,
+) {
+    let Some(status) = exit_status.filter(|_| remote_command) else {
         {
             let h = output_handle.blocking_lock();
-            if let Some(ref tx) = h.control_tx {
-                drop(tx.blocking_send(EncryptedFrame::PtyExit));
+            for client in &h.clients {
+                let frame = match exit_status {
+                    Some(status) if client.pty_exit_status => EncryptedFrame::PtyExitStatus(status),
+                    _ => EncryptedFrame::PtyExit,
+                };
+                drop(client.control_tx.blocking_send(frame));
             }
         }
         // Give the UdpSender one select! tick to deliver PtyExit before the token cancel
         // races with the send.
         sleep(Duration::from_millis(50));
+        return;
+    };
 
-        // PTY process has exited — clean up the session.
-        {
-            let mut h = output_handle.blocking_lock();
-            if let Some(token) = h.conn_token.take() {
-                token.cancel();
-            }
-            if let Some(port) = h.udp_port.take() {
-                let mut pool = port_pool.blocking_lock();
-                let _ = pool.insert(port);
-            }
-            h.data_tx = None;
-            h.control_tx = None;
+    let exit_acked = output_handle.blocking_lock().exit_acked.clone();
+    let deadline = Instant::now() + COMMAND_EXIT_LINGER;
+    while !exit_acked.load(Ordering::Relaxed) && Instant::now() < deadline {
+        // Each resend goes to whichever connections are current.
+        for client in &output_handle.blocking_lock().clients {
+            drop(
+                client
+                    .control_tx
+                    .try_send(EncryptedFrame::CommandExit(status)),
+            );
         }
-        {
-            let mut sr = session_registry.blocking_lock();
-            drop(sr.remove(&session_uuid));
+        sleep(COMMAND_EXIT_RESEND_INTERVAL);
+    }
+    if !exit_acked.load(Ordering::Relaxed) {
+        warn!("remote command exit status ({status}) was never acknowledged");
+    }
+}
+
+/// The session's program has exited — clean up the session.
+#[cfg_attr(coverage_nightly, coverage(off))]
+fn end_session(
+    session_uuid: Uuid,
+    output_handle: &Arc<Mutex<SessionOutputHandle>>,
+    port_pool: &Arc<Mutex<BTreeSet<u16>>>,
+    session_registry: &SessionRegistry,
+    full_registry: &FullSessionRegistry,
+) {
+    {
+        let mut h = output_handle.blocking_lock();
+        let mut pool = port_pool.blocking_lock();
+        for client in h.clients.drain(..) {
+            client.conn_token.cancel();
+            let _ = pool.insert(client.udp_port);
         }
+    }
+    {
+        let mut sr = session_registry.blocking_lock();
+        drop(sr.remove(&session_uuid));
+    }
+    {
+        let mut fr = full_registry.blocking_lock();
+        // Stop the session's `mp -R` listeners now rather than when the
+        // last connection task lets go of the mux.
+        if let Some(record) = fr.remove(&session_uuid)
+            && let Some(forwards) = &record.forwards
         {
-            let mut fr = full_registry.blocking_lock();
-            drop(fr.remove(&session_uuid));
+            forwards.close();
         }
-        info!(session = %session_uuid, "session ended, client exited cleanly");
-    });
+    }
+    info!(session = %session_uuid, "session ended, client exited cleanly");
+}
+
+/// Relay one connection's keystrokes and terminal size to its session.
+///
+/// A read-only client's keystrokes and terminal size are dropped here, so
+/// the PTY takes the smallest size among the writable clients.  Once the
+/// connection's reader stops, the client is detached and the PTY resized for
+/// those remaining.
+#[cfg_attr(coverage_nightly, coverage(off))]
+async fn relay_client_input(
+    mut client_rx: Receiver<TerminalMessage>,
+    term_tx: Sender<TerminalMessage>,
+    output_handle: Arc<Mutex<SessionOutputHandle>>,
+    port_pool: Arc<Mutex<BTreeSet<u16>>>,
+    kex_uuid: Uuid,
+    read_only: bool,
+) {
+    while let Some(message) = client_rx.recv().await {
+        let message = match message {
+            TerminalMessage::Input(_) | TerminalMessage::Resize { .. } if read_only => continue,
+            TerminalMessage::Resize { columns, rows } => {
+                let size = output_handle.lock().await.resize(kex_uuid, rows, columns);
+                let Some((rows, columns)) = size else {
+                    continue;
+                };
+                TerminalMessage::Resize { columns, rows }
+            }
+            input @ TerminalMessage::Input(_) => input,
+        };
+        if term_tx.send(message).await.is_err() {
+            break;
+        }
+    }
+    let mut h = output_handle.lock().await;
+    if let Some(client) = h.detach(kex_uuid) {
+        let _ = port_pool.lock().await.insert(client.udp_port);
+        if let Some((rows, columns)) = h.pty_size() {
+            drop(term_tx.try_send(TerminalMessage::Resize { columns, rows }));
+        }
+    }
 }
 
 #[cfg(unix)]
-const PROTECTED_ENV: &[&str] = &["HOME", "USER", "LOGNAME", "SHELL", "TERM", "PATH"];
+const PROTECTED_ENV: &[&str] = &[
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "TERM",
+    "PATH",
+    "MOSHPIT_AGENT_SOCK",
+];
 
 /// Spawn the long-lived PTY OS thread for a new session.
 ///
@@ -1414,7 +2010,7 @@ const PROTECTED_ENV: &[&str] = &["HOME", "USER", "LOGNAME", "SHELL", "TERM", "PA
 #[cfg_attr(coverage_nightly, coverage(off))]
 fn spawn_pty(
     session_uuid: Uuid,
-    #[cfg_attr(not(unix), allow(unused_variables))] user: String,
+    user: String,
     shell: String,
     mut term_rx: Receiver<TerminalMessage>,
     term_tx: Sender<TerminalMessage>,
@@ -1424,7 +2020,7 @@ fn spawn_pty(
     dirty_counter: Arc<AtomicU64>,
     diff_in_flight: Arc<AtomicBool>,
     pacing_delay: Duration,
-    #[cfg_attr(not(unix), allow(unused_variables))] term_type: String,
+    term_type: String,
     port_pool: Arc<Mutex<BTreeSet<u16>>>,
     session_registry: SessionRegistry,
     full_registry: FullSessionRegistry,
@@ -1435,9 +2031,21 @@ fn spawn_pty(
     #[cfg_attr(not(target_os = "linux"), allow(unused_variables))] namespace_escape: bool,
     #[cfg_attr(not(target_os = "linux"), allow(unused_variables))] use_logind: bool,
     #[cfg_attr(not(target_os = "linux"), allow(unused_variables))] use_utmp: bool,
-    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))] remote_host: Option<String>,
+    remote_host: Option<String>,
+    #[cfg_attr(not(unix), allow(unused_variables))] agent_socket: Option<PathBuf>,
+    command: Option<RemoteCommand>,
+    #[cfg_attr(not(unix), allow(unused_variables))] forwards: Option<ForwardMux>,
+    record: Option<RecordSettings>,
 ) {
+    // Carrying a piped command's standard streams needs the runtime.
+    #[cfg(unix)]
+    let runtime = Handle::current();
     let _term_handle = thread::spawn(move || {
+        // Without a PTY, a remote command's standard streams are pipes.
+        let pty = command.as_ref().is_none_or(RemoteCommand::pty);
+        // The reaper hands the program's exit status to whoever reports it.
+        let (status_tx, status_rx) = mpsc::channel::<ExitStatus>();
+
         let pty_system = native_pty_system();
         let pair = match pty_system.openpty(PtySize {
             rows: 24,
@@ -1452,6 +2060,28 @@ fn spawn_pty(
             }
         };
 
+        // Recording covers everything with a terminal; a session that must
+        // be recorded does not start without its recording.
+        let recorder = match record.filter(|_| pty) {
+            Some(settings) => {
+                let session = RecordedSession {
+                    user: user.clone(),
+                    client_ip: remote_host.clone(),
+                    session_uuid,
+                    shell: shell.clone(),
+                    term_type: term_type.clone(),
+                };
+                match Recorder::start(settings, session, 24, 80) {
+                    Ok(recorder) => Some(Arc::new(Mutex::new(recorder))),
+                    Err(e) => {
+                        error!(session = %session_uuid, "Cannot record session for {user}: {e:#}");
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+
         // Held for the lifetime of the PTY thread; dropping it on thread exit
         // releases the logind session (closes the session fifo) when the shell
         // ends.
@@ -1464,6 +2094,11 @@ fn spawn_pty(
         #[cfg(target_os = "linux")]
         let mut utmp_guard: Option<crate::utmp::UtmpSession> = None;
 
+        // What a hand-off to a new `mps` needs of the PTY; the login records
+        // move in with it once the program runs.
+        #[cfg(unix)]
+        let mut handoff_state: Option<PtyState> = None;
+
         #[cfg(unix)]
         {
             let daemon_uid = unsafe { libc::getuid() };
@@ -1552,16 +2187,10 @@ fn spawn_pty(
                 let _ = cmd.arg("/etc/nologin");
                 cmd
             } else {
-                let mut cmd = Command::new(&account.shell);
-                let _ = cmd.arg("-li");
-                cmd
+                shell_command(&account.shell, command.as_ref())
             };
             #[cfg(not(target_os = "linux"))]
-            let mut cmd = {
-                let mut cmd = Command::new(&account.shell);
-                let _ = cmd.arg("-li");
-                cmd
-            };
+            let mut cmd = shell_command(&account.shell, command.as_ref());
 
             let _ = cmd.env_clear();
 
@@ -1577,6 +2206,9 @@ fn spawn_pty(
             let _ = cmd.env("SHELL", &account.shell);
             let _ = cmd.env("TERM", &term_type);
             let _ = cmd.env("PATH", &pty_path);
+            if let Some(agent_socket) = &agent_socket {
+                let _ = cmd.env("MOSHPIT_AGENT_SOCK", agent_socket);
+            }
 
             // XDG_RUNTIME_DIR points at /run/user/UID.  With logind enabled the
             // directory is created as part of the session registered below; in the
@@ -1646,7 +2278,8 @@ fn spawn_pty(
                     if libc::setsid() < 0 {
                         return Err(Error::last_os_error());
                     }
-                    if libc::ioctl(0, tiocsctty_request, 0) < 0 {
+                    // Piped commands have no terminal to control.
+                    if pty && libc::ioctl(0, tiocsctty_request, 0) < 0 {
                         return Err(Error::last_os_error());
                     }
 
@@ -1686,12 +2319,19 @@ fn spawn_pty(
                 })
             };
 
-            let _ = cmd
-                .stdin(Stdio::from(stdin_file))
-                .stdout(Stdio::from(stdout_file))
-                .stderr(Stdio::from(stderr_file));
+            if pty {
+                let _ = cmd
+                    .stdin(Stdio::from(stdin_file))
+                    .stdout(Stdio::from(stdout_file))
+                    .stderr(Stdio::from(stderr_file));
+            } else {
+                let _ = cmd
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped());
+            }
 
-            let child = match cmd.spawn() {
+            let mut child = match cmd.spawn() {
                 Ok(child) => child,
                 Err(e) => {
                     error!("Failed to spawn shell for user {user}: {e}");
@@ -1706,7 +2346,11 @@ fn spawn_pty(
             // logind creates /run/user/UID as part of CreateSession.
             #[cfg(target_os = "linux")]
             if logind_enabled {
-                let tty_name = tty_path.to_string_lossy();
+                let tty_name = if pty {
+                    tty_path.to_string_lossy()
+                } else {
+                    "".into()
+                };
                 let tty_name = tty_name.strip_prefix("/dev/").unwrap_or(&tty_name);
                 match crate::logind::create_session(
                     account.uid,
@@ -1734,8 +2378,9 @@ fn spawn_pty(
             // Record the session in /var/run/utmp and /var/log/wtmp like an SSH
             // login does, so it shows up in `who`, `w`, and `last`.  Needs root
             // (the login databases are root/utmp-owned); failures are non-fatal.
+            // Like sshd, a command without a terminal is not a login.
             #[cfg(target_os = "linux")]
-            if use_utmp && daemon_uid == 0 {
+            if use_utmp && daemon_uid == 0 && pty {
                 let tty_name = tty_path.to_string_lossy();
                 let tty_name = tty_name.strip_prefix("/dev/").unwrap_or(&tty_name);
                 match crate::utmp::login(
@@ -1754,25 +2399,90 @@ fn spawn_pty(
                 }
             }
 
+            if pty && let Some(master) = pair.master.as_raw_fd() {
+                let tty = tty_path.to_string_lossy();
+                handoff_state = Some(PtyState {
+                    master,
+                    child_pid: child.id(),
+                    tty: tty.strip_prefix("/dev/").unwrap_or(&tty).to_owned(),
+                    diff_mode,
+                    remote_command: command.is_some(),
+                    #[cfg(target_os = "linux")]
+                    logind: logind_guard.take(),
+                    #[cfg(target_os = "linux")]
+                    utmp: utmp_guard.take(),
+                    recorder: recorder.clone(),
+                });
+            }
+
+            let streams = (child.stdin.take(), child.stdout.take(), child.stderr.take());
+
             // Reap the shell when it exits so it does not linger as a zombie
             // (which would also keep its logind session scope from cleaning up).
             // PTY master EOF — not this wait — drives moshpit session teardown.
             let _reaper = thread::spawn(move || {
-                let mut child = child;
-                let _exit_status = child.wait();
+                if let Some(status) = crate::handoff::reap(child) {
+                    let _ = status_tx.send(status);
+                }
             });
 
             drop(pair.slave);
             drop(slave);
+
+            if !pty {
+                let (Some(stdin), Some(stdout), Some(stderr)) = streams else {
+                    error!("Piped command for {user} has no standard streams");
+                    return;
+                };
+                let Some(forwards) = forwards else {
+                    error!("Piped command for {user} has no forwarding channels");
+                    return;
+                };
+                drop(term_tx);
+                spawn_command_stdio(
+                    session_uuid,
+                    &runtime,
+                    forwards,
+                    (stdin, stdout, stderr),
+                    status_rx,
+                    output_handle,
+                    port_pool,
+                    session_registry,
+                    full_registry,
+                );
+                // Nothing reads the client's keyboard; drain it until the
+                // session's last sender is gone.
+                while term_rx.blocking_recv().is_some() {}
+                #[cfg(target_os = "linux")]
+                drop(logind_guard);
+                return;
+            }
         }
 
         #[cfg(windows)]
         {
-            let cmd = CommandBuilder::new(shell);
-            if let Err(e) = pair.slave.spawn_command(cmd) {
-                error!("Failed to spawn shell: {e}");
+            if !pty {
+                error!("Piped remote commands are not supported on this platform");
                 return;
             }
+            let mut cmd = CommandBuilder::new(shell);
+            if let Some(command) = &command {
+                cmd.arg("-c");
+                cmd.arg(command.command());
+            }
+            let mut child = match pair.slave.spawn_command(cmd) {
+                Ok(child) => child,
+                Err(e) => {
+                    error!("Failed to spawn shell: {e}");
+                    return;
+                }
+            };
+            let _reaper = thread::spawn(move || {
+                if let Ok(status) = child.wait() {
+                    let code = i32::from_ne_bytes(status.exit_code().to_ne_bytes());
+                    drop(status_tx.send(ExitStatus::Code(code)));
+                }
+            });
         }
 
         let master = pair.master;
@@ -1792,7 +2502,24 @@ fn spawn_pty(
             }
         };
 
-        spawn_pty_reader(
+        // A hand-off pauses the reader and finds the PTY in the session's record.
+        #[cfg(unix)]
+        let (term_out, pty_slot) = match handoff_state {
+            Some(state) => {
+                let term_out: Box<dyn Read + Send> =
+                    Box::new(PausableReader::new(term_out, state.master));
+                let slot = full_registry
+                    .blocking_lock()
+                    .get(&session_uuid)
+                    .map(|record| record.pty.clone())
+                    .unwrap_or_default();
+                slot.fill(state);
+                (term_out, slot)
+            }
+            None => (term_out, PtySlot::default()),
+        };
+
+        let pty_reader = spawn_pty_reader(
             session_uuid,
             term_out,
             term_tx,
@@ -1807,38 +2534,35 @@ fn spawn_pty(
             full_registry,
             effective_mtu,
             diff_mode,
+            status_rx,
+            command.is_some(),
+            recorder.clone(),
         );
 
-        while let Some(terminal_message) = term_rx.blocking_recv() {
-            match terminal_message {
-                TerminalMessage::Resize { columns, rows } => {
-                    if let Err(e) = master.resize(PtySize {
-                        rows,
-                        cols: columns,
-                        pixel_width: 0,
-                        pixel_height: 0,
-                    }) {
-                        error!("error resizing terminal: {e}");
-                    }
-                    // Keep the server-side emulator in sync with the PTY dimensions.
-                    server_emulator
-                        .blocking_lock()
-                        .screen_mut()
-                        .set_size(rows, columns);
-                    // Resize changes the rendered screen layout — mark dirty.
-                    let _ = dirty_counter.fetch_add(1, Ordering::Relaxed);
-                }
-                TerminalMessage::Input(data) => {
-                    if let Err(e) = term_in.write_all(&data) {
-                        error!("error writing to terminal: {e}");
-                        break;
-                    }
-                }
-            }
-        }
+        relay_terminal_input(
+            &mut term_rx,
+            &mut term_in,
+            |rows, cols| {
+                master.resize(PtySize {
+                    rows,
+                    cols,
+                    pixel_width: 0,
+                    pixel_height: 0,
+                })
+            },
+            recorder.as_ref(),
+            &server_emulator,
+            &dirty_counter,
+        );
 
-        // PTY thread is ending: write the logout records (DEAD_PROCESS) before
-        // releasing the logind session (closes its fifo).
+        // PTY thread is ending: log how the program ended, then write the logout
+        // records (DEAD_PROCESS) before releasing the logind session (closes its
+        // fifo).
+        log_program_end(session_uuid, pty_reader);
+        #[cfg(unix)]
+        if let Some(state) = pty_slot.take() {
+            state.release();
+        }
         #[cfg(target_os = "linux")]
         if let Some(session) = utmp_guard.take()
             && let Err(e) = crate::utmp::logout(&session)
@@ -1850,6 +2574,374 @@ fn spawn_pty(
     });
 }
 
+/// Feed a session's keystrokes and resizes to its PTY until the session
+/// ends, keeping its recording and the server-side emulator in step.
+#[cfg_attr(coverage_nightly, coverage(off))]
+fn relay_terminal_input(
+    term_rx: &mut Receiver<TerminalMessage>,
+    term_in: &mut impl Write,
+    mut resize: impl FnMut(u16, u16) -> Result<()>,
+    recorder: Option<&Arc<Mutex<Recorder>>>,
+    server_emulator: &Arc<Mutex<vt100::Parser>>,
+    dirty_counter: &AtomicU64,
+) {
+    while let Some(terminal_message) = term_rx.blocking_recv() {
+        match terminal_message {
+            TerminalMessage::Resize { columns, rows } => {
+                if let Err(e) = resize(rows, columns) {
+                    error!("error resizing terminal: {e}");
+                }
+                if let Some(recorder) = recorder {
+                    recorder.blocking_lock().resize(rows, columns);
+                }
+                // Keep the server-side emulator in sync with the PTY dimensions.
+                server_emulator
+                    .blocking_lock()
+                    .screen_mut()
+                    .set_size(rows, columns);
+                // Resize changes the rendered screen layout — mark dirty.
+                let _ = dirty_counter.fetch_add(1, Ordering::Relaxed);
+            }
+            TerminalMessage::Input(data) => {
+                if let Some(recorder) = recorder {
+                    recorder.blocking_lock().input(&data);
+                }
+                if let Err(e) = term_in.write_all(&data) {
+                    error!("error writing to terminal: {e}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Log how a session's program ended, once its PTY reader is done.
+#[cfg_attr(coverage_nightly, coverage(off))]
+fn log_program_end(session_uuid: Uuid, pty_reader: JoinHandle<Option<ExitStatus>>) {
+    match pty_reader.join() {
+        Ok(Some(status)) => {
+            info!(session = %session_uuid, "session program exited with {status}");
+        }
+        Ok(None) => info!(session = %session_uuid, "session program exit status unknown"),
+        Err(_) => error!(session = %session_uuid, "PTY reader thread panicked"),
+    }
+}
+
+/// Register the sessions taken over from the previous `mps` and carry them
+/// on, headless until their clients reconnect.
+#[cfg(unix)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+async fn adopt_sessions(
+    sessions: Vec<AdoptedSession>,
+    config: &Config,
+    full_registry: &FullSessionRegistry,
+) {
+    let port_pool = config.port_pool();
+    let session_registry = config.session_registry();
+    let pacing_delay =
+        Duration::from_micros(config.pacing_delay_us().unwrap_or(DEFAULT_PACING_DELAY_US));
+    for AdoptedSession {
+        session,
+        master,
+        state,
+    } in sessions
+    {
+        let session_uuid = session.uuid.as_uuid();
+        let (term_tx, term_rx) = channel::<TerminalMessage>(256);
+        let output_handle = Arc::new(Mutex::new(SessionOutputHandle::headless()));
+        let mut scrollback = VecDeque::with_capacity(SCROLLBACK_CAPACITY);
+        scrollback.extend(session.scrollback.iter().copied());
+        let scrollback = Arc::new(Mutex::new(scrollback));
+        let mut emulator = vt100::Parser::new(session.rows, session.cols, 0);
+        emulator.process(&session.screen);
+        let server_emulator = Arc::new(Mutex::new(emulator));
+        // Start at 1 so the first sync tick always sends an initial screen state.
+        let dirty_counter = Arc::new(AtomicU64::new(1));
+        let diff_in_flight = Arc::new(AtomicBool::new(false));
+        let effective_mtu = Arc::new(AtomicUsize::new(MAX_UDP_PAYLOAD));
+        let recorder = state.recorder.clone();
+        let (diff_mode, remote_command) = (state.diff_mode, state.remote_command);
+        let status_rx = crate::handoff::program_end(state.child_pid);
+        let pty = PtySlot::default();
+        pty.fill(state);
+
+        drop(session_registry.lock().await.insert(
+            session_uuid,
+            SessionEntry::new(session.user.clone(), session.name.clone()),
+        ));
+        drop(full_registry.lock().await.insert(
+            session_uuid,
+            SessionRecord {
+                term_tx: term_tx.clone(),
+                output_handle: output_handle.clone(),
+                scrollback: scrollback.clone(),
+                server_emulator: server_emulator.clone(),
+                dirty_counter: dirty_counter.clone(),
+                diff_in_flight: diff_in_flight.clone(),
+                effective_mtu: effective_mtu.clone(),
+                forwards: None,
+                created: from_epoch_secs(session.created),
+                last_attach: from_epoch_secs(session.last_attach),
+                title: session.title.clone(),
+                pty: pty.clone(),
+            },
+        ));
+        info!(session = %session_uuid, "took over the session of {}", session.user);
+
+        spawn_adopted_pty(
+            session_uuid,
+            master,
+            pty,
+            term_rx,
+            term_tx,
+            output_handle,
+            scrollback,
+            server_emulator,
+            dirty_counter,
+            diff_in_flight,
+            pacing_delay,
+            port_pool.clone(),
+            session_registry.clone(),
+            full_registry.clone(),
+            effective_mtu,
+            diff_mode,
+            status_rx,
+            remote_command,
+            recorder,
+        );
+    }
+}
+
+/// Carry on a session taken over from the previous `mps` on its PTY
+/// `master`, as [`spawn_pty`] does for the sessions it starts.
+///
+/// The program is not this process's child: `status_rx` hears how it ends
+/// from the reaper the previous `mps` left behind.
+#[cfg(unix)]
+#[cfg_attr(
+    nightly,
+    allow(clippy::too_many_arguments, clippy::needless_pass_by_value)
+)]
+#[cfg_attr(not(nightly), allow(clippy::needless_pass_by_value))]
+#[cfg_attr(coverage_nightly, coverage(off))]
+fn spawn_adopted_pty(
+    session_uuid: Uuid,
+    master: File,
+    pty_slot: PtySlot,
+    mut term_rx: Receiver<TerminalMessage>,
+    term_tx: Sender<TerminalMessage>,
+    output_handle: Arc<Mutex<SessionOutputHandle>>,
+    scrollback: Arc<Mutex<VecDeque<u8>>>,
+    server_emulator: Arc<Mutex<vt100::Parser>>,
+    dirty_counter: Arc<AtomicU64>,
+    diff_in_flight: Arc<AtomicBool>,
+    pacing_delay: Duration,
+    port_pool: Arc<Mutex<BTreeSet<u16>>>,
+    session_registry: SessionRegistry,
+    full_registry: FullSessionRegistry,
+    effective_mtu: Arc<AtomicUsize>,
+    diff_mode: DiffMode,
+    status_rx: mpsc::Receiver<ExitStatus>,
+    remote_command: bool,
+    recorder: Option<Arc<Mutex<Recorder>>>,
+) {
+    let _term_handle = thread::spawn(move || {
+        let master_fd = master.as_raw_fd();
+        let term_out = match master.try_clone() {
+            Ok(file) => file,
+            Err(e) => {
+                error!(session = %session_uuid, "Failed to clone PTY reader: {e}");
+                return;
+            }
+        };
+        let pty_reader = spawn_pty_reader(
+            session_uuid,
+            Box::new(PausableReader::new(Box::new(term_out), master_fd)),
+            term_tx,
+            output_handle,
+            scrollback,
+            server_emulator.clone(),
+            dirty_counter.clone(),
+            diff_in_flight,
+            pacing_delay,
+            port_pool,
+            session_registry,
+            full_registry,
+            effective_mtu,
+            diff_mode,
+            status_rx,
+            remote_command,
+            recorder.clone(),
+        );
+
+        let mut term_in = master;
+        relay_terminal_input(
+            &mut term_rx,
+            &mut term_in,
+            |rows, cols| Ok(resize(master_fd, rows, cols)?),
+            recorder.as_ref(),
+            &server_emulator,
+            &dirty_counter,
+        );
+
+        log_program_end(session_uuid, pty_reader);
+        if let Some(state) = pty_slot.take() {
+            state.release();
+        }
+    });
+}
+
+/// The user's login shell, or — for a remote command — the shell running it
+/// with `-c` as sshd does, or for a file copy our own copy helper.
+#[cfg(unix)]
+fn shell_command(shell: &str, command: Option<&RemoteCommand>) -> Command {
+    if let Some(copy) = command.and_then(|command| command.file_copy().as_ref()) {
+        return crate::copy::helper_command(copy);
+    }
+    let mut cmd = Command::new(shell);
+    if let Some(command) = command {
+        let _ = cmd.arg("-c").arg(command.command());
+    } else {
+        let _ = cmd.arg("-li");
+    }
+    cmd
+}
+
+/// Carry a piped remote command's standard streams over the session's
+/// forwarding channels, then report its exit status once its output has been
+/// delivered and end the session.
+#[cfg(unix)]
+#[cfg_attr(nightly, allow(clippy::too_many_arguments))]
+#[cfg_attr(coverage_nightly, coverage(off))]
+fn spawn_command_stdio(
+    session_uuid: Uuid,
+    runtime: &Handle,
+    forwards: ForwardMux,
+    (stdin, stdout, stderr): (ChildStdin, ChildStdout, ChildStderr),
+    exit_status: mpsc::Receiver<ExitStatus>,
+    output_handle: Arc<Mutex<SessionOutputHandle>>,
+    port_pool: Arc<Mutex<BTreeSet<u16>>>,
+    session_registry: SessionRegistry,
+    full_registry: FullSessionRegistry,
+) {
+    let runtime = runtime.clone();
+    let _stdio_handle = thread::spawn(move || {
+        let delivered = runtime.block_on(async move {
+            let stdin = tokio::process::ChildStdin::from_std(stdin)?;
+            let stdout = tokio::process::ChildStdout::from_std(stdout)?;
+            let stderr = tokio::process::ChildStderr::from_std(stderr)?;
+            let _stdin = forwards.open_stdio(0, None, Some(Box::new(stdin))).await;
+            let stdout = forwards.open_stdio(1, Some(Box::new(stdout)), None).await;
+            let stderr = forwards.open_stdio(2, Some(Box::new(stderr)), None).await;
+            // Only the output matters for the exit status; standard input may
+            // stay open long after the command stopped reading it.
+            let _ = stdout.await;
+            let _ = stderr.await;
+            Ok::<_, Error>(())
+        });
+        if let Err(e) = delivered {
+            error!(session = %session_uuid, "cannot carry remote command streams: {e}");
+        }
+        let status = exit_status.recv().ok();
+        if let Some(status) = status {
+            info!(session = %session_uuid, "session program exited with {status}");
+        }
+        announce_session_end(&output_handle, status, true);
+        end_session(
+            session_uuid,
+            &output_handle,
+            &port_pool,
+            &session_registry,
+            &full_registry,
+        );
+    });
+}
+
+/// Spawn the thread that answers `mp ls`: print the user's sessions other
+/// than `session_uuid` on the client's standard output, then end this session
+/// as a command that exited with status 0.
+#[cfg_attr(coverage_nightly, coverage(off))]
+fn spawn_session_list(
+    session_uuid: Uuid,
+    user: String,
+    forwards: Option<ForwardMux>,
+    output_handle: Arc<Mutex<SessionOutputHandle>>,
+    port_pool: Arc<Mutex<BTreeSet<u16>>>,
+    session_registry: SessionRegistry,
+    full_registry: FullSessionRegistry,
+) {
+    let runtime = Handle::current();
+    let _list_handle = thread::spawn(move || {
+        runtime.block_on(async {
+            let sessions =
+                session_summaries(session_uuid, &user, &session_registry, &full_registry).await;
+            let listing = format_session_list(&sessions, SystemTime::now());
+            if let Some(forwards) = &forwards {
+                let stdout = forwards
+                    .open_stdio(1, Some(Box::new(Cursor::new(listing.into_bytes()))), None)
+                    .await;
+                let _ = stdout.await;
+            }
+        });
+        announce_session_end(&output_handle, Some(ExitStatus::Code(0)), true);
+        end_session(
+            session_uuid,
+            &output_handle,
+            &port_pool,
+            &session_registry,
+            &full_registry,
+        );
+    });
+}
+
+/// `user`'s sessions other than `session_uuid`, oldest first.
+async fn session_summaries(
+    session_uuid: Uuid,
+    user: &str,
+    session_registry: &SessionRegistry,
+    full_registry: &FullSessionRegistry,
+) -> Vec<SessionSummary> {
+    let names: Vec<(Uuid, Option<String>)> = session_registry
+        .lock()
+        .await
+        .iter()
+        .filter(|(uuid, entry)| **uuid != session_uuid && entry.user() == user)
+        .map(|(uuid, entry)| (*uuid, entry.name().clone()))
+        .collect();
+    // Copy out what each record shares so its own locks are taken without
+    // holding the registry's.
+    let records: Vec<_> = {
+        let registry = full_registry.lock().await;
+        names
+            .into_iter()
+            .filter_map(|(uuid, name)| {
+                let record = registry.get(&uuid)?;
+                Some((
+                    name,
+                    record.output_handle.clone(),
+                    record.server_emulator.clone(),
+                    record.created,
+                    record.last_attach,
+                    record.title.clone(),
+                ))
+            })
+            .collect()
+    };
+    let mut sessions = Vec::with_capacity(records.len());
+    for (name, output_handle, server_emulator, created, last_attach, title) in records {
+        sessions.push(SessionSummary {
+            name,
+            clients: output_handle.lock().await.clients.len(),
+            size: server_emulator.lock().await.screen().size(),
+            created,
+            last_attach,
+            title,
+        });
+    }
+    sessions.sort_by_key(|session| session.created);
+    sessions
+}
+
 /// Parse `/etc/environment` (the system file `pam_env` reads) into `KEY=VALUE`
 /// pairs.  A missing or unreadable file yields an empty list.
 #[cfg(unix)]
@@ -1966,22 +3058,75 @@ fn resolve_user_account(username: &str, fallback_shell: &str) -> Result<Resolved
     })
 }
 
+/// The account forwards act for: the session's user when running as root,
+/// `None` otherwise, when the daemon's own permissions already apply.
+///
+/// # Errors
+/// * Running as root and `username` cannot be resolved.
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn forward_user(username: &str) -> Result<Option<ForwardUser>> {
+    if unsafe { libc::getuid() } != 0 {
+        return Ok(None);
+    }
+    let account = resolve_user_account(username, "")?;
+    let groups = group_list(&account.username, account.gid);
+    Ok(Some(ForwardUser::new(account.uid, account.gid, groups)))
+}
+
+#[cfg(all(unix, target_os = "linux"))]
+#[allow(unsafe_code)]
+fn group_list(username: &str, gid: libc::gid_t) -> Vec<libc::gid_t> {
+    let Ok(username_c) = CString::new(username) else {
+        return vec![gid];
+    };
+    let mut capacity: libc::c_int = 32;
+    loop {
+        let mut groups = vec![0; usize::try_from(capacity).unwrap_or_default()];
+        let mut found = capacity;
+        let rc = unsafe {
+            libc::getgrouplist(
+                username_c.as_ptr(),
+                gid,
+                groups.as_mut_ptr(),
+                &raw mut found,
+            )
+        };
+        if rc >= 0 {
+            groups.truncate(usize::try_from(found).unwrap_or_default());
+            return groups;
+        }
+        if found <= capacity {
+            return vec![gid];
+        }
+        capacity = found;
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn group_list(_username: &str, gid: libc::gid_t) -> Vec<libc::gid_t> {
+    vec![gid]
+}
+
 #[cfg(test)]
 #[allow(dead_code, clippy::all)]
 mod test {
-    use libmoshpit::{EncryptedFrame, Kex, ServerKex};
+    use libmoshpit::{EncryptedFrame, Kex, ServerKex, TerminalMessage};
     use tokio::sync::{Mutex, mpsc::channel};
     use tokio::task::yield_now;
     use tokio::time::{advance, timeout};
     use tokio_util::sync::CancellationToken;
     use uuid::Uuid;
 
-    use super::apply_client_ack;
+    #[cfg(target_os = "linux")]
+    use super::group_list;
+    use super::{apply_client_ack, relay_client_input};
     #[cfg(unix)]
     use super::{
         current_daemon_user, parse_environment_file, parse_etc_environment, resolve_user_account,
     };
-    use std::collections::VecDeque;
+    use crate::session::{ClientOutput, SessionOutputHandle};
+    use std::collections::{BTreeSet, VecDeque};
     use std::{
         sync::{
             Arc,
@@ -2132,6 +3277,18 @@ mod test {
         assert!(result.is_err());
     }
 
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn group_list_includes_the_primary_group() {
+        let Some(username) = current_daemon_user() else {
+            panic!("expected current daemon user on unix")
+        };
+        let Ok(account) = resolve_user_account(&username, "/bin/sh") else {
+            panic!("expected the current daemon user to resolve")
+        };
+        assert!(group_list(&username, account.gid).contains(&account.gid));
+    }
+
     #[cfg(unix)]
     #[test]
     fn resolve_user_account_current_user_roundtrip() {
@@ -2215,7 +3372,7 @@ mod test {
             &registry,
         )
         .await?;
-        assert_eq!(output_handle.lock().await.kex_uuid, kex.uuid());
+        assert_eq!(output_handle.lock().await.clients[0].kex_uuid, kex.uuid());
         Ok(())
     }
 
@@ -2356,6 +3513,7 @@ mod test {
             50_000,
             data_tx,
             control_tx,
+            &Arc::new(Mutex::new(BTreeSet::new())),
             &registry,
         )
         .await?;
@@ -2397,6 +3555,8 @@ mod test {
         let (resume_data_tx, mut resume_data_rx) = channel::<EncryptedFrame>(16);
         let (resume_ctrl_tx, _resume_ctrl_rx) = channel::<EncryptedFrame>(4);
 
+        let port_pool = Arc::new(Mutex::new(BTreeSet::new()));
+
         let (_, maybe_rx, output_handle, _, _, _, _, _) = resolve_session(
             &new_kex,
             &skex_resume,
@@ -2404,14 +3564,21 @@ mod test {
             50_001,
             resume_data_tx,
             resume_ctrl_tx,
+            &port_pool,
             &registry,
         )
         .await?;
 
         // Resume → no new PTY → None
         assert!(maybe_rx.is_none());
-        // Output handle should be updated with the new kex uuid
-        assert_eq!(output_handle.lock().await.kex_uuid, new_kex.uuid());
+        // The new connection displaces the first, whose port goes back to the pool
+        assert!(conn_token.is_cancelled());
+        assert!(port_pool.lock().await.contains(&50_000));
+        {
+            let h = output_handle.lock().await;
+            assert_eq!(h.clients.len(), 1);
+            assert_eq!(h.clients[0].kex_uuid, new_kex.uuid());
+        }
         // A ScreenState frame should have been sent on the *new* connection's data channel
         let mut saw_screen_state = false;
         while let Ok(frame) = resume_data_rx.try_recv() {
@@ -2427,6 +3594,143 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn resolve_session_shared_join_keeps_other_clients() -> anyhow::Result<()> {
+        let kex = Kex::default();
+        let session_uuid = Uuid::new_v4();
+        let conn_token = CancellationToken::new();
+        let (data_tx, _data_rx) = channel::<EncryptedFrame>(16);
+        let (control_tx, _control_rx) = channel::<EncryptedFrame>(4);
+        let registry = new_full_registry();
+        let _first_session = new_session(
+            &kex,
+            &conn_token,
+            50_000,
+            session_uuid,
+            data_tx,
+            control_tx,
+            &registry,
+        )
+        .await?;
+
+        let viewer_kex = Kex::default();
+        let skex_join = ServerKex::builder()
+            .user("bob".to_string())
+            .shell("/usr/bin/fish".to_string())
+            .session_uuid(session_uuid)
+            .is_resume(true)
+            .shared(true)
+            .read_only(true)
+            .build();
+        let (viewer_data_tx, _viewer_data_rx) = channel::<EncryptedFrame>(16);
+        let (viewer_ctrl_tx, _viewer_ctrl_rx) = channel::<EncryptedFrame>(4);
+        let (_, maybe_rx, output_handle, _, _, _, _, _) = resolve_session(
+            &viewer_kex,
+            &skex_join,
+            &CancellationToken::new(),
+            50_001,
+            viewer_data_tx,
+            viewer_ctrl_tx,
+            &Arc::new(Mutex::new(BTreeSet::new())),
+            &registry,
+        )
+        .await?;
+
+        assert!(maybe_rx.is_none());
+        assert!(!conn_token.is_cancelled());
+        let h = output_handle.lock().await;
+        let attached: Vec<Uuid> = h.clients.iter().map(|c| c.kex_uuid).collect();
+        assert_eq!(attached, [kex.uuid(), viewer_kex.uuid()]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn relay_drops_a_read_only_clients_input_and_size() -> anyhow::Result<()> {
+        for read_only in [true, false] {
+            let (data_tx, _data_rx) = channel::<EncryptedFrame>(1);
+            let (control_tx, _control_rx) = channel::<EncryptedFrame>(1);
+            let kex_uuid = Uuid::new_v4();
+            let output_handle = Arc::new(Mutex::new(SessionOutputHandle::new(ClientOutput {
+                kex_uuid,
+                data_tx,
+                control_tx,
+                conn_token: CancellationToken::new(),
+                udp_port: 50_000,
+                pty_exit_status: true,
+                shared: true,
+                size: None,
+            })));
+            let (client_tx, client_rx) = channel::<TerminalMessage>(4);
+            let (term_tx, mut term_rx) = channel::<TerminalMessage>(4);
+            let relay = tokio::spawn(relay_client_input(
+                client_rx,
+                term_tx,
+                output_handle.clone(),
+                Arc::new(Mutex::new(BTreeSet::new())),
+                kex_uuid,
+                read_only,
+            ));
+
+            client_tx
+                .send(TerminalMessage::Input(b"exit\n".to_vec()))
+                .await?;
+            client_tx
+                .send(TerminalMessage::Resize {
+                    columns: 20,
+                    rows: 5,
+                })
+                .await?;
+            drop(client_tx);
+            relay.await?;
+
+            let mut relayed = Vec::new();
+            while let Some(message) = term_rx.recv().await {
+                relayed.push(message);
+            }
+            if read_only {
+                assert!(relayed.is_empty(), "relayed {relayed:?}");
+            } else {
+                assert_eq!(relayed.len(), 2, "relayed {relayed:?}");
+                assert!(matches!(
+                    relayed[1],
+                    TerminalMessage::Resize {
+                        columns: 20,
+                        rows: 5
+                    }
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resolve_session_shared_join_never_starts_a_session() {
+        let skex = ServerKex::builder()
+            .user("bob".to_string())
+            .shell("/usr/bin/fish".to_string())
+            .session_uuid(Uuid::new_v4())
+            .is_resume(true)
+            .shared(true)
+            .build();
+        let (data_tx, _data_rx) = channel::<EncryptedFrame>(4);
+        let (control_tx, _control_rx) = channel::<EncryptedFrame>(4);
+        let registry = new_full_registry();
+
+        let result = resolve_session(
+            &Kex::default(),
+            &skex,
+            &CancellationToken::new(),
+            50_000,
+            data_tx,
+            control_tx,
+            &Arc::new(Mutex::new(BTreeSet::new())),
+            &registry,
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(registry.lock().await.is_empty());
+    }
+
     #[tokio::test]
     async fn resolve_session_resume_expired() -> anyhow::Result<()> {
         let kex = Kex::default();
@@ -2450,6 +3754,7 @@ mod test {
             50_000,
             data_tx,
             control_tx,
+            &Arc::new(Mutex::new(BTreeSet::new())),
             &registry,
         )
         .await?;
@@ -11,40 +11,127 @@ use std::{
     fmt,
     sync::Arc,
     sync::atomic::{AtomicBool, AtomicU64, AtomicUsize},
+    time::{Duration, SystemTime},
 };
 
-use libmoshpit::{EncryptedFrame, TerminalMessage};
+use libmoshpit::{EncryptedFrame, ForwardMux, TerminalMessage};
 use tokio::sync::{Mutex, mpsc::Sender};
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+#[cfg(unix)]
+use crate::handoff::PtySlot;
+
 /// Maximum bytes kept in the per-session scrollback ring buffer (64 KiB).
 pub(crate) const SCROLLBACK_CAPACITY: usize = 65_536;
 
-/// Replaceable output handle for a session.
+/// Longest window title kept for `mp ls`; the rest of a longer one is dropped.
+const MAX_TITLE_BYTES: usize = 256;
+
+/// One client connection attached to a session.
 #[derive(Debug)]
-pub(crate) struct SessionOutputHandle {
+pub(crate) struct ClientOutput {
     /// Per-connection UUID used to tag outbound [`EncryptedFrame::Bytes`] datagrams.
     pub kex_uuid: Uuid,
     /// Data channel to the live [`libmoshpit::UdpSender`] (PTY diffs, screen state).
-    /// Set to `None` when the client has disconnected and the PTY is running headless.
-    pub data_tx: Option<Sender<EncryptedFrame>>,
+    pub data_tx: Sender<EncryptedFrame>,
     /// Control channel to the live [`libmoshpit::UdpSender`] (Keepalive, Shutdown).
     /// Polled before the data channel inside `UdpSender` to prevent HOL-blocking.
-    pub control_tx: Option<Sender<EncryptedFrame>>,
-    /// Cancellation token for the current connection's UDP tasks.  Cancelled on resume
-    /// to shut down the stale reader/sender pair.
-    pub conn_token: Option<CancellationToken>,
-    /// UDP port allocated for the current connection, returned to the pool when the PTY
-    /// session ends.
-    pub udp_port: Option<u16>,
+    pub control_tx: Sender<EncryptedFrame>,
+    /// Cancellation token for the connection's UDP tasks.  Cancelled when a
+    /// resume displaces the connection, to shut down the stale reader/sender pair.
+    pub conn_token: CancellationToken,
+    /// UDP port allocated for the connection, returned to the pool when the
+    /// client leaves or the PTY session ends.
+    pub udp_port: u16,
+    /// Whether the client understands [`EncryptedFrame::PtyExitStatus`],
+    /// negotiated afresh on every connection.
+    pub pty_exit_status: bool,
+    /// Whether the client joined alongside the others (`mp attach --shared`),
+    /// so a later resume leaves it attached.
+    pub shared: bool,
+    /// The terminal size the client last reported, in rows and columns.
+    pub size: Option<(u16, u16)>,
+}
+
+/// Output handle for a session, fanning PTY output out to every attached client.
+#[derive(Debug)]
+pub(crate) struct SessionOutputHandle {
+    /// The connected clients.  Empty while the PTY is running headless.
+    pub clients: Vec<ClientOutput>,
+    /// Set once the client acknowledges a remote command's
+    /// [`EncryptedFrame::CommandExit`], which stops the server resending it.
+    /// Unused by interactive shell sessions.
+    pub exit_acked: Arc<AtomicBool>,
+}
+
+impl SessionOutputHandle {
+    /// A handle for a new session with `client` attached.
+    pub(crate) fn new(client: ClientOutput) -> Self {
+        Self {
+            clients: vec![client],
+            exit_acked: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// A handle for a session no client is attached to yet, such as one taken
+    /// over from a previous `mps`.
+    #[cfg(unix)]
+    pub(crate) fn headless() -> Self {
+        Self {
+            clients: Vec::new(),
+            exit_acked: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Attach `client`, returning the clients it displaces: a resume takes
+    /// the session over from every client that did not join it shared, while
+    /// a shared client displaces nobody.
+    pub(crate) fn attach(&mut self, client: ClientOutput) -> Vec<ClientOutput> {
+        let displaced = if client.shared {
+            Vec::new()
+        } else {
+            let (kept, displaced) = std::mem::take(&mut self.clients)
+                .into_iter()
+                .partition(|other| other.shared);
+            self.clients = kept;
+            displaced
+        };
+        self.clients.push(client);
+        displaced
+    }
+
+    /// Detach the client of connection `kex_uuid`, if it is still attached.
+    pub(crate) fn detach(&mut self, kex_uuid: Uuid) -> Option<ClientOutput> {
+        let index = self.clients.iter().position(|c| c.kex_uuid == kex_uuid)?;
+        Some(self.clients.remove(index))
+    }
+
+    /// Record the terminal size of connection `kex_uuid`'s client and return
+    /// the size the PTY should take.
+    pub(crate) fn resize(&mut self, kex_uuid: Uuid, rows: u16, columns: u16) -> Option<(u16, u16)> {
+        if let Some(client) = self.clients.iter_mut().find(|c| c.kex_uuid == kex_uuid) {
+            client.size = Some((rows, columns));
+        }
+        self.pty_size()
+    }
+
+    /// The size the PTY should take, in rows and columns: the smallest rows
+    /// and columns any client reported, so every client sees the whole
+    /// screen.  `None` until a client reports its size.
+    pub(crate) fn pty_size(&self) -> Option<(u16, u16)> {
+        self.clients
+            .iter()
+            .filter_map(|c| c.size)
+            .reduce(|(rows, columns), (r, c)| (rows.min(r), columns.min(c)))
+    }
 }
 
 /// Full state for one live PTY session.
 pub(crate) struct SessionRecord {
     /// Forward keyboard / resize events from the connected client into this channel.
     pub term_tx: Sender<TerminalMessage>,
-    /// Shared, replaceable output handle – updated on every reconnect.
+    /// Shared output handle – updated as clients attach and leave.
     pub output_handle: Arc<Mutex<SessionOutputHandle>>,
     /// Ring buffer of raw PTY output bytes for scrollback replay on reconnect.
     pub scrollback: Arc<Mutex<VecDeque<u8>>>,
@@ -65,6 +152,20 @@ pub(crate) struct SessionRecord {
     /// watchdog when the path proves it can handle larger datagrams without loss.
     /// Stored in `SessionRecord` so reconnects reuse the already-probed value.
     pub effective_mtu: Arc<AtomicUsize>,
+    /// Forwarded TCP channels of this session.  Created by the first connection that
+    /// negotiates port forwarding and attached to every later one, so forwarded
+    /// streams survive a roam or reconnect; dropped with the session.
+    pub forwards: Option<ForwardMux>,
+    /// When the session was started.
+    pub created: SystemTime,
+    /// When a client last connected to the session.
+    pub last_attach: SystemTime,
+    /// The window title the session's program last set, shown by `mp ls`.
+    pub title: String,
+    /// The session's PTY and login records, for handing to a new `mps`.
+    /// Empty for a command without a PTY, which is never handed over.
+    #[cfg(unix)]
+    pub pty: PtySlot,
 }
 
 impl fmt::Debug for SessionRecord {
@@ -84,22 +185,163 @@ pub(crate) fn new_full_registry() -> FullSessionRegistry {
     Arc::new(Mutex::new(HashMap::new()))
 }
 
+/// Follows the window title a program sets with `OSC 0` or `OSC 2` across
+/// PTY reads, which may split an escape sequence anywhere.
+#[derive(Debug, Default)]
+pub(crate) struct TitleTracker {
+    state: TitleState,
+    osc: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+enum TitleState {
+    #[default]
+    Ground,
+    Escape,
+    Osc,
+    OscEscape,
+}
+
+impl TitleTracker {
+    /// Feed PTY output through the tracker, returning the last title it sets.
+    pub(crate) fn feed(&mut self, bytes: &[u8]) -> Option<String> {
+        let mut title = None;
+        for &byte in bytes {
+            self.state = match (self.state, byte) {
+                (TitleState::Ground | TitleState::Escape, 0x1b) => TitleState::Escape,
+                (TitleState::Escape | TitleState::OscEscape, b']') => {
+                    self.osc.clear();
+                    TitleState::Osc
+                }
+                (TitleState::Osc, 0x07) | (TitleState::OscEscape, b'\\') => {
+                    title = self.title().or(title);
+                    TitleState::Ground
+                }
+                (TitleState::Osc, 0x1b) => TitleState::OscEscape,
+                (TitleState::Osc, _) => {
+                    if self.osc.len() < MAX_TITLE_BYTES {
+                        self.osc.push(byte);
+                    }
+                    TitleState::Osc
+                }
+                _ => TitleState::Ground,
+            };
+        }
+        title
+    }
+
+    /// The title set by the `OSC` sequence just finished, if it sets one.
+    fn title(&self) -> Option<String> {
+        let (kind, text) = self.osc.split_at(self.osc.iter().position(|&b| b == b';')?);
+        matches!(kind, b"0" | b"2").then(|| String::from_utf8_lossy(&text[1..]).into_owned())
+    }
+}
+
+/// One of a user's sessions as `mp ls` shows it.
+#[derive(Clone, Debug)]
+pub(crate) struct SessionSummary {
+    /// The name the session was started with, if any.
+    pub name: Option<String>,
+    /// How many clients are connected to the session.
+    pub clients: usize,
+    /// The session's terminal size, in rows and columns.
+    pub size: (u16, u16),
+    /// When the session was started.
+    pub created: SystemTime,
+    /// When a client last connected to the session.
+    pub last_attach: SystemTime,
+    /// The window title the session's program last set.
+    pub title: String,
+}
+
+/// Render `sessions` as the table `mp ls` prints, with times relative to `now`.
+pub(crate) fn format_session_list(sessions: &[SessionSummary], now: SystemTime) -> String {
+    if sessions.is_empty() {
+        return "no sessions\n".to_string();
+    }
+    let rows: Vec<[String; 6]> = sessions
+        .iter()
+        .map(|session| {
+            [
+                session.name.clone().unwrap_or_else(|| "-".to_string()),
+                match session.clients {
+                    0 => "detached".to_string(),
+                    1 => "attached".to_string(),
+                    clients => format!("attached ({clients})"),
+                },
+                format!("{}x{}", session.size.1, session.size.0),
+                ago(session.created, now),
+                ago(session.last_attach, now),
+                session.title.chars().filter(|c| !c.is_control()).collect(),
+            ]
+        })
+        .collect();
+    let header = ["NAME", "STATE", "SIZE", "CREATED", "ATTACHED", "TITLE"].map(str::to_string);
+    let mut widths = header.clone().map(|column| column.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// How long before `now` the moment `then` was, in its largest whole unit.
+fn ago(then: SystemTime, now: SystemTime) -> String {
+    let secs = now.duration_since(then).unwrap_or(Duration::ZERO).as_secs();
+    match secs {
+        0..60 => format!("{secs}s ago"),
+        60..3_600 => format!("{}m ago", secs / 60),
+        3_600..86_400 => format!("{}h ago", secs / 3_600),
+        _ => format!("{}d ago", secs / 86_400),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::{
         collections::VecDeque,
         sync::Arc,
         sync::atomic::{AtomicBool, AtomicU64, AtomicUsize},
+        time::{Duration, SystemTime},
     };
 
     use libmoshpit::{EncryptedFrame, TerminalMessage};
-    use tokio::sync::{
-        Mutex,
-        mpsc::{Sender, channel},
-    };
+    use tokio::sync::{Mutex, mpsc::channel};
+    use tokio_util::sync::CancellationToken;
     use uuid::Uuid;
 
-    use super::{SCROLLBACK_CAPACITY, SessionOutputHandle, SessionRecord, new_full_registry};
+    #[cfg(unix)]
+    use super::PtySlot;
+    use super::{
+        ClientOutput, SCROLLBACK_CAPACITY, SessionOutputHandle, SessionRecord, SessionSummary,
+        TitleTracker, format_session_list, new_full_registry,
+    };
+
+    fn client(shared: bool, size: Option<(u16, u16)>) -> ClientOutput {
+        let (data_tx, _data_rx) = channel::<EncryptedFrame>(1);
+        let (control_tx, _control_rx) = channel::<EncryptedFrame>(1);
+        ClientOutput {
+            kex_uuid: Uuid::new_v4(),
+            data_tx,
+            control_tx,
+            conn_token: CancellationToken::new(),
+            udp_port: 50_000,
+            pty_exit_status: true,
+            shared,
+            size,
+        }
+    }
 
     #[test]
     fn scrollback_capacity_is_64kib() {
@@ -114,30 +356,51 @@ mod test {
 
     #[test]
     fn session_output_handle_debug() {
-        let handle = SessionOutputHandle {
-            kex_uuid: Uuid::nil(),
-            data_tx: None::<Sender<EncryptedFrame>>,
-            control_tx: None::<Sender<EncryptedFrame>>,
-            conn_token: None,
-            udp_port: None,
-        };
+        let handle = SessionOutputHandle::new(client(false, None));
         let s = format!("{handle:?}");
         assert!(s.contains("SessionOutputHandle"));
         assert!(s.contains("kex_uuid"));
     }
 
+    #[test]
+    fn resume_displaces_all_but_shared_clients() {
+        let owner = client(false, None);
+        let owner_uuid = owner.kex_uuid;
+        let mut handle = SessionOutputHandle::new(owner);
+        let viewer = client(true, None);
+        let viewer_uuid = viewer.kex_uuid;
+        assert!(handle.attach(viewer).is_empty());
+        assert_eq!(handle.clients.len(), 2);
+
+        let displaced = handle.attach(client(false, None));
+        assert_eq!(displaced.len(), 1);
+        assert_eq!(displaced[0].kex_uuid, owner_uuid);
+        assert_eq!(handle.clients.len(), 2);
+        assert!(handle.detach(viewer_uuid).is_some());
+        assert!(handle.detach(viewer_uuid).is_none());
+        assert_eq!(handle.clients.len(), 1);
+    }
+
+    #[test]
+    fn smallest_client_sets_the_pty_size() {
+        let owner = client(false, Some((50, 200)));
+        let owner_uuid = owner.kex_uuid;
+        let mut handle = SessionOutputHandle::new(owner);
+        assert_eq!(handle.pty_size(), Some((50, 200)));
+        let viewer = client(true, None);
+        let viewer_uuid = viewer.kex_uuid;
+        drop(handle.attach(viewer));
+        assert_eq!(handle.pty_size(), Some((50, 200)));
+        assert_eq!(handle.resize(viewer_uuid, 60, 80), Some((50, 80)));
+        assert_eq!(handle.resize(owner_uuid, 40, 120), Some((40, 80)));
+        drop(handle.detach(viewer_uuid));
+        assert_eq!(handle.pty_size(), Some((40, 120)));
+    }
+
     #[tokio::test]
     async fn session_record_debug() {
         let (term_tx, _term_rx) = channel::<TerminalMessage>(1);
-        let (data_tx, _data_rx) = channel::<EncryptedFrame>(1);
-        let (_ctrl_tx, _ctrl_rx) = channel::<EncryptedFrame>(1);
-        let output_handle = Arc::new(Mutex::new(SessionOutputHandle {
-            kex_uuid: Uuid::nil(),
-            data_tx: Some(data_tx),
-            control_tx: None,
-            conn_token: None,
-            udp_port: None,
-        }));
+        let output_handle = Arc::new(Mutex::new(SessionOutputHandle::new(client(false, None))));
         let scrollback = Arc::new(Mutex::new(VecDeque::<u8>::new()));
         let server_emulator = Arc::new(Mutex::new(vt100::Parser::new(24, 80, 0)));
         let dirty_counter = Arc::new(AtomicU64::new(1));
@@ -151,9 +414,60 @@ mod test {
             dirty_counter,
             diff_in_flight,
             effective_mtu,
+            forwards: None,
+            created: SystemTime::now(),
+            last_attach: SystemTime::now(),
+            title: String::new(),
+            #[cfg(unix)]
+            pty: PtySlot::default(),
         };
         let s = format!("{record:?}");
         assert!(s.contains("SessionRecord"));
         assert!(s.contains("output_handle"));
     }
+
+    #[test]
+    fn title_tracker_follows_osc_titles_across_reads() {
+        let mut tracker = TitleTracker::default();
+        assert_eq!(tracker.feed(b"plain \x1b[1mtext"), None);
+        assert_eq!(
+            tracker.feed(b"\x1b]0;vim notes\x07$ "),
+            Some("vim notes".to_string())
+        );
+        assert_eq!(tracker.feed(b"\x1b]2;bu"), None);
+        assert_eq!(tracker.feed(b"ild\x1b"), None);
+        assert_eq!(tracker.feed(b"\\"), Some("build".to_string()));
+        // Other OSC sequences, such as the working directory, are not titles.
+        assert_eq!(tracker.feed(b"\x1b]7;file:///tmp\x07"), None);
+    }
+
+    #[test]
+    fn session_list_is_a_table_with_relative_times() {
+        let now = SystemTime::now();
+        let sessions = [
+            SessionSummary {
+                name: Some("work".to_string()),
+                clients: 0,
+                size: (24, 80),
+                created: now - Duration::from_hours(3 * 24),
+                last_attach: now - Duration::from_hours(2),
+                title: "vim\x07 notes".to_string(),
+            },
+            SessionSummary {
+                name: None,
+                clients: 2,
+                size: (50, 200),
+                created: now - Duration::from_secs(90),
+                last_attach: now - Duration::from_secs(5),
+                title: String::new(),
+            },
+        ];
+        assert_eq!(
+            format_session_list(&sessions, now),
+            "NAME  STATE         SIZE    CREATED  ATTACHED  TITLE\n\
+             work  detached      80x24   3d ago   2h ago    vim notes\n\
+             -     attached (2)  200x50  1m ago   5s ago\n"
+        );
+        assert_eq!(format_session_list(&[], now), "no sessions\n");
+    }
 }
@@ -140,12 +140,18 @@ fn login_to(
     put_utmp(utmp_path, tty, &bytes).context("update /var/run/utmp")?;
     put_wtmp(wtmp_path, &bytes).context("append /var/log/wtmp")?;
 
-    Ok(UtmpSession {
+    Ok(adopt(tty, pid))
+}
+
+/// Take over the record [`login`] wrote for `pid` on `tty` in a previous
+/// `mps`, so [`logout`] clears it once the shell ends.
+pub(crate) fn adopt(tty: &str, pid: u32) -> UtmpSession {
+    UtmpSession {
         line: tty.to_owned(),
-        id,
+        id: ut_id_from_line(tty),
         #[allow(clippy::cast_possible_wrap)]
         pid: pid as i32,
-    })
+    }
 }
 
 /// Record a logout: flip the session's `/var/run/utmp` slot to `DEAD_PROCESS`
@@ -336,8 +342,8 @@ mod test {
 
     use super::{
         DEAD_PROCESS, LINE_LEN, OFF_ADDR, OFF_HOST, OFF_ID, OFF_LINE, OFF_PID, OFF_TV, OFF_TYPE,
-        OFF_USER, RECORD_SIZE, USER_PROCESS, Utmpx, addr_v6, line_matches, login_to, logout_to,
-        put_utmp, put_wtmp, record_bytes, record_bytes_raw, ut_id_from_line,
+        OFF_USER, RECORD_SIZE, USER_PROCESS, Utmpx, addr_v6, adopt, line_matches, login_to,
+        logout_to, put_utmp, put_wtmp, record_bytes, record_bytes_raw, ut_id_from_line,
     };
 
     #[test]
@@ -537,6 +543,33 @@ mod test {
         drop(remove_file(wtmp));
     }
 
+    #[test]
+    fn adopted_session_logs_out_the_original_login() {
+        let dir = temp_dir();
+        let pid = id();
+        let utmp = dir.join(format!("moshpit-utmp-adopt-{pid}"));
+        let wtmp = dir.join(format!("moshpit-wtmp-adopt-{pid}"));
+        let utmp = utmp.to_str().unwrap();
+        let wtmp = wtmp.to_str().unwrap();
+        drop(remove_file(utmp));
+        drop(remove_file(wtmp));
+
+        drop(login_to(utmp, wtmp, "dave", 5151, "pts/12", None).unwrap());
+        let session = adopt("pts/12", 5151);
+        assert_eq!(session.id, ut_id_from_line("pts/12"));
+        logout_to(utmp, wtmp, &session).unwrap();
+
+        let after_logout = read(utmp).unwrap();
+        assert_eq!(after_logout.len(), RECORD_SIZE, "slot reused, not appended");
+        assert_eq!(
+            i16::from_ne_bytes([after_logout[OFF_TYPE], after_logout[OFF_TYPE + 1]]),
+            DEAD_PROCESS
+        );
+
+        drop(remove_file(utmp));
+        drop(remove_file(wtmp));
+    }
+
     #[test]
     fn put_utmp_reuses_first_free_slot() {
         let dir = temp_dir();
@@ -288,6 +288,8 @@ end
 # ── Standard ECDH identity key tests (x25519 key pair) ───────────────────────
 def_test "Default (x25519-sha256 / aes256-gcm-siv / hmac-sha512 / hkdf-sha256)" \
     0 x25519 "" ""
+def_test "ML-KEM-768 + X25519 hybrid KEX" \
+    0 x25519 "--kex-algos mlkem768x25519-sha256" "--kex-algos mlkem768x25519-sha256"
 def_test "ML-KEM-768 KEX" \
     0 x25519 "--kex-algos ml-kem-768-sha256" "--kex-algos ml-kem-768-sha256"
 def_test "ML-KEM-512 KEX" \
@@ -307,6 +309,8 @@ def_test "AES-128-GCM-SIV AEAD" \
     0 x25519 "--aead-algos aes128-gcm-siv" "--aead-algos aes128-gcm-siv"
 def_test "HMAC-SHA256 MAC" \
     0 x25519 "--mac-algos hmac-sha256" "--mac-algos hmac-sha256"
+def_test "AEAD-implicit MAC" \
+    0 x25519 "--mac-algos aead-implicit" "--mac-algos aead-implicit"
 def_test "HKDF-SHA384 KDF" \
     0 x25519 "--kdf-algos hkdf-sha384" "--kdf-algos hkdf-sha384"
 def_test "HKDF-SHA512 KDF" \
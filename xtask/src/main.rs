@@ -173,7 +173,7 @@ fn mp_command() -> Command {
             Arg::new("server-destination")
                 .value_name("SERVER_DESTINATION")
                 .required(true)
-                .help("The IP address of the server to connect to (user@address or address)"),
+                .help("The server to connect to ([user@]host[:port]; host may be a name, IPv4, or [IPv6])"),
         )
         .arg(
             Arg::new("predict")
@@ -216,7 +216,7 @@ fn mp_command() -> Command {
             Arg::new("kex-algos")
                 .long("kex-algos")
                 .value_name("ALGOS")
-                .help("Ordered KEX algorithms to offer, comma-separated [supported: x25519-sha256 (default), ml-kem-768-sha256, ml-kem-512-sha256, ml-kem-1024-sha256, p384-sha384, p256-sha256]"),
+                .help("Ordered KEX algorithms to offer, comma-separated [supported: x25519-sha256 (default), mlkem768x25519-sha256, ml-kem-768-sha256, ml-kem-512-sha256, ml-kem-1024-sha256, p384-sha384, p256-sha256]"),
         )
         .arg(
             Arg::new("aead-algos")
@@ -228,7 +228,7 @@ fn mp_command() -> Command {
             Arg::new("mac-algos")
                 .long("mac-algos")
                 .value_name("ALGOS")
-                .help("Ordered MAC algorithms to offer, comma-separated [supported: hmac-sha512 (default), hmac-sha256]"),
+                .help("Ordered MAC algorithms to offer, comma-separated [supported: hmac-sha512 (default), hmac-sha256, aead-implicit]"),
         )
         .arg(
             Arg::new("kdf-algos")
@@ -280,7 +280,7 @@ fn mps_command() -> Command {
             Arg::new("kex-algos")
                 .long("kex-algos")
                 .value_name("ALGOS")
-                .help("Ordered KEX algorithms to prefer, comma-separated [supported: x25519-sha256 (default), ml-kem-768-sha256, ml-kem-512-sha256, ml-kem-1024-sha256, p384-sha384, p256-sha256]"),
+                .help("Ordered KEX algorithms to prefer, comma-separated [supported: x25519-sha256 (default), mlkem768x25519-sha256, ml-kem-768-sha256, ml-kem-512-sha256, ml-kem-1024-sha256, p384-sha384, p256-sha256]"),
         )
         .arg(
             Arg::new("aead-algos")
@@ -292,7 +292,7 @@ fn mps_command() -> Command {
             Arg::new("mac-algos")
                 .long("mac-algos")
                 .value_name("ALGOS")
-                .help("Ordered MAC algorithms to prefer, comma-separated [supported: hmac-sha512 (default), hmac-sha256]"),
+                .help("Ordered MAC algorithms to prefer, comma-separated [supported: hmac-sha512 (default), hmac-sha256, aead-implicit]"),
         )
         .arg(
             Arg::new("kdf-algos")